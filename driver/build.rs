@@ -1,6 +1,11 @@
+#[cfg(windows)]
 fn main() -> Result<(), wdk_build::ConfigError> {
     let mut config = wdk_build::Config::from_env_auto()?;
     config.driver_config = wdk_build::DriverConfig::WDM();
     config.configure_binary_build();
     Ok(())
 }
+
+/// The driver is only built for Windows; other hosts build nothing.
+#[cfg(not(windows))]
+fn main() {}
@@ -1,21 +1,20 @@
 //! This module provides a safe wrapper around the system's memory validation function, typically named `MmIsAddressValid`.
 //! It allows checking the validity of addresses in a way that integrates with a system's memory management routines.
-//! The implementation uses a global atomic pointer to hold and replace the original system function with a custom hook,
-//! ensuring that any calls to check memory validity are routed through this custom implementation.
+//! The hook is declared with `kernel_hook!`, which provides the typed trampoline to the original system function and
+//! the registration with the `HookManager`, ensuring that any calls to check memory validity are routed through this custom implementation.
+//! It also provides root-mode callbacks tracing `NtCreateFile` on entry and on return.
 //! Credits to Matthias: https://github.com/not-matthias/amd_hypervisor/blob/main/driver/src/hook.rs
 
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
 #![allow(dead_code)]
 
-use core::{
-    mem, ptr,
-    sync::atomic::{AtomicPtr, Ordering},
-};
-use wdk_sys::{
-    ACCESS_MASK, NTSTATUS, PHANDLE, PIO_STATUS_BLOCK, PLARGE_INTEGER, POBJECT_ATTRIBUTES, PVOID,
-    ULONG,
+use hypervisor::{
+    intel::guest_memory::GuestMemory,
+    kernel_hook,
+    utils::{capture::GuestRegisters, function_hook::HookAction, return_hook::CallContext},
 };
+use wdk_sys::NTSTATUS;
 
 // Extern block for interfacing with LLVM intrinsic for getting the return address.
 extern "C" {
@@ -24,97 +23,83 @@ extern "C" {
     fn return_address() -> *const u64;
 }
 
-/// A global atomic pointer to hold the original `mm_is_address_valid` function.
-/// It's initialized to a null mutable pointer and will be set during runtime to the actual function.
-pub static MM_IS_ADDRESS_VALID_ORIGINAL: AtomicPtr<u64> = AtomicPtr::new(ptr::null_mut());
-
-/// The type of the `MmIsAddressValid` function.
-type MmIsAddressValidType = extern "C" fn(VirtualAddress: PVOID) -> bool;
+kernel_hook! {
+    /// A safe wrapper around the `MmIsAddressValid` function.
+    ///
+    /// ## Parameters
+    /// - `virtual_address`: The address to check for validity.
+    ///
+    /// ## Returns
+    /// Returns `true` if the address is valid, `false` otherwise.
+    "MmIsAddressValid" => pub fn mm_is_address_valid(virtual_address: u64) -> bool {
+        // Log the address from which `MmIsAddressValid` was called.
+        log::debug!("MmIsAddressValid called from {:#x}", unsafe {
+            return_address().read_volatile() // Reads the return address in a volatile manner to prevent optimizations.
+        });
+
+        log::debug!("First Parameter Value: {:x}", virtual_address);
+
+        // Call the original `MmIsAddressValid` function through the trampoline.
+        original(virtual_address).unwrap_or(false)
+    }
+}
 
-/// A safe wrapper around the `MmIsAddressValid` function.
+/// The root-mode entry callback of the `NtCreateFile` hook.
+///
+/// Logs the call and continues into the original function, so the return callback observes the result.
 ///
 /// ## Parameters
-/// - `ptr`: The pointer to check for validity.
+/// - `guest_registers`: The guest registers at function entry.
+/// - `guest_memory`: The accessor for the guest memory.
 ///
 /// ## Returns
-/// Returns `true` if the address is valid, `false` otherwise.
-///
-/// ## Safety
-/// This function assumes that the original `MmIsAddressValid` function is correctly set and points to a valid function.
-/// The caller must ensure this is the case to avoid undefined behavior.
-pub extern "C" fn mm_is_address_valid(virtual_address: u64) -> bool {
-    // Log the address from which `MmIsAddressValid` was called.
-    log::debug!("MmIsAddressValid called from {:#x}", unsafe {
-        return_address().read_volatile() // Reads the return address in a svolatile manner to prevent optimizations.
-    });
-
-    log::debug!("First Parameter Value: {:x}", virtual_address);
-
-    // Load the original function pointer from the global atomic pointer.
-    let fn_ptr = MM_IS_ADDRESS_VALID_ORIGINAL.load(Ordering::Relaxed); // Using relaxed ordering for atomic loading.
-
-    // Transmute the function pointer to the expected function type.
-    let fn_ptr = unsafe { mem::transmute::<_, MmIsAddressValidType>(fn_ptr) };
-
-    // Call the original `MmIsAddressValid` function with the provided pointer.
-    fn_ptr(virtual_address as _)
+/// Returns `HookAction::Continue` to execute the original `NtCreateFile`.
+pub fn nt_create_file_entry(
+    guest_registers: &mut GuestRegisters,
+    guest_memory: &GuestMemory,
+) -> HookAction {
+    if let Ok(return_address) = guest_memory.read::<u64>(guest_registers.rsp) {
+        log::debug!("NtCreateFile called from {:#x}", return_address);
+    }
+
+    log::debug!("First Parameter Value: {:x}", guest_registers.rcx);
+
+    HookAction::Continue
 }
 
-/// A global atomic pointer to hold the original `nt_create_file` function.
-/// It's initialized to a null mutable pointer and will be set during runtime to the actual function.
-pub static NT_CREATE_FILE_ORIGINAL: AtomicPtr<u64> = AtomicPtr::new(ptr::null_mut());
-
-type NtCreateFileType = extern "C" fn(
-    FileHandle: PHANDLE,
-    DesiredAccess: ACCESS_MASK,
-    ObjectAttributes: POBJECT_ATTRIBUTES,
-    IoStatusBlock: PIO_STATUS_BLOCK,
-    AllocationSize: PLARGE_INTEGER,
-    FileAttributes: ULONG,
-    ShareAccess: ULONG,
-    CreateDisposition: ULONG,
-    CreateOptions: ULONG,
-    EaBuffer: PVOID,
-    EaLength: ULONG,
-) -> NTSTATUS;
-
-pub extern "C" fn nt_create_file(
-    file_handle: PHANDLE,
-    desired_access: ACCESS_MASK,
-    object_attributes: POBJECT_ATTRIBUTES,
-    io_status_block: PIO_STATUS_BLOCK,
-    allocation_size: PLARGE_INTEGER,
-    file_attributes: ULONG,
-    share_access: ULONG,
-    create_disposition: ULONG,
-    create_options: ULONG,
-    ea_buffer: PVOID,
-    ea_length: ULONG,
-) -> NTSTATUS {
-    log::debug!("NtCreateFile called from {:#x}", unsafe {
-        return_address().read_volatile() // Reads the return address in a volatile manner to prevent optimizations.
-    });
-
-    log::debug!("First Parameter Value: {:x}", file_handle as u64);
-
-    // Load the original function pointer from the global atomic pointer.
-    let fn_ptr = NT_CREATE_FILE_ORIGINAL.load(Ordering::Relaxed); // Using relaxed ordering for atomic loading.
-
-    // Transmute the function pointer to the expected function type.
-    let fn_ptr = unsafe { mem::transmute::<_, NtCreateFileType>(fn_ptr) };
-
-    // Call the original `NtCreateFile` function with the provided pointer.
-    fn_ptr(
-        file_handle,
-        desired_access,
-        object_attributes,
-        io_status_block,
-        allocation_size,
-        file_attributes,
-        share_access,
-        create_disposition,
-        create_options,
-        ea_buffer,
-        ea_length,
-    )
+/// The root-mode return callback of the `NtCreateFile` hook.
+///
+/// Logs the returned status and, if the call succeeded, the handle stored in `FileHandle`.
+///
+/// ## Parameters
+/// - `guest_registers`: The guest registers after the return, with the status in RAX.
+/// - `guest_memory`: The accessor for the guest memory.
+/// - `context`: The context recorded at function entry, including the entry arguments.
+pub fn nt_create_file_exit(
+    guest_registers: &mut GuestRegisters,
+    guest_memory: &GuestMemory,
+    context: &CallContext,
+) {
+    let status = guest_registers.rax as NTSTATUS;
+
+    // The first argument is the `FileHandle` pointer, which receives the handle on success.
+    let file_handle_ptr = context.arguments[0];
+
+    if status >= 0 {
+        match guest_memory.read::<u64>(file_handle_ptr) {
+            Ok(file_handle) => log::debug!(
+                "NtCreateFile returned {:#x} to {:#x} with handle {:#x}",
+                status,
+                context.return_address,
+                file_handle
+            ),
+            Err(e) => log::debug!("Failed to read the file handle: {:?}", e),
+        }
+    } else {
+        log::debug!(
+            "NtCreateFile returned {:#x} to {:#x}",
+            status,
+            context.return_address
+        );
+    }
 }
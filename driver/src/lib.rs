@@ -3,9 +3,10 @@
 //! This crate provides a basic hypervisor kernel driver. It interfaces with the
 //! system to virtualize processors and manage hypervisor-related activities.
 
+#![cfg(windows)]
 #![no_std]
 #![allow(unused_mut)]
-#![feature(allocator_api, new_uninit)]
+#![feature(allocator_api)]
 #![feature(link_llvm_intrinsics)]
 
 // Set up a panic handler for non-test configurations.
@@ -26,18 +27,25 @@ static GLOBAL: hypervisor::utils::alloc::KernelAlloc = hypervisor::utils::alloc:
 use {
     crate::expanded_stack::with_expanded_stack,
     alloc::boxed::Box,
-    alloc::vec,
-    core::sync::atomic::Ordering,
+    alloc::vec::Vec,
+    core::ops::Range,
     hypervisor::{
         error::HypervisorError,
         intel::{
             ept::{
-                hooks::{Hook, HookManager, HookType},
+                hooks::HookManager,
                 paging::{AccessType, Ept},
             },
+            vmexit::io::{protect_port, PortAccess},
             vmm::Hypervisor,
         },
-        utils::{alloc::PhysicalAllocator, nt::update_ntoskrnl_cr3, ssdt::ssdt_hook::SsdtHook},
+        utils::{
+            alloc::PhysicalAllocator,
+            function_hook::HookHandler,
+            nt::{read_registry_value, update_ntoskrnl_cr3},
+            ssdt::{ssdt_hook::SsdtHook, sys_info::Sysinfo},
+            symbols::{self, Symbols},
+        },
     },
     log::LevelFilter,
     log::{self},
@@ -50,6 +58,12 @@ use {
 pub mod expanded_stack;
 pub mod hook;
 
+/// The base port of COM2, which the logger writes to.
+const COM2_PORT: u16 = 0x2F8;
+
+/// The number of ports of a serial port (UART).
+const COM_PORT_COUNT: u16 = 8;
+
 /// The main entry point for the driver.
 ///
 /// This function is invoked by the system when the driver is loaded. It initializes
@@ -58,7 +72,7 @@ pub mod hook;
 /// # Parameters
 ///
 /// * `driver`: Reference to the system's DRIVER_OBJECT for this driver.
-/// * `_registry_path`: Unused. Path to the driver's registry key.
+/// * `registry_path`: Path to the driver's registry key, whose `Symbols` value optionally contains the kernel symbols.
 ///
 /// # Returns
 ///
@@ -69,7 +83,7 @@ pub mod hook;
 #[export_name = "DriverEntry"]
 pub unsafe extern "system" fn driver_entry(
     driver: &mut DRIVER_OBJECT,
-    _registry_path: PUNICODE_STRING,
+    registry_path: PUNICODE_STRING,
 ) -> NTSTATUS {
     // Due to post-vmlaunch issues with the kernel logger, we transition to using a serial port logger.
     // This logger writes to the host OS via VMware Workstation.
@@ -77,7 +91,7 @@ pub unsafe extern "system" fn driver_entry(
     // Initialize the COM2 port logger with level filter set to Info.
 
     com_logger::builder()
-        .base(0x2f8)
+        .base(COM2_PORT)
         .filter(LevelFilter::Debug)
         .setup();
 
@@ -86,8 +100,15 @@ pub unsafe extern "system" fn driver_entry(
     // Remove if manually mapping the kernel driver
     driver.DriverUnload = Some(driver_unload);
 
+    // The symbols are optional, only the hooks of non-exported functions need them.
+    load_symbols(registry_path);
+
+    // The driver keeps logging from the guest after virtualization, through its own image.
+    let driver_start = driver.DriverStart as u64;
+    let driver_image = driver_start..driver_start + driver.DriverSize as u64;
+
     with_expanded_stack(|| {
-        match virtualize_system() {
+        match virtualize_system(driver_image) {
             Ok(_) => log::info!("Virtualized system successfully!"),
             Err(err) => {
                 log::error!("Virtualization failed: {:?}", err);
@@ -121,6 +142,25 @@ pub extern "C" fn driver_unload(_driver: *mut DRIVER_OBJECT) {
     }
 }
 
+/// Loads the kernel symbols from the `Symbols` value of the driver's registry key.
+///
+/// The value is a `REG_BINARY` blob created offline from the PDB of ntoskrnl.exe with the
+/// `extract_symbols` example of the hypervisor crate.
+///
+/// # Parameters
+///
+/// * `registry_path`: Path to the driver's registry key.
+fn load_symbols(registry_path: PUNICODE_STRING) {
+    let result = read_registry_value(registry_path, "Symbols")
+        .and_then(|blob| Symbols::from_bytes(&blob))
+        .and_then(symbols::install);
+
+    match result {
+        Ok(symbols) => log::info!("Loaded {} kernel symbols", symbols.symbol_count()),
+        Err(err) => log::debug!("Kernel symbols not loaded: {:?}", err),
+    }
+}
+
 /// The main hypervisor object.
 ///
 /// This static mutable option holds the global instance of the hypervisor used by this driver.
@@ -131,42 +171,41 @@ static mut HYPERVISOR: Option<Hypervisor> = None;
 /// This function initializes a new hypervisor and then attempts to virtualize all
 /// processors on the system.
 ///
+/// # Arguments
+///
+/// * `driver_image` - The addresses of the driver's image, whose accesses to the serial port of the
+///   logger are let through.
+///
 /// # Returns
 ///
 /// * `Some(())` if the system was successfully virtualized.
 /// * `None` if there was an error during virtualization.
 ///
 /// Credits: Jess / jessiep_
-fn virtualize_system() -> Result<(), HypervisorError> {
+fn virtualize_system(driver_image: Range<u64>) -> Result<(), HypervisorError> {
     // Example 1: Normal EPT Hook MmIsAddressValid
     //
     //
 
-    let mm_is_address_valid =
-        Hook::hook_function("MmIsAddressValid", hook::mm_is_address_valid as *const ())
-            .ok_or(HypervisorError::HookError)?;
+    let mut hook_manager = HookManager::new(Vec::new());
+    let sys_info = Sysinfo::new()?;
 
-    if let HookType::Function { ref inline_hook } = mm_is_address_valid.hook_type {
-        hook::MM_IS_ADDRESS_VALID_ORIGINAL
-            .store(inline_hook.trampoline_address(), Ordering::Relaxed);
-    }
+    hook::mm_is_address_valid::register(&mut hook_manager, &sys_info)?;
 
-    // Example 2: Syscall EPT Hook NtCreateFile via SSDT Function Entry
+    // Example 2: Syscall EPT Hook NtCreateFile via SSDT Function Entry, traced in VMX root operation on entry and on return
     //
     //
-    let ssdt_nt_create_file_addy = SsdtHook::find_ssdt_function_address(0x0055, false)?;
+    let ssdt_nt_create_file_addy = SsdtHook::find_ssdt_function_address_by_name("NtCreateFile")?;
 
-    let nt_create_file_syscall_hook = Hook::hook_function_ptr(
+    hook_manager.hook_function_ptr(
+        &sys_info,
         ssdt_nt_create_file_addy.function_address as _,
-        hook::nt_create_file as *const (),
-    )
-    .ok_or(HypervisorError::HookError)?;
-
-    if let HookType::Function { ref inline_hook } = nt_create_file_syscall_hook.hook_type {
-        hook::NT_CREATE_FILE_ORIGINAL.store(inline_hook.trampoline_address(), Ordering::Relaxed);
-    }
-
-    let hook_manager = HookManager::new(vec![mm_is_address_valid, nt_create_file_syscall_hook]);
+        0,
+        HookHandler::CallbackWithReturn {
+            entry: hook::nt_create_file_entry,
+            exit: hook::nt_create_file_exit,
+        },
+    )?;
 
     let mut primary_ept: Box<Ept, PhysicalAllocator> =
         unsafe { Box::try_new_zeroed_in(PhysicalAllocator)?.assume_init() };
@@ -183,12 +222,26 @@ fn virtualize_system() -> Result<(), HypervisorError> {
     log::debug!("Enabling hooks");
     hook_manager.enable_hooks(&mut primary_ept, &mut secondary_ept)?;
 
-    let mut hv = match Hypervisor::builder()
+    let mut builder = Hypervisor::builder()
         .primary_ept(primary_ept)
         .secondary_ept(secondary_ept)
-        .hook_manager(hook_manager)
-        .build()
-    {
+        .hook_manager(hook_manager);
+
+    // Protect the serial port of the logger from the guest, which would otherwise interfere with the logs.
+    // The logs of the hypervisor are written in VMX root operation and never cause a VM exit, while the
+    // driver's own logs, written from the guest after virtualization, are forwarded to the port.
+    for port in COM2_PORT..COM2_PORT + COM_PORT_COUNT {
+        let driver_image = driver_image.clone();
+        builder = builder.intercept_port(port, move |access: &mut PortAccess| {
+            if driver_image.contains(&access.guest_rip) {
+                access.forward();
+            } else {
+                protect_port(access);
+            }
+        });
+    }
+
+    let mut hv = match builder.build() {
         Ok(hv) => hv,
         Err(err) => return Err(err),
     };
@@ -0,0 +1,193 @@
+//! Extracts the symbols and structure layouts of the kernel from the PDB of ntoskrnl.exe and writes the blob
+//! the driver loads from the `Symbols` value of its service key.
+//!
+//! ```text
+//! cargo run -p hypervisor --example extract_symbols -- <pdb> <output> [--image <ntoskrnl.exe>] [--symbol <name>]... [--struct <name>]...
+//! ```
+//!
+//! The PDB is the one of the kernel of the target machine, for example downloaded with `symchk.exe /r
+//! C:\Windows\System32\ntoskrnl.exe /s srv*C:\Symbols*https://msdl.microsoft.com/download/symbols`.
+//! If the image is given, the PDB is checked against its CodeView record, which the driver does as well
+//! before installing the symbols. The default names are used when no `--symbol` or `--struct` is given.
+//!
+//! The crate links the kernel on Windows targets, so the tool runs on a Linux or WSL host.
+
+use {
+    hypervisor::utils::{
+        pdb::Pdb,
+        pe::PeImage,
+        symbols::{Symbols, SYMBOLS_VERSION},
+    },
+    std::{env, fs, process::ExitCode},
+};
+
+/// The symbols extracted when no `--symbol` is given.
+const DEFAULT_SYMBOLS: &[&str] = &[
+    "KeServiceDescriptorTableShadow",
+    "KiSystemServiceStart",
+    "PspCidTable",
+];
+
+/// The structures extracted when no `--struct` is given.
+const DEFAULT_STRUCTS: &[&str] = &["_EPROCESS", "_KPCR", "_KPROCESS", "_KTHREAD"];
+
+/// The arguments of the tool.
+struct Arguments {
+    /// The path of the PDB.
+    pdb: String,
+
+    /// The path the blob is written to.
+    output: String,
+
+    /// The path of the image the PDB is checked against.
+    image: Option<String>,
+
+    /// The names of the symbols.
+    symbols: Vec<String>,
+
+    /// The names of the structures.
+    structs: Vec<String>,
+}
+
+impl Arguments {
+    /// Parses the command line.
+    fn parse() -> Result<Self, String> {
+        let mut arguments = env::args().skip(1);
+        let mut positional = Vec::new();
+        let mut image = None;
+        let mut symbols = Vec::new();
+        let mut structs = Vec::new();
+
+        while let Some(argument) = arguments.next() {
+            let mut value = || {
+                arguments
+                    .next()
+                    .ok_or_else(|| format!("Missing value of {}", argument))
+            };
+
+            match argument.as_str() {
+                "--image" => image = Some(value()?),
+                "--symbol" => symbols.push(value()?),
+                "--struct" => structs.push(value()?),
+                _ if argument.starts_with("--") => {
+                    return Err(format!("Unknown option {}", argument))
+                }
+                _ => positional.push(argument),
+            }
+        }
+
+        let [pdb, output] = <[String; 2]>::try_from(positional)
+            .map_err(|_| String::from("Expected the paths of the PDB and the output"))?;
+
+        if symbols.is_empty() {
+            symbols = DEFAULT_SYMBOLS
+                .iter()
+                .map(|name| name.to_string())
+                .collect();
+        }
+
+        if structs.is_empty() {
+            structs = DEFAULT_STRUCTS
+                .iter()
+                .map(|name| name.to_string())
+                .collect();
+        }
+
+        Ok(Self {
+            pdb,
+            output,
+            image,
+            symbols,
+            structs,
+        })
+    }
+}
+
+/// Extracts the symbols and writes the blob.
+fn run(arguments: &Arguments) -> Result<Symbols, String> {
+    let pdb = fs::read(&arguments.pdb).map_err(|e| format!("{}: {}", arguments.pdb, e))?;
+    let pdb = Pdb::parse(&pdb).map_err(|e| format!("{}: {}", arguments.pdb, e))?;
+
+    let symbol_names = arguments
+        .symbols
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    let struct_names = arguments
+        .structs
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+
+    let symbols = pdb
+        .extract(&symbol_names, &struct_names)
+        .map_err(|e| format!("{}: {}", arguments.pdb, e))?;
+
+    if let Some(path) = &arguments.image {
+        let image = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        let image = PeImage::parse_file(&image).map_err(|e| format!("{}: {}", path, e))?;
+
+        if !symbols.matches(&image) {
+            return Err(format!("{} isn't the PDB of {}", arguments.pdb, path));
+        }
+    }
+
+    for name in &symbol_names {
+        match symbols.symbol_rva(name) {
+            Some(rva) => println!("{} = {:#x}", name, rva),
+            None => eprintln!("Symbol {} not found", name),
+        }
+    }
+
+    for name in &struct_names {
+        match symbols.structure(name) {
+            Some(layout) => println!(
+                "{}: {:#x} bytes, {} fields",
+                name,
+                layout.size,
+                layout.fields.len()
+            ),
+            None => eprintln!("Structure {} not found", name),
+        }
+    }
+
+    fs::write(&arguments.output, symbols.to_bytes())
+        .map_err(|e| format!("{}: {}", arguments.output, e))?;
+
+    Ok(symbols)
+}
+
+fn main() -> ExitCode {
+    let arguments = match Arguments::parse() {
+        Ok(arguments) => arguments,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!(
+                "Usage: extract_symbols <pdb> <output> [--image <ntoskrnl.exe>] [--symbol <name>]... [--struct <name>]..."
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match run(&arguments) {
+        Ok(symbols) => {
+            println!(
+                "Wrote {} symbols and {} structures (format version {}) to {}",
+                symbols.symbol_count(),
+                symbols.struct_count(),
+                SYMBOLS_VERSION,
+                arguments.output
+            );
+            println!("Install the blob on the target machine with:");
+            println!(
+                "Set-ItemProperty -Path HKLM:\\SYSTEM\\CurrentControlSet\\Services\\matrix -Name Symbols -Type Binary -Value ([System.IO.File]::ReadAllBytes(\"{}\"))",
+                arguments.output
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -1,4 +1,5 @@
-use alloc::ffi::NulError;
+use crate::intel::{vmentry_checks::VmEntryViolation, vmerror::VmInstructionError};
+use alloc::{ffi::NulError, vec::Vec};
 use thiserror_no_std::Error;
 
 #[derive(Error, Debug)]
@@ -155,4 +156,82 @@ pub enum HypervisorError {
 
     #[error("Failed to parse hexadecimal string")]
     HexParseError,
+
+    #[error("Failed to translate guest virtual address")]
+    GuestAddressTranslationFailed,
+
+    #[error("Too many calls are pending a return")]
+    TooManyPendingReturns,
+
+    #[error("Invalid PE image")]
+    InvalidPeImage,
+
+    #[error("Export not found")]
+    ExportNotFound,
+
+    #[error("Syscall number not found")]
+    SyscallNumberNotFound,
+
+    #[error("SSDT entry not found")]
+    SsdtEntryNotFound,
+
+    #[error("Session process not found")]
+    SessionProcessNotFound,
+
+    #[error("Win32k is not loaded")]
+    Win32kNotLoaded,
+
+    #[error("Module not found")]
+    ModuleNotFound,
+
+    #[error("Pattern matches are ambiguous")]
+    AmbiguousPattern,
+
+    #[error("Invalid PDB")]
+    InvalidPdb,
+
+    #[error("Invalid symbols")]
+    InvalidSymbols,
+
+    #[error("Symbols don't match the kernel")]
+    SymbolsMismatch,
+
+    #[error("Symbol not found")]
+    SymbolNotFound,
+
+    #[error("Registry value not found")]
+    RegistryValueNotFound,
+
+    #[error("RtlGetVersion failed")]
+    RtlGetVersionFailed,
+
+    #[error("Unsupported Windows build")]
+    UnsupportedWindowsBuild,
+
+    #[error("VM Fail Valid: {0}")]
+    VmFailValid(VmInstructionError),
+
+    #[error("Invalid VMCS snapshot")]
+    InvalidVmcsSnapshot,
+
+    #[error("The guest can't be resumed after the VM exit")]
+    UnrecoverableVmExit,
+
+    #[error("Invalid exit qualification")]
+    InvalidExitQualification,
+
+    #[error("Invalid VM-exit instruction information")]
+    InvalidInstructionInformation,
+
+    #[error("Too many CR3-target values")]
+    TooManyCr3Targets,
+
+    #[error("Jmp hooks require a guest-side handler")]
+    UnsupportedHookHandler,
+
+    #[error("Guest page access rights violated")]
+    GuestPageProtectionViolation,
+
+    #[error("The VMCS violates {} VM-entry checks", .0.len())]
+    VmEntryChecksFailed(Vec<VmEntryViolation>),
 }
@@ -0,0 +1,267 @@
+//! Provides an address-space monitor based on CR3-load exiting.
+//!
+//! Every `MOV CR3` of the guest causes a VM exit, and the monitor calls the registered callbacks with the
+//! old and the new CR3, which tells when the guest switches processes. The switches to the values of the
+//! CR3-target list don't cause VM exits, so they aren't reported, which avoids the cost of the VM exits for
+//! the address spaces that don't need to be monitored.
+//!
+//! The callbacks are registered before the processors are virtualized, and CR3-load exiting is only
+//! enabled if there is at least one callback.
+//!
+//! A CR3 load is only reported if it changes the address space. By default, the address space of a CR3 is
+//! its page-table base, without the PCID (bits 11:0) and the bit 63 that preserves the TLB entries of the
+//! PCID. With KVA shadowing (KPTI), each process has two page tables, `DirectoryTableBase` for the kernel
+//! and `UserDirectoryTableBase` for user mode, and the guest flips between them on every system call and
+//! interrupt from user mode. Registering a resolver that maps the `UserDirectoryTableBase` of a process
+//! to its `DirectoryTableBase` pairs them, so that the flips aren't reported.
+//!
+//! Filtering the flips doesn't make them free: every `MOV CR3` still causes a VM exit, so a system call
+//! costs two VM exits, plus an INVVPID of the VPID on each flip that doesn't set bit 63, for example if
+//! the guest doesn't use PCIDs. Monitoring address-space switches on a KPTI guest therefore slows down
+//! every system call.
+
+use {
+    crate::{error::HypervisorError, intel::vmcs_access::VmcsAccess},
+    alloc::{boxed::Box, vec::Vec},
+    core::sync::atomic::{AtomicU64, Ordering},
+    x86::msr::{rdmsr, IA32_VMX_MISC},
+};
+
+/// The number of CR3-target values in the VMCS.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 25.6.7 CR3-Target Controls
+pub const MAX_CR3_TARGETS: usize = 4;
+
+/// An address-space switch of the guest, passed to the callbacks.
+pub struct AddressSpaceSwitch<'a> {
+    /// The CR3 before the switch.
+    pub old_cr3: u64,
+
+    /// The CR3 after the switch, without the bit 63 that preserves the TLB entries of the PCID.
+    pub new_cr3: u64,
+
+    /// The VMCS of the guest, which already contains the new CR3. The callbacks can, for example, switch
+    /// the EPT pointer to a view of the new process.
+    pub vmcs: &'a mut dyn VmcsAccess,
+}
+
+/// A callback called in VMX root operation on each address-space switch.
+pub type AddressSpaceCallback = Box<dyn Fn(&mut AddressSpaceSwitch) + Send + Sync>;
+
+/// A resolver called in VMX root operation to map a CR3, without bit 63, to its address space.
+pub type AddressSpaceResolver = Box<dyn Fn(u64) -> u64 + Send + Sync>;
+
+/// The PCID of a CR3 (bits 11:0), or its PWT and PCD flags if CR4.PCIDE is 0.
+const CR3_PCID: u64 = 0xFFF;
+
+/// The bit of the value written to CR3 that preserves the TLB entries of the PCID if CR4.PCIDE is set.
+pub const CR3_NO_FLUSH: u64 = 1 << 63;
+
+/// Returns the page-table base of a CR3, which is its address space by default.
+pub fn directory_table_base(cr3: u64) -> u64 {
+    cr3 & !(CR3_PCID | CR3_NO_FLUSH)
+}
+
+/// Monitors the address-space switches of the guest through CR3-load exiting.
+pub struct AddressSpaceMonitor {
+    /// The callbacks, in registration order.
+    callbacks: Vec<AddressSpaceCallback>,
+
+    /// The CR3 values whose loads don't cause VM exits.
+    cr3_targets: Vec<u64>,
+
+    /// Maps the CR3 values to their address spaces, `directory_table_base` if no resolver is registered.
+    resolver: Option<AddressSpaceResolver>,
+
+    /// The number of reported switches.
+    switches: AtomicU64,
+}
+
+impl AddressSpaceMonitor {
+    /// Creates a new `AddressSpaceMonitor` instance without callbacks.
+    pub fn new() -> Self {
+        Self {
+            callbacks: Vec::new(),
+            cr3_targets: Vec::new(),
+            resolver: None,
+            switches: AtomicU64::new(0),
+        }
+    }
+
+    /// Registers a callback called on each address-space switch.
+    ///
+    /// Registering a callback enables CR3-load exiting, so every `MOV CR3` of the guest outside of the
+    /// CR3-target list causes a VM exit, including the KPTI flips that aren't reported.
+    ///
+    /// # Arguments
+    ///
+    /// * `callback` - The callback, which is called in VMX root operation and must not allocate.
+    pub fn on_switch(
+        &mut self,
+        callback: impl Fn(&mut AddressSpaceSwitch) + Send + Sync + 'static,
+    ) {
+        self.callbacks.push(Box::new(callback));
+    }
+
+    /// Registers the resolver that maps a CR3 to its address space, replacing `directory_table_base`.
+    ///
+    /// The CR3 loads that don't change the address space aren't reported. On a KPTI guest, the resolver
+    /// maps the `UserDirectoryTableBase` of each process to its `DirectoryTableBase`, so that the flips
+    /// between them on system calls aren't reported.
+    ///
+    /// # Arguments
+    ///
+    /// * `resolver` - The resolver, which is called in VMX root operation on each CR3 load and must not
+    ///   allocate.
+    pub fn resolve_address_spaces(
+        &mut self,
+        resolver: impl Fn(u64) -> u64 + Send + Sync + 'static,
+    ) {
+        self.resolver = Some(Box::new(resolver));
+    }
+
+    /// Returns the address space of a CR3, according to the resolver.
+    pub fn address_space(&self, cr3: u64) -> u64 {
+        match &self.resolver {
+            Some(resolver) => resolver(cr3 & !CR3_NO_FLUSH),
+            None => directory_table_base(cr3),
+        }
+    }
+
+    /// Adds a CR3 value to the CR3-target list, so that switching to it doesn't cause a VM exit.
+    ///
+    /// # Arguments
+    ///
+    /// * `cr3` - The CR3 value, which must match the loaded value exactly, including the PCID.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), HypervisorError>` - `TooManyCr3Targets` if the processor doesn't support more CR3-target values.
+    ///
+    /// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: A.6 MISCELLANEOUS DATA
+    pub fn add_cr3_target(&mut self, cr3: u64) -> Result<(), HypervisorError> {
+        // The bits 24:16 of IA32_VMX_MISC are the number of CR3-target values supported.
+        let supported = ((unsafe { rdmsr(IA32_VMX_MISC) } >> 16) & 0x1FF) as usize;
+
+        if self.cr3_targets.contains(&cr3) {
+            return Ok(());
+        }
+
+        if self.cr3_targets.len() >= supported.min(MAX_CR3_TARGETS) {
+            return Err(HypervisorError::TooManyCr3Targets);
+        }
+
+        self.cr3_targets.push(cr3);
+
+        Ok(())
+    }
+
+    /// Returns the CR3-target list.
+    pub fn cr3_targets(&self) -> &[u64] {
+        &self.cr3_targets
+    }
+
+    /// Checks whether CR3-load exiting is needed, which is the case if a callback is registered.
+    pub fn is_enabled(&self) -> bool {
+        !self.callbacks.is_empty()
+    }
+
+    /// Calls the callbacks with an address-space switch, unless the old and the new CR3 belong to the
+    /// same address space.
+    ///
+    /// # Arguments
+    ///
+    /// * `switch` - The address-space switch.
+    pub fn notify(&self, switch: &mut AddressSpaceSwitch) {
+        if self.callbacks.is_empty()
+            || self.address_space(switch.old_cr3) == self.address_space(switch.new_cr3)
+        {
+            return;
+        }
+
+        log::trace!(
+            "Address-space switch from {:#x} to {:#x}",
+            switch.old_cr3,
+            switch.new_cr3
+        );
+
+        self.switches.fetch_add(1, Ordering::Relaxed);
+
+        for callback in &self.callbacks {
+            callback(switch);
+        }
+    }
+
+    /// Returns the number of reported address-space switches.
+    pub fn switches(&self) -> u64 {
+        self.switches.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for AddressSpaceMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::intel::vmcs_access::SoftVmcs};
+
+    /// The page tables of a process with KVA shadowing.
+    const KERNEL_CR3: u64 = 0x1AD000;
+    const USER_CR3: u64 = 0x1AE000;
+
+    /// Notifies a CR3 load and returns whether it was reported.
+    fn load(monitor: &AddressSpaceMonitor, old_cr3: u64, new_cr3: u64) -> bool {
+        let switches = monitor.switches();
+
+        monitor.notify(&mut AddressSpaceSwitch {
+            old_cr3,
+            new_cr3,
+            vmcs: &mut SoftVmcs::new(),
+        });
+
+        monitor.switches() != switches
+    }
+
+    fn monitor() -> AddressSpaceMonitor {
+        let mut monitor = AddressSpaceMonitor::new();
+        monitor.on_switch(|_| {});
+        monitor
+    }
+
+    #[test]
+    fn switches_are_reported() {
+        assert!(load(&monitor(), KERNEL_CR3, 0x2000));
+    }
+
+    #[test]
+    fn pcid_changes_are_not_switches() {
+        let monitor = monitor();
+
+        assert!(!load(&monitor, KERNEL_CR3 | 2, KERNEL_CR3 | 1));
+        assert!(!load(&monitor, KERNEL_CR3, KERNEL_CR3));
+    }
+
+    #[test]
+    fn kpti_flips_are_not_switches_with_a_resolver() {
+        let mut monitor = monitor();
+
+        assert!(load(&monitor, KERNEL_CR3 | 2, USER_CR3 | 1));
+
+        monitor.resolve_address_spaces(|cr3| match directory_table_base(cr3) {
+            USER_CR3 => KERNEL_CR3,
+            base => base,
+        });
+
+        assert!(!load(&monitor, KERNEL_CR3 | 2, USER_CR3 | 1));
+        assert!(!load(&monitor, USER_CR3 | 1, KERNEL_CR3 | 2));
+        assert!(load(&monitor, KERNEL_CR3 | 2, 0x2000 | 2));
+    }
+
+    #[test]
+    fn nothing_is_reported_without_callbacks() {
+        assert!(!load(&AddressSpaceMonitor::new(), KERNEL_CR3, 0x2000));
+    }
+}
@@ -24,7 +24,7 @@ pub enum VmxControl {
 /// # Returns
 ///
 /// Returns the adjusted control value based on system capabilities and the requested value.
-pub fn adjust_vmx_controls(control: VmxControl, requested_value: u64) -> u64 {
+pub fn adjust_vmx_controls(control: VmxControl, requested_value: u32) -> u32 {
     const IA32_VMX_BASIC_VMX_CONTROLS_FLAG: u64 = 1 << 55;
 
     let vmx_basic = unsafe { msr::rdmsr(msr::IA32_VMX_BASIC) };
@@ -46,8 +46,8 @@ pub fn adjust_vmx_controls(control: VmxControl, requested_value: u64) -> u64 {
     let capabilities = unsafe { msr::rdmsr(cap_msr) };
     let allowed0 = capabilities as u32;
     let allowed1 = (capabilities >> 32) as u32;
-    let mut effective_value = u32::try_from(requested_value).unwrap();
+    let mut effective_value = requested_value;
     effective_value |= allowed0;
     effective_value &= allowed1;
-    u64::from(effective_value)
+    effective_value
 }
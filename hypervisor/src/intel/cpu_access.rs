@@ -0,0 +1,334 @@
+//! Provides an abstraction over the instructions that the VM-exit handlers execute in VMX root operation.
+//!
+//! The handlers execute CPUID, RDMSR, WRMSR, XSETBV, RDTSC, INVEPT, INVVPID, IN and OUT, and access CR2
+//! and DR6, through `CpuAccess` instead of executing them directly. `HardwareCpu` executes the instructions on the
+//! current processor, while `SoftCpu` answers them from scripted values and records the writes and
+//! invalidations, so the handlers can be exercised outside of VMX root operation together with `SoftVmcs`.
+
+use {
+    crate::{
+        intel::{
+            invept::invept_all_contexts,
+            invvpid::{invvpid_all_contexts, invvpid_individual_address, invvpid_single_context},
+        },
+        utils::instructions::{
+            cr2_write, cr4, cr4_write, dr6, dr6_write, inb, inl, inw, outb, outl, outw, rdmsr,
+            rdtsc, wrmsr, xsetbv,
+        },
+    },
+    alloc::{
+        collections::{BTreeMap, VecDeque},
+        vec::Vec,
+    },
+    x86::{
+        controlregs::{Cr4, Xcr0},
+        cpuid::{cpuid, CpuIdResult},
+    },
+};
+
+/// An invalidation of cached translations, as recorded by `SoftCpu`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Invalidation {
+    /// INVEPT of all the EPT contexts.
+    EptAllContexts,
+    /// INVVPID of all the VPIDs.
+    VpidAllContexts,
+    /// INVVPID of the translations of a VPID.
+    VpidSingleContext(u16),
+    /// INVVPID of the translations of a linear address for a VPID.
+    VpidIndividualAddress(u16, u64),
+}
+
+/// Executes the instructions that the VM-exit handlers use on behalf of the guest.
+pub trait CpuAccess {
+    /// Executes CPUID with a leaf and sub-leaf.
+    fn cpuid(&self, leaf: u32, sub_leaf: u32) -> CpuIdResult;
+
+    /// Reads an MSR.
+    fn rdmsr(&self, msr: u32) -> u64;
+
+    /// Writes an MSR.
+    fn wrmsr(&mut self, msr: u32, value: u64);
+
+    /// Writes an extended control register.
+    fn xsetbv(&mut self, xcr: u32, value: u64);
+
+    /// Reads the time-stamp counter.
+    fn rdtsc(&self) -> u64;
+
+    /// Invalidates the cached translations of all the EPT contexts.
+    fn invept_all_contexts(&mut self);
+
+    /// Invalidates the cached translations of all the VPIDs.
+    fn invvpid_all_contexts(&mut self);
+
+    /// Invalidates the cached translations of a VPID.
+    fn invvpid_single_context(&mut self, vpid: u16);
+
+    /// Invalidates the cached translations of a linear address for a VPID.
+    fn invvpid_individual_address(&mut self, vpid: u16, linear_address: u64);
+
+    /// Writes CR2, which isn't part of the guest-state area, so the guest receives the value of the
+    /// processor. It's the faulting address of a page fault injected into the guest.
+    fn write_cr2(&mut self, value: u64);
+
+    /// Reads DR6, which isn't part of the guest-state area either.
+    fn read_dr6(&self) -> u64;
+
+    /// Writes DR6, which holds the conditions of a debug exception injected into the guest.
+    fn write_dr6(&mut self, value: u64);
+
+    /// Reads a value of 1, 2 or 4 bytes from an I/O port.
+    fn port_in(&mut self, port: u16, size: u8) -> u32;
+
+    /// Writes a value of 1, 2 or 4 bytes to an I/O port.
+    fn port_out(&mut self, port: u16, size: u8, value: u32);
+}
+
+/// Executes the instructions on the current processor.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HardwareCpu;
+
+impl CpuAccess for HardwareCpu {
+    fn cpuid(&self, leaf: u32, sub_leaf: u32) -> CpuIdResult {
+        cpuid!(leaf, sub_leaf)
+    }
+
+    fn rdmsr(&self, msr: u32) -> u64 {
+        rdmsr(msr)
+    }
+
+    fn wrmsr(&mut self, msr: u32, value: u64) {
+        wrmsr(msr, value)
+    }
+
+    /// Writes XCR0, enabling the OS XSAVE feature in CR4 first. The other extended control registers
+    /// aren't supported.
+    fn xsetbv(&mut self, _xcr: u32, value: u64) {
+        cr4_write(cr4() | Cr4::CR4_ENABLE_OS_XSAVE);
+        xsetbv(Xcr0::from_bits_truncate(value));
+    }
+
+    fn rdtsc(&self) -> u64 {
+        rdtsc()
+    }
+
+    fn invept_all_contexts(&mut self) {
+        invept_all_contexts()
+    }
+
+    fn invvpid_all_contexts(&mut self) {
+        invvpid_all_contexts()
+    }
+
+    fn invvpid_single_context(&mut self, vpid: u16) {
+        invvpid_single_context(vpid)
+    }
+
+    fn invvpid_individual_address(&mut self, vpid: u16, linear_address: u64) {
+        invvpid_individual_address(vpid, linear_address)
+    }
+
+    fn write_cr2(&mut self, value: u64) {
+        cr2_write(value)
+    }
+
+    fn read_dr6(&self) -> u64 {
+        dr6()
+    }
+
+    fn write_dr6(&mut self, value: u64) {
+        dr6_write(value)
+    }
+
+    fn port_in(&mut self, port: u16, size: u8) -> u32 {
+        match size {
+            1 => inb(port) as u32,
+            2 => inw(port) as u32,
+            _ => inl(port),
+        }
+    }
+
+    fn port_out(&mut self, port: u16, size: u8, value: u32) {
+        match size {
+            1 => outb(port, value as u8),
+            2 => outw(port, value as u16),
+            _ => outl(port, value),
+        }
+    }
+}
+
+/// A processor whose instructions are answered from scripted values.
+///
+/// CPUID leaves, MSRs and I/O ports that weren't scripted read as 0. Written MSRs, extended control
+/// registers, CR2 and DR6 are stored, and the invalidations and the writes to I/O ports are recorded in order.
+#[derive(Clone, Default)]
+pub struct SoftCpu {
+    /// The results of the scripted CPUID leaves, keyed by leaf and sub-leaf.
+    cpuid: BTreeMap<(u32, u32), CpuIdResult>,
+
+    /// The values of the MSRs, keyed by their addresses.
+    msrs: BTreeMap<u32, u64>,
+
+    /// The values of the written extended control registers.
+    xcrs: BTreeMap<u32, u64>,
+
+    /// The value of the time-stamp counter.
+    tsc: u64,
+
+    /// The invalidations, in execution order.
+    invalidations: Vec<Invalidation>,
+
+    /// The value of CR2.
+    cr2: u64,
+
+    /// The value of DR6.
+    dr6: u64,
+
+    /// The values returned by the reads of the I/O ports, in order, keyed by the ports.
+    port_inputs: BTreeMap<u16, VecDeque<u32>>,
+
+    /// The writes to I/O ports as port, size and value, in execution order.
+    port_outputs: Vec<(u16, u8, u32)>,
+}
+
+impl SoftCpu {
+    /// Creates a processor without any scripted value.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Scripts the result of a CPUID leaf and sub-leaf.
+    pub fn with_cpuid(mut self, leaf: u32, sub_leaf: u32, result: CpuIdResult) -> Self {
+        self.cpuid.insert((leaf, sub_leaf), result);
+        self
+    }
+
+    /// Scripts the value of an MSR.
+    pub fn with_msr(mut self, msr: u32, value: u64) -> Self {
+        self.msrs.insert(msr, value);
+        self
+    }
+
+    /// Scripts the value of the time-stamp counter.
+    pub fn with_tsc(mut self, tsc: u64) -> Self {
+        self.tsc = tsc;
+        self
+    }
+
+    /// Scripts the value of DR6.
+    pub fn with_dr6(mut self, dr6: u64) -> Self {
+        self.dr6 = dr6;
+        self
+    }
+
+    /// Scripts the values returned by the next reads of an I/O port.
+    pub fn with_port_inputs(mut self, port: u16, values: &[u32]) -> Self {
+        self.port_inputs
+            .entry(port)
+            .or_default()
+            .extend(values.iter().copied());
+        self
+    }
+
+    /// Returns the value of an MSR, or `None` if it was neither scripted nor written.
+    pub fn msr(&self, msr: u32) -> Option<u64> {
+        self.msrs.get(&msr).copied()
+    }
+
+    /// Returns the value of an extended control register, or `None` if it was never written.
+    pub fn xcr(&self, xcr: u32) -> Option<u64> {
+        self.xcrs.get(&xcr).copied()
+    }
+
+    /// Returns the invalidations, in execution order.
+    pub fn invalidations(&self) -> &[Invalidation] {
+        &self.invalidations
+    }
+
+    /// Returns the value of CR2.
+    pub fn cr2(&self) -> u64 {
+        self.cr2
+    }
+
+    /// Returns the value of DR6.
+    pub fn dr6(&self) -> u64 {
+        self.dr6
+    }
+
+    /// Returns the writes to I/O ports as port, size and value, in execution order.
+    pub fn port_outputs(&self) -> &[(u16, u8, u32)] {
+        &self.port_outputs
+    }
+}
+
+impl CpuAccess for SoftCpu {
+    fn cpuid(&self, leaf: u32, sub_leaf: u32) -> CpuIdResult {
+        self.cpuid
+            .get(&(leaf, sub_leaf))
+            .copied()
+            .unwrap_or(CpuIdResult {
+                eax: 0,
+                ebx: 0,
+                ecx: 0,
+                edx: 0,
+            })
+    }
+
+    fn rdmsr(&self, msr: u32) -> u64 {
+        self.msr(msr).unwrap_or_default()
+    }
+
+    fn wrmsr(&mut self, msr: u32, value: u64) {
+        self.msrs.insert(msr, value);
+    }
+
+    fn xsetbv(&mut self, xcr: u32, value: u64) {
+        self.xcrs.insert(xcr, value);
+    }
+
+    fn rdtsc(&self) -> u64 {
+        self.tsc
+    }
+
+    fn invept_all_contexts(&mut self) {
+        self.invalidations.push(Invalidation::EptAllContexts);
+    }
+
+    fn invvpid_all_contexts(&mut self) {
+        self.invalidations.push(Invalidation::VpidAllContexts);
+    }
+
+    fn invvpid_single_context(&mut self, vpid: u16) {
+        self.invalidations
+            .push(Invalidation::VpidSingleContext(vpid));
+    }
+
+    fn invvpid_individual_address(&mut self, vpid: u16, linear_address: u64) {
+        self.invalidations
+            .push(Invalidation::VpidIndividualAddress(vpid, linear_address));
+    }
+
+    fn write_cr2(&mut self, value: u64) {
+        self.cr2 = value;
+    }
+
+    fn read_dr6(&self) -> u64 {
+        self.dr6
+    }
+
+    fn write_dr6(&mut self, value: u64) {
+        self.dr6 = value;
+    }
+
+    fn port_in(&mut self, port: u16, _size: u8) -> u32 {
+        self.port_inputs
+            .get_mut(&port)
+            .and_then(|values| values.pop_front())
+            .unwrap_or_default()
+    }
+
+    fn port_out(&mut self, port: u16, size: u8, value: u32) {
+        self.port_outputs.push((port, size, value));
+    }
+}
@@ -5,18 +5,25 @@
 //!
 //! Credits to Matthias: https://github.com/not-matthias/amd_hypervisor/blob/main/hypervisor/src/hook.rs
 
+use {
+    crate::utils::{
+        addresses::PhysicalAddress, function_hook::FunctionHook, return_hook::ReturnHooks,
+    },
+    alloc::{boxed::Box, vec::Vec},
+};
+#[cfg(windows)]
 use {
     crate::{
         error::HypervisorError,
         intel::ept::paging::{AccessType, Ept},
         utils::{
-            addresses::PhysicalAddress,
             alloc::PhysicalAllocator,
-            function_hook::FunctionHook,
-            nt::{get_ntoskrnl_export, RtlCopyMemory},
+            function_hook::{HookCallback, HookHandler, BP_SHELLCODE_LEN, DEFAULT_PRIORITY},
+            nt::{find_function, resolve_export, RtlCopyMemory},
+            return_hook::ReturnCallback,
+            ssdt::sys_info::Sysinfo,
         },
     },
-    alloc::{boxed::Box, vec::Vec},
     x86::current::paging::{PAddr, VAddr, BASE_PAGE_SIZE},
     x86_64::instructions::interrupts::without_interrupts,
 };
@@ -57,6 +64,8 @@ pub struct Hook {
     pub hook_type: HookType,
 }
 
+/// Hooks are only created in the kernel, where the hooked functions and pages are resolved.
+#[cfg(windows)]
 impl Hook {
     /// Creates a copy of a page in memory.
     ///
@@ -110,6 +119,67 @@ impl Hook {
         page_start + base_offset
     }
 
+    /// Computes the number of bytes at the start of a function that may be relocated into the trampoline.
+    ///
+    /// The relocated instructions are read from the copied page, so they are bounded by the end of the page. If the
+    /// exception directory of the module describes the function, they are also bounded by its prologue when it covers
+    /// the breakpoint, and by the end of the function otherwise. Instructions of the prologue are never the target of
+    /// a branch within the function, so relocating them is always safe.
+    ///
+    /// # Arguments
+    ///
+    /// * `sys_info` - The loaded modules.
+    /// * `address` - The address of the function.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<usize>` - The number of bytes that may be relocated, or `None` if the address is in the middle of a function.
+    fn relocation_limit(sys_info: &Sysinfo, address: u64) -> Option<usize> {
+        let available = BASE_PAGE_SIZE - VAddr::from(address).base_page_offset() as usize;
+
+        let (base, function) = match find_function(sys_info, address) {
+            Ok(Some(function)) => function,
+            Ok(None) => {
+                log::debug!("No unwind information for {:#x}", address);
+                return Some(available);
+            }
+            Err(e) => {
+                log::warn!("Failed to find the function at {:#x}: {}", address, e);
+                return Some(available);
+            }
+        };
+
+        let function_start = base + function.begin_address as u64;
+        if function_start != address {
+            log::error!(
+                "{:#x} is not the start of a function (the function starts at {:#x})",
+                address,
+                function_start
+            );
+            return None;
+        }
+
+        let Some(size) = function.size() else {
+            log::error!("Invalid boundaries of the function at {:#x}", address);
+            return None;
+        };
+
+        log::debug!(
+            "Function size: {:#x}, prologue size: {:#x}",
+            size,
+            function.prologue_size
+        );
+
+        let prologue_size = function.prologue_size as usize;
+        let limit = if prologue_size >= BP_SHELLCODE_LEN {
+            prologue_size
+        } else {
+            size as usize
+        };
+
+        Some(limit.min(available))
+    }
+
     /// Creates a hook on a function by its pointer.
     ///
     /// This function sets up a hook directly using the function's pointer. It copies the page where the function resides,
@@ -117,13 +187,115 @@ impl Hook {
     ///
     /// # Arguments
     ///
+    /// * `sys_info` - The loaded modules.
     /// * `function_ptr` - The pointer to the function to be hooked.
     /// * `handler` - A pointer to the handler function that will be called instead of the original function.
     ///
     /// # Returns
     ///
     /// * `Option<Self>` - An instance of `Hook` if successful, or `None` if an error occurred.
-    pub fn hook_function_ptr(function_ptr: u64, handler: *const ()) -> Option<Self> {
+    pub fn hook_function_ptr(
+        sys_info: &Sysinfo,
+        function_ptr: u64,
+        handler: *const (),
+    ) -> Option<Self> {
+        Self::hook_function_ptr_with_handler(
+            sys_info,
+            function_ptr,
+            HookHandler::Redirect(handler as u64),
+        )
+    }
+
+    /// Creates a hook on a function by its pointer, whose callback runs in VMX root operation.
+    ///
+    /// # Arguments
+    ///
+    /// * `sys_info` - The loaded modules.
+    /// * `function_ptr` - The pointer to the function to be hooked.
+    /// * `callback` - The callback executed on the breakpoint VM exit.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Self>` - An instance of `Hook` if successful, or `None` if an error occurred.
+    pub fn hook_function_ptr_with_callback(
+        sys_info: &Sysinfo,
+        function_ptr: u64,
+        callback: HookCallback,
+    ) -> Option<Self> {
+        Self::hook_function_ptr_with_handler(
+            sys_info,
+            function_ptr,
+            HookHandler::Callback(callback),
+        )
+    }
+
+    /// Creates a hook on a function by its pointer, whose callbacks run in VMX root operation on entry and on return.
+    ///
+    /// # Arguments
+    ///
+    /// * `sys_info` - The loaded modules.
+    /// * `function_ptr` - The pointer to the function to be hooked.
+    /// * `entry` - The callback executed on function entry.
+    /// * `exit` - The callback executed when the function returns.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Self>` - An instance of `Hook` if successful, or `None` if an error occurred.
+    pub fn hook_function_ptr_with_return_callback(
+        sys_info: &Sysinfo,
+        function_ptr: u64,
+        entry: HookCallback,
+        exit: ReturnCallback,
+    ) -> Option<Self> {
+        Self::hook_function_ptr_with_handler(
+            sys_info,
+            function_ptr,
+            HookHandler::CallbackWithReturn { entry, exit },
+        )
+    }
+
+    /// Creates a hook on a function by its pointer with the given handler, registered with the default priority.
+    ///
+    /// # Arguments
+    ///
+    /// * `sys_info` - The loaded modules.
+    /// * `function_ptr` - The pointer to the function to be hooked.
+    /// * `handler` - The handler invoked when the hook is hit.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Self>` - An instance of `Hook` if successful, or `None` if the pointer isn't the start of a function or an error occurred.
+    pub fn hook_function_ptr_with_handler(
+        sys_info: &Sysinfo,
+        function_ptr: u64,
+        handler: HookHandler,
+    ) -> Option<Self> {
+        Self::hook_function_ptr_with_priority(sys_info, function_ptr, DEFAULT_PRIORITY, handler)
+    }
+
+    /// Creates a hook on a function by its pointer with the given handler and priority.
+    ///
+    /// The pointer is checked against the exception directory of the module containing it. Pointers into
+    /// the middle of a function are rejected, and the relocated prologue is bounded by the prologue or the end of the function.
+    ///
+    /// # Arguments
+    ///
+    /// * `sys_info` - The loaded modules.
+    /// * `function_ptr` - The pointer to the function to be hooked.
+    /// * `priority` - The priority of the handler in the handler chain. Handlers with a higher priority run first.
+    /// * `handler` - The handler invoked when the hook is hit.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Self>` - An instance of `Hook` if successful, or `None` if the pointer isn't the start of a function or an error occurred.
+    pub fn hook_function_ptr_with_priority(
+        sys_info: &Sysinfo,
+        function_ptr: u64,
+        priority: i32,
+        handler: HookHandler,
+    ) -> Option<Self> {
+        let relocation_limit = Self::relocation_limit(sys_info, function_ptr)?;
+
         let original_pa = PhysicalAddress::from_va(function_ptr);
 
         // Copy the page where the function resides to prevent modifying the original page.
@@ -135,7 +307,9 @@ impl Hook {
         let hook_va = Self::address_in_page(page_va, function_ptr);
         let hook_pa = PhysicalAddress::from_va(hook_va);
 
-        log::debug!("Handler address: {:#x}", handler as u64);
+        if let HookHandler::Redirect(handler) = handler {
+            log::debug!("Handler address: {:#x}", handler);
+        }
 
         log::debug!("Original virtual address: {:#x}", function_ptr);
         log::debug!("Original physical address: {:#x}", original_pa.as_u64());
@@ -147,7 +321,8 @@ impl Hook {
         log::debug!("Hook physical address: {:#x}", hook_pa.as_u64());
 
         // Create an inline hook at the new address in the copied page.
-        let inline_hook = FunctionHook::new(function_ptr, hook_va, handler)?;
+        let inline_hook =
+            FunctionHook::new(function_ptr, hook_va, priority, handler, relocation_limit)?;
 
         Some(Self {
             original_va: function_ptr,
@@ -168,26 +343,122 @@ impl Hook {
     ///
     /// # Arguments
     ///
-    /// * `function_name` - The name of the function to be hooked.
+    /// * `sys_info` - The loaded modules.
+    /// * `function_name` - The name of the function to be hooked (`function` or `module!function`).
     /// * `handler` - A pointer to the handler function.
     ///
     /// # Returns
     ///
     /// * `Option<Self>` - An instance of `Hook` if successful, or `None` if the function cannot be found or an error occurred.
-    pub fn hook_function(function_name: &str, handler: *const ()) -> Option<Self> {
-        // Obtain the address of the NT kernel function by its name.
-        let address = get_ntoskrnl_export(function_name);
+    pub fn hook_function(
+        sys_info: &Sysinfo,
+        function_name: &str,
+        handler: *const (),
+    ) -> Option<Self> {
+        Self::hook_function_with_handler(
+            sys_info,
+            function_name,
+            HookHandler::Redirect(handler as u64),
+        )
+    }
 
-        // Error handling if the function address could not be retrieved.
-        if address.is_null() {
-            log::error!("Failed to find function: {}", function_name);
-            return None;
-        }
+    /// Creates a hook on a function by its name, whose callback runs in VMX root operation.
+    ///
+    /// # Arguments
+    ///
+    /// * `sys_info` - The loaded modules.
+    /// * `function_name` - The name of the function to be hooked (`function` or `module!function`).
+    /// * `callback` - The callback executed on the breakpoint VM exit.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Self>` - An instance of `Hook` if successful, or `None` if the function cannot be found or an error occurred.
+    pub fn hook_function_with_callback(
+        sys_info: &Sysinfo,
+        function_name: &str,
+        callback: HookCallback,
+    ) -> Option<Self> {
+        Self::hook_function_with_handler(sys_info, function_name, HookHandler::Callback(callback))
+    }
+
+    /// Creates a hook on a function by its name, whose callbacks run in VMX root operation on entry and on return.
+    ///
+    /// # Arguments
+    ///
+    /// * `sys_info` - The loaded modules.
+    /// * `function_name` - The name of the function to be hooked (`function` or `module!function`).
+    /// * `entry` - The callback executed on function entry.
+    /// * `exit` - The callback executed when the function returns.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Self>` - An instance of `Hook` if successful, or `None` if the function cannot be found or an error occurred.
+    pub fn hook_function_with_return_callback(
+        sys_info: &Sysinfo,
+        function_name: &str,
+        entry: HookCallback,
+        exit: ReturnCallback,
+    ) -> Option<Self> {
+        Self::hook_function_with_handler(
+            sys_info,
+            function_name,
+            HookHandler::CallbackWithReturn { entry, exit },
+        )
+    }
+
+    /// Creates a hook on a function by its name with the given handler.
+    ///
+    /// # Arguments
+    ///
+    /// * `sys_info` - The loaded modules.
+    /// * `function_name` - The name of the function to be hooked, either an export of ntoskrnl.exe or `module!function` for the exports of other loaded modules.
+    /// * `handler` - The handler invoked when the hook is hit.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Self>` - An instance of `Hook` if successful, or `None` if the function cannot be found or an error occurred.
+    pub fn hook_function_with_handler(
+        sys_info: &Sysinfo,
+        function_name: &str,
+        handler: HookHandler,
+    ) -> Option<Self> {
+        // Obtain the address of the exported function by its name.
+        let address = match resolve_export(function_name) {
+            Ok(address) => address,
+            Err(e) => {
+                log::error!("Failed to find function {}: {}", function_name, e);
+                return None;
+            }
+        };
 
-        log::debug!("Function to be hooked: {} {:p}", function_name, address);
+        log::debug!("Function to be hooked: {} {:#x}", function_name, address);
 
         // Utilize the previously defined function for hooking by address.
-        Self::hook_function_ptr(address as u64, handler)
+        Self::hook_function_ptr_with_handler(sys_info, address, handler)
+    }
+
+    /// Adds a handler to the handler chain of a function hook.
+    ///
+    /// # Arguments
+    ///
+    /// * `priority` - The priority of the handler. Handlers with a higher priority run first.
+    /// * `handler` - The handler to add.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), HypervisorError>` - `Ok` if the handler was added, or an error if the hook isn't a function hook.
+    pub fn add_handler(
+        &mut self,
+        priority: i32,
+        handler: HookHandler,
+    ) -> Result<(), HypervisorError> {
+        match &mut self.hook_type {
+            HookType::Function { inline_hook } => {
+                inline_hook.add_handler(priority, handler);
+                Ok(())
+            }
+            HookType::Page => Err(HypervisorError::HookError),
+        }
     }
 
     /// Creates a hook on a specific page.
@@ -231,20 +502,147 @@ impl Hook {
 pub struct HookManager {
     /// A collection of hooks managed by the HookManager.
     pub hooks: Vec<Hook>,
+
+    /// The calls of function hooks pending a return.
+    pub return_hooks: ReturnHooks,
 }
 
 impl HookManager {
     /// Constructs a new `HookManager` with a given set of hooks.
     ///
+    /// Function hooks on the same function are merged into a single hook, whose handler chain
+    /// contains the handlers of all of them.
+    ///
     /// # Arguments
     ///
     /// * `hooks` - A vector of `Hook` instances to be managed.
     pub fn new(hooks: Vec<Hook>) -> Box<Self> {
-        let hooks = Self { hooks };
-        let instance = Box::new(hooks);
+        let mut instance = Box::new(Self {
+            hooks: Vec::with_capacity(hooks.len()),
+            return_hooks: ReturnHooks::new(),
+        });
+
+        for hook in hooks {
+            instance.add_hook(hook);
+        }
+
         instance
     }
 
+    /// Hooks a function by its pointer with the given handler and registers the hook. Must be called
+    /// before the hooks are enabled.
+    ///
+    /// If a function hook on the same function already exists, the handler is added to its handler
+    /// chain, so independent modules can hook the same function without copying its page and building
+    /// a trampoline again.
+    ///
+    /// # Arguments
+    ///
+    /// * `sys_info` - The loaded modules, queried once for all the hooks that are registered.
+    /// * `function_ptr` - The pointer to the function to be hooked.
+    /// * `priority` - The priority of the handler. Handlers with a higher priority run first.
+    /// * `handler` - The handler invoked when the hook is hit.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<&Hook, HypervisorError>` - The hook the handler was registered with, or an error if the hook couldn't be created.
+    #[cfg(windows)]
+    pub fn hook_function_ptr(
+        &mut self,
+        sys_info: &Sysinfo,
+        function_ptr: u64,
+        priority: i32,
+        handler: HookHandler,
+    ) -> Result<&Hook, HypervisorError> {
+        let index = match self
+            .hooks
+            .iter()
+            .position(|hook| hook.original_va == function_ptr)
+        {
+            Some(index) => {
+                log::debug!(
+                    "Adding the handler to the existing hook at {:#x}",
+                    function_ptr
+                );
+                self.hooks[index].add_handler(priority, handler)?;
+                index
+            }
+            None => {
+                let hook = Hook::hook_function_ptr_with_priority(
+                    sys_info,
+                    function_ptr,
+                    priority,
+                    handler,
+                )
+                .ok_or(HypervisorError::HookError)?;
+                self.hooks.push(hook);
+                self.hooks.len() - 1
+            }
+        };
+
+        Ok(&self.hooks[index])
+    }
+
+    /// Hooks a function by its name with the given handler and registers the hook, similar to `hook_function_ptr`.
+    ///
+    /// # Arguments
+    ///
+    /// * `sys_info` - The loaded modules.
+    /// * `function_name` - The name of the function to be hooked, either an export of ntoskrnl.exe or `module!function` for the exports of other loaded modules.
+    /// * `priority` - The priority of the handler. Handlers with a higher priority run first.
+    /// * `handler` - The handler invoked when the hook is hit.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<&Hook, HypervisorError>` - The hook the handler was registered with, or an error if the function cannot be found or the hook couldn't be created.
+    #[cfg(windows)]
+    pub fn hook_function(
+        &mut self,
+        sys_info: &Sysinfo,
+        function_name: &str,
+        priority: i32,
+        handler: HookHandler,
+    ) -> Result<&Hook, HypervisorError> {
+        let address = resolve_export(function_name)?;
+
+        log::debug!("Function to be hooked: {} {:#x}", function_name, address);
+
+        self.hook_function_ptr(sys_info, address, priority, handler)
+    }
+
+    /// Adds a hook to the `HookManager`. Must be called before the hooks are enabled.
+    ///
+    /// If a function hook on the same function already exists, the handlers of the new hook are
+    /// added to its handler chain and the new hook is discarded. Prefer `hook_function_ptr` and
+    /// `hook_function`, which check for an existing hook before the page is copied and the
+    /// trampoline is built.
+    ///
+    /// # Arguments
+    ///
+    /// * `hook` - The hook to add.
+    pub fn add_hook(&mut self, hook: Hook) {
+        let existing = self
+            .hooks
+            .iter_mut()
+            .find_map(|existing| match &mut existing.hook_type {
+                HookType::Function { inline_hook } if existing.original_va == hook.original_va => {
+                    Some(inline_hook)
+                }
+                _ => None,
+            });
+
+        match (existing, hook.hook_type) {
+            (Some(existing), HookType::Function { inline_hook }) => {
+                log::debug!(
+                    "Merging handlers into the existing hook at {:#x}",
+                    hook.original_va
+                );
+                existing.merge(inline_hook);
+            }
+            (_, hook_type) => self.hooks.push(Hook { hook_type, ..hook }),
+        }
+    }
+
     /// Enables all the hooks managed by the `HookManager`.
     ///
     /// It sets the necessary permissions on the primary and secondary Extended Page Tables (EPTs)
@@ -267,6 +665,7 @@ impl HookManager {
     /// for the execution when hooks are active, respectively.
     ///
     /// Reference: https://tandasat.github.io/VXCON/AMD-V_for_Hackers.pdf
+    #[cfg(windows)]
     pub fn enable_hooks(
         &self,
         primary_ept: &mut Box<Ept, PhysicalAllocator>,
@@ -276,7 +675,7 @@ impl HookManager {
             // Enable the hook if it is a function hook, which involves
             // modifying the targeted function's instructions.
             if let HookType::Function { inline_hook } = &hook.hook_type {
-                inline_hook.enable();
+                inline_hook.enable()?;
             }
 
             let original_page = hook.original_pa.align_down_to_large_page().as_u64();
@@ -332,12 +731,6 @@ impl HookManager {
     ///
     /// * `Option<&Hook>` - A reference to the hook if found, or `None` if not found.
     pub fn find_hook_by_address(&self, address: u64) -> Option<&Hook> {
-        for hook in self.hooks.iter() {
-            if hook.original_va == address {
-                return Some(hook);
-            }
-        }
-
-        None
+        self.hooks.iter().find(|hook| hook.original_va == address)
     }
 }
@@ -156,7 +156,7 @@ impl Mtrr {
     /// # Returns
     /// An iterator over the range of MTRR indexes.
     pub fn indexes() -> impl Iterator<Item = MtrrIndex> {
-        (0..Self::count() as u8).into_iter().map(|v| MtrrIndex(v))
+        (0..Self::count() as u8).map(MtrrIndex)
     }
 
     /// Retrieves the configuration for a specific MTRR.
@@ -213,6 +213,12 @@ impl Mtrr {
     }
 }
 
+impl Default for Mtrr {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Represents an index into the array of variable MTRRs.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct MtrrIndex(pub u8);
@@ -434,6 +434,7 @@ impl Ept {
     /// * `host_pa`: The host physical address to remap to.
     /// * `access_type`: The type of access allowed for this page (read, write, execute).
     /// * `mtrr`: The Memory Type Range Registers (MTRR) to use for this page.
+    ///
     /// Credits: Jess / jessiep_
     pub fn remap_page(
         &mut self,
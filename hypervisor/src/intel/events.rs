@@ -5,11 +5,11 @@
 
 use {
     crate::intel::{
-        support::vmwrite,
+        vmcs_access::{VmcsAccess, VmcsFieldAccess},
+        vmcs_fields::control,
         vmerror::{ExceptionInterrupt, InterruptionType},
     },
     bitfield::bitfield,
-    x86::vmx::vmcs,
 };
 
 bitfield! {
@@ -63,6 +63,17 @@ impl EventInjection {
         event.0
     }
 
+    /// Inject Debug Exception (#DB) to the guest (Event Injection).
+    fn debug() -> u32 {
+        let mut event = EventInjection(0);
+
+        event.set_vector(ExceptionInterrupt::Debug as u32);
+        event.set_type(InterruptionType::HardwareException as u32);
+        event.set_valid(VALID);
+
+        event.0
+    }
+
     /// Inject Breakpoint (#BP) to the guest (Event Injection).
     fn breakpoint() -> u32 {
         let mut event = EventInjection(0);
@@ -80,6 +91,7 @@ impl EventInjection {
 
         event.set_vector(ExceptionInterrupt::PageFault as u32);
         event.set_type(InterruptionType::HardwareException as u32);
+        event.set_deliver_error_code(1);
         event.set_valid(VALID);
 
         event.0
@@ -96,6 +108,71 @@ impl EventInjection {
         event.0
     }
 
+    /// Injects an exception into the guest.
+    ///
+    /// # Arguments
+    ///
+    /// * `vmcs` - The VMCS of the guest.
+    /// * `exception` - The exception.
+    /// * `error_code` - The error code, which is only delivered if the exception pushes one.
+    ///
+    /// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 25.8.3 VM-Entry Controls for Event Injection
+    /// and Table 25-17. Format of the VM-Entry Interruption-Information Field.
+    pub fn vmentry_inject_exception(
+        vmcs: &mut dyn VmcsAccess,
+        exception: ExceptionInterrupt,
+        error_code: u32,
+    ) {
+        // The exceptions that push an error code in protected mode.
+        //
+        // Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 6.13 ERROR CODE
+        let deliver_error_code = matches!(
+            exception,
+            ExceptionInterrupt::DoubleFault
+                | ExceptionInterrupt::InvalidTSS
+                | ExceptionInterrupt::SegmentNotPresent
+                | ExceptionInterrupt::StackSegmentFault
+                | ExceptionInterrupt::GeneralProtectionFault
+                | ExceptionInterrupt::PageFault
+                | ExceptionInterrupt::AlignmentCheck
+                | ExceptionInterrupt::ControlProtectionException
+        );
+
+        let mut event = EventInjection(0);
+
+        event.set_vector(exception as u32);
+        event.set_type(InterruptionType::HardwareException as u32);
+        event.set_deliver_error_code(deliver_error_code as u32);
+        event.set_valid(VALID);
+
+        if deliver_error_code {
+            vmcs.set(control::VMENTRY_EXCEPTION_ERR_CODE, error_code);
+        }
+
+        vmcs.set(control::VMENTRY_INTERRUPTION_INFO_FIELD, event.0);
+    }
+
+    /// Injects an external interrupt into the guest.
+    ///
+    /// This function is used to deliver an external interrupt that was acknowledged on VM exit.
+    ///
+    /// # Arguments
+    ///
+    /// * `vmcs` - The VMCS of the guest.
+    /// * `vector` - The vector of the interrupt.
+    ///
+    /// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 25.8.3 VM-Entry Controls for Event Injection
+    /// and Table 25-17. Format of the VM-Entry Interruption-Information Field.
+    pub fn vmentry_inject_external_interrupt(vmcs: &mut dyn VmcsAccess, vector: u8) {
+        let mut event = EventInjection(0);
+
+        event.set_vector(vector as u32);
+        event.set_type(InterruptionType::ExternalInterrupt as u32);
+        event.set_valid(VALID);
+
+        vmcs.set(control::VMENTRY_INTERRUPTION_INFO_FIELD, event.0);
+    }
+
     /// Injects a general protection fault into the guest.
     ///
     /// This function is used to signal to the guest that a protection violation
@@ -103,14 +180,15 @@ impl EventInjection {
     ///
     /// # Arguments
     ///
+    /// * `vmcs` - The VMCS of the guest.
     /// * `error_code` - The error code to be associated with the fault.
     ///
     /// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 25.8.3 VM-Entry Controls for Event Injection
     /// and Table 25-17. Format of the VM-Entry Interruption-Information Field.
-    pub fn vmentry_inject_gp(error_code: u32) {
-        vmwrite(vmcs::control::VMENTRY_EXCEPTION_ERR_CODE, error_code);
-        vmwrite(
-            vmcs::control::VMENTRY_INTERRUPTION_INFO_FIELD,
+    pub fn vmentry_inject_gp(vmcs: &mut dyn VmcsAccess, error_code: u32) {
+        vmcs.set(control::VMENTRY_EXCEPTION_ERR_CODE, error_code);
+        vmcs.set(
+            control::VMENTRY_INTERRUPTION_INFO_FIELD,
             EventInjection::general_protection(),
         );
     }
@@ -122,14 +200,15 @@ impl EventInjection {
     ///
     /// # Arguments
     ///
+    /// * `vmcs` - The VMCS of the guest.
     /// * `error_code` - The error code to be associated with the page fault.
     ///
     /// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 25.8.3 VM-Entry Controls for Event Injection
     /// and Table 25-17. Format of the VM-Entry Interruption-Information Field.
-    pub fn vmentry_inject_pf(error_code: u32) {
-        vmwrite(vmcs::control::VMENTRY_EXCEPTION_ERR_CODE, error_code);
-        vmwrite(
-            vmcs::control::VMENTRY_INTERRUPTION_INFO_FIELD,
+    pub fn vmentry_inject_pf(vmcs: &mut dyn VmcsAccess, error_code: u32) {
+        vmcs.set(control::VMENTRY_EXCEPTION_ERR_CODE, error_code);
+        vmcs.set(
+            control::VMENTRY_INTERRUPTION_INFO_FIELD,
             EventInjection::page_fault(),
         );
     }
@@ -139,25 +218,51 @@ impl EventInjection {
     /// This function is used to signal to the guest that a breakpoint exception
     /// has occurred, typically used for debugging purposes.
     ///
+    /// # Arguments
+    ///
+    /// * `vmcs` - The VMCS of the guest.
+    ///
     /// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 25.8.3 VM-Entry Controls for Event Injection
     /// and Table 25-17. Format of the VM-Entry Interruption-Information Field.
-    pub fn vmentry_inject_bp() {
-        vmwrite(
-            vmcs::control::VMENTRY_INTERRUPTION_INFO_FIELD,
+    pub fn vmentry_inject_bp(vmcs: &mut dyn VmcsAccess) {
+        vmcs.set(
+            control::VMENTRY_INTERRUPTION_INFO_FIELD,
             EventInjection::breakpoint(),
         );
     }
 
+    /// Injects a debug exception into the guest.
+    ///
+    /// This function is used to deliver a debug exception that was intercepted by
+    /// the hypervisor, for example a single step of a debugger.
+    ///
+    /// # Arguments
+    ///
+    /// * `vmcs` - The VMCS of the guest.
+    ///
+    /// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 25.8.3 VM-Entry Controls for Event Injection
+    /// and Table 25-17. Format of the VM-Entry Interruption-Information Field.
+    pub fn vmentry_inject_db(vmcs: &mut dyn VmcsAccess) {
+        vmcs.set(
+            control::VMENTRY_INTERRUPTION_INFO_FIELD,
+            EventInjection::debug(),
+        );
+    }
+
     /// Injects an undefined opcode exception into the guest.
     ///
     /// This function is used to signal to the guest that an invalid or undefined opcode
     /// has been encountered, typically indicating an error in the guest's execution.
     ///
+    /// # Arguments
+    ///
+    /// * `vmcs` - The VMCS of the guest.
+    ///
     /// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 25.8.3 VM-Entry Controls for Event Injection
     /// and Table 25-17. Format of the VM-Entry Interruption-Information Field.
-    pub fn vmentry_inject_ud() {
-        vmwrite(
-            vmcs::control::VMENTRY_INTERRUPTION_INFO_FIELD,
+    pub fn vmentry_inject_ud(vmcs: &mut dyn VmcsAccess) {
+        vmcs.set(
+            control::VMENTRY_INTERRUPTION_INFO_FIELD,
             EventInjection::undefined_opcode(),
         );
     }
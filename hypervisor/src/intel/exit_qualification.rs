@@ -0,0 +1,903 @@
+//! This module provides decoders for the exit qualification and the VM-exit instruction-information field.
+//! The meaning of both fields depends on the exit reason, so every format has its own structure, which
+//! is decoded from the raw value of the field with `from_u64`.
+//!
+//! The EPT violation qualification and the interruption information are decoded in `vmerror`.
+//!
+//! Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 28.2.1 Basic VM-Exit Information
+//! and 28.2.5 Information for VM Exits Due to Instruction Execution
+
+/// A general-purpose register, as encoded in the exit qualification and the instruction information.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GeneralPurposeRegister {
+    Rax = 0,
+    Rcx = 1,
+    Rdx = 2,
+    Rbx = 3,
+    Rsp = 4,
+    Rbp = 5,
+    Rsi = 6,
+    Rdi = 7,
+    R8 = 8,
+    R9 = 9,
+    R10 = 10,
+    R11 = 11,
+    R12 = 12,
+    R13 = 13,
+    R14 = 14,
+    R15 = 15,
+}
+
+impl GeneralPurposeRegister {
+    /// Converts the 4 bits of a register field into the register.
+    pub fn from_bits(bits: u64) -> Self {
+        match bits & 0xF {
+            0 => Self::Rax,
+            1 => Self::Rcx,
+            2 => Self::Rdx,
+            3 => Self::Rbx,
+            4 => Self::Rsp,
+            5 => Self::Rbp,
+            6 => Self::Rsi,
+            7 => Self::Rdi,
+            8 => Self::R8,
+            9 => Self::R9,
+            10 => Self::R10,
+            11 => Self::R11,
+            12 => Self::R12,
+            13 => Self::R13,
+            14 => Self::R14,
+            _ => Self::R15,
+        }
+    }
+}
+
+/// A segment register, as encoded in the instruction information.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SegmentRegister {
+    Es = 0,
+    Cs = 1,
+    Ss = 2,
+    Ds = 3,
+    Fs = 4,
+    Gs = 5,
+}
+
+impl SegmentRegister {
+    /// Converts the 3 bits of a segment register field into the register.
+    pub fn from_bits(bits: u64) -> Option<Self> {
+        match bits & 0b111 {
+            0 => Some(Self::Es),
+            1 => Some(Self::Cs),
+            2 => Some(Self::Ss),
+            3 => Some(Self::Ds),
+            4 => Some(Self::Fs),
+            5 => Some(Self::Gs),
+            _ => None,
+        }
+    }
+}
+
+/// The address size of a memory operand, as encoded in the instruction information.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AddressSize {
+    Bits16,
+    Bits32,
+    Bits64,
+}
+
+impl AddressSize {
+    /// Converts the 3 bits of the address-size field into the address size.
+    pub fn from_bits(bits: u64) -> Option<Self> {
+        match bits & 0b111 {
+            0 => Some(Self::Bits16),
+            1 => Some(Self::Bits32),
+            2 => Some(Self::Bits64),
+            _ => None,
+        }
+    }
+
+    /// Returns the mask of the addresses of this size.
+    pub fn mask(self) -> u64 {
+        match self {
+            Self::Bits16 => 0xFFFF,
+            Self::Bits32 => 0xFFFF_FFFF,
+            Self::Bits64 => u64::MAX,
+        }
+    }
+}
+
+/// A memory operand of an instruction, described by the instruction information.
+///
+/// The displacement of the operand is stored in the exit qualification.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MemoryOperand {
+    /// The scaling of the index register, as a shift: 0 (×1), 1 (×2), 2 (×4) or 3 (×8).
+    pub scaling: u8,
+    /// The address size of the operand.
+    pub address_size: AddressSize,
+    /// The segment register of the operand.
+    pub segment: SegmentRegister,
+    /// The index register, if the operand has one.
+    pub index: Option<GeneralPurposeRegister>,
+    /// The base register, if the operand has one.
+    pub base: Option<GeneralPurposeRegister>,
+}
+
+impl MemoryOperand {
+    /// Decodes the memory operand from the instruction information: the scaling (bits 1:0), address size
+    /// (bits 9:7), segment register (bits 17:15), index register (bits 21:18, invalid if bit 22 is set)
+    /// and base register (bits 26:23, invalid if bit 27 is set).
+    ///
+    /// Returns `None` if the address size or the segment register is reserved.
+    pub fn from_u64(value: u64) -> Option<Self> {
+        Some(Self {
+            scaling: (value & 0b11) as u8,
+            address_size: AddressSize::from_bits(value >> 7)?,
+            segment: SegmentRegister::from_bits(value >> 15)?,
+            index: (value & (1 << 22) == 0).then(|| GeneralPurposeRegister::from_bits(value >> 18)),
+            base: (value & (1 << 27) == 0).then(|| GeneralPurposeRegister::from_bits(value >> 23)),
+        })
+    }
+
+    /// Computes the offset of the operand in its segment.
+    ///
+    /// # Arguments
+    ///
+    /// * `displacement` - The displacement of the operand, from the exit qualification.
+    /// * `register` - Returns the value of a general-purpose register of the guest.
+    ///
+    /// # Returns
+    ///
+    /// * `u64` - The offset, truncated to the address size of the operand.
+    pub fn offset(
+        &self,
+        displacement: u64,
+        register: impl Fn(GeneralPurposeRegister) -> u64,
+    ) -> u64 {
+        let base = self.base.map_or(0, &register);
+        let index = self.index.map_or(0, &register) << self.scaling;
+
+        base.wrapping_add(index).wrapping_add(displacement) & self.address_size.mask()
+    }
+}
+
+/// An operand that is either a register or a memory location.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Operand {
+    Register(GeneralPurposeRegister),
+    Memory(MemoryOperand),
+}
+
+impl Operand {
+    /// Decodes the operand from the instruction information: a register (bits 6:3) if bit 10 is set,
+    /// otherwise a memory operand.
+    ///
+    /// Returns `None` if the memory operand has a reserved address size or segment register.
+    pub fn from_u64(value: u64) -> Option<Self> {
+        match value & (1 << 10) != 0 {
+            true => Some(Self::Register(GeneralPurposeRegister::from_bits(
+                value >> 3,
+            ))),
+            false => MemoryOperand::from_u64(value).map(Self::Memory),
+        }
+    }
+}
+
+/// Represents the exit qualification of debug exceptions.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: Table 28-1. Exit Qualification for Debug Exceptions
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DebugExceptionQualification {
+    /// The breakpoint conditions that were met (B3-B0, bits 3:0), in the format of DR6.
+    pub breakpoints: u8,
+    /// A debug register access was detected (BD, bit 13).
+    pub debug_register_access: bool,
+    /// The exception was caused by a single step (BS, bit 14).
+    pub single_step: bool,
+    /// The exception occurred inside an RTM region (bit 16).
+    pub rtm: bool,
+}
+
+impl DebugExceptionQualification {
+    /// Decodes the exit qualification of a debug exception. The bits that aren't described are ignored.
+    pub fn from_u64(value: u64) -> Self {
+        Self {
+            breakpoints: (value & 0xF) as u8,
+            debug_register_access: value & (1 << 13) != 0,
+            single_step: value & (1 << 14) != 0,
+            rtm: value & (1 << 16) != 0,
+        }
+    }
+
+    /// Returns the conditions of the exception in the format of DR6.
+    pub fn dr6_bits(&self) -> u64 {
+        u64::from(self.breakpoints)
+            | (u64::from(self.debug_register_access) << 13)
+            | (u64::from(self.single_step) << 14)
+    }
+}
+
+/// The source of a task switch.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TaskSwitchSource {
+    Call = 0,
+    Iret = 1,
+    Jmp = 2,
+    TaskGateInIdt = 3,
+}
+
+/// Represents the exit qualification of task switches.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: Table 28-2. Exit Qualification for Task Switches
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TaskSwitchQualification {
+    /// The selector of the TSS of the new task (bits 15:0).
+    pub tss_selector: u16,
+    /// The source of the task switch (bits 31:30).
+    pub source: TaskSwitchSource,
+}
+
+impl TaskSwitchQualification {
+    /// Decodes the exit qualification of a task switch.
+    pub fn from_u64(value: u64) -> Self {
+        Self {
+            tss_selector: value as u16,
+            source: match (value >> 30) & 0b11 {
+                0 => TaskSwitchSource::Call,
+                1 => TaskSwitchSource::Iret,
+                2 => TaskSwitchSource::Jmp,
+                _ => TaskSwitchSource::TaskGateInIdt,
+            },
+        }
+    }
+}
+
+/// The access type of a control-register access.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ControlRegisterAccessType {
+    /// MOV to CR from a general-purpose register.
+    MovToCr(GeneralPurposeRegister),
+    /// MOV from CR to a general-purpose register.
+    MovFromCr(GeneralPurposeRegister),
+    /// CLTS.
+    Clts,
+    /// LMSW, with the source data.
+    Lmsw(LmswOperand),
+}
+
+/// The source operand of LMSW.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LmswOperand {
+    /// The operand is a memory location (bit 6), otherwise a register.
+    pub memory: bool,
+    /// The source data of LMSW (bits 31:16).
+    pub source_data: u16,
+}
+
+/// Represents the exit qualification of control-register accesses.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: Table 28-3. Exit Qualification for Control-Register Accesses
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ControlRegisterAccessQualification {
+    /// The number of the control register (bits 3:0), 0 for CLTS and LMSW.
+    pub control_register: u8,
+    /// The access type (bits 5:4), with the general-purpose register (bits 11:8) or the LMSW operand.
+    pub access_type: ControlRegisterAccessType,
+}
+
+impl ControlRegisterAccessQualification {
+    /// Decodes the exit qualification of a control-register access. The general-purpose register is only
+    /// meaningful for MOV to CR and MOV from CR.
+    pub fn from_u64(value: u64) -> Self {
+        let register = GeneralPurposeRegister::from_bits(value >> 8);
+
+        Self {
+            control_register: (value & 0xF) as u8,
+            access_type: match (value >> 4) & 0b11 {
+                0 => ControlRegisterAccessType::MovToCr(register),
+                1 => ControlRegisterAccessType::MovFromCr(register),
+                2 => ControlRegisterAccessType::Clts,
+                _ => ControlRegisterAccessType::Lmsw(LmswOperand {
+                    memory: value & (1 << 6) != 0,
+                    source_data: (value >> 16) as u16,
+                }),
+            },
+        }
+    }
+}
+
+/// Represents the exit qualification of MOV DR.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: Table 28-4. Exit Qualification for MOV DR
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MovDrQualification {
+    /// The number of the debug register (bits 2:0).
+    pub debug_register: u8,
+    /// The direction of the access (bit 4): MOV from DR if set, MOV to DR otherwise.
+    pub from_debug_register: bool,
+    /// The general-purpose register (bits 11:8).
+    pub register: GeneralPurposeRegister,
+}
+
+impl MovDrQualification {
+    /// Decodes the exit qualification of MOV DR.
+    pub fn from_u64(value: u64) -> Self {
+        Self {
+            debug_register: (value & 0b111) as u8,
+            from_debug_register: value & (1 << 4) != 0,
+            register: GeneralPurposeRegister::from_bits(value >> 8),
+        }
+    }
+}
+
+/// Represents the exit qualification of I/O instructions.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: Table 28-5. Exit Qualification for I/O Instructions
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IoInstructionQualification {
+    /// The size of the access in bytes: 1, 2 or 4 (bits 2:0).
+    pub size: u8,
+    /// The direction of the access (bit 3): IN if set, OUT otherwise.
+    pub input: bool,
+    /// The instruction is a string instruction, INS or OUTS (bit 4).
+    pub string: bool,
+    /// The instruction has a REP prefix (bit 5).
+    pub rep: bool,
+    /// The port is an immediate operand (bit 6), otherwise it's in DX.
+    pub immediate: bool,
+    /// The port number (bits 31:16).
+    pub port: u16,
+}
+
+impl IoInstructionQualification {
+    /// Decodes the exit qualification of an I/O instruction.
+    ///
+    /// Returns `None` if the size of the access (bits 2:0) isn't 1, 2 or 4 bytes.
+    pub fn from_u64(value: u64) -> Option<Self> {
+        let size = match value & 0b111 {
+            0 => 1,
+            1 => 2,
+            3 => 4,
+            _ => return None,
+        };
+
+        Some(Self {
+            size,
+            input: value & (1 << 3) != 0,
+            string: value & (1 << 4) != 0,
+            rep: value & (1 << 5) != 0,
+            immediate: value & (1 << 6) != 0,
+            port: (value >> 16) as u16,
+        })
+    }
+}
+
+/// The type of an access to the APIC-access page.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ApicAccessType {
+    LinearRead = 0,
+    LinearWrite = 1,
+    LinearFetch = 2,
+    LinearEventDelivery = 3,
+    GuestPhysicalEventDelivery = 10,
+    GuestPhysicalFetch = 15,
+}
+
+/// Represents the exit qualification of APIC accesses.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: Table 28-6. Exit Qualification for APIC-Access VM Exits from Linear Accesses and Guest-Physical Accesses
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ApicAccessQualification {
+    /// The offset of the access in the APIC-access page (bits 11:0), only for linear accesses.
+    pub offset: u16,
+    /// The access type (bits 15:12).
+    pub access_type: ApicAccessType,
+    /// The access was asynchronous to instruction execution (bit 16).
+    pub asynchronous: bool,
+}
+
+impl ApicAccessQualification {
+    /// Decodes the exit qualification of an APIC access.
+    ///
+    /// Returns `None` if the access type (bits 15:12) is reserved.
+    pub fn from_u64(value: u64) -> Option<Self> {
+        let access_type = match (value >> 12) & 0xF {
+            0 => ApicAccessType::LinearRead,
+            1 => ApicAccessType::LinearWrite,
+            2 => ApicAccessType::LinearFetch,
+            3 => ApicAccessType::LinearEventDelivery,
+            10 => ApicAccessType::GuestPhysicalEventDelivery,
+            15 => ApicAccessType::GuestPhysicalFetch,
+            _ => return None,
+        };
+
+        Some(Self {
+            offset: (value & 0xFFF) as u16,
+            access_type,
+            asynchronous: value & (1 << 16) != 0,
+        })
+    }
+}
+
+/// Represents the exit qualification of VM-entry failures due to invalid guest state.
+///
+/// For VM-entry failures due to MSR loading, the exit qualification is the 1-based index of the failing
+/// entry of the VM-entry MSR-load area instead.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 27.8 VM-ENTRY FAILURES DURING OR AFTER LOADING GUEST STATE
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VmEntryFailureQualification {
+    /// The failure isn't described further.
+    NotUsed = 0,
+    /// The PDPTEs couldn't be loaded.
+    PdpteLoading = 2,
+    /// An NMI was injected while blocked by NMI.
+    NmiInjection = 3,
+    /// The VMCS link pointer is invalid.
+    InvalidVmcsLinkPointer = 4,
+}
+
+impl VmEntryFailureQualification {
+    /// Decodes the exit qualification of a VM-entry failure due to invalid guest state.
+    ///
+    /// Returns `None` if the value is reserved.
+    pub fn from_u64(value: u64) -> Option<Self> {
+        match value {
+            0 => Some(Self::NotUsed),
+            2 => Some(Self::PdpteLoading),
+            3 => Some(Self::NmiInjection),
+            4 => Some(Self::InvalidVmcsLinkPointer),
+            _ => None,
+        }
+    }
+}
+
+/// Represents the exit qualification of INVLPG, which is the linear address of the operand.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 28.2.1 Basic VM-Exit Information
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InvlpgQualification {
+    /// The linear address of the operand.
+    pub linear_address: u64,
+}
+
+impl InvlpgQualification {
+    /// Decodes the exit qualification of INVLPG.
+    pub fn from_u64(value: u64) -> Self {
+        Self {
+            linear_address: value,
+        }
+    }
+}
+
+/// The instruction of a VM exit due to a descriptor-table access.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DescriptorTableInstruction {
+    Sgdt = 0,
+    Sidt = 1,
+    Lgdt = 2,
+    Lidt = 3,
+}
+
+/// Represents the instruction information of LIDT, LGDT, SIDT and SGDT.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: Table 28-10. Format of the VM-Exit Instruction-Information Field as Used for LIDT, LGDT, SIDT, or SGDT
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DescriptorTableInstructionInformation {
+    /// The instruction (bits 29:28).
+    pub instruction: DescriptorTableInstruction,
+    /// The operand size is 32 bits (bit 11), otherwise 16 bits. Ignored in 64-bit mode.
+    pub operand_size_32: bool,
+    /// The memory operand.
+    pub operand: MemoryOperand,
+}
+
+impl DescriptorTableInstructionInformation {
+    /// Decodes the instruction information of LIDT, LGDT, SIDT and SGDT.
+    ///
+    /// Returns `None` if the address size or the segment register of the memory operand is reserved.
+    pub fn from_u64(value: u64) -> Option<Self> {
+        Some(Self {
+            instruction: match (value >> 28) & 0b11 {
+                0 => DescriptorTableInstruction::Sgdt,
+                1 => DescriptorTableInstruction::Sidt,
+                2 => DescriptorTableInstruction::Lgdt,
+                _ => DescriptorTableInstruction::Lidt,
+            },
+            operand_size_32: value & (1 << 11) != 0,
+            operand: MemoryOperand::from_u64(value)?,
+        })
+    }
+}
+
+/// The instruction of a VM exit due to an LDTR or TR access.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SegmentTableInstruction {
+    Sldt = 0,
+    Str = 1,
+    Lldt = 2,
+    Ltr = 3,
+}
+
+/// Represents the instruction information of LLDT, LTR, SLDT and STR.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: Table 28-11. Format of the VM-Exit Instruction-Information Field as Used for LLDT, LTR, SLDT, and STR
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SegmentTableInstructionInformation {
+    /// The instruction (bits 29:28).
+    pub instruction: SegmentTableInstruction,
+    /// The register or memory operand.
+    pub operand: Operand,
+}
+
+impl SegmentTableInstructionInformation {
+    /// Decodes the instruction information of LLDT, LTR, SLDT and STR.
+    ///
+    /// Returns `None` if the operand is a memory operand with a reserved address size or segment register.
+    pub fn from_u64(value: u64) -> Option<Self> {
+        Some(Self {
+            instruction: match (value >> 28) & 0b11 {
+                0 => SegmentTableInstruction::Sldt,
+                1 => SegmentTableInstruction::Str,
+                2 => SegmentTableInstruction::Lldt,
+                _ => SegmentTableInstruction::Ltr,
+            },
+            operand: Operand::from_u64(value)?,
+        })
+    }
+}
+
+/// Represents the instruction information of INVEPT, INVPCID and INVVPID.
+///
+/// The memory operand is the descriptor of the invalidation, and the register operand is its type.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: Table 28-9. Format of the VM-Exit Instruction-Information Field as Used for INVEPT, INVPCID, and INVVPID
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InvalidationInstructionInformation {
+    /// The memory operand (the descriptor).
+    pub operand: MemoryOperand,
+    /// The register operand (the type), in bits 31:28.
+    pub register: GeneralPurposeRegister,
+}
+
+impl InvalidationInstructionInformation {
+    /// Decodes the instruction information of INVEPT, INVPCID and INVVPID.
+    ///
+    /// Returns `None` if the address size or the segment register of the memory operand is reserved.
+    pub fn from_u64(value: u64) -> Option<Self> {
+        Some(Self {
+            operand: MemoryOperand::from_u64(value)?,
+            register: GeneralPurposeRegister::from_bits(value >> 28),
+        })
+    }
+}
+
+/// Represents the instruction information of the VMX instructions: VMCLEAR, VMPTRLD, VMPTRST, VMXON,
+/// VMREAD and VMWRITE.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: Table 28-13. Format of the VM-Exit Instruction-Information Field as Used for VMCLEAR, VMPTRLD, VMPTRST, VMXON, XRSTORS, and XSAVES
+/// and Table 28-14. Format of the VM-Exit Instruction-Information Field as Used for VMREAD and VMWRITE
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct VmxInstructionInformation {
+    /// The register or memory operand. Always a memory operand for VMCLEAR, VMPTRLD, VMPTRST and VMXON.
+    pub operand: Operand,
+    /// The second register operand (bits 31:28), which holds the VMCS field encoding of VMREAD and
+    /// VMWRITE. Undefined for the other instructions.
+    pub register2: GeneralPurposeRegister,
+}
+
+impl VmxInstructionInformation {
+    /// Decodes the instruction information of VMCLEAR, VMPTRLD, VMPTRST, VMXON, VMREAD and VMWRITE.
+    ///
+    /// Returns `None` if the operand is a memory operand with a reserved address size or segment register.
+    pub fn from_u64(value: u64) -> Option<Self> {
+        Some(Self {
+            operand: Operand::from_u64(value)?,
+            register2: GeneralPurposeRegister::from_bits(value >> 28),
+        })
+    }
+}
+
+/// The operand size of an instruction, as encoded in the instruction information.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OperandSize {
+    Bits16,
+    Bits32,
+    Bits64,
+}
+
+impl OperandSize {
+    /// Converts the 2 bits of the operand-size field into the operand size.
+    pub fn from_bits(bits: u64) -> Option<Self> {
+        match bits & 0b11 {
+            0 => Some(Self::Bits16),
+            1 => Some(Self::Bits32),
+            2 => Some(Self::Bits64),
+            _ => None,
+        }
+    }
+}
+
+/// Represents the instruction information of RDRAND, RDSEED, TPAUSE and UMWAIT.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: Table 28-12. Format of the VM-Exit Instruction-Information Field as Used for RDRAND, RDSEED, TPAUSE, and UMWAIT
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RegisterInstructionInformation {
+    /// The register operand (bits 6:3).
+    pub register: GeneralPurposeRegister,
+    /// The operand size (bits 12:11).
+    pub operand_size: OperandSize,
+}
+
+impl RegisterInstructionInformation {
+    /// Decodes the instruction information of RDRAND, RDSEED, TPAUSE and UMWAIT.
+    ///
+    /// Returns `None` if the operand size (bits 12:11) is reserved.
+    pub fn from_u64(value: u64) -> Option<Self> {
+        Some(Self {
+            register: GeneralPurposeRegister::from_bits(value >> 3),
+            operand_size: OperandSize::from_bits(value >> 11)?,
+        })
+    }
+}
+
+/// Represents the instruction information of INS and OUTS.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: Table 28-8. Format of the VM-Exit Instruction-Information Field as Used for INS and OUTS
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct StringIoInstructionInformation {
+    /// The address size (bits 9:7).
+    pub address_size: AddressSize,
+    /// The segment register of the source operand of OUTS (bits 17:15). INS always uses ES.
+    pub segment: SegmentRegister,
+}
+
+impl StringIoInstructionInformation {
+    /// Decodes the instruction information of INS and OUTS.
+    ///
+    /// Returns `None` if the address size or the segment register is reserved.
+    pub fn from_u64(value: u64) -> Option<Self> {
+        Some(Self {
+            address_size: AddressSize::from_bits(value >> 7)?,
+            segment: SegmentRegister::from_bits(value >> 15)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `[rbx + rcx * 4]` with a 64-bit address size and DS.
+    const MEMORY_OPERAND: u64 = 2 | 2 << 7 | 3 << 15 | 1 << 18 | 3 << 23;
+
+    #[test]
+    fn memory_operands_are_decoded() {
+        let operand = MemoryOperand::from_u64(MEMORY_OPERAND).unwrap();
+
+        assert_eq!(
+            operand,
+            MemoryOperand {
+                scaling: 2,
+                address_size: AddressSize::Bits64,
+                segment: SegmentRegister::Ds,
+                index: Some(GeneralPurposeRegister::Rcx),
+                base: Some(GeneralPurposeRegister::Rbx),
+            }
+        );
+        assert_eq!(
+            operand.offset(0x10, |register| match register {
+                GeneralPurposeRegister::Rbx => 0x1000,
+                _ => 3,
+            }),
+            0x101C
+        );
+    }
+
+    #[test]
+    fn memory_operands_without_index_or_base_are_decoded() {
+        // `[disp32]` with a 32-bit address size and ES.
+        let operand = MemoryOperand::from_u64(1 << 7 | 1 << 22 | 1 << 27).unwrap();
+
+        assert_eq!(operand.index, None);
+        assert_eq!(operand.base, None);
+        assert_eq!(operand.offset(0x1_0000_0010, |_| unreachable!()), 0x10);
+    }
+
+    #[test]
+    fn reserved_memory_operands_are_rejected() {
+        assert_eq!(MemoryOperand::from_u64(3 << 7), None);
+        assert_eq!(MemoryOperand::from_u64(6 << 15), None);
+        assert_eq!(Operand::from_u64(3 << 7), None);
+    }
+
+    #[test]
+    fn register_operands_are_decoded() {
+        assert_eq!(
+            Operand::from_u64(1 << 10 | 9 << 3 | 3 << 7),
+            Some(Operand::Register(GeneralPurposeRegister::R9))
+        );
+    }
+
+    #[test]
+    fn debug_exception_qualifications_are_decoded() {
+        let qualification = DebugExceptionQualification::from_u64(0b0101 | 1 << 14 | 1 << 16);
+
+        assert_eq!(qualification.breakpoints, 0b0101);
+        assert!(!qualification.debug_register_access);
+        assert!(qualification.single_step);
+        assert!(qualification.rtm);
+        assert_eq!(qualification.dr6_bits(), 0b0101 | 1 << 14);
+    }
+
+    #[test]
+    fn task_switch_qualifications_are_decoded() {
+        assert_eq!(
+            TaskSwitchQualification::from_u64(2 << 30 | 0x28),
+            TaskSwitchQualification {
+                tss_selector: 0x28,
+                source: TaskSwitchSource::Jmp,
+            }
+        );
+    }
+
+    #[test]
+    fn control_register_access_qualifications_are_decoded() {
+        let decode = |value| ControlRegisterAccessQualification::from_u64(value).access_type;
+
+        let mov_to_cr3 = ControlRegisterAccessQualification::from_u64(3 | 12 << 8);
+        assert_eq!(mov_to_cr3.control_register, 3);
+        assert_eq!(
+            mov_to_cr3.access_type,
+            ControlRegisterAccessType::MovToCr(GeneralPurposeRegister::R12)
+        );
+
+        assert_eq!(
+            decode(8 | 1 << 4 | 1 << 8),
+            ControlRegisterAccessType::MovFromCr(GeneralPurposeRegister::Rcx)
+        );
+        assert_eq!(decode(2 << 4), ControlRegisterAccessType::Clts);
+        assert_eq!(
+            decode(3 << 4 | 1 << 6 | 0x1_0000),
+            ControlRegisterAccessType::Lmsw(LmswOperand {
+                memory: true,
+                source_data: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn mov_dr_qualifications_are_decoded() {
+        assert_eq!(
+            MovDrQualification::from_u64(7 | 1 << 4 | 2 << 8),
+            MovDrQualification {
+                debug_register: 7,
+                from_debug_register: true,
+                register: GeneralPurposeRegister::Rdx,
+            }
+        );
+    }
+
+    #[test]
+    fn io_instruction_qualifications_are_decoded() {
+        // `rep outsd` to port 0x3F8.
+        assert_eq!(
+            IoInstructionQualification::from_u64(3 | 1 << 4 | 1 << 5 | 0x3F8 << 16),
+            Some(IoInstructionQualification {
+                size: 4,
+                input: false,
+                string: true,
+                rep: true,
+                immediate: false,
+                port: 0x3F8,
+            })
+        );
+
+        // `in al, 0x60`.
+        let input = IoInstructionQualification::from_u64(1 << 3 | 1 << 6 | 0x60 << 16).unwrap();
+        assert_eq!(input.size, 1);
+        assert!(input.input && input.immediate);
+    }
+
+    #[test]
+    fn reserved_io_sizes_are_rejected() {
+        assert_eq!(IoInstructionQualification::from_u64(2), None);
+        assert_eq!(IoInstructionQualification::from_u64(4), None);
+    }
+
+    #[test]
+    fn apic_access_qualifications_are_decoded() {
+        assert_eq!(
+            ApicAccessQualification::from_u64(1 << 12 | 0x80),
+            Some(ApicAccessQualification {
+                offset: 0x80,
+                access_type: ApicAccessType::LinearWrite,
+                asynchronous: false,
+            })
+        );
+        assert_eq!(ApicAccessQualification::from_u64(4 << 12), None);
+    }
+
+    #[test]
+    fn vm_entry_failure_qualifications_are_decoded() {
+        assert_eq!(
+            VmEntryFailureQualification::from_u64(4),
+            Some(VmEntryFailureQualification::InvalidVmcsLinkPointer)
+        );
+        assert_eq!(VmEntryFailureQualification::from_u64(1), None);
+    }
+
+    #[test]
+    fn descriptor_table_instruction_information_is_decoded() {
+        let information =
+            DescriptorTableInstructionInformation::from_u64(3 << 28 | 1 << 11 | MEMORY_OPERAND)
+                .unwrap();
+
+        assert_eq!(information.instruction, DescriptorTableInstruction::Lidt);
+        assert!(information.operand_size_32);
+        assert_eq!(
+            information.operand,
+            MemoryOperand::from_u64(MEMORY_OPERAND).unwrap()
+        );
+    }
+
+    #[test]
+    fn segment_table_instruction_information_is_decoded() {
+        assert_eq!(
+            SegmentTableInstructionInformation::from_u64(3 << 28 | 1 << 10 | 5 << 3),
+            Some(SegmentTableInstructionInformation {
+                instruction: SegmentTableInstruction::Ltr,
+                operand: Operand::Register(GeneralPurposeRegister::Rbp),
+            })
+        );
+    }
+
+    #[test]
+    fn invalidation_instruction_information_is_decoded() {
+        let information =
+            InvalidationInstructionInformation::from_u64(10 << 28 | MEMORY_OPERAND).unwrap();
+
+        assert_eq!(information.register, GeneralPurposeRegister::R10);
+        assert_eq!(information.operand.base, Some(GeneralPurposeRegister::Rbx));
+    }
+
+    #[test]
+    fn vmx_instruction_information_is_decoded() {
+        // `vmread rbx, rdx`.
+        assert_eq!(
+            VmxInstructionInformation::from_u64(2 << 28 | 1 << 10 | 3 << 3),
+            Some(VmxInstructionInformation {
+                operand: Operand::Register(GeneralPurposeRegister::Rbx),
+                register2: GeneralPurposeRegister::Rdx,
+            })
+        );
+    }
+
+    #[test]
+    fn register_instruction_information_is_decoded() {
+        // `rdrand r15`.
+        assert_eq!(
+            RegisterInstructionInformation::from_u64(15 << 3 | 2 << 11),
+            Some(RegisterInstructionInformation {
+                register: GeneralPurposeRegister::R15,
+                operand_size: OperandSize::Bits64,
+            })
+        );
+        assert_eq!(RegisterInstructionInformation::from_u64(3 << 11), None);
+    }
+
+    #[test]
+    fn string_io_instruction_information_is_decoded() {
+        assert_eq!(
+            StringIoInstructionInformation::from_u64(2 << 7 | 4 << 15),
+            Some(StringIoInstructionInformation {
+                address_size: AddressSize::Bits64,
+                segment: SegmentRegister::Fs,
+            })
+        );
+        assert_eq!(StringIoInstructionInformation::from_u64(7 << 15), None);
+    }
+}
@@ -0,0 +1,606 @@
+//! Provides access to guest memory from VMX root operation.
+//!
+//! Guest virtual addresses are translated by walking the guest's paging structures referenced by the
+//! guest CR3, so both kernel and user-mode addresses of the current guest address space can be accessed.
+//! The accesses are checked against the access rights of the translation like the processor would: writes
+//! need a writable page if CR0.WP is set, and accesses on behalf of user mode need a user-mode page.
+//!
+//! Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 4.5 4-LEVEL PAGING AND 5-LEVEL PAGING
+
+use {
+    crate::{
+        error::HypervisorError,
+        intel::{
+            cpu_access::CpuAccess,
+            events::EventInjection,
+            vmcs_access::{VmcsAccess, VmcsFieldAccess},
+            vmcs_fields::guest,
+        },
+        utils::{addresses::PhysicalAddress, capture::GuestRegisters},
+    },
+    core::mem::{size_of, MaybeUninit},
+    x86::bits64::paging::{BASE_PAGE_SIZE, HUGE_PAGE_SIZE, LARGE_PAGE_SIZE},
+};
+
+/// Mask of the physical address bits in CR3 and the paging-structure entries.
+const ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+/// The present flag of a paging-structure entry.
+const PRESENT: u64 = 1 << 0;
+
+/// The read/write flag of a paging-structure entry.
+const WRITABLE: u64 = 1 << 1;
+
+/// The user/supervisor flag of a paging-structure entry.
+const USER: u64 = 1 << 2;
+
+/// The execute-disable flag of a paging-structure entry.
+const EXECUTE_DISABLE: u64 = 1 << 63;
+
+/// The write-protect flag of CR0.
+const CR0_WRITE_PROTECT: u64 = 1 << 16;
+
+/// The page-size flag of a PDPTE or PDE.
+const PAGE_SIZE: u64 = 1 << 7;
+
+/// Number of the arguments passed in registers by the Microsoft x64 calling convention.
+const REGISTER_ARGUMENTS: usize = 4;
+
+/// A translated guest virtual address with the access rights of its page.
+///
+/// The access rights are combined over all the paging-structure entries of the translation.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 4.6 ACCESS RIGHTS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Translation {
+    /// The guest physical address.
+    pub physical_address: u64,
+
+    /// Whether the page is writable.
+    pub writable: bool,
+
+    /// Whether the page is accessible from user mode.
+    pub user: bool,
+
+    /// Whether instructions can be fetched from the page.
+    pub executable: bool,
+}
+
+/// A page fault that an access to guest memory raises, as the processor would deliver it to the guest.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 4.7 PAGE-FAULT EXCEPTIONS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageFault {
+    /// The linear address that caused the fault, which is loaded into CR2.
+    pub address: u64,
+
+    /// The error code: P (bit 0) if the page is present but the access rights deny the access, W/R (bit 1)
+    /// for writes and U/S (bit 2) for accesses on behalf of user mode.
+    pub error_code: u32,
+}
+
+impl PageFault {
+    /// Injects the page fault into the guest.
+    ///
+    /// # Arguments
+    ///
+    /// * `vmcs` - The VMCS of the guest.
+    /// * `cpu` - The processor whose CR2 receives the faulting address.
+    pub fn inject(&self, vmcs: &mut dyn VmcsAccess, cpu: &mut dyn CpuAccess) {
+        cpu.write_cr2(self.address);
+        EventInjection::vmentry_inject_pf(vmcs, self.error_code);
+    }
+}
+
+/// An accessor for the memory of the guest address space referenced by a CR3 value.
+#[derive(Debug, Clone, Copy)]
+pub struct GuestMemory {
+    /// The guest CR3 used for the translation.
+    cr3: u64,
+
+    /// Whether the accesses are made on behalf of user mode.
+    user_mode: bool,
+
+    /// Whether supervisor-mode writes to read-only pages are rejected (CR0.WP).
+    write_protect: bool,
+}
+
+impl GuestMemory {
+    /// Creates a new `GuestMemory` for the given guest CR3, accessing the memory on behalf of supervisor
+    /// mode with CR0.WP set.
+    pub fn new(cr3: u64) -> Self {
+        Self {
+            cr3,
+            user_mode: false,
+            write_protect: true,
+        }
+    }
+
+    /// Creates a new `GuestMemory` for the address space of the guest that caused the VM exit, accessing
+    /// the memory on behalf of the current privilege level of the guest.
+    ///
+    /// # Arguments
+    ///
+    /// * `vmcs` - The VMCS of the guest.
+    pub fn current(vmcs: &dyn VmcsAccess) -> Self {
+        Self {
+            cr3: vmcs.get(guest::CR3),
+            user_mode: vmcs.get(guest::CS_SELECTOR) & 3 == 3,
+            write_protect: vmcs.get(guest::CR0) & CR0_WRITE_PROTECT != 0,
+        }
+    }
+
+    /// Returns a `GuestMemory` for the same address space that accesses the memory on behalf of user mode
+    /// or supervisor mode.
+    pub fn with_user_mode(self, user_mode: bool) -> Self {
+        Self { user_mode, ..self }
+    }
+
+    /// Checks whether the accesses are made on behalf of user mode.
+    pub fn is_user_mode(&self) -> bool {
+        self.user_mode
+    }
+
+    /// Returns the guest CR3 used for the translation.
+    pub fn cr3(&self) -> u64 {
+        self.cr3
+    }
+
+    /// Translates a guest virtual address to a guest physical address.
+    ///
+    /// # Arguments
+    ///
+    /// * `va` - The guest virtual address to translate.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Translation, HypervisorError>` - The guest physical address with the access rights of the page, or an error if the address isn't mapped.
+    pub fn translate(&self, va: u64) -> Result<Translation, HypervisorError> {
+        let pml4e = Self::read_entry(self.cr3 & ADDRESS_MASK, (va >> 39) & 0x1FF)?;
+        let pdpte = Self::read_entry(pml4e & ADDRESS_MASK, (va >> 30) & 0x1FF)?;
+
+        if pdpte & PAGE_SIZE != 0 {
+            let base = pdpte & ADDRESS_MASK & !(HUGE_PAGE_SIZE as u64 - 1);
+            return Ok(Self::translation(
+                base + (va & (HUGE_PAGE_SIZE as u64 - 1)),
+                &[pml4e, pdpte],
+            ));
+        }
+
+        let pde = Self::read_entry(pdpte & ADDRESS_MASK, (va >> 21) & 0x1FF)?;
+
+        if pde & PAGE_SIZE != 0 {
+            let base = pde & ADDRESS_MASK & !(LARGE_PAGE_SIZE as u64 - 1);
+            return Ok(Self::translation(
+                base + (va & (LARGE_PAGE_SIZE as u64 - 1)),
+                &[pml4e, pdpte, pde],
+            ));
+        }
+
+        let pte = Self::read_entry(pde & ADDRESS_MASK, (va >> 12) & 0x1FF)?;
+
+        Ok(Self::translation(
+            (pte & ADDRESS_MASK) + (va & (BASE_PAGE_SIZE as u64 - 1)),
+            &[pml4e, pdpte, pde, pte],
+        ))
+    }
+
+    /// Combines the access rights of the paging-structure entries of a translation.
+    fn translation(physical_address: u64, entries: &[u64]) -> Translation {
+        Translation {
+            physical_address,
+            writable: entries.iter().all(|entry| entry & WRITABLE != 0),
+            user: entries.iter().all(|entry| entry & USER != 0),
+            executable: entries.iter().all(|entry| entry & EXECUTE_DISABLE == 0),
+        }
+    }
+
+    /// Checks whether the access rights of a translation permit an access.
+    ///
+    /// # Arguments
+    ///
+    /// * `translation` - The translation of the accessed address.
+    /// * `write` - Whether the access is a write.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), HypervisorError>` - `GuestPageProtectionViolation` if the access isn't permitted.
+    pub fn check_access(
+        &self,
+        translation: &Translation,
+        write: bool,
+    ) -> Result<(), HypervisorError> {
+        let user_violation = self.user_mode && !translation.user;
+        let write_violation =
+            write && !translation.writable && (self.user_mode || self.write_protect);
+
+        match user_violation || write_violation {
+            true => Err(HypervisorError::GuestPageProtectionViolation),
+            false => Ok(()),
+        }
+    }
+
+    /// Reads a present paging-structure entry.
+    fn read_entry(table_pa: u64, index: u64) -> Result<u64, HypervisorError> {
+        let table_va = PhysicalAddress::va_from_pa(table_pa);
+        if table_va == 0 {
+            return Err(HypervisorError::GuestAddressTranslationFailed);
+        }
+
+        let entry = unsafe { (table_va as *const u64).add(index as usize).read_volatile() };
+        if entry & PRESENT == 0 {
+            return Err(HypervisorError::GuestAddressTranslationFailed);
+        }
+
+        Ok(entry)
+    }
+
+    /// Returns a host pointer to the guest virtual address and the number of bytes left in its page,
+    /// after checking the access rights of the page for the access.
+    fn host_pointer(&self, va: u64, write: bool) -> Result<(*mut u8, usize), HypervisorError> {
+        let translation = self.translate(va)?;
+        self.check_access(&translation, write)?;
+
+        let host_va = PhysicalAddress::va_from_pa(translation.physical_address);
+        if host_va == 0 {
+            return Err(HypervisorError::GuestAddressTranslationFailed);
+        }
+
+        let remaining = BASE_PAGE_SIZE - (va as usize & (BASE_PAGE_SIZE - 1));
+
+        Ok((host_va as *mut u8, remaining))
+    }
+
+    /// Reads guest memory into a buffer. Reads spanning multiple pages are translated page by page.
+    pub fn read_bytes(&self, va: u64, buffer: &mut [u8]) -> Result<(), HypervisorError> {
+        let mut offset = 0;
+
+        while offset < buffer.len() {
+            let (source, remaining) = self.host_pointer(va + offset as u64, false)?;
+            let length = remaining.min(buffer.len() - offset);
+
+            unsafe {
+                core::ptr::copy_nonoverlapping(source, buffer[offset..].as_mut_ptr(), length)
+            };
+
+            offset += length;
+        }
+
+        Ok(())
+    }
+
+    /// Writes a buffer to guest memory. Writes spanning multiple pages are translated page by page.
+    pub fn write_bytes(&self, va: u64, buffer: &[u8]) -> Result<(), HypervisorError> {
+        let mut offset = 0;
+
+        while offset < buffer.len() {
+            let (destination, remaining) = self.host_pointer(va + offset as u64, true)?;
+            let length = remaining.min(buffer.len() - offset);
+
+            unsafe {
+                core::ptr::copy_nonoverlapping(buffer[offset..].as_ptr(), destination, length)
+            };
+
+            offset += length;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the page fault that an access to guest memory raises, without performing the access.
+    ///
+    /// The pages of the access are checked in order, so the fault is reported for the first page that
+    /// can't be accessed, like the processor would.
+    ///
+    /// # Arguments
+    ///
+    /// * `va` - The guest virtual address of the access.
+    /// * `length` - The length of the access in bytes.
+    /// * `write` - Whether the access is a write.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<PageFault>` - The page fault, or `None` if the access is permitted.
+    pub fn page_fault(&self, va: u64, length: usize, write: bool) -> Option<PageFault> {
+        const PROTECTION_VIOLATION: u32 = 1 << 0;
+        const WRITE: u32 = 1 << 1;
+        const USER_MODE: u32 = 1 << 2;
+
+        let mut offset = 0;
+
+        while offset < length {
+            let address = va.wrapping_add(offset as u64);
+
+            match self.host_pointer(address, write) {
+                Ok((_, remaining)) => offset += remaining,
+                Err(error) => {
+                    let mut error_code = match error {
+                        HypervisorError::GuestPageProtectionViolation => PROTECTION_VIOLATION,
+                        _ => 0,
+                    };
+
+                    if write {
+                        error_code |= WRITE;
+                    }
+
+                    if self.user_mode {
+                        error_code |= USER_MODE;
+                    }
+
+                    return Some(PageFault {
+                        address,
+                        error_code,
+                    });
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Reads a value of type `T` from guest memory.
+    pub fn read<T: Copy>(&self, va: u64) -> Result<T, HypervisorError> {
+        let mut value = MaybeUninit::<T>::uninit();
+        let buffer = unsafe {
+            core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>())
+        };
+
+        self.read_bytes(va, buffer)?;
+
+        Ok(unsafe { value.assume_init() })
+    }
+
+    /// Writes a value of type `T` to guest memory.
+    pub fn write<T: Copy>(&self, va: u64, value: T) -> Result<(), HypervisorError> {
+        let buffer =
+            unsafe { core::slice::from_raw_parts(&value as *const T as *const u8, size_of::<T>()) };
+
+        self.write_bytes(va, buffer)
+    }
+
+    /// Returns the guest address of a stack argument at function entry.
+    ///
+    /// At function entry `rsp` points to the return address, followed by the 32 bytes of home space
+    /// for the register arguments and the remaining arguments.
+    fn stack_argument_address(guest_registers: &GuestRegisters, index: usize) -> u64 {
+        guest_registers.rsp + (size_of::<u64>() * (index + 1)) as u64
+    }
+
+    /// Reads an argument of the function that is about to execute, following the Microsoft x64 calling convention.
+    ///
+    /// # Arguments
+    ///
+    /// * `guest_registers` - The guest registers at function entry.
+    /// * `index` - The zero-based index of the argument.
+    pub fn argument(
+        &self,
+        guest_registers: &GuestRegisters,
+        index: usize,
+    ) -> Result<u64, HypervisorError> {
+        match index {
+            0 => Ok(guest_registers.rcx),
+            1 => Ok(guest_registers.rdx),
+            2 => Ok(guest_registers.r8),
+            3 => Ok(guest_registers.r9),
+            _ => self.read(Self::stack_argument_address(guest_registers, index)),
+        }
+    }
+
+    /// Modifies an argument of the function that is about to execute, following the Microsoft x64 calling convention.
+    ///
+    /// # Arguments
+    ///
+    /// * `guest_registers` - The guest registers at function entry.
+    /// * `index` - The zero-based index of the argument.
+    /// * `value` - The new value of the argument.
+    pub fn set_argument(
+        &self,
+        guest_registers: &mut GuestRegisters,
+        index: usize,
+        value: u64,
+    ) -> Result<(), HypervisorError> {
+        match index {
+            0 => guest_registers.rcx = value,
+            1 => guest_registers.rdx = value,
+            2 => guest_registers.r8 = value,
+            3 => guest_registers.r9 = value,
+            _ => self.write(Self::stack_argument_address(guest_registers, index), value)?,
+        }
+
+        Ok(())
+    }
+
+    /// Reads the register arguments of the function that is about to execute.
+    pub fn register_arguments(guest_registers: &GuestRegisters) -> [u64; REGISTER_ARGUMENTS] {
+        [
+            guest_registers.rcx,
+            guest_registers.rdx,
+            guest_registers.r8,
+            guest_registers.r9,
+        ]
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {super::*, alloc::boxed::Box};
+
+    /// A page of the guest, used both as paging structure and as data.
+    #[repr(C, align(4096))]
+    pub(crate) struct Page(pub(crate) [u64; 512]);
+
+    /// The guest virtual address mapped by the paging structures of `PageTables`.
+    pub(crate) const VA: u64 = 0x0000_7FF6_1234_5000;
+
+    /// Paging structures mapping `VA` and the page after it, with the physical addresses being the host
+    /// addresses of the pages.
+    pub(crate) struct PageTables {
+        pub(crate) pml4: Box<Page>,
+        pdpt: Box<Page>,
+        pd: Box<Page>,
+        pt: Box<Page>,
+        pub(crate) data: [Box<Page>; 2],
+    }
+
+    impl PageTables {
+        /// Maps `VA` and the page after it with the flags of the PTEs, and the upper levels writable and
+        /// accessible from user mode.
+        pub(crate) fn new(pte_flags: u64) -> Self {
+            let mut tables = Self {
+                pml4: Box::new(Page([0; 512])),
+                pdpt: Box::new(Page([0; 512])),
+                pd: Box::new(Page([0; 512])),
+                pt: Box::new(Page([0; 512])),
+                data: [Box::new(Page([0; 512])), Box::new(Page([0; 512]))],
+            };
+
+            let upper = PRESENT | WRITABLE | USER;
+            tables.pml4.0[((VA >> 39) & 0x1FF) as usize] = address(&tables.pdpt) | upper;
+            tables.pdpt.0[((VA >> 30) & 0x1FF) as usize] = address(&tables.pd) | upper;
+            tables.pd.0[((VA >> 21) & 0x1FF) as usize] = address(&tables.pt) | upper;
+
+            for (index, data) in tables.data.iter().enumerate() {
+                let pte_index = ((VA >> 12) & 0x1FF) as usize + index;
+                tables.pt.0[pte_index] = address(data) | pte_flags;
+            }
+
+            tables
+        }
+
+        /// Returns the guest memory of the paging structures, accessed on behalf of supervisor mode.
+        pub(crate) fn memory(&self) -> GuestMemory {
+            GuestMemory::new(address(&self.pml4))
+        }
+    }
+
+    /// Returns the physical address of a page, which is its host address.
+    pub(crate) fn address(page: &Page) -> u64 {
+        page as *const Page as u64
+    }
+
+    #[test]
+    fn translate_combines_the_access_rights() {
+        let tables = PageTables::new(PRESENT | EXECUTE_DISABLE);
+
+        assert_eq!(
+            tables.memory().translate(VA + 0x18).unwrap(),
+            Translation {
+                physical_address: address(&tables.data[0]) + 0x18,
+                writable: false,
+                user: false,
+                executable: false,
+            }
+        );
+    }
+
+    #[test]
+    fn translate_fails_for_unmapped_addresses() {
+        let tables = PageTables::new(PRESENT);
+
+        assert!(matches!(
+            tables.memory().translate(VA + 0x2000),
+            Err(HypervisorError::GuestAddressTranslationFailed)
+        ));
+        assert!(matches!(
+            tables.memory().translate(VA ^ (1 << 39)),
+            Err(HypervisorError::GuestAddressTranslationFailed)
+        ));
+    }
+
+    #[test]
+    fn reads_and_writes_span_pages() {
+        let mut tables = PageTables::new(PRESENT | WRITABLE);
+        tables.data[0].0[511] = 0x1122_3344_5566_7788;
+        tables.data[1].0[0] = 0x99AA_BBCC_DDEE_FF00;
+
+        let memory = tables.memory();
+        let value = memory.read::<[u64; 2]>(VA + 0xFF8).unwrap();
+        assert_eq!(value, [0x1122_3344_5566_7788, 0x99AA_BBCC_DDEE_FF00]);
+
+        memory.write(VA + 0xFFC, 0xDEAD_BEEF_CAFE_F00Du64).unwrap();
+        assert_eq!(tables.data[0].0[511], 0xCAFE_F00D_5566_7788);
+        assert_eq!(tables.data[1].0[0], 0x99AA_BBCC_DEAD_BEEF);
+    }
+
+    #[test]
+    fn writes_to_read_only_pages_are_rejected() {
+        let tables = PageTables::new(PRESENT | USER);
+        let memory = tables.memory();
+
+        assert!(matches!(memory.read::<u64>(VA), Ok(0)));
+        assert!(matches!(
+            memory.write(VA, 1u64),
+            Err(HypervisorError::GuestPageProtectionViolation)
+        ));
+        assert!(matches!(
+            memory.with_user_mode(true).write(VA, 1u64),
+            Err(HypervisorError::GuestPageProtectionViolation)
+        ));
+    }
+
+    #[test]
+    fn supervisor_writes_ignore_read_only_pages_without_write_protect() {
+        let tables = PageTables::new(PRESENT);
+        let memory = GuestMemory {
+            write_protect: false,
+            ..tables.memory()
+        };
+
+        assert!(matches!(memory.write(VA, 1u64), Ok(())));
+        assert_eq!(tables.data[0].0[0], 1);
+    }
+
+    #[test]
+    fn user_mode_accesses_to_supervisor_pages_are_rejected() {
+        let tables = PageTables::new(PRESENT | WRITABLE);
+        let memory = tables.memory().with_user_mode(true);
+
+        assert!(matches!(
+            memory.read::<u64>(VA),
+            Err(HypervisorError::GuestPageProtectionViolation)
+        ));
+        assert!(matches!(
+            memory.write(VA, 1u64),
+            Err(HypervisorError::GuestPageProtectionViolation)
+        ));
+        assert!(matches!(tables.memory().write(VA, 1u64), Ok(())));
+    }
+
+    #[test]
+    fn page_faults_describe_the_first_page_that_fails() {
+        let tables = PageTables::new(PRESENT | USER);
+        let memory = tables.memory();
+
+        assert_eq!(memory.page_fault(VA + 0xFF8, 0x10, false), None);
+        assert_eq!(
+            memory.page_fault(VA + 0x1FF8, 0x10, false),
+            Some(PageFault {
+                address: VA + 0x2000,
+                error_code: 0,
+            })
+        );
+        assert_eq!(
+            memory.with_user_mode(true).page_fault(VA + 8, 4, true),
+            Some(PageFault {
+                address: VA + 8,
+                error_code: 0b111,
+            })
+        );
+    }
+
+    #[test]
+    fn current_uses_the_privilege_level_of_the_guest() {
+        use crate::intel::vmcs_access::SoftVmcs;
+
+        let tables = PageTables::new(PRESENT | WRITABLE);
+        let vmcs = SoftVmcs::new()
+            .with(guest::CR3, address(&tables.pml4))
+            .with(guest::CS_SELECTOR, 0x33)
+            .with(guest::CR0, CR0_WRITE_PROTECT);
+
+        assert!(matches!(
+            GuestMemory::current(&vmcs).read::<u64>(VA),
+            Err(HypervisorError::GuestPageProtectionViolation)
+        ));
+    }
+}
@@ -52,8 +52,8 @@ fn invept(invept_type: InveptType, eptp: u64) {
 ///
 /// # Arguments
 /// * `eptp` - The Extended Page Table Pointer used for Single Context INVEPT.
-///            It should be a 64-bit value formed by concatenating the EPTP's memory type (bits 2:0),
-///            page-walk length (bits 5:3), and address of the EPTP (bits 63:12).
+///   It should be a 64-bit value formed by concatenating the EPTP's memory type (bits 2:0),
+///   page-walk length (bits 5:3), and address of the EPTP (bits 63:12).
 pub fn invept_single_context(eptp: u64) {
     // Perform the INVEPT operation for a single context.
     invept(InveptType::SingleContext, eptp);
@@ -0,0 +1,63 @@
+//! This module provides utilities and structures to manage the I/O Bitmaps in VMX.
+//! The I/O Bitmaps are used to control the behavior of the I/O instructions (IN, INS, OUT and OUTS)
+//! in a virtualized environment.
+
+use {crate::utils::alloc::PhysicalAllocator, alloc::boxed::Box};
+
+/// Represents the I/O Bitmaps used in VMX.
+///
+/// In processors that support the 1-setting of the “use I/O bitmaps” VM-execution control, the
+/// VM-execution control fields include the 64-bit physical addresses of I/O bitmaps A and B, which are
+/// each 4 KBytes in size. An I/O instruction causes a VM exit if the bit of any port it accesses is set.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 25.6.4 I/O-Bitmap Addresses
+#[repr(C, align(4096))]
+pub struct IoBitmap {
+    /// I/O bitmap A. Contains one bit for each I/O port in the range 0000H through 7FFFH.
+    pub bitmap_a: [u8; 0x1000],
+
+    /// I/O bitmap B. Contains one bit for each I/O port in the range 8000H through FFFFH.
+    pub bitmap_b: [u8; 0x1000],
+}
+
+impl IoBitmap {
+    /// Sets up the I/O Bitmaps, without any intercepted port.
+    pub fn new() -> Box<IoBitmap, PhysicalAllocator> {
+        log::trace!("Setting up I/O Bitmaps");
+
+        let instance = Self {
+            bitmap_a: [0; 0x1000],
+            bitmap_b: [0; 0x1000],
+        };
+        let instance = Box::<Self, PhysicalAllocator>::new_in(instance, PhysicalAllocator);
+
+        log::trace!("I/O Bitmaps setup successfully!");
+
+        instance
+    }
+
+    /// Intercepts the accesses to an I/O port, so the I/O instructions accessing it cause VM exits.
+    ///
+    /// # Arguments
+    /// * `port` - The I/O port.
+    pub fn hook_port(&mut self, port: u16) {
+        let (bitmap, index) = match port {
+            0..=0x7FFF => (&mut self.bitmap_a, port as usize),
+            _ => (&mut self.bitmap_b, (port - 0x8000) as usize),
+        };
+
+        bitmap[index / 8] |= 1 << (index % 8);
+
+        log::trace!("Intercepting I/O port {:#x}", port);
+    }
+
+    /// Checks whether the accesses to an I/O port are intercepted.
+    pub fn is_port_hooked(&self, port: u16) -> bool {
+        let (bitmap, index) = match port {
+            0..=0x7FFF => (&self.bitmap_a, port as usize),
+            _ => (&self.bitmap_b, (port - 0x8000) as usize),
+        };
+
+        bitmap[index / 8] & (1 << (index % 8)) != 0
+    }
+}
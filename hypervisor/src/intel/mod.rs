@@ -1,20 +1,36 @@
+pub mod address_space;
 pub mod controls;
+pub mod cpu_access;
 pub mod descriptor;
 pub mod ept;
 pub mod events;
+pub mod exit_qualification;
+pub mod guest_memory;
 pub mod invept;
 pub mod invvpid;
+pub mod io_bitmap;
 pub mod msr_bitmap;
 pub mod paging;
 pub mod segmentation;
 pub mod shared_data;
 pub mod support;
+pub mod syscall_hook;
+pub mod syscall_tracer;
+#[cfg(windows)]
 pub mod vcpu;
 pub mod vmcs;
+pub mod vmcs_access;
+pub mod vmcs_fields;
+pub mod vmcs_snapshot;
+pub mod vmentry_checks;
 pub mod vmerror;
 pub mod vmexit;
+#[cfg(windows)]
 pub mod vmlaunch;
+#[cfg(windows)]
 pub mod vmm;
+#[cfg(windows)]
 pub mod vmstack;
+#[cfg(windows)]
 pub mod vmx;
 pub mod vmxon;
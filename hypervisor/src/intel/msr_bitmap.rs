@@ -2,15 +2,7 @@
 //! The MSR Bitmap is used to control the behavior of RDMSR and WRMSR instructions
 //! in a virtualized environment.
 
-use {
-    crate::utils::alloc::PhysicalAllocator,
-    alloc::boxed::Box,
-    core::mem::MaybeUninit,
-    wdk_sys::{
-        ntddk::{RtlClearAllBits, RtlInitializeBitMap},
-        RTL_BITMAP,
-    },
-};
+use {crate::utils::alloc::PhysicalAllocator, alloc::boxed::Box};
 
 /// Represents the MSR Bitmap structure used in VMX.
 ///
@@ -52,32 +44,43 @@ impl MsrBitmap {
             write_low_msrs: [0; 0x400],
             write_high_msrs: [0; 0x400],
         };
-        let mut instance = Box::<Self, PhysicalAllocator>::new_in(instance, PhysicalAllocator);
 
-        log::trace!("Initializing MSR Bitmap");
-
-        Self::initialize_bitmap(instance.as_mut() as *mut _ as _);
+        // The bitmaps are created cleared, so no MSR access causes a VM exit until it's hooked.
+        let instance = Box::<Self, PhysicalAllocator>::new_in(instance, PhysicalAllocator);
 
         log::trace!("MSR Bitmap setup successfully!");
 
         instance
     }
 
-    /// Initializes the MSR Bitmap.
+    /// Intercepts reads and writes of an MSR, so RDMSR and WRMSR applied to it cause VM exits.
     ///
     /// # Arguments
-    /// * `bitmap_ptr` - The virtual address of the MSR Bitmap.
-    fn initialize_bitmap(bitmap_ptr: *mut u64) {
-        let mut bitmap_header: MaybeUninit<RTL_BITMAP> = MaybeUninit::uninit();
-        let bitmap_header_ptr = bitmap_header.as_mut_ptr() as *mut _;
+    /// * `msr` - The address of the MSR. Must be in the range 00000000H to 00001FFFH or C0000000H to C0001FFFH.
+    pub fn hook_msr(&mut self, msr: u32) {
+        const LOW_MSR_END: u32 = 0x00001FFF;
+        const HIGH_MSR_START: u32 = 0xC0000000;
+        const HIGH_MSR_END: u32 = 0xC0001FFF;
+
+        let (read_bitmap, write_bitmap, index) = match msr {
+            0..=LOW_MSR_END => (&mut self.read_low_msrs, &mut self.write_low_msrs, msr),
+            HIGH_MSR_START..=HIGH_MSR_END => (
+                &mut self.read_high_msrs,
+                &mut self.write_high_msrs,
+                msr - HIGH_MSR_START,
+            ),
+            _ => {
+                log::error!("MSR {:#x} can't be intercepted by the MSR Bitmap", msr);
+                return;
+            }
+        };
+
+        let byte = (index / 8) as usize;
+        let bit = 1u8 << (index % 8);
+
+        read_bitmap[byte] |= bit;
+        write_bitmap[byte] |= bit;
 
-        unsafe {
-            RtlInitializeBitMap(
-                bitmap_header_ptr as _,
-                bitmap_ptr as _,
-                core::mem::size_of::<Self>() as u32,
-            )
-        }
-        unsafe { RtlClearAllBits(bitmap_header_ptr as _) }
+        log::trace!("Intercepting MSR {:#x}", msr);
     }
 }
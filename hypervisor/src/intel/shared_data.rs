@@ -1,17 +1,23 @@
 //! A crate for managing hypervisor functionality, particularly focused on
-//! Extended Page Tables (EPT) and Model-Specific Register (MSR) bitmaps.
+//! Extended Page Tables (EPT), Model-Specific Register (MSR) bitmaps and I/O bitmaps.
 //! Includes support for primary and optional secondary EPTs.
 
 use {
     crate::{
         error::HypervisorError,
         intel::{
+            address_space::AddressSpaceMonitor,
             ept::{hooks::HookManager, paging::Ept},
+            io_bitmap::IoBitmap,
             msr_bitmap::MsrBitmap,
+            syscall_hook::SyscallHooks,
+            syscall_tracer::SyscallTracer,
+            vmexit::registry::{VmExitData, VmExitHandlers},
         },
         utils::alloc::PhysicalAllocator,
     },
     alloc::boxed::Box,
+    x86::msr::{IA32_EFER, IA32_LSTAR},
 };
 
 /// Represents shared data structures for hypervisor operations.
@@ -23,6 +29,9 @@ pub struct SharedData {
     /// A bitmap for handling MSRs.
     pub msr_bitmap: Box<MsrBitmap, PhysicalAllocator>,
 
+    /// The I/O bitmaps intercepting the ports of the I/O port handlers.
+    pub io_bitmap: Box<IoBitmap, PhysicalAllocator>,
+
     /// The primary Extended Page Table.
     pub primary_ept: Box<Ept, PhysicalAllocator>,
 
@@ -39,17 +48,34 @@ pub struct SharedData {
 
     /// The hook manager.
     pub hook_manager: Box<HookManager>,
+
+    /// The syscall hooks intercepting `IA32_LSTAR`, if enabled.
+    pub syscall_hooks: Option<Box<SyscallHooks>>,
+
+    /// The syscall tracer clearing `EFER.SCE`, if enabled.
+    pub syscall_tracer: Option<Box<SyscallTracer>>,
+
+    /// The address-space monitor enabling CR3-load exiting, if enabled.
+    pub address_space_monitor: Option<Box<AddressSpaceMonitor>>,
+
+    /// The VM-exit handlers keyed by the basic exit reason.
+    pub vmexit_handlers: Box<VmExitHandlers>,
 }
 
 impl SharedData {
     /// Creates a new instance of `SharedData` with primary and optionally secondary EPTs.
     ///
-    /// This function initializes the MSR bitmap and sets up the EPTs.
+    /// This function initializes the MSR bitmap and the I/O bitmaps, and sets up the EPTs.
     ///
     /// # Arguments
     ///
     /// * `primary_ept`: The primary EPT to be used.
     /// * `secondary_ept`: The secondary EPT to be used if the feature is enabled.
+    /// * `hook_manager`: The hook manager.
+    /// * `syscall_hooks`: The syscall hooks intercepting `IA32_LSTAR`, if enabled.
+    /// * `syscall_tracer`: The syscall tracer clearing `EFER.SCE`, if enabled.
+    /// * `address_space_monitor`: The address-space monitor enabling CR3-load exiting, if enabled.
+    /// * `vmexit_handlers`: The VM-exit handlers.
     ///
     /// # Returns
     /// A result containing a boxed `SharedData` instance or an error of type `HypervisorError`.
@@ -58,32 +84,59 @@ impl SharedData {
         primary_ept: Box<Ept, PhysicalAllocator>,
         secondary_ept: Box<Ept, PhysicalAllocator>,
         hook_manager: Box<HookManager>,
+        syscall_hooks: Option<Box<SyscallHooks>>,
+        syscall_tracer: Option<Box<SyscallTracer>>,
+        address_space_monitor: Option<Box<AddressSpaceMonitor>>,
+        vmexit_handlers: Box<VmExitHandlers>,
     ) -> Result<Box<Self>, HypervisorError> {
         log::trace!("Initializing shared data");
 
         let primary_eptp = primary_ept.create_eptp_with_wb_and_4lvl_walk()?;
         let secondary_eptp = secondary_ept.create_eptp_with_wb_and_4lvl_walk()?;
 
-        let bitmap = MsrBitmap::new();
-        //bitmap.hook_msr(IA32_EFER);
+        let mut bitmap = MsrBitmap::new();
+
+        if syscall_tracer.is_some() {
+            bitmap.hook_msr(IA32_EFER);
+        }
+
+        if syscall_hooks.is_some() {
+            bitmap.hook_msr(IA32_LSTAR);
+        }
+
+        let mut io_bitmap = IoBitmap::new();
+
+        for port in vmexit_handlers.io_ports().ports() {
+            io_bitmap.hook_port(port);
+        }
 
         Ok(Box::new(Self {
             msr_bitmap: { bitmap },
+            io_bitmap,
             primary_ept,
             primary_eptp,
             secondary_ept,
             secondary_eptp,
             hook_manager,
+            syscall_hooks,
+            syscall_tracer,
+            address_space_monitor,
+            vmexit_handlers,
         }))
     }
 
     /// Creates a new instance of `SharedData` with primary EPTs.
     ///
-    /// This function initializes the MSR bitmap and sets up the EPTs.
+    /// This function initializes the MSR bitmap and the I/O bitmaps, and sets up the EPTs.
     ///
     /// # Arguments
     ///
     /// * `primary_ept`: The primary EPT to be used.
+    /// * `hook_manager`: The hook manager.
+    /// * `syscall_hooks`: The syscall hooks intercepting `IA32_LSTAR`, if enabled.
+    /// * `syscall_tracer`: The syscall tracer clearing `EFER.SCE`, if enabled.
+    /// * `address_space_monitor`: The address-space monitor enabling CR3-load exiting, if enabled.
+    /// * `vmexit_handlers`: The VM-exit handlers.
     ///
     /// # Returns
     /// A result containing a boxed `SharedData` instance or an error of type `HypervisorError`.
@@ -91,19 +144,59 @@ impl SharedData {
     pub fn new(
         primary_ept: Box<Ept, PhysicalAllocator>,
         hook_manager: Box<HookManager>,
+        syscall_hooks: Option<Box<SyscallHooks>>,
+        syscall_tracer: Option<Box<SyscallTracer>>,
+        address_space_monitor: Option<Box<AddressSpaceMonitor>>,
+        vmexit_handlers: Box<VmExitHandlers>,
     ) -> Result<Option<Box<Self>>, HypervisorError> {
         log::trace!("Initializing shared data");
 
         let primary_eptp = primary_ept.create_eptp_with_wb_and_4lvl_walk()?;
 
-        let bitmap = MsrBitmap::new();
-        //bitmap.hook_msr(IA32_EFER);
+        let mut bitmap = MsrBitmap::new();
+
+        if syscall_tracer.is_some() {
+            bitmap.hook_msr(IA32_EFER);
+        }
+
+        if syscall_hooks.is_some() {
+            bitmap.hook_msr(IA32_LSTAR);
+        }
+
+        let mut io_bitmap = IoBitmap::new();
+
+        for port in vmexit_handlers.io_ports().ports() {
+            io_bitmap.hook_port(port);
+        }
 
         Ok(Some(Box::new(Self {
             msr_bitmap: { bitmap },
+            io_bitmap,
             primary_ept,
             primary_eptp,
             hook_manager,
+            syscall_hooks,
+            syscall_tracer,
+            address_space_monitor,
+            vmexit_handlers,
         })))
     }
+
+    /// Borrows the state that the VM-exit handlers use.
+    ///
+    /// # Returns
+    ///
+    /// * `VmExitData` - The hooks, the syscall hooks and tracer, the address-space monitor, the I/O port handlers and the EPT pointers.
+    pub fn vmexit_data(&self) -> VmExitData<'_> {
+        VmExitData {
+            hook_manager: Some(&self.hook_manager),
+            syscall_hooks: self.syscall_hooks.as_deref(),
+            syscall_tracer: self.syscall_tracer.as_deref(),
+            address_space_monitor: self.address_space_monitor.as_deref(),
+            io_ports: Some(self.vmexit_handlers.io_ports()),
+            primary_eptp: self.primary_eptp,
+            #[cfg(feature = "secondary-ept")]
+            secondary_eptp: self.secondary_eptp,
+        }
+    }
 }
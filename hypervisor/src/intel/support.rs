@@ -1,5 +1,6 @@
-use super::vmcs::Vmcs;
+use super::{vmcs::Vmcs, vmerror::VmInstructionError};
 use crate::error::HypervisorError;
+use x86::vmx::{vmcs, VmFail};
 
 /// Enable VMX operation.
 pub fn vmxon(vmxon_region: u64) {
@@ -30,15 +31,39 @@ pub fn vmptrst() -> *const Vmcs {
     unsafe { x86::bits64::vmx::vmptrst().unwrap() as *const Vmcs }
 }
 
-/// Read a specified field from a VMCS.
-pub fn vmread(field: u32) -> u64 {
-    unsafe { x86::bits64::vmx::vmread(field) }.unwrap_or(0)
+/// Read a specified field from a VMCS, reporting a VMfail as an error.
+///
+/// The fields are accessed through `vmcs_access`, which only takes the fields of the catalog.
+pub(crate) fn try_vmread(field: u32) -> Result<u64, HypervisorError> {
+    unsafe { x86::bits64::vmx::vmread(field) }.map_err(vmfail_error)
 }
 
-/// Write to a specified field in a VMCS.
-pub fn vmwrite<T: Into<u64>>(field: u32, val: T)
-where
-    u64: From<T>,
-{
-    unsafe { x86::bits64::vmx::vmwrite(field, u64::from(val)) }.unwrap();
+/// Write to a specified field in a VMCS, reporting a VMfail as an error.
+///
+/// The fields are accessed through `vmcs_access`, which only takes the fields of the catalog.
+pub(crate) fn try_vmwrite(field: u32, value: u64) -> Result<(), HypervisorError> {
+    unsafe { x86::bits64::vmx::vmwrite(field, value) }.map_err(vmfail_error)
+}
+
+/// Converts the VMfail of a VMX instruction into an error.
+///
+/// On VMfailValid, the VM-instruction error of the current VMCS is decoded into `VmFailValid`.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 31.2 CONVENTIONS
+/// and 31.4 VM INSTRUCTION ERROR NUMBERS
+pub fn vmfail_error(fail: VmFail) -> HypervisorError {
+    match fail {
+        VmFail::VmFailInvalid => HypervisorError::VmFailInvalid,
+        VmFail::VmFailValid => vm_instruction_error().map_or(
+            HypervisorError::UnknownVMInstructionError,
+            HypervisorError::VmFailValid,
+        ),
+    }
+}
+
+/// Returns the VM-instruction error of the current VMCS, or `None` if the error number is unknown.
+pub fn vm_instruction_error() -> Option<VmInstructionError> {
+    unsafe { x86::bits64::vmx::vmread(vmcs::ro::VM_INSTRUCTION_ERROR) }
+        .ok()
+        .and_then(|error| VmInstructionError::from_u32(error as u32))
 }
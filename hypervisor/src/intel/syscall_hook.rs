@@ -0,0 +1,464 @@
+//! Provides syscall interception through the `IA32_LSTAR` MSR, as an alternative to hooking
+//! the individual functions of the SSDT with EPT hooks.
+//!
+//! The system call entry point (`KiSystemCall64` or `KiSystemCall64Shadow`) stored in `IA32_LSTAR` is
+//! replaced on every processor with a dispatcher stub consisting of `int3` instructions. Every `syscall`
+//! therefore causes a VM exit at the dispatcher, where the pre callback registered for the syscall number
+//! is executed before execution continues at the original entry point. With kernel virtual address
+//! shadowing the dispatcher isn't mapped in the user address space, so the VM exit is caused by the
+//! instruction fetch page fault instead of the breakpoint.
+//!
+//! Post callbacks are executed by setting the trap flag in the RFLAGS restored by `sysret` (R11), which
+//! causes a debug exception after the first instruction at the return address (usually the `ret` of the
+//! ntdll stub). Calls pending a return are keyed by the address space and the TEB of the calling thread.
+//!
+//! `IA32_LSTAR` is intercepted through the MSR bitmap, so the guest reads the original entry point and
+//! writes update it without replacing the dispatcher. Note that every syscall causes a VM exit,
+//! including the syscalls without registered callbacks.
+
+use {
+    crate::{
+        error::HypervisorError,
+        intel::{
+            cpu_access::CpuAccess,
+            guest_memory::GuestMemory,
+            vmcs_access::{VmcsAccess, VmcsFieldAccess},
+            vmcs_fields::guest,
+        },
+        utils::{capture::GuestRegisters, function_hook::HookAction},
+    },
+    alloc::{boxed::Box, collections::BTreeMap, vec},
+    core::{
+        mem::size_of,
+        sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
+    spin::Mutex,
+    x86::msr::{rdmsr, wrmsr, IA32_FMASK, IA32_LSTAR, IA32_STAR},
+};
+
+/// Maximum number of syscalls that can be pending a return at the same time.
+pub const MAX_PENDING_SYSCALLS: usize = 256;
+
+/// Size of the dispatcher stub.
+const DISPATCHER_SIZE: usize = 16;
+
+/// Number of the register arguments of a syscall (R10, RDX, R8, R9).
+const REGISTER_ARGUMENTS: usize = 4;
+
+/// Offset of the first stack argument from the user-mode stack pointer at the `syscall` instruction.
+/// The stack contains the return address of the ntdll stub and the home space of the register arguments.
+const STACK_ARGUMENTS_OFFSET: u64 = 0x28;
+
+/// The trap flag (TF) in RFLAGS.
+pub const RFLAGS_TRAP_FLAG: u64 = 1 << 8;
+
+/// The RFLAGS bits that are restored by `sysret` from R11.
+const SYSRET_RFLAGS_MASK: u64 = 0x3C_7FD7;
+
+/// The resume flag (RF) in RFLAGS.
+const RFLAGS_RESUME_FLAG: u64 = 1 << 16;
+
+/// The reserved bit 1 of RFLAGS, which is always set.
+const RFLAGS_RESERVED: u64 = 1 << 1;
+
+/// A callback executed in VMX root operation when a syscall is entered.
+///
+/// The callback receives the guest registers at the `syscall` instruction (the number is in RAX, the
+/// arguments in R10, RDX, R8, R9 and on the user stack), an accessor for the guest memory and the
+/// context of the syscall. Returning `HookAction::Return` completes the syscall with the value in RAX
+/// without executing it.
+pub type SyscallCallback = fn(&mut GuestRegisters, &GuestMemory, &SyscallContext) -> HookAction;
+
+/// A callback executed in VMX root operation after a syscall returned to user mode.
+///
+/// The callback receives the guest registers after the return (the status is in RAX), an accessor
+/// for the guest memory and the context recorded when the syscall was entered.
+pub type SyscallReturnCallback = fn(&mut GuestRegisters, &GuestMemory, &SyscallContext);
+
+/// The context of a syscall recorded at the `syscall` instruction.
+#[derive(Debug, Clone, Copy)]
+pub struct SyscallContext {
+    /// The syscall number (EAX).
+    pub number: u32,
+
+    /// The register arguments (R10, RDX, R8, R9).
+    pub arguments: [u64; REGISTER_ARGUMENTS],
+
+    /// The user-mode return address (RCX).
+    pub return_address: u64,
+
+    /// The user-mode stack pointer.
+    pub user_rsp: u64,
+
+    /// The guest CR3.
+    pub cr3: u64,
+
+    /// The TEB of the calling thread (the user-mode GS base).
+    pub teb: u64,
+
+    /// Whether the trap flag was already set by the caller.
+    user_trap_flag: bool,
+}
+
+impl SyscallContext {
+    /// Captures the context of a syscall at the `syscall` instruction.
+    ///
+    /// # Arguments
+    ///
+    /// * `guest_registers` - The guest registers at the dispatcher.
+    /// * `guest_memory` - The accessor for the guest memory.
+    /// * `vmcs` - The VMCS of the guest.
+    pub fn capture(
+        guest_registers: &GuestRegisters,
+        guest_memory: &GuestMemory,
+        vmcs: &dyn VmcsAccess,
+    ) -> Self {
+        Self {
+            number: guest_registers.rax as u32,
+            arguments: [
+                guest_registers.r10,
+                guest_registers.rdx,
+                guest_registers.r8,
+                guest_registers.r9,
+            ],
+            return_address: guest_registers.rcx,
+            user_rsp: guest_registers.rsp,
+            cr3: guest_memory.cr3(),
+            // `swapgs` hasn't been executed yet, so the GS base is still the user-mode one.
+            teb: vmcs.get(guest::GS_BASE),
+            user_trap_flag: guest_registers.r11 & RFLAGS_TRAP_FLAG != 0,
+        }
+    }
+
+    /// Reads an argument of the syscall.
+    ///
+    /// # Arguments
+    ///
+    /// * `guest_memory` - The accessor for the guest memory.
+    /// * `index` - The zero-based index of the argument.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<u64, HypervisorError>` - The value of the argument, or an error if the user stack couldn't be read.
+    pub fn argument(
+        &self,
+        guest_memory: &GuestMemory,
+        index: usize,
+    ) -> Result<u64, HypervisorError> {
+        match self.arguments.get(index) {
+            Some(argument) => Ok(*argument),
+            None => {
+                let offset = STACK_ARGUMENTS_OFFSET
+                    + ((index - REGISTER_ARGUMENTS) * size_of::<u64>()) as u64;
+
+                guest_memory.read::<u64>(self.user_rsp + offset)
+            }
+        }
+    }
+
+    /// Returns whether the trap flag was already set by the caller.
+    pub fn user_trap_flag(&self) -> bool {
+        self.user_trap_flag
+    }
+
+    /// Checks whether the syscall was made by the thread with the given TEB in the given address space.
+    fn is_thread(&self, cr3: u64, teb: u64) -> bool {
+        self.cr3 == cr3 && self.teb == teb
+    }
+
+    /// Checks whether the stack pointer matches a return from the syscall, after executing one instruction
+    /// at the return address (which is the `ret` of the ntdll stub or another instruction).
+    fn is_return(&self, rsp: u64) -> bool {
+        rsp == self.user_rsp || rsp == self.user_rsp + size_of::<u64>() as u64
+    }
+}
+
+/// The callbacks registered for a syscall number.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SyscallHandler {
+    /// The callback executed when the syscall is entered.
+    pub pre: Option<SyscallCallback>,
+
+    /// The callback executed after the syscall returned.
+    pub post: Option<SyscallReturnCallback>,
+}
+
+/// Manages the interception of `IA32_LSTAR` and the callbacks of the intercepted syscalls.
+pub struct SyscallHooks {
+    /// The dispatcher stub `IA32_LSTAR` points to.
+    dispatcher: Box<[u8]>,
+
+    /// The original system call entry point, as seen by the guest.
+    original_lstar: AtomicU64,
+
+    /// Whether `IA32_LSTAR` points to the dispatcher.
+    enabled: AtomicBool,
+
+    /// Whether a processor restores the original `IA32_LSTAR`, so the write passes through.
+    restoring: AtomicBool,
+
+    /// The callbacks keyed by syscall number.
+    handlers: BTreeMap<u32, SyscallHandler>,
+
+    /// The syscalls pending a return.
+    pending: Mutex<[Option<SyscallContext>; MAX_PENDING_SYSCALLS]>,
+
+    /// The slot that is evicted next if no slot is free.
+    next_eviction: AtomicUsize,
+}
+
+impl SyscallHooks {
+    /// Creates a new `SyscallHooks` instance, allocating the dispatcher stub and saving the current `IA32_LSTAR`.
+    pub fn new() -> Self {
+        let dispatcher = vec![0xCC_u8; DISPATCHER_SIZE].into_boxed_slice();
+        let original_lstar = unsafe { rdmsr(IA32_LSTAR) };
+
+        log::debug!(
+            "Syscall dispatcher address: {:#x}, original LSTAR: {:#x}",
+            dispatcher.as_ptr() as u64,
+            original_lstar
+        );
+
+        Self {
+            dispatcher,
+            original_lstar: AtomicU64::new(original_lstar),
+            enabled: AtomicBool::new(true),
+            restoring: AtomicBool::new(false),
+            handlers: BTreeMap::new(),
+            pending: Mutex::new([None; MAX_PENDING_SYSCALLS]),
+            next_eviction: AtomicUsize::new(0),
+        }
+    }
+
+    /// Registers the callback executed when a syscall is entered, replacing any previous one.
+    ///
+    /// # Arguments
+    ///
+    /// * `number` - The syscall number.
+    /// * `callback` - The callback.
+    pub fn register_pre(&mut self, number: u32, callback: SyscallCallback) {
+        self.handlers.entry(number).or_default().pre = Some(callback);
+    }
+
+    /// Registers the callback executed after a syscall returned, replacing any previous one.
+    ///
+    /// # Arguments
+    ///
+    /// * `number` - The syscall number.
+    /// * `callback` - The callback.
+    pub fn register_post(&mut self, number: u32, callback: SyscallReturnCallback) {
+        self.handlers.entry(number).or_default().post = Some(callback);
+    }
+
+    /// Returns the callbacks registered for a syscall number.
+    pub fn handler(&self, number: u32) -> Option<&SyscallHandler> {
+        self.handlers.get(&number)
+    }
+
+    /// Returns the address of the dispatcher stub.
+    pub fn dispatcher_address(&self) -> u64 {
+        self.dispatcher.as_ptr() as u64
+    }
+
+    /// Checks whether the address is the address of the dispatcher stub.
+    pub fn is_dispatcher(&self, address: u64) -> bool {
+        address == self.dispatcher_address()
+    }
+
+    /// Returns the original system call entry point.
+    pub fn original_lstar(&self) -> u64 {
+        self.original_lstar.load(Ordering::Relaxed)
+    }
+
+    /// Sets the original system call entry point, when the guest writes `IA32_LSTAR`.
+    pub fn set_original_lstar(&self, value: u64) {
+        self.original_lstar.store(value, Ordering::Relaxed);
+    }
+
+    /// Checks whether `IA32_LSTAR` points to the dispatcher.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Points `IA32_LSTAR` of the current processor to the dispatcher stub.
+    ///
+    /// Must be called on every processor before the guest is launched.
+    pub fn install(&self) {
+        unsafe { wrmsr(IA32_LSTAR, self.dispatcher_address()) };
+    }
+
+    /// Checks whether a processor restores the original `IA32_LSTAR`.
+    pub fn is_restoring(&self) -> bool {
+        self.restoring.load(Ordering::SeqCst)
+    }
+
+    /// Restores the original `IA32_LSTAR` of the current processor.
+    ///
+    /// It's called from the guest on every processor, and the write passes through the interception. The accesses
+    /// of the other processors remain shadowed until `disable` is called after every processor has been restored.
+    pub fn uninstall(&self) {
+        self.restoring.store(true, Ordering::SeqCst);
+        unsafe { wrmsr(IA32_LSTAR, self.original_lstar()) };
+        self.restoring.store(false, Ordering::SeqCst);
+    }
+
+    /// Stops shadowing `IA32_LSTAR`, after `uninstall` has been called on every processor.
+    pub fn disable(&self) {
+        self.enabled.store(false, Ordering::Relaxed);
+    }
+
+    /// Records a syscall pending a return.
+    ///
+    /// If no slot is free, the entry of a syscall that didn't return through `sysret`
+    /// (for example `NtContinue`) is evicted.
+    pub fn begin_return(&self, context: SyscallContext) {
+        let mut pending = self.pending.lock();
+
+        let slot = pending.iter().position(Option::is_none).unwrap_or_else(|| {
+            self.next_eviction.fetch_add(1, Ordering::Relaxed) % MAX_PENDING_SYSCALLS
+        });
+
+        pending[slot] = Some(context);
+    }
+
+    /// Takes the most recent syscall of the current thread pending a return.
+    ///
+    /// # Arguments
+    ///
+    /// * `cr3` - The guest CR3.
+    /// * `teb` - The TEB of the current thread (the user-mode GS base).
+    /// * `rsp` - The user-mode stack pointer.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<SyscallContext>` - The context of the syscall, or `None` if the thread has no syscall pending a return with the stack pointer.
+    pub fn take_return(&self, cr3: u64, teb: u64, rsp: u64) -> Option<SyscallContext> {
+        let mut pending = self.pending.lock();
+
+        // Nested syscalls (from user-mode callbacks) return first and use a lower stack pointer.
+        pending
+            .iter_mut()
+            .filter(|entry| {
+                entry.is_some_and(|entry| entry.is_thread(cr3, teb) && entry.is_return(rsp))
+            })
+            .min_by_key(|entry| entry.map(|entry| entry.user_rsp))
+            .and_then(Option::take)
+    }
+
+    /// Checks whether the current thread has a syscall pending a return for which the trap flag was set by the dispatcher.
+    pub fn has_pending_trap(&self, cr3: u64, teb: u64) -> bool {
+        self.pending.lock().iter().any(|entry| {
+            entry.is_some_and(|entry| entry.is_thread(cr3, teb) && !entry.user_trap_flag)
+        })
+    }
+}
+
+impl Default for SyscallHooks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Emulates `syscall` in 64-bit mode.
+///
+/// Saves the return address in RCX and RFLAGS in R11, masks RFLAGS with `IA32_FMASK`, switches CS and SS
+/// to the kernel-mode selectors of `IA32_STAR` and continues at `IA32_LSTAR`.
+///
+/// # Arguments
+///
+/// * `guest_registers` - The guest registers at the `syscall` instruction.
+/// * `instruction_length` - The length of the `syscall` instruction.
+/// * `vmcs` - The VMCS of the guest.
+/// * `cpu` - The processor whose syscall MSRs are used.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: SYSCALL—Fast System Call.
+pub fn emulate_syscall(
+    guest_registers: &mut GuestRegisters,
+    instruction_length: u64,
+    vmcs: &mut dyn VmcsAccess,
+    cpu: &dyn CpuAccess,
+) {
+    /// The access rights of the flat 64-bit kernel-mode code segment (type 0xB, S, DPL 0, P, L, G).
+    const KERNEL_CODE_ACCESS_RIGHTS: u32 = 0xA09B;
+
+    /// The access rights of the flat kernel-mode stack segment (type 0x3, S, DPL 0, P, D/B, G).
+    const KERNEL_STACK_ACCESS_RIGHTS: u32 = 0xC093;
+
+    let syscall_selector = (cpu.rdmsr(IA32_STAR) >> 32) as u16;
+    let fmask = cpu.rdmsr(IA32_FMASK);
+
+    guest_registers.rcx = guest_registers.rip + instruction_length;
+    guest_registers.r11 = guest_registers.rflags & !RFLAGS_RESUME_FLAG;
+    guest_registers.rflags =
+        (guest_registers.rflags & !fmask & !RFLAGS_RESUME_FLAG) | RFLAGS_RESERVED;
+
+    // Continues at the dispatcher if the syscall hooks are installed.
+    guest_registers.rip = cpu.rdmsr(IA32_LSTAR);
+
+    vmcs.set(guest::CS_SELECTOR, syscall_selector & !3);
+    vmcs.set(guest::CS_BASE, 0u64);
+    vmcs.set(guest::CS_LIMIT, u32::MAX);
+    vmcs.set(guest::CS_ACCESS_RIGHTS, KERNEL_CODE_ACCESS_RIGHTS);
+
+    vmcs.set(guest::SS_SELECTOR, (syscall_selector + 8) & !3);
+    vmcs.set(guest::SS_BASE, 0u64);
+    vmcs.set(guest::SS_LIMIT, u32::MAX);
+    vmcs.set(guest::SS_ACCESS_RIGHTS, KERNEL_STACK_ACCESS_RIGHTS);
+
+    vmcs.set(guest::RIP, guest_registers.rip);
+    vmcs.set(guest::RFLAGS, guest_registers.rflags);
+}
+
+/// Emulates `sysret` to return from a syscall to user mode.
+///
+/// Loads RIP from RCX and RFLAGS from R11, and switches CS and SS to the user-mode selectors of `IA32_STAR`.
+/// The caller must ensure that RCX is canonical for a return to 64-bit mode, as `sysret` raises `#GP(0)` otherwise.
+///
+/// # Arguments
+///
+/// * `guest_registers` - The guest registers at the `sysret` instruction (or the `syscall` instruction, to complete a syscall without executing it).
+/// * `to_64bit_mode` - Whether to return to 64-bit mode (`sysretq`, REX.W) or to compatibility mode.
+/// * `vmcs` - The VMCS of the guest.
+/// * `cpu` - The processor whose syscall MSRs are used.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: SYSRET—Return From Fast System Call.
+pub fn emulate_sysret(
+    guest_registers: &mut GuestRegisters,
+    to_64bit_mode: bool,
+    vmcs: &mut dyn VmcsAccess,
+    cpu: &dyn CpuAccess,
+) {
+    /// The access rights of the flat 64-bit user-mode code segment (type 0xB, S, DPL 3, P, L, G).
+    const USER_CODE_ACCESS_RIGHTS: u32 = 0xA0FB;
+
+    /// The access rights of the flat 32-bit user-mode code segment (type 0xB, S, DPL 3, P, D/B, G).
+    const USER_CODE32_ACCESS_RIGHTS: u32 = 0xC0FB;
+
+    /// The access rights of the flat user-mode stack segment (type 0x3, S, DPL 3, P, D/B, G).
+    const USER_STACK_ACCESS_RIGHTS: u32 = 0xC0F3;
+
+    let sysret_selector = (cpu.rdmsr(IA32_STAR) >> 48) as u16;
+
+    let (code_selector, code_access_rights) = if to_64bit_mode {
+        guest_registers.rip = guest_registers.rcx;
+        (sysret_selector + 16, USER_CODE_ACCESS_RIGHTS)
+    } else {
+        guest_registers.rip = guest_registers.rcx & u32::MAX as u64;
+        (sysret_selector, USER_CODE32_ACCESS_RIGHTS)
+    };
+
+    guest_registers.rflags = (guest_registers.r11 & SYSRET_RFLAGS_MASK) | RFLAGS_RESERVED;
+
+    vmcs.set(guest::CS_SELECTOR, code_selector | 3);
+    vmcs.set(guest::CS_BASE, 0u64);
+    vmcs.set(guest::CS_LIMIT, u32::MAX);
+    vmcs.set(guest::CS_ACCESS_RIGHTS, code_access_rights);
+
+    vmcs.set(guest::SS_SELECTOR, (sysret_selector + 8) | 3);
+    vmcs.set(guest::SS_BASE, 0u64);
+    vmcs.set(guest::SS_LIMIT, u32::MAX);
+    vmcs.set(guest::SS_ACCESS_RIGHTS, USER_STACK_ACCESS_RIGHTS);
+
+    vmcs.set(guest::RIP, guest_registers.rip);
+    vmcs.set(guest::RFLAGS, guest_registers.rflags);
+}
@@ -0,0 +1,398 @@
+//! Provides a generic syscall tracer based on clearing `EFER.SCE` for the guest.
+//!
+//! With the syscall enable bit cleared, `syscall` and `sysret` raise an invalid opcode exception (`#UD`),
+//! which causes a VM exit. The hypervisor records the syscall and emulates both instructions in VMX root
+//! operation, so every syscall of the system can be traced without knowing the addresses of the syscalls.
+//!
+//! `IA32_EFER` is intercepted through the MSR bitmap and loaded on VM entry, so the guest reads and writes
+//! its own view of the register with `EFER.SCE` set, while the processor executes the guest with it cleared.
+//! The view is shared by all processors, and a write on one processor is applied to the VMCS of the others
+//! on their next VM exit.
+//!
+//! The calling process is identified by the kernel, through the current thread of the processor control
+//! region (`KPCR`), instead of the TEB, which is writable by user mode.
+//! The records are stored in a fixed-size ring buffer, which doesn't allocate in VMX root operation.
+
+use {
+    crate::{
+        intel::{
+            cpu_access::CpuAccess,
+            guest_memory::GuestMemory,
+            vmcs_access::{VmcsAccess, VmcsFieldAccess},
+            vmcs_fields::guest,
+        },
+        utils::{capture::GuestRegisters, windows_build::KernelOffsets},
+    },
+    alloc::{vec, vec::Vec},
+    core::sync::atomic::{AtomicU64, Ordering},
+    spin::Mutex,
+    x86::msr::IA32_KERNEL_GSBASE,
+};
+
+/// Number of the records kept by the tracer. The oldest record is overwritten when the buffer is full.
+pub const SYSCALL_TRACE_CAPACITY: usize = 1024;
+
+/// The syscall enable bit (SCE) of `IA32_EFER`.
+pub const EFER_SCE: u64 = 1 << 0;
+
+/// A syscall recorded by the tracer.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SyscallRecord {
+    /// The syscall number (EAX).
+    pub number: u32,
+
+    /// The register arguments (R10, RDX, R8, R9).
+    pub arguments: [u64; 4],
+
+    /// The address of the `syscall` instruction.
+    pub caller_rip: u64,
+
+    /// The stack pointer at the `syscall` instruction.
+    pub caller_rsp: u64,
+
+    /// The guest CR3.
+    pub cr3: u64,
+
+    /// The address of the `EPROCESS` of the calling process, or 0 if it couldn't be read.
+    pub process: u64,
+
+    /// The address of the `KTHREAD` of the calling thread, or 0 if it couldn't be read.
+    pub thread: u64,
+}
+
+/// The ring buffer holding the records.
+struct TraceBuffer {
+    /// The records, allocated with the full capacity.
+    records: Vec<SyscallRecord>,
+
+    /// The index the next record is written to.
+    next: usize,
+
+    /// The number of valid records.
+    len: usize,
+
+    /// The number of records that were overwritten before they were taken.
+    dropped: u64,
+}
+
+/// Traces the syscalls of the guest by clearing `EFER.SCE`.
+pub struct SyscallTracer {
+    /// The value of `IA32_EFER` as seen by the guest.
+    guest_efer: AtomicU64,
+
+    /// The offsets of the `KPCR` and `KTHREAD` fields of the running build.
+    offsets: KernelOffsets,
+
+    /// The `EPROCESS` addresses of the traced processes. All processes are traced if it's empty.
+    process_filter: Mutex<Vec<u64>>,
+
+    /// The recorded syscalls.
+    buffer: Mutex<TraceBuffer>,
+}
+
+impl SyscallTracer {
+    /// Creates a new `SyscallTracer` instance, saving the current `IA32_EFER` as the guest view and using the
+    /// offsets of the running build.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, HypervisorError>` - The tracer, or `UnsupportedWindowsBuild` if the running build isn't supported.
+    #[cfg(windows)]
+    pub fn new() -> Result<Self, crate::error::HypervisorError> {
+        let build = crate::utils::windows_build::current()?;
+
+        Ok(Self::with_guest_efer(
+            unsafe { x86::msr::rdmsr(x86::msr::IA32_EFER) },
+            build.offsets,
+        ))
+    }
+
+    /// Creates a new `SyscallTracer` instance with the given guest view of `IA32_EFER`.
+    ///
+    /// # Arguments
+    ///
+    /// * `guest_efer` - The value of `IA32_EFER` as seen by the guest.
+    /// * `offsets` - The offsets of the kernel structures of the traced build.
+    pub fn with_guest_efer(guest_efer: u64, offsets: KernelOffsets) -> Self {
+        log::debug!("Syscall tracer guest EFER: {:#x}", guest_efer);
+
+        Self {
+            guest_efer: AtomicU64::new(guest_efer),
+            offsets,
+            process_filter: Mutex::new(Vec::new()),
+            buffer: Mutex::new(TraceBuffer {
+                records: vec![SyscallRecord::default(); SYSCALL_TRACE_CAPACITY],
+                next: 0,
+                len: 0,
+                dropped: 0,
+            }),
+        }
+    }
+
+    /// Returns the value of `IA32_EFER` as seen by the guest.
+    pub fn guest_efer(&self) -> u64 {
+        self.guest_efer.load(Ordering::Relaxed)
+    }
+
+    /// Sets the value of `IA32_EFER` as seen by the guest, when the guest writes `IA32_EFER`.
+    pub fn set_guest_efer(&self, value: u64) {
+        self.guest_efer.store(value, Ordering::Relaxed);
+    }
+
+    /// Returns the value of `IA32_EFER` the guest is executed with, which has `EFER.SCE` cleared.
+    pub fn effective_efer(&self) -> u64 {
+        self.guest_efer() & !EFER_SCE
+    }
+
+    /// Loads the current guest view of `IA32_EFER` into the VMCS of the current processor, if it was
+    /// changed on another processor.
+    ///
+    /// # Arguments
+    ///
+    /// * `vmcs` - The VMCS of the guest.
+    pub fn sync_efer(&self, vmcs: &mut dyn VmcsAccess) {
+        let efer = self.effective_efer();
+
+        if vmcs.get(guest::IA32_EFER_FULL) != efer {
+            vmcs.set(guest::IA32_EFER_FULL, efer);
+        }
+    }
+
+    /// Checks whether the guest has enabled `syscall` and `sysret` in its view of `IA32_EFER`.
+    pub fn is_syscall_enabled(&self) -> bool {
+        self.guest_efer() & EFER_SCE != 0
+    }
+
+    /// Adds a process to the traced processes. Once a process is added, only the added processes are traced.
+    ///
+    /// # Arguments
+    ///
+    /// * `process` - The address of the `EPROCESS` of the process, for example from `PsLookupProcessByProcessId`.
+    ///   The process must stay referenced while it's traced, so the address isn't reused.
+    pub fn trace_process(&self, process: u64) {
+        let mut process_filter = self.process_filter.lock();
+
+        if !process_filter.contains(&process) {
+            process_filter.push(process);
+        }
+    }
+
+    /// Removes a process from the traced processes.
+    ///
+    /// # Arguments
+    ///
+    /// * `process` - The address of the `EPROCESS` of the process.
+    pub fn untrace_process(&self, process: u64) {
+        self.process_filter
+            .lock()
+            .retain(|traced| *traced != process);
+    }
+
+    /// Checks whether the syscalls of a process are traced.
+    pub fn is_traced(&self, process: u64) -> bool {
+        let process_filter = self.process_filter.lock();
+
+        process_filter.is_empty() || process_filter.contains(&process)
+    }
+
+    /// Reads the current thread and process of the processor from the `KPCR`.
+    ///
+    /// # Arguments
+    ///
+    /// * `guest_memory` - The accessor for the guest memory.
+    /// * `vmcs` - The VMCS of the guest.
+    /// * `cpu` - The processor, whose `IA32_KERNEL_GS_BASE` holds the `KPCR` while the guest is in user mode.
+    ///
+    /// # Returns
+    ///
+    /// * `(u64, u64)` - The addresses of the `KTHREAD` and the `EPROCESS`, or 0 if they couldn't be read.
+    fn current_thread(
+        &self,
+        guest_memory: &GuestMemory,
+        vmcs: &dyn VmcsAccess,
+        cpu: &dyn CpuAccess,
+    ) -> (u64, u64) {
+        // The GS base is swapped with IA32_KERNEL_GS_BASE on kernel entry, so it's the KPCR in kernel mode.
+        let kpcr = if guest_memory.is_user_mode() {
+            cpu.rdmsr(IA32_KERNEL_GSBASE)
+        } else {
+            vmcs.get(guest::GS_BASE)
+        };
+
+        // The kernel structures are only accessible from supervisor mode.
+        let kernel_memory = guest_memory.with_user_mode(false);
+
+        let Ok(thread) = kernel_memory.read::<u64>(kpcr + self.offsets.kpcr_current_thread) else {
+            return (0, 0);
+        };
+        let process = kernel_memory
+            .read::<u64>(thread + self.offsets.kthread_process)
+            .unwrap_or_default();
+
+        (thread, process)
+    }
+
+    /// Records a syscall at the `syscall` instruction, if the calling process is traced.
+    ///
+    /// # Arguments
+    ///
+    /// * `guest_registers` - The guest registers at the `syscall` instruction.
+    /// * `guest_memory` - The accessor for the guest memory.
+    /// * `vmcs` - The VMCS of the guest.
+    /// * `cpu` - The processor executing the syscall.
+    pub fn record(
+        &self,
+        guest_registers: &GuestRegisters,
+        guest_memory: &GuestMemory,
+        vmcs: &dyn VmcsAccess,
+        cpu: &dyn CpuAccess,
+    ) {
+        let (thread, process) = self.current_thread(guest_memory, vmcs, cpu);
+
+        let record = SyscallRecord {
+            number: guest_registers.rax as u32,
+            arguments: [
+                guest_registers.r10,
+                guest_registers.rdx,
+                guest_registers.r8,
+                guest_registers.r9,
+            ],
+            caller_rip: guest_registers.rip,
+            caller_rsp: guest_registers.rsp,
+            cr3: guest_memory.cr3(),
+            process,
+            thread,
+        };
+
+        if !self.is_traced(record.process) {
+            return;
+        }
+
+        log::trace!(
+            "Syscall {:#x} from {:#x} (process {:#x}, thread {:#x})",
+            record.number,
+            record.caller_rip,
+            record.process,
+            record.thread
+        );
+
+        let mut buffer = self.buffer.lock();
+        let index = buffer.next;
+
+        buffer.records[index] = record;
+        buffer.next = (index + 1) % SYSCALL_TRACE_CAPACITY;
+
+        if buffer.len == SYSCALL_TRACE_CAPACITY {
+            buffer.dropped += 1;
+        } else {
+            buffer.len += 1;
+        }
+    }
+
+    /// Takes the recorded syscalls, oldest first.
+    ///
+    /// Must not be called in VMX root operation, as it allocates.
+    pub fn take_records(&self) -> Vec<SyscallRecord> {
+        let mut buffer = self.buffer.lock();
+
+        let start = (buffer.next + SYSCALL_TRACE_CAPACITY - buffer.len) % SYSCALL_TRACE_CAPACITY;
+        let records = (0..buffer.len)
+            .map(|offset| buffer.records[(start + offset) % SYSCALL_TRACE_CAPACITY])
+            .collect();
+
+        buffer.len = 0;
+
+        records
+    }
+
+    /// Returns the number of records that were overwritten before they were taken.
+    pub fn dropped(&self) -> u64 {
+        self.buffer.lock().dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::intel::{
+            cpu_access::SoftCpu,
+            guest_memory::tests::{address, PageTables, VA},
+            vmcs_access::SoftVmcs,
+        },
+        crate::utils::windows_build::lookup,
+    };
+
+    /// The flags of supervisor pages that are present and writable.
+    const SUPERVISOR_PAGE: u64 = 0x3;
+
+    /// The address of the `EPROCESS` of the calling process.
+    const PROCESS: u64 = 0xFFFF_A000_1234_5080;
+
+    /// Returns the offsets of Windows 11 23H2.
+    fn offsets() -> KernelOffsets {
+        lookup(22631).unwrap().offsets
+    }
+
+    /// Maps a `KPCR` at `VA`, whose current thread is at the next page and belongs to `PROCESS`.
+    fn kernel_structures() -> PageTables {
+        let mut tables = PageTables::new(SUPERVISOR_PAGE);
+        tables.data[0].0[offsets().kpcr_current_thread as usize / 8] = VA + 0x1000;
+        tables.data[1].0[offsets().kthread_process as usize / 8] = PROCESS;
+        tables
+    }
+
+    /// Records a syscall made from user mode with the `KPCR` at `VA`.
+    fn record(syscall_tracer: &SyscallTracer, tables: &PageTables) {
+        let vmcs = SoftVmcs::new()
+            .with(guest::CR3, address(&tables.pml4))
+            .with(guest::CS_SELECTOR, 0x33)
+            .with(guest::GS_BASE, 0x0000_00A0_0000_0000);
+        let cpu = SoftCpu::new().with_msr(IA32_KERNEL_GSBASE, VA);
+        let guest_registers = GuestRegisters {
+            rax: 0x55,
+            ..Default::default()
+        };
+
+        syscall_tracer.record(&guest_registers, &GuestMemory::current(&vmcs), &vmcs, &cpu);
+    }
+
+    #[test]
+    fn records_identify_the_process_from_the_kpcr() {
+        let tables = kernel_structures();
+        let syscall_tracer = SyscallTracer::with_guest_efer(EFER_SCE, offsets());
+
+        record(&syscall_tracer, &tables);
+
+        let records = syscall_tracer.take_records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].number, 0x55);
+        assert_eq!(records[0].thread, VA + 0x1000);
+        assert_eq!(records[0].process, PROCESS);
+    }
+
+    #[test]
+    fn only_the_traced_processes_are_recorded() {
+        let tables = kernel_structures();
+        let syscall_tracer = SyscallTracer::with_guest_efer(EFER_SCE, offsets());
+
+        syscall_tracer.trace_process(PROCESS + 0x1000);
+        record(&syscall_tracer, &tables);
+        assert!(syscall_tracer.take_records().is_empty());
+
+        syscall_tracer.trace_process(PROCESS);
+        record(&syscall_tracer, &tables);
+        assert_eq!(syscall_tracer.take_records().len(), 1);
+    }
+
+    #[test]
+    fn sync_efer_loads_the_shared_view() {
+        let syscall_tracer = SyscallTracer::with_guest_efer(0xD01, offsets());
+        let mut vmcs = SoftVmcs::new().with(guest::IA32_EFER_FULL, 0xD00);
+
+        syscall_tracer.set_guest_efer(0x901);
+        syscall_tracer.sync_efer(&mut vmcs);
+
+        assert_eq!(vmcs.get(guest::IA32_EFER_FULL), 0x900);
+    }
+}
@@ -86,7 +86,8 @@ impl Vcpu {
 
             log::info!("Virtualization complete for processor {}", self.index);
 
-            vmx.run(self.index);
+            // Only returns if the VMCS fails the VM-entry checks, in which case the VM isn't launched.
+            vmx.run(self.index)?;
 
             // We should never reach this point as the VM should have been launched.
         }
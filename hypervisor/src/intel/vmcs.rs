@@ -16,14 +16,17 @@ use {
             paging::PageTables,
             segmentation::SegmentDescriptor,
             shared_data::SharedData,
-            support::{vmclear, vmptrld, vmread, vmwrite},
+            support::{vmclear, vmptrld},
+            vmcs_access::{HardwareVmcs, VmcsAccess, VmcsFieldAccess},
+            vmcs_fields::{control, guest, host},
             vmerror::ExceptionInterrupt,
+            vmexit::control_register::{cr0_guest_host_mask, cr4_guest_host_mask, cr4_read_shadow},
         },
         utils::capture::GuestRegisters,
         utils::{
             instructions::cr3,
             addresses::PhysicalAddress,
-            alloc::{KernelAlloc, PhysicalAllocator},
+            alloc::PhysicalAllocator,
             capture::CONTEXT,
         },
     },
@@ -39,7 +42,7 @@ use {
         msr::{self},
         segmentation::SegmentSelector,
         task,
-        vmx::vmcs::{self},
+        vmx::vmcs::control::{EntryControls, ExitControls, PrimaryControls, SecondaryControls},
     },
     x86_64::registers::control::{Cr0, Cr4},
 };
@@ -104,71 +107,72 @@ impl Vmcs {
     /// Intel® 64 and IA-32 Architectures Software Developer's Manual 25.4 GUEST-STATE AREA.
     ///
     /// # Arguments
+    /// * `vmcs` - The VMCS to initialize, `HardwareVmcs` for the currently loaded VMCS.
     /// * `context` - Context containing the guest's register states.
     /// * `guest_descriptor_table` - Descriptor tables for the guest.
     /// * `guest_registers` - Guest registers for the guest.
     #[rustfmt::skip]
-    pub fn setup_guest_registers_state(context: &CONTEXT, guest_descriptor_table: &Box<DescriptorTables, KernelAlloc>, guest_registers: &mut GuestRegisters) {
+    pub fn setup_guest_registers_state(vmcs: &mut dyn VmcsAccess, context: &CONTEXT, guest_descriptor_table: &DescriptorTables, guest_registers: &mut GuestRegisters) -> Result<(), HypervisorError> {
         log::debug!("Setting up Guest Registers State");
 
-        vmwrite(vmcs::guest::CR0, Cr0::read_raw());
-        vmwrite(vmcs::guest::CR3, cr3());
-        vmwrite(vmcs::guest::CR4, Cr4::read_raw());
-
-        vmwrite(vmcs::guest::DR7, context.Dr7);
-
-        vmwrite(vmcs::guest::RSP, context.Rsp);
-        vmwrite(vmcs::guest::RIP, context.Rip);
-        vmwrite(vmcs::guest::RFLAGS, context.EFlags);
-
-        vmwrite(vmcs::guest::CS_SELECTOR, context.SegCs);
-        vmwrite(vmcs::guest::SS_SELECTOR, context.SegSs);
-        vmwrite(vmcs::guest::DS_SELECTOR, context.SegDs);
-        vmwrite(vmcs::guest::ES_SELECTOR, context.SegEs);
-        vmwrite(vmcs::guest::FS_SELECTOR, context.SegFs);
-        vmwrite(vmcs::guest::GS_SELECTOR, context.SegGs);
-        unsafe { vmwrite(vmcs::guest::LDTR_SELECTOR, dtables::ldtr().bits() as u64) };
-        unsafe { vmwrite(vmcs::guest::TR_SELECTOR, task::tr().bits() as u64) };
-
-        vmwrite(vmcs::guest::CS_BASE, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegCs), &guest_descriptor_table.gdtr).base_address);
-        vmwrite(vmcs::guest::SS_BASE, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegSs), &guest_descriptor_table.gdtr).base_address);
-        vmwrite(vmcs::guest::DS_BASE, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegDs), &guest_descriptor_table.gdtr).base_address);
-        vmwrite(vmcs::guest::ES_BASE, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegEs), &guest_descriptor_table.gdtr).base_address);
-        unsafe { vmwrite(vmcs::guest::FS_BASE, msr::rdmsr(msr::IA32_FS_BASE)) };
-        unsafe { vmwrite(vmcs::guest::GS_BASE, msr::rdmsr(msr::IA32_GS_BASE)) };
-        unsafe { vmwrite(vmcs::guest::LDTR_BASE, SegmentDescriptor::from_selector(SegmentSelector::from_raw(dtables::ldtr().bits()), &guest_descriptor_table.gdtr).base_address) };
-        unsafe { vmwrite(vmcs::guest::TR_BASE, SegmentDescriptor::from_selector(SegmentSelector::from_raw(task::tr().bits()), &guest_descriptor_table.gdtr).base_address) };
-
-        vmwrite(vmcs::guest::CS_LIMIT, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegCs), &guest_descriptor_table.gdtr).segment_limit);
-        vmwrite(vmcs::guest::SS_LIMIT, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegSs), &guest_descriptor_table.gdtr).segment_limit);
-        vmwrite(vmcs::guest::DS_LIMIT, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegDs), &guest_descriptor_table.gdtr).segment_limit);
-        vmwrite(vmcs::guest::ES_LIMIT, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegEs), &guest_descriptor_table.gdtr).segment_limit);
-        vmwrite(vmcs::guest::FS_LIMIT, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegFs), &guest_descriptor_table.gdtr).segment_limit);
-        vmwrite(vmcs::guest::GS_LIMIT, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegGs), &guest_descriptor_table.gdtr).segment_limit);
-        unsafe { vmwrite(vmcs::guest::LDTR_LIMIT, SegmentDescriptor::from_selector(SegmentSelector::from_raw(dtables::ldtr().bits()), &guest_descriptor_table.gdtr).segment_limit) };
-        unsafe { vmwrite(vmcs::guest::TR_LIMIT, SegmentDescriptor::from_selector(SegmentSelector::from_raw(task::tr().bits()), &guest_descriptor_table.gdtr).segment_limit) };
-
-        vmwrite(vmcs::guest::CS_ACCESS_RIGHTS, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegCs), &guest_descriptor_table.gdtr).access_rights.bits());
-        vmwrite(vmcs::guest::SS_ACCESS_RIGHTS, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegSs), &guest_descriptor_table.gdtr).access_rights.bits());
-        vmwrite(vmcs::guest::DS_ACCESS_RIGHTS, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegDs), &guest_descriptor_table.gdtr).access_rights.bits());
-        vmwrite(vmcs::guest::ES_ACCESS_RIGHTS, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegEs), &guest_descriptor_table.gdtr).access_rights.bits());
-        vmwrite(vmcs::guest::FS_ACCESS_RIGHTS, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegFs), &guest_descriptor_table.gdtr).access_rights.bits());
-        vmwrite(vmcs::guest::GS_ACCESS_RIGHTS, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegGs), &guest_descriptor_table.gdtr).access_rights.bits());
-        unsafe { vmwrite(vmcs::guest::LDTR_ACCESS_RIGHTS, SegmentDescriptor::from_selector(SegmentSelector::from_raw(dtables::ldtr().bits()), &guest_descriptor_table.gdtr).access_rights.bits()) };
-        unsafe { vmwrite(vmcs::guest::TR_ACCESS_RIGHTS, SegmentDescriptor::from_selector(SegmentSelector::from_raw(task::tr().bits()), &guest_descriptor_table.gdtr).access_rights.bits()) };
-
-        vmwrite(vmcs::guest::GDTR_BASE, guest_descriptor_table.gdtr.base as u64);
-        vmwrite(vmcs::guest::IDTR_BASE, guest_descriptor_table.idtr.base as u64);
-
-        vmwrite(vmcs::guest::GDTR_LIMIT, guest_descriptor_table.gdtr.limit as u64);
-        vmwrite(vmcs::guest::IDTR_LIMIT, guest_descriptor_table.idtr.limit as u64);
+        vmcs.try_set(guest::CR0, Cr0::read_raw())?;
+        vmcs.try_set(guest::CR3, cr3())?;
+        vmcs.try_set(guest::CR4, Cr4::read_raw())?;
+
+        vmcs.try_set(guest::DR7, context.Dr7)?;
+
+        vmcs.try_set(guest::RSP, context.Rsp)?;
+        vmcs.try_set(guest::RIP, context.Rip)?;
+        vmcs.try_set(guest::RFLAGS, context.EFlags.into())?;
+
+        vmcs.try_set(guest::CS_SELECTOR, context.SegCs)?;
+        vmcs.try_set(guest::SS_SELECTOR, context.SegSs)?;
+        vmcs.try_set(guest::DS_SELECTOR, context.SegDs)?;
+        vmcs.try_set(guest::ES_SELECTOR, context.SegEs)?;
+        vmcs.try_set(guest::FS_SELECTOR, context.SegFs)?;
+        vmcs.try_set(guest::GS_SELECTOR, context.SegGs)?;
+        unsafe { vmcs.try_set(guest::LDTR_SELECTOR, dtables::ldtr().bits()) }?;
+        unsafe { vmcs.try_set(guest::TR_SELECTOR, task::tr().bits()) }?;
+
+        vmcs.try_set(guest::CS_BASE, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegCs), &guest_descriptor_table.gdtr).base_address)?;
+        vmcs.try_set(guest::SS_BASE, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegSs), &guest_descriptor_table.gdtr).base_address)?;
+        vmcs.try_set(guest::DS_BASE, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegDs), &guest_descriptor_table.gdtr).base_address)?;
+        vmcs.try_set(guest::ES_BASE, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegEs), &guest_descriptor_table.gdtr).base_address)?;
+        unsafe { vmcs.try_set(guest::FS_BASE, msr::rdmsr(msr::IA32_FS_BASE)) }?;
+        unsafe { vmcs.try_set(guest::GS_BASE, msr::rdmsr(msr::IA32_GS_BASE)) }?;
+        unsafe { vmcs.try_set(guest::LDTR_BASE, SegmentDescriptor::from_selector(SegmentSelector::from_raw(dtables::ldtr().bits()), &guest_descriptor_table.gdtr).base_address) }?;
+        unsafe { vmcs.try_set(guest::TR_BASE, SegmentDescriptor::from_selector(SegmentSelector::from_raw(task::tr().bits()), &guest_descriptor_table.gdtr).base_address) }?;
+
+        vmcs.try_set(guest::CS_LIMIT, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegCs), &guest_descriptor_table.gdtr).segment_limit)?;
+        vmcs.try_set(guest::SS_LIMIT, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegSs), &guest_descriptor_table.gdtr).segment_limit)?;
+        vmcs.try_set(guest::DS_LIMIT, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegDs), &guest_descriptor_table.gdtr).segment_limit)?;
+        vmcs.try_set(guest::ES_LIMIT, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegEs), &guest_descriptor_table.gdtr).segment_limit)?;
+        vmcs.try_set(guest::FS_LIMIT, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegFs), &guest_descriptor_table.gdtr).segment_limit)?;
+        vmcs.try_set(guest::GS_LIMIT, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegGs), &guest_descriptor_table.gdtr).segment_limit)?;
+        unsafe { vmcs.try_set(guest::LDTR_LIMIT, SegmentDescriptor::from_selector(SegmentSelector::from_raw(dtables::ldtr().bits()), &guest_descriptor_table.gdtr).segment_limit) }?;
+        unsafe { vmcs.try_set(guest::TR_LIMIT, SegmentDescriptor::from_selector(SegmentSelector::from_raw(task::tr().bits()), &guest_descriptor_table.gdtr).segment_limit) }?;
+
+        vmcs.try_set(guest::CS_ACCESS_RIGHTS, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegCs), &guest_descriptor_table.gdtr).access_rights.bits())?;
+        vmcs.try_set(guest::SS_ACCESS_RIGHTS, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegSs), &guest_descriptor_table.gdtr).access_rights.bits())?;
+        vmcs.try_set(guest::DS_ACCESS_RIGHTS, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegDs), &guest_descriptor_table.gdtr).access_rights.bits())?;
+        vmcs.try_set(guest::ES_ACCESS_RIGHTS, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegEs), &guest_descriptor_table.gdtr).access_rights.bits())?;
+        vmcs.try_set(guest::FS_ACCESS_RIGHTS, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegFs), &guest_descriptor_table.gdtr).access_rights.bits())?;
+        vmcs.try_set(guest::GS_ACCESS_RIGHTS, SegmentDescriptor::from_selector(SegmentSelector::from_raw(context.SegGs), &guest_descriptor_table.gdtr).access_rights.bits())?;
+        unsafe { vmcs.try_set(guest::LDTR_ACCESS_RIGHTS, SegmentDescriptor::from_selector(SegmentSelector::from_raw(dtables::ldtr().bits()), &guest_descriptor_table.gdtr).access_rights.bits()) }?;
+        unsafe { vmcs.try_set(guest::TR_ACCESS_RIGHTS, SegmentDescriptor::from_selector(SegmentSelector::from_raw(task::tr().bits()), &guest_descriptor_table.gdtr).access_rights.bits()) }?;
+
+        vmcs.try_set(guest::GDTR_BASE, guest_descriptor_table.gdtr.base as u64)?;
+        vmcs.try_set(guest::IDTR_BASE, guest_descriptor_table.idtr.base as u64)?;
+
+        vmcs.try_set(guest::GDTR_LIMIT, guest_descriptor_table.gdtr.limit.into())?;
+        vmcs.try_set(guest::IDTR_LIMIT, guest_descriptor_table.idtr.limit.into())?;
 
         unsafe {
-            vmwrite(vmcs::guest::IA32_DEBUGCTL_FULL, msr::rdmsr(msr::IA32_DEBUGCTL));
-            vmwrite(vmcs::guest::IA32_SYSENTER_CS, msr::rdmsr(msr::IA32_SYSENTER_CS));
-            vmwrite(vmcs::guest::IA32_SYSENTER_ESP, msr::rdmsr(msr::IA32_SYSENTER_ESP));
-            vmwrite(vmcs::guest::IA32_SYSENTER_EIP, msr::rdmsr(msr::IA32_SYSENTER_EIP));
-            vmwrite(vmcs::guest::LINK_PTR_FULL, u64::MAX);
+            vmcs.try_set(guest::IA32_DEBUGCTL_FULL, msr::rdmsr(msr::IA32_DEBUGCTL))?;
+            vmcs.try_set(guest::IA32_SYSENTER_CS, msr::rdmsr(msr::IA32_SYSENTER_CS) as u32)?;
+            vmcs.try_set(guest::IA32_SYSENTER_ESP, msr::rdmsr(msr::IA32_SYSENTER_ESP))?;
+            vmcs.try_set(guest::IA32_SYSENTER_EIP, msr::rdmsr(msr::IA32_SYSENTER_EIP))?;
+            vmcs.try_set(guest::LINK_PTR_FULL, u64::MAX)?;
         }
 
         let xmm_context = unsafe { context.Anonymous.Anonymous };
@@ -209,6 +213,8 @@ impl Vmcs {
         guest_registers.r15 = context.R15;
 
         log::debug!("Guest Registers State setup successfully!");
+
+        Ok(())
     }
 
     /// Initialize the host state for the currently loaded VMCS.
@@ -217,38 +223,40 @@ impl Vmcs {
     /// Intel® 64 and IA-32 Architectures Software Developer's Manual 25.5 HOST-STATE AREA.
     ///
     /// # Arguments
+    /// * `vmcs` - The VMCS to initialize, `HardwareVmcs` for the currently loaded VMCS.
     /// * `context` - Context containing the host's register states.
     /// * `host_descriptor_table` - Descriptor tables for the host.
+    /// * `host_paging` - Page tables for the host.
     #[rustfmt::skip]
-    pub fn setup_host_registers_state(context: &CONTEXT, host_descriptor_table: &Box<DescriptorTables, KernelAlloc>, host_paging: &Box<PageTables, PhysicalAllocator>) -> Result<(), HypervisorError> {
+    pub fn setup_host_registers_state(vmcs: &mut dyn VmcsAccess, context: &CONTEXT, host_descriptor_table: &DescriptorTables, host_paging: &PageTables) -> Result<(), HypervisorError> {
         log::debug!("Setting up Host Registers State");
 
-        unsafe { vmwrite(vmcs::host::CR0, controlregs::cr0().bits() as u64) };
-        vmwrite(vmcs::host::CR3, host_paging.get_pml4_pa()?);
-        vmwrite(vmcs::host::CR4, Cr4::read_raw());
+        unsafe { vmcs.try_set(host::CR0, controlregs::cr0().bits() as u64) }?;
+        vmcs.try_set(host::CR3, host_paging.get_pml4_pa()?)?;
+        vmcs.try_set(host::CR4, Cr4::read_raw())?;
 
         // The RIP/RSP registers are set within `launch_vm`.
 
         const SELECTOR_MASK: u16 = 0xF8;
-        vmwrite(vmcs::host::CS_SELECTOR, context.SegCs & SELECTOR_MASK);
-        vmwrite(vmcs::host::SS_SELECTOR, context.SegSs & SELECTOR_MASK);
-        vmwrite(vmcs::host::DS_SELECTOR, context.SegDs & SELECTOR_MASK);
-        vmwrite(vmcs::host::ES_SELECTOR, context.SegEs & SELECTOR_MASK);
-        vmwrite(vmcs::host::FS_SELECTOR, context.SegFs & SELECTOR_MASK);
-        vmwrite(vmcs::host::GS_SELECTOR, context.SegGs & SELECTOR_MASK);
-        unsafe { vmwrite(vmcs::host::TR_SELECTOR, task::tr().bits() & SELECTOR_MASK) };
+        vmcs.try_set(host::CS_SELECTOR, context.SegCs & SELECTOR_MASK)?;
+        vmcs.try_set(host::SS_SELECTOR, context.SegSs & SELECTOR_MASK)?;
+        vmcs.try_set(host::DS_SELECTOR, context.SegDs & SELECTOR_MASK)?;
+        vmcs.try_set(host::ES_SELECTOR, context.SegEs & SELECTOR_MASK)?;
+        vmcs.try_set(host::FS_SELECTOR, context.SegFs & SELECTOR_MASK)?;
+        vmcs.try_set(host::GS_SELECTOR, context.SegGs & SELECTOR_MASK)?;
+        unsafe { vmcs.try_set(host::TR_SELECTOR, task::tr().bits() & SELECTOR_MASK) }?;
 
-        unsafe { vmwrite(vmcs::host::FS_BASE, msr::rdmsr(msr::IA32_FS_BASE)) };
-        unsafe { vmwrite(vmcs::host::GS_BASE, msr::rdmsr(msr::IA32_GS_BASE)) };
-        unsafe { vmwrite(vmcs::host::TR_BASE, SegmentDescriptor::from_selector(SegmentSelector::from_raw(task::tr().bits()), &host_descriptor_table.gdtr).base_address) };
+        unsafe { vmcs.try_set(host::FS_BASE, msr::rdmsr(msr::IA32_FS_BASE)) }?;
+        unsafe { vmcs.try_set(host::GS_BASE, msr::rdmsr(msr::IA32_GS_BASE)) }?;
+        unsafe { vmcs.try_set(host::TR_BASE, SegmentDescriptor::from_selector(SegmentSelector::from_raw(task::tr().bits()), &host_descriptor_table.gdtr).base_address) }?;
 
-        vmwrite(vmcs::host::GDTR_BASE, host_descriptor_table.gdtr.base as u64);
-        vmwrite(vmcs::host::IDTR_BASE, host_descriptor_table.idtr.base as u64);
+        vmcs.try_set(host::GDTR_BASE, host_descriptor_table.gdtr.base as u64)?;
+        vmcs.try_set(host::IDTR_BASE, host_descriptor_table.idtr.base as u64)?;
 
         unsafe {
-            vmwrite(vmcs::host::IA32_SYSENTER_CS, msr::rdmsr(msr::IA32_SYSENTER_CS));
-            vmwrite(vmcs::host::IA32_SYSENTER_ESP, msr::rdmsr(msr::IA32_SYSENTER_ESP));
-            vmwrite(vmcs::host::IA32_SYSENTER_EIP, msr::rdmsr(msr::IA32_SYSENTER_EIP));
+            vmcs.try_set(host::IA32_SYSENTER_CS, msr::rdmsr(msr::IA32_SYSENTER_CS) as u32)?;
+            vmcs.try_set(host::IA32_SYSENTER_ESP, msr::rdmsr(msr::IA32_SYSENTER_ESP))?;
+            vmcs.try_set(host::IA32_SYSENTER_EIP, msr::rdmsr(msr::IA32_SYSENTER_EIP))?;
         }
 
         log::debug!("Host Registers State setup successfully!");
@@ -265,37 +273,89 @@ impl Vmcs {
     /// - 25.8 VM-ENTRY CONTROL FIELDS
     ///
     /// # Arguments
+    /// * `vmcs` - The VMCS to initialize, `HardwareVmcs` for the currently loaded VMCS.
     /// * `shared_data` - Shared data between processors.
     #[rustfmt::skip]
-    pub fn setup_vmcs_control_fields(shared_data: &mut SharedData) -> Result<(), HypervisorError> {
+    pub fn setup_vmcs_control_fields(vmcs: &mut dyn VmcsAccess, shared_data: &mut SharedData) -> Result<(), HypervisorError> {
         log::debug!("Setting up VMCS Control Fields");
 
-        const PRIMARY_CTL: u64 = (vmcs::control::PrimaryControls::SECONDARY_CONTROLS.bits() | vmcs::control::PrimaryControls::USE_MSR_BITMAPS.bits()) as u64;
-        const SECONDARY_CTL: u64 = (vmcs::control::SecondaryControls::ENABLE_RDTSCP.bits()
-            | vmcs::control::SecondaryControls::ENABLE_XSAVES_XRSTORS.bits()
-            | vmcs::control::SecondaryControls::ENABLE_INVPCID.bits()
-            | vmcs::control::SecondaryControls::ENABLE_VPID.bits()
-            | vmcs::control::SecondaryControls::ENABLE_EPT.bits()) as u64;
-        const ENTRY_CTL: u64 = vmcs::control::EntryControls::IA32E_MODE_GUEST.bits() as u64;
-        const EXIT_CTL: u64 = vmcs::control::ExitControls::HOST_ADDRESS_SPACE_SIZE.bits() as u64;
-        const PINBASED_CTL: u64 = 0;
-
-        vmwrite(vmcs::control::PRIMARY_PROCBASED_EXEC_CONTROLS, adjust_vmx_controls(VmxControl::ProcessorBased, PRIMARY_CTL));
-        vmwrite(vmcs::control::SECONDARY_PROCBASED_EXEC_CONTROLS, adjust_vmx_controls(VmxControl::ProcessorBased2, SECONDARY_CTL));
-        vmwrite(vmcs::control::VMENTRY_CONTROLS, adjust_vmx_controls(VmxControl::VmEntry, ENTRY_CTL));
-        vmwrite(vmcs::control::VMEXIT_CONTROLS, adjust_vmx_controls(VmxControl::VmExit, EXIT_CTL));
-        vmwrite(vmcs::control::PINBASED_EXEC_CONTROLS, adjust_vmx_controls(VmxControl::PinBased, PINBASED_CTL));
+        const PRIMARY_CTL: u32 = PrimaryControls::SECONDARY_CONTROLS.bits() | PrimaryControls::USE_MSR_BITMAPS.bits() | PrimaryControls::USE_IO_BITMAPS.bits();
+        const SECONDARY_CTL: u32 = SecondaryControls::ENABLE_RDTSCP.bits()
+            | SecondaryControls::ENABLE_XSAVES_XRSTORS.bits()
+            | SecondaryControls::ENABLE_INVPCID.bits()
+            | SecondaryControls::ENABLE_VPID.bits()
+            | SecondaryControls::ENABLE_EPT.bits();
+        const ENTRY_CTL: u32 = EntryControls::IA32E_MODE_GUEST.bits();
+        const EXIT_CTL: u32 = ExitControls::HOST_ADDRESS_SPACE_SIZE.bits();
+        const PINBASED_CTL: u32 = 0;
+
+        // The address-space monitor loads CR3 with VM exits, except for the values of the CR3-target list.
+        let primary_ctl = match shared_data.address_space_monitor.as_ref().filter(|monitor| monitor.is_enabled()) {
+            Some(address_space_monitor) => {
+                let cr3_targets = address_space_monitor.cr3_targets();
+
+                for (field, cr3) in [control::CR3_TARGET_VALUE0, control::CR3_TARGET_VALUE1, control::CR3_TARGET_VALUE2, control::CR3_TARGET_VALUE3].into_iter().zip(cr3_targets) {
+                    vmcs.try_set(field, *cr3)?;
+                }
+
+                vmcs.try_set(control::CR3_TARGET_COUNT, cr3_targets.len() as u32)?;
+
+                PRIMARY_CTL | PrimaryControls::CR3_LOAD_EXITING.bits()
+            }
+            None => PRIMARY_CTL,
+        };
+
+        vmcs.try_set(control::PRIMARY_PROCBASED_EXEC_CONTROLS, adjust_vmx_controls(VmxControl::ProcessorBased, primary_ctl))?;
+        vmcs.try_set(control::SECONDARY_PROCBASED_EXEC_CONTROLS, adjust_vmx_controls(VmxControl::ProcessorBased2, SECONDARY_CTL))?;
+
+        // The syscall tracer executes the guest with its own IA32_EFER, which has EFER.SCE cleared, while the host keeps the original one.
+        let (entry_ctl, exit_ctl) = match shared_data.syscall_tracer.as_ref() {
+            Some(syscall_tracer) => {
+                vmcs.try_set(guest::IA32_EFER_FULL, syscall_tracer.effective_efer())?;
+                vmcs.try_set(host::IA32_EFER_FULL, unsafe { msr::rdmsr(msr::IA32_EFER) })?;
+
+                (ENTRY_CTL | EntryControls::LOAD_IA32_EFER.bits(), EXIT_CTL | ExitControls::LOAD_IA32_EFER.bits())
+            }
+            None => (ENTRY_CTL, EXIT_CTL),
+        };
+
+        vmcs.try_set(control::VMENTRY_CONTROLS, adjust_vmx_controls(VmxControl::VmEntry, entry_ctl))?;
+        vmcs.try_set(control::VMEXIT_CONTROLS, adjust_vmx_controls(VmxControl::VmExit, exit_ctl))?;
+        vmcs.try_set(control::PINBASED_EXEC_CONTROLS, adjust_vmx_controls(VmxControl::PinBased, PINBASED_CTL))?;
+
+        // The hypervisor owns the bits of CR0 and CR4 fixed in VMX operation and CR4.VMXE, so the guest reads them from the read shadows and can't change them without a VM exit.
+        vmcs.try_set(control::CR0_GUEST_HOST_MASK, cr0_guest_host_mask())?;
+        vmcs.try_set(control::CR4_GUEST_HOST_MASK, cr4_guest_host_mask())?;
 
         unsafe {
-            vmwrite(vmcs::control::CR0_READ_SHADOW, controlregs::cr0().bits() as u64);
-            vmwrite(vmcs::control::CR4_READ_SHADOW, Cr4::read_raw());
+            vmcs.try_set(control::CR0_READ_SHADOW, controlregs::cr0().bits() as u64)?;
+            vmcs.try_set(control::CR4_READ_SHADOW, cr4_read_shadow(Cr4::read_raw()))?;
         };
 
-        vmwrite(vmcs::control::MSR_BITMAPS_ADDR_FULL, PhysicalAddress::pa_from_va(shared_data.msr_bitmap.as_ref() as *const _ as _));
-        vmwrite(vmcs::control::EXCEPTION_BITMAP, 1u64 << (ExceptionInterrupt::Breakpoint as u32));
+        vmcs.try_set(control::MSR_BITMAPS_ADDR_FULL, PhysicalAddress::pa_from_va(shared_data.msr_bitmap.as_ref() as *const _ as _))?;
+        vmcs.try_set(control::IO_BITMAP_A_ADDR_FULL, PhysicalAddress::pa_from_va(shared_data.io_bitmap.bitmap_a.as_ptr() as _))?;
+        vmcs.try_set(control::IO_BITMAP_B_ADDR_FULL, PhysicalAddress::pa_from_va(shared_data.io_bitmap.bitmap_b.as_ptr() as _))?;
+        let mut exception_bitmap = 1u32 << (ExceptionInterrupt::Breakpoint as u32);
+
+        // The syscall hooks intercept the instruction fetch page faults at the dispatcher (with kernel virtual address shadowing) and the single steps after the return.
+        if shared_data.syscall_hooks.is_some() {
+            const PFEC_PRESENT_USER_FETCH: u32 = 0x1 | 0x4 | 0x10;
+            const PFEC_NOT_PRESENT_KERNEL_FETCH: u32 = 0x10;
+
+            exception_bitmap |= (1u32 << (ExceptionInterrupt::PageFault as u32)) | (1u32 << (ExceptionInterrupt::Debug as u32));
+            vmcs.try_set(control::PAGE_FAULT_ERR_CODE_MASK, PFEC_PRESENT_USER_FETCH)?;
+            vmcs.try_set(control::PAGE_FAULT_ERR_CODE_MATCH, PFEC_NOT_PRESENT_KERNEL_FETCH)?;
+        }
+
+        // The syscall tracer intercepts the invalid opcode exceptions raised by syscall and sysret.
+        if shared_data.syscall_tracer.is_some() {
+            exception_bitmap |= 1u32 << (ExceptionInterrupt::InvalidOpcode as u32);
+        }
+
+        vmcs.try_set(control::EXCEPTION_BITMAP, exception_bitmap)?;
 
-        vmwrite(vmcs::control::EPTP_FULL, shared_data.primary_eptp);
-        vmwrite(vmcs::control::VPID, VPID_TAG);
+        vmcs.try_set(control::EPTP_FULL, shared_data.primary_eptp)?;
+        vmcs.try_set(control::VPID, VPID_TAG)?;
 
         invept_single_context(shared_data.primary_eptp);
         invvpid_single_context(VPID_TAG);
@@ -328,93 +388,97 @@ impl fmt::Debug for Vmcs {
             .field("Revision ID: ", &self.revision_id)
 
             /* VMCS Guest state fields */
-            .field("Guest CR0: ", &vmread(vmcs::guest::CR0))
-            .field("Guest CR3: ", &vmread(vmcs::guest::CR3))
-            .field("Guest CR4: ", &vmread(vmcs::guest::CR4))
-            .field("Guest DR7: ", &vmread(vmcs::guest::DR7))
-            .field("Guest RSP: ", &vmread(vmcs::guest::RSP))
-            .field("Guest RIP: ", &vmread(vmcs::guest::RIP))
-            .field("Guest RFLAGS: ", &vmread(vmcs::guest::RFLAGS))
-
-            .field("Guest CS Selector: ", &vmread(vmcs::guest::CS_SELECTOR))
-            .field("Guest SS Selector: ", &vmread(vmcs::guest::SS_SELECTOR))
-            .field("Guest DS Selector: ", &vmread(vmcs::guest::DS_SELECTOR))
-            .field("Guest ES Selector: ", &vmread(vmcs::guest::ES_SELECTOR))
-            .field("Guest FS Selector: ", &vmread(vmcs::guest::FS_SELECTOR))
-            .field("Guest GS Selector: ", &vmread(vmcs::guest::GS_SELECTOR))
-            .field("Guest LDTR Selector: ", &vmread(vmcs::guest::LDTR_SELECTOR))
-            .field("Guest TR Selector: ", &vmread(vmcs::guest::TR_SELECTOR))
-
-            .field("Guest CS Base: ", &vmread(vmcs::guest::CS_BASE))
-            .field("Guest SS Base: ", &vmread(vmcs::guest::SS_BASE))
-            .field("Guest DS Base: ", &vmread(vmcs::guest::DS_BASE))
-            .field("Guest ES Base: ", &vmread(vmcs::guest::ES_BASE))
-            .field("Guest FS Base: ", &vmread(vmcs::guest::FS_BASE))
-            .field("Guest GS Base: ", &vmread(vmcs::guest::GS_BASE))
-            .field("Guest LDTR Base: ", &vmread(vmcs::guest::LDTR_BASE))
-            .field("Guest TR Base: ", &vmread(vmcs::guest::TR_BASE))
-
-            .field("Guest CS Limit: ", &vmread(vmcs::guest::CS_LIMIT))
-            .field("Guest SS Limit: ", &vmread(vmcs::guest::SS_LIMIT))
-            .field("Guest DS Limit: ", &vmread(vmcs::guest::DS_LIMIT))
-            .field("Guest ES Limit: ", &vmread(vmcs::guest::ES_LIMIT))
-            .field("Guest FS Limit: ", &vmread(vmcs::guest::FS_LIMIT))
-            .field("Guest GS Limit: ", &vmread(vmcs::guest::GS_LIMIT))
-            .field("Guest LDTR Limit: ", &vmread(vmcs::guest::LDTR_LIMIT))
-            .field("Guest TR Limit: ", &vmread(vmcs::guest::TR_LIMIT))
-
-            .field("Guest CS Access Rights: ", &vmread(vmcs::guest::CS_ACCESS_RIGHTS))
-            .field("Guest SS Access Rights: ", &vmread(vmcs::guest::SS_ACCESS_RIGHTS))
-            .field("Guest DS Access Rights: ", &vmread(vmcs::guest::DS_ACCESS_RIGHTS))
-            .field("Guest ES Access Rights: ", &vmread(vmcs::guest::ES_ACCESS_RIGHTS))
-            .field("Guest FS Access Rights: ", &vmread(vmcs::guest::FS_ACCESS_RIGHTS))
-            .field("Guest GS Access Rights: ", &vmread(vmcs::guest::GS_ACCESS_RIGHTS))
-            .field("Guest LDTR Access Rights: ", &vmread(vmcs::guest::LDTR_ACCESS_RIGHTS))
-            .field("Guest TR Access Rights: ", &vmread(vmcs::guest::TR_ACCESS_RIGHTS))
-
-            .field("Guest GDTR Base: ", &vmread(vmcs::guest::GDTR_BASE))
-            .field("Guest IDTR Base: ", &vmread(vmcs::guest::IDTR_BASE))
-            .field("Guest GDTR Limit: ", &vmread(vmcs::guest::GDTR_LIMIT))
-            .field("Guest IDTR Limit: ", &vmread(vmcs::guest::IDTR_LIMIT))
-
-            .field("Guest IA32_DEBUGCTL_FULL: ", &vmread(vmcs::guest::IA32_DEBUGCTL_FULL))
-            .field("Guest IA32_SYSENTER_CS: ", &vmread(vmcs::guest::IA32_SYSENTER_CS))
-            .field("Guest IA32_SYSENTER_ESP: ", &vmread(vmcs::guest::IA32_SYSENTER_ESP))
-            .field("Guest IA32_SYSENTER_EIP: ", &vmread(vmcs::guest::IA32_SYSENTER_EIP))
-            .field("Guest VMCS Link Pointer: ", &vmread(vmcs::guest::LINK_PTR_FULL))
+            .field("Guest CR0: ", &HardwareVmcs.try_get(guest::CR0))
+            .field("Guest CR3: ", &HardwareVmcs.try_get(guest::CR3))
+            .field("Guest CR4: ", &HardwareVmcs.try_get(guest::CR4))
+            .field("Guest DR7: ", &HardwareVmcs.try_get(guest::DR7))
+            .field("Guest RSP: ", &HardwareVmcs.try_get(guest::RSP))
+            .field("Guest RIP: ", &HardwareVmcs.try_get(guest::RIP))
+            .field("Guest RFLAGS: ", &HardwareVmcs.try_get(guest::RFLAGS))
+
+            .field("Guest CS Selector: ", &HardwareVmcs.try_get(guest::CS_SELECTOR))
+            .field("Guest SS Selector: ", &HardwareVmcs.try_get(guest::SS_SELECTOR))
+            .field("Guest DS Selector: ", &HardwareVmcs.try_get(guest::DS_SELECTOR))
+            .field("Guest ES Selector: ", &HardwareVmcs.try_get(guest::ES_SELECTOR))
+            .field("Guest FS Selector: ", &HardwareVmcs.try_get(guest::FS_SELECTOR))
+            .field("Guest GS Selector: ", &HardwareVmcs.try_get(guest::GS_SELECTOR))
+            .field("Guest LDTR Selector: ", &HardwareVmcs.try_get(guest::LDTR_SELECTOR))
+            .field("Guest TR Selector: ", &HardwareVmcs.try_get(guest::TR_SELECTOR))
+
+            .field("Guest CS Base: ", &HardwareVmcs.try_get(guest::CS_BASE))
+            .field("Guest SS Base: ", &HardwareVmcs.try_get(guest::SS_BASE))
+            .field("Guest DS Base: ", &HardwareVmcs.try_get(guest::DS_BASE))
+            .field("Guest ES Base: ", &HardwareVmcs.try_get(guest::ES_BASE))
+            .field("Guest FS Base: ", &HardwareVmcs.try_get(guest::FS_BASE))
+            .field("Guest GS Base: ", &HardwareVmcs.try_get(guest::GS_BASE))
+            .field("Guest LDTR Base: ", &HardwareVmcs.try_get(guest::LDTR_BASE))
+            .field("Guest TR Base: ", &HardwareVmcs.try_get(guest::TR_BASE))
+
+            .field("Guest CS Limit: ", &HardwareVmcs.try_get(guest::CS_LIMIT))
+            .field("Guest SS Limit: ", &HardwareVmcs.try_get(guest::SS_LIMIT))
+            .field("Guest DS Limit: ", &HardwareVmcs.try_get(guest::DS_LIMIT))
+            .field("Guest ES Limit: ", &HardwareVmcs.try_get(guest::ES_LIMIT))
+            .field("Guest FS Limit: ", &HardwareVmcs.try_get(guest::FS_LIMIT))
+            .field("Guest GS Limit: ", &HardwareVmcs.try_get(guest::GS_LIMIT))
+            .field("Guest LDTR Limit: ", &HardwareVmcs.try_get(guest::LDTR_LIMIT))
+            .field("Guest TR Limit: ", &HardwareVmcs.try_get(guest::TR_LIMIT))
+
+            .field("Guest CS Access Rights: ", &HardwareVmcs.try_get(guest::CS_ACCESS_RIGHTS))
+            .field("Guest SS Access Rights: ", &HardwareVmcs.try_get(guest::SS_ACCESS_RIGHTS))
+            .field("Guest DS Access Rights: ", &HardwareVmcs.try_get(guest::DS_ACCESS_RIGHTS))
+            .field("Guest ES Access Rights: ", &HardwareVmcs.try_get(guest::ES_ACCESS_RIGHTS))
+            .field("Guest FS Access Rights: ", &HardwareVmcs.try_get(guest::FS_ACCESS_RIGHTS))
+            .field("Guest GS Access Rights: ", &HardwareVmcs.try_get(guest::GS_ACCESS_RIGHTS))
+            .field("Guest LDTR Access Rights: ", &HardwareVmcs.try_get(guest::LDTR_ACCESS_RIGHTS))
+            .field("Guest TR Access Rights: ", &HardwareVmcs.try_get(guest::TR_ACCESS_RIGHTS))
+
+            .field("Guest GDTR Base: ", &HardwareVmcs.try_get(guest::GDTR_BASE))
+            .field("Guest IDTR Base: ", &HardwareVmcs.try_get(guest::IDTR_BASE))
+            .field("Guest GDTR Limit: ", &HardwareVmcs.try_get(guest::GDTR_LIMIT))
+            .field("Guest IDTR Limit: ", &HardwareVmcs.try_get(guest::IDTR_LIMIT))
+
+            .field("Guest IA32_DEBUGCTL_FULL: ", &HardwareVmcs.try_get(guest::IA32_DEBUGCTL_FULL))
+            .field("Guest IA32_SYSENTER_CS: ", &HardwareVmcs.try_get(guest::IA32_SYSENTER_CS))
+            .field("Guest IA32_SYSENTER_ESP: ", &HardwareVmcs.try_get(guest::IA32_SYSENTER_ESP))
+            .field("Guest IA32_SYSENTER_EIP: ", &HardwareVmcs.try_get(guest::IA32_SYSENTER_EIP))
+            .field("Guest VMCS Link Pointer: ", &HardwareVmcs.try_get(guest::LINK_PTR_FULL))
 
             /* VMCS Host state fields */
-            .field("Host CR0: ", &vmread(vmcs::host::CR0))
-            .field("Host CR3: ", &vmread(vmcs::host::CR3))
-            .field("Host CR4: ", &vmread(vmcs::host::CR4))
-            .field("Host RSP: ", &vmread(vmcs::host::RSP))
-            .field("Host RIP: ", &vmread(vmcs::host::RIP))
-            .field("Host CS Selector: ", &vmread(vmcs::host::CS_SELECTOR))
-            .field("Host SS Selector: ", &vmread(vmcs::host::SS_SELECTOR))
-            .field("Host DS Selector: ", &vmread(vmcs::host::DS_SELECTOR))
-            .field("Host ES Selector: ", &vmread(vmcs::host::ES_SELECTOR))
-            .field("Host FS Selector: ", &vmread(vmcs::host::FS_SELECTOR))
-            .field("Host GS Selector: ", &vmread(vmcs::host::GS_SELECTOR))
-            .field("Host TR Selector: ", &vmread(vmcs::host::TR_SELECTOR))
-            .field("Host FS Base: ", &vmread(vmcs::host::FS_BASE))
-            .field("Host GS Base: ", &vmread(vmcs::host::GS_BASE))
-            .field("Host TR Base: ", &vmread(vmcs::host::TR_BASE))
-            .field("Host GDTR Base: ", &vmread(vmcs::host::GDTR_BASE))
-            .field("Host IDTR Base: ", &vmread(vmcs::host::IDTR_BASE))
-            .field("Host IA32_SYSENTER_CS: ", &vmread(vmcs::host::IA32_SYSENTER_CS))
-            .field("Host IA32_SYSENTER_ESP: ", &vmread(vmcs::host::IA32_SYSENTER_ESP))
-            .field("Host IA32_SYSENTER_EIP: ", &vmread(vmcs::host::IA32_SYSENTER_EIP))
+            .field("Host CR0: ", &HardwareVmcs.try_get(host::CR0))
+            .field("Host CR3: ", &HardwareVmcs.try_get(host::CR3))
+            .field("Host CR4: ", &HardwareVmcs.try_get(host::CR4))
+            .field("Host RSP: ", &HardwareVmcs.try_get(host::RSP))
+            .field("Host RIP: ", &HardwareVmcs.try_get(host::RIP))
+            .field("Host CS Selector: ", &HardwareVmcs.try_get(host::CS_SELECTOR))
+            .field("Host SS Selector: ", &HardwareVmcs.try_get(host::SS_SELECTOR))
+            .field("Host DS Selector: ", &HardwareVmcs.try_get(host::DS_SELECTOR))
+            .field("Host ES Selector: ", &HardwareVmcs.try_get(host::ES_SELECTOR))
+            .field("Host FS Selector: ", &HardwareVmcs.try_get(host::FS_SELECTOR))
+            .field("Host GS Selector: ", &HardwareVmcs.try_get(host::GS_SELECTOR))
+            .field("Host TR Selector: ", &HardwareVmcs.try_get(host::TR_SELECTOR))
+            .field("Host FS Base: ", &HardwareVmcs.try_get(host::FS_BASE))
+            .field("Host GS Base: ", &HardwareVmcs.try_get(host::GS_BASE))
+            .field("Host TR Base: ", &HardwareVmcs.try_get(host::TR_BASE))
+            .field("Host GDTR Base: ", &HardwareVmcs.try_get(host::GDTR_BASE))
+            .field("Host IDTR Base: ", &HardwareVmcs.try_get(host::IDTR_BASE))
+            .field("Host IA32_SYSENTER_CS: ", &HardwareVmcs.try_get(host::IA32_SYSENTER_CS))
+            .field("Host IA32_SYSENTER_ESP: ", &HardwareVmcs.try_get(host::IA32_SYSENTER_ESP))
+            .field("Host IA32_SYSENTER_EIP: ", &HardwareVmcs.try_get(host::IA32_SYSENTER_EIP))
 
             /* VMCS Control fields */
-            .field("Primary Proc Based Execution Controls: ", &vmread(vmcs::control::PRIMARY_PROCBASED_EXEC_CONTROLS))
-            .field("Secondary Proc Based Execution Controls: ", &vmread(vmcs::control::SECONDARY_PROCBASED_EXEC_CONTROLS))
-            .field("VM Entry Controls: ", &vmread(vmcs::control::VMENTRY_CONTROLS))
-            .field("VM Exit Controls: ", &vmread(vmcs::control::VMEXIT_CONTROLS))
-            .field("Pin Based Execution Controls: ", &vmread(vmcs::control::PINBASED_EXEC_CONTROLS))
-            .field("CR0 Read Shadow: ", &vmread(vmcs::control::CR0_READ_SHADOW))
-            .field("CR4 Read Shadow: ", &vmread(vmcs::control::CR4_READ_SHADOW))
-            .field("MSR Bitmaps Address: ", &vmread(vmcs::control::MSR_BITMAPS_ADDR_FULL))
-            .field("EPT Pointer: ", &vmread(vmcs::control::EPTP_FULL))
+            .field("Primary Proc Based Execution Controls: ", &HardwareVmcs.try_get(control::PRIMARY_PROCBASED_EXEC_CONTROLS))
+            .field("Secondary Proc Based Execution Controls: ", &HardwareVmcs.try_get(control::SECONDARY_PROCBASED_EXEC_CONTROLS))
+            .field("VM Entry Controls: ", &HardwareVmcs.try_get(control::VMENTRY_CONTROLS))
+            .field("VM Exit Controls: ", &HardwareVmcs.try_get(control::VMEXIT_CONTROLS))
+            .field("Pin Based Execution Controls: ", &HardwareVmcs.try_get(control::PINBASED_EXEC_CONTROLS))
+            .field("CR0 Guest/Host Mask: ", &HardwareVmcs.try_get(control::CR0_GUEST_HOST_MASK))
+            .field("CR4 Guest/Host Mask: ", &HardwareVmcs.try_get(control::CR4_GUEST_HOST_MASK))
+            .field("CR0 Read Shadow: ", &HardwareVmcs.try_get(control::CR0_READ_SHADOW))
+            .field("CR4 Read Shadow: ", &HardwareVmcs.try_get(control::CR4_READ_SHADOW))
+            .field("MSR Bitmaps Address: ", &HardwareVmcs.try_get(control::MSR_BITMAPS_ADDR_FULL))
+            .field("I/O Bitmap A Address: ", &HardwareVmcs.try_get(control::IO_BITMAP_A_ADDR_FULL))
+            .field("I/O Bitmap B Address: ", &HardwareVmcs.try_get(control::IO_BITMAP_B_ADDR_FULL))
+            .field("EPT Pointer: ", &HardwareVmcs.try_get(control::EPTP_FULL))
             .finish_non_exhaustive()
     }
 }
@@ -0,0 +1,217 @@
+//! Provides an abstraction over the accesses to the fields of the current VMCS.
+//!
+//! The VM-exit handlers and the VMCS setup read and write the VMCS through `VmcsAccess` instead of
+//! executing VMREAD and VMWRITE directly. `HardwareVmcs` executes the instructions on the current
+//! VMCS of the processor, while `SoftVmcs` stores the fields in memory, so the handlers can be
+//! exercised outside of VMX root operation with scripted exit reasons and qualifications.
+//!
+//! The fields are only accessed through the typed fields of `vmcs_fields`, with `VmcsFieldAccess`, which
+//! checks their width and access type at compile time. `VmcsAccess` itself only takes the descriptions of
+//! the catalog, so a raw encoding can't be read or written.
+
+use {
+    crate::{
+        error::HypervisorError,
+        intel::{
+            support,
+            vmcs_fields::{FieldAccess, FieldInfo, FieldWidth, ReadWrite, VmcsField},
+            vmerror::VmInstructionError,
+        },
+    },
+    alloc::collections::BTreeMap,
+};
+
+/// Reads and writes the fields of a VMCS, described by the catalog of `vmcs_fields`.
+///
+/// The fields are accessed with the typed methods of `VmcsFieldAccess`, which are implemented on top of
+/// these two methods.
+pub trait VmcsAccess {
+    /// Reads a field, reporting a VMfail as an error.
+    fn read_field(&self, field: &FieldInfo) -> Result<u64, HypervisorError>;
+
+    /// Writes a field, reporting a VMfail as an error.
+    fn write_field(&mut self, field: &FieldInfo, value: u64) -> Result<(), HypervisorError>;
+}
+
+/// Accesses the typed fields of `vmcs_fields` on any `VmcsAccess`.
+pub trait VmcsFieldAccess: VmcsAccess {
+    /// Reads a field, truncated to its width.
+    ///
+    /// # Panics
+    ///
+    /// Panics with the decoded VM-instruction error if the field can't be read.
+    fn get<W: FieldWidth, A: FieldAccess>(&self, field: VmcsField<W, A>) -> W::Value {
+        match self.try_get(field) {
+            Ok(value) => value,
+            Err(error) => panic!("VMREAD from {:?} failed: {}", field, error),
+        }
+    }
+
+    /// Reads a field, truncated to its width, reporting a VMfail as an error.
+    fn try_get<W: FieldWidth, A: FieldAccess>(
+        &self,
+        field: VmcsField<W, A>,
+    ) -> Result<W::Value, HypervisorError> {
+        self.read_field(&field.info()).map(W::from_raw)
+    }
+
+    /// Writes a writable field with a value of its width.
+    ///
+    /// # Panics
+    ///
+    /// Panics with the decoded VM-instruction error if the field can't be written.
+    fn set<W: FieldWidth>(&mut self, field: VmcsField<W, ReadWrite>, value: W::Value) {
+        if let Err(error) = self.try_set(field, value) {
+            panic!("VMWRITE to {:?} failed: {}", field, error);
+        }
+    }
+
+    /// Writes a writable field with a value of its width, reporting a VMfail as an error.
+    fn try_set<W: FieldWidth>(
+        &mut self,
+        field: VmcsField<W, ReadWrite>,
+        value: W::Value,
+    ) -> Result<(), HypervisorError> {
+        self.write_field(&field.info(), value.into())
+    }
+}
+
+impl<T: VmcsAccess + ?Sized> VmcsFieldAccess for T {}
+
+/// Accesses the current VMCS of the processor with VMREAD and VMWRITE.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HardwareVmcs;
+
+impl VmcsAccess for HardwareVmcs {
+    fn read_field(&self, field: &FieldInfo) -> Result<u64, HypervisorError> {
+        support::try_vmread(field.encoding)
+    }
+
+    fn write_field(&mut self, field: &FieldInfo, value: u64) -> Result<(), HypervisorError> {
+        support::try_vmwrite(field.encoding, value)
+    }
+}
+
+/// A VMCS whose fields are stored in memory.
+///
+/// Accesses are checked like the processor would: writes to the read-only fields fail with
+/// `VmwriteReadonlyVmcsComponent` and written values are truncated to the width of the field. Fields that
+/// were never written read as 0.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SoftVmcs {
+    /// The values of the written fields, keyed by their encodings.
+    fields: BTreeMap<u32, u64>,
+}
+
+impl SoftVmcs {
+    /// Creates a VMCS without any field.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets a field, for example the exit reason or qualification of a scripted VM-exit.
+    ///
+    /// Unlike `set`, the read-only fields can be set as well.
+    pub fn with<W: FieldWidth, A: FieldAccess>(
+        mut self,
+        field: VmcsField<W, A>,
+        value: W::Value,
+    ) -> Self {
+        self.fields.insert(field.encoding(), value.into());
+        self
+    }
+
+    /// Returns the value of a field, or `None` if it was never written.
+    pub fn value<W: FieldWidth, A: FieldAccess>(&self, field: VmcsField<W, A>) -> Option<W::Value> {
+        self.fields.get(&field.encoding()).copied().map(W::from_raw)
+    }
+
+    /// Removes a field, so it reads as 0 again.
+    pub fn remove<W: FieldWidth, A: FieldAccess>(
+        &mut self,
+        field: VmcsField<W, A>,
+    ) -> Option<W::Value> {
+        self.fields.remove(&field.encoding()).map(W::from_raw)
+    }
+
+    /// Returns an iterator over the written fields and their values, sorted by their encodings.
+    pub fn fields(&self) -> impl Iterator<Item = (u32, u64)> + '_ {
+        self.fields.iter().map(|(field, value)| (*field, *value))
+    }
+}
+
+impl VmcsAccess for SoftVmcs {
+    fn read_field(&self, field: &FieldInfo) -> Result<u64, HypervisorError> {
+        Ok(self
+            .fields
+            .get(&field.encoding)
+            .copied()
+            .unwrap_or_default())
+    }
+
+    fn write_field(&mut self, field: &FieldInfo, value: u64) -> Result<(), HypervisorError> {
+        if !field.writable {
+            return Err(HypervisorError::VmFailValid(
+                VmInstructionError::VmwriteReadonlyVmcsComponent,
+            ));
+        }
+
+        self.fields
+            .insert(field.encoding, value & field.width.mask());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::intel::{
+            vmcs_fields::{guest, ro},
+            vmcs_snapshot::VmcsSnapshot,
+        },
+    };
+
+    #[test]
+    fn soft_vmcs_truncates_the_values_to_the_width_of_the_field() {
+        let mut vmcs = SoftVmcs::new();
+
+        vmcs.write_field(&guest::CS_SELECTOR.info(), 0x1_0033)
+            .unwrap();
+
+        assert_eq!(vmcs.get(guest::CS_SELECTOR), 0x33);
+        assert_eq!(vmcs.get(guest::RIP), 0);
+    }
+
+    #[test]
+    fn soft_vmcs_rejects_writes_to_read_only_fields() {
+        let mut vmcs = SoftVmcs::new();
+
+        assert!(matches!(
+            vmcs.write_field(&ro::EXIT_REASON.info(), 1),
+            Err(HypervisorError::VmFailValid(
+                VmInstructionError::VmwriteReadonlyVmcsComponent
+            ))
+        ));
+        assert_eq!(vmcs.value(ro::EXIT_REASON), None);
+    }
+
+    #[test]
+    fn try_get_reports_fields_that_fail_to_read() {
+        let snapshot = VmcsSnapshot::empty();
+
+        assert!(matches!(
+            snapshot.try_get(guest::RIP),
+            Err(HypervisorError::VmFailValid(
+                VmInstructionError::VmreadVmwriteUnsupportedVmcsComponent
+            ))
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "VMREAD from guest::RIP")]
+    fn get_panics_on_fields_that_fail_to_read() {
+        VmcsSnapshot::empty().get(guest::RIP);
+    }
+}
@@ -0,0 +1,628 @@
+//! Provides a typed catalog of the VMCS fields.
+//!
+//! Every field of `x86::vmx::vmcs` is described by a `VmcsField`, which carries the width of the field
+//! and whether it's writable in its type. Reads return a value of the width of the field, and writes only
+//! accept writable fields and values of their width, so writing a selector with a 64-bit value or writing
+//! a VM-exit information field is rejected at compile time instead of failing with VMfail at run time.
+//! The width and access type declared in the catalog are checked against the encodings at compile time.
+//!
+//! Only the full encodings of the 64-bit fields are listed, since the high encodings are only used in
+//! 32-bit mode.
+//!
+//! Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 25.11.2 VMREAD, VMWRITE, and Encodings of VMCS Fields
+//! and APPENDIX B FIELD ENCODING IN VMCS
+
+use core::{fmt, marker::PhantomData};
+
+/// The width of a VMCS field, encoded in bits 14:13 of its encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Width {
+    Bits16,
+    Bits64,
+    Bits32,
+    Natural,
+}
+
+impl Width {
+    /// Returns the width of the field with the given encoding.
+    pub const fn from_encoding(encoding: u32) -> Self {
+        match (encoding >> 13) & 0b11 {
+            0 => Self::Bits16,
+            1 => Self::Bits64,
+            2 => Self::Bits32,
+            _ => Self::Natural,
+        }
+    }
+
+    /// Returns the mask of the bits that a field of this width can hold in 64-bit mode.
+    pub const fn mask(self) -> u64 {
+        match self {
+            Self::Bits16 => u16::MAX as u64,
+            Self::Bits32 => u32::MAX as u64,
+            Self::Bits64 | Self::Natural => u64::MAX,
+        }
+    }
+}
+
+/// Checks whether the field with the given encoding is a read-only VM-exit information field,
+/// encoded with type 1 in bits 11:10 of its encoding.
+pub const fn is_read_only(encoding: u32) -> bool {
+    (encoding >> 10) & 0b11 == 1
+}
+
+/// A width of a VMCS field, as a type.
+pub trait FieldWidth {
+    /// The width of the fields.
+    const WIDTH: Width;
+
+    /// The type of the values of the fields.
+    type Value: Copy + Into<u64>;
+
+    /// Truncates a value read with VMREAD to the width of the fields.
+    fn from_raw(raw: u64) -> Self::Value;
+}
+
+/// The width of the 16-bit fields.
+pub enum Bits16 {}
+
+/// The width of the 32-bit fields.
+pub enum Bits32 {}
+
+/// The width of the 64-bit fields.
+pub enum Bits64 {}
+
+/// The width of the natural-width fields, which are 64-bit in 64-bit mode.
+pub enum Natural {}
+
+impl FieldWidth for Bits16 {
+    const WIDTH: Width = Width::Bits16;
+    type Value = u16;
+
+    fn from_raw(raw: u64) -> u16 {
+        raw as u16
+    }
+}
+
+impl FieldWidth for Bits32 {
+    const WIDTH: Width = Width::Bits32;
+    type Value = u32;
+
+    fn from_raw(raw: u64) -> u32 {
+        raw as u32
+    }
+}
+
+impl FieldWidth for Bits64 {
+    const WIDTH: Width = Width::Bits64;
+    type Value = u64;
+
+    fn from_raw(raw: u64) -> u64 {
+        raw
+    }
+}
+
+impl FieldWidth for Natural {
+    const WIDTH: Width = Width::Natural;
+    type Value = u64;
+
+    fn from_raw(raw: u64) -> u64 {
+        raw
+    }
+}
+
+/// The access type of a VMCS field, as a type.
+pub trait FieldAccess {
+    /// Whether the fields can be written with VMWRITE.
+    const WRITABLE: bool;
+}
+
+/// The access type of the read-only VM-exit information fields.
+pub enum ReadOnly {}
+
+/// The access type of the control, guest-state and host-state fields.
+pub enum ReadWrite {}
+
+impl FieldAccess for ReadOnly {
+    const WRITABLE: bool = false;
+}
+
+impl FieldAccess for ReadWrite {
+    const WRITABLE: bool = true;
+}
+
+/// A VMCS field with its width `W` and access type `A`.
+pub struct VmcsField<W, A> {
+    /// The encoding of the field.
+    encoding: u32,
+
+    /// The name of the field, for example `guest::CS_SELECTOR`.
+    name: &'static str,
+
+    _marker: PhantomData<fn() -> (W, A)>,
+}
+
+impl<W: FieldWidth, A: FieldAccess> VmcsField<W, A> {
+    /// Creates a field, checking that the width and access type match the encoding.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the width or the access type don't match the encoding, which fails the compilation
+    /// of the catalog.
+    pub const fn new(encoding: u32, name: &'static str) -> Self {
+        assert!(
+            Width::from_encoding(encoding) as u8 == W::WIDTH as u8,
+            "The width of the VMCS field doesn't match its encoding"
+        );
+        assert!(
+            is_read_only(encoding) != A::WRITABLE,
+            "The access type of the VMCS field doesn't match its encoding"
+        );
+
+        Self {
+            encoding,
+            name,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the description of the field.
+    pub const fn info(&self) -> FieldInfo {
+        FieldInfo {
+            encoding: self.encoding,
+            name: self.name,
+            width: W::WIDTH,
+            writable: A::WRITABLE,
+        }
+    }
+}
+
+impl<W, A> VmcsField<W, A> {
+    /// Returns the encoding of the field, as used by VMREAD and VMWRITE.
+    pub const fn encoding(&self) -> u32 {
+        self.encoding
+    }
+
+    /// Returns the name of the field.
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl<W, A> Clone for VmcsField<W, A> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<W, A> Copy for VmcsField<W, A> {}
+
+impl<W, A> fmt::Debug for VmcsField<W, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({:#x})", self.name, self.encoding)
+    }
+}
+
+/// The description of a VMCS field of the catalog.
+///
+/// Descriptions can only be created from the typed fields, so a `VmcsAccess` is never asked for a field
+/// that isn't in the catalog.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FieldInfo {
+    /// The encoding of the field.
+    pub encoding: u32,
+
+    /// The name of the field, for example `guest::CS_SELECTOR`.
+    pub name: &'static str,
+
+    /// The width of the field.
+    pub width: Width,
+
+    /// Whether the field can be written with VMWRITE.
+    pub writable: bool,
+}
+
+impl<W: FieldWidth, A: FieldAccess> From<VmcsField<W, A>> for FieldInfo {
+    fn from(field: VmcsField<W, A>) -> Self {
+        field.info()
+    }
+}
+
+/// Looks up the description of a field by its encoding.
+///
+/// # Arguments
+///
+/// * `encoding` - The encoding of the field.
+///
+/// # Returns
+///
+/// * `Option<&'static FieldInfo>` - The description, or `None` if the field isn't in the catalog.
+pub fn lookup(encoding: u32) -> Option<&'static FieldInfo> {
+    FIELDS.iter().find(|field| field.encoding == encoding)
+}
+
+/// Generates the typed fields of every module of `x86::vmx::vmcs` and the table of all fields.
+macro_rules! vmcs_fields {
+    ($(
+        $(#[$module_meta:meta])*
+        $module:ident: $access:ident {
+            $($(#[$meta:meta])* $name:ident: $width:ident,)*
+        }
+    )*) => {
+        $(
+            $(#[$module_meta])*
+            pub mod $module {
+                use super::*;
+
+                $(
+                    $(#[$meta])*
+                    pub const $name: VmcsField<$width, $access> = VmcsField::new(
+                        x86::vmx::vmcs::$module::$name,
+                        concat!(stringify!($module), "::", stringify!($name)),
+                    );
+                )*
+            }
+        )*
+
+        /// The descriptions of all the fields of the catalog.
+        pub const FIELDS: &[FieldInfo] = &[$($($module::$name.info(),)*)*];
+    };
+}
+
+vmcs_fields! {
+    /// VM-execution, VM-exit and VM-entry control fields.
+    control: ReadWrite {
+        // 16-bit control fields
+        /// Virtual-processor identifier (VPID).
+        VPID: Bits16,
+        /// Posted-interrupt notification vector.
+        POSTED_INTERRUPT_NOTIFICATION_VECTOR: Bits16,
+        /// EPTP index.
+        EPTP_INDEX: Bits16,
+
+        // 64-bit control fields
+        /// Address of I/O bitmap A.
+        IO_BITMAP_A_ADDR_FULL: Bits64,
+        /// Address of I/O bitmap B.
+        IO_BITMAP_B_ADDR_FULL: Bits64,
+        /// Address of MSR bitmaps.
+        MSR_BITMAPS_ADDR_FULL: Bits64,
+        /// VM-exit MSR-store address.
+        VMEXIT_MSR_STORE_ADDR_FULL: Bits64,
+        /// VM-exit MSR-load address.
+        VMEXIT_MSR_LOAD_ADDR_FULL: Bits64,
+        /// VM-entry MSR-load address.
+        VMENTRY_MSR_LOAD_ADDR_FULL: Bits64,
+        /// Executive-VMCS pointer.
+        EXECUTIVE_VMCS_PTR_FULL: Bits64,
+        /// PML address.
+        PML_ADDR_FULL: Bits64,
+        /// TSC offset.
+        TSC_OFFSET_FULL: Bits64,
+        /// Virtual-APIC address.
+        VIRT_APIC_ADDR_FULL: Bits64,
+        /// APIC-access address.
+        APIC_ACCESS_ADDR_FULL: Bits64,
+        /// Posted-interrupt descriptor address.
+        POSTED_INTERRUPT_DESC_ADDR_FULL: Bits64,
+        /// VM-function controls.
+        VM_FUNCTION_CONTROLS_FULL: Bits64,
+        /// EPT pointer.
+        EPTP_FULL: Bits64,
+        /// EOI-exit bitmap 0.
+        EOI_EXIT0_FULL: Bits64,
+        /// EOI-exit bitmap 1.
+        EOI_EXIT1_FULL: Bits64,
+        /// EOI-exit bitmap 2.
+        EOI_EXIT2_FULL: Bits64,
+        /// EOI-exit bitmap 3.
+        EOI_EXIT3_FULL: Bits64,
+        /// EPTP-list address.
+        EPTP_LIST_ADDR_FULL: Bits64,
+        /// VMREAD-bitmap address.
+        VMREAD_BITMAP_ADDR_FULL: Bits64,
+        /// VMWRITE-bitmap address.
+        VMWRITE_BITMAP_ADDR_FULL: Bits64,
+        /// Virtualization-exception information address.
+        VIRT_EXCEPTION_INFO_ADDR_FULL: Bits64,
+        /// XSS-exiting bitmap.
+        XSS_EXITING_BITMAP_FULL: Bits64,
+        /// ENCLS-exiting bitmap.
+        ENCLS_EXITING_BITMAP_FULL: Bits64,
+        /// Sub-page-permission-table pointer.
+        SUBPAGE_PERM_TABLE_PTR_FULL: Bits64,
+        /// TSC multiplier.
+        TSC_MULTIPLIER_FULL: Bits64,
+
+        // 32-bit control fields
+        /// Pin-based VM-execution controls.
+        PINBASED_EXEC_CONTROLS: Bits32,
+        /// Primary processor-based VM-execution controls.
+        PRIMARY_PROCBASED_EXEC_CONTROLS: Bits32,
+        /// Exception bitmap.
+        EXCEPTION_BITMAP: Bits32,
+        /// Page-fault error-code mask.
+        PAGE_FAULT_ERR_CODE_MASK: Bits32,
+        /// Page-fault error-code match.
+        PAGE_FAULT_ERR_CODE_MATCH: Bits32,
+        /// CR3-target count.
+        CR3_TARGET_COUNT: Bits32,
+        /// VM-exit controls.
+        VMEXIT_CONTROLS: Bits32,
+        /// VM-exit MSR-store count.
+        VMEXIT_MSR_STORE_COUNT: Bits32,
+        /// VM-exit MSR-load count.
+        VMEXIT_MSR_LOAD_COUNT: Bits32,
+        /// VM-entry controls.
+        VMENTRY_CONTROLS: Bits32,
+        /// VM-entry MSR-load count.
+        VMENTRY_MSR_LOAD_COUNT: Bits32,
+        /// VM-entry interruption-information field.
+        VMENTRY_INTERRUPTION_INFO_FIELD: Bits32,
+        /// VM-entry exception error code.
+        VMENTRY_EXCEPTION_ERR_CODE: Bits32,
+        /// VM-entry instruction length.
+        VMENTRY_INSTRUCTION_LEN: Bits32,
+        /// TPR threshold.
+        TPR_THRESHOLD: Bits32,
+        /// Secondary processor-based VM-execution controls.
+        SECONDARY_PROCBASED_EXEC_CONTROLS: Bits32,
+        /// PLE_Gap.
+        PLE_GAP: Bits32,
+        /// PLE_Window.
+        PLE_WINDOW: Bits32,
+
+        // Natural-width control fields
+        /// CR0 guest/host mask.
+        CR0_GUEST_HOST_MASK: Natural,
+        /// CR4 guest/host mask.
+        CR4_GUEST_HOST_MASK: Natural,
+        /// CR0 read shadow.
+        CR0_READ_SHADOW: Natural,
+        /// CR4 read shadow.
+        CR4_READ_SHADOW: Natural,
+        /// CR3-target value 0.
+        CR3_TARGET_VALUE0: Natural,
+        /// CR3-target value 1.
+        CR3_TARGET_VALUE1: Natural,
+        /// CR3-target value 2.
+        CR3_TARGET_VALUE2: Natural,
+        /// CR3-target value 3.
+        CR3_TARGET_VALUE3: Natural,
+    }
+
+    /// Guest-state fields.
+    guest: ReadWrite {
+        // 16-bit guest-state fields
+        /// Guest ES selector.
+        ES_SELECTOR: Bits16,
+        /// Guest CS selector.
+        CS_SELECTOR: Bits16,
+        /// Guest SS selector.
+        SS_SELECTOR: Bits16,
+        /// Guest DS selector.
+        DS_SELECTOR: Bits16,
+        /// Guest FS selector.
+        FS_SELECTOR: Bits16,
+        /// Guest GS selector.
+        GS_SELECTOR: Bits16,
+        /// Guest LDTR selector.
+        LDTR_SELECTOR: Bits16,
+        /// Guest TR selector.
+        TR_SELECTOR: Bits16,
+        /// Guest interrupt status.
+        INTERRUPT_STATUS: Bits16,
+        /// PML index.
+        PML_INDEX: Bits16,
+
+        // 64-bit guest-state fields
+        /// VMCS link pointer.
+        LINK_PTR_FULL: Bits64,
+        /// Guest IA32_DEBUGCTL.
+        IA32_DEBUGCTL_FULL: Bits64,
+        /// Guest IA32_PAT.
+        IA32_PAT_FULL: Bits64,
+        /// Guest IA32_EFER.
+        IA32_EFER_FULL: Bits64,
+        /// Guest IA32_PERF_GLOBAL_CTRL.
+        IA32_PERF_GLOBAL_CTRL_FULL: Bits64,
+        /// Guest PDPTE0.
+        PDPTE0_FULL: Bits64,
+        /// Guest PDPTE1.
+        PDPTE1_FULL: Bits64,
+        /// Guest PDPTE2.
+        PDPTE2_FULL: Bits64,
+        /// Guest PDPTE3.
+        PDPTE3_FULL: Bits64,
+        /// Guest IA32_BNDCFGS.
+        IA32_BNDCFGS_FULL: Bits64,
+        /// Guest IA32_RTIT_CTL.
+        IA32_RTIT_CTL_FULL: Bits64,
+
+        // 32-bit guest-state fields
+        /// Guest ES limit.
+        ES_LIMIT: Bits32,
+        /// Guest CS limit.
+        CS_LIMIT: Bits32,
+        /// Guest SS limit.
+        SS_LIMIT: Bits32,
+        /// Guest DS limit.
+        DS_LIMIT: Bits32,
+        /// Guest FS limit.
+        FS_LIMIT: Bits32,
+        /// Guest GS limit.
+        GS_LIMIT: Bits32,
+        /// Guest LDTR limit.
+        LDTR_LIMIT: Bits32,
+        /// Guest TR limit.
+        TR_LIMIT: Bits32,
+        /// Guest GDTR limit.
+        GDTR_LIMIT: Bits32,
+        /// Guest IDTR limit.
+        IDTR_LIMIT: Bits32,
+        /// Guest ES access rights.
+        ES_ACCESS_RIGHTS: Bits32,
+        /// Guest CS access rights.
+        CS_ACCESS_RIGHTS: Bits32,
+        /// Guest SS access rights.
+        SS_ACCESS_RIGHTS: Bits32,
+        /// Guest DS access rights.
+        DS_ACCESS_RIGHTS: Bits32,
+        /// Guest FS access rights.
+        FS_ACCESS_RIGHTS: Bits32,
+        /// Guest GS access rights.
+        GS_ACCESS_RIGHTS: Bits32,
+        /// Guest LDTR access rights.
+        LDTR_ACCESS_RIGHTS: Bits32,
+        /// Guest TR access rights.
+        TR_ACCESS_RIGHTS: Bits32,
+        /// Guest interruptibility state.
+        INTERRUPTIBILITY_STATE: Bits32,
+        /// Guest activity state.
+        ACTIVITY_STATE: Bits32,
+        /// Guest SMBASE.
+        SMBASE: Bits32,
+        /// Guest IA32_SYSENTER_CS.
+        IA32_SYSENTER_CS: Bits32,
+        /// VMX-preemption timer value.
+        VMX_PREEMPTION_TIMER_VALUE: Bits32,
+
+        // Natural-width guest-state fields
+        /// Guest CR0.
+        CR0: Natural,
+        /// Guest CR3.
+        CR3: Natural,
+        /// Guest CR4.
+        CR4: Natural,
+        /// Guest ES base.
+        ES_BASE: Natural,
+        /// Guest CS base.
+        CS_BASE: Natural,
+        /// Guest SS base.
+        SS_BASE: Natural,
+        /// Guest DS base.
+        DS_BASE: Natural,
+        /// Guest FS base.
+        FS_BASE: Natural,
+        /// Guest GS base.
+        GS_BASE: Natural,
+        /// Guest LDTR base.
+        LDTR_BASE: Natural,
+        /// Guest TR base.
+        TR_BASE: Natural,
+        /// Guest GDTR base.
+        GDTR_BASE: Natural,
+        /// Guest IDTR base.
+        IDTR_BASE: Natural,
+        /// Guest DR7.
+        DR7: Natural,
+        /// Guest RSP.
+        RSP: Natural,
+        /// Guest RIP.
+        RIP: Natural,
+        /// Guest RFLAGS.
+        RFLAGS: Natural,
+        /// Guest pending debug exceptions.
+        PENDING_DBG_EXCEPTIONS: Natural,
+        /// Guest IA32_SYSENTER_ESP.
+        IA32_SYSENTER_ESP: Natural,
+        /// Guest IA32_SYSENTER_EIP.
+        IA32_SYSENTER_EIP: Natural,
+    }
+
+    /// Host-state fields.
+    host: ReadWrite {
+        // 16-bit host-state fields
+        /// Host ES selector.
+        ES_SELECTOR: Bits16,
+        /// Host CS selector.
+        CS_SELECTOR: Bits16,
+        /// Host SS selector.
+        SS_SELECTOR: Bits16,
+        /// Host DS selector.
+        DS_SELECTOR: Bits16,
+        /// Host FS selector.
+        FS_SELECTOR: Bits16,
+        /// Host GS selector.
+        GS_SELECTOR: Bits16,
+        /// Host TR selector.
+        TR_SELECTOR: Bits16,
+
+        // 64-bit host-state fields
+        /// Host IA32_PAT.
+        IA32_PAT_FULL: Bits64,
+        /// Host IA32_EFER.
+        IA32_EFER_FULL: Bits64,
+        /// Host IA32_PERF_GLOBAL_CTRL.
+        IA32_PERF_GLOBAL_CTRL_FULL: Bits64,
+
+        // 32-bit host-state field
+        /// Host IA32_SYSENTER_CS.
+        IA32_SYSENTER_CS: Bits32,
+
+        // Natural-width host-state fields
+        /// Host CR0.
+        CR0: Natural,
+        /// Host CR3.
+        CR3: Natural,
+        /// Host CR4.
+        CR4: Natural,
+        /// Host FS base.
+        FS_BASE: Natural,
+        /// Host GS base.
+        GS_BASE: Natural,
+        /// Host TR base.
+        TR_BASE: Natural,
+        /// Host GDTR base.
+        GDTR_BASE: Natural,
+        /// Host IDTR base.
+        IDTR_BASE: Natural,
+        /// Host IA32_SYSENTER_ESP.
+        IA32_SYSENTER_ESP: Natural,
+        /// Host IA32_SYSENTER_EIP.
+        IA32_SYSENTER_EIP: Natural,
+        /// Host RSP.
+        RSP: Natural,
+        /// Host RIP.
+        RIP: Natural,
+    }
+
+    /// VM-exit information fields, which are read-only.
+    ro: ReadOnly {
+        // 64-bit read-only data fields
+        /// Guest-physical address.
+        GUEST_PHYSICAL_ADDR_FULL: Bits64,
+
+        // 32-bit read-only data fields
+        /// VM-instruction error.
+        VM_INSTRUCTION_ERROR: Bits32,
+        /// Exit reason.
+        EXIT_REASON: Bits32,
+        /// VM-exit interruption information.
+        VMEXIT_INTERRUPTION_INFO: Bits32,
+        /// VM-exit interruption error code.
+        VMEXIT_INTERRUPTION_ERR_CODE: Bits32,
+        /// IDT-vectoring information field.
+        IDT_VECTORING_INFO: Bits32,
+        /// IDT-vectoring error code.
+        IDT_VECTORING_ERR_CODE: Bits32,
+        /// VM-exit instruction length.
+        VMEXIT_INSTRUCTION_LEN: Bits32,
+        /// VM-exit instruction information.
+        VMEXIT_INSTRUCTION_INFO: Bits32,
+
+        // Natural-width read-only data fields
+        /// Exit qualification.
+        EXIT_QUALIFICATION: Natural,
+        /// I/O RCX.
+        IO_RCX: Natural,
+        /// I/O RSI.
+        IO_RSI: Natural,
+        /// I/O RDI.
+        IO_RDI: Natural,
+        /// I/O RIP.
+        IO_RIP: Natural,
+        /// Guest-linear address.
+        GUEST_LINEAR_ADDR: Natural,
+    }
+}
@@ -0,0 +1,363 @@
+//! Provides a snapshot of all the fields of a VMCS for failure diagnostics.
+//!
+//! `VmcsSnapshot::capture` reads every field of the `vmcs_fields` catalog from the current VMCS, so the
+//! complete guest state, host state, controls and exit information can be reported when VMLAUNCH or
+//! VMRESUME fails or a VM-exit can't be handled. The snapshot is printed grouped by the sections of the
+//! VMCS in the SDM, and is written to the log line by line, so the serial logger outputs every field as
+//! a separate record.
+//!
+//! The snapshot can also be serialized into a compact blob with `VmcsSnapshot::to_bytes`. The blob is
+//! little-endian and consists of a header (magic, version, count) and the captured fields (encoding and
+//! value). Fields that couldn't be read aren't stored.
+//!
+//! Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 25.3 ORGANIZATION OF VMCS DATA
+
+use {
+    crate::{
+        error::HypervisorError,
+        intel::{
+            vmcs_access::{HardwareVmcs, VmcsAccess},
+            vmcs_fields::{ro, FieldAccess, FieldInfo, FieldWidth, VmcsField, Width, FIELDS},
+            vmerror::{VmInstructionError, VmxBasicExitReason},
+        },
+        utils::bytes::ByteReader,
+    },
+    alloc::vec::Vec,
+    core::fmt,
+};
+
+/// The magic of the VMCS snapshot blob (`HVVS`).
+pub const VMCS_SNAPSHOT_MAGIC: u32 = u32::from_le_bytes(*b"HVVS");
+
+/// The version of the VMCS snapshot blob format.
+pub const VMCS_SNAPSHOT_VERSION: u32 = 1;
+
+/// The sections of the VMCS, in the order of the SDM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmcsSection {
+    GuestState,
+    HostState,
+    ExecutionControls,
+    ExitControls,
+    EntryControls,
+    ExitInformation,
+}
+
+impl VmcsSection {
+    /// All the sections, in the order of the SDM.
+    pub const ALL: [Self; 6] = [
+        Self::GuestState,
+        Self::HostState,
+        Self::ExecutionControls,
+        Self::ExitControls,
+        Self::EntryControls,
+        Self::ExitInformation,
+    ];
+
+    /// Returns the section of a field of the catalog.
+    pub fn of(field: &FieldInfo) -> Self {
+        let (module, name) = field.name.split_once("::").unwrap_or(("", field.name));
+
+        match module {
+            "guest" => Self::GuestState,
+            "host" => Self::HostState,
+            "ro" => Self::ExitInformation,
+            _ if name.starts_with("VMEXIT_") => Self::ExitControls,
+            _ if name.starts_with("VMENTRY_") => Self::EntryControls,
+            _ => Self::ExecutionControls,
+        }
+    }
+
+    /// Returns the title of the section in the SDM.
+    pub fn title(self) -> &'static str {
+        match self {
+            Self::GuestState => "25.4 Guest-State Area",
+            Self::HostState => "25.5 Host-State Area",
+            Self::ExecutionControls => "25.6 VM-Execution Control Fields",
+            Self::ExitControls => "25.7 VM-Exit Control Fields",
+            Self::EntryControls => "25.8 VM-Entry Control Fields",
+            Self::ExitInformation => "25.9 VM-Exit Information Fields",
+        }
+    }
+}
+
+/// The values of all the fields of a VMCS at a point in time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmcsSnapshot {
+    /// The values of the fields, indexed like `FIELDS`. Fields that couldn't be read are `None`.
+    values: [Option<u64>; FIELDS.len()],
+}
+
+impl VmcsSnapshot {
+    /// Creates a snapshot without any field.
+    pub fn empty() -> Self {
+        Self {
+            values: [None; FIELDS.len()],
+        }
+    }
+
+    /// Captures all the fields of the current VMCS of the processor.
+    pub fn capture() -> Self {
+        Self::capture_from(&HardwareVmcs)
+    }
+
+    /// Captures all the fields of a VMCS.
+    ///
+    /// # Arguments
+    ///
+    /// * `vmcs` - The VMCS, for example a `SoftVmcs` with crafted fields.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The snapshot. Fields that fail with VMfail, for example because the processor doesn't support them, are `None`.
+    pub fn capture_from(vmcs: &dyn VmcsAccess) -> Self {
+        let mut snapshot = Self::empty();
+
+        for (value, field) in snapshot.values.iter_mut().zip(FIELDS) {
+            *value = vmcs.read_field(field).ok();
+        }
+
+        snapshot
+    }
+
+    /// Returns the value of a field, or `None` if it couldn't be read.
+    pub fn value<W: FieldWidth, A: FieldAccess>(&self, field: VmcsField<W, A>) -> Option<W::Value> {
+        self.values[Self::index(field.encoding())?].map(W::from_raw)
+    }
+
+    /// Sets the value of a field, or marks it as not captured with `None`.
+    pub fn set_value<W: FieldWidth, A: FieldAccess>(
+        &mut self,
+        field: VmcsField<W, A>,
+        value: Option<W::Value>,
+    ) {
+        // The typed fields are all in the catalog.
+        let _ = self.set_encoding(field.encoding(), value.map(Into::into));
+    }
+
+    /// Sets the value of a field by its encoding, truncated to the width of the field.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), HypervisorError>` - `VmreadVmwriteUnsupportedVmcsComponent` if the field isn't in the catalog.
+    fn set_encoding(&mut self, encoding: u32, value: Option<u64>) -> Result<(), HypervisorError> {
+        let index = Self::index(encoding).ok_or(HypervisorError::VmFailValid(
+            VmInstructionError::VmreadVmwriteUnsupportedVmcsComponent,
+        ))?;
+
+        self.values[index] = value.map(|value| value & FIELDS[index].width.mask());
+
+        Ok(())
+    }
+
+    /// Returns an iterator over all the fields of the catalog and their captured values.
+    pub fn fields(&self) -> impl Iterator<Item = (&'static FieldInfo, Option<u64>)> + '_ {
+        FIELDS.iter().zip(self.values.iter().copied())
+    }
+
+    /// Returns an iterator over the fields of a section and their captured values.
+    pub fn section(
+        &self,
+        section: VmcsSection,
+    ) -> impl Iterator<Item = (&'static FieldInfo, Option<u64>)> + '_ {
+        self.fields()
+            .filter(move |(field, _)| VmcsSection::of(field) == section)
+    }
+
+    /// Writes the snapshot to the log, one record per line.
+    ///
+    /// # Arguments
+    ///
+    /// * `level` - The level of the records.
+    pub fn log(&self, level: log::Level) {
+        log::log!(level, "{}", self.header());
+
+        for section in VmcsSection::ALL {
+            log::log!(level, "{}", section.title());
+
+            for (field, value) in self.section(section) {
+                log::log!(level, "{}", FieldLine { field, value });
+            }
+        }
+    }
+
+    /// Serializes the snapshot into a blob.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let captured = self.values.iter().flatten().count() as u32;
+        let mut blob = Vec::new();
+
+        blob.extend_from_slice(&VMCS_SNAPSHOT_MAGIC.to_le_bytes());
+        blob.extend_from_slice(&VMCS_SNAPSHOT_VERSION.to_le_bytes());
+        blob.extend_from_slice(&captured.to_le_bytes());
+
+        for (field, value) in self.fields() {
+            if let Some(value) = value {
+                blob.extend_from_slice(&field.encoding.to_le_bytes());
+                blob.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        blob
+    }
+
+    /// Parses a blob created by `to_bytes`.
+    ///
+    /// # Arguments
+    ///
+    /// * `blob` - The blob.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, HypervisorError>` - The snapshot, or an error if the blob is invalid or has a different version.
+    pub fn from_bytes(blob: &[u8]) -> Result<Self, HypervisorError> {
+        Self::read(&mut ByteReader::new(blob, || {
+            HypervisorError::InvalidVmcsSnapshot
+        }))
+    }
+
+    /// Reads the snapshot from the blob.
+    fn read(reader: &mut ByteReader) -> Result<Self, HypervisorError> {
+        if reader.u32()? != VMCS_SNAPSHOT_MAGIC || reader.u32()? != VMCS_SNAPSHOT_VERSION {
+            return Err(HypervisorError::InvalidVmcsSnapshot);
+        }
+
+        let mut snapshot = Self::empty();
+        let count = reader.u32()?;
+
+        for _ in 0..count {
+            let encoding = reader.u32()?;
+            let value = reader.u64()?;
+
+            snapshot
+                .set_encoding(encoding, Some(value))
+                .map_err(|_| HypervisorError::InvalidVmcsSnapshot)?;
+        }
+
+        Ok(snapshot)
+    }
+
+    /// Returns the index of a field in `FIELDS`.
+    fn index(encoding: u32) -> Option<usize> {
+        FIELDS.iter().position(|field| field.encoding == encoding)
+    }
+
+    /// Returns the header of the printed snapshot, with the exit reason and the VM-instruction error.
+    fn header(&self) -> SnapshotHeader {
+        SnapshotHeader {
+            captured: self.values.iter().flatten().count(),
+            exit_reason: self.value(ro::EXIT_REASON),
+            instruction_error: self.value(ro::VM_INSTRUCTION_ERROR),
+        }
+    }
+}
+
+impl Default for VmcsSnapshot {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+/// The snapshot can be read like a VMCS, for example by the code that validates a VMCS before it's
+/// launched. Writes change the captured values, but not the VMCS the snapshot was captured from.
+impl VmcsAccess for VmcsSnapshot {
+    fn read_field(&self, field: &FieldInfo) -> Result<u64, HypervisorError> {
+        Self::index(field.encoding)
+            .and_then(|index| self.values[index])
+            .ok_or(HypervisorError::VmFailValid(
+                VmInstructionError::VmreadVmwriteUnsupportedVmcsComponent,
+            ))
+    }
+
+    fn write_field(&mut self, field: &FieldInfo, value: u64) -> Result<(), HypervisorError> {
+        self.set_encoding(field.encoding, Some(value))
+    }
+}
+
+impl fmt::Display for VmcsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.header())?;
+
+        for section in VmcsSection::ALL {
+            writeln!(f, "{}", section.title())?;
+
+            for (field, value) in self.section(section) {
+                writeln!(f, "{}", FieldLine { field, value })?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The first line of a printed snapshot.
+struct SnapshotHeader {
+    /// The number of captured fields.
+    captured: usize,
+
+    /// The exit reason, if it was captured.
+    exit_reason: Option<u32>,
+
+    /// The VM-instruction error, if it was captured.
+    instruction_error: Option<u32>,
+}
+
+impl fmt::Display for SnapshotHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "VMCS snapshot: {} of {} fields captured",
+            self.captured,
+            FIELDS.len()
+        )?;
+
+        if let Some(exit_reason) = self.exit_reason {
+            // Bit 31 of the exit reason is set if the VM-entry failed.
+            let failed_entry = exit_reason & (1 << 31) != 0;
+
+            match VmxBasicExitReason::from_u32(exit_reason & 0xFFFF) {
+                Some(reason) => write!(f, ", exit reason: {}", reason)?,
+                None => write!(f, ", exit reason: {:#x}", exit_reason & 0xFFFF)?,
+            }
+
+            if failed_entry {
+                write!(f, " (VM-entry failure)")?;
+            }
+        }
+
+        match self.instruction_error {
+            Some(0) | None => Ok(()),
+            Some(error) => match VmInstructionError::from_u32(error) {
+                Some(error) => write!(f, ", VM-instruction error: {}", error),
+                None => write!(f, ", VM-instruction error: {:#x}", error),
+            },
+        }
+    }
+}
+
+/// A line of a printed snapshot, with the name, encoding and value of a field.
+struct FieldLine {
+    /// The field.
+    field: &'static FieldInfo,
+
+    /// The value of the field, if it was captured.
+    value: Option<u64>,
+}
+
+impl fmt::Display for FieldLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self
+            .field
+            .name
+            .split_once("::")
+            .map_or(self.field.name, |(_, name)| name);
+
+        write!(f, "  {:<40} {:#06x}  ", name, self.field.encoding)?;
+
+        match (self.value, self.field.width) {
+            (None, _) => write!(f, "<unsupported>"),
+            (Some(value), Width::Bits16) => write!(f, "{:#06x}", value),
+            (Some(value), Width::Bits32) => write!(f, "{:#010x}", value),
+            (Some(value), Width::Bits64 | Width::Natural) => write!(f, "{:#018x}", value),
+        }
+    }
+}
@@ -0,0 +1,1804 @@
+//! Provides a software implementation of the checks performed by the processor on VM entry.
+//!
+//! A VMCS that violates one of the checks on the VMX controls or the host-state area fails VMLAUNCH
+//! with a VM-instruction error, and a VMCS that violates one of the checks on the guest-state area fails
+//! with a "VM entry with invalid guest state" exit, without any indication of the violated rule. The
+//! checks are run over a `VmcsSnapshot` before the VM is launched, and every violated rule is reported
+//! with its reference in the SDM.
+//!
+//! The checks only depend on the snapshot and the VMX capabilities of the processor, so crafted snapshots
+//! can be checked outside of VMX operation. The checks specific to virtual-8086 mode, SMM, Intel PT and
+//! CET aren't implemented.
+//!
+//! Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 27.2 CHECKS ON VMX CONTROLS AND HOST-STATE AREA
+//! and 27.3 CHECKING AND LOADING GUEST STATE
+
+use {
+    crate::intel::{
+        vmcs_access::VmcsAccess,
+        vmcs_fields::{control, guest, host, Bits32, FieldInfo, ReadWrite, VmcsField},
+        vmcs_snapshot::VmcsSnapshot,
+    },
+    alloc::vec::Vec,
+    core::fmt,
+    x86::{
+        cpuid::cpuid,
+        msr,
+        vmx::vmcs::control::{
+            EntryControls, ExitControls, PinbasedControls, PrimaryControls, SecondaryControls,
+        },
+    },
+};
+
+/// The VMX capabilities of a processor that the checks depend on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmxCapabilities {
+    /// The allowed settings of the pin-based VM-execution controls (`IA32_VMX_TRUE_PINBASED_CTLS`).
+    pub pinbased_ctls: u64,
+
+    /// The allowed settings of the primary processor-based VM-execution controls (`IA32_VMX_TRUE_PROCBASED_CTLS`).
+    pub procbased_ctls: u64,
+
+    /// The allowed settings of the secondary processor-based VM-execution controls (`IA32_VMX_PROCBASED_CTLS2`).
+    pub procbased_ctls2: u64,
+
+    /// The allowed settings of the VM-exit controls (`IA32_VMX_TRUE_EXIT_CTLS`).
+    pub exit_ctls: u64,
+
+    /// The allowed settings of the VM-entry controls (`IA32_VMX_TRUE_ENTRY_CTLS`).
+    pub entry_ctls: u64,
+
+    /// The miscellaneous data (`IA32_VMX_MISC`).
+    pub misc: u64,
+
+    /// The bits of CR0 fixed to 1 (`IA32_VMX_CR0_FIXED0`).
+    pub cr0_fixed0: u64,
+
+    /// The bits of CR0 allowed to be 1 (`IA32_VMX_CR0_FIXED1`).
+    pub cr0_fixed1: u64,
+
+    /// The bits of CR4 fixed to 1 (`IA32_VMX_CR4_FIXED0`).
+    pub cr4_fixed0: u64,
+
+    /// The bits of CR4 allowed to be 1 (`IA32_VMX_CR4_FIXED1`).
+    pub cr4_fixed1: u64,
+
+    /// The EPT and VPID capabilities (`IA32_VMX_EPT_VPID_CAP`).
+    pub ept_vpid_cap: u64,
+
+    /// The physical-address width (MAXPHYADDR, `CPUID.80000008H:EAX[7:0]`).
+    pub physical_address_width: u8,
+}
+
+impl VmxCapabilities {
+    /// Reads the VMX capabilities of the current processor.
+    ///
+    /// The TRUE capability MSRs are used if they're supported (`IA32_VMX_BASIC[55]`), like in `adjust_vmx_controls`.
+    pub fn read() -> Self {
+        const IA32_VMX_BASIC_VMX_CONTROLS_FLAG: u64 = 1 << 55;
+
+        let rdmsr = |msr| unsafe { msr::rdmsr(msr) };
+        let true_cap_msr_supported =
+            rdmsr(msr::IA32_VMX_BASIC) & IA32_VMX_BASIC_VMX_CONTROLS_FLAG != 0;
+        let (pinbased, procbased, exit, entry) = match true_cap_msr_supported {
+            true => (
+                msr::IA32_VMX_TRUE_PINBASED_CTLS,
+                msr::IA32_VMX_TRUE_PROCBASED_CTLS,
+                msr::IA32_VMX_TRUE_EXIT_CTLS,
+                msr::IA32_VMX_TRUE_ENTRY_CTLS,
+            ),
+            false => (
+                msr::IA32_VMX_PINBASED_CTLS,
+                msr::IA32_VMX_PROCBASED_CTLS,
+                msr::IA32_VMX_EXIT_CTLS,
+                msr::IA32_VMX_ENTRY_CTLS,
+            ),
+        };
+
+        Self {
+            pinbased_ctls: rdmsr(pinbased),
+            procbased_ctls: rdmsr(procbased),
+            procbased_ctls2: rdmsr(msr::IA32_VMX_PROCBASED_CTLS2),
+            exit_ctls: rdmsr(exit),
+            entry_ctls: rdmsr(entry),
+            misc: rdmsr(msr::IA32_VMX_MISC),
+            cr0_fixed0: rdmsr(msr::IA32_VMX_CR0_FIXED0),
+            cr0_fixed1: rdmsr(msr::IA32_VMX_CR0_FIXED1),
+            cr4_fixed0: rdmsr(msr::IA32_VMX_CR4_FIXED0),
+            cr4_fixed1: rdmsr(msr::IA32_VMX_CR4_FIXED1),
+            ept_vpid_cap: rdmsr(msr::IA32_VMX_EPT_VPID_CAP),
+            physical_address_width: cpuid!(0x8000_0008).eax as u8,
+        }
+    }
+}
+
+/// A VM-entry check violated by a VMCS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmEntryViolation {
+    /// The section of the SDM that describes the check, for example `27.3.1.1`.
+    pub reference: &'static str,
+
+    /// The field that violates the check.
+    pub field: FieldInfo,
+
+    /// The value of the field.
+    pub value: u64,
+
+    /// The description of the check.
+    pub rule: &'static str,
+}
+
+impl fmt::Display for VmEntryViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "SDM {}: {} ({} = {:#x})",
+            self.reference, self.rule, self.field.name, self.value
+        )
+    }
+}
+
+/// Checks a VMCS against the checks performed by the processor on VM entry.
+///
+/// # Arguments
+///
+/// * `snapshot` - The snapshot of the VMCS. Fields that weren't captured read as 0.
+/// * `capabilities` - The VMX capabilities of the processor that will launch the VMCS.
+///
+/// # Returns
+///
+/// * `Vec<VmEntryViolation>` - The violated checks, in the order of the SDM. Empty if the VMCS passes all the implemented checks.
+pub fn check_vm_entry(
+    snapshot: &VmcsSnapshot,
+    capabilities: &VmxCapabilities,
+) -> Vec<VmEntryViolation> {
+    let mut checker = Checker {
+        vmcs: snapshot,
+        capabilities,
+        violations: Vec::new(),
+    };
+
+    checker.check_execution_controls();
+    checker.check_exit_controls();
+    checker.check_entry_controls();
+    checker.check_host_state();
+    checker.check_guest_control_registers();
+    checker.check_guest_segments();
+    checker.check_guest_descriptor_tables();
+    checker.check_guest_rip_and_rflags();
+    checker.check_guest_non_register_state();
+
+    checker.violations
+}
+
+const CR0_PE: u64 = 1 << 0;
+const CR0_PG: u64 = 1 << 31;
+const CR4_PAE: u64 = 1 << 5;
+const CR4_PCIDE: u64 = 1 << 17;
+
+const EFER_LME: u64 = 1 << 8;
+const EFER_LMA: u64 = 1 << 10;
+/// The bits of IA32_EFER that aren't reserved: SCE, LME, LMA and NXE.
+const EFER_DEFINED: u64 = 1 << 0 | EFER_LME | EFER_LMA | 1 << 11;
+
+const RFLAGS_TF: u64 = 1 << 8;
+const RFLAGS_IF: u64 = 1 << 9;
+const RFLAGS_VM: u64 = 1 << 17;
+/// The bits of RFLAGS that must be 0: 63:22, 15, 5 and 3.
+const RFLAGS_RESERVED: u64 = !0x3F_FFFF | 1 << 15 | 1 << 5 | 1 << 3;
+/// Bit 1 of RFLAGS, which must be 1.
+const RFLAGS_FIXED: u64 = 1 << 1;
+
+const DEBUGCTL_BTF: u64 = 1 << 1;
+/// The bits of IA32_DEBUGCTL that must be 0: 5:2 and 63:16.
+const DEBUGCTL_RESERVED: u64 = 0x3C | !0xFFFF;
+
+/// The interruption types of the VM-entry interruption-information field.
+const INTERRUPTION_TYPE_EXTERNAL_INTERRUPT: u64 = 0;
+const INTERRUPTION_TYPE_RESERVED: u64 = 1;
+const INTERRUPTION_TYPE_NMI: u64 = 2;
+const INTERRUPTION_TYPE_HARDWARE_EXCEPTION: u64 = 3;
+const INTERRUPTION_TYPE_OTHER_EVENT: u64 = 7;
+
+const INTERRUPTIBILITY_STI: u64 = 1 << 0;
+const INTERRUPTIBILITY_MOV_SS: u64 = 1 << 1;
+const INTERRUPTIBILITY_SMI: u64 = 1 << 2;
+const INTERRUPTIBILITY_NMI: u64 = 1 << 3;
+
+const ACTIVITY_STATE_ACTIVE: u64 = 0;
+const ACTIVITY_STATE_HLT: u64 = 1;
+
+/// The BS flag of the pending debug exceptions.
+const PENDING_DEBUG_BS: u64 = 1 << 14;
+/// The bits of the pending debug exceptions that aren't reserved: B3-B0, enabled breakpoint, BS and RTM.
+const PENDING_DEBUG_DEFINED: u64 = 0xF | 1 << 12 | PENDING_DEBUG_BS | 1 << 16;
+
+/// A segment register of the guest-state area.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: Table 25-2. Format of Access Rights
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    selector: u64,
+    base: u64,
+    limit: u64,
+    access_rights: u64,
+
+    /// The selector, base and access rights fields, to report the violations.
+    selector_field: FieldInfo,
+    base_field: FieldInfo,
+    access_rights_field: FieldInfo,
+}
+
+impl Segment {
+    /// The bits of the access rights that must be 0: 11:8 and 31:17.
+    const RESERVED: u64 = 0xF00 | 0xFFFE_0000;
+
+    fn rpl(&self) -> u64 {
+        self.selector & 0b11
+    }
+
+    fn ti(&self) -> bool {
+        self.selector & 0b100 != 0
+    }
+
+    fn segment_type(&self) -> u64 {
+        self.access_rights & 0xF
+    }
+
+    fn s(&self) -> bool {
+        self.access_rights & (1 << 4) != 0
+    }
+
+    fn dpl(&self) -> u64 {
+        (self.access_rights >> 5) & 0b11
+    }
+
+    fn present(&self) -> bool {
+        self.access_rights & (1 << 7) != 0
+    }
+
+    fn long_mode(&self) -> bool {
+        self.access_rights & (1 << 13) != 0
+    }
+
+    fn default_big(&self) -> bool {
+        self.access_rights & (1 << 14) != 0
+    }
+
+    fn granularity(&self) -> bool {
+        self.access_rights & (1 << 15) != 0
+    }
+
+    fn usable(&self) -> bool {
+        self.access_rights & (1 << 16) == 0
+    }
+
+    /// Checks the granularity against the limit: G must be 0 if any of the bits 11:0 of the limit is
+    /// 0, and 1 if any of the bits 31:20 is 1.
+    fn granularity_matches_limit(&self) -> bool {
+        (self.limit & 0xFFF == 0xFFF || !self.granularity())
+            && (self.limit >> 20 == 0 || self.granularity())
+    }
+}
+
+/// Runs the checks over a snapshot and collects the violations.
+struct Checker<'a> {
+    vmcs: &'a VmcsSnapshot,
+    capabilities: &'a VmxCapabilities,
+    violations: Vec<VmEntryViolation>,
+}
+
+impl Checker<'_> {
+    /// Reads a field of the snapshot, 0 if it wasn't captured.
+    fn read(&self, field: impl Into<FieldInfo>) -> u64 {
+        self.vmcs.read_field(&field.into()).unwrap_or(0)
+    }
+
+    /// Records a violation of a check if its condition doesn't hold.
+    fn require(
+        &mut self,
+        condition: bool,
+        reference: &'static str,
+        field: impl Into<FieldInfo>,
+        rule: &'static str,
+    ) {
+        let field = field.into();
+
+        if !condition {
+            self.violations.push(VmEntryViolation {
+                reference,
+                field,
+                value: self.read(field),
+                rule,
+            });
+        }
+    }
+
+    /// Checks a control field against its allowed 0-settings (bits 31:0) and allowed 1-settings (bits 63:32).
+    fn require_allowed_controls(
+        &mut self,
+        field: impl Into<FieldInfo>,
+        capability: u64,
+        reference: &'static str,
+        rule: &'static str,
+    ) {
+        let field = field.into();
+        let value = self.read(field);
+        let allowed0 = capability & 0xFFFF_FFFF;
+        let allowed1 = capability >> 32;
+
+        self.require(
+            value & allowed0 == allowed0 && value & !allowed1 == 0,
+            reference,
+            field,
+            rule,
+        );
+    }
+
+    /// Checks that an address field is aligned and within the physical-address width.
+    fn require_address(
+        &mut self,
+        field: impl Into<FieldInfo>,
+        alignment: u64,
+        reference: &'static str,
+        rule: &'static str,
+    ) {
+        let field = field.into();
+        let address = self.read(field);
+
+        self.require(
+            address & (alignment - 1) == 0 && self.is_physical_address(address),
+            reference,
+            field,
+            rule,
+        );
+    }
+
+    /// Checks whether an address is within the physical-address width.
+    fn is_physical_address(&self, address: u64) -> bool {
+        address >> self.capabilities.physical_address_width == 0
+    }
+
+    fn pinbased_controls(&self) -> PinbasedControls {
+        PinbasedControls::from_bits_truncate(self.read(control::PINBASED_EXEC_CONTROLS) as u32)
+    }
+
+    fn primary_controls(&self) -> PrimaryControls {
+        PrimaryControls::from_bits_truncate(
+            self.read(control::PRIMARY_PROCBASED_EXEC_CONTROLS) as u32
+        )
+    }
+
+    /// Returns the secondary processor-based controls, which are all 0 if they aren't activated.
+    fn secondary_controls(&self) -> SecondaryControls {
+        match self
+            .primary_controls()
+            .contains(PrimaryControls::SECONDARY_CONTROLS)
+        {
+            true => SecondaryControls::from_bits_truncate(
+                self.read(control::SECONDARY_PROCBASED_EXEC_CONTROLS) as u32,
+            ),
+            false => SecondaryControls::empty(),
+        }
+    }
+
+    fn exit_controls(&self) -> ExitControls {
+        ExitControls::from_bits_truncate(self.read(control::VMEXIT_CONTROLS) as u32)
+    }
+
+    fn entry_controls(&self) -> EntryControls {
+        EntryControls::from_bits_truncate(self.read(control::VMENTRY_CONTROLS) as u32)
+    }
+
+    fn unrestricted_guest(&self) -> bool {
+        self.secondary_controls()
+            .contains(SecondaryControls::UNRESTRICTED_GUEST)
+    }
+
+    fn ia32e_mode_guest(&self) -> bool {
+        self.entry_controls()
+            .contains(EntryControls::IA32E_MODE_GUEST)
+    }
+
+    fn host_address_space_size(&self) -> bool {
+        self.exit_controls()
+            .contains(ExitControls::HOST_ADDRESS_SPACE_SIZE)
+    }
+
+    /// Returns the interruption type of the injected event, or `None` if no event is injected.
+    fn injected_event_type(&self) -> Option<u64> {
+        let information = self.read(control::VMENTRY_INTERRUPTION_INFO_FIELD);
+
+        (information & (1 << 31) != 0).then_some((information >> 8) & 0b111)
+    }
+
+    fn guest_segment(
+        &self,
+        selector_field: impl Into<FieldInfo>,
+        base_field: impl Into<FieldInfo>,
+        limit_field: impl Into<FieldInfo>,
+        access_rights_field: impl Into<FieldInfo>,
+    ) -> Segment {
+        let selector_field = selector_field.into();
+        let base_field = base_field.into();
+        let access_rights_field = access_rights_field.into();
+
+        Segment {
+            selector: self.read(selector_field),
+            base: self.read(base_field),
+            limit: self.read(limit_field),
+            access_rights: self.read(access_rights_field),
+            selector_field,
+            base_field,
+            access_rights_field,
+        }
+    }
+
+    /// Intel® 64 and IA-32 Architectures Software Developer's Manual: 27.2.1.1 VM-Execution Control Fields
+    fn check_execution_controls(&mut self) {
+        const REFERENCE: &str = "27.2.1.1";
+
+        let capabilities = *self.capabilities;
+
+        self.require_allowed_controls(
+            control::PINBASED_EXEC_CONTROLS,
+            capabilities.pinbased_ctls,
+            REFERENCE,
+            "The pin-based VM-execution controls must respect their allowed settings",
+        );
+        self.require_allowed_controls(
+            control::PRIMARY_PROCBASED_EXEC_CONTROLS,
+            capabilities.procbased_ctls,
+            REFERENCE,
+            "The primary processor-based VM-execution controls must respect their allowed settings",
+        );
+
+        let pinbased = self.pinbased_controls();
+        let primary = self.primary_controls();
+        let secondary = self.secondary_controls();
+
+        if primary.contains(PrimaryControls::SECONDARY_CONTROLS) {
+            self.require_allowed_controls(
+                control::SECONDARY_PROCBASED_EXEC_CONTROLS,
+                capabilities.procbased_ctls2,
+                REFERENCE,
+                "The secondary processor-based VM-execution controls must respect their allowed settings",
+            );
+        }
+
+        self.require(
+            self.read(control::CR3_TARGET_COUNT) <= (capabilities.misc >> 16) & 0x1FF,
+            REFERENCE,
+            control::CR3_TARGET_COUNT,
+            "The CR3-target count must not be greater than the number of CR3-target values supported",
+        );
+
+        if primary.contains(PrimaryControls::USE_IO_BITMAPS) {
+            for field in [
+                control::IO_BITMAP_A_ADDR_FULL,
+                control::IO_BITMAP_B_ADDR_FULL,
+            ] {
+                self.require_address(
+                    field,
+                    0x1000,
+                    REFERENCE,
+                    "The addresses of the I/O bitmaps must be 4-KByte aligned and within the physical-address width",
+                );
+            }
+        }
+
+        if primary.contains(PrimaryControls::USE_MSR_BITMAPS) {
+            self.require_address(
+                control::MSR_BITMAPS_ADDR_FULL,
+                0x1000,
+                REFERENCE,
+                "The address of the MSR bitmaps must be 4-KByte aligned and within the physical-address width",
+            );
+        }
+
+        if primary.contains(PrimaryControls::USE_TPR_SHADOW) {
+            self.require_address(
+                control::VIRT_APIC_ADDR_FULL,
+                0x1000,
+                REFERENCE,
+                "The virtual-APIC address must be 4-KByte aligned and within the physical-address width",
+            );
+        } else {
+            self.require(
+                !secondary.intersects(
+                    SecondaryControls::VIRTUALIZE_X2APIC
+                        | SecondaryControls::VIRTUALIZE_APIC_REGISTER
+                        | SecondaryControls::VIRTUAL_INTERRUPT_DELIVERY,
+                ),
+                REFERENCE,
+                control::SECONDARY_PROCBASED_EXEC_CONTROLS,
+                "Virtualize x2APIC mode, APIC-register virtualization and virtual-interrupt delivery require use TPR shadow",
+            );
+        }
+
+        self.require(
+            pinbased.contains(PinbasedControls::NMI_EXITING)
+                || !pinbased.contains(PinbasedControls::VIRTUAL_NMIS),
+            REFERENCE,
+            control::PINBASED_EXEC_CONTROLS,
+            "Virtual NMIs require NMI exiting",
+        );
+        self.require(
+            pinbased.contains(PinbasedControls::VIRTUAL_NMIS)
+                || !primary.contains(PrimaryControls::NMI_WINDOW_EXITING),
+            REFERENCE,
+            control::PRIMARY_PROCBASED_EXEC_CONTROLS,
+            "NMI-window exiting requires virtual NMIs",
+        );
+
+        if secondary.contains(SecondaryControls::VIRTUALIZE_APIC) {
+            self.require_address(
+                control::APIC_ACCESS_ADDR_FULL,
+                0x1000,
+                REFERENCE,
+                "The APIC-access address must be 4-KByte aligned and within the physical-address width",
+            );
+        }
+
+        self.require(
+            !secondary.contains(SecondaryControls::VIRTUAL_INTERRUPT_DELIVERY)
+                || pinbased.contains(PinbasedControls::EXTERNAL_INTERRUPT_EXITING),
+            REFERENCE,
+            control::PINBASED_EXEC_CONTROLS,
+            "Virtual-interrupt delivery requires external-interrupt exiting",
+        );
+
+        if pinbased.contains(PinbasedControls::POSTED_INTERRUPTS) {
+            self.require(
+                secondary.contains(SecondaryControls::VIRTUAL_INTERRUPT_DELIVERY),
+                REFERENCE,
+                control::SECONDARY_PROCBASED_EXEC_CONTROLS,
+                "Process posted interrupts requires virtual-interrupt delivery",
+            );
+            self.require(
+                self.exit_controls()
+                    .contains(ExitControls::ACK_INTERRUPT_ON_EXIT),
+                REFERENCE,
+                control::VMEXIT_CONTROLS,
+                "Process posted interrupts requires acknowledge interrupt on exit",
+            );
+            self.require_address(
+                control::POSTED_INTERRUPT_DESC_ADDR_FULL,
+                64,
+                REFERENCE,
+                "The posted-interrupt descriptor address must be 64-byte aligned and within the physical-address width",
+            );
+        }
+
+        if secondary.contains(SecondaryControls::ENABLE_VPID) {
+            self.require(
+                self.read(control::VPID) != 0,
+                REFERENCE,
+                control::VPID,
+                "The VPID must not be 0 if enable VPID is 1",
+            );
+        }
+
+        if secondary.contains(SecondaryControls::ENABLE_EPT) {
+            self.check_eptp();
+        }
+
+        if secondary.contains(SecondaryControls::ENABLE_PML) {
+            self.require(
+                secondary.contains(SecondaryControls::ENABLE_EPT),
+                REFERENCE,
+                control::SECONDARY_PROCBASED_EXEC_CONTROLS,
+                "Enable PML requires enable EPT",
+            );
+            self.require_address(
+                control::PML_ADDR_FULL,
+                0x1000,
+                REFERENCE,
+                "The PML address must be 4-KByte aligned and within the physical-address width",
+            );
+        }
+
+        for (required, rule) in [
+            (
+                SecondaryControls::UNRESTRICTED_GUEST,
+                "Unrestricted guest requires enable EPT",
+            ),
+            (
+                SecondaryControls::MODE_BASED_EPT,
+                "Mode-based execute control for EPT requires enable EPT",
+            ),
+        ] {
+            self.require(
+                !secondary.contains(required) || secondary.contains(SecondaryControls::ENABLE_EPT),
+                REFERENCE,
+                control::SECONDARY_PROCBASED_EXEC_CONTROLS,
+                rule,
+            );
+        }
+
+        if secondary.contains(SecondaryControls::VMCS_SHADOWING) {
+            for field in [
+                control::VMREAD_BITMAP_ADDR_FULL,
+                control::VMWRITE_BITMAP_ADDR_FULL,
+            ] {
+                self.require_address(
+                    field,
+                    0x1000,
+                    REFERENCE,
+                    "The VMREAD and VMWRITE bitmap addresses must be 4-KByte aligned and within the physical-address width",
+                );
+            }
+        }
+    }
+
+    /// Checks the EPT pointer against the EPT capabilities of the processor.
+    ///
+    /// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 27.2.1.1 VM-Execution Control Fields
+    /// and Table 25-9. Format of Extended-Page-Table Pointer
+    fn check_eptp(&mut self) {
+        const REFERENCE: &str = "27.2.1.1";
+
+        const EPT_PAGE_WALK_4: u64 = 1 << 6;
+        const EPT_PAGE_WALK_5: u64 = 1 << 7;
+        const EPT_MEMORY_TYPE_UC: u64 = 1 << 8;
+        const EPT_MEMORY_TYPE_WB: u64 = 1 << 14;
+        const EPT_ACCESSED_DIRTY: u64 = 1 << 21;
+
+        let eptp = self.read(control::EPTP_FULL);
+        let capability = self.capabilities.ept_vpid_cap;
+
+        let memory_type = eptp & 0b111;
+        self.require(
+            (memory_type == 0 && capability & EPT_MEMORY_TYPE_UC != 0)
+                || (memory_type == 6 && capability & EPT_MEMORY_TYPE_WB != 0),
+            REFERENCE,
+            control::EPTP_FULL,
+            "The EPT paging-structure memory type must be UC or WB, and supported",
+        );
+
+        let page_walk_length = (eptp >> 3) & 0b111;
+        self.require(
+            (page_walk_length == 3 && capability & EPT_PAGE_WALK_4 != 0)
+                || (page_walk_length == 4 && capability & EPT_PAGE_WALK_5 != 0),
+            REFERENCE,
+            control::EPTP_FULL,
+            "The EPT page-walk length minus 1 must be 3 or 4, and supported",
+        );
+
+        self.require(
+            eptp & (1 << 6) == 0 || capability & EPT_ACCESSED_DIRTY != 0,
+            REFERENCE,
+            control::EPTP_FULL,
+            "The accessed and dirty flags for EPT must be supported if they're enabled",
+        );
+
+        self.require(
+            eptp & 0xF00 == 0 && self.is_physical_address(eptp),
+            REFERENCE,
+            control::EPTP_FULL,
+            "Bits 11:8 of the EPT pointer and the bits beyond the physical-address width must be 0",
+        );
+    }
+
+    /// Intel® 64 and IA-32 Architectures Software Developer's Manual: 27.2.1.2 VM-Exit Control Fields
+    fn check_exit_controls(&mut self) {
+        const REFERENCE: &str = "27.2.1.2";
+
+        self.require_allowed_controls(
+            control::VMEXIT_CONTROLS,
+            self.capabilities.exit_ctls,
+            REFERENCE,
+            "The VM-exit controls must respect their allowed settings",
+        );
+
+        self.require(
+            self.pinbased_controls()
+                .contains(PinbasedControls::VMX_PREEMPTION_TIMER)
+                || !self
+                    .exit_controls()
+                    .contains(ExitControls::SAVE_VMX_PREEMPTION_TIMER),
+            REFERENCE,
+            control::VMEXIT_CONTROLS,
+            "Save VMX-preemption timer value requires activate VMX-preemption timer",
+        );
+
+        for (count, address) in [
+            (
+                control::VMEXIT_MSR_STORE_COUNT,
+                control::VMEXIT_MSR_STORE_ADDR_FULL,
+            ),
+            (
+                control::VMEXIT_MSR_LOAD_COUNT,
+                control::VMEXIT_MSR_LOAD_ADDR_FULL,
+            ),
+        ] {
+            if self.read(count) != 0 {
+                self.require_address(
+                    address,
+                    16,
+                    REFERENCE,
+                    "The VM-exit MSR-store and MSR-load addresses must be 16-byte aligned and within the physical-address width",
+                );
+            }
+        }
+    }
+
+    /// Intel® 64 and IA-32 Architectures Software Developer's Manual: 27.2.1.3 VM-Entry Control Fields
+    fn check_entry_controls(&mut self) {
+        const REFERENCE: &str = "27.2.1.3";
+
+        self.require_allowed_controls(
+            control::VMENTRY_CONTROLS,
+            self.capabilities.entry_ctls,
+            REFERENCE,
+            "The VM-entry controls must respect their allowed settings",
+        );
+
+        self.require(
+            !self
+                .entry_controls()
+                .intersects(EntryControls::ENTRY_TO_SMM | EntryControls::DEACTIVATE_DUAL_MONITOR),
+            REFERENCE,
+            control::VMENTRY_CONTROLS,
+            "Entry to SMM and deactivate dual-monitor treatment must be 0 outside of SMM",
+        );
+
+        if self.read(control::VMENTRY_MSR_LOAD_COUNT) != 0 {
+            self.require_address(
+                control::VMENTRY_MSR_LOAD_ADDR_FULL,
+                16,
+                REFERENCE,
+                "The VM-entry MSR-load address must be 16-byte aligned and within the physical-address width",
+            );
+        }
+
+        self.check_event_injection();
+    }
+
+    /// Checks the event injected on VM entry.
+    ///
+    /// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 27.2.1.3 VM-Entry Control Fields
+    /// and Table 25-17. Format of the VM-Entry Interruption-Information Field
+    fn check_event_injection(&mut self) {
+        const REFERENCE: &str = "27.2.1.3";
+        const FIELD: VmcsField<Bits32, ReadWrite> = control::VMENTRY_INTERRUPTION_INFO_FIELD;
+
+        let Some(event_type) = self.injected_event_type() else {
+            return;
+        };
+
+        let information = self.read(FIELD);
+        let vector = information & 0xFF;
+        let deliver_error_code = information & (1 << 11) != 0;
+        let monitor_trap_flag_supported = (self.capabilities.procbased_ctls >> 32)
+            & PrimaryControls::MONITOR_TRAP_FLAG.bits() as u64
+            != 0;
+
+        self.require(
+            event_type != INTERRUPTION_TYPE_RESERVED,
+            REFERENCE,
+            FIELD,
+            "The interruption type must not be 1 (reserved)",
+        );
+        self.require(
+            event_type != INTERRUPTION_TYPE_OTHER_EVENT || monitor_trap_flag_supported,
+            REFERENCE,
+            FIELD,
+            "The interruption type 7 (other event) requires support for the monitor trap flag",
+        );
+        self.require(
+            event_type != INTERRUPTION_TYPE_NMI || vector == 2,
+            REFERENCE,
+            FIELD,
+            "The vector of an NMI must be 2",
+        );
+        self.require(
+            event_type != INTERRUPTION_TYPE_HARDWARE_EXCEPTION || vector <= 31,
+            REFERENCE,
+            FIELD,
+            "The vector of a hardware exception must not be greater than 31",
+        );
+        self.require(
+            event_type != INTERRUPTION_TYPE_OTHER_EVENT || vector == 0,
+            REFERENCE,
+            FIELD,
+            "The vector of an other event must be 0",
+        );
+
+        // Only the #DF, #TS, #NP, #SS, #GP, #PF, #AC and #CP exceptions push an error code, and only in protected mode.
+        let protected_mode = !self.unrestricted_guest() || self.read(guest::CR0) & CR0_PE != 0;
+        let has_error_code = protected_mode
+            && event_type == INTERRUPTION_TYPE_HARDWARE_EXCEPTION
+            && matches!(vector, 8 | 10..=14 | 17 | 21);
+
+        self.require(
+            deliver_error_code == has_error_code,
+            REFERENCE,
+            FIELD,
+            "Deliver error code must be 1 exactly for the hardware exceptions that push an error code in protected mode",
+        );
+        self.require(
+            information & 0x7FFF_F000 == 0,
+            REFERENCE,
+            FIELD,
+            "Bits 30:12 of the VM-entry interruption-information field must be 0",
+        );
+
+        if deliver_error_code {
+            self.require(
+                self.read(control::VMENTRY_EXCEPTION_ERR_CODE) >> 16 == 0,
+                REFERENCE,
+                control::VMENTRY_EXCEPTION_ERR_CODE,
+                "Bits 31:16 of the VM-entry exception error code must be 0",
+            );
+        }
+
+        // Software interrupts, privileged software exceptions and software exceptions.
+        if matches!(event_type, 4..=6) {
+            let length = self.read(control::VMENTRY_INSTRUCTION_LEN);
+            let zero_length_supported = self.capabilities.misc & (1 << 30) != 0;
+
+            self.require(
+                (1..=15).contains(&length) || (length == 0 && zero_length_supported),
+                REFERENCE,
+                control::VMENTRY_INSTRUCTION_LEN,
+                "The VM-entry instruction length of a software interrupt or exception must be in the range 1-15",
+            );
+        }
+    }
+
+    /// Intel® 64 and IA-32 Architectures Software Developer's Manual: 27.2.2 Checks on Host Control Registers, MSRs, and SSP,
+    /// 27.2.3 Checks on Host Segment and Descriptor-Table Registers and 27.2.4 Checks Related to Address-Space Size
+    ///
+    /// The host always runs in IA-32e mode, so only the checks of a 64-bit host are implemented.
+    fn check_host_state(&mut self) {
+        const CONTROL_REGISTERS: &str = "27.2.2";
+        const SEGMENTS: &str = "27.2.3";
+        const ADDRESS_SPACE_SIZE: &str = "27.2.4";
+
+        let capabilities = *self.capabilities;
+        let cr4 = self.read(host::CR4);
+
+        self.require(
+            is_fixed(
+                self.read(host::CR0),
+                capabilities.cr0_fixed0,
+                capabilities.cr0_fixed1,
+            ),
+            CONTROL_REGISTERS,
+            host::CR0,
+            "The host CR0 must respect IA32_VMX_CR0_FIXED0 and IA32_VMX_CR0_FIXED1",
+        );
+        self.require(
+            is_fixed(cr4, capabilities.cr4_fixed0, capabilities.cr4_fixed1),
+            CONTROL_REGISTERS,
+            host::CR4,
+            "The host CR4 must respect IA32_VMX_CR4_FIXED0 and IA32_VMX_CR4_FIXED1",
+        );
+        self.require(
+            self.is_physical_address(self.read(host::CR3)),
+            CONTROL_REGISTERS,
+            host::CR3,
+            "The bits of the host CR3 beyond the physical-address width must be 0",
+        );
+
+        for field in [host::IA32_SYSENTER_ESP, host::IA32_SYSENTER_EIP] {
+            self.require(
+                is_canonical(self.read(field)),
+                CONTROL_REGISTERS,
+                field,
+                "The host IA32_SYSENTER_ESP and IA32_SYSENTER_EIP must be canonical",
+            );
+        }
+
+        if self.exit_controls().contains(ExitControls::LOAD_IA32_PAT) {
+            self.require(
+                is_valid_pat(self.read(host::IA32_PAT_FULL)),
+                CONTROL_REGISTERS,
+                host::IA32_PAT_FULL,
+                "The memory types of the host IA32_PAT must be valid",
+            );
+        }
+
+        if self.exit_controls().contains(ExitControls::LOAD_IA32_EFER) {
+            let efer = self.read(host::IA32_EFER_FULL);
+            let long_mode = self.host_address_space_size();
+
+            self.require(
+                efer & !EFER_DEFINED == 0,
+                CONTROL_REGISTERS,
+                host::IA32_EFER_FULL,
+                "The reserved bits of the host IA32_EFER must be 0",
+            );
+            self.require(
+                (efer & EFER_LMA != 0) == long_mode && (efer & EFER_LME != 0) == long_mode,
+                CONTROL_REGISTERS,
+                host::IA32_EFER_FULL,
+                "IA32_EFER.LMA and IA32_EFER.LME of the host must equal host address-space size",
+            );
+        }
+
+        for field in [
+            host::ES_SELECTOR,
+            host::CS_SELECTOR,
+            host::SS_SELECTOR,
+            host::DS_SELECTOR,
+            host::FS_SELECTOR,
+            host::GS_SELECTOR,
+            host::TR_SELECTOR,
+        ] {
+            self.require(
+                self.read(field) & 0b111 == 0,
+                SEGMENTS,
+                field,
+                "The RPL and TI flag of the host selectors must be 0",
+            );
+        }
+
+        self.require(
+            self.read(host::CS_SELECTOR) != 0,
+            SEGMENTS,
+            host::CS_SELECTOR,
+            "The host CS selector must not be 0",
+        );
+        self.require(
+            self.read(host::TR_SELECTOR) != 0,
+            SEGMENTS,
+            host::TR_SELECTOR,
+            "The host TR selector must not be 0",
+        );
+
+        for field in [
+            host::FS_BASE,
+            host::GS_BASE,
+            host::GDTR_BASE,
+            host::IDTR_BASE,
+            host::TR_BASE,
+        ] {
+            self.require(
+                is_canonical(self.read(field)),
+                SEGMENTS,
+                field,
+                "The host FS, GS, GDTR, IDTR and TR bases must be canonical",
+            );
+        }
+
+        self.require(
+            self.host_address_space_size(),
+            ADDRESS_SPACE_SIZE,
+            control::VMEXIT_CONTROLS,
+            "Host address-space size must be 1 if the processor is in IA-32e mode",
+        );
+        self.require(
+            cr4 & CR4_PAE != 0,
+            ADDRESS_SPACE_SIZE,
+            host::CR4,
+            "The host CR4.PAE must be 1 if host address-space size is 1",
+        );
+        self.require(
+            is_canonical(self.read(host::RIP)),
+            ADDRESS_SPACE_SIZE,
+            host::RIP,
+            "The host RIP must be canonical if host address-space size is 1",
+        );
+    }
+
+    /// Intel® 64 and IA-32 Architectures Software Developer's Manual: 27.3.1.1 Checks on Guest Control Registers, Debug Registers, and MSRs
+    fn check_guest_control_registers(&mut self) {
+        const REFERENCE: &str = "27.3.1.1";
+
+        let capabilities = *self.capabilities;
+        let entry_controls = self.entry_controls();
+        let ia32e_mode_guest = self.ia32e_mode_guest();
+        let cr0 = self.read(guest::CR0);
+        let cr4 = self.read(guest::CR4);
+
+        // An unrestricted guest can run with protection and paging disabled.
+        let cr0_fixed0 = match self.unrestricted_guest() {
+            true => capabilities.cr0_fixed0 & !(CR0_PE | CR0_PG),
+            false => capabilities.cr0_fixed0,
+        };
+
+        self.require(
+            is_fixed(cr0, cr0_fixed0, capabilities.cr0_fixed1),
+            REFERENCE,
+            guest::CR0,
+            "The guest CR0 must respect IA32_VMX_CR0_FIXED0 and IA32_VMX_CR0_FIXED1",
+        );
+        self.require(
+            cr0 & CR0_PG == 0 || cr0 & CR0_PE != 0,
+            REFERENCE,
+            guest::CR0,
+            "CR0.PE must be 1 if CR0.PG is 1",
+        );
+        self.require(
+            is_fixed(cr4, capabilities.cr4_fixed0, capabilities.cr4_fixed1),
+            REFERENCE,
+            guest::CR4,
+            "The guest CR4 must respect IA32_VMX_CR4_FIXED0 and IA32_VMX_CR4_FIXED1",
+        );
+
+        if entry_controls.contains(EntryControls::LOAD_DEBUG_CONTROLS) {
+            self.require(
+                self.read(guest::IA32_DEBUGCTL_FULL) & DEBUGCTL_RESERVED == 0,
+                REFERENCE,
+                guest::IA32_DEBUGCTL_FULL,
+                "The reserved bits of the guest IA32_DEBUGCTL must be 0",
+            );
+            self.require(
+                self.read(guest::DR7) >> 32 == 0,
+                REFERENCE,
+                guest::DR7,
+                "Bits 63:32 of the guest DR7 must be 0",
+            );
+        }
+
+        if ia32e_mode_guest {
+            self.require(
+                cr0 & CR0_PG != 0,
+                REFERENCE,
+                guest::CR0,
+                "CR0.PG must be 1 in an IA-32e mode guest",
+            );
+            self.require(
+                cr4 & CR4_PAE != 0,
+                REFERENCE,
+                guest::CR4,
+                "CR4.PAE must be 1 in an IA-32e mode guest",
+            );
+        } else {
+            self.require(
+                cr4 & CR4_PCIDE == 0,
+                REFERENCE,
+                guest::CR4,
+                "CR4.PCIDE must be 0 outside of an IA-32e mode guest",
+            );
+        }
+
+        self.require(
+            self.is_physical_address(self.read(guest::CR3)),
+            REFERENCE,
+            guest::CR3,
+            "The bits of the guest CR3 beyond the physical-address width must be 0",
+        );
+
+        for field in [guest::IA32_SYSENTER_ESP, guest::IA32_SYSENTER_EIP] {
+            self.require(
+                is_canonical(self.read(field)),
+                REFERENCE,
+                field,
+                "The guest IA32_SYSENTER_ESP and IA32_SYSENTER_EIP must be canonical",
+            );
+        }
+
+        if entry_controls.contains(EntryControls::LOAD_IA32_PAT) {
+            self.require(
+                is_valid_pat(self.read(guest::IA32_PAT_FULL)),
+                REFERENCE,
+                guest::IA32_PAT_FULL,
+                "The memory types of the guest IA32_PAT must be valid",
+            );
+        }
+
+        if entry_controls.contains(EntryControls::LOAD_IA32_EFER) {
+            let efer = self.read(guest::IA32_EFER_FULL);
+
+            self.require(
+                efer & !EFER_DEFINED == 0,
+                REFERENCE,
+                guest::IA32_EFER_FULL,
+                "The reserved bits of the guest IA32_EFER must be 0",
+            );
+            self.require(
+                (efer & EFER_LMA != 0) == ia32e_mode_guest,
+                REFERENCE,
+                guest::IA32_EFER_FULL,
+                "IA32_EFER.LMA of the guest must equal IA-32e mode guest",
+            );
+            self.require(
+                cr0 & CR0_PG == 0 || (efer & EFER_LMA != 0) == (efer & EFER_LME != 0),
+                REFERENCE,
+                guest::IA32_EFER_FULL,
+                "IA32_EFER.LMA of the guest must equal IA32_EFER.LME if CR0.PG is 1",
+            );
+        }
+    }
+
+    /// Intel® 64 and IA-32 Architectures Software Developer's Manual: 27.3.1.2 Checks on Guest Segment Registers
+    ///
+    /// The checks of virtual-8086 mode aren't implemented, so the segments aren't checked if RFLAGS.VM is 1.
+    fn check_guest_segments(&mut self) {
+        const REFERENCE: &str = "27.3.1.2";
+
+        if self.read(guest::RFLAGS) & RFLAGS_VM != 0 {
+            return;
+        }
+
+        let unrestricted_guest = self.unrestricted_guest();
+        let ia32e_mode_guest = self.ia32e_mode_guest();
+        let protected_mode = self.read(guest::CR0) & CR0_PE != 0;
+
+        let cs = self.guest_segment(
+            guest::CS_SELECTOR,
+            guest::CS_BASE,
+            guest::CS_LIMIT,
+            guest::CS_ACCESS_RIGHTS,
+        );
+        let ss = self.guest_segment(
+            guest::SS_SELECTOR,
+            guest::SS_BASE,
+            guest::SS_LIMIT,
+            guest::SS_ACCESS_RIGHTS,
+        );
+        let ds = self.guest_segment(
+            guest::DS_SELECTOR,
+            guest::DS_BASE,
+            guest::DS_LIMIT,
+            guest::DS_ACCESS_RIGHTS,
+        );
+        let es = self.guest_segment(
+            guest::ES_SELECTOR,
+            guest::ES_BASE,
+            guest::ES_LIMIT,
+            guest::ES_ACCESS_RIGHTS,
+        );
+        let fs = self.guest_segment(
+            guest::FS_SELECTOR,
+            guest::FS_BASE,
+            guest::FS_LIMIT,
+            guest::FS_ACCESS_RIGHTS,
+        );
+        let gs = self.guest_segment(
+            guest::GS_SELECTOR,
+            guest::GS_BASE,
+            guest::GS_LIMIT,
+            guest::GS_ACCESS_RIGHTS,
+        );
+        let tr = self.guest_segment(
+            guest::TR_SELECTOR,
+            guest::TR_BASE,
+            guest::TR_LIMIT,
+            guest::TR_ACCESS_RIGHTS,
+        );
+        let ldtr = self.guest_segment(
+            guest::LDTR_SELECTOR,
+            guest::LDTR_BASE,
+            guest::LDTR_LIMIT,
+            guest::LDTR_ACCESS_RIGHTS,
+        );
+
+        // Selector fields.
+        self.require(
+            !tr.ti(),
+            REFERENCE,
+            tr.selector_field,
+            "The TI flag of the guest TR selector must be 0",
+        );
+        self.require(
+            !ldtr.usable() || !ldtr.ti(),
+            REFERENCE,
+            ldtr.selector_field,
+            "The TI flag of the guest LDTR selector must be 0 if LDTR is usable",
+        );
+        self.require(
+            unrestricted_guest || ss.rpl() == cs.rpl(),
+            REFERENCE,
+            ss.selector_field,
+            "The RPL of the guest SS selector must equal the RPL of the CS selector",
+        );
+
+        // Base-address fields.
+        for segment in [tr, fs, gs] {
+            self.require(
+                is_canonical(segment.base),
+                REFERENCE,
+                segment.base_field,
+                "The guest TR, FS and GS bases must be canonical",
+            );
+        }
+        self.require(
+            !ldtr.usable() || is_canonical(ldtr.base),
+            REFERENCE,
+            ldtr.base_field,
+            "The guest LDTR base must be canonical if LDTR is usable",
+        );
+        self.require(
+            cs.base >> 32 == 0,
+            REFERENCE,
+            cs.base_field,
+            "Bits 63:32 of the guest CS base must be 0",
+        );
+        for segment in [ss, ds, es] {
+            self.require(
+                !segment.usable() || segment.base >> 32 == 0,
+                REFERENCE,
+                segment.base_field,
+                "Bits 63:32 of the guest SS, DS and ES bases must be 0 if they're usable",
+            );
+        }
+
+        // Access-rights fields of CS.
+        let cs_type = cs.segment_type();
+
+        self.require(
+            matches!(cs_type, 9 | 11 | 13 | 15) || (unrestricted_guest && cs_type == 3),
+            REFERENCE,
+            cs.access_rights_field,
+            "The type of the guest CS must be an accessed code segment, or an accessed read/write data segment in an unrestricted guest",
+        );
+        self.require(
+            cs.s(),
+            REFERENCE,
+            cs.access_rights_field,
+            "The guest CS must be a code or data segment",
+        );
+        match cs_type {
+            3 => self.require(
+                cs.dpl() == 0,
+                REFERENCE,
+                cs.access_rights_field,
+                "The DPL of the guest CS must be 0 if its type is 3",
+            ),
+            9 | 11 => self.require(
+                cs.dpl() == ss.dpl(),
+                REFERENCE,
+                cs.access_rights_field,
+                "The DPL of a non-conforming guest CS must equal the DPL of SS",
+            ),
+            13 | 15 => self.require(
+                cs.dpl() <= ss.dpl(),
+                REFERENCE,
+                cs.access_rights_field,
+                "The DPL of a conforming guest CS must not be greater than the DPL of SS",
+            ),
+            _ => {}
+        }
+        self.require(
+            !ia32e_mode_guest || !cs.long_mode() || !cs.default_big(),
+            REFERENCE,
+            cs.access_rights_field,
+            "The D/B flag of the guest CS must be 0 if its L flag is 1 in an IA-32e mode guest",
+        );
+
+        // Access-rights fields of SS.
+        if ss.usable() {
+            self.require(
+                matches!(ss.segment_type(), 3 | 7),
+                REFERENCE,
+                ss.access_rights_field,
+                "The type of the guest SS must be a read/write data segment",
+            );
+            self.require(
+                ss.s(),
+                REFERENCE,
+                ss.access_rights_field,
+                "The guest SS must be a code or data segment",
+            );
+        }
+        self.require(
+            unrestricted_guest || ss.dpl() == ss.rpl(),
+            REFERENCE,
+            ss.access_rights_field,
+            "The DPL of the guest SS must equal the RPL of its selector",
+        );
+        self.require(
+            (cs_type != 3 && protected_mode) || ss.dpl() == 0,
+            REFERENCE,
+            ss.access_rights_field,
+            "The DPL of the guest SS must be 0 if the type of CS is 3 or CR0.PE is 0",
+        );
+
+        // Access-rights fields of DS, ES, FS and GS.
+        for segment in [ds, es, fs, gs] {
+            if !segment.usable() {
+                continue;
+            }
+
+            let segment_type = segment.segment_type();
+
+            self.require(
+                segment_type & 0b0001 != 0,
+                REFERENCE,
+                segment.access_rights_field,
+                "The type of the guest DS, ES, FS and GS must be accessed if they're usable",
+            );
+            self.require(
+                segment_type & 0b1000 == 0 || segment_type & 0b0010 != 0,
+                REFERENCE,
+                segment.access_rights_field,
+                "The code segments in the guest DS, ES, FS and GS must be readable",
+            );
+            self.require(
+                segment.s(),
+                REFERENCE,
+                segment.access_rights_field,
+                "The guest DS, ES, FS and GS must be code or data segments if they're usable",
+            );
+            self.require(
+                unrestricted_guest || segment_type > 11 || segment.dpl() >= segment.rpl(),
+                REFERENCE,
+                segment.access_rights_field,
+                "The DPL of the guest DS, ES, FS and GS must not be less than the RPL of their selectors",
+            );
+        }
+
+        // Access-rights fields of TR.
+        let tr_type = tr.segment_type();
+
+        self.require(
+            tr_type == 11 || (!ia32e_mode_guest && tr_type == 3),
+            REFERENCE,
+            tr.access_rights_field,
+            "The type of the guest TR must be a busy TSS, of 64 bits in an IA-32e mode guest",
+        );
+        self.require(
+            !tr.s(),
+            REFERENCE,
+            tr.access_rights_field,
+            "The guest TR must be a system segment",
+        );
+        self.require(
+            tr.usable(),
+            REFERENCE,
+            tr.access_rights_field,
+            "The guest TR must be usable",
+        );
+
+        // Access-rights fields of LDTR.
+        if ldtr.usable() {
+            self.require(
+                ldtr.segment_type() == 2,
+                REFERENCE,
+                ldtr.access_rights_field,
+                "The type of the guest LDTR must be an LDT if it's usable",
+            );
+            self.require(
+                !ldtr.s(),
+                REFERENCE,
+                ldtr.access_rights_field,
+                "The guest LDTR must be a system segment if it's usable",
+            );
+        }
+
+        // The checks common to all the usable segments.
+        for segment in [cs, ss, ds, es, fs, gs, tr, ldtr] {
+            if !segment.usable() {
+                continue;
+            }
+
+            self.require(
+                segment.present(),
+                REFERENCE,
+                segment.access_rights_field,
+                "The usable guest segments must be present",
+            );
+            self.require(
+                segment.access_rights & Segment::RESERVED == 0,
+                REFERENCE,
+                segment.access_rights_field,
+                "The reserved bits of the access rights of the usable guest segments must be 0",
+            );
+            self.require(
+                segment.granularity_matches_limit(),
+                REFERENCE,
+                segment.access_rights_field,
+                "The G flag of the usable guest segments must be consistent with their limit",
+            );
+        }
+    }
+
+    /// Intel® 64 and IA-32 Architectures Software Developer's Manual: 27.3.1.3 Checks on Guest Descriptor-Table Registers
+    fn check_guest_descriptor_tables(&mut self) {
+        const REFERENCE: &str = "27.3.1.3";
+
+        for field in [guest::GDTR_BASE, guest::IDTR_BASE] {
+            self.require(
+                is_canonical(self.read(field)),
+                REFERENCE,
+                field,
+                "The guest GDTR and IDTR bases must be canonical",
+            );
+        }
+
+        for field in [guest::GDTR_LIMIT, guest::IDTR_LIMIT] {
+            self.require(
+                self.read(field) >> 16 == 0,
+                REFERENCE,
+                field,
+                "Bits 31:16 of the guest GDTR and IDTR limits must be 0",
+            );
+        }
+    }
+
+    /// Intel® 64 and IA-32 Architectures Software Developer's Manual: 27.3.1.4 Checks on Guest RIP, RFLAGS, and SSP
+    fn check_guest_rip_and_rflags(&mut self) {
+        const REFERENCE: &str = "27.3.1.4";
+
+        let rip = self.read(guest::RIP);
+        let rflags = self.read(guest::RFLAGS);
+        let ia32e_mode_guest = self.ia32e_mode_guest();
+        let long_mode = self.read(guest::CS_ACCESS_RIGHTS) & (1 << 13) != 0;
+
+        if ia32e_mode_guest && long_mode {
+            self.require(
+                is_canonical(rip),
+                REFERENCE,
+                guest::RIP,
+                "The guest RIP must be canonical in 64-bit mode",
+            );
+        } else {
+            self.require(
+                rip >> 32 == 0,
+                REFERENCE,
+                guest::RIP,
+                "Bits 63:32 of the guest RIP must be 0 outside of 64-bit mode",
+            );
+        }
+
+        self.require(
+            rflags & RFLAGS_RESERVED == 0 && rflags & RFLAGS_FIXED != 0,
+            REFERENCE,
+            guest::RFLAGS,
+            "The reserved bits of the guest RFLAGS must be 0 and bit 1 must be 1",
+        );
+        self.require(
+            (!ia32e_mode_guest && self.read(guest::CR0) & CR0_PE != 0) || rflags & RFLAGS_VM == 0,
+            REFERENCE,
+            guest::RFLAGS,
+            "RFLAGS.VM must be 0 in an IA-32e mode guest or if CR0.PE is 0",
+        );
+        self.require(
+            self.injected_event_type() != Some(INTERRUPTION_TYPE_EXTERNAL_INTERRUPT)
+                || rflags & RFLAGS_IF != 0,
+            REFERENCE,
+            guest::RFLAGS,
+            "RFLAGS.IF must be 1 if an external interrupt is injected",
+        );
+    }
+
+    /// Intel® 64 and IA-32 Architectures Software Developer's Manual: 27.3.1.5 Checks on Guest Non-Register State
+    fn check_guest_non_register_state(&mut self) {
+        const REFERENCE: &str = "27.3.1.5";
+
+        let activity_state = self.read(guest::ACTIVITY_STATE);
+        let interruptibility = self.read(guest::INTERRUPTIBILITY_STATE);
+        let rflags = self.read(guest::RFLAGS);
+        let injected_event_type = self.injected_event_type();
+        let blocking_by_sti = interruptibility & INTERRUPTIBILITY_STI != 0;
+        let blocking_by_mov_ss = interruptibility & INTERRUPTIBILITY_MOV_SS != 0;
+
+        // The HLT, shutdown and wait-for-SIPI states are supported if the bits 6, 7 and 8 of IA32_VMX_MISC are set.
+        self.require(
+            activity_state == ACTIVITY_STATE_ACTIVE
+                || (activity_state <= 3
+                    && self.capabilities.misc & (1 << (5 + activity_state)) != 0),
+            REFERENCE,
+            guest::ACTIVITY_STATE,
+            "The guest activity state must be supported",
+        );
+        self.require(
+            activity_state != ACTIVITY_STATE_HLT
+                || (self.read(guest::SS_ACCESS_RIGHTS) >> 5) & 0b11 == 0,
+            REFERENCE,
+            guest::SS_ACCESS_RIGHTS,
+            "The DPL of the guest SS must be 0 in the HLT state",
+        );
+        self.require(
+            activity_state == ACTIVITY_STATE_ACTIVE || !(blocking_by_sti || blocking_by_mov_ss),
+            REFERENCE,
+            guest::ACTIVITY_STATE,
+            "The guest activity state must be active with blocking by STI or by MOV SS",
+        );
+
+        self.require(
+            interruptibility >> 5 == 0,
+            REFERENCE,
+            guest::INTERRUPTIBILITY_STATE,
+            "Bits 31:5 of the guest interruptibility state must be 0",
+        );
+        self.require(
+            !(blocking_by_sti && blocking_by_mov_ss),
+            REFERENCE,
+            guest::INTERRUPTIBILITY_STATE,
+            "Blocking by STI and blocking by MOV SS must not both be 1",
+        );
+        self.require(
+            rflags & RFLAGS_IF != 0 || !blocking_by_sti,
+            REFERENCE,
+            guest::INTERRUPTIBILITY_STATE,
+            "Blocking by STI must be 0 if RFLAGS.IF is 0",
+        );
+        self.require(
+            injected_event_type != Some(INTERRUPTION_TYPE_EXTERNAL_INTERRUPT)
+                || !(blocking_by_sti || blocking_by_mov_ss),
+            REFERENCE,
+            guest::INTERRUPTIBILITY_STATE,
+            "Blocking by STI and by MOV SS must be 0 if an external interrupt is injected",
+        );
+        self.require(
+            injected_event_type != Some(INTERRUPTION_TYPE_NMI) || !blocking_by_mov_ss,
+            REFERENCE,
+            guest::INTERRUPTIBILITY_STATE,
+            "Blocking by MOV SS must be 0 if an NMI is injected",
+        );
+        self.require(
+            injected_event_type != Some(INTERRUPTION_TYPE_NMI)
+                || !self
+                    .pinbased_controls()
+                    .contains(PinbasedControls::VIRTUAL_NMIS)
+                || interruptibility & INTERRUPTIBILITY_NMI == 0,
+            REFERENCE,
+            guest::INTERRUPTIBILITY_STATE,
+            "Blocking by NMI must be 0 if an NMI is injected with virtual NMIs",
+        );
+        self.require(
+            interruptibility & INTERRUPTIBILITY_SMI == 0,
+            REFERENCE,
+            guest::INTERRUPTIBILITY_STATE,
+            "Blocking by SMI must be 0 outside of SMM",
+        );
+
+        let pending_debug_exceptions = self.read(guest::PENDING_DBG_EXCEPTIONS);
+
+        self.require(
+            pending_debug_exceptions & !PENDING_DEBUG_DEFINED == 0,
+            REFERENCE,
+            guest::PENDING_DBG_EXCEPTIONS,
+            "The reserved bits of the guest pending debug exceptions must be 0",
+        );
+
+        // A single step is pending if the trap flag is set and not used for branches.
+        if blocking_by_sti || blocking_by_mov_ss || activity_state == ACTIVITY_STATE_HLT {
+            let single_step =
+                rflags & RFLAGS_TF != 0 && self.read(guest::IA32_DEBUGCTL_FULL) & DEBUGCTL_BTF == 0;
+
+            self.require(
+                (pending_debug_exceptions & PENDING_DEBUG_BS != 0) == single_step,
+                REFERENCE,
+                guest::PENDING_DBG_EXCEPTIONS,
+                "The BS flag of the guest pending debug exceptions must be 1 exactly if RFLAGS.TF is 1 and IA32_DEBUGCTL.BTF is 0",
+            );
+        }
+
+        let link_pointer = self.read(guest::LINK_PTR_FULL);
+
+        self.require(
+            link_pointer == u64::MAX
+                || (link_pointer & 0xFFF == 0 && self.is_physical_address(link_pointer)),
+            REFERENCE,
+            guest::LINK_PTR_FULL,
+            "The VMCS link pointer must be FFFFFFFF_FFFFFFFFH, or 4-KByte aligned and within the physical-address width",
+        );
+    }
+}
+
+/// Checks a control register against the bits fixed to 1 and the bits allowed to be 1 in VMX operation.
+fn is_fixed(value: u64, fixed0: u64, fixed1: u64) -> bool {
+    value & fixed0 == fixed0 && value & !fixed1 == 0
+}
+
+/// Checks whether an address is canonical with 48-bit linear addresses.
+fn is_canonical(address: u64) -> bool {
+    let upper = (address as i64) >> 47;
+    upper == 0 || upper == -1
+}
+
+/// Checks whether every entry of an IA32_PAT value is a valid memory type: UC, WC, WT, WP, WB or UC-.
+fn is_valid_pat(pat: u64) -> bool {
+    pat.to_le_bytes()
+        .iter()
+        .all(|memory_type| matches!(memory_type, 0 | 1 | 4 | 5 | 6 | 7))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::intel::{vmcs_access::VmcsFieldAccess, vmcs_fields::FieldWidth},
+    };
+
+    const CR0: u64 = 0x8005_0033;
+    const CR4: u64 = CR4_PAE | 1 << 13;
+
+    /// A processor that allows every control to be 0 or 1.
+    fn capabilities() -> VmxCapabilities {
+        const ANY_CONTROLS: u64 = 0xFFFF_FFFF_0000_0000;
+
+        VmxCapabilities {
+            pinbased_ctls: ANY_CONTROLS,
+            procbased_ctls: ANY_CONTROLS,
+            procbased_ctls2: ANY_CONTROLS,
+            exit_ctls: ANY_CONTROLS,
+            entry_ctls: ANY_CONTROLS,
+            misc: 0,
+            cr0_fixed0: CR0_PE | CR0_PG | 1 << 5,
+            cr0_fixed1: u64::MAX,
+            cr4_fixed0: 1 << 13,
+            cr4_fixed1: u64::MAX,
+            ept_vpid_cap: 1 << 6 | 1 << 14,
+            physical_address_width: 39,
+        }
+    }
+
+    /// A 64-bit guest and host, configured like `Vmcs::setup_*`.
+    fn snapshot() -> VmcsSnapshot {
+        let mut vmcs = VmcsSnapshot::empty();
+
+        vmcs.set(
+            control::PRIMARY_PROCBASED_EXEC_CONTROLS,
+            (PrimaryControls::SECONDARY_CONTROLS | PrimaryControls::USE_MSR_BITMAPS).bits(),
+        );
+        vmcs.set(
+            control::SECONDARY_PROCBASED_EXEC_CONTROLS,
+            (SecondaryControls::ENABLE_EPT | SecondaryControls::ENABLE_VPID).bits(),
+        );
+        vmcs.set(control::MSR_BITMAPS_ADDR_FULL, 0x1000);
+        vmcs.set(control::VPID, 1);
+        vmcs.set(control::EPTP_FULL, 0x2000 | 3 << 3 | 6);
+        vmcs.set(
+            control::VMEXIT_CONTROLS,
+            ExitControls::HOST_ADDRESS_SPACE_SIZE.bits(),
+        );
+        vmcs.set(
+            control::VMENTRY_CONTROLS,
+            EntryControls::IA32E_MODE_GUEST.bits(),
+        );
+
+        vmcs.set(host::CR0, CR0);
+        vmcs.set(host::CR3, 0x1AD000);
+        vmcs.set(host::CR4, CR4);
+        vmcs.set(host::CS_SELECTOR, 0x10);
+        vmcs.set(host::TR_SELECTOR, 0x40);
+        vmcs.set(host::RIP, 0xFFFF_F800_0000_1000);
+
+        vmcs.set(guest::CR0, CR0);
+        vmcs.set(guest::CR3, 0x1AD000);
+        vmcs.set(guest::CR4, CR4);
+        vmcs.set(guest::RIP, 0xFFFF_F800_0000_2000);
+        vmcs.set(guest::RFLAGS, RFLAGS_FIXED | RFLAGS_IF);
+        vmcs.set(guest::LINK_PTR_FULL, u64::MAX);
+        vmcs.set(guest::GDTR_LIMIT, 0x57);
+        vmcs.set(guest::IDTR_LIMIT, 0xFFF);
+
+        vmcs.set(guest::CS_SELECTOR, 0x10);
+        vmcs.set(guest::CS_LIMIT, u32::MAX);
+        vmcs.set(guest::CS_ACCESS_RIGHTS, 0xA09B);
+        vmcs.set(guest::SS_SELECTOR, 0x18);
+        vmcs.set(guest::SS_LIMIT, u32::MAX);
+        vmcs.set(guest::SS_ACCESS_RIGHTS, 0xC093);
+        vmcs.set(guest::TR_SELECTOR, 0x40);
+        vmcs.set(guest::TR_LIMIT, 0x67);
+        vmcs.set(guest::TR_ACCESS_RIGHTS, 0x8B);
+
+        for access_rights in [
+            guest::DS_ACCESS_RIGHTS,
+            guest::ES_ACCESS_RIGHTS,
+            guest::FS_ACCESS_RIGHTS,
+            guest::GS_ACCESS_RIGHTS,
+            guest::LDTR_ACCESS_RIGHTS,
+        ] {
+            vmcs.set(access_rights, 1 << 16);
+        }
+
+        vmcs
+    }
+
+    /// Checks a snapshot that differs from the valid one by a field, and returns the violations.
+    fn violations_with<W: FieldWidth>(
+        field: VmcsField<W, ReadWrite>,
+        value: W::Value,
+    ) -> Vec<VmEntryViolation> {
+        let mut snapshot = snapshot();
+        snapshot.set(field, value);
+
+        check_vm_entry(&snapshot, &capabilities())
+    }
+
+    /// Asserts that a single check is violated, by a field.
+    fn assert_violation(
+        violations: &[VmEntryViolation],
+        reference: &str,
+        field: impl Into<FieldInfo>,
+    ) {
+        assert_eq!(violations.len(), 1, "{:?}", violations);
+        assert_eq!(violations[0].reference, reference);
+        assert_eq!(violations[0].field, field.into());
+    }
+
+    #[test]
+    fn a_valid_vmcs_passes_the_checks() {
+        assert_eq!(check_vm_entry(&snapshot(), &capabilities()), []);
+    }
+
+    #[test]
+    fn controls_must_respect_their_allowed_settings() {
+        let mut capabilities = capabilities();
+        capabilities.pinbased_ctls |= PinbasedControls::NMI_EXITING.bits() as u64;
+
+        let violations = check_vm_entry(&snapshot(), &capabilities);
+
+        assert_violation(&violations, "27.2.1.1", control::PINBASED_EXEC_CONTROLS);
+    }
+
+    #[test]
+    fn the_eptp_must_be_supported() {
+        let violations = violations_with(control::EPTP_FULL, 0x2000 | 4 << 3 | 6);
+
+        assert_violation(&violations, "27.2.1.1", control::EPTP_FULL);
+    }
+
+    #[test]
+    fn injected_events_must_be_consistent() {
+        // An NMI with the vector of #BP.
+        let violations = violations_with(
+            control::VMENTRY_INTERRUPTION_INFO_FIELD,
+            1 << 31 | 2 << 8 | 3,
+        );
+
+        assert_violation(
+            &violations,
+            "27.2.1.3",
+            control::VMENTRY_INTERRUPTION_INFO_FIELD,
+        );
+    }
+
+    #[test]
+    fn the_host_state_must_be_valid() {
+        let violations = violations_with(host::TR_SELECTOR, 0);
+
+        assert_violation(&violations, "27.2.3", host::TR_SELECTOR);
+        assert_eq!(violations[0].value, 0);
+    }
+
+    #[test]
+    fn the_guest_control_registers_must_be_valid() {
+        let violations = violations_with(guest::CR4, CR4 & !CR4_PAE);
+
+        assert_violation(&violations, "27.3.1.1", guest::CR4);
+    }
+
+    #[test]
+    fn the_guest_segments_must_be_valid() {
+        // A 64-bit code segment with the D/B flag set.
+        let violations = violations_with(guest::CS_ACCESS_RIGHTS, 0xE09B);
+
+        assert_violation(&violations, "27.3.1.2", guest::CS_ACCESS_RIGHTS);
+    }
+
+    #[test]
+    fn the_guest_rflags_must_be_valid() {
+        let violations = violations_with(guest::RFLAGS, RFLAGS_IF);
+
+        assert_violation(&violations, "27.3.1.4", guest::RFLAGS);
+    }
+
+    #[test]
+    fn the_guest_non_register_state_must_be_valid() {
+        let violations = violations_with(guest::LINK_PTR_FULL, 0x1234);
+
+        assert_violation(&violations, "27.3.1.5", guest::LINK_PTR_FULL);
+    }
+
+    #[test]
+    fn every_violation_is_reported() {
+        let violations = check_vm_entry(&VmcsSnapshot::empty(), &capabilities());
+
+        assert!(violations.len() > 1);
+        assert!(violations
+            .iter()
+            .any(|violation| violation.field == host::CS_SELECTOR.info()));
+        assert!(violations
+            .iter()
+            .any(|violation| violation.field == guest::TR_ACCESS_RIGHTS.info()));
+    }
+
+    #[test]
+    fn violations_are_printed_with_their_reference() {
+        let violations = violations_with(host::TR_SELECTOR, 0);
+
+        assert_eq!(
+            violations[0].to_string(),
+            "SDM 27.2.3: The host TR selector must not be 0 (host::TR_SELECTOR = 0x0)"
+        );
+    }
+}
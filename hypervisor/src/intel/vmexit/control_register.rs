@@ -0,0 +1,286 @@
+//! Handles control-register access VM exits: MOV to and from CR0, CR3, CR4 and CR8, CLTS and LMSW.
+//!
+//! The hypervisor owns the bits of CR0 and CR4 that are fixed in VMX operation, and CR4.VMXE, which is
+//! hidden from the guest like the VMX support in CPUID. The guest reads these bits from the read shadows,
+//! and the accesses that would change them cause VM exits. The values written by the guest are stored in
+//! the read shadows, and the fixed bits are applied to the values loaded into the control registers of
+//! the guest.
+//!
+//! With CR3-load exiting, MOV to CR3 is emulated and reported to the address-space monitor.
+//!
+//! The guest always runs in IA-32e mode, because the hypervisor enters it with the "IA-32e mode guest"
+//! VM-entry control, so the values that would leave IA-32e mode raise #GP(0).
+
+use {
+    crate::{
+        error::HypervisorError,
+        intel::{
+            address_space::{AddressSpaceSwitch, CR3_NO_FLUSH},
+            cpu_access::CpuAccess,
+            events::EventInjection,
+            exit_qualification::{ControlRegisterAccessQualification, ControlRegisterAccessType},
+            vmcs_access::{VmcsAccess, VmcsFieldAccess},
+            vmcs_fields::{control, guest, ro},
+            vmexit::{guest_register, registry::VmExitData, set_guest_register, ExitType},
+        },
+        utils::{
+            capture::GuestRegisters,
+            instructions::{cr8, cr8_write},
+        },
+    },
+    x86::{
+        controlregs::{Cr0, Cr4},
+        msr,
+    },
+};
+
+/// The control-flow enforcement technology flag of CR4.
+const CR4_CET: u64 = 1 << 23;
+
+/// The bits of CR4 whose modification invalidates the TLB entries and the paging-structure caches.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 4.10.4.1 Operations that Invalidate TLBs and Paging-Structure Caches
+const CR4_TLB_FLUSH_BITS: u64 = (Cr4::CR4_ENABLE_GLOBAL_PAGES.bits()
+    | Cr4::CR4_ENABLE_PAE.bits()
+    | Cr4::CR4_ENABLE_PSE.bits()
+    | Cr4::CR4_ENABLE_SMEP.bits()
+    | Cr4::CR4_ENABLE_SMAP.bits()
+    | Cr4::CR4_ENABLE_PROTECTION_KEY.bits()
+    | Cr4::CR4_ENABLE_LA57.bits()) as u64;
+
+/// Returns the CR0 guest/host mask: the bits fixed in VMX operation.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 25.6.6 Guest/Host Masks and Read Shadows for CR0 and CR4
+pub fn cr0_guest_host_mask() -> u64 {
+    let fixed0 = unsafe { msr::rdmsr(msr::IA32_VMX_CR0_FIXED0) };
+    let fixed1 = unsafe { msr::rdmsr(msr::IA32_VMX_CR0_FIXED1) };
+
+    fixed0 | !fixed1
+}
+
+/// Returns the CR4 guest/host mask: the bits fixed in VMX operation and CR4.VMXE.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 25.6.6 Guest/Host Masks and Read Shadows for CR0 and CR4
+pub fn cr4_guest_host_mask() -> u64 {
+    let fixed0 = unsafe { msr::rdmsr(msr::IA32_VMX_CR4_FIXED0) };
+    let fixed1 = unsafe { msr::rdmsr(msr::IA32_VMX_CR4_FIXED1) };
+
+    fixed0 | !fixed1 | Cr4::CR4_ENABLE_VMX.bits() as u64
+}
+
+/// Returns the CR4 read shadow for a value of CR4, which hides CR4.VMXE.
+pub fn cr4_read_shadow(cr4: u64) -> u64 {
+    cr4 & !(Cr4::CR4_ENABLE_VMX.bits() as u64)
+}
+
+/// Returns the CR0 seen by the guest: the owned bits come from the read shadow, and the others from the
+/// guest CR0.
+pub fn guest_visible_cr0(vmcs: &dyn VmcsAccess) -> u64 {
+    let mask = vmcs.get(control::CR0_GUEST_HOST_MASK);
+
+    (vmcs.get(guest::CR0) & !mask) | (vmcs.get(control::CR0_READ_SHADOW) & mask)
+}
+
+/// Returns the CR4 seen by the guest: the owned bits come from the read shadow, and the others from the
+/// guest CR4.
+pub fn guest_visible_cr4(vmcs: &dyn VmcsAccess) -> u64 {
+    let mask = vmcs.get(control::CR4_GUEST_HOST_MASK);
+
+    (vmcs.get(guest::CR4) & !mask) | (vmcs.get(control::CR4_READ_SHADOW) & mask)
+}
+
+/// Handles the control-register access VM exit.
+///
+/// The guest receives #GP(0) for the values that MOV to a control register rejects, like without VMX.
+///
+/// # Arguments
+///
+/// * `guest_registers` - A mutable reference to the guest's current register state.
+/// * `data` - The shared state, with the address-space monitor.
+/// * `vmcs` - The VMCS of the guest.
+/// * `cpu` - The processor whose VMX capabilities and translations are used.
+///
+/// # Returns
+///
+/// * `Result<ExitType, HypervisorError>` - `ExitType::IncrementRIP` to move past the instruction, `ExitType::Continue` if #GP is injected, or `InvalidExitQualification` for an access that can't cause a VM exit.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 26.1.3 Instructions That Cause VM Exits Conditionally
+/// and MOV—Move to/from Control Registers
+pub fn handle_control_register_access(
+    guest_registers: &mut GuestRegisters,
+    data: &VmExitData,
+    vmcs: &mut dyn VmcsAccess,
+    cpu: &mut dyn CpuAccess,
+) -> Result<ExitType, HypervisorError> {
+    log::debug!("Handling control-register access VM exit...");
+
+    let qualification =
+        ControlRegisterAccessQualification::from_u64(vmcs.get(ro::EXIT_QUALIFICATION));
+
+    log::trace!("Control-register access: {:?}", qualification);
+
+    match (qualification.access_type, qualification.control_register) {
+        (ControlRegisterAccessType::MovToCr(register), control_register) => {
+            let value = guest_register(guest_registers, register);
+
+            let valid = match control_register {
+                0 => is_valid_cr0(vmcs, value),
+                3 => is_valid_cr3(vmcs, cpu, value),
+                4 => is_valid_cr4(vmcs, cpu, value),
+                // The bits 63:4 of CR8 are reserved.
+                8 => value >> 4 == 0,
+                _ => return Err(HypervisorError::InvalidExitQualification),
+            };
+
+            if !valid {
+                log::trace!("Invalid value {:#x} for CR{}", value, control_register);
+                EventInjection::vmentry_inject_gp(vmcs, 0);
+                return Ok(ExitType::Continue);
+            }
+
+            match control_register {
+                0 => write_cr0(vmcs, cpu, value),
+                3 => {
+                    let old_cr3 = vmcs.get(guest::CR3);
+                    write_cr3(vmcs, cpu, value);
+
+                    if let Some(monitor) = data.address_space_monitor {
+                        monitor.notify(&mut AddressSpaceSwitch {
+                            old_cr3,
+                            new_cr3: value & !CR3_NO_FLUSH,
+                            vmcs,
+                        });
+                    }
+                }
+                4 => write_cr4(vmcs, cpu, value),
+                _ => cr8_write(value),
+            }
+        }
+        (ControlRegisterAccessType::MovFromCr(register), control_register) => {
+            let value = match control_register {
+                3 => vmcs.get(guest::CR3),
+                8 => cr8(),
+                _ => return Err(HypervisorError::InvalidExitQualification),
+            };
+
+            set_guest_register(guest_registers, vmcs, register, value);
+        }
+        (ControlRegisterAccessType::Clts, _) => {
+            let cr0 = guest_visible_cr0(vmcs);
+            write_cr0(vmcs, cpu, cr0 & !(Cr0::CR0_TASK_SWITCHED.bits() as u64));
+        }
+        (ControlRegisterAccessType::Lmsw(operand), _) => {
+            // LMSW loads CR0.PE, MP, EM and TS, but can't clear CR0.PE.
+            let cr0 = guest_visible_cr0(vmcs);
+            let source_data = operand.source_data as u64 & 0xF;
+
+            write_cr0(vmcs, cpu, (cr0 & !0xE) | source_data);
+        }
+    }
+
+    log::debug!("Control-register access VMEXIT handled successfully!");
+
+    Ok(ExitType::IncrementRIP)
+}
+
+/// Checks whether MOV to CR0 accepts a value.
+///
+/// The bits 63:32 are reserved, CR0.PG can't be set without CR0.PE nor cleared in IA-32e mode, CR0.NW
+/// can't be set without CR0.CD, and CR0.WP can't be cleared while CR4.CET is set.
+fn is_valid_cr0(vmcs: &dyn VmcsAccess, value: u64) -> bool {
+    let paging = value & Cr0::CR0_ENABLE_PAGING.bits() as u64 != 0;
+    let protected_mode = value & Cr0::CR0_PROTECTED_MODE.bits() as u64 != 0;
+    let not_write_through = value & Cr0::CR0_NOT_WRITE_THROUGH.bits() as u64 != 0;
+    let cache_disable = value & Cr0::CR0_CACHE_DISABLE.bits() as u64 != 0;
+    let write_protect = value & Cr0::CR0_WRITE_PROTECT.bits() as u64 != 0;
+    let cet = guest_visible_cr4(vmcs) & CR4_CET != 0;
+
+    value >> 32 == 0
+        && paging
+        && protected_mode
+        && (!not_write_through || cache_disable)
+        && (write_protect || !cet)
+}
+
+/// Checks whether MOV to CR3 accepts a value.
+///
+/// The bits above MAXPHYADDR are reserved, except for bit 63 if CR4.PCIDE is set.
+fn is_valid_cr3(vmcs: &dyn VmcsAccess, cpu: &dyn CpuAccess, value: u64) -> bool {
+    let physical_address_width = cpu.cpuid(0x8000_0008, 0).eax as u8;
+    let pcid_enabled = guest_visible_cr4(vmcs) & Cr4::CR4_ENABLE_PCID.bits() as u64 != 0;
+
+    let value = match pcid_enabled {
+        true => value & !CR3_NO_FLUSH,
+        false => value,
+    };
+
+    value >> physical_address_width == 0
+}
+
+/// Checks whether MOV to CR4 accepts a value.
+///
+/// The bits that the processor doesn't support are reserved, as is CR4.VMXE for the guest. CR4.PAE can't
+/// be cleared and CR4.LA57 can't be changed in IA-32e mode, CR4.PCIDE can't be set unless the PCID of CR3
+/// is 0, and CR4.CET can't be set while CR0.WP is clear.
+fn is_valid_cr4(vmcs: &dyn VmcsAccess, cpu: &dyn CpuAccess, value: u64) -> bool {
+    let fixed1 = cpu.rdmsr(msr::IA32_VMX_CR4_FIXED1);
+    let supported = cr4_read_shadow(fixed1);
+    let current = guest_visible_cr4(vmcs);
+
+    let pae = value & Cr4::CR4_ENABLE_PAE.bits() as u64 != 0;
+    let la57_changed = (value ^ current) & Cr4::CR4_ENABLE_LA57.bits() as u64 != 0;
+    let pcid_set = value & !current & Cr4::CR4_ENABLE_PCID.bits() as u64 != 0;
+    let cet = value & CR4_CET != 0;
+    let write_protect = guest_visible_cr0(vmcs) & Cr0::CR0_WRITE_PROTECT.bits() as u64 != 0;
+
+    value & !supported == 0
+        && pae
+        && !la57_changed
+        && (!pcid_set || vmcs.get(guest::CR3) & 0xFFF == 0)
+        && (!cet || write_protect)
+}
+
+/// Writes the CR0 of the guest: the read shadow receives the value, and the guest CR0 the value with the
+/// bits fixed in VMX operation applied.
+fn write_cr0(vmcs: &mut dyn VmcsAccess, cpu: &dyn CpuAccess, value: u64) {
+    let fixed0 = cpu.rdmsr(msr::IA32_VMX_CR0_FIXED0);
+    let fixed1 = cpu.rdmsr(msr::IA32_VMX_CR0_FIXED1);
+
+    vmcs.set(control::CR0_READ_SHADOW, value);
+    vmcs.set(guest::CR0, (value | fixed0) & fixed1);
+}
+
+/// Writes the CR4 of the guest: the read shadow receives the value, and the guest CR4 the value with the
+/// bits fixed in VMX operation applied, which include CR4.VMXE.
+///
+/// The TLB entries of the guest are invalidated like MOV to CR4 does.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 4.10.4.1 Operations that Invalidate TLBs and Paging-Structure Caches
+fn write_cr4(vmcs: &mut dyn VmcsAccess, cpu: &mut dyn CpuAccess, value: u64) {
+    let fixed0 = cpu.rdmsr(msr::IA32_VMX_CR4_FIXED0);
+    let fixed1 = cpu.rdmsr(msr::IA32_VMX_CR4_FIXED1);
+    let current = guest_visible_cr4(vmcs);
+
+    vmcs.set(control::CR4_READ_SHADOW, cr4_read_shadow(value));
+    vmcs.set(guest::CR4, (value | fixed0) & fixed1);
+
+    let pcid_cleared = current & !value & Cr4::CR4_ENABLE_PCID.bits() as u64 != 0;
+
+    if (current ^ value) & CR4_TLB_FLUSH_BITS != 0 || pcid_cleared {
+        cpu.invvpid_single_context(vmcs.get(control::VPID));
+    }
+}
+
+/// Writes the CR3 of the guest and invalidates the TLB entries of the guest, unless the guest preserves
+/// them with bit 63.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 4.10.4.1 Operations that Invalidate TLBs and Paging-Structure Caches
+fn write_cr3(vmcs: &mut dyn VmcsAccess, cpu: &mut dyn CpuAccess, value: u64) {
+    let pcid_enabled = vmcs.get(guest::CR4) & Cr4::CR4_ENABLE_PCID.bits() as u64 != 0;
+
+    vmcs.set(guest::CR3, value & !CR3_NO_FLUSH);
+
+    if !pcid_enabled || value & CR3_NO_FLUSH == 0 {
+        cpu.invvpid_single_context(vmcs.get(control::VPID));
+    }
+}
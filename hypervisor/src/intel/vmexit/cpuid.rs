@@ -4,9 +4,11 @@
 #![allow(dead_code)]
 
 use {
-    crate::{intel::vmexit::ExitType, utils::capture::GuestRegisters},
+    crate::{
+        intel::{cpu_access::CpuAccess, vmexit::ExitType},
+        utils::capture::GuestRegisters,
+    },
     bitfield::BitMut,
-    x86::cpuid::cpuid,
 };
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -69,6 +71,7 @@ enum FeatureBits {
 /// # Arguments
 ///
 /// * `registers` - A mutable reference to the guest's current register state.
+/// * `cpu` - The processor executing `CPUID` on the host.
 ///
 /// # Returns
 ///
@@ -76,14 +79,14 @@ enum FeatureBits {
 ///
 /// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual, Table C-1. Basic Exit Reasons 10.
 #[rustfmt::skip]
-pub fn handle_cpuid(guest_registers: &mut GuestRegisters) -> ExitType {
+pub fn handle_cpuid(guest_registers: &mut GuestRegisters, cpu: &dyn CpuAccess) -> ExitType {
     log::trace!("Handling CPUID VM exit...");
 
     let leaf = guest_registers.rax as u32;
     let sub_leaf = guest_registers.rcx as u32;
 
     // Execute CPUID instruction on the host and retrieve the result
-    let mut cpuid_result = cpuid!(leaf, sub_leaf);
+    let mut cpuid_result = cpu.cpuid(leaf, sub_leaf);
 
     log::trace!("Before modification: CPUID Leaf: {:#x}, EAX: {:#x}, EBX: {:#x}, ECX: {:#x}, EDX: {:#x}", leaf, cpuid_result.eax, cpuid_result.ebx, cpuid_result.ecx, cpuid_result.edx);
 
@@ -0,0 +1,250 @@
+//! Handles descriptor-table VM exits: SGDT, SIDT, LGDT and LIDT, which access GDTR and IDTR, and SLDT,
+//! STR, LLDT and LTR, which access LDTR and TR.
+//!
+//! The registers are part of the guest-state area of the VMCS, and the memory operands are accessed
+//! through `GuestMemory`.
+
+use {
+    crate::{
+        error::HypervisorError,
+        intel::{
+            events::EventInjection,
+            exit_qualification::{
+                DescriptorTableInstruction, DescriptorTableInstructionInformation, Operand,
+                SegmentTableInstruction, SegmentTableInstructionInformation,
+            },
+            guest_memory::GuestMemory,
+            segmentation::SegmentAccessRights,
+            vmcs_access::{VmcsAccess, VmcsFieldAccess},
+            vmcs_fields::{guest, ro},
+            vmerror::ExceptionInterrupt,
+            vmexit::{
+                guest_register, is_guest_64bit_mode, operand_linear_address, set_guest_register,
+                ExitType,
+            },
+        },
+        utils::capture::GuestRegisters,
+    },
+    bit_field::BitField,
+};
+
+/// The type of an LDT descriptor, which is loaded by LLDT.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: Table 3-2. System-Segment and Gate-Descriptor Types
+const LDT_TYPE: u64 = 0x2;
+
+/// The type of an available 64-bit TSS descriptor, which is loaded by LTR.
+const AVAILABLE_TSS_TYPE: u64 = 0x9;
+
+/// The busy flag of the type of a TSS descriptor.
+const TSS_BUSY: u64 = 1 << 41;
+
+/// Handles the VM exits due to SGDT, SIDT, LGDT and LIDT.
+///
+/// The pseudo-descriptor in memory consists of the 16-bit limit followed by the base address, which has
+/// 64 bits in 64-bit mode, and 32 bits otherwise. LGDT and LIDT with a 16-bit operand size only load 24
+/// bits of the base address.
+///
+/// # Arguments
+///
+/// * `guest_registers` - The guest's current register state.
+/// * `vmcs` - The VMCS of the guest.
+///
+/// # Returns
+///
+/// * `Result<ExitType, HypervisorError>` - `ExitType::IncrementRIP` to move past the instruction, or an error if the instruction information is invalid or the pseudo-descriptor can't be accessed.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: LGDT/LIDT—Load Global/Interrupt Descriptor Table Register
+/// and SGDT—Store Global Descriptor Table Register
+pub fn handle_descriptor_table_access(
+    guest_registers: &mut GuestRegisters,
+    vmcs: &mut dyn VmcsAccess,
+) -> Result<ExitType, HypervisorError> {
+    log::debug!("Handling GDTR/IDTR access VM exit...");
+
+    let information = DescriptorTableInstructionInformation::from_u64(
+        vmcs.get(ro::VMEXIT_INSTRUCTION_INFO).into(),
+    )
+    .ok_or(HypervisorError::InvalidInstructionInformation)?;
+
+    log::trace!("GDTR/IDTR access: {:?}", information);
+
+    let address = operand_linear_address(&information.operand, guest_registers, vmcs);
+    let guest_memory = GuestMemory::current(vmcs);
+    let base_size = match is_guest_64bit_mode(vmcs) {
+        true => 8,
+        false => 4,
+    };
+
+    let (base_field, limit_field) = match information.instruction {
+        DescriptorTableInstruction::Sgdt | DescriptorTableInstruction::Lgdt => {
+            (guest::GDTR_BASE, guest::GDTR_LIMIT)
+        }
+        DescriptorTableInstruction::Sidt | DescriptorTableInstruction::Lidt => {
+            (guest::IDTR_BASE, guest::IDTR_LIMIT)
+        }
+    };
+
+    match information.instruction {
+        DescriptorTableInstruction::Sgdt | DescriptorTableInstruction::Sidt => {
+            let mut pseudo_descriptor = [0u8; 10];
+            pseudo_descriptor[..2].copy_from_slice(&(vmcs.get(limit_field) as u16).to_le_bytes());
+            pseudo_descriptor[2..].copy_from_slice(&vmcs.get(base_field).to_le_bytes());
+
+            guest_memory.write_bytes(address, &pseudo_descriptor[..2 + base_size])?;
+        }
+        DescriptorTableInstruction::Lgdt | DescriptorTableInstruction::Lidt => {
+            let mut pseudo_descriptor = [0u8; 10];
+            guest_memory.read_bytes(address, &mut pseudo_descriptor[..2 + base_size])?;
+
+            let limit = u16::from_le_bytes([pseudo_descriptor[0], pseudo_descriptor[1]]);
+            let mut base = u64::from_le_bytes(pseudo_descriptor[2..].try_into().unwrap());
+
+            if base_size == 4 && !information.operand_size_32 {
+                base &= 0xFF_FFFF;
+            }
+
+            vmcs.set(limit_field, limit.into());
+            vmcs.set(base_field, base);
+        }
+    }
+
+    log::debug!("GDTR/IDTR access VMEXIT handled successfully!");
+
+    Ok(ExitType::IncrementRIP)
+}
+
+/// Handles the VM exits due to SLDT, STR, LLDT and LTR.
+///
+/// LLDT and LTR load the descriptor referenced by the selector from the GDT of the guest, and LTR marks
+/// the TSS as busy. The guest receives #GP or #NP for invalid selectors and descriptors, like without
+/// VMX.
+///
+/// # Arguments
+///
+/// * `guest_registers` - A mutable reference to the guest's current register state.
+/// * `vmcs` - The VMCS of the guest.
+///
+/// # Returns
+///
+/// * `Result<ExitType, HypervisorError>` - `ExitType::IncrementRIP` to move past the instruction, `ExitType::Continue` if an exception is injected, or an error if the instruction information is invalid or guest memory can't be accessed.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: LLDT—Load Local Descriptor Table Register
+/// and LTR—Load Task Register
+pub fn handle_segment_table_access(
+    guest_registers: &mut GuestRegisters,
+    vmcs: &mut dyn VmcsAccess,
+) -> Result<ExitType, HypervisorError> {
+    log::debug!("Handling LDTR/TR access VM exit...");
+
+    let information =
+        SegmentTableInstructionInformation::from_u64(vmcs.get(ro::VMEXIT_INSTRUCTION_INFO).into())
+            .ok_or(HypervisorError::InvalidInstructionInformation)?;
+
+    log::trace!("LDTR/TR access: {:?}", information);
+
+    let guest_memory = GuestMemory::current(vmcs);
+
+    let (selector_field, base_field, limit_field, access_rights_field) =
+        match information.instruction {
+            SegmentTableInstruction::Sldt | SegmentTableInstruction::Lldt => (
+                guest::LDTR_SELECTOR,
+                guest::LDTR_BASE,
+                guest::LDTR_LIMIT,
+                guest::LDTR_ACCESS_RIGHTS,
+            ),
+            SegmentTableInstruction::Str | SegmentTableInstruction::Ltr => (
+                guest::TR_SELECTOR,
+                guest::TR_BASE,
+                guest::TR_LIMIT,
+                guest::TR_ACCESS_RIGHTS,
+            ),
+        };
+
+    if let SegmentTableInstruction::Sldt | SegmentTableInstruction::Str = information.instruction {
+        let selector = vmcs.get(selector_field);
+
+        match information.operand {
+            Operand::Register(register) => {
+                set_guest_register(guest_registers, vmcs, register, selector as u64)
+            }
+            Operand::Memory(operand) => {
+                let address = operand_linear_address(&operand, guest_registers, vmcs);
+                guest_memory.write::<u16>(address, selector)?;
+            }
+        }
+
+        return Ok(ExitType::IncrementRIP);
+    }
+
+    let selector = match information.operand {
+        Operand::Register(register) => guest_register(guest_registers, register) as u16,
+        Operand::Memory(operand) => {
+            let address = operand_linear_address(&operand, guest_registers, vmcs);
+            guest_memory.read::<u16>(address)?
+        }
+    };
+
+    let is_ltr = information.instruction == SegmentTableInstruction::Ltr;
+    let index = selector as u64 >> 3;
+
+    // A null selector makes LDTR unusable. TR can't be loaded with a null selector.
+    if index == 0 && !is_ltr {
+        vmcs.set(selector_field, selector);
+        vmcs.set(access_rights_field, SegmentAccessRights::UNUSABLE.bits());
+        return Ok(ExitType::IncrementRIP);
+    }
+
+    // The selector must reference a 16-byte system descriptor in the GDT.
+    let error_code = selector as u32 & 0xFFFC;
+    let gdtr_limit = u64::from(vmcs.get(guest::GDTR_LIMIT));
+
+    if index == 0 || selector & 0b100 != 0 || index * 8 + 15 > gdtr_limit {
+        EventInjection::vmentry_inject_gp(vmcs, error_code);
+        return Ok(ExitType::Continue);
+    }
+
+    let descriptor_address = vmcs.get(guest::GDTR_BASE).wrapping_add(index * 8);
+    let [low, high] = guest_memory.read::<[u64; 2]>(descriptor_address)?;
+
+    let expected_type = if is_ltr { AVAILABLE_TSS_TYPE } else { LDT_TYPE };
+    let access_rights = SegmentAccessRights::from_descriptor(low);
+
+    // The S flag (bit 44) must be clear for system descriptors.
+    if low.get_bits(40..44) != expected_type || low.get_bit(44) {
+        EventInjection::vmentry_inject_gp(vmcs, error_code);
+        return Ok(ExitType::Continue);
+    }
+
+    if !access_rights.contains(SegmentAccessRights::PRESENT) {
+        EventInjection::vmentry_inject_exception(
+            vmcs,
+            ExceptionInterrupt::SegmentNotPresent,
+            error_code,
+        );
+        return Ok(ExitType::Continue);
+    }
+
+    let base = low.get_bits(16..40) | (low.get_bits(56..64) << 24) | (high.get_bits(0..32) << 32);
+    let mut limit = low.get_bits(0..16) | (low.get_bits(48..52) << 16);
+
+    if access_rights.contains(SegmentAccessRights::GRANULARITY) {
+        limit = (limit << 12) | 0xFFF;
+    }
+
+    let mut access_rights = access_rights.bits();
+
+    if is_ltr {
+        guest_memory.write::<u64>(descriptor_address, low | TSS_BUSY)?;
+        access_rights |= (TSS_BUSY >> 40) as u32;
+    }
+
+    vmcs.set(selector_field, selector);
+    vmcs.set(base_field, base);
+    vmcs.set(limit_field, limit as u32);
+    vmcs.set(access_rights_field, access_rights);
+
+    log::debug!("LDTR/TR access VMEXIT handled successfully!");
+
+    Ok(ExitType::IncrementRIP)
+}
@@ -1,22 +1,22 @@
-use {
-    crate::{
-        intel::{
-            invept::invept_all_contexts, support::vmread, support::vmwrite,
-            vmerror::EptViolationExitQualification, vmexit::ExitType, vmx::Vmx,
-        },
-        utils::{addresses::PhysicalAddress, capture::GuestRegisters},
+use crate::{
+    intel::{
+        cpu_access::CpuAccess,
+        vmcs_access::{VmcsAccess, VmcsFieldAccess},
+        vmcs_fields::{control, ro},
+        vmerror::EptViolationExitQualification,
+        vmexit::{registry::VmExitData, ExitType},
     },
-    x86::vmx::vmcs,
+    utils::{addresses::PhysicalAddress, capture::GuestRegisters},
 };
 
 /// Handle VM exits for EPT violations. Violations are thrown whenever an operation is performed on an EPT entry that does not provide permissions to access that page.
 /// 29.3.3.2 EPT Violations
 /// Table 28-7. Exit Qualification for EPT Violations
 #[rustfmt::skip]
-pub fn handle_ept_violation(_guest_registers: &mut GuestRegisters, vmx: &mut Vmx) -> ExitType {
+pub fn handle_ept_violation(_guest_registers: &mut GuestRegisters, data: &VmExitData, vmcs: &mut dyn VmcsAccess, cpu: &mut dyn CpuAccess) -> ExitType {
     log::debug!("Handling EPT Violation VM exit...");
 
-    let guest_physical_address = vmread(vmcs::ro::GUEST_PHYSICAL_ADDR_FULL);
+    let guest_physical_address = vmcs.get(ro::GUEST_PHYSICAL_ADDR_FULL);
     log::debug!("EPT Violation: Guest Physical Address: {:#x}", guest_physical_address);
 
     // Translate the page from a physical address to virtual so we can read its memory.
@@ -24,7 +24,7 @@ pub fn handle_ept_violation(_guest_registers: &mut GuestRegisters, vmx: &mut Vmx
     log::debug!("EPT Violation: Guest Virtual Address: {:#x}", va);
 
     // Log the detailed information about the EPT violation
-    let exit_qualification_value = vmread(vmcs::ro::EXIT_QUALIFICATION);
+    let exit_qualification_value = vmcs.get(ro::EXIT_QUALIFICATION);
     let ept_violation_qualification = EptViolationExitQualification::from_exit_qualification(exit_qualification_value);
     log::debug!("Exit Qualification for EPT Violations: {}", ept_violation_qualification);
 
@@ -35,9 +35,9 @@ pub fn handle_ept_violation(_guest_registers: &mut GuestRegisters, vmx: &mut Vmx
         // The hooked page that is Execute-Only will be executed from the secondary EPTP.
         // if Read or Write occurs on that page, then a vmexit will occur
         // and we can swap the page back to the primary EPTP, (original page) with RW permissions.
-        let secondary_eptp = unsafe { vmx.shared_data.as_mut().secondary_eptp };
-        vmwrite(vmcs::control::EPTP_FULL, secondary_eptp);
-        invept_all_contexts();
+        #[cfg(feature = "secondary-ept")]
+        vmcs.set(control::EPTP_FULL, data.secondary_eptp);
+        cpu.invept_all_contexts();
         //invept_single_context(secondary_eptp);
     }
 
@@ -47,9 +47,8 @@ pub fn handle_ept_violation(_guest_registers: &mut GuestRegisters, vmx: &mut Vmx
         // The original page that is Read-Write-Only will be executed from the primary EPTP.
         // if Execute occurs on that page, then a vmexit will occur
         // and we can swap the page back to the secondary EPTP, (hooked page) with X permissions.
-        let primary_eptp = unsafe { vmx.shared_data.as_mut().primary_eptp };
-        vmwrite(vmcs::control::EPTP_FULL, primary_eptp);
-        invept_all_contexts();
+        vmcs.set(control::EPTP_FULL, data.primary_eptp);
+        cpu.invept_all_contexts();
         //invept_single_context(primary_eptp);
     }
 
@@ -77,11 +76,11 @@ pub fn handle_ept_violation(_guest_registers: &mut GuestRegisters, vmx: &mut Vmx
 ///
 /// Reference: 29.3.3.1 EPT Misconfigurations
 #[rustfmt::skip]
-pub fn handle_ept_misconfiguration() -> ExitType {
+pub fn handle_ept_misconfiguration(vmcs: &dyn VmcsAccess) -> ExitType {
     log::debug!("Handling EPT Misconfiguration VM exit...");
 
     // Retrieve the guest physical address that caused the EPT misconfiguration.
-    let guest_physical_address = vmread(vmcs::ro::GUEST_PHYSICAL_ADDR_FULL);
+    let guest_physical_address = vmcs.get(ro::GUEST_PHYSICAL_ADDR_FULL);
 
     // Log the critical error information.
     log::trace!("EPT Misconfiguration: Faulting guest address: {:#x}. This is a critical error that cannot be safely ignored.", guest_physical_address);
@@ -94,5 +93,5 @@ pub fn handle_ept_misconfiguration() -> ExitType {
     // EPT misconfiguration is a fatal exception and continuing may lead to system crashes.
 
     // We may chose to exit the hypervisor here instead of triggering a breakpoint exception.
-    return ExitType::ExitHypervisor;
+    ExitType::ExitHypervisor
 }
@@ -2,21 +2,29 @@
 //! It includes handling for various types of exceptions such as page faults,
 //! general protection faults, breakpoints, and invalid opcodes.
 
-use {
-    crate::{
-        intel::{
-            ept::hooks::HookType,
-            events::EventInjection,
-            support::{vmread, vmwrite},
-            vmerror::{
-                EptViolationExitQualification, ExceptionInterrupt, VmExitInterruptionInformation,
-            },
-            vmexit::ExitType,
-            vmx::Vmx,
+use crate::{
+    error::HypervisorError,
+    intel::{
+        cpu_access::CpuAccess,
+        ept::hooks::{HookManager, HookType},
+        events::EventInjection,
+        exit_qualification::DebugExceptionQualification,
+        guest_memory::GuestMemory,
+        vmcs_access::{VmcsAccess, VmcsFieldAccess},
+        vmcs_fields::{guest, ro},
+        vmerror::{
+            EptViolationExitQualification, ExceptionInterrupt, VmExitInterruptionInformation,
         },
-        utils::capture::GuestRegisters,
+        vmexit::{
+            registry::VmExitData,
+            syscall::{handle_syscall_entry, handle_syscall_instruction, handle_syscall_return},
+            ExitType,
+        },
+    },
+    utils::{
+        capture::GuestRegisters,
+        function_hook::{HookAction, HookHandler},
     },
-    x86::vmx::vmcs,
 };
 
 /// Handles exceptions and NMIs that occur during VM execution.
@@ -28,35 +36,50 @@ use {
 /// # Arguments
 ///
 /// * `guest_registers` - A mutable reference to the guest's register state.
-/// * `vmx` - A mutable reference to the Vmx structure representing the current VM.
+/// * `data` - The shared state of the hooks and of the syscall tracer.
+/// * `vmcs` - The VMCS of the guest.
+/// * `cpu` - The processor whose syscall MSRs are used to emulate `syscall` and `sysret`, and whose CR2 and DR6
+///   are loaded before a page fault or a debug exception is delivered.
 ///
 /// # Returns
 ///
 /// * `ExitType::Continue` - Indicating that VM execution should continue after handling the exception
 #[rustfmt::skip]
-pub fn handle_exception(guest_registers: &mut GuestRegisters, vmx: &mut Vmx) -> ExitType {
+pub fn handle_exception(guest_registers: &mut GuestRegisters, data: &VmExitData, vmcs: &mut dyn VmcsAccess, cpu: &mut dyn CpuAccess) -> ExitType {
     log::debug!("Handling ExceptionOrNmi VM exit...");
 
-    let interruption_info_value = vmread(vmcs::ro::VMEXIT_INTERRUPTION_INFO);
-    let interruption_error_code_value = vmread(vmcs::ro::VMEXIT_INTERRUPTION_ERR_CODE);
+    let interruption_info_value = vmcs.get(ro::VMEXIT_INTERRUPTION_INFO);
+    let interruption_error_code_value = vmcs.get(ro::VMEXIT_INTERRUPTION_ERR_CODE);
 
-    if let Some(interruption_info) = VmExitInterruptionInformation::from_u32(interruption_info_value as u32) {
+    if let Some(interruption_info) = VmExitInterruptionInformation::from_u32(interruption_info_value) {
         if let Some(exception_interrupt) = ExceptionInterrupt::from_u32(interruption_info.vector.into()) {
             match exception_interrupt {
                 ExceptionInterrupt::PageFault => {
-                    let exit_qualification_value = vmread(vmcs::ro::EXIT_QUALIFICATION);
-                    let ept_violation_qualification = EptViolationExitQualification::from_exit_qualification(exit_qualification_value);
-                    log::trace!("Exit Qualification for EPT Violations: {}", ept_violation_qualification);
-                    EventInjection::vmentry_inject_pf(interruption_error_code_value as u32);
+                    let exit_qualification_value = vmcs.get(ro::EXIT_QUALIFICATION);
+
+                    // With kernel virtual address shadowing, the syscall dispatcher isn't mapped in the user address space.
+                    if !handle_syscall_dispatcher_fault(guest_registers, data, vmcs, cpu, exit_qualification_value) {
+                        let ept_violation_qualification = EptViolationExitQualification::from_exit_qualification(exit_qualification_value);
+                        log::trace!("Exit Qualification for EPT Violations: {}", ept_violation_qualification);
+
+                        // The VM exit doesn't update CR2, so it's loaded with the faulting address before the page fault is delivered.
+                        cpu.write_cr2(exit_qualification_value);
+                        EventInjection::vmentry_inject_pf(vmcs, interruption_error_code_value);
+                    }
+                },
+                ExceptionInterrupt::Debug => {
+                    handle_debug_exception(guest_registers, data, vmcs, cpu);
                 },
                 ExceptionInterrupt::GeneralProtectionFault => {
-                    EventInjection::vmentry_inject_gp(interruption_error_code_value as u32);
+                    EventInjection::vmentry_inject_gp(vmcs, interruption_error_code_value);
                 },
                 ExceptionInterrupt::Breakpoint => {
-                    handle_breakpoint_exception(guest_registers, vmx);
+                    handle_breakpoint_exception(guest_registers, data, vmcs, cpu);
                 },
                 ExceptionInterrupt::InvalidOpcode => {
-                    EventInjection::vmentry_inject_ud();
+                    if !handle_syscall_tracer_fault(guest_registers, data, vmcs, cpu) {
+                        EventInjection::vmentry_inject_ud(vmcs);
+                    }
                 },
                 _ => {
                     panic!("Unhandled exception: {:?}", exception_interrupt);
@@ -77,47 +100,299 @@ pub fn handle_exception(guest_registers: &mut GuestRegisters, vmx: &mut Vmx) ->
 /// Handles breakpoint (`#BP`) exceptions specifically.
 ///
 /// When a breakpoint exception occurs, this function checks for a registered hook
-/// at the current instruction pointer (RIP). If a hook is found, it transfers control
-/// to the hook's handler. Otherwise, it injects a breakpoint exception into the VM.
+/// at the current instruction pointer (RIP). If a hook is found, it runs the hook's handler chain,
+/// which either transfers control to a guest-side handler or runs callbacks in VMX root operation.
+/// Otherwise, it injects a breakpoint exception into the VM.
 ///
 /// # Arguments
 ///
 /// * `guest_registers` - A mutable reference to the guest's current register state.
-/// * `vmx` - A mutable reference to the Vmx structure.
-fn handle_breakpoint_exception(guest_registers: &mut GuestRegisters, vmx: &mut Vmx) {
+/// * `data` - The shared state of the hooks and of the syscall tracer.
+/// * `vmcs` - The VMCS of the guest.
+/// * `cpu` - The processor whose syscall MSRs are used.
+fn handle_breakpoint_exception(
+    guest_registers: &mut GuestRegisters,
+    data: &VmExitData,
+    vmcs: &mut dyn VmcsAccess,
+    cpu: &dyn CpuAccess,
+) {
     log::debug!("Breakpoint Exception");
 
-    let hook_manager = unsafe { vmx.shared_data.as_mut().hook_manager.as_mut() };
+    // A syscall entered the dispatcher of the syscall hooks.
+    //
+    if let Some(syscall_hooks) = data.syscall_hooks {
+        if syscall_hooks.is_dispatcher(guest_registers.rip) {
+            handle_syscall_entry(guest_registers, syscall_hooks, vmcs, cpu);
+            return;
+        }
+    }
+
+    let Some(hook_manager) = data.hook_manager else {
+        EventInjection::vmentry_inject_bp(vmcs);
+        log::debug!("Breakpoint exception handled successfully!");
+        return;
+    };
+
+    // A hooked function returned to the return thunk, so execute its return callbacks
+    // and resume at the original return address.
+    //
+    if hook_manager.return_hooks.is_thunk(guest_registers.rip) {
+        handle_return_thunk(guest_registers, hook_manager, vmcs);
+        return;
+    }
 
     log::trace!("Finding hook for RIP: {:#x}", guest_registers.rip);
 
-    // Find the handler address for the current instruction pointer (RIP) and
-    // transfer the execution to it. If we couldn't find a hook, we inject the
-    // #BP exception.
+    // Find the hook for the current instruction pointer (RIP). If we couldn't find a hook,
+    // we inject the #BP exception.
+    //
+    let Some(inline_hook) = hook_manager
+        .find_hook_by_address(guest_registers.rip)
+        .and_then(|hook| match &hook.hook_type {
+            HookType::Function { inline_hook } => Some(inline_hook),
+            HookType::Page => None,
+        })
+    else {
+        EventInjection::vmentry_inject_bp(vmcs);
+        log::debug!("Breakpoint exception handled successfully!");
+        return;
+    };
+
+    log::trace!("Found hook for RIP: {:#x}", guest_registers.rip);
+
+    let function_address = guest_registers.rip;
+    let guest_memory = GuestMemory::current(vmcs);
+
+    // Run the handler chain in order of priority. Callbacks pass through to the next handler by
+    // continuing, and short-circuit the chain (including the original function) by returning.
     //
-    if let Some(Some(handler)) =
-        hook_manager
-            .find_hook_by_address(guest_registers.rip)
-            .map(|hook| {
-                log::trace!("Found hook for RIP: {:#x}", guest_registers.rip);
-                if let HookType::Function { inline_hook } = &hook.hook_type {
-                    log::trace!("Getting handler address");
-                    Some(inline_hook.handler_address())
-                } else {
-                    None
+    let mut next_rip = inline_hook.trampoline_address() as u64;
+    let mut install_return_hook = false;
+
+    for chained in inline_hook.handlers() {
+        let action = match chained.handler {
+            HookHandler::Redirect(handler) => {
+                // Call our hook handle function (it will automatically call trampoline).
+                log::trace!("Transferring execution to handler: {:#x}", handler);
+                next_rip = handler;
+                break;
+            }
+            HookHandler::Callback(callback) => {
+                log::trace!("Executing root-mode hook callback");
+                callback(guest_registers, &guest_memory)
+            }
+            HookHandler::CallbackWithReturn { entry, .. } => {
+                log::trace!("Executing root-mode hook entry callback");
+                install_return_hook = true;
+                entry(guest_registers, &guest_memory)
+            }
+        };
+
+        if let HookAction::Return(value) = action {
+            log::trace!("Hook callback short-circuited with {:#x}", value);
+
+            match return_to_caller(guest_registers, &guest_memory, value) {
+                Ok(()) => {
+                    next_rip = guest_registers.rip;
+                    install_return_hook = false;
                 }
-            })
+                Err(e) => log::error!("Failed to return to caller: {:?}", e),
+            }
+            break;
+        }
+    }
+
+    if install_return_hook {
+        if let Err(e) =
+            hook_manager
+                .return_hooks
+                .install(guest_registers, &guest_memory, function_address)
+        {
+            log::error!("Failed to install return hook: {:?}", e);
+        }
+    }
+
+    guest_registers.rip = next_rip;
+
+    // The callbacks may have changed the stack pointer and the flags as well.
+    vmcs.set(guest::RIP, guest_registers.rip);
+    vmcs.set(guest::RSP, guest_registers.rsp);
+    vmcs.set(guest::RFLAGS, guest_registers.rflags);
+
+    log::debug!("Breakpoint (int3) hook handled successfully!");
+}
+
+/// Handles a return of a hooked function to the return thunk.
+///
+/// Executes the return callbacks of the hook in reverse order of the entry callbacks and
+/// resumes guest execution at the original return address.
+///
+/// # Arguments
+///
+/// * `guest_registers` - A mutable reference to the guest's current register state.
+/// * `hook_manager` - The hook manager holding the hooks and the calls pending a return.
+/// * `vmcs` - The VMCS of the guest.
+fn handle_return_thunk(
+    guest_registers: &mut GuestRegisters,
+    hook_manager: &HookManager,
+    vmcs: &mut dyn VmcsAccess,
+) {
+    log::trace!("Return thunk hit with RSP: {:#x}", guest_registers.rsp);
+
+    let guest_memory = GuestMemory::current(vmcs);
+
+    let Some(context) = hook_manager
+        .return_hooks
+        .take_return(guest_registers, &guest_memory)
+    else {
+        log::error!("No pending return for RSP: {:#x}", guest_registers.rsp);
+        EventInjection::vmentry_inject_bp(vmcs);
+        return;
+    };
+
+    log::trace!(
+        "Handling return of {:#x} to {:#x}",
+        context.function_address,
+        context.return_address
+    );
+
+    if let Some(HookType::Function { inline_hook }) = hook_manager
+        .find_hook_by_address(context.function_address)
+        .map(|hook| &hook.hook_type)
     {
-        // Call our hook handle function (it will automatically call trampoline).
-        log::trace!("Transferring execution to handler: {:#x}", handler);
-        guest_registers.rip = handler;
-        vmwrite(vmcs::guest::RIP, guest_registers.rip);
+        for callback in inline_hook.return_callbacks().rev() {
+            callback(guest_registers, &guest_memory, &context);
+        }
+    }
 
-        log::debug!("Breakpoint (int3) hook handled successfully!");
-    } else {
-        EventInjection::vmentry_inject_bp();
-        log::debug!("Breakpoint exception handled successfully!");
+    guest_registers.rip = context.return_address;
+
+    vmcs.set(guest::RIP, guest_registers.rip);
+    vmcs.set(guest::RFLAGS, guest_registers.rflags);
+
+    log::debug!("Return hook handled successfully!");
+}
+
+/// Emulates a `ret` at function entry, so the hooked function returns `value` to its caller without executing.
+///
+/// # Arguments
+///
+/// * `guest_registers` - The guest registers at function entry.
+/// * `guest_memory` - The accessor for the guest memory.
+/// * `value` - The return value placed in RAX.
+fn return_to_caller(
+    guest_registers: &mut GuestRegisters,
+    guest_memory: &GuestMemory,
+    value: u64,
+) -> Result<(), HypervisorError> {
+    let return_address = guest_memory.read::<u64>(guest_registers.rsp)?;
+
+    guest_registers.rax = value;
+    guest_registers.rip = return_address;
+    guest_registers.rsp += core::mem::size_of::<u64>() as u64;
+
+    Ok(())
+}
+
+/// Handles instruction fetch page faults (`#PF`) at the dispatcher of the syscall hooks.
+///
+/// # Arguments
+///
+/// * `guest_registers` - A mutable reference to the guest's current register state.
+/// * `data` - The shared state of the hooks and of the syscall tracer.
+/// * `vmcs` - The VMCS of the guest.
+/// * `cpu` - The processor whose syscall MSRs are used.
+/// * `faulting_address` - The faulting linear address from the exit qualification.
+///
+/// # Returns
+///
+/// * `bool` - `true` if the page fault was caused by a syscall entering the dispatcher, `false` otherwise.
+fn handle_syscall_dispatcher_fault(
+    guest_registers: &mut GuestRegisters,
+    data: &VmExitData,
+    vmcs: &mut dyn VmcsAccess,
+    cpu: &dyn CpuAccess,
+    faulting_address: u64,
+) -> bool {
+    let Some(syscall_hooks) = data.syscall_hooks else {
+        return false;
     };
+
+    if !syscall_hooks.is_dispatcher(guest_registers.rip)
+        || !syscall_hooks.is_dispatcher(faulting_address)
+    {
+        return false;
+    }
+
+    handle_syscall_entry(guest_registers, syscall_hooks, vmcs, cpu);
+
+    true
+}
+
+/// Handles invalid opcode (`#UD`) exceptions raised by `syscall` and `sysret` for the syscall tracer.
+///
+/// # Arguments
+///
+/// * `guest_registers` - A mutable reference to the guest's current register state.
+/// * `data` - The shared state of the hooks and of the syscall tracer.
+/// * `vmcs` - The VMCS of the guest.
+/// * `cpu` - The processor whose syscall MSRs are used.
+///
+/// # Returns
+///
+/// * `bool` - `true` if the exception was raised by `syscall` or `sysret` and handled, `false` otherwise.
+fn handle_syscall_tracer_fault(
+    guest_registers: &mut GuestRegisters,
+    data: &VmExitData,
+    vmcs: &mut dyn VmcsAccess,
+    cpu: &dyn CpuAccess,
+) -> bool {
+    let Some(syscall_tracer) = data.syscall_tracer else {
+        return false;
+    };
+
+    handle_syscall_instruction(guest_registers, syscall_tracer, vmcs, cpu)
+}
+
+/// Handles debug (`#DB`) exceptions.
+///
+/// Single steps caused by the syscall hooks are handled by executing the post callbacks,
+/// all other debug exceptions are delivered to the guest.
+///
+/// # Arguments
+///
+/// * `guest_registers` - A mutable reference to the guest's current register state.
+/// * `data` - The shared state of the hooks and of the syscall tracer.
+/// * `vmcs` - The VMCS of the guest.
+/// * `cpu` - The processor whose DR6 is updated before the debug exception is delivered.
+fn handle_debug_exception(
+    guest_registers: &mut GuestRegisters,
+    data: &VmExitData,
+    vmcs: &mut dyn VmcsAccess,
+    cpu: &mut dyn CpuAccess,
+) {
+    log::debug!("Debug Exception");
+
+    let exit_qualification =
+        DebugExceptionQualification::from_u64(vmcs.get(ro::EXIT_QUALIFICATION));
+
+    if exit_qualification.single_step {
+        if let Some(syscall_hooks) = data.syscall_hooks {
+            if handle_syscall_return(guest_registers, syscall_hooks, vmcs) {
+                log::debug!("Syscall return handled successfully!");
+                return;
+            }
+        }
+    }
+
+    // The VM exit doesn't update DR6, so it's updated before the debug exception is delivered.
+    //
+    // Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 28.2.1 Basic VM-Exit Information
+    cpu.write_dr6(cpu.read_dr6() | exit_qualification.dr6_bits());
+
+    EventInjection::vmentry_inject_db(vmcs);
+
+    log::debug!("Debug exception handled successfully!");
 }
 
 /// Handles undefined opcode (`#UD`) exceptions.
@@ -125,15 +400,84 @@ fn handle_breakpoint_exception(guest_registers: &mut GuestRegisters, vmx: &mut V
 /// This function is invoked when the VM attempts to execute an invalid or undefined
 /// opcode. It injects an undefined opcode exception into the VM.
 ///
+/// # Arguments
+///
+/// * `vmcs` - The VMCS of the guest.
+///
 /// # Returns
 ///
 /// * `ExitType::Continue` - Indicating that VM execution should continue.
-pub fn handle_undefined_opcode_exception() -> ExitType {
+pub fn handle_undefined_opcode_exception(vmcs: &mut dyn VmcsAccess) -> ExitType {
     log::debug!("Undefined Opcode Exception");
 
-    EventInjection::vmentry_inject_ud();
+    EventInjection::vmentry_inject_ud(vmcs);
 
     log::debug!("Undefined Opcode Exception handled successfully!");
 
     ExitType::Continue
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::intel::{cpu_access::SoftCpu, vmcs_access::SoftVmcs, vmcs_fields::control},
+    };
+
+    /// The VM-exit interruption information of a hardware exception with an error code.
+    fn hardware_exception(vector: ExceptionInterrupt, error_code_valid: bool) -> u32 {
+        (1 << 31) | (u32::from(error_code_valid) << 11) | (3 << 8) | vector as u32
+    }
+
+    #[test]
+    fn reflected_page_faults_load_the_faulting_address_into_cr2() {
+        let mut vmcs = SoftVmcs::new()
+            .with(
+                ro::VMEXIT_INTERRUPTION_INFO,
+                hardware_exception(ExceptionInterrupt::PageFault, true),
+            )
+            .with(ro::VMEXIT_INTERRUPTION_ERR_CODE, 0b110)
+            .with(ro::EXIT_QUALIFICATION, 0x0000_7FF6_1234_5678);
+        let mut cpu = SoftCpu::new();
+
+        handle_exception(
+            &mut GuestRegisters::default(),
+            &VmExitData::default(),
+            &mut vmcs,
+            &mut cpu,
+        );
+
+        assert_eq!(cpu.cr2(), 0x0000_7FF6_1234_5678);
+        assert_eq!(
+            vmcs.value(control::VMENTRY_INTERRUPTION_INFO_FIELD),
+            Some(hardware_exception(ExceptionInterrupt::PageFault, true))
+        );
+        assert_eq!(vmcs.value(control::VMENTRY_EXCEPTION_ERR_CODE), Some(0b110));
+    }
+
+    #[test]
+    fn reflected_debug_exceptions_add_the_conditions_to_dr6() {
+        // B1 and BS.
+        let mut vmcs = SoftVmcs::new()
+            .with(
+                ro::VMEXIT_INTERRUPTION_INFO,
+                hardware_exception(ExceptionInterrupt::Debug, false),
+            )
+            .with(ro::EXIT_QUALIFICATION, (1 << 14) | (1 << 1));
+        let mut cpu = SoftCpu::new().with_dr6(0xFFFF_0FF0);
+
+        handle_exception(
+            &mut GuestRegisters::default(),
+            &VmExitData::default(),
+            &mut vmcs,
+            &mut cpu,
+        );
+
+        assert_eq!(cpu.dr6(), 0xFFFF_0FF0 | (1 << 14) | (1 << 1));
+        assert_eq!(
+            vmcs.value(control::VMENTRY_INTERRUPTION_INFO_FIELD)
+                .map(|info| info & 0xFF),
+            Some(ExceptionInterrupt::Debug as u32)
+        );
+    }
+}
@@ -0,0 +1,87 @@
+//! Handles VM exits due to the instructions that idle or throttle the processor: HLT, MONITOR, MWAIT,
+//! PAUSE, TPAUSE and UMWAIT.
+
+use crate::intel::{
+    vmcs_access::{VmcsAccess, VmcsFieldAccess},
+    vmcs_fields::guest,
+    vmexit::ExitType,
+};
+
+/// The HLT activity state of the guest.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 25.4.2 Guest Non-Register State
+const ACTIVITY_STATE_HLT: u32 = 1;
+
+/// The blocking by STI and blocking by MOV SS bits of the guest interruptibility state.
+const BLOCKING_BY_STI_OR_MOV_SS: u32 = 0b11;
+
+/// The status flags of RFLAGS: CF, PF, AF, ZF, SF and OF.
+const RFLAGS_STATUS_FLAGS: u64 = 0x8D5;
+
+/// Handles the HLT VM exit by halting the guest in the HLT activity state.
+///
+/// The instruction is completed, so the blocking by STI of `sti; hlt` ends and the guest is woken up by
+/// the next interrupt after VM-entry.
+///
+/// # Arguments
+///
+/// * `vmcs` - The VMCS of the guest.
+///
+/// # Returns
+///
+/// * `ExitType::IncrementRIP` - To move past the `HLT` instruction in the VM.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 27.7.4 VM Entries and the HLT Activity State
+pub fn handle_hlt(vmcs: &mut dyn VmcsAccess) -> ExitType {
+    log::debug!("Handling HLT VM exit...");
+
+    let interruptibility_state = vmcs.get(guest::INTERRUPTIBILITY_STATE);
+    vmcs.set(
+        guest::INTERRUPTIBILITY_STATE,
+        interruptibility_state & !BLOCKING_BY_STI_OR_MOV_SS,
+    );
+    vmcs.set(guest::ACTIVITY_STATE, ACTIVITY_STATE_HLT);
+
+    log::debug!("HLT VMEXIT handled successfully!");
+
+    ExitType::IncrementRIP
+}
+
+/// Handles the MONITOR, MWAIT and PAUSE VM exits by executing them as NOPs.
+///
+/// MWAIT is allowed to return at any time, so the address range armed by MONITOR doesn't need to be
+/// monitored.
+///
+/// # Returns
+///
+/// * `ExitType::IncrementRIP` - To move past the instruction in the VM.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 9.10.4 MONITOR/MWAIT Instruction
+pub fn handle_monitor_mwait_pause() -> ExitType {
+    log::debug!("Handling MONITOR/MWAIT/PAUSE VM exit...");
+
+    core::hint::spin_loop();
+
+    ExitType::IncrementRIP
+}
+
+/// Handles the TPAUSE and UMWAIT VM exits by waking up immediately.
+///
+/// The status flags are cleared, and RFLAGS.CF reports that the wait didn't end because of the OS time
+/// limit.
+///
+/// # Arguments
+///
+/// * `vmcs` - The VMCS of the guest.
+///
+/// # Returns
+///
+/// * `ExitType::IncrementRIP` - To move past the instruction in the VM.
+pub fn handle_tpause_umwait(vmcs: &mut dyn VmcsAccess) -> ExitType {
+    log::debug!("Handling TPAUSE/UMWAIT VM exit...");
+
+    let rflags = vmcs.get(guest::RFLAGS);
+    vmcs.set(guest::RFLAGS, rflags & !RFLAGS_STATUS_FLAGS);
+
+    ExitType::IncrementRIP
+}
@@ -0,0 +1,136 @@
+//! Handles VM exits related to the delivery of events: external interrupts, interrupt and NMI windows,
+//! the monitor trap flag and the notify window of instruction timeouts.
+
+use {
+    crate::{
+        error::HypervisorError,
+        intel::{
+            events::EventInjection,
+            vmcs_access::{VmcsAccess, VmcsFieldAccess},
+            vmcs_fields::{control, ro},
+            vmerror::VmExitInterruptionInformation,
+            vmexit::ExitType,
+        },
+    },
+    x86::vmx::vmcs::control::PrimaryControls,
+};
+
+/// Handles the external interrupt VM exit.
+///
+/// If the interrupt was acknowledged on VM exit, its vector is valid in the VM-exit interruption
+/// information and it's injected into the guest. Otherwise, it remains pending in the local APIC and is
+/// delivered to the guest after VM-entry.
+///
+/// # Arguments
+///
+/// * `vmcs` - The VMCS of the guest.
+///
+/// # Returns
+///
+/// * `ExitType::Continue` - The guest continues with the interrupt.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 28.2.2 Information for VM Exits Due to Vectored Events
+pub fn handle_external_interrupt(vmcs: &mut dyn VmcsAccess) -> ExitType {
+    log::debug!("Handling external interrupt VM exit...");
+
+    let interruption_info = vmcs.get(ro::VMEXIT_INTERRUPTION_INFO);
+
+    match VmExitInterruptionInformation::from_u32(interruption_info) {
+        Some(information) if information.valid => {
+            log::trace!("Reinjecting external interrupt {:#x}", information.vector);
+            EventInjection::vmentry_inject_external_interrupt(vmcs, information.vector);
+        }
+        _ => log::trace!("External interrupt remains pending"),
+    }
+
+    log::debug!("External interrupt VMEXIT handled successfully!");
+
+    ExitType::Continue
+}
+
+/// Handles the interrupt-window VM exit by disabling interrupt-window exiting.
+///
+/// # Arguments
+///
+/// * `vmcs` - The VMCS of the guest.
+///
+/// # Returns
+///
+/// * `ExitType::Continue` - The guest can now receive interrupts.
+pub fn handle_interrupt_window(vmcs: &mut dyn VmcsAccess) -> ExitType {
+    log::debug!("Handling interrupt-window VM exit...");
+
+    clear_primary_control(vmcs, PrimaryControls::INTERRUPT_WINDOW_EXITING);
+
+    ExitType::Continue
+}
+
+/// Handles the NMI-window VM exit by disabling NMI-window exiting.
+///
+/// # Arguments
+///
+/// * `vmcs` - The VMCS of the guest.
+///
+/// # Returns
+///
+/// * `ExitType::Continue` - The guest can now receive NMIs.
+pub fn handle_nmi_window(vmcs: &mut dyn VmcsAccess) -> ExitType {
+    log::debug!("Handling NMI-window VM exit...");
+
+    clear_primary_control(vmcs, PrimaryControls::NMI_WINDOW_EXITING);
+
+    ExitType::Continue
+}
+
+/// Handles the monitor trap flag VM exit by disabling the monitor trap flag.
+///
+/// # Arguments
+///
+/// * `vmcs` - The VMCS of the guest.
+///
+/// # Returns
+///
+/// * `ExitType::Continue` - The instruction has already been executed.
+pub fn handle_monitor_trap_flag(vmcs: &mut dyn VmcsAccess) -> ExitType {
+    log::debug!("Handling monitor trap flag VM exit...");
+
+    clear_primary_control(vmcs, PrimaryControls::MONITOR_TRAP_FLAG);
+
+    ExitType::Continue
+}
+
+/// Handles the instruction timeout (notify) VM exit, which occurs when the processor doesn't reach an
+/// instruction boundary within the notify window.
+///
+/// # Arguments
+///
+/// * `vmcs` - The VMCS of the guest.
+///
+/// # Returns
+///
+/// * `Result<ExitType, HypervisorError>` - `ExitType::Continue`, or `UnrecoverableVmExit` if the VM context is invalid.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 26.2 OTHER CAUSES OF VM EXITS
+pub fn handle_instruction_timeout(vmcs: &mut dyn VmcsAccess) -> Result<ExitType, HypervisorError> {
+    /// The VM-context-invalid bit of the exit qualification.
+    const VM_CONTEXT_INVALID: u64 = 1 << 12;
+
+    log::debug!("Handling instruction timeout VM exit...");
+
+    if vmcs.get(ro::EXIT_QUALIFICATION) & VM_CONTEXT_INVALID != 0 {
+        log::error!("The VM context is invalid after the instruction timeout");
+        return Err(HypervisorError::UnrecoverableVmExit);
+    }
+
+    Ok(ExitType::Continue)
+}
+
+/// Clears a primary processor-based VM-execution control.
+fn clear_primary_control(vmcs: &mut dyn VmcsAccess, flag: PrimaryControls) {
+    let controls = vmcs.get(control::PRIMARY_PROCBASED_EXEC_CONTROLS);
+
+    vmcs.set(
+        control::PRIMARY_PROCBASED_EXEC_CONTROLS,
+        controls & !flag.bits(),
+    );
+}
@@ -24,3 +24,25 @@ pub fn handle_invd(_guest_registers: &mut GuestRegisters) -> ExitType {
 
     ExitType::IncrementRIP
 }
+
+/// Manages the WBINVD and WBNOINVD instruction VM exits by writing back and invalidating
+/// the caches, and advancing the guest's instruction pointer.
+///
+/// WBNOINVD is emulated with WBINVD, which additionally invalidates the caches.
+///
+/// # Arguments
+///
+/// * `registers` - General-purpose registers of the guest VM at the VM exit.
+///
+/// # Returns
+///
+/// * `ExitType::IncrementRIP` - To move past the `WBINVD` or `WBNOINVD` instruction in the VM.
+pub fn handle_wbinvd(_guest_registers: &mut GuestRegisters) -> ExitType {
+    log::debug!("Handling WBINVD VM exit...");
+
+    wbinvd();
+
+    log::debug!("WBINVD VMEXIT handled successfully!");
+
+    ExitType::IncrementRIP
+}
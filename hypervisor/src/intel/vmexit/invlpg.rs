@@ -0,0 +1,126 @@
+//! Handles INVLPG and INVPCID VM exits by invalidating the TLB entries of the guest.
+//!
+//! The translations of the guest are tagged with its VPID, so they're invalidated with INVVPID instead of
+//! executing the instruction in VMX root operation, which would only invalidate the translations of the
+//! hypervisor.
+
+use crate::{
+    error::HypervisorError,
+    intel::{
+        cpu_access::CpuAccess,
+        events::EventInjection,
+        exit_qualification::{InvalidationInstructionInformation, InvlpgQualification},
+        guest_memory::GuestMemory,
+        vmcs_access::{VmcsAccess, VmcsFieldAccess},
+        vmcs_fields::{control, ro},
+        vmexit::{guest_register, operand_linear_address, ExitType},
+    },
+    utils::capture::GuestRegisters,
+};
+
+/// The INVPCID descriptor.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: Figure 3-24. INVPCID Descriptor
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct InvpcidDescriptor {
+    /// The PCID (bits 11:0), the other bits are reserved.
+    pcid: u64,
+    /// The linear address.
+    linear_address: u64,
+}
+
+/// Handles the `INVLPG` VM-exit by invalidating the linear address for the VPID of the guest.
+///
+/// # Arguments
+///
+/// * `vmcs` - The VMCS of the guest.
+/// * `cpu` - The processor executing `INVVPID`.
+///
+/// # Returns
+///
+/// * `ExitType::IncrementRIP` - To move past the `INVLPG` instruction in the VM.
+pub fn handle_invlpg(vmcs: &mut dyn VmcsAccess, cpu: &mut dyn CpuAccess) -> ExitType {
+    log::debug!("Handling INVLPG VM exit...");
+
+    let qualification = InvlpgQualification::from_u64(vmcs.get(ro::EXIT_QUALIFICATION));
+    let vpid = vmcs.get(control::VPID);
+
+    cpu.invvpid_individual_address(vpid, qualification.linear_address);
+
+    log::debug!("INVLPG VMEXIT handled successfully!");
+
+    ExitType::IncrementRIP
+}
+
+/// Handles the `INVPCID` VM-exit.
+///
+/// The individual-address invalidation is performed for the linear address, and the other types
+/// invalidate all the translations of the VPID of the guest, which includes those of the PCID. The guest
+/// receives #GP(0) for invalid types and descriptors, like without VMX.
+///
+/// # Arguments
+///
+/// * `guest_registers` - The guest's current register state.
+/// * `vmcs` - The VMCS of the guest.
+/// * `cpu` - The processor executing `INVVPID`.
+///
+/// # Returns
+///
+/// * `Result<ExitType, HypervisorError>` - `ExitType::IncrementRIP` to move past the instruction, `ExitType::Continue` if #GP is injected, or an error if the descriptor can't be read.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: INVPCID—Invalidate Process-Context Identifier
+pub fn handle_invpcid(
+    guest_registers: &mut GuestRegisters,
+    vmcs: &mut dyn VmcsAccess,
+    cpu: &mut dyn CpuAccess,
+) -> Result<ExitType, HypervisorError> {
+    // The invalidation types of INVPCID.
+    const INDIVIDUAL_ADDRESS: u64 = 0;
+    const SINGLE_CONTEXT: u64 = 1;
+    const ALL_CONTEXTS_INCLUDING_GLOBAL: u64 = 2;
+    const ALL_CONTEXTS: u64 = 3;
+
+    log::debug!("Handling INVPCID VM exit...");
+
+    let information =
+        InvalidationInstructionInformation::from_u64(vmcs.get(ro::VMEXIT_INSTRUCTION_INFO).into())
+            .ok_or(HypervisorError::InvalidInstructionInformation)?;
+
+    let invalidation_type = guest_register(guest_registers, information.register);
+    let address = operand_linear_address(&information.operand, guest_registers, vmcs);
+    let descriptor = GuestMemory::current(vmcs).read::<InvpcidDescriptor>(address)?;
+
+    let valid = match invalidation_type {
+        INDIVIDUAL_ADDRESS => descriptor.pcid >> 12 == 0 && is_canonical(descriptor.linear_address),
+        SINGLE_CONTEXT => descriptor.pcid >> 12 == 0,
+        ALL_CONTEXTS_INCLUDING_GLOBAL | ALL_CONTEXTS => true,
+        _ => false,
+    };
+
+    if !valid {
+        log::trace!(
+            "Invalid INVPCID type {:#x} or descriptor {:x?}",
+            invalidation_type,
+            descriptor
+        );
+        EventInjection::vmentry_inject_gp(vmcs, 0);
+        return Ok(ExitType::Continue);
+    }
+
+    let vpid = vmcs.get(control::VPID);
+
+    match invalidation_type {
+        INDIVIDUAL_ADDRESS => cpu.invvpid_individual_address(vpid, descriptor.linear_address),
+        _ => cpu.invvpid_single_context(vpid),
+    }
+
+    log::debug!("INVPCID VMEXIT handled successfully!");
+
+    Ok(ExitType::IncrementRIP)
+}
+
+/// Checks whether a linear address is canonical for 4-level paging.
+fn is_canonical(address: u64) -> bool {
+    ((address as i64) << 16 >> 16) as u64 == address
+}
@@ -1,19 +1,23 @@
 //! Manages VM exits related to Virtual Processor Identifier (VPID) operations in Intel VT-x technology.
 
-use crate::intel::{invvpid::invvpid_all_contexts, vmexit::ExitType};
+use crate::intel::{cpu_access::CpuAccess, vmexit::ExitType};
 
 /// Handles the INVVPID VM exit.
 ///
 /// Invalidates all VPID contexts and increments the VM's instruction pointer.
 ///
+/// # Arguments
+///
+/// * `cpu` - The processor executing `INVVPID` on the host.
+///
 /// # Returns
 ///
 /// * `ExitType::IncrementRIP` - Advances past the `INVVPID` instruction in the VM.
-pub fn handle_invvpid() -> ExitType {
+pub fn handle_invvpid(cpu: &mut dyn CpuAccess) -> ExitType {
     log::debug!("Handling INVVPID VM exit...");
 
     // Invalidate all VPID contexts to ensure consistency of TLB entries with the current VM state.
-    invvpid_all_contexts();
+    cpu.invvpid_all_contexts();
 
     log::debug!("INVVPID VMEXIT handled successfully!");
 
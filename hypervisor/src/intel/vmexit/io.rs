@@ -0,0 +1,717 @@
+//! Handles I/O instruction VM exits by performing the port I/O on behalf of the guest.
+//!
+//! IN and OUT transfer the value in AL, AX or EAX. INS and OUTS transfer the values in guest memory,
+//! which is accessed through `GuestMemory`. The iterations of a REP prefix are performed in bounded
+//! batches, and RIP is only moved past the instruction once RCX reaches 0, so the guest re-executes it
+//! for the next batch. Accesses to guest memory that fault inject #PF, or #GP for non-canonical addresses,
+//! like the processor would, with the registers reflecting the iterations already completed.
+//!
+//! The ports are intercepted through the I/O bitmaps, and each access to an intercepted port is passed to
+//! the port handler registered for it in `IoPortHandlers`. The accesses that are performed on the physical
+//! ports go through `CpuAccess`.
+
+use {
+    crate::{
+        error::HypervisorError,
+        intel::{
+            cpu_access::CpuAccess,
+            events::EventInjection,
+            exit_qualification::{
+                AddressSize, IoInstructionQualification, SegmentRegister,
+                StringIoInstructionInformation,
+            },
+            guest_memory::GuestMemory,
+            vmcs_access::{VmcsAccess, VmcsFieldAccess},
+            vmcs_fields::{guest, ro},
+            vmerror::ExceptionInterrupt,
+            vmexit::{guest_segment_base, is_guest_64bit_mode, ExitType},
+        },
+        utils::capture::GuestRegisters,
+    },
+    alloc::{boxed::Box, collections::BTreeMap},
+    core::fmt,
+};
+
+/// The direction flag of RFLAGS.
+const RFLAGS_DIRECTION_FLAG: u64 = 1 << 10;
+
+/// The maximum number of iterations of a REP prefix performed in a single VM exit, which bounds the time
+/// spent in VMX root operation and lets pending events be delivered between the batches.
+const STRING_IO_BATCH: u64 = 64;
+
+/// The direction of an access to an I/O port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoDirection {
+    /// IN or INS, which read from the port.
+    In,
+
+    /// OUT or OUTS, which write to the port.
+    Out,
+}
+
+/// An access of the guest to an intercepted I/O port, passed to the port handlers.
+pub struct PortAccess<'a> {
+    /// The first port accessed.
+    pub port: u16,
+
+    /// The size of the access in bytes: 1, 2 or 4.
+    pub size: u8,
+
+    /// The direction of the access.
+    pub direction: IoDirection,
+
+    /// The value written to the port by OUT, or the value returned to IN, which is set by the handler.
+    pub value: u32,
+
+    /// The guest RIP of the I/O instruction.
+    pub guest_rip: u64,
+
+    /// The processor performing the forwarded accesses.
+    cpu: &'a mut dyn CpuAccess,
+}
+
+impl PortAccess<'_> {
+    /// Performs the access on the physical port, for handlers that only monitor the accesses.
+    pub fn forward(&mut self) {
+        match self.direction {
+            IoDirection::In => self.value = self.cpu.port_in(self.port, self.size),
+            IoDirection::Out => self.cpu.port_out(self.port, self.size, self.value),
+        }
+    }
+}
+
+impl fmt::Debug for PortAccess<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PortAccess")
+            .field("port", &self.port)
+            .field("size", &self.size)
+            .field("direction", &self.direction)
+            .field("value", &self.value)
+            .field("guest_rip", &self.guest_rip)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A handler of the accesses to an I/O port.
+///
+/// The handler is called in VMX root operation for each access to the port, including each iteration of
+/// INS and OUTS. It's implemented for all functions with the signature of `handle`.
+pub trait IoPortHandler: Send + Sync {
+    /// Handles an access to the port.
+    ///
+    /// # Arguments
+    ///
+    /// * `access` - The access, whose value is set by the handler for IN.
+    fn handle(&self, access: &mut PortAccess);
+}
+
+impl<F> IoPortHandler for F
+where
+    F: Fn(&mut PortAccess) + Send + Sync,
+{
+    fn handle(&self, access: &mut PortAccess) {
+        self(access)
+    }
+}
+
+/// The handlers of the intercepted I/O ports keyed by the port.
+pub struct IoPortHandlers {
+    /// The handler of every intercepted port.
+    handlers: BTreeMap<u16, Box<dyn IoPortHandler>>,
+}
+
+impl IoPortHandlers {
+    /// Creates a registry without any intercepted port.
+    pub fn new() -> Self {
+        Self {
+            handlers: BTreeMap::new(),
+        }
+    }
+
+    /// Intercepts the accesses to an I/O port, replacing the previous handler of the port.
+    ///
+    /// # Arguments
+    ///
+    /// * `port` - The I/O port.
+    /// * `handler` - The handler.
+    pub fn intercept_port(&mut self, port: u16, handler: impl IoPortHandler + 'static) {
+        self.handlers.insert(port, Box::new(handler));
+    }
+
+    /// Returns the intercepted ports.
+    pub fn ports(&self) -> impl Iterator<Item = u16> + '_ {
+        self.handlers.keys().copied()
+    }
+
+    /// Dispatches an access to the handler of the first intercepted port it accesses, or performs it on
+    /// the physical port if none of its ports is intercepted.
+    ///
+    /// # Arguments
+    ///
+    /// * `access` - The access.
+    pub fn dispatch(&self, access: &mut PortAccess) {
+        let handler = (0..access.size as u16)
+            .find_map(|offset| self.handlers.get(&access.port.wrapping_add(offset)));
+
+        match handler {
+            Some(handler) => handler.handle(access),
+            None => access.forward(),
+        }
+    }
+}
+
+impl Default for IoPortHandlers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Protects an I/O port from the guest: writes are discarded, and reads return all ones, like for a port
+/// without any device.
+///
+/// # Arguments
+///
+/// * `access` - The access to the port.
+pub fn protect_port(access: &mut PortAccess) {
+    log::trace!("Blocked guest access to protected port: {:x?}", access);
+
+    if access.direction == IoDirection::In {
+        access.value = u32::MAX;
+    }
+}
+
+/// Handles the I/O instruction VM exit.
+///
+/// # Arguments
+///
+/// * `guest_registers` - A mutable reference to the guest's current register state.
+/// * `io_ports` - The handlers of the intercepted I/O ports.
+/// * `vmcs` - The VMCS of the guest.
+/// * `cpu` - The processor, which performs the forwarded accesses and whose CR2 is loaded when a page fault is injected.
+///
+/// # Returns
+///
+/// * `Result<ExitType, HypervisorError>` - `ExitType::IncrementRIP` to move past the instruction, `ExitType::Continue` if iterations of a REP prefix remain or an exception is injected, or an error if the exit information is invalid.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: Table 28-5. Exit Qualification for I/O Instructions
+pub fn handle_io_instruction(
+    guest_registers: &mut GuestRegisters,
+    io_ports: &IoPortHandlers,
+    vmcs: &mut dyn VmcsAccess,
+    cpu: &mut dyn CpuAccess,
+) -> Result<ExitType, HypervisorError> {
+    log::debug!("Handling I/O instruction VM exit...");
+
+    let qualification = IoInstructionQualification::from_u64(vmcs.get(ro::EXIT_QUALIFICATION))
+        .ok_or(HypervisorError::InvalidExitQualification)?;
+
+    log::trace!("I/O instruction: {:?}", qualification);
+
+    let exit_type = if qualification.string {
+        handle_string_io(guest_registers, io_ports, vmcs, cpu, &qualification)?
+    } else if qualification.input {
+        let value = transfer(io_ports, vmcs, cpu, &qualification, 0);
+
+        // A 32-bit destination is zero-extended to RAX, smaller ones are merged into RAX.
+        guest_registers.rax = match qualification.size {
+            1 => (guest_registers.rax & !0xFF) | value as u64,
+            2 => (guest_registers.rax & !0xFFFF) | value as u64,
+            _ => value as u64,
+        };
+
+        ExitType::IncrementRIP
+    } else {
+        transfer(
+            io_ports,
+            vmcs,
+            cpu,
+            &qualification,
+            guest_registers.rax as u32,
+        );
+
+        ExitType::IncrementRIP
+    };
+
+    log::debug!("I/O instruction VMEXIT handled successfully!");
+
+    Ok(exit_type)
+}
+
+/// Performs INS or OUTS, with up to `STRING_IO_BATCH` iterations of a REP prefix.
+///
+/// RCX, RSI and RDI are updated after each iteration, so they're consistent with the iterations already
+/// performed when an exception is injected or the batch ends.
+///
+/// # Returns
+///
+/// * `Result<ExitType, HypervisorError>` - `ExitType::IncrementRIP` once all the iterations are performed, `ExitType::Continue` to re-execute the instruction, or an error if the instruction information is invalid.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: Table 28-8. Format of the VM-Exit Instruction-Information Field as Used for INS and OUTS
+fn handle_string_io(
+    guest_registers: &mut GuestRegisters,
+    io_ports: &IoPortHandlers,
+    vmcs: &mut dyn VmcsAccess,
+    cpu: &mut dyn CpuAccess,
+    qualification: &IoInstructionQualification,
+) -> Result<ExitType, HypervisorError> {
+    let information =
+        StringIoInstructionInformation::from_u64(vmcs.get(ro::VMEXIT_INSTRUCTION_INFO).into())
+            .ok_or(HypervisorError::InvalidInstructionInformation)?;
+
+    let guest_memory = GuestMemory::current(vmcs);
+    let mask = information.address_size.mask();
+    let size = qualification.size as usize;
+    let step = match vmcs.get(guest::RFLAGS) & RFLAGS_DIRECTION_FLAG != 0 {
+        true => (size as u64).wrapping_neg(),
+        false => size as u64,
+    };
+
+    let count = match qualification.rep {
+        true => guest_registers.rcx & mask,
+        false => 1,
+    };
+
+    for _ in 0..count.min(STRING_IO_BATCH) {
+        // INS always stores to ES:RDI, and OUTS loads from the segment of the instruction.
+        let segment = match qualification.input {
+            true => SegmentRegister::Es,
+            false => information.segment,
+        };
+        let offset = match qualification.input {
+            true => guest_registers.rdi,
+            false => guest_registers.rsi,
+        };
+        let address = guest_segment_base(vmcs, segment).wrapping_add(offset & mask);
+
+        if is_guest_64bit_mode(vmcs)
+            && !(is_canonical(address) && is_canonical(address.wrapping_add(size as u64 - 1)))
+        {
+            log::trace!("Non-canonical address of string I/O: {:#x}", address);
+
+            match segment {
+                SegmentRegister::Ss => EventInjection::vmentry_inject_exception(
+                    vmcs,
+                    ExceptionInterrupt::StackSegmentFault,
+                    0,
+                ),
+                _ => EventInjection::vmentry_inject_gp(vmcs, 0),
+            }
+
+            return Ok(ExitType::Continue);
+        }
+
+        // INS writes to guest memory, so the port is only read once the write is known to succeed.
+        if let Some(fault) = guest_memory.page_fault(address, size, qualification.input) {
+            log::trace!("Page fault in string I/O: {:x?}", fault);
+            fault.inject(vmcs, cpu);
+
+            return Ok(ExitType::Continue);
+        }
+
+        if qualification.input {
+            let value = transfer(io_ports, vmcs, cpu, qualification, 0);
+
+            guest_memory.write_bytes(address, &value.to_le_bytes()[..size])?;
+            guest_registers.rdi = update_register(
+                guest_registers.rdi,
+                guest_registers.rdi.wrapping_add(step),
+                information.address_size,
+            );
+        } else {
+            let mut value = [0u8; 4];
+
+            guest_memory.read_bytes(address, &mut value[..size])?;
+            transfer(
+                io_ports,
+                vmcs,
+                cpu,
+                qualification,
+                u32::from_le_bytes(value),
+            );
+            guest_registers.rsi = update_register(
+                guest_registers.rsi,
+                guest_registers.rsi.wrapping_add(step),
+                information.address_size,
+            );
+        }
+
+        if qualification.rep {
+            guest_registers.rcx = update_register(
+                guest_registers.rcx,
+                guest_registers.rcx.wrapping_sub(1),
+                information.address_size,
+            );
+        }
+    }
+
+    match qualification.rep && guest_registers.rcx & mask != 0 {
+        true => Ok(ExitType::Continue),
+        false => Ok(ExitType::IncrementRIP),
+    }
+}
+
+/// Performs one transfer of an I/O instruction through the port handlers.
+///
+/// # Arguments
+///
+/// * `io_ports` - The handlers of the intercepted I/O ports.
+/// * `vmcs` - The VMCS of the guest.
+/// * `cpu` - The processor performing the forwarded accesses.
+/// * `qualification` - The exit qualification of the I/O instruction.
+/// * `value` - The value written by OUT and OUTS, ignored for IN and INS.
+///
+/// # Returns
+///
+/// * `u32` - The value read by IN and INS, truncated to the size of the access.
+fn transfer(
+    io_ports: &IoPortHandlers,
+    vmcs: &dyn VmcsAccess,
+    cpu: &mut dyn CpuAccess,
+    qualification: &IoInstructionQualification,
+    value: u32,
+) -> u32 {
+    // Only the bits of the size of the access are passed to and taken from the handlers.
+    let mask = match qualification.size {
+        1 => 0xFF,
+        2 => 0xFFFF,
+        _ => u32::MAX,
+    };
+
+    let mut access = PortAccess {
+        port: qualification.port,
+        size: qualification.size,
+        direction: match qualification.input {
+            true => IoDirection::In,
+            false => IoDirection::Out,
+        },
+        value: value & mask,
+        guest_rip: vmcs.get(guest::RIP),
+        cpu,
+    };
+
+    io_ports.dispatch(&mut access);
+
+    access.value & mask
+}
+
+/// Updates the part of a register used with an address size: 16-bit updates preserve the upper bits,
+/// and 32-bit updates are zero-extended.
+fn update_register(register: u64, value: u64, address_size: AddressSize) -> u64 {
+    match address_size {
+        AddressSize::Bits16 => (register & !0xFFFF) | (value & 0xFFFF),
+        AddressSize::Bits32 => value & 0xFFFF_FFFF,
+        AddressSize::Bits64 => value,
+    }
+}
+
+/// Checks whether a linear address is canonical for 4-level paging.
+fn is_canonical(address: u64) -> bool {
+    ((address as i64) << 16 >> 16) as u64 == address
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::intel::{
+            cpu_access::SoftCpu,
+            guest_memory::tests::{address, PageTables, VA},
+            vmcs_access::SoftVmcs,
+            vmcs_fields::control,
+        },
+        alloc::{sync::Arc, vec::Vec},
+        spin::Mutex,
+    };
+
+    /// The intercepted port.
+    const PORT: u64 = 0x2F8;
+
+    /// The exit qualification of REP OUTSB to `PORT`.
+    const REP_OUTSB: u64 = (PORT << 16) | (1 << 5) | (1 << 4);
+
+    /// The exit qualification of REP INSD from `PORT`.
+    const REP_INSD: u64 = (PORT << 16) | (1 << 5) | (1 << 4) | (1 << 3) | 3;
+
+    /// The flags of supervisor pages that are present and writable.
+    const SUPERVISOR_PAGE: u64 = 0x3;
+
+    /// The WP flag of CR0.
+    const CR0_WRITE_PROTECT: u64 = 1 << 16;
+
+    /// The guest RIP of the I/O instructions.
+    const GUEST_RIP: u64 = 0xFFFF_F806_1234_5678;
+
+    /// Handles an I/O instruction of a 64-bit guest with 64-bit addresses and DS as segment.
+    ///
+    /// # Returns
+    ///
+    /// The exit type, the VMCS and the processor.
+    fn io_instruction(
+        qualification: u64,
+        guest_registers: &mut GuestRegisters,
+        tables: &PageTables,
+        cr0: u64,
+        io_ports: &IoPortHandlers,
+        mut cpu: SoftCpu,
+    ) -> (ExitType, SoftVmcs, SoftCpu) {
+        let mut vmcs = SoftVmcs::new()
+            .with(ro::EXIT_QUALIFICATION, qualification)
+            .with(ro::VMEXIT_INSTRUCTION_INFO, (3 << 15) | (2 << 7))
+            .with(guest::CR3, address(&tables.pml4))
+            .with(guest::CR0, cr0)
+            .with(guest::CS_ACCESS_RIGHTS, 1 << 13)
+            .with(guest::RFLAGS, 0x2)
+            .with(guest::RIP, GUEST_RIP);
+
+        let exit_type =
+            handle_io_instruction(guest_registers, io_ports, &mut vmcs, &mut cpu).unwrap();
+
+        (exit_type, vmcs, cpu)
+    }
+
+    /// Handles a string I/O instruction with `PORT` intercepted.
+    ///
+    /// # Returns
+    ///
+    /// The exit type, the VMCS, the processor and the values written to `PORT`. The port returns the
+    /// number of values read so far to INS.
+    fn string_io(
+        qualification: u64,
+        guest_registers: &mut GuestRegisters,
+        tables: &PageTables,
+        cr0: u64,
+    ) -> (ExitType, SoftVmcs, SoftCpu, Vec<u32>) {
+        let accesses = Arc::new(Mutex::new(Vec::new()));
+        let mut io_ports = IoPortHandlers::new();
+        let port_accesses = accesses.clone();
+        io_ports.intercept_port(PORT as u16, move |access: &mut PortAccess| {
+            let mut accesses = port_accesses.lock();
+            access.value = accesses.len() as u32;
+            accesses.push(access.value);
+        });
+
+        let (exit_type, vmcs, cpu) = io_instruction(
+            qualification,
+            guest_registers,
+            tables,
+            cr0,
+            &io_ports,
+            SoftCpu::new(),
+        );
+        let accesses = accesses.lock().clone();
+
+        (exit_type, vmcs, cpu, accesses)
+    }
+
+    #[test]
+    fn rep_outs_is_performed_in_batches_until_rcx_reaches_zero() {
+        let mut tables = PageTables::new(SUPERVISOR_PAGE);
+        tables.data[0].0[0] = 0x0807_0605_0403_0201;
+        let mut guest_registers = GuestRegisters {
+            rcx: STRING_IO_BATCH + 6,
+            rsi: VA,
+            ..Default::default()
+        };
+
+        let (exit_type, ..) = string_io(REP_OUTSB, &mut guest_registers, &tables, 0);
+        assert!(matches!(exit_type, ExitType::Continue));
+        assert_eq!(guest_registers.rcx, 6);
+        assert_eq!(guest_registers.rsi, VA + STRING_IO_BATCH);
+
+        let (exit_type, ..) = string_io(REP_OUTSB, &mut guest_registers, &tables, 0);
+        assert!(matches!(exit_type, ExitType::IncrementRIP));
+        assert_eq!(guest_registers.rcx, 0);
+        assert_eq!(guest_registers.rsi, VA + STRING_IO_BATCH + 6);
+    }
+
+    #[test]
+    fn outs_writes_the_values_in_guest_memory_to_the_port() {
+        let mut tables = PageTables::new(SUPERVISOR_PAGE);
+        tables.data[0].0[0] = 0x0807_0605_0403_0201;
+        let mut guest_registers = GuestRegisters {
+            rcx: 3,
+            rsi: VA,
+            ..Default::default()
+        };
+
+        let (_, _, _, accesses) = string_io(REP_OUTSB, &mut guest_registers, &tables, 0);
+        assert_eq!(accesses, [0, 1, 2]);
+    }
+
+    #[test]
+    fn ins_injects_a_page_fault_after_the_iterations_that_succeed() {
+        let tables = PageTables::new(SUPERVISOR_PAGE);
+        let mut guest_registers = GuestRegisters {
+            rcx: 4,
+            rdi: VA + 0x1FF8,
+            ..Default::default()
+        };
+
+        let (exit_type, vmcs, cpu, accesses) =
+            string_io(REP_INSD, &mut guest_registers, &tables, 0);
+        assert!(matches!(exit_type, ExitType::Continue));
+        assert_eq!(accesses.len(), 2);
+        assert_eq!(tables.data[1].0[511], 0x0000_0001_0000_0000);
+        assert_eq!(guest_registers.rcx, 2);
+        assert_eq!(guest_registers.rdi, VA + 0x2000);
+        assert_eq!(cpu.cr2(), VA + 0x2000);
+        assert_eq!(
+            vmcs.value(control::VMENTRY_INTERRUPTION_INFO_FIELD),
+            Some((1 << 31) | (1 << 11) | (3 << 8) | 14)
+        );
+        assert_eq!(vmcs.value(control::VMENTRY_EXCEPTION_ERR_CODE), Some(0b010));
+    }
+
+    #[test]
+    fn ins_does_not_read_the_port_for_read_only_pages() {
+        let tables = PageTables::new(0x1);
+        let mut guest_registers = GuestRegisters {
+            rcx: 1,
+            rdi: VA,
+            ..Default::default()
+        };
+
+        let (exit_type, vmcs, cpu, accesses) =
+            string_io(REP_INSD, &mut guest_registers, &tables, CR0_WRITE_PROTECT);
+        assert!(matches!(exit_type, ExitType::Continue));
+        assert!(accesses.is_empty());
+        assert_eq!(guest_registers.rcx, 1);
+        assert_eq!(cpu.cr2(), VA);
+        assert_eq!(vmcs.value(control::VMENTRY_EXCEPTION_ERR_CODE), Some(0b011));
+    }
+
+    #[test]
+    fn non_canonical_addresses_inject_a_general_protection_fault() {
+        let tables = PageTables::new(SUPERVISOR_PAGE);
+        let mut guest_registers = GuestRegisters {
+            rcx: 1,
+            rsi: 0x0000_8000_0000_0000,
+            ..Default::default()
+        };
+
+        let (exit_type, vmcs, _, accesses) = string_io(REP_OUTSB, &mut guest_registers, &tables, 0);
+        assert!(matches!(exit_type, ExitType::Continue));
+        assert!(accesses.is_empty());
+        assert_eq!(
+            vmcs.value(control::VMENTRY_INTERRUPTION_INFO_FIELD)
+                .map(|info| info & 0xFF),
+            Some(13)
+        );
+    }
+
+    #[test]
+    fn forwarded_string_io_is_performed_by_the_processor() {
+        let mut tables = PageTables::new(SUPERVISOR_PAGE);
+        for (index, qword) in tables.data[0].0.iter_mut().take(9).enumerate() {
+            *qword = u64::from_le_bytes(core::array::from_fn(|byte| (index * 8 + byte) as u8));
+        }
+
+        let mut io_ports = IoPortHandlers::new();
+        io_ports.intercept_port(PORT as u16, |access: &mut PortAccess| {
+            assert_eq!(access.guest_rip, GUEST_RIP);
+            access.forward();
+        });
+
+        // A batch of REP OUTSB writes the bytes in guest memory to the port.
+        let mut guest_registers = GuestRegisters {
+            rcx: STRING_IO_BATCH + 1,
+            rsi: VA,
+            ..Default::default()
+        };
+        let (exit_type, _, cpu) = io_instruction(
+            REP_OUTSB,
+            &mut guest_registers,
+            &tables,
+            0,
+            &io_ports,
+            SoftCpu::new(),
+        );
+        assert!(matches!(exit_type, ExitType::Continue));
+        assert_eq!(guest_registers.rcx, 1);
+        assert_eq!(
+            cpu.port_outputs(),
+            (0..STRING_IO_BATCH as u32)
+                .map(|value| (PORT as u16, 1, value))
+                .collect::<Vec<_>>()
+        );
+
+        // REP INSD stores the values read from the port in guest memory.
+        let mut guest_registers = GuestRegisters {
+            rcx: 2,
+            rdi: VA + 0x1000,
+            ..Default::default()
+        };
+        let cpu = SoftCpu::new().with_port_inputs(PORT as u16, &[0x1111_1111, 0x2222_2222]);
+        let (exit_type, _, cpu) =
+            io_instruction(REP_INSD, &mut guest_registers, &tables, 0, &io_ports, cpu);
+        assert!(matches!(exit_type, ExitType::IncrementRIP));
+        assert_eq!(tables.data[1].0[0], 0x2222_2222_1111_1111);
+        assert!(cpu.port_outputs().is_empty());
+    }
+
+    #[test]
+    fn ports_without_a_handler_are_forwarded() {
+        let tables = PageTables::new(SUPERVISOR_PAGE);
+        let mut guest_registers = GuestRegisters {
+            rax: 0xAABB_CCDD_EEFF_0011,
+            ..Default::default()
+        };
+
+        // IN AX, DX
+        let cpu = SoftCpu::new().with_port_inputs(PORT as u16, &[0x1234]);
+        let (_, _, cpu) = io_instruction(
+            (PORT << 16) | (1 << 3) | 1,
+            &mut guest_registers,
+            &tables,
+            0,
+            &IoPortHandlers::new(),
+            cpu,
+        );
+        assert_eq!(guest_registers.rax, 0xAABB_CCDD_EEFF_1234);
+
+        // OUT DX, EAX
+        let (_, _, cpu) = io_instruction(
+            (PORT << 16) | 3,
+            &mut guest_registers,
+            &tables,
+            0,
+            &IoPortHandlers::new(),
+            cpu,
+        );
+        assert_eq!(cpu.port_outputs(), [(PORT as u16, 4, 0xEEFF_1234)]);
+    }
+
+    #[test]
+    fn protected_ports_discard_writes_and_read_all_ones() {
+        let tables = PageTables::new(SUPERVISOR_PAGE);
+        let mut io_ports = IoPortHandlers::new();
+        io_ports.intercept_port(PORT as u16, protect_port);
+        let mut guest_registers = GuestRegisters {
+            rax: 0x55,
+            ..Default::default()
+        };
+
+        // OUT DX, AL
+        let (_, _, cpu) = io_instruction(
+            PORT << 16,
+            &mut guest_registers,
+            &tables,
+            0,
+            &io_ports,
+            SoftCpu::new().with_port_inputs(PORT as u16, &[0x12]),
+        );
+        assert!(cpu.port_outputs().is_empty());
+
+        // IN AL, DX
+        let (_, _, cpu) = io_instruction(
+            (PORT << 16) | (1 << 3),
+            &mut guest_registers,
+            &tables,
+            0,
+            &io_ports,
+            cpu,
+        );
+        assert_eq!(guest_registers.rax, 0xFF);
+        assert!(cpu.port_outputs().is_empty());
+    }
+}
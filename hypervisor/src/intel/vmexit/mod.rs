@@ -4,37 +4,39 @@
 //! The handlers interpret and respond to different VM exit reasons, ensuring the safe and correct execution of the virtual machine.
 
 use {
-    super::{support::vmwrite, vmerror::VmxBasicExitReason},
+    super::vmerror::{ExceptionInterrupt, VmxBasicExitReason},
     crate::{
         error::HypervisorError,
         intel::{
-            support::vmread,
-            vmexit::{
-                cpuid::handle_cpuid,
-                ept::{handle_ept_misconfiguration, handle_ept_violation},
-                exception::{handle_exception, handle_undefined_opcode_exception},
-                invd::handle_invd,
-                invept::handle_invept,
-                invvpid::handle_invvpid,
-                msr::{handle_msr_access, MsrAccessType},
-                rdtsc::handle_rdtsc,
-                xsetbv::handle_xsetbv,
-            },
-            vmx::Vmx,
+            cpu_access::CpuAccess,
+            exit_qualification::{GeneralPurposeRegister, MemoryOperand, SegmentRegister},
+            vmcs_access::{VmcsAccess, VmcsFieldAccess},
+            vmcs_fields::{guest, ro},
+            vmexit::registry::{VmExitContext, VmExitData, VmExitHandlers},
         },
         utils::capture::GuestRegisters,
     },
-    x86::vmx::vmcs::{guest, ro},
 };
 
+pub mod control_register;
 pub mod cpuid;
+pub mod descriptor_table;
 pub mod ept;
 pub mod exception;
+pub mod idle;
+pub mod interrupt;
 pub mod invd;
 pub mod invept;
+pub mod invlpg;
 pub mod invvpid;
+pub mod io;
+pub mod mov_dr;
 pub mod msr;
+pub mod random;
+pub mod rdpmc;
 pub mod rdtsc;
+pub mod registry;
+pub mod syscall;
 pub mod xsetbv;
 
 /// Represents the type of VM exit.
@@ -45,6 +47,20 @@ pub enum ExitType {
     Continue,
 }
 
+/// The default handling of a basic exit reason, as recorded in `VmExit::policy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmExitPolicy {
+    /// The instruction or event is emulated by the hypervisor, and the guest continues.
+    Emulate,
+    /// The exception is injected into the guest, as the processor would raise it without VMX.
+    Reflect(ExceptionInterrupt),
+    /// The guest continues without further action. Used for trap-like VM exits, which are only
+    /// notifications, and for events that are discarded.
+    Resume,
+    /// The guest can't continue, and the VM exit fails with `UnrecoverableVmExit`.
+    Fatal,
+}
+
 /// Represents a VM exit, which can be caused by various reasons.
 ///
 /// A VM exit transfers control from the guest to the host (hypervisor).
@@ -58,11 +74,17 @@ impl VmExit {
 
     /// Handles the VM-exit.
     ///
-    /// This function interprets the VM exit reason and invokes the appropriate handler based on the exit type.
+    /// This function interprets the VM exit reason and dispatches it to the handlers registered for it.
+    /// In VMX root operation, the VMCS and the processor are `HardwareVmcs` and `HardwareCpu`, while
+    /// `SoftVmcs` and `SoftCpu` script the VM exit outside of it.
     ///
     /// # Arguments
     ///
     /// * `registers` - A mutable reference to the guest's current register state.
+    /// * `handlers` - The handlers of the exit reasons.
+    /// * `data` - The state shared between the processors that the handlers use.
+    /// * `vmcs` - The VMCS of the guest.
+    /// * `cpu` - The processor executing the instructions that the handlers use on behalf of the guest.
     ///
     /// # Returns
     ///
@@ -71,19 +93,22 @@ impl VmExit {
     /// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 25.9 VM-EXIT INFORMATION FIELDS
     /// - APPENDIX C VMX BASIC EXIT REASONS
     /// - Table C-1. Basic Exit Reasons
-    pub fn handle_vmexit(
+    pub fn handle_vmexit<V: VmcsAccess, C: CpuAccess>(
         &self,
         guest_registers: &mut GuestRegisters,
-        vmx: &mut Vmx,
+        handlers: &VmExitHandlers,
+        data: VmExitData,
+        vmcs: &mut V,
+        cpu: &mut C,
     ) -> Result<(), HypervisorError> {
         log::debug!("Handling VMEXIT...");
 
         // Upon VM-exit, transfer the guest register values from VMCS to `self.registers` to ensure it reflects the latest and complete state.
-        guest_registers.rip = vmread(guest::RIP);
-        guest_registers.rsp = vmread(guest::RSP);
-        guest_registers.rflags = vmread(guest::RFLAGS);
+        guest_registers.rip = vmcs.get(guest::RIP);
+        guest_registers.rsp = vmcs.get(guest::RSP);
+        guest_registers.rflags = vmcs.get(guest::RFLAGS);
 
-        let exit_reason = vmread(ro::EXIT_REASON) as u32;
+        let exit_reason = vmcs.get(ro::EXIT_REASON);
 
         let Some(basic_exit_reason) = VmxBasicExitReason::from_u32(exit_reason) else {
             log::error!("Unknown exit reason: {:#x}", exit_reason);
@@ -97,40 +122,28 @@ impl VmExit {
             guest_registers
         );
 
-        // Intel® 64 and IA-32 Architectures Software Developer's Manual: 26.1.2 Instructions That Cause VM Exits Unconditionally:
-        // - The following instructions cause VM exits when they are executed in VMX non-root operation: CPUID, GETSEC, INVD, and XSETBV.
-        // - This is also true of instructions introduced with VMX, which include: INVEPT, INVVPID, VMCALL, VMCLEAR, VMLAUNCH, VMPTRLD, VMPTRST, VMRESUME, VMXOFF, and VMXON.
-        //
-        // 26.1.3 Instructions That Cause VM Exits Conditionally: Certain instructions cause VM exits in VMX non-root operation depending on the setting of the VM-execution controls.
-        let exit_type = match basic_exit_reason {
-            VmxBasicExitReason::ExceptionOrNmi => handle_exception(guest_registers, vmx),
-            VmxBasicExitReason::Cpuid => handle_cpuid(guest_registers),
+        // Load the guest view of IA32_EFER that might have been written on another processor.
+        if let Some(syscall_tracer) = data.syscall_tracer {
+            syscall_tracer.sync_efer(vmcs);
+        }
 
-            // Grouping multiple exit reasons that are handled by the same function
-            VmxBasicExitReason::Getsec
-            | VmxBasicExitReason::Vmcall
-            | VmxBasicExitReason::Vmclear
-            | VmxBasicExitReason::Vmlaunch
-            | VmxBasicExitReason::Vmptrld
-            | VmxBasicExitReason::Vmptrst
-            | VmxBasicExitReason::Vmresume
-            | VmxBasicExitReason::Vmxon
-            | VmxBasicExitReason::Vmxoff => handle_undefined_opcode_exception(),
-
-            VmxBasicExitReason::Rdmsr => handle_msr_access(guest_registers, MsrAccessType::Read),
-            VmxBasicExitReason::Wrmsr => handle_msr_access(guest_registers, MsrAccessType::Write),
-            VmxBasicExitReason::Invd => handle_invd(guest_registers),
-            VmxBasicExitReason::Rdtsc => handle_rdtsc(guest_registers),
-            VmxBasicExitReason::EptViolation => handle_ept_violation(guest_registers, vmx),
-            VmxBasicExitReason::EptMisconfiguration => handle_ept_misconfiguration(),
-            VmxBasicExitReason::Invept => handle_invept(),
-            VmxBasicExitReason::Invvpid => handle_invvpid(),
-            VmxBasicExitReason::Xsetbv => handle_xsetbv(guest_registers),
-            _ => return Err(HypervisorError::UnhandledVmExit),
+        // The handlers and the data are only borrowed immutably, because the other processors handle their
+        // VM-exits with them at the same time.
+        let mut exit = VmExitContext {
+            reason: basic_exit_reason,
+            guest_registers: &mut *guest_registers,
+            data,
+            vmcs: &mut *vmcs,
+            cpu: &mut *cpu,
         };
 
+        let exit_type = handlers.dispatch(&mut exit).map_err(|error| {
+            log::error!("Failed to handle {}: {:?}", basic_exit_reason, error);
+            error
+        })?;
+
         if exit_type == ExitType::IncrementRIP {
-            self.advance_guest_rip(guest_registers);
+            self.advance_guest_rip(guest_registers, vmcs);
         }
 
         log::debug!(
@@ -139,7 +152,111 @@ impl VmExit {
         );
         log::debug!("VMEXIT handled successfully.");
 
-        return Ok(());
+        Ok(())
+    }
+
+    /// Returns the default handling of a basic exit reason.
+    ///
+    /// The default handlers registered by `VmExitHandlers::with_defaults` follow this table, so every exit
+    /// reason has a defined behaviour, even if the VM-execution controls of the hypervisor never cause it.
+    ///
+    /// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: APPENDIX C VMX BASIC EXIT REASONS
+    /// and 26.1 INSTRUCTIONS THAT CAUSE VM EXITS
+    pub const fn policy(reason: VmxBasicExitReason) -> VmExitPolicy {
+        match reason {
+            // The guest can't be resumed after a triple fault, which shuts the processor down, or after a
+            // VM-entry failure, which leaves the guest state invalid. APIC accesses need the faulting
+            // instruction to be decoded, and SPP-related events a sub-page permission table, neither of
+            // which the hypervisor has.
+            VmxBasicExitReason::TripleFault
+            | VmxBasicExitReason::VmEntryFailureInvalidGuestState
+            | VmxBasicExitReason::VmEntryFailureMsrLoading
+            | VmxBasicExitReason::VmEntryFailureMachineCheckEvent
+            | VmxBasicExitReason::ApicAccess
+            | VmxBasicExitReason::SppRelatedEvent => VmExitPolicy::Fatal,
+
+            // INIT and SIPI are discarded, because the processor stays in VMX operation and never enters the
+            // wait-for-SIPI state. SMIs only cause VM exits with the dual-monitor treatment of SMM. The other
+            // VM exits are trap-like: the instruction has already completed, and the guest continues after it.
+            VmxBasicExitReason::InitSignal
+            | VmxBasicExitReason::StartupIpi
+            | VmxBasicExitReason::IoSystemManagementInterrupt
+            | VmxBasicExitReason::OtherSmi
+            | VmxBasicExitReason::TprBelowThreshold
+            | VmxBasicExitReason::VirtualizedEoi
+            | VmxBasicExitReason::ApicWrite
+            | VmxBasicExitReason::VmxPreemptionTimerExpired
+            | VmxBasicExitReason::PageModificationLogFull
+            | VmxBasicExitReason::BusLock => VmExitPolicy::Resume,
+
+            // The hypervisor doesn't support nested virtualization, SMX, SGX, key locker or PCONFIG, so the
+            // instructions raise #UD as if the processor didn't support them. XSAVES and XRSTORS raise #UD as
+            // if they weren't enabled.
+            VmxBasicExitReason::Getsec
+            | VmxBasicExitReason::Rsm
+            | VmxBasicExitReason::Vmcall
+            | VmxBasicExitReason::Vmclear
+            | VmxBasicExitReason::Vmlaunch
+            | VmxBasicExitReason::Vmptrld
+            | VmxBasicExitReason::Vmptrst
+            | VmxBasicExitReason::Vmread
+            | VmxBasicExitReason::Vmresume
+            | VmxBasicExitReason::Vmwrite
+            | VmxBasicExitReason::Vmxoff
+            | VmxBasicExitReason::Vmxon
+            | VmxBasicExitReason::Vmfunc
+            | VmxBasicExitReason::Encls
+            | VmxBasicExitReason::Enclv
+            | VmxBasicExitReason::Xsaves
+            | VmxBasicExitReason::Xrstors
+            | VmxBasicExitReason::Pconfig
+            | VmxBasicExitReason::Loadiwkey => {
+                VmExitPolicy::Reflect(ExceptionInterrupt::InvalidOpcode)
+            }
+
+            // The guest runs in IA-32e mode, which doesn't support task switches, and ENQCMD and ENQCMDS raise
+            // #GP(0) if the PASID can't be translated.
+            VmxBasicExitReason::TaskSwitch
+            | VmxBasicExitReason::EnqcmdPasidTranslationFailure
+            | VmxBasicExitReason::EnqcmdsPasidTranslationFailure => {
+                VmExitPolicy::Reflect(ExceptionInterrupt::GeneralProtectionFault)
+            }
+
+            VmxBasicExitReason::ExceptionOrNmi
+            | VmxBasicExitReason::ExternalInterrupt
+            | VmxBasicExitReason::InterruptWindow
+            | VmxBasicExitReason::NmiWindow
+            | VmxBasicExitReason::Cpuid
+            | VmxBasicExitReason::Hlt
+            | VmxBasicExitReason::Invd
+            | VmxBasicExitReason::Invlpg
+            | VmxBasicExitReason::Rdpmc
+            | VmxBasicExitReason::Rdtsc
+            | VmxBasicExitReason::ControlRegisterAccesses
+            | VmxBasicExitReason::MovDr
+            | VmxBasicExitReason::IoInstruction
+            | VmxBasicExitReason::Rdmsr
+            | VmxBasicExitReason::Wrmsr
+            | VmxBasicExitReason::Mwait
+            | VmxBasicExitReason::MonitorTrapFlag
+            | VmxBasicExitReason::Monitor
+            | VmxBasicExitReason::Pause
+            | VmxBasicExitReason::AccessToGdtrOrIdtr
+            | VmxBasicExitReason::AccessToLdtrOrTr
+            | VmxBasicExitReason::EptViolation
+            | VmxBasicExitReason::EptMisconfiguration
+            | VmxBasicExitReason::Invept
+            | VmxBasicExitReason::Rdtscp
+            | VmxBasicExitReason::Invvpid
+            | VmxBasicExitReason::WbinvdOrWbnoinvd
+            | VmxBasicExitReason::Xsetbv
+            | VmxBasicExitReason::Rdrand
+            | VmxBasicExitReason::Invpcid
+            | VmxBasicExitReason::Rdseed
+            | VmxBasicExitReason::Umwait
+            | VmxBasicExitReason::Tpause
+            | VmxBasicExitReason::InstructionTimeout => VmExitPolicy::Emulate,
+        }
     }
 
     /// Advances the guest's instruction pointer (RIP) after a VM exit.
@@ -148,11 +265,435 @@ impl VmExit {
     /// to the hypervisor. To ensure that the guest does not re-execute the instruction that
     /// caused the VM exit, the hypervisor needs to advance the guest's RIP to the next instruction.
     #[rustfmt::skip]
-    fn advance_guest_rip(&self, guest_registers: &mut GuestRegisters) {
+    fn advance_guest_rip(&self, guest_registers: &mut GuestRegisters, vmcs: &mut dyn VmcsAccess) {
         log::trace!("Advancing guest RIP...");
-        let len = vmread(ro::VMEXIT_INSTRUCTION_LEN);
-        guest_registers.rip += len;
-        vmwrite(guest::RIP, guest_registers.rip);
-        log::trace!("Guest RIP advanced to: {:#x}", vmread(guest::RIP));
+        let len = vmcs.get(ro::VMEXIT_INSTRUCTION_LEN);
+        guest_registers.rip += u64::from(len);
+        vmcs.set(guest::RIP, guest_registers.rip);
+        log::trace!("Guest RIP advanced to: {:#x}", vmcs.get(guest::RIP));
+    }
+}
+
+impl Default for VmExit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reads a general-purpose register of the guest.
+///
+/// # Arguments
+///
+/// * `guest_registers` - The guest registers, with RSP read from the VMCS.
+/// * `register` - The register, as encoded in the exit qualification or the instruction information.
+pub fn guest_register(guest_registers: &GuestRegisters, register: GeneralPurposeRegister) -> u64 {
+    match register {
+        GeneralPurposeRegister::Rax => guest_registers.rax,
+        GeneralPurposeRegister::Rcx => guest_registers.rcx,
+        GeneralPurposeRegister::Rdx => guest_registers.rdx,
+        GeneralPurposeRegister::Rbx => guest_registers.rbx,
+        GeneralPurposeRegister::Rsp => guest_registers.rsp,
+        GeneralPurposeRegister::Rbp => guest_registers.rbp,
+        GeneralPurposeRegister::Rsi => guest_registers.rsi,
+        GeneralPurposeRegister::Rdi => guest_registers.rdi,
+        GeneralPurposeRegister::R8 => guest_registers.r8,
+        GeneralPurposeRegister::R9 => guest_registers.r9,
+        GeneralPurposeRegister::R10 => guest_registers.r10,
+        GeneralPurposeRegister::R11 => guest_registers.r11,
+        GeneralPurposeRegister::R12 => guest_registers.r12,
+        GeneralPurposeRegister::R13 => guest_registers.r13,
+        GeneralPurposeRegister::R14 => guest_registers.r14,
+        GeneralPurposeRegister::R15 => guest_registers.r15,
+    }
+}
+
+/// Writes a general-purpose register of the guest.
+///
+/// RSP is also written to the VMCS, because it's loaded from the guest-state area on VM-entry.
+///
+/// # Arguments
+///
+/// * `guest_registers` - The guest registers.
+/// * `vmcs` - The VMCS of the guest.
+/// * `register` - The register, as encoded in the exit qualification or the instruction information.
+/// * `value` - The new value of the register.
+pub fn set_guest_register(
+    guest_registers: &mut GuestRegisters,
+    vmcs: &mut dyn VmcsAccess,
+    register: GeneralPurposeRegister,
+    value: u64,
+) {
+    let target = match register {
+        GeneralPurposeRegister::Rax => &mut guest_registers.rax,
+        GeneralPurposeRegister::Rcx => &mut guest_registers.rcx,
+        GeneralPurposeRegister::Rdx => &mut guest_registers.rdx,
+        GeneralPurposeRegister::Rbx => &mut guest_registers.rbx,
+        GeneralPurposeRegister::Rsp => {
+            vmcs.set(guest::RSP, value);
+            &mut guest_registers.rsp
+        }
+        GeneralPurposeRegister::Rbp => &mut guest_registers.rbp,
+        GeneralPurposeRegister::Rsi => &mut guest_registers.rsi,
+        GeneralPurposeRegister::Rdi => &mut guest_registers.rdi,
+        GeneralPurposeRegister::R8 => &mut guest_registers.r8,
+        GeneralPurposeRegister::R9 => &mut guest_registers.r9,
+        GeneralPurposeRegister::R10 => &mut guest_registers.r10,
+        GeneralPurposeRegister::R11 => &mut guest_registers.r11,
+        GeneralPurposeRegister::R12 => &mut guest_registers.r12,
+        GeneralPurposeRegister::R13 => &mut guest_registers.r13,
+        GeneralPurposeRegister::R14 => &mut guest_registers.r14,
+        GeneralPurposeRegister::R15 => &mut guest_registers.r15,
+    };
+
+    *target = value;
+}
+
+/// Returns the base address of a segment of the guest, as used to compute linear addresses.
+///
+/// In 64-bit mode, only the bases of FS and GS are used, and the bases of the other segments are treated
+/// as 0.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 3.2.4 Segmentation in IA-32e Mode
+pub fn guest_segment_base(vmcs: &dyn VmcsAccess, segment: SegmentRegister) -> u64 {
+    if is_guest_64bit_mode(vmcs) && !matches!(segment, SegmentRegister::Fs | SegmentRegister::Gs) {
+        return 0;
+    }
+
+    vmcs.get(match segment {
+        SegmentRegister::Es => guest::ES_BASE,
+        SegmentRegister::Cs => guest::CS_BASE,
+        SegmentRegister::Ss => guest::SS_BASE,
+        SegmentRegister::Ds => guest::DS_BASE,
+        SegmentRegister::Fs => guest::FS_BASE,
+        SegmentRegister::Gs => guest::GS_BASE,
+    })
+}
+
+/// Computes the linear address of the memory operand of the instruction that caused the VM exit.
+///
+/// # Arguments
+///
+/// * `operand` - The memory operand, from the instruction information.
+/// * `guest_registers` - The guest registers.
+/// * `vmcs` - The VMCS of the guest, with the displacement of the operand in the exit qualification.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 28.2.1 Basic VM-Exit Information
+pub fn operand_linear_address(
+    operand: &MemoryOperand,
+    guest_registers: &GuestRegisters,
+    vmcs: &dyn VmcsAccess,
+) -> u64 {
+    // The displacement is sign-extended to 64 bits in the exit qualification.
+    let displacement = vmcs.get(ro::EXIT_QUALIFICATION);
+    let offset = operand.offset(displacement, |register| {
+        guest_register(guest_registers, register)
+    });
+
+    guest_segment_base(vmcs, operand.segment).wrapping_add(offset)
+}
+
+/// Checks whether the guest runs in 64-bit mode, which is the case if CS.L is set in IA-32e mode.
+pub fn is_guest_64bit_mode(vmcs: &dyn VmcsAccess) -> bool {
+    /// The L flag of the access rights of CS.
+    const CS_LONG_MODE: u32 = 1 << 13;
+
+    vmcs.get(guest::CS_ACCESS_RIGHTS) & CS_LONG_MODE != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::intel::{
+            cpu_access::{Invalidation, SoftCpu},
+            vmcs_access::SoftVmcs,
+            vmcs_fields::control,
+        },
+        x86::{cpuid::CpuIdResult, msr},
+    };
+
+    /// The guest RIP of the scripted VM exits.
+    const RIP: u64 = 0x1000;
+
+    /// The length of the instruction of the scripted VM exits.
+    const INSTRUCTION_LENGTH: u32 = 3;
+
+    /// Scripts a VM exit at `RIP` caused by an instruction of `INSTRUCTION_LENGTH` bytes.
+    fn scripted_exit(reason: VmxBasicExitReason) -> SoftVmcs {
+        SoftVmcs::new()
+            .with(ro::EXIT_REASON, reason as u32)
+            .with(ro::VMEXIT_INSTRUCTION_LEN, INSTRUCTION_LENGTH)
+            .with(guest::RIP, RIP)
+    }
+
+    /// Handles a scripted VM exit with the default handlers.
+    fn handle(
+        guest_registers: &mut GuestRegisters,
+        vmcs: &mut SoftVmcs,
+        cpu: &mut SoftCpu,
+    ) -> Result<(), HypervisorError> {
+        let handlers = VmExitHandlers::with_defaults();
+
+        VmExit::new().handle_vmexit(guest_registers, &handlers, VmExitData::default(), vmcs, cpu)
+    }
+
+    #[test]
+    fn cpuid_hides_vmx_and_hypervisor_present() {
+        let mut vmcs = scripted_exit(VmxBasicExitReason::Cpuid);
+        let mut cpu = SoftCpu::new().with_cpuid(
+            1,
+            0,
+            CpuIdResult {
+                eax: 0x906EA,
+                ebx: 0x1,
+                ecx: (1 << 31) | (1 << 5) | 1,
+                edx: 0x2,
+            },
+        );
+        let mut guest_registers = GuestRegisters {
+            rax: 1,
+            ..Default::default()
+        };
+
+        handle(&mut guest_registers, &mut vmcs, &mut cpu).unwrap();
+
+        assert_eq!(guest_registers.rax, 0x906EA);
+        assert_eq!(guest_registers.rbx, 0x1);
+        assert_eq!(guest_registers.rcx, 1);
+        assert_eq!(guest_registers.rdx, 0x2);
+        assert_eq!(
+            vmcs.value(guest::RIP),
+            Some(RIP + u64::from(INSTRUCTION_LENGTH))
+        );
+    }
+
+    #[test]
+    fn rdmsr_splits_the_value_into_edx_eax() {
+        let mut vmcs = scripted_exit(VmxBasicExitReason::Rdmsr);
+        let mut cpu = SoftCpu::new().with_msr(msr::IA32_TSC_AUX, 0x1234_5678_9ABC_DEF0);
+        let mut guest_registers = GuestRegisters {
+            rcx: msr::IA32_TSC_AUX as u64,
+            ..Default::default()
+        };
+
+        handle(&mut guest_registers, &mut vmcs, &mut cpu).unwrap();
+
+        assert_eq!(guest_registers.rax, 0x9ABC_DEF0);
+        assert_eq!(guest_registers.rdx, 0x1234_5678);
+        assert_eq!(
+            vmcs.value(guest::RIP),
+            Some(RIP + u64::from(INSTRUCTION_LENGTH))
+        );
+    }
+
+    #[test]
+    fn wrmsr_combines_edx_eax() {
+        let mut vmcs = scripted_exit(VmxBasicExitReason::Wrmsr);
+        let mut cpu = SoftCpu::new();
+        let mut guest_registers = GuestRegisters {
+            rcx: msr::IA32_TSC_AUX as u64,
+            rax: 0xFFFF_FFFF_9ABC_DEF0,
+            rdx: 0x1234_5678,
+            ..Default::default()
+        };
+
+        handle(&mut guest_registers, &mut vmcs, &mut cpu).unwrap();
+
+        assert_eq!(cpu.msr(msr::IA32_TSC_AUX), Some(0x1234_5678_9ABC_DEF0));
+    }
+
+    #[test]
+    fn msr_outside_of_the_valid_ranges_raises_gp() {
+        let mut vmcs = scripted_exit(VmxBasicExitReason::Rdmsr);
+        let mut cpu = SoftCpu::new();
+        let mut guest_registers = GuestRegisters {
+            rcx: 0x8000_0000,
+            ..Default::default()
+        };
+
+        handle(&mut guest_registers, &mut vmcs, &mut cpu).unwrap();
+
+        assert_eq!(
+            vmcs.value(control::VMENTRY_INTERRUPTION_INFO_FIELD),
+            Some(0x8000_0B0D)
+        );
+        assert_eq!(vmcs.value(guest::RIP), Some(RIP));
+    }
+
+    #[test]
+    fn xsetbv_writes_the_extended_control_register() {
+        let mut vmcs = scripted_exit(VmxBasicExitReason::Xsetbv);
+        let mut cpu = SoftCpu::new();
+        let mut guest_registers = GuestRegisters {
+            rcx: 0,
+            rax: 0x7,
+            ..Default::default()
+        };
+
+        handle(&mut guest_registers, &mut vmcs, &mut cpu).unwrap();
+
+        assert_eq!(cpu.xcr(0), Some(0x7));
+        assert_eq!(
+            vmcs.value(guest::RIP),
+            Some(RIP + u64::from(INSTRUCTION_LENGTH))
+        );
+    }
+
+    #[test]
+    fn invept_and_invvpid_invalidate_all_contexts() {
+        let mut cpu = SoftCpu::new();
+
+        for reason in [VmxBasicExitReason::Invept, VmxBasicExitReason::Invvpid] {
+            let mut vmcs = scripted_exit(reason);
+            handle(&mut GuestRegisters::default(), &mut vmcs, &mut cpu).unwrap();
+        }
+
+        assert_eq!(
+            cpu.invalidations(),
+            [Invalidation::EptAllContexts, Invalidation::VpidAllContexts]
+        );
+    }
+
+    #[test]
+    fn rdtscp_reads_the_counter_and_tsc_aux() {
+        let mut vmcs = scripted_exit(VmxBasicExitReason::Rdtscp);
+        let mut cpu = SoftCpu::new()
+            .with_tsc(0x0000_0002_0000_0001)
+            .with_msr(msr::IA32_TSC_AUX, 5);
+        let mut guest_registers = GuestRegisters::default();
+
+        handle(&mut guest_registers, &mut vmcs, &mut cpu).unwrap();
+
+        assert_eq!(guest_registers.rax, 1);
+        assert_eq!(guest_registers.rdx, 2);
+        assert_eq!(guest_registers.rcx, 5);
+    }
+
+    #[test]
+    fn mov_to_cr3_invalidates_the_vpid() {
+        // MOV to CR3 from RAX.
+        let mut vmcs = scripted_exit(VmxBasicExitReason::ControlRegisterAccesses)
+            .with(ro::EXIT_QUALIFICATION, 3)
+            .with(control::VPID, 1);
+        let mut cpu = SoftCpu::new().with_cpuid(
+            0x8000_0008,
+            0,
+            CpuIdResult {
+                eax: 46,
+                ebx: 0,
+                ecx: 0,
+                edx: 0,
+            },
+        );
+        let mut guest_registers = GuestRegisters {
+            rax: 0x1AD000,
+            ..Default::default()
+        };
+
+        handle(&mut guest_registers, &mut vmcs, &mut cpu).unwrap();
+
+        assert_eq!(vmcs.value(guest::CR3), Some(0x1AD000));
+        assert_eq!(cpu.invalidations(), [Invalidation::VpidSingleContext(1)]);
+        assert_eq!(
+            vmcs.value(guest::RIP),
+            Some(RIP + u64::from(INSTRUCTION_LENGTH))
+        );
+    }
+
+    #[test]
+    fn mov_to_cr3_above_maxphyaddr_raises_gp() {
+        let mut vmcs = scripted_exit(VmxBasicExitReason::ControlRegisterAccesses)
+            .with(ro::EXIT_QUALIFICATION, 3);
+        let mut cpu = SoftCpu::new().with_cpuid(
+            0x8000_0008,
+            0,
+            CpuIdResult {
+                eax: 39,
+                ebx: 0,
+                ecx: 0,
+                edx: 0,
+            },
+        );
+        let mut guest_registers = GuestRegisters {
+            rax: 1 << 40,
+            ..Default::default()
+        };
+
+        handle(&mut guest_registers, &mut vmcs, &mut cpu).unwrap();
+
+        assert_eq!(
+            vmcs.value(control::VMENTRY_INTERRUPTION_INFO_FIELD),
+            Some(0x8000_0B0D)
+        );
+        assert_eq!(vmcs.value(guest::CR3), None);
+        assert!(cpu.invalidations().is_empty());
+    }
+
+    #[test]
+    fn vmx_instructions_raise_ud() {
+        let mut vmcs = scripted_exit(VmxBasicExitReason::Vmcall);
+        let mut cpu = SoftCpu::new();
+
+        handle(&mut GuestRegisters::default(), &mut vmcs, &mut cpu).unwrap();
+
+        assert_eq!(
+            vmcs.value(control::VMENTRY_INTERRUPTION_INFO_FIELD),
+            Some(0x8000_0306)
+        );
+        assert_eq!(vmcs.value(guest::RIP), Some(RIP));
+    }
+
+    #[test]
+    fn triple_fault_is_unrecoverable() {
+        let mut vmcs = scripted_exit(VmxBasicExitReason::TripleFault);
+        let mut cpu = SoftCpu::new();
+
+        assert!(matches!(
+            handle(&mut GuestRegisters::default(), &mut vmcs, &mut cpu),
+            Err(HypervisorError::UnrecoverableVmExit)
+        ));
+    }
+
+    #[test]
+    fn unknown_exit_reason_fails() {
+        let mut vmcs = SoftVmcs::new().with(ro::EXIT_REASON, 0xFFFF);
+        let mut cpu = SoftCpu::new();
+
+        assert!(matches!(
+            handle(&mut GuestRegisters::default(), &mut vmcs, &mut cpu),
+            Err(HypervisorError::UnknownVMExitReason)
+        ));
+    }
+
+    #[test]
+    fn only_fs_and_gs_have_a_base_in_64bit_mode() {
+        // `[rbx + 0x10]` with a 64-bit address size, and DS or FS.
+        let operand = |segment: u64| {
+            MemoryOperand::from_u64(2 << 7 | segment << 15 | 1 << 22 | 3 << 23).unwrap()
+        };
+        let guest_registers = GuestRegisters {
+            rbx: 0x1000,
+            ..Default::default()
+        };
+        let vmcs = SoftVmcs::new()
+            .with(ro::EXIT_QUALIFICATION, 0x10)
+            .with(guest::DS_BASE, 0x10_0000)
+            .with(guest::FS_BASE, 0x20_0000);
+
+        assert_eq!(
+            operand_linear_address(&operand(3), &guest_registers, &vmcs),
+            0x10_1010
+        );
+
+        let vmcs = vmcs.with(guest::CS_ACCESS_RIGHTS, 0xA09B);
+
+        assert_eq!(
+            operand_linear_address(&operand(3), &guest_registers, &vmcs),
+            0x1010
+        );
+        assert_eq!(
+            operand_linear_address(&operand(4), &guest_registers, &vmcs),
+            0x20_1010
+        );
     }
 }
@@ -0,0 +1,92 @@
+//! Handles MOV DR VM exits by accessing the debug registers on behalf of the guest.
+//!
+//! DR7 is part of the guest-state area of the VMCS. The other debug registers aren't switched on VM
+//! entries and VM exits, so the hypervisor accesses them directly.
+
+use {
+    crate::{
+        intel::{
+            events::EventInjection,
+            exit_qualification::MovDrQualification,
+            vmcs_access::{VmcsAccess, VmcsFieldAccess},
+            vmcs_fields::{guest, ro},
+            vmexit::{guest_register, set_guest_register, ExitType},
+        },
+        utils::capture::GuestRegisters,
+    },
+    x86::{
+        controlregs::Cr4,
+        debugregs::{self, Dr6},
+    },
+};
+
+/// Handles the `MOV DR` VM-exit.
+///
+/// DR4 and DR5 are aliased to DR6 and DR7 if CR4.DE is clear, and raise #UD otherwise. Writing a value
+/// with any of the upper 32 bits set to DR6 or DR7 raises #GP(0).
+///
+/// # Arguments
+///
+/// * `guest_registers` - A mutable reference to the guest's current register state.
+/// * `vmcs` - The VMCS of the guest.
+///
+/// # Returns
+///
+/// * `ExitType::IncrementRIP` - To move past the `MOV DR` instruction in the VM, or `ExitType::Continue` if an exception is injected.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 18.2 DEBUG REGISTERS
+pub fn handle_mov_dr(guest_registers: &mut GuestRegisters, vmcs: &mut dyn VmcsAccess) -> ExitType {
+    log::debug!("Handling MOV DR VM exit...");
+
+    let qualification = MovDrQualification::from_u64(vmcs.get(ro::EXIT_QUALIFICATION));
+    let debug_extensions = vmcs.get(guest::CR4) & Cr4::CR4_DEBUGGING_EXTENSIONS.bits() as u64 != 0;
+
+    let debug_register = match qualification.debug_register {
+        4 | 5 if debug_extensions => {
+            EventInjection::vmentry_inject_ud(vmcs);
+            return ExitType::Continue;
+        }
+        4 | 5 => qualification.debug_register + 2,
+        debug_register => debug_register,
+    };
+
+    if qualification.from_debug_register {
+        let value = unsafe {
+            match debug_register {
+                0 => debugregs::dr0() as u64,
+                1 => debugregs::dr1() as u64,
+                2 => debugregs::dr2() as u64,
+                3 => debugregs::dr3() as u64,
+                6 => debugregs::dr6().bits() as u64,
+                _ => vmcs.get(guest::DR7),
+            }
+        };
+
+        log::trace!("MOV from DR{}: {:#x}", debug_register, value);
+        set_guest_register(guest_registers, vmcs, qualification.register, value);
+    } else {
+        let value = guest_register(guest_registers, qualification.register);
+
+        log::trace!("MOV to DR{}: {:#x}", debug_register, value);
+
+        if debug_register >= 6 && value >> 32 != 0 {
+            EventInjection::vmentry_inject_gp(vmcs, 0);
+            return ExitType::Continue;
+        }
+
+        unsafe {
+            match debug_register {
+                0 => debugregs::dr0_write(value as usize),
+                1 => debugregs::dr1_write(value as usize),
+                2 => debugregs::dr2_write(value as usize),
+                3 => debugregs::dr3_write(value as usize),
+                6 => debugregs::dr6_write(Dr6::from_bits_truncate(value as usize)),
+                _ => vmcs.set(guest::DR7, value),
+            }
+        }
+    }
+
+    log::debug!("MOV DR VMEXIT handled successfully!");
+
+    ExitType::IncrementRIP
+}
@@ -2,9 +2,17 @@
 //! read and write operations. It ensures that guest MSR accesses are properly
 //! intercepted and handled, with support for injecting faults for unauthorized accesses.
 
-use crate::{
-    intel::{events::EventInjection, vmexit::ExitType},
-    utils::capture::GuestRegisters,
+use {
+    crate::{
+        intel::{
+            cpu_access::CpuAccess,
+            events::EventInjection,
+            vmcs_access::VmcsAccess,
+            vmexit::{registry::VmExitData, ExitType},
+        },
+        utils::capture::GuestRegisters,
+    },
+    x86::msr::{IA32_EFER, IA32_LSTAR},
 };
 
 /// Enum representing the type of MSR access.
@@ -23,9 +31,17 @@ pub enum MsrAccessType {
 /// on the access type. For reserved or synthetic MSRs, a general protection
 /// fault is injected.
 ///
+/// If the syscall hooks are enabled, reads of `IA32_LSTAR` return the original system call
+/// entry point and writes update it, while the processor keeps using the dispatcher.
+/// If the syscall tracer is enabled, `IA32_EFER` is shadowed the same way, while the guest
+/// is executed with `EFER.SCE` cleared.
+///
 /// # Arguments
 ///
 /// * `registers` - A mutable reference to the guest's current register state.
+/// * `data` - The shared state of the syscall hooks and of the syscall tracer.
+/// * `vmcs` - The VMCS of the guest.
+/// * `cpu` - The processor executing the MSR accesses on the host.
 /// * `access_type` - The type of MSR access (read or write).
 ///
 /// # Returns
@@ -36,6 +52,9 @@ pub enum MsrAccessType {
 /// and Table C-1. Basic Exit Reasons 31 and 32.
 pub fn handle_msr_access(
     guest_registers: &mut GuestRegisters,
+    data: &VmExitData,
+    vmcs: &mut dyn VmcsAccess,
+    cpu: &mut dyn CpuAccess,
     access_type: MsrAccessType,
 ) -> ExitType {
     log::debug!("Handling MSR VM exit...");
@@ -52,11 +71,59 @@ pub fn handle_msr_access(
 
     let msr_id = guest_registers.rcx;
 
+    if msr_id == IA32_LSTAR as u64 {
+        if let Some(syscall_hooks) = data.syscall_hooks {
+            // The write restoring the original entry point is passed through.
+            let restoring =
+                matches!(access_type, MsrAccessType::Write) && syscall_hooks.is_restoring();
+
+            if syscall_hooks.is_enabled() && !restoring {
+                log::trace!("Shadowed IA32_LSTAR access attempted");
+                match access_type {
+                    MsrAccessType::Read => {
+                        let msr_value = syscall_hooks.original_lstar();
+                        guest_registers.rdx = msr_value >> 32;
+                        guest_registers.rax = msr_value & MSR_MASK_LOW;
+                    }
+                    MsrAccessType::Write => {
+                        let msr_value =
+                            (guest_registers.rdx << 32) | (guest_registers.rax & MSR_MASK_LOW);
+                        syscall_hooks.set_original_lstar(msr_value);
+                    }
+                }
+                return ExitType::IncrementRIP;
+            }
+        }
+    }
+
+    if msr_id == IA32_EFER as u64 {
+        if let Some(syscall_tracer) = data.syscall_tracer {
+            log::trace!("Shadowed IA32_EFER access attempted");
+            match access_type {
+                MsrAccessType::Read => {
+                    let msr_value = syscall_tracer.guest_efer();
+                    guest_registers.rdx = msr_value >> 32;
+                    guest_registers.rax = msr_value & MSR_MASK_LOW;
+                }
+                MsrAccessType::Write => {
+                    let msr_value =
+                        (guest_registers.rdx << 32) | (guest_registers.rax & MSR_MASK_LOW);
+                    syscall_tracer.set_guest_efer(msr_value);
+
+                    // IA32_EFER is loaded from the VMCS on VM entry. The other processors load the new
+                    // view on their next VM exit.
+                    syscall_tracer.sync_efer(vmcs);
+                }
+            }
+            return ExitType::IncrementRIP;
+        }
+    }
+
     // If the MSR address falls within a synthetic or reserved range, inject a general protection fault.
     /*
         if (msr_id >= HYPERV_MSR_START) && (msr_id <= HYPERV_MSR_END) {
             log::trace!("Synthetic MSR access attempted: {:#x}", msr_id);
-            EventInjection::vmentry_inject_gp(0);
+            EventInjection::vmentry_inject_gp(vmcs, 0);
             return ExitType::Continue;
         }
     */
@@ -64,25 +131,25 @@ pub fn handle_msr_access(
     // Determine if the MSR address is in a valid, reserved, or synthetic range.
     // If the MSR address is valid, execute the appropriate read or write operation.
     if (msr_id <= MSR_RANGE_LOW_END)
-        || ((msr_id >= MSR_RANGE_HIGH_START) && (msr_id <= MSR_RANGE_HIGH_END))
-        || (msr_id >= HYPERV_MSR_START) && (msr_id <= HYPERV_MSR_END)
+        || (MSR_RANGE_HIGH_START..=MSR_RANGE_HIGH_END).contains(&msr_id)
+        || (HYPERV_MSR_START..=HYPERV_MSR_END).contains(&msr_id)
     {
         log::trace!("Valid MSR access attempted: {:#x}", msr_id);
         match access_type {
             MsrAccessType::Read => {
-                let msr_value = unsafe { x86::msr::rdmsr(msr_id as _) };
+                let msr_value = cpu.rdmsr(msr_id as _);
                 guest_registers.rdx = msr_value >> 32;
                 guest_registers.rax = msr_value & MSR_MASK_LOW;
             }
             MsrAccessType::Write => {
                 let msr_value = (guest_registers.rdx << 32) | (guest_registers.rax & MSR_MASK_LOW);
-                unsafe { x86::msr::wrmsr(msr_id as _, msr_value) };
+                cpu.wrmsr(msr_id as _, msr_value);
             }
         }
     } else {
         // If the MSR is neither a known valid MSR nor a synthetic MSR, inject a general protection fault.
         log::trace!("Invalid MSR access attempted: {:#x}", msr_id);
-        EventInjection::vmentry_inject_gp(0);
+        EventInjection::vmentry_inject_gp(vmcs, 0);
         return ExitType::Continue;
     }
 
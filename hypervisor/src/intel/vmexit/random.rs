@@ -0,0 +1,106 @@
+//! Handles RDRAND and RDSEED VM exits by executing the instructions in VMX root operation and writing
+//! the random value to the destination register of the guest.
+
+use {
+    crate::{
+        error::HypervisorError,
+        intel::{
+            exit_qualification::{OperandSize, RegisterInstructionInformation},
+            vmcs_access::{VmcsAccess, VmcsFieldAccess},
+            vmcs_fields::{guest, ro},
+            vmexit::{guest_register, set_guest_register, ExitType},
+        },
+        utils::capture::GuestRegisters,
+    },
+    x86::random::{rdrand16, rdrand32, rdrand64, rdseed16, rdseed32, rdseed64},
+};
+
+/// The status flags of RFLAGS: CF, PF, AF, ZF, SF and OF.
+const RFLAGS_STATUS_FLAGS: u64 = 0x8D5;
+
+/// The carry flag of RFLAGS, which is set if a random value is available.
+const RFLAGS_CARRY_FLAG: u64 = 1 << 0;
+
+/// The instruction that caused the VM exit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RandomInstruction {
+    Rdrand,
+    Rdseed,
+}
+
+/// Handles the `RDRAND` and `RDSEED` VM-exits.
+///
+/// The value is written to the destination register with the operand size of the instruction, and
+/// RFLAGS.CF reports whether a random value was available. The other status flags are cleared.
+///
+/// # Arguments
+///
+/// * `guest_registers` - A mutable reference to the guest's current register state.
+/// * `vmcs` - The VMCS of the guest.
+/// * `instruction` - The instruction that caused the VM exit.
+///
+/// # Returns
+///
+/// * `Result<ExitType, HypervisorError>` - `ExitType::IncrementRIP` to move past the instruction, or an error if the instruction information is invalid.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: Table 28-12. Format of the VM-Exit Instruction-Information Field as Used for RDRAND, RDSEED, TPAUSE, and UMWAIT
+pub fn handle_random(
+    guest_registers: &mut GuestRegisters,
+    vmcs: &mut dyn VmcsAccess,
+    instruction: RandomInstruction,
+) -> Result<ExitType, HypervisorError> {
+    log::debug!("Handling {:?} VM exit...", instruction);
+
+    let information =
+        RegisterInstructionInformation::from_u64(vmcs.get(ro::VMEXIT_INSTRUCTION_INFO).into())
+            .ok_or(HypervisorError::InvalidInstructionInformation)?;
+
+    let previous = guest_register(guest_registers, information.register);
+
+    let (value, available) = unsafe {
+        match (instruction, information.operand_size) {
+            (RandomInstruction::Rdrand, OperandSize::Bits16) => {
+                let mut value = 0u16;
+                let available = rdrand16(&mut value);
+                ((previous & !0xFFFF) | value as u64, available)
+            }
+            (RandomInstruction::Rdrand, OperandSize::Bits32) => {
+                let mut value = 0u32;
+                let available = rdrand32(&mut value);
+                (value as u64, available)
+            }
+            (RandomInstruction::Rdrand, OperandSize::Bits64) => {
+                let mut value = 0u64;
+                let available = rdrand64(&mut value);
+                (value, available)
+            }
+            (RandomInstruction::Rdseed, OperandSize::Bits16) => {
+                let mut value = 0u16;
+                let available = rdseed16(&mut value);
+                ((previous & !0xFFFF) | value as u64, available)
+            }
+            (RandomInstruction::Rdseed, OperandSize::Bits32) => {
+                let mut value = 0u32;
+                let available = rdseed32(&mut value);
+                (value as u64, available)
+            }
+            (RandomInstruction::Rdseed, OperandSize::Bits64) => {
+                let mut value = 0u64;
+                let available = rdseed64(&mut value);
+                (value, available)
+            }
+        }
+    };
+
+    set_guest_register(guest_registers, vmcs, information.register, value);
+
+    let rflags = vmcs.get(guest::RFLAGS) & !RFLAGS_STATUS_FLAGS;
+    vmcs.set(
+        guest::RFLAGS,
+        rflags | if available { RFLAGS_CARRY_FLAG } else { 0 },
+    );
+
+    log::debug!("{:?} VMEXIT handled successfully!", instruction);
+
+    Ok(ExitType::IncrementRIP)
+}
@@ -0,0 +1,60 @@
+//! Handles RDPMC VM exits by reading the performance-monitoring counter selected by the guest.
+
+use {
+    crate::{
+        intel::{events::EventInjection, vmcs_access::VmcsAccess, vmexit::ExitType},
+        utils::{capture::GuestRegisters, instructions::rdpmc},
+    },
+    x86::cpuid::cpuid,
+};
+
+/// The bit of ECX that selects the fixed-function counters.
+const FIXED_FUNCTION_COUNTER: u32 = 1 << 30;
+
+/// Handles the `RDPMC` VM-exit.
+///
+/// The counter selected by ECX is validated against the counters enumerated by CPUID leaf 0AH, because
+/// executing RDPMC with an invalid counter in VMX root operation would fault in the hypervisor. The guest
+/// receives #GP(0) for invalid counters, like without VMX.
+///
+/// # Arguments
+///
+/// * `guest_registers` - A mutable reference to the guest's current register state.
+/// * `vmcs` - The VMCS of the guest.
+///
+/// # Returns
+///
+/// * `ExitType::IncrementRIP` - To move past the `RDPMC` instruction in the VM, or `ExitType::Continue` if #GP is injected.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual: 20.2 ARCHITECTURAL PERFORMANCE MONITORING
+pub fn handle_rdpmc(guest_registers: &mut GuestRegisters, vmcs: &mut dyn VmcsAccess) -> ExitType {
+    log::debug!("Handling RDPMC VM exit...");
+
+    let counter = guest_registers.rcx as u32;
+
+    // CPUID.0AH: EAX[15:8] is the number of general-purpose counters and EDX[4:0] the number of fixed-function counters.
+    let leaf = cpuid!(0xA);
+    let general_purpose_counters = (leaf.eax >> 8) & 0xFF;
+    let fixed_function_counters = leaf.edx & 0x1F;
+
+    let index = counter & !FIXED_FUNCTION_COUNTER;
+    let valid = match counter & FIXED_FUNCTION_COUNTER != 0 {
+        true => index < fixed_function_counters,
+        false => index < general_purpose_counters,
+    };
+
+    if !valid {
+        log::trace!("Invalid performance-monitoring counter: {:#x}", counter);
+        EventInjection::vmentry_inject_gp(vmcs, 0);
+        return ExitType::Continue;
+    }
+
+    let value = rdpmc(counter);
+
+    guest_registers.rax = value & 0xFFFFFFFF;
+    guest_registers.rdx = value >> 32;
+
+    log::debug!("RDPMC VMEXIT handled successfully!");
+
+    ExitType::IncrementRIP
+}
@@ -3,8 +3,11 @@
 //! information is provided to the guest while maintaining the integrity of the hypervisor.
 
 use {
-    crate::{intel::vmexit::ExitType, utils::capture::GuestRegisters},
-    x86::time::rdtsc,
+    crate::{
+        intel::{cpu_access::CpuAccess, vmexit::ExitType},
+        utils::capture::GuestRegisters,
+    },
+    x86::msr,
 };
 
 /*
@@ -22,17 +25,18 @@ User can add the following later:
 /// # Arguments
 ///
 /// * `guest_registers` - A mutable reference to the guest's current register state.
+/// * `cpu` - The processor whose time-stamp counter is read.
 ///
 /// # Returns
 ///
 /// * `ExitType::IncrementRIP` - To move past the `RDTSC` instruction in the VM.
 ///
 /// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual, Table C-1. Basic Exit Reasons 10.
-pub fn handle_rdtsc(guest_registers: &mut GuestRegisters) -> ExitType {
+pub fn handle_rdtsc(guest_registers: &mut GuestRegisters, cpu: &dyn CpuAccess) -> ExitType {
     log::debug!("Handling RDTSC VM exit...");
 
     // Read the time stamp counter.
-    let rdtsc_value: u64 = unsafe { rdtsc() };
+    let rdtsc_value: u64 = cpu.rdtsc();
 
     // Update the guest's RAX and RDX registers.
     guest_registers.rax = rdtsc_value & 0xFFFFFFFF; // Low 32 bits
@@ -42,3 +46,36 @@ pub fn handle_rdtsc(guest_registers: &mut GuestRegisters) -> ExitType {
 
     ExitType::IncrementRIP
 }
+
+/// Handles the `RDTSCP` VM-exit.
+///
+/// This function is invoked when the guest executes the `RDTSCP` instruction.
+/// It updates the guest's RAX and RDX registers like `handle_rdtsc`, and the guest's RCX
+/// register with the value of IA32_TSC_AUX.
+///
+/// # Arguments
+///
+/// * `guest_registers` - A mutable reference to the guest's current register state.
+/// * `cpu` - The processor whose time-stamp counter is read.
+///
+/// # Returns
+///
+/// * `ExitType::IncrementRIP` - To move past the `RDTSCP` instruction in the VM.
+///
+/// Reference: Intel® 64 and IA-32 Architectures Software Developer's Manual, Table C-1. Basic Exit Reasons 51.
+pub fn handle_rdtscp(guest_registers: &mut GuestRegisters, cpu: &dyn CpuAccess) -> ExitType {
+    log::debug!("Handling RDTSCP VM exit...");
+
+    // Read the time stamp counter and the processor ID.
+    let rdtsc_value: u64 = cpu.rdtsc();
+    let tsc_aux = cpu.rdmsr(msr::IA32_TSC_AUX);
+
+    // Update the guest's RAX, RDX and RCX registers.
+    guest_registers.rax = rdtsc_value & 0xFFFFFFFF; // Low 32 bits
+    guest_registers.rdx = rdtsc_value >> 32; // High 32 bits
+    guest_registers.rcx = tsc_aux & 0xFFFFFFFF;
+
+    log::debug!("RDTSCP VMEXIT handled successfully!");
+
+    ExitType::IncrementRIP
+}
@@ -0,0 +1,760 @@
+//! Provides a registry of VM-exit handlers keyed by the basic exit reason.
+//!
+//! Every exit reason has a chain of handlers. The most recently registered handler is called first
+//! and can either handle the VM-exit itself or delegate to the handler registered before it through
+//! `Next`, so consumers can override or extend the default handling (for example of CPUID, MSR
+//! accesses, exceptions or EPT violations) without modifying this crate.
+
+use {
+    crate::{
+        error::HypervisorError,
+        intel::{
+            address_space::AddressSpaceMonitor,
+            cpu_access::CpuAccess,
+            ept::hooks::HookManager,
+            events::EventInjection,
+            exit_qualification::VmEntryFailureQualification,
+            syscall_hook::SyscallHooks,
+            syscall_tracer::SyscallTracer,
+            vmcs_access::{VmcsAccess, VmcsFieldAccess},
+            vmcs_fields::ro,
+            vmerror::{ExceptionInterrupt, VmxBasicExitReason},
+            vmexit::{
+                control_register::handle_control_register_access,
+                cpuid::handle_cpuid,
+                descriptor_table::{handle_descriptor_table_access, handle_segment_table_access},
+                ept::{handle_ept_misconfiguration, handle_ept_violation},
+                exception::handle_exception,
+                idle::{handle_hlt, handle_monitor_mwait_pause, handle_tpause_umwait},
+                interrupt::{
+                    handle_external_interrupt, handle_instruction_timeout, handle_interrupt_window,
+                    handle_monitor_trap_flag, handle_nmi_window,
+                },
+                invd::{handle_invd, handle_wbinvd},
+                invept::handle_invept,
+                invlpg::{handle_invlpg, handle_invpcid},
+                invvpid::handle_invvpid,
+                io::{handle_io_instruction, IoPortHandler, IoPortHandlers},
+                mov_dr::handle_mov_dr,
+                msr::{handle_msr_access, MsrAccessType},
+                random::{handle_random, RandomInstruction},
+                rdpmc::handle_rdpmc,
+                rdtsc::{handle_rdtsc, handle_rdtscp},
+                xsetbv::handle_xsetbv,
+                ExitType, VmExit, VmExitPolicy,
+            },
+        },
+        utils::capture::GuestRegisters,
+    },
+    alloc::{boxed::Box, collections::BTreeMap, vec::Vec},
+};
+
+/// The state of a VM-exit passed to the handlers.
+pub struct VmExitContext<'a> {
+    /// The basic exit reason.
+    pub reason: VmxBasicExitReason,
+
+    /// The guest registers, with RIP, RSP and RFLAGS read from the VMCS.
+    pub guest_registers: &'a mut GuestRegisters,
+
+    /// The state shared between the processors that the handlers use.
+    pub data: VmExitData<'a>,
+
+    /// The VMCS of the guest, through which the exit information is read and the guest state is
+    /// written.
+    pub vmcs: &'a mut dyn VmcsAccess,
+
+    /// The processor executing the instructions that the handlers use on behalf of the guest.
+    pub cpu: &'a mut dyn CpuAccess,
+}
+
+/// The state shared between the processors that the handlers use, borrowed from the shared data.
+///
+/// The state is only borrowed immutably, because the handlers of all processors use it at the same
+/// time. The parts that change in VMX root operation synchronize internally.
+#[derive(Clone, Copy, Default)]
+pub struct VmExitData<'a> {
+    /// The hook manager, whose function hooks are called on breakpoints.
+    pub hook_manager: Option<&'a HookManager>,
+
+    /// The syscall hooks intercepting `IA32_LSTAR`, if enabled.
+    pub syscall_hooks: Option<&'a SyscallHooks>,
+
+    /// The syscall tracer clearing `EFER.SCE`, if enabled.
+    pub syscall_tracer: Option<&'a SyscallTracer>,
+
+    /// The address-space monitor, if enabled.
+    pub address_space_monitor: Option<&'a AddressSpaceMonitor>,
+
+    /// The handlers of the intercepted I/O ports.
+    pub io_ports: Option<&'a IoPortHandlers>,
+
+    /// The pointer to the primary EPT.
+    pub primary_eptp: u64,
+
+    /// The pointer to the secondary EPT.
+    #[cfg(feature = "secondary-ept")]
+    pub secondary_eptp: u64,
+}
+
+/// A handler of VM-exits.
+///
+/// The handler is called in VMX root operation with the state of the VM-exit and the rest of the
+/// chain of handlers registered for the exit reason. It's implemented for all functions with the
+/// signature of `handle`.
+pub trait VmExitHandler: Send + Sync {
+    /// Handles a VM-exit.
+    ///
+    /// # Arguments
+    ///
+    /// * `exit` - The state of the VM-exit.
+    /// * `next` - The handlers registered before this one, which handle the VM-exit when called.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<ExitType, HypervisorError>` - How the guest continues, or an error if the VM-exit couldn't be handled.
+    fn handle(&self, exit: &mut VmExitContext, next: Next) -> Result<ExitType, HypervisorError>;
+}
+
+impl<F> VmExitHandler for F
+where
+    F: Fn(&mut VmExitContext, Next) -> Result<ExitType, HypervisorError> + Send + Sync,
+{
+    fn handle(&self, exit: &mut VmExitContext, next: Next) -> Result<ExitType, HypervisorError> {
+        self(exit, next)
+    }
+}
+
+/// The rest of a chain of handlers.
+#[derive(Clone, Copy)]
+pub struct Next<'a> {
+    /// The handlers registered before the current one, in registration order.
+    handlers: &'a [Box<dyn VmExitHandler>],
+}
+
+impl<'a> Next<'a> {
+    /// Calls the next handler of the chain.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<ExitType, HypervisorError>` - The result of the next handler, or `UnhandledVmExit` if the chain is exhausted.
+    pub fn run(self, exit: &mut VmExitContext) -> Result<ExitType, HypervisorError> {
+        match self.handlers.split_last() {
+            Some((handler, handlers)) => handler.handle(exit, Next { handlers }),
+            None => Err(HypervisorError::UnhandledVmExit),
+        }
+    }
+
+    /// Checks whether the chain is exhausted.
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+}
+
+/// The chains of VM-exit handlers keyed by the basic exit reason, and the handlers of the intercepted
+/// I/O ports, to which the default handler of the I/O instruction VM-exits dispatches.
+pub struct VmExitHandlers {
+    /// The handlers of every exit reason, in registration order.
+    handlers: BTreeMap<u16, Vec<Box<dyn VmExitHandler>>>,
+
+    /// The handlers of the intercepted I/O ports.
+    io_ports: IoPortHandlers,
+}
+
+impl VmExitHandlers {
+    /// Creates a registry without any handler.
+    pub fn new() -> Self {
+        Self {
+            handlers: BTreeMap::new(),
+            io_ports: IoPortHandlers::new(),
+        }
+    }
+
+    /// Creates a registry with the default handlers of the hypervisor.
+    ///
+    /// Every basic exit reason has a handler, which follows the policy of the exit reason in
+    /// `VmExit::policy`: the exit reasons that are emulated have their own handler, and the others inject
+    /// an exception, resume the guest or fail with `UnrecoverableVmExit`.
+    ///
+    /// # Panics
+    ///
+    /// If an exit reason is emulated according to its policy, but has no handler.
+    ///
+    /// Intel® 64 and IA-32 Architectures Software Developer's Manual: 26.1.2 Instructions That Cause VM Exits Unconditionally:
+    /// - The following instructions cause VM exits when they are executed in VMX non-root operation: CPUID, GETSEC, INVD, and XSETBV.
+    /// - This is also true of instructions introduced with VMX, which include: INVEPT, INVVPID, VMCALL, VMCLEAR, VMLAUNCH, VMPTRLD, VMPTRST, VMRESUME, VMXOFF, and VMXON.
+    ///
+    /// 26.1.3 Instructions That Cause VM Exits Conditionally: Certain instructions cause VM exits in VMX non-root operation depending on the setting of the VM-execution controls.
+    pub fn with_defaults() -> Self {
+        let mut handlers = Self::new();
+
+        handlers.register(VmxBasicExitReason::ExceptionOrNmi, default_exception);
+        handlers.register(
+            VmxBasicExitReason::ExternalInterrupt,
+            default_external_interrupt,
+        );
+        handlers.register(
+            VmxBasicExitReason::InterruptWindow,
+            default_interrupt_window,
+        );
+        handlers.register(VmxBasicExitReason::NmiWindow, default_nmi_window);
+        handlers.register(VmxBasicExitReason::Cpuid, default_cpuid);
+        handlers.register(VmxBasicExitReason::Hlt, default_hlt);
+        handlers.register(VmxBasicExitReason::Invd, default_invd);
+        handlers.register(VmxBasicExitReason::Invlpg, default_invlpg);
+        handlers.register(VmxBasicExitReason::Rdpmc, default_rdpmc);
+        handlers.register(VmxBasicExitReason::Rdtsc, default_rdtsc);
+        handlers.register(
+            VmxBasicExitReason::ControlRegisterAccesses,
+            default_control_register_access,
+        );
+        handlers.register(VmxBasicExitReason::MovDr, default_mov_dr);
+        handlers.register(VmxBasicExitReason::IoInstruction, default_io_instruction);
+        handlers.register(VmxBasicExitReason::Rdmsr, default_rdmsr);
+        handlers.register(VmxBasicExitReason::Wrmsr, default_wrmsr);
+        handlers.register(
+            VmxBasicExitReason::MonitorTrapFlag,
+            default_monitor_trap_flag,
+        );
+
+        for reason in [
+            VmxBasicExitReason::Mwait,
+            VmxBasicExitReason::Monitor,
+            VmxBasicExitReason::Pause,
+        ] {
+            handlers.register(reason, default_monitor_mwait_pause);
+        }
+
+        handlers.register(
+            VmxBasicExitReason::AccessToGdtrOrIdtr,
+            default_descriptor_table_access,
+        );
+        handlers.register(
+            VmxBasicExitReason::AccessToLdtrOrTr,
+            default_segment_table_access,
+        );
+        handlers.register(VmxBasicExitReason::EptViolation, default_ept_violation);
+        handlers.register(
+            VmxBasicExitReason::EptMisconfiguration,
+            default_ept_misconfiguration,
+        );
+        handlers.register(VmxBasicExitReason::Invept, default_invept);
+        handlers.register(VmxBasicExitReason::Rdtscp, default_rdtscp);
+        handlers.register(VmxBasicExitReason::Invvpid, default_invvpid);
+        handlers.register(VmxBasicExitReason::WbinvdOrWbnoinvd, default_wbinvd);
+        handlers.register(VmxBasicExitReason::Xsetbv, default_xsetbv);
+        handlers.register(VmxBasicExitReason::Rdrand, default_rdrand);
+        handlers.register(VmxBasicExitReason::Invpcid, default_invpcid);
+        handlers.register(VmxBasicExitReason::Rdseed, default_rdseed);
+
+        for reason in [VmxBasicExitReason::Umwait, VmxBasicExitReason::Tpause] {
+            handlers.register(reason, default_tpause_umwait);
+        }
+
+        handlers.register(
+            VmxBasicExitReason::InstructionTimeout,
+            default_instruction_timeout,
+        );
+
+        // The other exit reasons are handled according to their policy, which can't be to emulate them.
+        // The instruction timeout is the highest basic exit reason.
+        for reason in (0..=VmxBasicExitReason::InstructionTimeout as u32)
+            .filter_map(VmxBasicExitReason::from_u32)
+            .filter(|reason| !handlers.is_handled(*reason))
+            .collect::<Vec<_>>()
+        {
+            match VmExit::policy(reason) {
+                VmExitPolicy::Reflect(exception) => handlers.register(reason, Reflect(exception)),
+                VmExitPolicy::Resume => handlers.register(reason, default_resume),
+                VmExitPolicy::Fatal => handlers.register(reason, default_fatal),
+                VmExitPolicy::Emulate => {
+                    panic!("{:?} is emulated, but has no default handler", reason)
+                }
+            }
+        }
+
+        handlers
+    }
+
+    /// Registers a handler on top of the chain of an exit reason. The handler is called first and
+    /// can delegate to the previously registered handlers.
+    ///
+    /// # Arguments
+    ///
+    /// * `reason` - The basic exit reason.
+    /// * `handler` - The handler.
+    pub fn register(&mut self, reason: VmxBasicExitReason, handler: impl VmExitHandler + 'static) {
+        self.handlers
+            .entry(reason as u16)
+            .or_default()
+            .push(Box::new(handler));
+    }
+
+    /// Replaces the chain of an exit reason with a single handler.
+    ///
+    /// # Arguments
+    ///
+    /// * `reason` - The basic exit reason.
+    /// * `handler` - The handler.
+    pub fn replace(&mut self, reason: VmxBasicExitReason, handler: impl VmExitHandler + 'static) {
+        self.remove(reason);
+        self.register(reason, handler);
+    }
+
+    /// Removes all the handlers of an exit reason, so its VM-exits fail with `UnhandledVmExit`.
+    pub fn remove(&mut self, reason: VmxBasicExitReason) {
+        self.handlers.remove(&(reason as u16));
+    }
+
+    /// Checks whether an exit reason has at least one handler.
+    pub fn is_handled(&self, reason: VmxBasicExitReason) -> bool {
+        self.handlers
+            .get(&(reason as u16))
+            .is_some_and(|handlers| !handlers.is_empty())
+    }
+
+    /// Intercepts the accesses to an I/O port through the I/O bitmaps, replacing the previous handler of
+    /// the port.
+    ///
+    /// # Arguments
+    ///
+    /// * `port` - The I/O port.
+    /// * `handler` - The handler, which is called by the default handler of the I/O instruction VM-exits.
+    pub fn intercept_port(&mut self, port: u16, handler: impl IoPortHandler + 'static) {
+        self.io_ports.intercept_port(port, handler);
+    }
+
+    /// Returns the handlers of the intercepted I/O ports.
+    pub fn io_ports(&self) -> &IoPortHandlers {
+        &self.io_ports
+    }
+
+    /// Dispatches a VM-exit to the most recently registered handler of its exit reason.
+    ///
+    /// # Arguments
+    ///
+    /// * `exit` - The state of the VM-exit.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<ExitType, HypervisorError>` - How the guest continues, or `UnhandledVmExit` if the exit reason has no handler.
+    pub fn dispatch(&self, exit: &mut VmExitContext) -> Result<ExitType, HypervisorError> {
+        let handlers = self
+            .handlers
+            .get(&(exit.reason as u16))
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+
+        Next { handlers }.run(exit)
+    }
+}
+
+impl Default for VmExitHandlers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Injects the exception of the exit reasons with the `Reflect` policy.
+struct Reflect(ExceptionInterrupt);
+
+impl VmExitHandler for Reflect {
+    fn handle(&self, exit: &mut VmExitContext, _next: Next) -> Result<ExitType, HypervisorError> {
+        log::debug!("Reflecting {} as {:?}", exit.reason, self.0);
+
+        EventInjection::vmentry_inject_exception(exit.vmcs, self.0, 0);
+
+        Ok(ExitType::Continue)
+    }
+}
+
+/// Resumes the guest for the exit reasons with the `Resume` policy.
+fn default_resume(exit: &mut VmExitContext, _next: Next) -> Result<ExitType, HypervisorError> {
+    log::debug!("Resuming the guest after {}", exit.reason);
+
+    Ok(ExitType::Continue)
+}
+
+/// Fails the VM exit for the exit reasons with the `Fatal` policy.
+fn default_fatal(exit: &mut VmExitContext, _next: Next) -> Result<ExitType, HypervisorError> {
+    log::error!("The guest can't be resumed after {}", exit.reason);
+
+    if exit.reason == VmxBasicExitReason::VmEntryFailureInvalidGuestState {
+        let qualification = exit.vmcs.get(ro::EXIT_QUALIFICATION);
+
+        match VmEntryFailureQualification::from_u64(qualification) {
+            Some(qualification) => log::error!("VM-entry failure: {:?}", qualification),
+            None => log::error!("VM-entry failure: {:#x}", qualification),
+        }
+    }
+
+    Err(HypervisorError::UnrecoverableVmExit)
+}
+
+/// Handles exceptions and NMIs with `handle_exception`.
+fn default_exception(exit: &mut VmExitContext, _next: Next) -> Result<ExitType, HypervisorError> {
+    Ok(handle_exception(
+        exit.guest_registers,
+        &exit.data,
+        exit.vmcs,
+        exit.cpu,
+    ))
+}
+
+/// Handles CPUID with `handle_cpuid`.
+fn default_cpuid(exit: &mut VmExitContext, _next: Next) -> Result<ExitType, HypervisorError> {
+    Ok(handle_cpuid(exit.guest_registers, exit.cpu))
+}
+
+/// Handles external interrupts with `handle_external_interrupt`.
+fn default_external_interrupt(
+    exit: &mut VmExitContext,
+    _next: Next,
+) -> Result<ExitType, HypervisorError> {
+    Ok(handle_external_interrupt(exit.vmcs))
+}
+
+/// Handles interrupt windows with `handle_interrupt_window`.
+fn default_interrupt_window(
+    exit: &mut VmExitContext,
+    _next: Next,
+) -> Result<ExitType, HypervisorError> {
+    Ok(handle_interrupt_window(exit.vmcs))
+}
+
+/// Handles NMI windows with `handle_nmi_window`.
+fn default_nmi_window(exit: &mut VmExitContext, _next: Next) -> Result<ExitType, HypervisorError> {
+    Ok(handle_nmi_window(exit.vmcs))
+}
+
+/// Handles HLT with `handle_hlt`.
+fn default_hlt(exit: &mut VmExitContext, _next: Next) -> Result<ExitType, HypervisorError> {
+    Ok(handle_hlt(exit.vmcs))
+}
+
+/// Handles INVLPG with `handle_invlpg`.
+fn default_invlpg(exit: &mut VmExitContext, _next: Next) -> Result<ExitType, HypervisorError> {
+    Ok(handle_invlpg(exit.vmcs, exit.cpu))
+}
+
+/// Handles RDPMC with `handle_rdpmc`.
+fn default_rdpmc(exit: &mut VmExitContext, _next: Next) -> Result<ExitType, HypervisorError> {
+    Ok(handle_rdpmc(exit.guest_registers, exit.vmcs))
+}
+
+/// Handles control-register accesses with `handle_control_register_access`.
+fn default_control_register_access(
+    exit: &mut VmExitContext,
+    _next: Next,
+) -> Result<ExitType, HypervisorError> {
+    handle_control_register_access(exit.guest_registers, &exit.data, exit.vmcs, exit.cpu)
+}
+
+/// Handles MOV DR with `handle_mov_dr`.
+fn default_mov_dr(exit: &mut VmExitContext, _next: Next) -> Result<ExitType, HypervisorError> {
+    Ok(handle_mov_dr(exit.guest_registers, exit.vmcs))
+}
+
+/// Handles I/O instructions with `handle_io_instruction`.
+fn default_io_instruction(
+    exit: &mut VmExitContext,
+    _next: Next,
+) -> Result<ExitType, HypervisorError> {
+    // Without intercepted ports, the I/O instructions are forwarded to the ports.
+    let no_io_ports = IoPortHandlers::new();
+    let io_ports = exit.data.io_ports.unwrap_or(&no_io_ports);
+
+    handle_io_instruction(exit.guest_registers, io_ports, exit.vmcs, exit.cpu)
+}
+
+/// Handles RDMSR with `handle_msr_access`.
+fn default_rdmsr(exit: &mut VmExitContext, _next: Next) -> Result<ExitType, HypervisorError> {
+    Ok(handle_msr_access(
+        exit.guest_registers,
+        &exit.data,
+        exit.vmcs,
+        exit.cpu,
+        MsrAccessType::Read,
+    ))
+}
+
+/// Handles WRMSR with `handle_msr_access`.
+fn default_wrmsr(exit: &mut VmExitContext, _next: Next) -> Result<ExitType, HypervisorError> {
+    Ok(handle_msr_access(
+        exit.guest_registers,
+        &exit.data,
+        exit.vmcs,
+        exit.cpu,
+        MsrAccessType::Write,
+    ))
+}
+
+/// Handles INVD with `handle_invd`.
+fn default_invd(exit: &mut VmExitContext, _next: Next) -> Result<ExitType, HypervisorError> {
+    Ok(handle_invd(exit.guest_registers))
+}
+
+/// Handles RDTSC with `handle_rdtsc`.
+fn default_rdtsc(exit: &mut VmExitContext, _next: Next) -> Result<ExitType, HypervisorError> {
+    Ok(handle_rdtsc(exit.guest_registers, exit.cpu))
+}
+
+/// Handles the monitor trap flag with `handle_monitor_trap_flag`.
+fn default_monitor_trap_flag(
+    exit: &mut VmExitContext,
+    _next: Next,
+) -> Result<ExitType, HypervisorError> {
+    Ok(handle_monitor_trap_flag(exit.vmcs))
+}
+
+/// Handles MONITOR, MWAIT and PAUSE with `handle_monitor_mwait_pause`.
+fn default_monitor_mwait_pause(
+    _exit: &mut VmExitContext,
+    _next: Next,
+) -> Result<ExitType, HypervisorError> {
+    Ok(handle_monitor_mwait_pause())
+}
+
+/// Handles SGDT, SIDT, LGDT and LIDT with `handle_descriptor_table_access`.
+fn default_descriptor_table_access(
+    exit: &mut VmExitContext,
+    _next: Next,
+) -> Result<ExitType, HypervisorError> {
+    handle_descriptor_table_access(exit.guest_registers, exit.vmcs)
+}
+
+/// Handles SLDT, STR, LLDT and LTR with `handle_segment_table_access`.
+fn default_segment_table_access(
+    exit: &mut VmExitContext,
+    _next: Next,
+) -> Result<ExitType, HypervisorError> {
+    handle_segment_table_access(exit.guest_registers, exit.vmcs)
+}
+
+/// Handles EPT violations with `handle_ept_violation`.
+fn default_ept_violation(
+    exit: &mut VmExitContext,
+    _next: Next,
+) -> Result<ExitType, HypervisorError> {
+    Ok(handle_ept_violation(
+        exit.guest_registers,
+        &exit.data,
+        exit.vmcs,
+        exit.cpu,
+    ))
+}
+
+/// Handles EPT misconfigurations with `handle_ept_misconfiguration`.
+fn default_ept_misconfiguration(
+    exit: &mut VmExitContext,
+    _next: Next,
+) -> Result<ExitType, HypervisorError> {
+    Ok(handle_ept_misconfiguration(exit.vmcs))
+}
+
+/// Handles INVEPT with `handle_invept`.
+fn default_invept(exit: &mut VmExitContext, _next: Next) -> Result<ExitType, HypervisorError> {
+    Ok(handle_invept(exit.cpu))
+}
+
+/// Handles INVVPID with `handle_invvpid`.
+fn default_invvpid(exit: &mut VmExitContext, _next: Next) -> Result<ExitType, HypervisorError> {
+    Ok(handle_invvpid(exit.cpu))
+}
+
+/// Handles XSETBV with `handle_xsetbv`.
+fn default_xsetbv(exit: &mut VmExitContext, _next: Next) -> Result<ExitType, HypervisorError> {
+    Ok(handle_xsetbv(exit.guest_registers, exit.cpu))
+}
+
+/// Handles RDTSCP with `handle_rdtscp`.
+fn default_rdtscp(exit: &mut VmExitContext, _next: Next) -> Result<ExitType, HypervisorError> {
+    Ok(handle_rdtscp(exit.guest_registers, exit.cpu))
+}
+
+/// Handles WBINVD and WBNOINVD with `handle_wbinvd`.
+fn default_wbinvd(exit: &mut VmExitContext, _next: Next) -> Result<ExitType, HypervisorError> {
+    Ok(handle_wbinvd(exit.guest_registers))
+}
+
+/// Handles RDRAND with `handle_random`.
+fn default_rdrand(exit: &mut VmExitContext, _next: Next) -> Result<ExitType, HypervisorError> {
+    handle_random(exit.guest_registers, exit.vmcs, RandomInstruction::Rdrand)
+}
+
+/// Handles INVPCID with `handle_invpcid`.
+fn default_invpcid(exit: &mut VmExitContext, _next: Next) -> Result<ExitType, HypervisorError> {
+    handle_invpcid(exit.guest_registers, exit.vmcs, exit.cpu)
+}
+
+/// Handles RDSEED with `handle_random`.
+fn default_rdseed(exit: &mut VmExitContext, _next: Next) -> Result<ExitType, HypervisorError> {
+    handle_random(exit.guest_registers, exit.vmcs, RandomInstruction::Rdseed)
+}
+
+/// Handles TPAUSE and UMWAIT with `handle_tpause_umwait`.
+fn default_tpause_umwait(
+    exit: &mut VmExitContext,
+    _next: Next,
+) -> Result<ExitType, HypervisorError> {
+    Ok(handle_tpause_umwait(exit.vmcs))
+}
+
+/// Handles instruction timeouts with `handle_instruction_timeout`.
+fn default_instruction_timeout(
+    exit: &mut VmExitContext,
+    _next: Next,
+) -> Result<ExitType, HypervisorError> {
+    handle_instruction_timeout(exit.vmcs)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::intel::{cpu_access::SoftCpu, vmcs_access::SoftVmcs},
+    };
+
+    /// Dispatches a VM exit of an exit reason to the handlers.
+    fn dispatch(
+        handlers: &VmExitHandlers,
+        reason: VmxBasicExitReason,
+        guest_registers: &mut GuestRegisters,
+    ) -> Result<ExitType, HypervisorError> {
+        let mut vmcs = SoftVmcs::new();
+        let mut cpu = SoftCpu::new();
+        let mut exit = VmExitContext {
+            reason,
+            guest_registers,
+            data: VmExitData::default(),
+            vmcs: &mut vmcs,
+            cpu: &mut cpu,
+        };
+
+        handlers.dispatch(&mut exit)
+    }
+
+    /// Records its call in RAX and delegates to the next handler.
+    fn record_first(exit: &mut VmExitContext, next: Next) -> Result<ExitType, HypervisorError> {
+        exit.guest_registers.rax = exit.guest_registers.rax * 10 + 1;
+        next.run(exit)
+    }
+
+    /// Records its call in RAX and delegates to the next handler.
+    fn record_second(exit: &mut VmExitContext, next: Next) -> Result<ExitType, HypervisorError> {
+        exit.guest_registers.rax = exit.guest_registers.rax * 10 + 2;
+        next.run(exit)
+    }
+
+    /// Records its call in RAX and handles the VM exit.
+    fn record_last(exit: &mut VmExitContext, next: Next) -> Result<ExitType, HypervisorError> {
+        assert!(next.is_empty());
+        exit.guest_registers.rax = exit.guest_registers.rax * 10 + 3;
+        Ok(ExitType::IncrementRIP)
+    }
+
+    /// Handles the VM exit without delegating.
+    fn handle_alone(exit: &mut VmExitContext, _next: Next) -> Result<ExitType, HypervisorError> {
+        exit.guest_registers.rbx = 1;
+        Ok(ExitType::Continue)
+    }
+
+    #[test]
+    fn handlers_run_from_the_most_recently_registered() {
+        let mut handlers = VmExitHandlers::new();
+        handlers.register(VmxBasicExitReason::Cpuid, record_last);
+        handlers.register(VmxBasicExitReason::Cpuid, record_second);
+        handlers.register(VmxBasicExitReason::Cpuid, record_first);
+
+        let mut guest_registers = GuestRegisters::default();
+        let exit_type = dispatch(&handlers, VmxBasicExitReason::Cpuid, &mut guest_registers);
+
+        assert!(matches!(exit_type, Ok(ExitType::IncrementRIP)));
+        assert_eq!(guest_registers.rax, 123);
+    }
+
+    #[test]
+    fn handler_can_skip_the_rest_of_the_chain() {
+        let mut handlers = VmExitHandlers::new();
+        handlers.register(VmxBasicExitReason::Cpuid, record_last);
+        handlers.register(VmxBasicExitReason::Cpuid, handle_alone);
+
+        let mut guest_registers = GuestRegisters::default();
+        let exit_type = dispatch(&handlers, VmxBasicExitReason::Cpuid, &mut guest_registers);
+
+        assert!(matches!(exit_type, Ok(ExitType::Continue)));
+        assert_eq!(guest_registers.rax, 0);
+        assert_eq!(guest_registers.rbx, 1);
+    }
+
+    #[test]
+    fn exhausted_chain_is_unhandled() {
+        let mut handlers = VmExitHandlers::new();
+        handlers.register(VmxBasicExitReason::Cpuid, record_first);
+
+        let mut guest_registers = GuestRegisters::default();
+
+        assert!(matches!(
+            dispatch(&handlers, VmxBasicExitReason::Cpuid, &mut guest_registers),
+            Err(HypervisorError::UnhandledVmExit)
+        ));
+        assert!(matches!(
+            dispatch(&handlers, VmxBasicExitReason::Hlt, &mut guest_registers),
+            Err(HypervisorError::UnhandledVmExit)
+        ));
+    }
+
+    #[test]
+    fn replace_and_remove_change_the_chain() {
+        let mut handlers = VmExitHandlers::new();
+        handlers.register(VmxBasicExitReason::Cpuid, record_first);
+        handlers.replace(VmxBasicExitReason::Cpuid, record_last);
+
+        let mut guest_registers = GuestRegisters::default();
+        dispatch(&handlers, VmxBasicExitReason::Cpuid, &mut guest_registers).unwrap();
+        assert_eq!(guest_registers.rax, 3);
+
+        handlers.remove(VmxBasicExitReason::Cpuid);
+        assert!(!handlers.is_handled(VmxBasicExitReason::Cpuid));
+    }
+
+    #[test]
+    fn default_matches_new() {
+        assert!(!VmExitHandlers::default().is_handled(VmxBasicExitReason::Cpuid));
+        assert!(VmExitHandlers::with_defaults().is_handled(VmxBasicExitReason::Cpuid));
+    }
+
+    #[test]
+    fn every_exit_reason_has_a_default_handler() {
+        let handlers = VmExitHandlers::with_defaults();
+
+        for reason in (0..=VmxBasicExitReason::InstructionTimeout as u32)
+            .filter_map(VmxBasicExitReason::from_u32)
+        {
+            assert!(handlers.is_handled(reason), "{:?}", reason);
+        }
+    }
+
+    #[test]
+    fn override_can_delegate_to_the_default_handler() {
+        let mut handlers = VmExitHandlers::with_defaults();
+        handlers.register(VmxBasicExitReason::Rdtsc, record_first);
+
+        let mut vmcs = SoftVmcs::new();
+        let mut cpu = SoftCpu::new().with_tsc(0x5);
+        let mut guest_registers = GuestRegisters::default();
+        let mut exit = VmExitContext {
+            reason: VmxBasicExitReason::Rdtsc,
+            guest_registers: &mut guest_registers,
+            data: VmExitData::default(),
+            vmcs: &mut vmcs,
+            cpu: &mut cpu,
+        };
+
+        let exit_type = handlers.dispatch(&mut exit);
+
+        assert!(matches!(exit_type, Ok(ExitType::IncrementRIP)));
+        // The default handler overwrote RAX with the time-stamp counter after the override recorded its call.
+        assert_eq!(guest_registers.rax, 0x5);
+    }
+}
@@ -0,0 +1,227 @@
+//! Handles the VM exits of the syscall hooks intercepting `IA32_LSTAR`: the entry of a syscall at the
+//! dispatcher stub and the single step after the syscall returned to user mode.
+//! Also handles the invalid opcode exceptions raised by `syscall` and `sysret` for the syscall tracer.
+
+use crate::{
+    intel::{
+        cpu_access::CpuAccess,
+        events::EventInjection,
+        guest_memory::GuestMemory,
+        syscall_hook::{
+            emulate_syscall, emulate_sysret, SyscallContext, SyscallHooks, RFLAGS_TRAP_FLAG,
+        },
+        syscall_tracer::SyscallTracer,
+        vmcs_access::{VmcsAccess, VmcsFieldAccess},
+        vmcs_fields::guest,
+    },
+    utils::{capture::GuestRegisters, function_hook::HookAction},
+};
+
+/// Handles the entry of a syscall at the dispatcher stub.
+///
+/// Executes the pre callback registered for the syscall number and continues at the original system
+/// call entry point, or completes the syscall by emulating `sysret` if the callback returned a value.
+/// If a post callback is registered, the trap flag is set in the RFLAGS restored by `sysret`.
+///
+/// # Arguments
+///
+/// * `guest_registers` - A mutable reference to the guest's register state at the dispatcher.
+/// * `syscall_hooks` - The syscall hooks.
+/// * `vmcs` - The VMCS of the guest.
+/// * `cpu` - The processor whose syscall MSRs are used.
+pub fn handle_syscall_entry(
+    guest_registers: &mut GuestRegisters,
+    syscall_hooks: &SyscallHooks,
+    vmcs: &mut dyn VmcsAccess,
+    cpu: &dyn CpuAccess,
+) {
+    let guest_memory = GuestMemory::current(vmcs);
+    let context = SyscallContext::capture(guest_registers, &guest_memory, vmcs);
+
+    log::trace!(
+        "Syscall {:#x} from {:#x}",
+        context.number,
+        context.return_address
+    );
+
+    let handler = syscall_hooks
+        .handler(context.number)
+        .copied()
+        .unwrap_or_default();
+
+    if let Some(pre) = handler.pre {
+        if let HookAction::Return(value) = pre(guest_registers, &guest_memory, &context) {
+            log::trace!("Syscall {:#x} completed with {:#x}", context.number, value);
+
+            guest_registers.rax = value;
+            emulate_sysret(guest_registers, true, vmcs, cpu);
+            vmcs.set(guest::RSP, guest_registers.rsp);
+            return;
+        }
+    }
+
+    if handler.post.is_some() {
+        // The pre callback may have changed the arguments, so the context is captured again.
+        syscall_hooks.begin_return(SyscallContext::capture(
+            guest_registers,
+            &guest_memory,
+            vmcs,
+        ));
+        guest_registers.r11 |= RFLAGS_TRAP_FLAG;
+    }
+
+    guest_registers.rip = syscall_hooks.original_lstar();
+
+    vmcs.set(guest::RIP, guest_registers.rip);
+    vmcs.set(guest::RSP, guest_registers.rsp);
+}
+
+/// Handles a single step that might have been caused by the trap flag set at the entry of a syscall.
+///
+/// Executes the post callback registered for the syscall number and clears the trap flag, unless it
+/// was already set by the caller.
+///
+/// # Arguments
+///
+/// * `guest_registers` - A mutable reference to the guest's register state after the single step.
+/// * `syscall_hooks` - The syscall hooks.
+/// * `vmcs` - The VMCS of the guest.
+///
+/// # Returns
+///
+/// * `bool` - `true` if the single step was caused by the syscall hooks, `false` if the debug exception must be delivered to the guest.
+pub fn handle_syscall_return(
+    guest_registers: &mut GuestRegisters,
+    syscall_hooks: &SyscallHooks,
+    vmcs: &mut dyn VmcsAccess,
+) -> bool {
+    let guest_memory = GuestMemory::current(vmcs);
+    let cr3 = guest_memory.cr3();
+    let teb = vmcs.get(guest::GS_BASE);
+
+    let Some(context) = syscall_hooks.take_return(cr3, teb, guest_registers.rsp) else {
+        // The trap flag was inherited by a return to user mode that isn't the return of the
+        // syscall (for example a user-mode callback), so the single step is swallowed.
+        if syscall_hooks.has_pending_trap(cr3, teb) {
+            log::trace!("Swallowing single step at {:#x}", guest_registers.rip);
+            clear_trap_flag(guest_registers, vmcs);
+            return true;
+        }
+
+        return false;
+    };
+
+    log::trace!(
+        "Syscall {:#x} returned {:#x} to {:#x}",
+        context.number,
+        guest_registers.rax,
+        context.return_address
+    );
+
+    if let Some(post) = syscall_hooks
+        .handler(context.number)
+        .and_then(|handler| handler.post)
+    {
+        post(guest_registers, &guest_memory, &context);
+    }
+
+    vmcs.set(guest::RIP, guest_registers.rip);
+    vmcs.set(guest::RSP, guest_registers.rsp);
+
+    if context.user_trap_flag() {
+        return false;
+    }
+
+    clear_trap_flag(guest_registers, vmcs);
+
+    true
+}
+
+/// Clears the trap flag in the guest RFLAGS.
+fn clear_trap_flag(guest_registers: &mut GuestRegisters, vmcs: &mut dyn VmcsAccess) {
+    guest_registers.rflags &= !RFLAGS_TRAP_FLAG;
+    vmcs.set(guest::RFLAGS, guest_registers.rflags);
+}
+
+/// Handles an invalid opcode exception (`#UD`) that might have been raised by `syscall` or `sysret`
+/// while the syscall tracer executes the guest with `EFER.SCE` cleared.
+///
+/// A `syscall` in 64-bit mode is recorded and emulated. A `sysret` is emulated, or raises `#GP(0)` if
+/// it's executed outside of kernel mode or returns to a non-canonical address in 64-bit mode.
+///
+/// # Arguments
+///
+/// * `guest_registers` - A mutable reference to the guest's register state.
+/// * `syscall_tracer` - The syscall tracer.
+/// * `vmcs` - The VMCS of the guest.
+/// * `cpu` - The processor whose syscall MSRs are used.
+///
+/// # Returns
+///
+/// * `bool` - `true` if the exception was raised by `syscall` or `sysret` and handled, `false` if the invalid opcode exception must be delivered to the guest.
+pub fn handle_syscall_instruction(
+    guest_registers: &mut GuestRegisters,
+    syscall_tracer: &SyscallTracer,
+    vmcs: &mut dyn VmcsAccess,
+    cpu: &dyn CpuAccess,
+) -> bool {
+    /// The L (64-bit mode) bit of the segment access rights.
+    const ACCESS_RIGHTS_LONG_MODE: u32 = 1 << 13;
+
+    /// The W bit of a REX prefix.
+    const REX_W: u8 = 0x48;
+
+    // The guest disabled syscall and sysret itself, so the exception is genuine.
+    if !syscall_tracer.is_syscall_enabled() {
+        return false;
+    }
+
+    let guest_memory = GuestMemory::current(vmcs);
+
+    // The instruction might end at the end of a page, so shorter reads are tried as well.
+    let mut bytes = [0u8; 3];
+    let Some(length) = (2..=bytes.len()).rev().find(|length| {
+        guest_memory
+            .read_bytes(guest_registers.rip, &mut bytes[..*length])
+            .is_ok()
+    }) else {
+        return false;
+    };
+
+    let cpl = vmcs.get(guest::CS_SELECTOR) & 3;
+    let long_mode = vmcs.get(guest::CS_ACCESS_RIGHTS) & ACCESS_RIGHTS_LONG_MODE != 0;
+
+    match &bytes[..length] {
+        // syscall: only valid in 64-bit mode on Intel processors.
+        [0x0F, 0x05, ..] if long_mode => {
+            syscall_tracer.record(guest_registers, &guest_memory, vmcs, cpu);
+            emulate_syscall(guest_registers, 2, vmcs, cpu);
+        }
+
+        // sysretq (REX.W) and sysret to compatibility mode.
+        [rex, 0x0F, 0x07] if *rex & 0xF8 == REX_W => {
+            if cpl != 0 || !is_canonical(guest_registers.rcx) {
+                EventInjection::vmentry_inject_gp(vmcs, 0);
+            } else {
+                emulate_sysret(guest_registers, true, vmcs, cpu);
+            }
+        }
+        [0x0F, 0x07, ..] => {
+            if cpl != 0 {
+                EventInjection::vmentry_inject_gp(vmcs, 0);
+            } else {
+                emulate_sysret(guest_registers, false, vmcs, cpu);
+            }
+        }
+
+        _ => return false,
+    }
+
+    true
+}
+
+/// Checks whether an address is canonical with 48-bit linear addresses.
+fn is_canonical(address: u64) -> bool {
+    let upper = (address as i64) >> 47;
+    upper == 0 || upper == -1
+}
@@ -3,11 +3,10 @@
 
 use {
     crate::{
-        intel::vmexit::ExitType,
+        intel::{cpu_access::CpuAccess, vmexit::ExitType},
         utils::capture::GuestRegisters,
-        utils::instructions::{cr4, cr4_write, xsetbv},
     },
-    x86::controlregs::{Cr4, Xcr0},
+    x86::controlregs::Xcr0,
 };
 
 /// Manages the XSETBV instruction during a VM exit. It logs the event, updates
@@ -17,11 +16,12 @@ use {
 /// # Arguments
 ///
 /// * `registers` - A mutable reference to the guest VM's general-purpose registers.
+/// * `cpu` - The processor executing `XSETBV` on the host.
 ///
 /// # Returns
 ///
 /// * `ExitType::IncrementRIP` - To move past the `XSETBV` instruction in the VM.
-pub fn handle_xsetbv(guest_registers: &mut GuestRegisters) -> ExitType {
+pub fn handle_xsetbv(guest_registers: &mut GuestRegisters, cpu: &mut dyn CpuAccess) -> ExitType {
     log::debug!("Handling XSETBV VM VM exit...");
 
     // Extract the XCR (extended control register) number from the guest's RCX register.
@@ -35,14 +35,11 @@ pub fn handle_xsetbv(guest_registers: &mut GuestRegisters) -> ExitType {
 
     log::trace!("XSETBV executed with xcr: {:#x}, value: {:#x}", xcr, value);
 
-    // Enable the OS XSAVE feature in CR4 before setting the extended control register value.
-    cr4_write(cr4() | Cr4::CR4_ENABLE_OS_XSAVE);
-
-    // Write the value to the specified XCR (extended control register).
-    xsetbv(value);
+    // Write the value to the specified XCR (extended control register). The OS XSAVE feature is enabled in CR4 first.
+    cpu.xsetbv(xcr, value.bits());
 
     log::debug!("XSETBV VM exit handled successfully!");
 
     // Advance the guest's instruction pointer to the next instruction to be executed.
-    return ExitType::IncrementRIP;
+    ExitType::IncrementRIP
 }
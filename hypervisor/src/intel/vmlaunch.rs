@@ -10,7 +10,15 @@
 //! Drew: https://github.com/drew-gpf
 
 use crate::{
-    intel::{support::vmread, vmerror::VmInstructionError, vmexit::VmExit, vmx::Vmx},
+    intel::{
+        cpu_access::HardwareCpu,
+        vmcs_access::{HardwareVmcs, VmcsFieldAccess},
+        vmcs_fields::ro,
+        vmcs_snapshot::VmcsSnapshot,
+        vmerror::VmInstructionError,
+        vmexit::VmExit,
+        vmx::Vmx,
+    },
     utils::capture::GuestRegisters,
 };
 
@@ -263,7 +271,8 @@ vmexit_stub:
 ///
 /// # Panics
 ///
-/// Panics if `registers` is a null pointer.
+/// Panics if `registers` is a null pointer, or if the VM exit can't be handled after logging a
+/// snapshot of the VMCS.
 #[no_mangle]
 pub unsafe extern "C" fn vmexit_handler(registers: *mut GuestRegisters, vmx: *mut u64) {
     if registers.is_null() {
@@ -277,7 +286,16 @@ pub unsafe extern "C" fn vmexit_handler(registers: *mut GuestRegisters, vmx: *mu
     let vmx = &mut *(vmx as *mut Vmx);
     let vmexit = VmExit::new();
 
-    if let Err(e) = vmexit.handle_vmexit(registers, vmx) {
+    let shared_data = vmx.shared_data.as_ref();
+
+    if let Err(e) = vmexit.handle_vmexit(
+        registers,
+        &shared_data.vmexit_handlers,
+        shared_data.vmexit_data(),
+        &mut HardwareVmcs,
+        &mut HardwareCpu,
+    ) {
+        VmcsSnapshot::capture().log(log::Level::Error);
         panic!("Failed to handle VMEXIT: {:?}", e);
     }
 }
@@ -285,7 +303,7 @@ pub unsafe extern "C" fn vmexit_handler(registers: *mut GuestRegisters, vmx: *mu
 /// Handles the failure of the `VMLAUNCH` instruction.
 ///
 /// This function is invoked when `VMLAUNCH` fails, and it retrieves and reports
-/// the specific VM instruction error.
+/// the specific VM instruction error, after logging a snapshot of the VMCS.
 ///
 /// # Panics
 ///
@@ -295,7 +313,9 @@ pub unsafe extern "C" fn vmexit_handler(registers: *mut GuestRegisters, vmx: *mu
 #[no_mangle]
 pub extern "C" fn vmlaunch_failed() {
     //unsafe { core::arch::asm!("int3") };
-    let instruction_error = vmread(x86::vmx::vmcs::ro::VM_INSTRUCTION_ERROR) as u32;
+    let instruction_error = HardwareVmcs.get(ro::VM_INSTRUCTION_ERROR);
+
+    VmcsSnapshot::capture().log(log::Level::Error);
 
     if let Some(error) = VmInstructionError::from_u32(instruction_error) {
         panic!("VMLAUNCH instruction error: {}", error);
@@ -307,7 +327,7 @@ pub extern "C" fn vmlaunch_failed() {
 /// Handles the failure of the `VMRESUME` instruction.
 ///
 /// This function is invoked when `VMRESUME` fails, retrieving and reporting
-/// the specific VM instruction error.
+/// the specific VM instruction error, after logging a snapshot of the VMCS.
 ///
 /// # Panics
 ///
@@ -317,7 +337,9 @@ pub extern "C" fn vmlaunch_failed() {
 #[no_mangle]
 pub extern "C" fn vmresume_failed() {
     //unsafe { core::arch::asm!("int3") };
-    let instruction_error = vmread(x86::vmx::vmcs::ro::VM_INSTRUCTION_ERROR) as u32;
+    let instruction_error = HardwareVmcs.get(ro::VM_INSTRUCTION_ERROR);
+
+    VmcsSnapshot::capture().log(log::Level::Error);
 
     if let Some(error) = VmInstructionError::from_u32(instruction_error) {
         panic!("VMRESUME instruction error: {}", error);
@@ -4,9 +4,17 @@ use {
     crate::{
         error::HypervisorError,
         intel::{
+            address_space::AddressSpaceMonitor,
             ept::{hooks::HookManager, paging::Ept},
             shared_data::SharedData,
+            syscall_hook::SyscallHooks,
+            syscall_tracer::SyscallTracer,
             vcpu::Vcpu,
+            vmerror::VmxBasicExitReason,
+            vmexit::{
+                io::IoPortHandler,
+                registry::{VmExitHandler, VmExitHandlers},
+            },
         },
         utils::{
             alloc::PhysicalAllocator,
@@ -27,6 +35,18 @@ pub struct HypervisorBuilder {
 
     /// The hook manager.
     hook_manager: Option<Box<HookManager>>,
+
+    /// The syscall hooks intercepting `IA32_LSTAR`, if enabled.
+    syscall_hooks: Option<Box<SyscallHooks>>,
+
+    /// The syscall tracer clearing `EFER.SCE`, if enabled.
+    syscall_tracer: Option<Box<SyscallTracer>>,
+
+    /// The address-space monitor enabling CR3-load exiting, if enabled.
+    address_space_monitor: Option<Box<AddressSpaceMonitor>>,
+
+    /// The VM-exit handlers, or the default handlers if not provided.
+    vmexit_handlers: Option<Box<VmExitHandlers>>,
 }
 
 impl HypervisorBuilder {
@@ -57,7 +77,15 @@ impl HypervisorBuilder {
             .ok_or(HypervisorError::PrimaryEPTNotProvided)?;
 
         #[cfg(not(feature = "secondary-ept"))]
-        let mut shared_data = SharedData::new(primary_ept, hook_manager)?;
+        let mut shared_data = SharedData::new(
+            primary_ept,
+            hook_manager,
+            self.syscall_hooks,
+            self.syscall_tracer,
+            self.address_space_monitor,
+            self.vmexit_handlers
+                .unwrap_or_else(|| Box::new(VmExitHandlers::with_defaults())),
+        )?;
 
         #[cfg(feature = "secondary-ept")]
         let shared_data = {
@@ -65,7 +93,16 @@ impl HypervisorBuilder {
                 .secondary_ept
                 .ok_or(HypervisorError::SecondaryEPTNotProvided)?;
 
-            SharedData::new(primary_ept, secondary_ept, hook_manager)?
+            SharedData::new(
+                primary_ept,
+                secondary_ept,
+                hook_manager,
+                self.syscall_hooks,
+                self.syscall_tracer,
+                self.address_space_monitor,
+                self.vmexit_handlers
+                    .unwrap_or_else(|| Box::new(VmExitHandlers::with_defaults())),
+            )?
         };
 
         Ok(Hypervisor {
@@ -89,6 +126,56 @@ impl HypervisorBuilder {
         self.hook_manager = Some(hook_manager);
         self
     }
+
+    /// Enables the interception of syscalls through `IA32_LSTAR` with the given syscall hooks.
+    pub fn syscall_hooks(mut self, syscall_hooks: Box<SyscallHooks>) -> Self {
+        self.syscall_hooks = Some(syscall_hooks);
+        self
+    }
+
+    /// Enables the tracing of syscalls through `EFER.SCE` with the given syscall tracer.
+    pub fn syscall_tracer(mut self, syscall_tracer: Box<SyscallTracer>) -> Self {
+        self.syscall_tracer = Some(syscall_tracer);
+        self
+    }
+
+    /// Enables the monitoring of the address-space switches through CR3-load exiting with the given
+    /// address-space monitor.
+    pub fn address_space_monitor(
+        mut self,
+        address_space_monitor: Box<AddressSpaceMonitor>,
+    ) -> Self {
+        self.address_space_monitor = Some(address_space_monitor);
+        self
+    }
+
+    /// Replaces the VM-exit handlers, including the default ones.
+    pub fn vmexit_handlers(mut self, vmexit_handlers: Box<VmExitHandlers>) -> Self {
+        self.vmexit_handlers = Some(vmexit_handlers);
+        self
+    }
+
+    /// Registers a VM-exit handler on top of the handlers of an exit reason, which are the default
+    /// ones unless replaced. The handler can delegate to the previous handlers.
+    pub fn vmexit_handler(
+        mut self,
+        reason: VmxBasicExitReason,
+        handler: impl VmExitHandler + 'static,
+    ) -> Self {
+        self.vmexit_handlers
+            .get_or_insert_with(|| Box::new(VmExitHandlers::with_defaults()))
+            .register(reason, handler);
+        self
+    }
+
+    /// Intercepts the accesses of the guest to an I/O port through the I/O bitmaps, on top of the
+    /// handlers of the VM-exits, which are the default ones unless replaced.
+    pub fn intercept_port(mut self, port: u16, handler: impl IoPortHandler + 'static) -> Self {
+        self.vmexit_handlers
+            .get_or_insert_with(|| Box::new(VmExitHandlers::with_defaults()))
+            .intercept_port(port, handler);
+        self
+    }
 }
 
 /// The main struct representing the hypervisor.
@@ -140,11 +227,21 @@ impl Hypervisor {
                 return Err(HypervisorError::ProcessorSwitchFailed);
             };
 
+            // Restore the original system call entry point while the write is still intercepted.
+            if let Some(syscall_hooks) = self.shared_data.syscall_hooks.as_ref() {
+                syscall_hooks.uninstall();
+            }
+
             processor.devirtualize_cpu()?;
 
             drop(executor);
         }
 
+        // Stop shadowing the system call entry point only after every processor has been restored.
+        if let Some(syscall_hooks) = self.shared_data.syscall_hooks.as_ref() {
+            syscall_hooks.disable();
+        }
+
         Ok(())
     }
 
@@ -11,6 +11,9 @@ use {
             shared_data::SharedData,
             vcpu::Vcpu,
             vmcs::Vmcs,
+            vmcs_access::HardwareVmcs,
+            vmcs_snapshot::VmcsSnapshot,
+            vmentry_checks::{check_vm_entry, VmxCapabilities},
             vmlaunch::launch_vm,
             vmstack::{VmStack, STACK_CONTENTS_SIZE},
             vmxon::Vmxon,
@@ -118,7 +121,11 @@ impl Vmx {
 
         instance.setup_virtualization(shared_data, context)?;
 
-        log::debug!("Dumping VMCS: {:#x?}", instance.vmcs_region);
+        // Capturing the snapshot reads every field of the catalog, so it's only done when it's logged.
+        if log::log_enabled!(log::Level::Trace) {
+            log::trace!("Dumping VMCS at {:p}", &*instance.vmcs_region);
+            VmcsSnapshot::capture().log(log::Level::Trace);
+        }
         log::debug!("Dumping CONTEXT: {:#x?}", &context);
 
         log::debug!("VMX setup successfully!");
@@ -149,15 +156,24 @@ impl Vmx {
         Vmcs::setup(&mut self.vmcs_region)?;
         VmStack::setup(&mut self.vmstack)?;
 
+        // The VMCS region was loaded as the current VMCS, so its fields are written with VMWRITE.
+        let mut vmcs = HardwareVmcs;
+
         /* Intel® 64 and IA-32 Architectures Software Developer's Manual: 25.4 GUEST-STATE AREA */
         Vmcs::setup_guest_registers_state(
+            &mut vmcs,
             &context,
             &self.guest_descriptor_table,
             &mut self.guest_registers,
-        );
+        )?;
 
         /* Intel® 64 and IA-32 Architectures Software Developer's Manual: 25.5 HOST-STATE AREA */
-        Vmcs::setup_host_registers_state(&context, &self.host_descriptor_table, &self.host_paging)?;
+        Vmcs::setup_host_registers_state(
+            &mut vmcs,
+            &context,
+            &self.host_descriptor_table,
+            &self.host_paging,
+        )?;
 
         /*
          * VMX controls:
@@ -166,7 +182,12 @@ impl Vmx {
          * - 25.7 VM-EXIT CONTROL FIELDS
          * - 25.8 VM-ENTRY CONTROL FIELDS
          */
-        Vmcs::setup_vmcs_control_fields(shared_data)?;
+        Vmcs::setup_vmcs_control_fields(&mut vmcs, shared_data)?;
+
+        // Redirect the system call entry point of this processor to the dispatcher of the syscall hooks.
+        if let Some(syscall_hooks) = shared_data.syscall_hooks.as_ref() {
+            syscall_hooks.install();
+        }
 
         log::debug!("Virtualization setup successfully!");
 
@@ -178,7 +199,12 @@ impl Vmx {
     /// This method will continuously execute the VM until a VM-exit event occurs. Upon VM-exit,
     /// it updates the VM state, interprets the VM-exit reason, and handles it appropriately.
     /// The loop continues until an unhandled or error-causing VM-exit is encountered.
-    pub fn run(&mut self, cpu_index: u32) {
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), HypervisorError>` - Only returns if the VMCS violates the VM-entry checks, in which
+    ///   case the VM isn't launched and the error holds the violated checks.
+    pub fn run(&mut self, cpu_index: u32) -> Result<(), HypervisorError> {
         log::trace!("Executing VMLAUNCH to run the guest until a VM-exit event occurs");
 
         let stack_contents_ptr = self.vmstack.stack_contents.as_mut_ptr();
@@ -186,8 +212,20 @@ impl Vmx {
 
         log::trace!("Vmx: {:#p}", self.vmstack.vmx);
 
+        // The processor only reports that the VMCS is invalid, so the checks are run in software to report the violated rules.
+        let violations = check_vm_entry(&VmcsSnapshot::capture(), &VmxCapabilities::read());
+        if !violations.is_empty() {
+            for violation in &violations {
+                log::error!("VM-entry check failed: {}", violation);
+            }
+
+            return Err(HypervisorError::VmEntryChecksFailed(violations));
+        }
+
         log::info!("Launching VM for processor {}", cpu_index);
         unsafe { launch_vm(&mut self.guest_registers, vmcs_host_rsp as *mut u64) };
+
+        Ok(())
     }
 
     /// Returns a mutable reference to the shared data.
@@ -1,14 +1,9 @@
 //! This crate provides an interface to a hypervisor.
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![feature(allocator_api)]
-#![feature(new_uninit)]
 #![feature(const_trait_impl)]
-#![feature(const_mut_refs)]
-#![feature(naked_functions)]
-#![feature(asm_const)]
-#![feature(once_cell_try)]
-#![feature(decl_macro)]
+#![cfg_attr(windows, feature(once_cell_try))]
 
 extern crate alloc;
 extern crate static_assertions;
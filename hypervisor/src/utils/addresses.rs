@@ -3,13 +3,17 @@
 //! This module introduces the `PhysicalAddress` structure that simplifies operations around
 //! physical addresses. It provides conversions between virtual addresses (VAs) and physical addresses (PAs),
 //! as well as methods for extracting page frame numbers (PFNs) and other address-related information.
+//!
+//! Outside of the kernel, where only the unit tests are built, virtual addresses are their own physical
+//! addresses, so structures built in host memory can be walked like physical memory.
 
+#[cfg(windows)]
+use wdk_sys::{
+    ntddk::{MmGetPhysicalAddress, MmGetVirtualForPhysical},
+    PHYSICAL_ADDRESS,
+};
 use {
     core::ops::{Deref, DerefMut},
-    wdk_sys::{
-        ntddk::{MmGetPhysicalAddress, MmGetVirtualForPhysical},
-        PHYSICAL_ADDRESS,
-    },
     x86::bits64::paging::{PAddr, BASE_PAGE_SHIFT},
 };
 
@@ -52,17 +56,31 @@ impl PhysicalAddress {
     }
 
     /// Converts a virtual address to its corresponding physical address.
+    #[cfg(windows)]
     pub fn pa_from_va(va: u64) -> u64 {
         unsafe { MmGetPhysicalAddress(va as _).QuadPart as u64 }
     }
 
     /// Converts a physical address to its corresponding virtual address.
+    #[cfg(windows)]
     pub fn va_from_pa(pa: u64) -> u64 {
         let mut physical_address: PHYSICAL_ADDRESS = unsafe { core::mem::zeroed() };
         (physical_address.QuadPart) = pa as i64;
 
         unsafe { MmGetVirtualForPhysical(physical_address) as u64 }
     }
+
+    /// Converts a virtual address to its corresponding physical address, which is the same outside of the kernel.
+    #[cfg(not(windows))]
+    pub fn pa_from_va(va: u64) -> u64 {
+        va
+    }
+
+    /// Converts a physical address to its corresponding virtual address, which is the same outside of the kernel.
+    #[cfg(not(windows))]
+    pub fn va_from_pa(pa: u64) -> u64 {
+        pa
+    }
 }
 
 impl Deref for PhysicalAddress {
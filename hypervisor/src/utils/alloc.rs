@@ -6,15 +6,18 @@
 //! - `GlobalAlloc` for `KernelAlloc`: Global memory allocator using the standard kernel allocator.
 //!
 //! All allocators interface directly with the Windows Driver Kit (WDK) to ensure
-//! safe and efficient memory operations.
+//! safe and efficient memory operations. On other targets, where only the unit tests are
+//! built, `PhysicalAllocator` and `KernelAlloc` allocate from the global allocator instead.
 //!
 //! Credits to Matthias for their valuable assistance in the implementation using winapi, a foundation now adapted for wdk-sys:
 //! https://github.com/not-matthias/kernel-alloc-rs
 
+#[cfg(not(windows))]
+use alloc::alloc::Global;
+#[cfg(windows)]
 use {
     alloc::alloc::handle_alloc_error,
-    core::alloc::{AllocError, Allocator, GlobalAlloc, Layout},
-    core::ptr::NonNull,
+    core::alloc::GlobalAlloc,
     wdk_sys::{
         ntddk::{
             ExAllocatePool, ExFreePool, MmAllocateContiguousMemorySpecifyCacheNode,
@@ -25,6 +28,10 @@ use {
         _POOL_TYPE::NonPagedPool,
     },
 };
+use {
+    core::alloc::{AllocError, Allocator, Layout},
+    core::ptr::NonNull,
+};
 
 /// Physical memory allocator for kernel space.
 ///
@@ -32,6 +39,7 @@ use {
 /// allocate memory that is physically contiguous.
 pub struct PhysicalAllocator;
 
+#[cfg(windows)]
 unsafe impl Allocator for PhysicalAllocator {
     /// Allocates a contiguous block of physical memory.
     ///
@@ -87,6 +95,7 @@ unsafe impl Allocator for PhysicalAllocator {
 /// Utilizes `ExAllocatePool` from the WDK for memory operations.
 pub struct KernelAlloc;
 
+#[cfg(windows)]
 unsafe impl Allocator for KernelAlloc {
     /// Allocates a block of kernel memory.
     ///
@@ -125,6 +134,7 @@ unsafe impl Allocator for KernelAlloc {
 /// This implementation allows `KernelAlloc` to be used as the global allocator,
 /// thereby providing memory allocation capabilities for the entire kernel space.
 /// It interfaces directly with the WDK's `ExAllocatePool` and `ExFreePool` functions.
+#[cfg(windows)]
 unsafe impl GlobalAlloc for KernelAlloc {
     /// Allocates a block of memory in the kernel space.
     ///
@@ -161,3 +171,27 @@ unsafe impl GlobalAlloc for KernelAlloc {
         ExFreePool(ptr as _);
     }
 }
+
+/// Allocates the memory of `PhysicalAllocator` from the global allocator, outside of the kernel.
+#[cfg(not(windows))]
+unsafe impl Allocator for PhysicalAllocator {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        Global.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        Global.deallocate(ptr, layout)
+    }
+}
+
+/// Allocates the memory of `KernelAlloc` from the global allocator, outside of the kernel.
+#[cfg(not(windows))]
+unsafe impl Allocator for KernelAlloc {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        Global.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        Global.deallocate(ptr, layout)
+    }
+}
@@ -0,0 +1,133 @@
+//! Provides a cursor over little-endian data, shared by the parsers of the PDB streams, the symbols
+//! blob and the VMCS snapshot blob.
+
+use {crate::error::HypervisorError, core::str};
+
+/// A cursor over little-endian data.
+///
+/// All reads fail with the error of the parser that created the reader if they run past the end of
+/// the data.
+#[derive(Debug, Clone)]
+pub struct ByteReader<'a> {
+    /// The data.
+    data: &'a [u8],
+
+    /// The position of the cursor.
+    position: usize,
+
+    /// Creates the error reported when the data is malformed.
+    error: fn() -> HypervisorError,
+}
+
+impl<'a> ByteReader<'a> {
+    /// Creates a new `ByteReader` at the start of the data.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The data to read.
+    /// * `error` - Creates the error reported when the data is malformed, e.g.
+    ///   `|| HypervisorError::InvalidPdb`.
+    pub fn new(data: &'a [u8], error: fn() -> HypervisorError) -> Self {
+        Self {
+            data,
+            position: 0,
+            error,
+        }
+    }
+
+    /// Returns the position of the cursor.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Returns the number of bytes after the cursor.
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    /// Checks whether the cursor is at the end of the data.
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// Moves the cursor to a position.
+    pub fn seek(&mut self, position: usize) -> Result<(), HypervisorError> {
+        if position > self.data.len() {
+            return Err((self.error)());
+        }
+
+        self.position = position;
+        Ok(())
+    }
+
+    /// Moves the cursor forward.
+    pub fn skip(&mut self, count: usize) -> Result<(), HypervisorError> {
+        self.bytes(count).map(|_| ())
+    }
+
+    /// Moves the cursor forward to the next multiple of `alignment`.
+    pub fn align(&mut self, alignment: usize) -> Result<(), HypervisorError> {
+        self.seek(
+            self.position
+                .next_multiple_of(alignment)
+                .min(self.data.len()),
+        )
+    }
+
+    /// Reads a number of bytes.
+    pub fn bytes(&mut self, count: usize) -> Result<&'a [u8], HypervisorError> {
+        let bytes = self
+            .position
+            .checked_add(count)
+            .and_then(|end| self.data.get(self.position..end))
+            .ok_or_else(self.error)?;
+
+        self.position += count;
+        Ok(bytes)
+    }
+
+    /// Reads an array of bytes.
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], HypervisorError> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    /// Reads a `u8`.
+    pub fn u8(&mut self) -> Result<u8, HypervisorError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    /// Reads a `u16`.
+    pub fn u16(&mut self) -> Result<u16, HypervisorError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    /// Reads a `u32`.
+    pub fn u32(&mut self) -> Result<u32, HypervisorError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    /// Reads a `u64`.
+    pub fn u64(&mut self) -> Result<u64, HypervisorError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    /// Reads a null-terminated UTF-8 string.
+    pub fn c_str(&mut self) -> Result<&'a str, HypervisorError> {
+        let rest = &self.data[self.position..];
+        let length = rest
+            .iter()
+            .position(|byte| *byte == 0)
+            .ok_or_else(self.error)?;
+
+        self.position += length + 1;
+        str::from_utf8(&rest[..length]).map_err(|_| (self.error)())
+    }
+
+    /// Reads a string prefixed with its `u16` length.
+    pub fn prefixed_str(&mut self) -> Result<&'a str, HypervisorError> {
+        let length = self.u16()? as usize;
+        str::from_utf8(self.bytes(length)?).map_err(|_| (self.error)())
+    }
+}
@@ -117,60 +117,60 @@ impl fmt::Debug for CONTEXT {
         f.write_str("CONTEXT {\n")?;
 
         // General-purpose registers and other fields in 4 columns
-        write!(
+        writeln!(
             f,
-            "  P1Home: {:#018x}, P2Home: {:#018x}, P3Home: {:#018x}, P4Home: {:#018x}\n",
+            "  P1Home: {:#018x}, P2Home: {:#018x}, P3Home: {:#018x}, P4Home: {:#018x}",
             self.P1Home, self.P2Home, self.P3Home, self.P4Home
         )?;
-        write!(
+        writeln!(
             f,
-            "  P5Home: {:#018x}, P6Home: {:#018x}, ContextFlags: {:?}, MxCsr: {:#010x}\n",
+            "  P5Home: {:#018x}, P6Home: {:#018x}, ContextFlags: {:?}, MxCsr: {:#010x}",
             self.P5Home, self.P6Home, self.ContextFlags, self.MxCsr
         )?;
-        write!(
+        writeln!(
             f,
-            "  SegCs: {:#06x}, SegDs: {:#06x}, SegEs: {:#06x}, SegFs: {:#06x}\n",
+            "  SegCs: {:#06x}, SegDs: {:#06x}, SegEs: {:#06x}, SegFs: {:#06x}",
             self.SegCs, self.SegDs, self.SegEs, self.SegFs
         )?;
-        write!(
+        writeln!(
             f,
-            "  SegGs: {:#06x}, SegSs: {:#06x}, EFlags: {:#010x}, Dr0: {:#018x}\n",
+            "  SegGs: {:#06x}, SegSs: {:#06x}, EFlags: {:#010x}, Dr0: {:#018x}",
             self.SegGs, self.SegSs, self.EFlags, self.Dr0
         )?;
-        write!(
+        writeln!(
             f,
-            "  Dr1: {:#018x}, Dr2: {:#018x}, Dr3: {:#018x}, Dr6: {:#018x}\n",
+            "  Dr1: {:#018x}, Dr2: {:#018x}, Dr3: {:#018x}, Dr6: {:#018x}",
             self.Dr1, self.Dr2, self.Dr3, self.Dr6
         )?;
-        write!(
+        writeln!(
             f,
-            "  Dr7: {:#018x}, Rax: {:#018x}, Rcx: {:#018x}, Rdx: {:#018x}\n",
+            "  Dr7: {:#018x}, Rax: {:#018x}, Rcx: {:#018x}, Rdx: {:#018x}",
             self.Dr7, self.Rax, self.Rcx, self.Rdx
         )?;
-        write!(
+        writeln!(
             f,
-            "  Rbx: {:#018x}, Rsp: {:#018x}, Rbp: {:#018x}, Rsi: {:#018x}\n",
+            "  Rbx: {:#018x}, Rsp: {:#018x}, Rbp: {:#018x}, Rsi: {:#018x}",
             self.Rbx, self.Rsp, self.Rbp, self.Rsi
         )?;
-        write!(
+        writeln!(
             f,
-            "  Rdi: {:#018x}, R8: {:#018x}, R9: {:#018x}, R10: {:#018x}\n",
+            "  Rdi: {:#018x}, R8: {:#018x}, R9: {:#018x}, R10: {:#018x}",
             self.Rdi, self.R8, self.R9, self.R10
         )?;
-        write!(
+        writeln!(
             f,
-            "  R11: {:#018x}, R12: {:#018x}, R13: {:#018x}, R14: {:#018x}\n",
+            "  R11: {:#018x}, R12: {:#018x}, R13: {:#018x}, R14: {:#018x}",
             self.R11, self.R12, self.R13, self.R14
         )?;
-        write!(
+        writeln!(
             f,
-            "  R15: {:#018x}, Rip: {:#018x}, VectorControl: {:#018x}, DebugControl: {:#018x}\n",
+            "  R15: {:#018x}, Rip: {:#018x}, VectorControl: {:#018x}, DebugControl: {:#018x}",
             self.R15, self.Rip, self.VectorControl, self.DebugControl
         )?;
-        write!(f, "  LastBranchToRip: {:#018x}, LastBranchFromRip: {:#018x}, LastExceptionToRip: {:#018x}, LastExceptionFromRip: {:#018x}\n", self.LastBranchToRip, self.LastBranchFromRip, self.LastExceptionToRip, self.LastExceptionFromRip)?;
+        writeln!(f, "  LastBranchToRip: {:#018x}, LastBranchFromRip: {:#018x}, LastExceptionToRip: {:#018x}, LastExceptionFromRip: {:#018x}", self.LastBranchToRip, self.LastBranchFromRip, self.LastExceptionToRip, self.LastExceptionFromRip)?;
 
         // Vector registers in 4 columns
-        write!(f, "  Vector Registers:\n")?;
+        writeln!(f, "  Vector Registers:")?;
         for i in 0..26 {
             write!(
                 f,
@@ -178,11 +178,11 @@ impl fmt::Debug for CONTEXT {
                 i, self.VectorRegister[i]
             )?;
             if i % 4 == 3 {
-                write!(f, "\n")?;
+                writeln!(f)?;
             }
         }
         if 26 % 4 != 0 {
-            write!(f, "\n")?;
+            writeln!(f)?;
         }
 
         f.write_str("}")
@@ -254,51 +254,51 @@ impl fmt::Debug for GuestRegisters {
         f.write_str("GuestRegisters {\n")?;
 
         // General-purpose registers in 4 columns
-        write!(
+        writeln!(
             f,
-            "  rax: {:#018x}, rbx: {:#018x}, rcx: {:#018x}, rdx: {:#018x}\n",
+            "  rax: {:#018x}, rbx: {:#018x}, rcx: {:#018x}, rdx: {:#018x}",
             self.rax, self.rbx, self.rcx, self.rdx
         )?;
-        write!(
+        writeln!(
             f,
-            "  rsi: {:#018x}, rdi: {:#018x}, rbp: {:#018x}, r8: {:#018x}\n",
+            "  rsi: {:#018x}, rdi: {:#018x}, rbp: {:#018x}, r8: {:#018x}",
             self.rsi, self.rdi, self.rbp, self.r8
         )?;
-        write!(
+        writeln!(
             f,
-            "  r9: {:#018x}, r10: {:#018x}, r11: {:#018x}, r12: {:#018x}\n",
+            "  r9: {:#018x}, r10: {:#018x}, r11: {:#018x}, r12: {:#018x}",
             self.r9, self.r10, self.r11, self.r12
         )?;
-        write!(
+        writeln!(
             f,
-            "  r13: {:#018x}, r14: {:#018x}, r15: {:#018x}, rip: {:#018x}\n",
+            "  r13: {:#018x}, r14: {:#018x}, r15: {:#018x}, rip: {:#018x}",
             self.r13, self.r14, self.r15, self.rip
         )?;
-        write!(
+        writeln!(
             f,
-            "  rsp: {:#018x}, rflags: {:#018x}\n",
+            "  rsp: {:#018x}, rflags: {:#018x}",
             self.rsp, self.rflags
         )?;
 
         // XMM registers in 4 columns
-        write!(
+        writeln!(
             f,
-            "  xmm0: {:?}, xmm1: {:?}, xmm2: {:?}, xmm3: {:?}\n",
+            "  xmm0: {:?}, xmm1: {:?}, xmm2: {:?}, xmm3: {:?}",
             self.xmm0, self.xmm1, self.xmm2, self.xmm3
         )?;
-        write!(
+        writeln!(
             f,
-            "  xmm4: {:?}, xmm5: {:?}, xmm6: {:?}, xmm7: {:?}\n",
+            "  xmm4: {:?}, xmm5: {:?}, xmm6: {:?}, xmm7: {:?}",
             self.xmm4, self.xmm5, self.xmm6, self.xmm7
         )?;
-        write!(
+        writeln!(
             f,
-            "  xmm8: {:?}, xmm9: {:?}, xmm10: {:?}, xmm11: {:?}\n",
+            "  xmm8: {:?}, xmm9: {:?}, xmm10: {:?}, xmm11: {:?}",
             self.xmm8, self.xmm9, self.xmm10, self.xmm11
         )?;
-        write!(
+        writeln!(
             f,
-            "  xmm12: {:?}, xmm13: {:?}, xmm14: {:?}, xmm15: {:?}\n",
+            "  xmm12: {:?}, xmm13: {:?}, xmm14: {:?}, xmm15: {:?}",
             self.xmm12, self.xmm13, self.xmm14, self.xmm15
         )?;
 
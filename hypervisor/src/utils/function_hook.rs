@@ -2,12 +2,18 @@
 //! It includes creating and managing hooks with support for different types, enabling and disabling hooks,
 //! and managing the necessary memory and page table entries.
 //! Credits to Matthias: https://github.com/not-matthias/amd_hypervisor/blob/main/hypervisor/src/utils/function_hook.rs
+//!
+//! Hooks are only created and enabled in the kernel, where the pages of the hooked functions are locked.
 
+#[cfg(windows)]
 use {
-    crate::{error::HypervisorError, utils::nt::RtlCopyMemory},
-    alloc::{boxed::Box, vec, vec::Vec},
-    iced_x86::{
-        BlockEncoder, BlockEncoderOptions, Decoder, DecoderOptions, FlowControl, InstructionBlock,
+    alloc::vec,
+    crate::{
+        error::HypervisorError,
+        utils::{
+            nt::RtlCopyMemory,
+            trampoline::{jmp_shellcode, TrampolineBuilder, MAX_INSTRUCTION_LEN},
+        },
     },
     wdk_sys::{
         ntddk::{IoAllocateMdl, IoFreeMdl, MmProbeAndLockPages, MmUnlockPages},
@@ -17,13 +23,23 @@ use {
     },
     x86::bits64::paging::BASE_PAGE_SIZE,
 };
+use {
+    crate::{
+        intel::guest_memory::GuestMemory,
+        utils::{capture::GuestRegisters, return_hook::ReturnCallback},
+    },
+    alloc::{boxed::Box, vec::Vec},
+};
 
-/// Length of JMP shellcode.
-pub const JMP_SHELLCODE_LEN: usize = 14;
+pub use crate::utils::trampoline::JMP_SHELLCODE_LEN;
 
 /// Length of Breakpoint shellcode.
 pub const BP_SHELLCODE_LEN: usize = 1;
 
+/// Capacity of the memory reserved for a trampoline.
+#[cfg(windows)]
+const TRAMPOLINE_CAPACITY: usize = 128;
+
 /// Define the types of hooks available: JMP for jump-based hooks, Breakpoint for hooks that use breakpoints.
 pub enum HookType {
     /// Jump-based hook.
@@ -33,21 +49,76 @@ pub enum HookType {
     Breakpoint,
 }
 
+/// The action taken after a root-mode hook callback returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookAction {
+    /// Continue into the original function by executing the trampoline.
+    Continue,
+
+    /// Skip the original function and return the value in RAX to the caller.
+    Return(u64),
+}
+
+/// A callback executed in VMX root operation when the guest hits a hook.
+///
+/// The callback receives the guest registers at function entry and an accessor for the guest memory,
+/// so it can inspect or modify the arguments (RCX, RDX, R8, R9 and the stack) before deciding how to continue.
+pub type HookCallback = fn(&mut GuestRegisters, &GuestMemory) -> HookAction;
+
+/// Defines what happens when the guest hits a hook.
+#[derive(Clone, Copy)]
+pub enum HookHandler {
+    /// Transfers guest execution to a guest-side handler function, which calls the trampoline itself.
+    Redirect(u64),
+
+    /// Executes a callback in VMX root operation on the breakpoint VM exit.
+    Callback(HookCallback),
+
+    /// Executes a callback in VMX root operation on function entry and another one when the function returns.
+    ///
+    /// The return callback is only executed if the entry callback continues into the original function.
+    CallbackWithReturn {
+        entry: HookCallback,
+        exit: ReturnCallback,
+    },
+}
+
+/// The priority of handlers that are registered without an explicit priority.
+pub const DEFAULT_PRIORITY: i32 = 0;
+
+/// A handler in the handler chain of a function hook.
+#[derive(Clone, Copy)]
+pub struct ChainedHandler {
+    /// The priority of the handler. Handlers with a higher priority run first.
+    pub priority: i32,
+
+    /// The handler.
+    pub handler: HookHandler,
+}
+
 /// Represents a function hook with the capability to enable inline hooking.
+///
+/// A function hook holds a chain of handlers ordered by priority. Root-mode callbacks pass through to the next
+/// handler by returning `HookAction::Continue` and short-circuit the chain (including the original function)
+/// by returning `HookAction::Return`. A `HookHandler::Redirect` handler ends the chain, because the guest-side
+/// handler calls the original function itself.
 pub struct FunctionHook {
     /// The trampoline code to execute the original function.
     trampoline: Box<[u8]>,
 
     /// The address where the hook is installed.
+    #[cfg(windows)]
     hook_address: u64,
 
-    /// The address of the handler function.
-    handler: u64,
+    /// The handler chain of the hook, ordered by descending priority.
+    handlers: Vec<ChainedHandler>,
 
     /// Memory descriptor list for the hook address.
+    #[cfg(windows)]
     mdl: PMDL,
 
     /// Type of the hook (Jmp or Breakpoint).
+    #[cfg(windows)]
     hook_type: HookType,
 }
 
@@ -57,24 +128,37 @@ impl FunctionHook {
     /// ## Parameters
     /// - `original_address`: The original address of the function to be hooked.
     /// - `hook_address`: The address where the hook will be placed.
-    /// - `handler`: Pointer to the handler function that will be called instead of the original.
+    /// - `priority`: The priority of the first handler of the chain.
+    /// - `handler`: The first handler of the chain.
+    /// - `relocation_limit`: The number of bytes at the original address that may be relocated into the trampoline.
     ///
     /// ## Returns
     /// Returns an Option containing the new FunctionHook if successful, or None if failed.
     ///
     /// ## Safety
     /// This function allocates memory and manipulates page table entries. Incorrect use may lead to system instability.
-    pub fn new(original_address: u64, hook_address: u64, handler: *const ()) -> Option<Self> {
+    #[cfg(windows)]
+    pub fn new(
+        original_address: u64,
+        hook_address: u64,
+        priority: i32,
+        handler: HookHandler,
+        relocation_limit: usize,
+    ) -> Option<Self> {
         log::debug!("Setting up hooks");
 
         let (hook_type, trampoline) = {
-            let trampoline =
-                Self::trampoline_shellcode(original_address, hook_address, BP_SHELLCODE_LEN)
-                    .map_err(|e| {
-                        log::warn!("Failed to create bp trampoline: {:?}", e);
-                        e
-                    })
-                    .ok()?;
+            let trampoline = Self::trampoline_shellcode(
+                original_address,
+                hook_address,
+                BP_SHELLCODE_LEN,
+                relocation_limit,
+            )
+            .map_err(|e| {
+                log::warn!("Failed to create bp trampoline: {:?}", e);
+                e
+            })
+            .ok()?;
 
             (HookType::Breakpoint, trampoline)
         };
@@ -101,7 +185,7 @@ impl FunctionHook {
             hook_type,
             hook_address,
             mdl,
-            handler: handler as u64,
+            handlers: vec![ChainedHandler { priority, handler }],
         })
     }
 
@@ -110,13 +194,22 @@ impl FunctionHook {
     /// ## Details
     /// Depending on the hook type, it writes the appropriate shellcode to jump to the handler or to trigger a breakpoint.
     ///
+    /// ## Returns
+    /// Returns `UnsupportedHookHandler` for a jmp hook whose first handler is a root-mode callback, because the jmp
+    /// transfers control to the guest-side handler without a VM exit.
+    ///
     /// ## Safety
     /// This function modifies the instruction at the hook address. Ensure that this doesn't corrupt the program flow or overlap with critical instructions.
-    pub fn enable(&self) {
+    #[cfg(windows)]
+    pub fn enable(&self) -> Result<(), HypervisorError> {
         log::debug!("Enabling hook");
-        let jmp_to_handler = match self.hook_type {
-            HookType::Jmp => Self::jmp_shellcode(self.handler).to_vec(),
-            HookType::Breakpoint => vec![0xCC_u8], // 0xCC is the opcode for INT3, a common breakpoint instruction.
+        let jmp_to_handler = match (&self.hook_type, self.handler_address()) {
+            (HookType::Jmp, Some(handler)) => jmp_shellcode(handler).to_vec(),
+            (HookType::Jmp, None) => {
+                log::warn!("Jmp hooks require a guest-side handler");
+                return Err(HypervisorError::UnsupportedHookHandler);
+            }
+            (HookType::Breakpoint, _) => vec![0xCC_u8], // 0xCC is the opcode for INT3, a common breakpoint instruction.
         };
 
         log::trace!(
@@ -139,166 +232,77 @@ impl FunctionHook {
 
         // Invalidate all processor caches to ensure the new instructions are used. (Will use invept instead of this later)
         //unsafe { KeInvalidateAllCaches() };
-    }
-
-    /// Creates the jmp shellcode.
-    ///
-    /// ## How it works.
-    ///
-    /// We are using the following assembly shellcode:
-    /// ```asm
-    /// jmp [rip+00h]
-    /// 0xDEADBEEF
-    /// ```
-    ///
-    /// Or in a different format:
-    ///
-    /// ```asm
-    /// jmp qword ptr cs:jmp_add
-    /// jmp_addr: dq 0xDEADBEEF
-    /// ```
-    ///
-    /// The core premise behind it is, that we jump to the address that is right
-    /// after the current instruction.
-    ///
-    /// ## Why use this instead of `mov rax, jmp rax`?
-    ///
-    /// This shellcode has one very important feature: **It doesn't require any
-    /// registers to store the jmp address**. And because of that, we don't
-    /// have to fear overwriting some register values.
-    fn jmp_shellcode(target_address: u64) -> [u8; 14] {
-        log::debug!(
-            "Creating the jmp shellcode for address: {:#x}",
-            target_address
-        );
 
-        // Create the shellcode. See function documentation for more information.
-        //
-        let mut shellcode = [
-            0xff, 0x25, 0x00, 0x00, 0x00, 0x00, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC,
-        ];
-
-        unsafe { (shellcode.as_mut_ptr().add(6) as *mut u64).write_volatile(target_address) };
-
-        log::trace!("Jmp shellcode: {:x?}", shellcode);
-
-        shellcode
+        Ok(())
     }
 
     /// Creates a trampoline shellcode that jumps to the original function.
     ///
-    /// NOTE: The trampoline doesn't support RIP-relative instructions. If any
-    /// of these relative instructions are found,
-    /// `InlineHookError::RelativeInstruction` will be returned.
+    /// The prologue is relocated by the `TrampolineBuilder`, so RIP-relative operands and relative
+    /// branches are re-encoded for the trampoline address.
     ///
     /// ## Parameters
     ///
     /// - `original_function_address`: The address of the original function (on the real page).
     /// - `copied_function_address`: The address of the copied function (on the fake page)
     /// - `required_size`: The minimum size of the trampoline.
+    /// - `max_size`: The maximum number of bytes that may be relocated.
     ///
     /// ## Returns
     ///
     /// The trampoline shellcode.
+    #[cfg(windows)]
     fn trampoline_shellcode(
         original_address: u64,
         address: u64,
         required_size: usize,
+        max_size: usize,
     ) -> Result<Box<[u8]>, HypervisorError> {
         log::debug!("Creating a trampoline");
 
-        // Read bytes from function and decode them. Read 2 times the amount needed, in
-        // case there are bigger instructions that take more space. If there's
-        // only 1 byte needed, we read 15 bytes instead so that we can find the
-        // first few valid instructions.
+        // Read enough bytes from the copied function, so that the last instruction covering
+        // `required_size` can be decoded entirely, but never more than may be relocated.
         //
         let bytes = unsafe {
-            core::slice::from_raw_parts(address as *mut u8, usize::max(required_size * 2, 15))
+            core::slice::from_raw_parts(
+                address as *const u8,
+                (required_size + MAX_INSTRUCTION_LEN - 1).min(max_size),
+            )
         };
 
-        let mut decoder = Decoder::with_ip(64, bytes, address, DecoderOptions::NONE);
-
-        let mut total_bytes = 0;
-        let mut trampoline = Vec::new();
-
-        for instr in &mut decoder {
-            if instr.is_invalid() {
-                return Err(HypervisorError::InvalidBytes);
-            }
-
-            if total_bytes >= required_size {
-                break;
-            }
-
-            if instr.is_ip_rel_memory_operand() {
-                return Err(HypervisorError::RelativeInstruction);
-            }
-
-            // Create the new trampoline instruction
-            //
-            match instr.flow_control() {
-                FlowControl::Next | FlowControl::Return => {
-                    total_bytes += instr.len();
-                    trampoline.push(instr);
-                }
-                FlowControl::Call
-                | FlowControl::ConditionalBranch
-                | FlowControl::UnconditionalBranch
-                | FlowControl::IndirectCall => {
-                    return Err(HypervisorError::RelativeInstruction);
-                }
-                FlowControl::IndirectBranch
-                | FlowControl::Interrupt
-                | FlowControl::XbeginXabortXend
-                | FlowControl::Exception => {
-                    return Err(HypervisorError::UnsupportedInstruction);
-                }
-            };
-        }
-
-        if total_bytes < required_size {
-            return Err(HypervisorError::NotEnoughBytes);
-        }
-
-        if trampoline.is_empty() {
-            return Err(HypervisorError::NoInstructions);
-        }
-
-        // Allocate new memory for the trampoline and encode the instructions.
+        // Allocate new memory for the trampoline. The size of the relocated instructions is only known
+        // after encoding them for the final address, so we reserve a fixed capacity upfront.
         //
-        let mut memory = Box::new_uninit_slice(total_bytes + JMP_SHELLCODE_LEN);
+        let mut memory = vec![0xCC_u8; TRAMPOLINE_CAPACITY].into_boxed_slice();
         log::debug!("Allocated trampoline memory at {:p}", memory.as_ptr());
 
-        let block = InstructionBlock::new(&trampoline, memory.as_mut_ptr() as _);
-
-        let mut encoded = BlockEncoder::encode(decoder.bitness(), block, BlockEncoderOptions::NONE)
-            .map(|b| b.code_buffer)
-            .map_err(|_| HypervisorError::EncodingFailed)?;
+        // Decode the instructions at the original address and encode them at the trampoline address.
+        // The trampoline ends with a jmp back to the original function. We can't use `address` for
+        // this, because the page will probably contain rip-relative instructions. And we already switch
+        // the page So the shadow page will be at the address of the original page.
+        //
+        let trampoline = TrampolineBuilder::new(bytes, original_address, memory.as_ptr() as u64)
+            .required_size(required_size)
+            .max_size(max_size)
+            .build()?;
 
-        log::trace!("Encoded trampoline: {:x?}", encoded);
+        log::trace!(
+            "Encoded trampoline: {:x?} (prologue length: {})",
+            trampoline.bytes,
+            trampoline.prologue_length
+        );
 
-        // Add jmp to the original function at the end. We can't use `address` for this,
-        // because the page will probably contain rip-relative instructions. And
-        // we already switch the page So the shadow page will be at the address
-        // of the original page.
-        //
-        let jmp_back_address = original_address + encoded.len() as u64;
-        let jmp_shellcode = Self::jmp_shellcode(jmp_back_address);
-        encoded.extend_from_slice(jmp_shellcode.as_slice());
+        if trampoline.bytes.len() > memory.len() {
+            return Err(HypervisorError::EncodingFailed);
+        }
 
         // Copy the encoded bytes and return the allocated memory.
         //
-        unsafe {
-            core::ptr::copy_nonoverlapping(
-                encoded.as_ptr(),
-                memory.as_mut_ptr() as _,
-                encoded.len(),
-            )
-        };
+        memory[..trampoline.bytes.len()].copy_from_slice(&trampoline.bytes);
 
         log::debug!("Trampoline setup successfully!");
 
-        Ok(unsafe { memory.assume_init() })
+        Ok(memory)
     }
 
     /// Provides a constant function to retrieve the address of the trampoline.
@@ -309,17 +313,77 @@ impl FunctionHook {
         self.trampoline.as_ptr() as _
     }
 
-    /// Provides a constant function to retrieve the address of the handler.
+    /// Provides a function to retrieve the address of the handler.
+    ///
+    /// ## Returns
+    /// Returns the address of the guest-side handler function if it's the first handler of the chain,
+    /// or `None` if the chain starts with a root-mode callback.
+    pub fn handler_address(&self) -> Option<u64> {
+        match self.handlers.first()?.handler {
+            HookHandler::Redirect(handler) => Some(handler),
+            HookHandler::Callback(_) | HookHandler::CallbackWithReturn { .. } => None,
+        }
+    }
+
+    /// Provides a function to retrieve the handler chain of the hook.
     ///
     /// ## Returns
-    /// Returns the address of the handler function as a 64-bit unsigned integer.
-    pub const fn handler_address(&self) -> u64 {
-        self.handler
+    /// Returns the handlers ordered by descending priority.
+    pub fn handlers(&self) -> &[ChainedHandler] {
+        &self.handlers
+    }
+
+    /// Adds a handler to the handler chain.
+    ///
+    /// Handlers with the same priority run in the order they were added.
+    ///
+    /// ## Parameters
+    /// - `priority`: The priority of the handler. Handlers with a higher priority run first.
+    /// - `handler`: The handler to add.
+    pub fn add_handler(&mut self, priority: i32, handler: HookHandler) {
+        let index = self
+            .handlers
+            .iter()
+            .position(|chained| chained.priority < priority)
+            .unwrap_or(self.handlers.len());
+
+        self.handlers
+            .insert(index, ChainedHandler { priority, handler });
+    }
+
+    /// Moves the handler chain of another function hook into this one.
+    ///
+    /// ## Parameters
+    /// - `other`: The function hook whose handlers are merged into this chain.
+    pub fn merge(&mut self, mut other: FunctionHook) {
+        for chained in other.handlers.drain(..) {
+            self.add_handler(chained.priority, chained.handler);
+        }
+    }
+
+    /// Returns the handlers whose return callbacks run when the hooked function returns.
+    ///
+    /// These are the handlers preceding the first `HookHandler::Redirect` in the chain, which are the handlers
+    /// executed on function entry if none of them short-circuited the chain.
+    pub fn return_callbacks(&self) -> impl DoubleEndedIterator<Item = ReturnCallback> + '_ {
+        let end = self
+            .handlers
+            .iter()
+            .position(|chained| matches!(chained.handler, HookHandler::Redirect(_)))
+            .unwrap_or(self.handlers.len());
+
+        self.handlers[..end]
+            .iter()
+            .filter_map(|chained| match chained.handler {
+                HookHandler::CallbackWithReturn { exit, .. } => Some(exit),
+                HookHandler::Redirect(_) | HookHandler::Callback(_) => None,
+            })
     }
 }
 
 /// Implementation of the Drop trait for FunctionHook.
 /// Ensures that when a FunctionHook is dropped, it unlocks and frees the pages associated with the hook.
+#[cfg(windows)]
 impl Drop for FunctionHook {
     fn drop(&mut self) {
         if !self.mdl.is_null() {
@@ -331,3 +395,76 @@ impl Drop for FunctionHook {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use {super::*, alloc::vec};
+
+    /// Appends 1 to the digits in RAX.
+    fn first(guest_registers: &mut GuestRegisters, _: &GuestMemory) -> HookAction {
+        guest_registers.rax = guest_registers.rax * 10 + 1;
+        HookAction::Continue
+    }
+
+    /// Appends 2 to the digits in RAX.
+    fn second(guest_registers: &mut GuestRegisters, _: &GuestMemory) -> HookAction {
+        guest_registers.rax = guest_registers.rax * 10 + 2;
+        HookAction::Continue
+    }
+
+    /// Appends 3 to the digits in RAX.
+    fn third(guest_registers: &mut GuestRegisters, _: &GuestMemory) -> HookAction {
+        guest_registers.rax = guest_registers.rax * 10 + 3;
+        HookAction::Continue
+    }
+
+    /// A function hook whose chain holds a single callback, like `FunctionHook::new` creates it.
+    fn function_hook(priority: i32, callback: HookCallback) -> FunctionHook {
+        FunctionHook {
+            trampoline: Box::new([]),
+            handlers: vec![ChainedHandler {
+                priority,
+                handler: HookHandler::Callback(callback),
+            }],
+        }
+    }
+
+    /// Runs the callbacks of the chain in order, like the breakpoint handler, and returns the digits they
+    /// appended to RAX.
+    fn dispatch(function_hook: &FunctionHook) -> u64 {
+        let mut guest_registers = GuestRegisters::default();
+        let guest_memory = GuestMemory::new(0);
+
+        for chained in function_hook.handlers() {
+            if let HookHandler::Callback(callback) = chained.handler {
+                callback(&mut guest_registers, &guest_memory);
+            }
+        }
+
+        guest_registers.rax
+    }
+
+    #[test]
+    fn handlers_keep_their_priorities_and_dispatch_in_priority_order() {
+        let mut function_hook = function_hook(-5, third);
+        function_hook.add_handler(10, HookHandler::Callback(first));
+        function_hook.merge(self::function_hook(DEFAULT_PRIORITY, second));
+
+        let priorities: Vec<i32> = function_hook
+            .handlers()
+            .iter()
+            .map(|chained| chained.priority)
+            .collect();
+
+        assert_eq!(priorities, [10, DEFAULT_PRIORITY, -5]);
+        assert_eq!(dispatch(&function_hook), 123);
+    }
+
+    #[test]
+    fn handlers_with_the_same_priority_dispatch_in_registration_order() {
+        let mut function_hook = function_hook(DEFAULT_PRIORITY, first);
+        function_hook.add_handler(DEFAULT_PRIORITY, HookHandler::Callback(second));
+
+        assert_eq!(dispatch(&function_hook), 12);
+    }
+}
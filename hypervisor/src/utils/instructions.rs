@@ -4,6 +4,7 @@ use {
     core::arch::asm,
     x86::{
         controlregs::{Cr0, Cr4, Xcr0},
+        debugregs::Dr6,
         dtables::DescriptorTablePointer,
     },
 };
@@ -46,6 +47,21 @@ pub fn cr0_write(val: Cr0) {
     unsafe { x86::controlregs::cr0_write(val) };
 }
 
+/// Writes a value to the CR2 register.
+pub fn cr2_write(val: u64) {
+    unsafe { x86::controlregs::cr2_write(val) };
+}
+
+/// Reads the DR6 register.
+pub fn dr6() -> u64 {
+    unsafe { x86::debugregs::dr6() }.bits() as u64
+}
+
+/// Writes a value to the DR6 register.
+pub fn dr6_write(val: u64) {
+    unsafe { x86::debugregs::dr6_write(Dr6::from_bits_truncate(val as usize)) };
+}
+
 /// Reads the CR3 register.
 pub fn cr3() -> u64 {
     unsafe { x86::controlregs::cr3() }
@@ -61,6 +77,27 @@ pub fn cr4_write(val: Cr4) {
     unsafe { x86::controlregs::cr4_write(val) };
 }
 
+/// Reads the CR8 register (task-priority register).
+pub fn cr8() -> u64 {
+    let value: u64;
+    unsafe { asm!("mov {}, cr8", out(reg) value, options(nomem, nostack, preserves_flags)) };
+    value
+}
+
+/// Writes a value to the CR8 register (task-priority register).
+pub fn cr8_write(val: u64) {
+    unsafe { asm!("mov cr8, {}", in(reg) val, options(nomem, nostack, preserves_flags)) };
+}
+
+/// Reads a performance-monitoring counter.
+pub fn rdpmc(counter: u32) -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        asm!("rdpmc", in("ecx") counter, out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags))
+    };
+    ((high as u64) << 32) | (low as u64)
+}
+
 /// Disables maskable interrupts.
 pub fn cli() {
     unsafe { x86::irq::disable() };
@@ -81,6 +118,26 @@ pub fn outb(port: u16, val: u8) {
     unsafe { x86::io::outb(port, val) };
 }
 
+/// Reads 16-bits from an IO port.
+pub fn inw(port: u16) -> u16 {
+    unsafe { x86::io::inw(port) }
+}
+
+/// Writes 16-bits to an IO port.
+pub fn outw(port: u16, val: u16) {
+    unsafe { x86::io::outw(port, val) };
+}
+
+/// Reads 32-bits from an IO port.
+pub fn inl(port: u16) -> u32 {
+    unsafe { x86::io::inl(port) }
+}
+
+/// Writes 32-bits to an IO port.
+pub fn outl(port: u16, val: u32) {
+    unsafe { x86::io::outl(port, val) };
+}
+
 /// Reads the IDTR register.
 pub fn sidt() -> DescriptorTablePointer<u64> {
     let mut idtr = DescriptorTablePointer::<u64>::default();
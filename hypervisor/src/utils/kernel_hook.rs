@@ -0,0 +1,374 @@
+//! Provides typed trampolines and the `kernel_hook!` macro for declaring guest-side hook handlers.
+//!
+//! A hook declared with `kernel_hook!` consists of a module containing the handler with the signature of
+//! the hooked function, a `Trampoline` holding the address of the relocated original function, a safe
+//! `original` helper calling it and functions registering the hook with the `HookManager`. The priority
+//! of the handler in the handler chain of the hooked function can be declared after the name of the
+//! export.
+//!
+//! The registration needs the kernel, while the trampolines and the handlers are available on any target.
+//!
+//! ```ignore
+//! hypervisor::kernel_hook! {
+//!     /// Logs every call to `MmIsAddressValid`.
+//!     "MmIsAddressValid", priority = 10 => pub fn mm_is_address_valid(virtual_address: u64) -> bool {
+//!         log::debug!("MmIsAddressValid called with {:#x}", virtual_address);
+//!         original(virtual_address).unwrap_or(false)
+//!     }
+//! }
+//!
+//! let sys_info = Sysinfo::new()?;
+//! mm_is_address_valid::register(&mut hook_manager, &sys_info)?;
+//! ```
+
+#[cfg(windows)]
+use crate::{
+    error::HypervisorError,
+    intel::ept::hooks::{HookManager, HookType},
+    utils::{function_hook::HookHandler, nt::resolve_export, ssdt::sys_info::Sysinfo},
+};
+use core::{
+    marker::PhantomData,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+mod sealed {
+    /// Prevents `FunctionPointer` from being implemented outside of this module.
+    pub trait Sealed {}
+}
+
+/// The `extern "C"` function pointer types, which are the only types a `Trampoline` can hold.
+///
+/// The trait is sealed, so a `Trampoline` can't turn an address into anything but a function pointer.
+pub trait FunctionPointer: sealed::Sealed + Copy {}
+
+/// Implements `FunctionPointer` for the `extern "C"` function pointers with up to as many arguments as given.
+macro_rules! impl_function_pointer {
+    () => {
+        impl<R> sealed::Sealed for extern "C" fn() -> R {}
+        impl<R> FunctionPointer for extern "C" fn() -> R {}
+    };
+    ($first:ident $(, $rest:ident)*) => {
+        impl<R, $first $(, $rest)*> sealed::Sealed for extern "C" fn($first $(, $rest)*) -> R {}
+        impl<R, $first $(, $rest)*> FunctionPointer for extern "C" fn($first $(, $rest)*) -> R {}
+
+        impl_function_pointer!($($rest),*);
+    };
+}
+
+impl_function_pointer!(A1, A2, A3, A4, A5, A6, A7, A8, A9, A10, A11, A12, A13, A14, A15, A16);
+
+/// The address of the relocated original function of a hook, typed with the signature of the function.
+///
+/// `F` must be the `extern "C"` function pointer type matching the signature of the hooked function. Other
+/// types are rejected:
+///
+/// ```compile_fail
+/// static ORIGINAL: hypervisor::utils::kernel_hook::Trampoline<u64> =
+///     hypervisor::utils::kernel_hook::Trampoline::new();
+/// ```
+pub struct Trampoline<F> {
+    /// The address of the trampoline, or 0 if the hook hasn't been registered yet.
+    address: AtomicU64,
+
+    /// The function pointer type of the trampoline.
+    _function: PhantomData<F>,
+}
+
+impl<F: FunctionPointer> Trampoline<F> {
+    /// Creates a new `Trampoline` that isn't set yet.
+    pub const fn new() -> Self {
+        Self {
+            address: AtomicU64::new(0),
+            _function: PhantomData,
+        }
+    }
+
+    /// Sets the address of the trampoline.
+    pub fn set(&self, address: u64) {
+        self.address.store(address, Ordering::Relaxed);
+    }
+
+    /// Returns the address of the trampoline, or `None` if it isn't set yet.
+    pub fn address(&self) -> Option<u64> {
+        match self.address.load(Ordering::Relaxed) {
+            0 => None,
+            address => Some(address),
+        }
+    }
+
+    /// Returns the trampoline as a function pointer, or `None` if it isn't set yet.
+    pub fn get(&self) -> Option<F> {
+        let address = self.address()?;
+
+        // SAFETY: `F` is an `extern "C"` function pointer type, which has the size of an address, matching
+        // the hooked function, whose relocated prologue is located at `address`.
+        Some(unsafe { core::mem::transmute_copy::<u64, F>(&address) })
+    }
+
+    /// Hooks the function at the given address, redirecting it to `handler`, and registers the hook with the `HookManager`.
+    ///
+    /// The trampoline is taken from the hook registered with the `HookManager`, which might be an existing hook
+    /// on the same function whose handler chain the new handler was merged into.
+    ///
+    /// # Arguments
+    ///
+    /// * `hook_manager` - The hook manager the hook is registered with.
+    /// * `sys_info` - The loaded modules.
+    /// * `function_address` - The address of the function to hook.
+    /// * `priority` - The priority of the handler. Handlers with a higher priority run first.
+    /// * `handler` - The address of the guest-side handler.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), HypervisorError>` - `Ok` if the hook was registered, or an error if the hook couldn't be created.
+    #[cfg(windows)]
+    pub fn register(
+        &self,
+        hook_manager: &mut HookManager,
+        sys_info: &Sysinfo,
+        function_address: u64,
+        priority: i32,
+        handler: u64,
+    ) -> Result<(), HypervisorError> {
+        let hook = hook_manager.hook_function_ptr(
+            sys_info,
+            function_address,
+            priority,
+            HookHandler::Redirect(handler),
+        )?;
+
+        let trampoline_address = match &hook.hook_type {
+            HookType::Function { inline_hook } => inline_hook.trampoline_address() as u64,
+            HookType::Page => return Err(HypervisorError::HookError),
+        };
+
+        self.set(trampoline_address);
+
+        Ok(())
+    }
+
+    /// Hooks an exported function of the kernel or a loaded module by its name and registers the hook with the `HookManager`.
+    ///
+    /// # Arguments
+    ///
+    /// * `hook_manager` - The hook manager the hook is registered with.
+    /// * `sys_info` - The loaded modules.
+    /// * `function_name` - The name of the exported function to hook, as an export of ntoskrnl.exe or `module!function`.
+    /// * `priority` - The priority of the handler. Handlers with a higher priority run first.
+    /// * `handler` - The address of the guest-side handler.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), HypervisorError>` - `Ok` if the hook was registered, or an error if the function cannot be found or the hook couldn't be created.
+    #[cfg(windows)]
+    pub fn register_export(
+        &self,
+        hook_manager: &mut HookManager,
+        sys_info: &Sysinfo,
+        function_name: &str,
+        priority: i32,
+        handler: u64,
+    ) -> Result<(), HypervisorError> {
+        let address = resolve_export(function_name).map_err(|e| {
+            log::error!("Failed to find function {}: {}", function_name, e);
+            e
+        })?;
+
+        self.register(hook_manager, sys_info, address, priority, handler)
+    }
+}
+
+impl<F: FunctionPointer> Default for Trampoline<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Declares a guest-side hook handler together with its typed trampoline.
+///
+/// The macro generates a module named after the handler, containing:
+/// - `Function`: The function pointer type of the hooked function.
+/// - `ORIGINAL`: The `Trampoline<Function>` of the hook.
+/// - `PRIORITY`: The priority of the handler, `DEFAULT_PRIORITY` unless declared with `priority = ...`.
+/// - `handler`: The handler with the signature of the hooked function, executing the declared body.
+/// - `original`: A safe helper calling the original function through the trampoline.
+/// - `register_at`: Registers the hook on a function by its address with the `HookManager`.
+/// - `register`: Registers the hook on the exported function with the `HookManager`, if the name of the export is declared.
+///
+/// The return type can be omitted for functions that don't return a value. The body of the handler can call
+/// `original` with the arguments to execute the original function, which returns `None` if the hook isn't
+/// registered. The registration functions are only generated for Windows.
+#[macro_export]
+macro_rules! kernel_hook {
+    (@priority) => {
+        $crate::utils::function_hook::DEFAULT_PRIORITY
+    };
+    (@priority $priority:expr) => {
+        $priority
+    };
+    (
+        @module
+        $(#[$attr:meta])*
+        $vis:vis fn $name:ident($($arg:ident: $ty:ty),*) -> $ret:ty $body:block
+        priority = ($($priority:expr)?)
+        { $($item:item)* }
+    ) => {
+        $(#[$attr])*
+        #[allow(dead_code)]
+        $vis mod $name {
+            #[allow(unused_imports)]
+            use super::*;
+
+            /// The function pointer type of the hooked function.
+            pub type Function = extern "C" fn($($ty),*) -> $ret;
+
+            /// The trampoline executing the original function.
+            pub static ORIGINAL: $crate::utils::kernel_hook::Trampoline<Function> =
+                $crate::utils::kernel_hook::Trampoline::new();
+
+            /// The priority of the handler in the handler chain of the hooked function.
+            pub const PRIORITY: i32 = $crate::kernel_hook!(@priority $($priority)?);
+
+            /// Calls the original function through the trampoline.
+            ///
+            /// Returns `None` if the hook hasn't been registered, which can't happen when called from the handler.
+            pub fn original($($arg: $ty),*) -> Option<$ret> {
+                ORIGINAL.get().map(|original| original($($arg),*))
+            }
+
+            /// The handler of the hook.
+            pub extern "C" fn handler($($arg: $ty),*) -> $ret $body
+
+            /// Registers the hook on a function by its address with the `HookManager`.
+            #[cfg(windows)]
+            pub fn register_at(
+                hook_manager: &mut $crate::intel::ept::hooks::HookManager,
+                sys_info: &$crate::utils::ssdt::sys_info::Sysinfo,
+                function_address: u64,
+            ) -> Result<(), $crate::error::HypervisorError> {
+                ORIGINAL.register(
+                    hook_manager,
+                    sys_info,
+                    function_address,
+                    PRIORITY,
+                    handler as *const () as u64,
+                )
+            }
+
+            $($item)*
+        }
+    };
+    (
+        $(#[$attr:meta])*
+        $export:literal $(, priority = $priority:expr)? => $vis:vis fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty $body:block
+    ) => {
+        $crate::kernel_hook! {
+            @module
+            $(#[$attr])*
+            $vis fn $name($($arg: $ty),*) -> $ret $body
+            priority = ($($priority)?)
+            {
+                /// The name of the exported function that is hooked.
+                pub const EXPORT: &str = $export;
+
+                /// Registers the hook on the exported function with the `HookManager`.
+                #[cfg(windows)]
+                pub fn register(
+                    hook_manager: &mut $crate::intel::ept::hooks::HookManager,
+                    sys_info: &$crate::utils::ssdt::sys_info::Sysinfo,
+                ) -> Result<(), $crate::error::HypervisorError> {
+                    ORIGINAL.register_export(
+                        hook_manager,
+                        sys_info,
+                        EXPORT,
+                        PRIORITY,
+                        handler as *const () as u64,
+                    )
+                }
+            }
+        }
+    };
+    (
+        $(#[$attr:meta])*
+        $(priority = $priority:expr =>)? $vis:vis fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty $body:block
+    ) => {
+        $crate::kernel_hook! {
+            @module
+            $(#[$attr])*
+            $vis fn $name($($arg: $ty),*) -> $ret $body
+            priority = ($($priority)?)
+            {}
+        }
+    };
+    (
+        $(#[$attr:meta])*
+        $export:literal $(, priority = $priority:expr)? => $vis:vis fn $name:ident($($arg:ident: $ty:ty),* $(,)?) $body:block
+    ) => {
+        $crate::kernel_hook! {
+            $(#[$attr])*
+            $export $(, priority = $priority)? => $vis fn $name($($arg: $ty),*) -> () $body
+        }
+    };
+    (
+        $(#[$attr:meta])*
+        $(priority = $priority:expr =>)? $vis:vis fn $name:ident($($arg:ident: $ty:ty),* $(,)?) $body:block
+    ) => {
+        $crate::kernel_hook! {
+            $(#[$attr])*
+            $(priority = $priority =>)? $vis fn $name($($arg: $ty),*) -> () $body
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    /// The original function of the hooks, standing in for the trampoline.
+    extern "C" fn add(a: u64, b: u64) -> u64 {
+        a + b
+    }
+
+    crate::kernel_hook! {
+        /// Doubles the result of the original function.
+        "Add", priority = 10 => pub fn double_add(a: u64, b: u64) -> u64 {
+            original(a, b).map_or(0, |result| result * 2)
+        }
+    }
+
+    crate::kernel_hook! {
+        /// Returns the result of the original function, or `u64::MAX` if the hook isn't registered.
+        fn unregistered_add(a: u64, b: u64) -> u64 {
+            original(a, b).unwrap_or(u64::MAX)
+        }
+    }
+
+    #[test]
+    fn handlers_call_the_original_function_through_the_trampoline() {
+        let trampoline: double_add::Function = add;
+        double_add::ORIGINAL.set(trampoline as usize as u64);
+
+        assert_eq!(
+            double_add::ORIGINAL.address(),
+            Some(trampoline as usize as u64)
+        );
+        assert_eq!(double_add::handler(2, 3), 10);
+        assert_eq!(double_add::original(2, 3), Some(5));
+    }
+
+    #[test]
+    fn declarations_provide_the_export_and_the_priority() {
+        assert_eq!(double_add::EXPORT, "Add");
+        assert_eq!(double_add::PRIORITY, 10);
+        assert_eq!(
+            unregistered_add::PRIORITY,
+            crate::utils::function_hook::DEFAULT_PRIORITY
+        );
+    }
+
+    #[test]
+    fn handlers_of_unregistered_hooks_see_no_original_function() {
+        assert_eq!(unregistered_add::ORIGINAL.address(), None);
+        assert_eq!(unregistered_add::original(2, 3), None);
+        assert_eq!(unregistered_add::handler(2, 3), u64::MAX);
+    }
+}
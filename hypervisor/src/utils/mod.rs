@@ -1,8 +1,19 @@
 pub mod addresses;
 pub mod alloc;
+pub mod bytes;
 pub mod capture;
 pub mod function_hook;
 pub mod instructions;
+pub mod kernel_hook;
+#[cfg(windows)]
 pub mod nt;
+pub mod pdb;
+pub mod pe;
+#[cfg(windows)]
 pub mod processor;
+pub mod return_hook;
+pub mod signature;
 pub mod ssdt;
+pub mod symbols;
+pub mod trampoline;
+pub mod windows_build;
@@ -3,13 +3,24 @@
 #![allow(non_camel_case_types)]
 
 use {
-    crate::error::HypervisorError,
-    alloc::vec::Vec,
+    crate::{
+        error::HypervisorError,
+        utils::{
+            pe::{ExportTarget, Function, PeImage},
+            ssdt::sys_info::Sysinfo,
+            symbols,
+        },
+    },
+    alloc::{string::String, vec, vec::Vec},
     wdk_sys::{
         ntddk::{
             KeLowerIrql, KeStackAttachProcess, KeUnstackDetachProcess, MmGetSystemRoutineAddress,
+            ZwClose, ZwOpenKey, ZwQueryValueKey,
         },
-        KIRQL, PEPROCESS, PRKPROCESS, PVOID, UNICODE_STRING, _KAPC_STATE,
+        HANDLE, KEY_READ, KEY_VALUE_PARTIAL_INFORMATION, KIRQL, NT_SUCCESS, OBJECT_ATTRIBUTES,
+        OBJ_CASE_INSENSITIVE, OBJ_KERNEL_HANDLE, PEPROCESS, PRKPROCESS, PUNICODE_STRING, PVOID,
+        STATUS_BUFFER_OVERFLOW, STATUS_BUFFER_TOO_SMALL, UNICODE_STRING, _KAPC_STATE,
+        _KEY_VALUE_INFORMATION_CLASS,
     },
 };
 
@@ -40,6 +51,194 @@ pub fn get_ntoskrnl_export(function_name: &str) -> PVOID {
     routine_address
 }
 
+/// Maximum number of forwarded exports followed when resolving an export.
+const MAX_FORWARDER_DEPTH: usize = 4;
+
+/// Gets the address of an exported function of a loaded kernel module by parsing its export directory.
+///
+/// Forwarded exports (`NTOSKRNL.KeBugCheckEx`, `HAL.#12`) are followed to the module they are forwarded to.
+/// Must be called at PASSIVE_LEVEL, as the loaded modules are queried with `ZwQuerySystemInformation`.
+///
+/// # Arguments
+/// * `module_name` - The file name of the module, for example `CI.dll`. The extension can be omitted.
+/// * `function_name` - The name of the function, or `#ordinal` to get it by its ordinal.
+///
+/// # Returns
+/// * `Result<u64, HypervisorError>` - The address of the function, or an error if the module or the export wasn't found.
+pub fn get_module_export(module_name: &str, function_name: &str) -> Result<u64, HypervisorError> {
+    let sys_info = Sysinfo::new()?;
+
+    let mut module_name = String::from(module_name);
+    let mut function_name = String::from(function_name);
+
+    for _ in 0..MAX_FORWARDER_DEPTH {
+        let (base, size) = sys_info
+            .find_module(&module_name)
+            .ok_or(HypervisorError::ModuleNotFound)?;
+
+        let image = unsafe { PeImage::from_base(base as *const u8, size as usize)? };
+        let exports = image.exports()?;
+
+        let export = match function_name.strip_prefix('#') {
+            Some(ordinal) => exports.by_ordinal(
+                ordinal
+                    .parse()
+                    .map_err(|_| HypervisorError::ExportNotFound)?,
+            )?,
+            None => exports.by_name(&function_name)?,
+        };
+
+        let ExportTarget::Forwarder(forwarder) = export.target else {
+            let address = base as u64 + export.rva().unwrap_or_default() as u64;
+            log::trace!(
+                "Resolved {}!{} to {:#x}",
+                module_name,
+                function_name,
+                address
+            );
+            return Ok(address);
+        };
+
+        log::trace!(
+            "{}!{} is forwarded to {}",
+            module_name,
+            function_name,
+            forwarder
+        );
+
+        let (forwarded_module, forwarded_function) =
+            export.forwarder().ok_or(HypervisorError::InvalidPeImage)?;
+
+        module_name = String::from(forwarded_module);
+        function_name = String::from(forwarded_function);
+    }
+
+    Err(HypervisorError::ExportNotFound)
+}
+
+/// Finds the function containing an address of a loaded kernel module, using the exception directory of the module.
+///
+/// # Arguments
+/// * `sys_info` - The loaded modules.
+/// * `address` - The address of an instruction.
+///
+/// # Returns
+/// * `Result<Option<(u64, Function)>, HypervisorError>` - The base address of the module and the function (relative to it),
+///   `None` if the exception directory has no entry for the address (for example a leaf function), or an error if no module contains the address.
+pub fn find_function(
+    sys_info: &Sysinfo,
+    address: u64,
+) -> Result<Option<(u64, Function)>, HypervisorError> {
+    let (base, size) = sys_info
+        .module_containing(address)
+        .ok_or(HypervisorError::ModuleNotFound)?;
+
+    let image = unsafe { PeImage::from_base(base as *const u8, size as usize)? };
+    let rva = (address - base as u64) as u32;
+
+    Ok(image
+        .function_containing(rva)
+        .map(|function| (base as u64, function)))
+}
+
+/// Gets the address of an exported function by its name.
+///
+/// # Arguments
+/// * `name` - The name of an ntoskrnl.exe export (`ExAllocatePoolWithTag`), or `module!function` for the exports of other loaded modules (`CI.dll!CiValidateImageHeader`). Names ntoskrnl.exe doesn't export are looked up in the installed symbols.
+///
+/// # Returns
+/// * `Result<u64, HypervisorError>` - The address of the function, or an error if it wasn't found.
+pub fn resolve_export(name: &str) -> Result<u64, HypervisorError> {
+    if let Some((module_name, function_name)) = name.split_once('!') {
+        return get_module_export(module_name, function_name);
+    }
+
+    match get_ntoskrnl_export(name) {
+        address if address.is_null() => {
+            // Fall back to the installed symbols for functions ntoskrnl.exe doesn't export.
+            symbols::kernel_symbol(name).map_err(|_| HypervisorError::ExportNotFound)
+        }
+        address => Ok(address as u64),
+    }
+}
+
+/// Reads a value of a registry key, for example of the service key passed to the driver entry.
+///
+/// # Arguments
+/// * `key_path` - The path of the registry key.
+/// * `value_name` - The name of the value.
+///
+/// # Returns
+/// * `Result<Vec<u8>, HypervisorError>` - The data of the value, or an error if the key or value doesn't exist.
+pub fn read_registry_value(
+    key_path: PUNICODE_STRING,
+    value_name: &str,
+) -> Result<Vec<u8>, HypervisorError> {
+    let mut object_attributes = OBJECT_ATTRIBUTES {
+        Length: core::mem::size_of::<OBJECT_ATTRIBUTES>() as u32,
+        ObjectName: key_path,
+        Attributes: OBJ_CASE_INSENSITIVE | OBJ_KERNEL_HANDLE,
+        ..Default::default()
+    };
+
+    let mut key: HANDLE = core::ptr::null_mut();
+    let status = unsafe { ZwOpenKey(&mut key, KEY_READ, &mut object_attributes) };
+    if !NT_SUCCESS(status) {
+        return Err(HypervisorError::RegistryValueNotFound);
+    }
+
+    let wide_name: Vec<u16> = value_name.encode_utf16().collect();
+    let mut unicode_name = UNICODE_STRING {
+        Length: (wide_name.len() * 2) as u16,
+        MaximumLength: (wide_name.len() * 2) as u16,
+        Buffer: wide_name.as_ptr() as *mut _,
+    };
+
+    // Query the size of the value first, then the value itself.
+    let mut result_length = 0;
+    let status = unsafe {
+        ZwQueryValueKey(
+            key,
+            &mut unicode_name,
+            _KEY_VALUE_INFORMATION_CLASS::KeyValuePartialInformation,
+            core::ptr::null_mut(),
+            0,
+            &mut result_length,
+        )
+    };
+
+    if status != STATUS_BUFFER_TOO_SMALL && status != STATUS_BUFFER_OVERFLOW {
+        unsafe { ZwClose(key) };
+        return Err(HypervisorError::RegistryValueNotFound);
+    }
+
+    let mut buffer = vec![0u8; result_length as usize];
+    let status = unsafe {
+        ZwQueryValueKey(
+            key,
+            &mut unicode_name,
+            _KEY_VALUE_INFORMATION_CLASS::KeyValuePartialInformation,
+            buffer.as_mut_ptr() as _,
+            result_length,
+            &mut result_length,
+        )
+    };
+
+    unsafe { ZwClose(key) };
+
+    if !NT_SUCCESS(status) {
+        return Err(HypervisorError::RegistryValueNotFound);
+    }
+
+    let information = unsafe { &*(buffer.as_ptr() as *const KEY_VALUE_PARTIAL_INFORMATION) };
+    let data_offset = core::mem::offset_of!(KEY_VALUE_PARTIAL_INFORMATION, Data);
+
+    buffer
+        .get(data_offset..data_offset + information.DataLength as usize)
+        .map(<[u8]>::to_vec)
+        .ok_or(HypervisorError::RegistryValueNotFound)
+}
+
 /// Raises the current IRQL to DISPATCH_LEVEL and returns the previous IRQL.
 ///
 /// # Returns
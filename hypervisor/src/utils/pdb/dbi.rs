@@ -0,0 +1,361 @@
+//! Provides a reader for the debug information (DBI) stream and the public symbols of a PDB file.
+//!
+//! The DBI stream references the symbol record stream, which contains the public symbols (`S_PUB32`),
+//! and the section header stream, which is needed to convert the `segment:offset` addresses of the
+//! symbols to RVAs.
+
+use {
+    crate::{error::HypervisorError, utils::bytes::ByteReader},
+    alloc::vec::Vec,
+};
+
+/// The signature of the DBI stream header.
+const DBI_VERSION_SIGNATURE: u32 = u32::MAX;
+
+/// The size of the DBI stream header.
+const DBI_HEADER_SIZE: usize = 64;
+
+/// The index of the section header stream in the optional debug header.
+const DEBUG_HEADER_SECTION_HEADERS: usize = 5;
+
+/// The index of a stream that doesn't exist.
+const NIL_STREAM_INDEX: u16 = u16::MAX;
+
+/// The size of a section header (`IMAGE_SECTION_HEADER`).
+const SECTION_HEADER_SIZE: usize = 40;
+
+/// The kind of a public symbol record.
+const S_PUB32: u16 = 0x110E;
+
+/// The header of the DBI stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DbiStream {
+    /// The age of the PDB, which matches the age in the CodeView record of the image.
+    pub age: u32,
+
+    /// The machine type of the image.
+    pub machine: u16,
+
+    /// The index of the symbol record stream.
+    pub symbol_records_stream: u16,
+
+    /// The index of the section header stream, if the PDB has one.
+    pub section_headers_stream: Option<u16>,
+}
+
+impl DbiStream {
+    /// Parses the header and the optional debug header of the DBI stream.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The data of the DBI stream (stream 3).
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, HypervisorError>` - The DBI stream, or an error if the header is invalid.
+    pub fn parse(data: &[u8]) -> Result<Self, HypervisorError> {
+        let mut reader = ByteReader::new(data, || HypervisorError::InvalidPdb);
+
+        if reader.u32()? != DBI_VERSION_SIGNATURE {
+            return Err(HypervisorError::InvalidPdb);
+        }
+
+        let _version = reader.u32()?;
+        let age = reader.u32()?;
+        let _global_stream = reader.u16()?;
+        let _build_number = reader.u16()?;
+        let _public_stream = reader.u16()?;
+        let _pdb_dll_version = reader.u16()?;
+        let symbol_records_stream = reader.u16()?;
+        let _pdb_dll_rebuild = reader.u16()?;
+
+        // The sizes of the substreams in order: module info, section contributions, section map,
+        // source info and type server map. They are followed by the EC substream and the optional
+        // debug header, whose sizes come after the MFC type server index.
+        let substreams_size = (0..5)
+            .map(|_| reader.u32().map(|size| size as usize))
+            .sum::<Result<usize, _>>()?;
+        let _mfc_type_server_index = reader.u32()?;
+        let debug_header_size = reader.u32()? as usize;
+        let ec_substream_size = reader.u32()? as usize;
+        let _flags = reader.u16()?;
+        let machine = reader.u16()?;
+
+        reader.seek(
+            DBI_HEADER_SIZE
+                .checked_add(substreams_size)
+                .and_then(|offset| offset.checked_add(ec_substream_size))
+                .ok_or(HypervisorError::InvalidPdb)?,
+        )?;
+        let debug_header = reader.bytes(debug_header_size)?;
+
+        let section_headers_stream = debug_header
+            .chunks_exact(2)
+            .nth(DEBUG_HEADER_SECTION_HEADERS)
+            .map(|index| u16::from_le_bytes([index[0], index[1]]))
+            .filter(|index| *index != NIL_STREAM_INDEX);
+
+        Ok(Self {
+            age,
+            machine,
+            symbol_records_stream,
+            section_headers_stream,
+        })
+    }
+}
+
+/// A public symbol (`S_PUB32`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublicSymbol<'a> {
+    /// The name of the symbol, which is decorated for C++ symbols.
+    pub name: &'a str,
+
+    /// The 1-based index of the section containing the symbol.
+    pub segment: u16,
+
+    /// The offset of the symbol in the section.
+    pub offset: u32,
+
+    /// The flags of the symbol (`CVPSF_*`), for example 2 for functions.
+    pub flags: u32,
+}
+
+/// Parses the public symbols of the symbol record stream.
+///
+/// # Arguments
+///
+/// * `data` - The data of the symbol record stream.
+///
+/// # Returns
+///
+/// * `Result<Vec<PublicSymbol>, HypervisorError>` - The public symbols, or an error if a record is truncated.
+pub fn public_symbols(data: &[u8]) -> Result<Vec<PublicSymbol<'_>>, HypervisorError> {
+    let mut reader = ByteReader::new(data, || HypervisorError::InvalidPdb);
+    let mut symbols = Vec::new();
+
+    while reader.remaining() >= 4 {
+        // The length doesn't include the length field itself.
+        let length = reader.u16()? as usize;
+        let start = reader.position();
+        let kind = reader.u16()?;
+
+        if kind == S_PUB32 {
+            let flags = reader.u32()?;
+            let offset = reader.u32()?;
+            let segment = reader.u16()?;
+            let name = reader.c_str()?;
+
+            symbols.push(PublicSymbol {
+                name,
+                segment,
+                offset,
+                flags,
+            });
+        }
+
+        reader.seek(start + length)?;
+    }
+
+    Ok(symbols)
+}
+
+/// Parses the virtual addresses of the sections from the section header stream.
+///
+/// # Arguments
+///
+/// * `data` - The data of the section header stream.
+///
+/// # Returns
+///
+/// * `Vec<u32>` - The RVAs of the sections, indexed by segment minus 1.
+pub fn section_addresses(data: &[u8]) -> Vec<u32> {
+    data.chunks_exact(SECTION_HEADER_SIZE)
+        .map(|header| u32::from_le_bytes([header[12], header[13], header[14], header[15]]))
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Builds a DBI stream with a module info substream, an EC substream and the optional debug header.
+    pub(crate) fn build_dbi(
+        age: u32,
+        machine: u16,
+        symbol_records_stream: u16,
+        section_headers_stream: u16,
+    ) -> Vec<u8> {
+        let module_info = [0xAA; 8];
+        let ec_substream = [0xBB; 4];
+        let debug_header = (0..11)
+            .map(|index| match index {
+                DEBUG_HEADER_SECTION_HEADERS => section_headers_stream,
+                _ => NIL_STREAM_INDEX,
+            })
+            .flat_map(u16::to_le_bytes)
+            .collect::<Vec<_>>();
+
+        let mut dbi = Vec::new();
+        dbi.extend_from_slice(&DBI_VERSION_SIGNATURE.to_le_bytes());
+        dbi.extend_from_slice(&19990903_u32.to_le_bytes());
+        dbi.extend_from_slice(&age.to_le_bytes());
+        for stream in [7_u16, 0x8E1D, 8, 0xE3B5, symbol_records_stream, 0] {
+            dbi.extend_from_slice(&stream.to_le_bytes());
+        }
+        for size in [module_info.len() as u32, 0, 0, 0, 0, 0] {
+            dbi.extend_from_slice(&size.to_le_bytes());
+        }
+        dbi.extend_from_slice(&(debug_header.len() as u32).to_le_bytes());
+        dbi.extend_from_slice(&(ec_substream.len() as u32).to_le_bytes());
+        dbi.extend_from_slice(&0_u16.to_le_bytes());
+        dbi.extend_from_slice(&machine.to_le_bytes());
+        dbi.extend_from_slice(&0_u32.to_le_bytes());
+        assert_eq!(dbi.len(), DBI_HEADER_SIZE);
+
+        dbi.extend_from_slice(&module_info);
+        dbi.extend_from_slice(&ec_substream);
+        dbi.extend_from_slice(&debug_header);
+        dbi
+    }
+
+    /// Builds an `S_PUB32` record, padded to 4 bytes.
+    pub(crate) fn pub32(name: &str, segment: u16, offset: u32) -> Vec<u8> {
+        symbol_record(S_PUB32, |record| {
+            record.extend_from_slice(&2_u32.to_le_bytes());
+            record.extend_from_slice(&offset.to_le_bytes());
+            record.extend_from_slice(&segment.to_le_bytes());
+            record.extend_from_slice(name.as_bytes());
+            record.push(0);
+        })
+    }
+
+    /// Builds a symbol record of a kind, padded to 4 bytes.
+    fn symbol_record(kind: u16, data: impl FnOnce(&mut Vec<u8>)) -> Vec<u8> {
+        let mut record = kind.to_le_bytes().to_vec();
+        data(&mut record);
+        record.resize((record.len() + 2).next_multiple_of(4) - 2, 0);
+
+        let mut bytes = (record.len() as u16).to_le_bytes().to_vec();
+        bytes.extend_from_slice(&record);
+        bytes
+    }
+
+    /// Builds a section header stream from the virtual addresses of the sections.
+    pub(crate) fn section_headers(addresses: &[u32]) -> Vec<u8> {
+        addresses
+            .iter()
+            .flat_map(|address| {
+                let mut header = [0u8; SECTION_HEADER_SIZE];
+                header[12..16].copy_from_slice(&address.to_le_bytes());
+                header
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_header_references_the_streams() {
+        let dbi = DbiStream::parse(&build_dbi(3, 0x8664, 9, 12)).unwrap();
+
+        assert_eq!(
+            dbi,
+            DbiStream {
+                age: 3,
+                machine: 0x8664,
+                symbol_records_stream: 9,
+                section_headers_stream: Some(12),
+            }
+        );
+    }
+
+    #[test]
+    fn missing_section_headers_are_none() {
+        let dbi = DbiStream::parse(&build_dbi(1, 0x8664, 9, NIL_STREAM_INDEX)).unwrap();
+        assert_eq!(dbi.section_headers_stream, None);
+
+        // Debug headers too short to contain the section header stream.
+        let mut data = build_dbi(1, 0x8664, 9, 12);
+        data.truncate(data.len() - 12);
+        data[DBI_HEADER_SIZE - 16..DBI_HEADER_SIZE - 12].copy_from_slice(&10_u32.to_le_bytes());
+        let dbi = DbiStream::parse(&data).unwrap();
+        assert_eq!(dbi.section_headers_stream, None);
+    }
+
+    #[test]
+    fn invalid_headers_are_rejected() {
+        let mut data = build_dbi(1, 0x8664, 9, 12);
+        data[0] = 0;
+        assert!(matches!(
+            DbiStream::parse(&data),
+            Err(HypervisorError::InvalidPdb)
+        ));
+
+        let data = build_dbi(1, 0x8664, 9, 12);
+        assert!(matches!(
+            DbiStream::parse(&data[..DBI_HEADER_SIZE + 4]),
+            Err(HypervisorError::InvalidPdb)
+        ));
+
+        let mut data = build_dbi(1, 0x8664, 9, 12);
+        data[24..28].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            DbiStream::parse(&data),
+            Err(HypervisorError::InvalidPdb)
+        ));
+    }
+
+    #[test]
+    fn public_symbols_skip_other_records() {
+        let mut records = pub32("KiSystemServiceStart", 1, 0x40);
+        records.extend(symbol_record(0x1125, |record| {
+            record.extend_from_slice(&[0; 10]);
+        }));
+        records.extend(pub32("PspCidTable", 3, 0x128));
+
+        let symbols = public_symbols(&records).unwrap();
+
+        assert_eq!(
+            symbols,
+            [
+                PublicSymbol {
+                    name: "KiSystemServiceStart",
+                    segment: 1,
+                    offset: 0x40,
+                    flags: 2,
+                },
+                PublicSymbol {
+                    name: "PspCidTable",
+                    segment: 3,
+                    offset: 0x128,
+                    flags: 2,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn truncated_public_symbols_are_rejected() {
+        let mut records = pub32("PspCidTable", 3, 0x128);
+        records.truncate(records.len() - 8);
+
+        assert!(matches!(
+            public_symbols(&records),
+            Err(HypervisorError::InvalidPdb)
+        ));
+
+        let mut records = pub32("PspCidTable", 3, 0x128);
+        records[0..2].copy_from_slice(&0x100_u16.to_le_bytes());
+
+        assert!(matches!(
+            public_symbols(&records),
+            Err(HypervisorError::InvalidPdb)
+        ));
+    }
+
+    #[test]
+    fn section_addresses_are_indexed_by_segment() {
+        let mut data = section_headers(&[0x1000, 0x20_0000, 0x30_0000]);
+        data.extend_from_slice(&[0; 10]);
+
+        assert_eq!(section_addresses(&data), [0x1000, 0x20_0000, 0x30_0000]);
+    }
+}
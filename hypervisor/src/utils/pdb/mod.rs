@@ -0,0 +1,355 @@
+//! Provides an offline reader for PDB files, which extracts the symbols and structure layouts of a
+//! kernel image that aren't exported.
+//!
+//! The reader only operates on the bytes of the PDB, so it runs outside the kernel, in the
+//! `extract_symbols` example of this crate. The extracted symbols are serialized into a blob that is
+//! passed to the driver:
+//!
+//! ```ignore
+//! let pdb = Pdb::parse(&pdb_bytes)?;
+//! let symbols = pdb.extract(&["PspCidTable", "KiSystemServiceStart"], &["_EPROCESS", "_KTHREAD"])?;
+//! let blob = symbols.to_bytes();
+//! ```
+
+pub mod dbi;
+pub mod msf;
+pub mod tpi;
+
+use {
+    crate::{
+        error::HypervisorError,
+        utils::{
+            bytes::ByteReader,
+            pdb::{
+                dbi::{public_symbols, section_addresses, DbiStream},
+                msf::Msf,
+                tpi::TypeInfo,
+            },
+            symbols::{PdbIdentity, Struct, Symbols},
+        },
+    },
+    alloc::vec::Vec,
+};
+
+/// The index of the PDB info stream.
+const PDB_STREAM: usize = 1;
+
+/// The index of the TPI stream.
+const TPI_STREAM: usize = 2;
+
+/// The index of the DBI stream.
+const DBI_STREAM: usize = 3;
+
+/// A public symbol with its RVA.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symbol<'a> {
+    /// The name of the symbol.
+    pub name: &'a str,
+
+    /// The RVA of the symbol.
+    pub rva: u32,
+}
+
+/// A parsed PDB file.
+#[derive(Debug, Clone)]
+pub struct Pdb<'a> {
+    /// The MSF container.
+    msf: Msf<'a>,
+
+    /// The identity of the PDB.
+    identity: PdbIdentity,
+
+    /// The header of the DBI stream.
+    dbi: DbiStream,
+}
+
+impl<'a> Pdb<'a> {
+    /// Parses the PDB info and DBI streams of a PDB file.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The bytes of the PDB file.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, HypervisorError>` - The PDB, or an error if the file is invalid.
+    pub fn parse(data: &'a [u8]) -> Result<Self, HypervisorError> {
+        let msf = Msf::parse(data)?;
+
+        let info = msf.stream(PDB_STREAM)?;
+        let mut info = ByteReader::new(&info, || HypervisorError::InvalidPdb);
+        let _version = info.u32()?;
+        let _signature = info.u32()?;
+        let _age = info.u32()?;
+        let guid = info.array()?;
+
+        // The age of the DBI stream is the one stored in the CodeView record of the image.
+        let dbi = DbiStream::parse(&msf.stream(DBI_STREAM)?)?;
+
+        Ok(Self {
+            msf,
+            identity: PdbIdentity { guid, age: dbi.age },
+            dbi,
+        })
+    }
+
+    /// Returns the identity of the PDB.
+    pub fn identity(&self) -> PdbIdentity {
+        self.identity
+    }
+
+    /// Returns the machine type of the image.
+    pub fn machine(&self) -> u16 {
+        self.dbi.machine
+    }
+
+    /// Calls `f` with the public symbols and their RVAs.
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - The function called with the symbols.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), HypervisorError>` - An error if the symbol record or section header streams are invalid, or the RVA of a symbol overflows.
+    pub fn for_each_symbol(&self, mut f: impl FnMut(Symbol)) -> Result<(), HypervisorError> {
+        let section_headers_stream = self
+            .dbi
+            .section_headers_stream
+            .ok_or(HypervisorError::InvalidPdb)?;
+
+        let sections = section_addresses(&self.msf.stream(section_headers_stream as usize)?);
+        let records = self.msf.stream(self.dbi.symbol_records_stream as usize)?;
+
+        for symbol in public_symbols(&records)? {
+            let Some(section) = (symbol.segment as usize)
+                .checked_sub(1)
+                .and_then(|index| sections.get(index))
+            else {
+                continue;
+            };
+
+            let rva = section
+                .checked_add(symbol.offset)
+                .ok_or(HypervisorError::InvalidPdb)?;
+
+            f(Symbol {
+                name: symbol.name,
+                rva,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Finds the layout of a structure, class or union by its name.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the type, for example `_EPROCESS`.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<Struct>, HypervisorError>` - The layout, `None` if the PDB doesn't define the type, or an error if the TPI stream is invalid.
+    pub fn find_struct(&self, name: &str) -> Result<Option<Struct>, HypervisorError> {
+        let types = self.msf.stream(TPI_STREAM)?;
+        TypeInfo::parse(&types)?.find_struct(name)
+    }
+
+    /// Extracts symbols and structure layouts into a `Symbols` instance.
+    ///
+    /// Names the PDB doesn't contain are skipped, as they differ between builds.
+    ///
+    /// # Arguments
+    ///
+    /// * `symbol_names` - The names of the public symbols.
+    /// * `struct_names` - The names of the structures.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Symbols, HypervisorError>` - The symbols, or an error if the PDB is invalid.
+    pub fn extract(
+        &self,
+        symbol_names: &[&str],
+        struct_names: &[&str],
+    ) -> Result<Symbols, HypervisorError> {
+        let mut symbols = Symbols::new(self.identity);
+
+        self.for_each_symbol(|symbol| {
+            if symbol_names.contains(&symbol.name) {
+                symbols.add_symbol(symbol.name, symbol.rva);
+            }
+        })?;
+
+        let types = self.msf.stream(TPI_STREAM)?;
+        let types = TypeInfo::parse(&types)?;
+
+        for name in struct_names {
+            match types.find_struct(name)? {
+                Some(layout) => symbols.add_struct(name, layout),
+                None => log::debug!("Structure {} not found", name),
+            }
+        }
+
+        let missing = symbol_names
+            .iter()
+            .filter(|name| symbols.symbol_rva(name).is_none())
+            .collect::<Vec<_>>();
+
+        if !missing.is_empty() {
+            log::debug!("Symbols not found: {:?}", missing);
+        }
+
+        Ok(symbols)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::utils::pdb::{
+            dbi::tests::{build_dbi, pub32, section_headers},
+            msf::tests::build_msf,
+            tpi::tests::{build_tpi, member, small, structure, type_record, FIRST_INDEX},
+        },
+        alloc::{string::String, vec},
+    };
+
+    /// The GUID of the PDBs built by `build_pdb`.
+    const GUID: [u8; 16] = [
+        0x10, 0x32, 0x54, 0x76, 0x98, 0xBA, 0xDC, 0xFE, 0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD,
+        0xEF,
+    ];
+
+    /// The kind of a field list type record.
+    const LF_FIELDLIST: u16 = 0x1203;
+
+    /// Builds a PDB with the public symbols and the section addresses, and a `_EPROCESS` structure.
+    fn build_pdb(symbols: &[u8], sections: &[u32]) -> Vec<u8> {
+        let mut info = Vec::new();
+        info.extend_from_slice(&20000404_u32.to_le_bytes());
+        info.extend_from_slice(&0x6543_2100_u32.to_le_bytes());
+        info.extend_from_slice(&1_u32.to_le_bytes());
+        info.extend_from_slice(&GUID);
+
+        let mut field_list = member("Pcb", 0x22, &small(0));
+        field_list.extend(member("UniqueProcessId", 0x23, &small(0x440)));
+        let tpi = build_tpi(&[
+            type_record(LF_FIELDLIST, &field_list),
+            structure("_EPROCESS", FIRST_INDEX, 0xA40, 0),
+        ]);
+
+        let dbi = build_dbi(2, 0x8664, 4, 5);
+        let sections = section_headers(sections);
+
+        build_msf(&[
+            Some(&[]),
+            Some(&info),
+            Some(&tpi),
+            Some(&dbi),
+            Some(symbols),
+            Some(&sections),
+        ])
+    }
+
+    /// Builds the symbol records of `KiSystemServiceStart` in the first and `PspCidTable` in the third section.
+    fn symbol_records() -> Vec<u8> {
+        let mut records = pub32("KiSystemServiceStart", 1, 0x40);
+        records.extend(pub32("PspCidTable", 3, 0x128));
+        records.extend(pub32("NoSection", 0, 0x10));
+        records.extend(pub32("UnknownSection", 9, 0x10));
+        records
+    }
+
+    #[test]
+    fn the_identity_uses_the_age_of_the_dbi_stream() {
+        let file = build_pdb(&symbol_records(), &[0x1000, 0x20_0000, 0x30_0000]);
+        let pdb = Pdb::parse(&file).unwrap();
+
+        assert_eq!(pdb.identity(), PdbIdentity { guid: GUID, age: 2 });
+        assert_eq!(pdb.machine(), 0x8664);
+    }
+
+    #[test]
+    fn symbols_are_converted_to_rvas() {
+        let file = build_pdb(&symbol_records(), &[0x1000, 0x20_0000, 0x30_0000]);
+        let pdb = Pdb::parse(&file).unwrap();
+
+        let mut symbols = Vec::new();
+        pdb.for_each_symbol(|symbol| symbols.push((String::from(symbol.name), symbol.rva)))
+            .unwrap();
+
+        assert_eq!(
+            symbols,
+            [
+                (String::from("KiSystemServiceStart"), 0x1040),
+                (String::from("PspCidTable"), 0x30_0128),
+            ]
+        );
+    }
+
+    #[test]
+    fn overflowing_rvas_are_rejected() {
+        let file = build_pdb(&pub32("PspCidTable", 1, 0x2000), &[0xFFFF_F000]);
+        let pdb = Pdb::parse(&file).unwrap();
+
+        assert!(matches!(
+            pdb.for_each_symbol(|_| {}),
+            Err(HypervisorError::InvalidPdb)
+        ));
+    }
+
+    #[test]
+    fn extracted_symbols_survive_serialization() {
+        let file = build_pdb(&symbol_records(), &[0x1000, 0x20_0000, 0x30_0000]);
+        let pdb = Pdb::parse(&file).unwrap();
+
+        let symbols = pdb
+            .extract(
+                &["PspCidTable", "PsActiveProcessHead"],
+                &["_EPROCESS", "_KTHREAD"],
+            )
+            .unwrap();
+
+        assert_eq!(symbols.symbol_count(), 1);
+        assert_eq!(symbols.struct_count(), 1);
+
+        let symbols = Symbols::from_bytes(&symbols.to_bytes()).unwrap();
+        assert_eq!(symbols.identity(), PdbIdentity { guid: GUID, age: 2 });
+        assert_eq!(symbols.symbol_rva("PspCidTable"), Some(0x30_0128));
+        assert_eq!(
+            symbols.address("PspCidTable", 0xFFFF_F800_0000_0000),
+            Some(0xFFFF_F800_0030_0128)
+        );
+        assert_eq!(
+            symbols.field_offset("_EPROCESS", "UniqueProcessId"),
+            Some(0x440)
+        );
+        assert_eq!(
+            symbols.structure("_EPROCESS").unwrap().fields[0].name,
+            String::from("Pcb")
+        );
+        assert_eq!(symbols.structure("_EPROCESS").unwrap().size, 0xA40);
+    }
+
+    #[test]
+    fn pdbs_without_streams_are_rejected() {
+        let file = build_msf(&[Some(&[]), None]);
+        assert!(matches!(
+            Pdb::parse(&file),
+            Err(HypervisorError::InvalidPdb)
+        ));
+
+        let file = build_msf(&[Some(&[]), Some(&[0; 28]), None, Some(&[0; 64])]);
+        assert!(matches!(
+            Pdb::parse(&file),
+            Err(HypervisorError::InvalidPdb)
+        ));
+
+        assert!(matches!(
+            Pdb::parse(&vec![0; 4096]),
+            Err(HypervisorError::InvalidPdb)
+        ));
+    }
+}
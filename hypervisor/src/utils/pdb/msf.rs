@@ -0,0 +1,300 @@
+//! Provides a reader for the Multi-Stream Format (MSF) container of PDB files.
+//!
+//! An MSF file is divided into fixed-size blocks. The stream directory lists the size and the blocks
+//! of every stream, so a stream is read by concatenating its blocks.
+
+use {
+    crate::{error::HypervisorError, utils::bytes::ByteReader},
+    alloc::vec::Vec,
+};
+
+/// The magic of the MSF 7.0 superblock.
+const MSF_MAGIC: &[u8; 32] = b"Microsoft C/C++ MSF 7.00\r\n\x1aDS\0\0\0";
+
+/// The size of a stream that doesn't exist.
+const NIL_STREAM_SIZE: u32 = u32::MAX;
+
+/// An MSF container.
+#[derive(Debug, Clone)]
+pub struct Msf<'a> {
+    /// The bytes of the file.
+    data: &'a [u8],
+
+    /// The size of a block.
+    block_size: usize,
+
+    /// The sizes and blocks of the streams, or `None` for streams that don't exist.
+    streams: Vec<Option<(u32, Vec<u32>)>>,
+}
+
+impl<'a> Msf<'a> {
+    /// Parses the superblock and the stream directory of an MSF file.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The bytes of the file.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, HypervisorError>` - The container, or an error if the file isn't an MSF 7.0 file.
+    pub fn parse(data: &'a [u8]) -> Result<Self, HypervisorError> {
+        let mut superblock = ByteReader::new(data, || HypervisorError::InvalidPdb);
+
+        if superblock.bytes(MSF_MAGIC.len())? != MSF_MAGIC {
+            return Err(HypervisorError::InvalidPdb);
+        }
+
+        let block_size = superblock.u32()? as usize;
+        let _free_block_map_block = superblock.u32()?;
+        let number_of_blocks = superblock.u32()? as usize;
+        let directory_size = superblock.u32()? as usize;
+        let _unknown = superblock.u32()?;
+        let block_map_address = superblock.u32()? as usize;
+
+        if !matches!(block_size, 512 | 1024 | 2048 | 4096)
+            || number_of_blocks
+                .checked_mul(block_size)
+                .is_none_or(|size| data.len() < size)
+        {
+            return Err(HypervisorError::InvalidPdb);
+        }
+
+        // The block map lists the blocks of the stream directory.
+        let mut block_map = ByteReader::new(data, || HypervisorError::InvalidPdb);
+        block_map.seek(
+            block_map_address
+                .checked_mul(block_size)
+                .ok_or(HypervisorError::InvalidPdb)?,
+        )?;
+
+        let directory_blocks = (0..directory_size.div_ceil(block_size))
+            .map(|_| block_map.u32())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let directory = Self::read_blocks(data, block_size, &directory_blocks, directory_size)?;
+        let mut directory = ByteReader::new(&directory, || HypervisorError::InvalidPdb);
+
+        let number_of_streams = directory.u32()? as usize;
+        let sizes = (0..number_of_streams)
+            .map(|_| directory.u32())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut streams = Vec::with_capacity(number_of_streams);
+        for size in sizes {
+            if size == NIL_STREAM_SIZE {
+                streams.push(None);
+                continue;
+            }
+
+            let blocks = (0..(size as usize).div_ceil(block_size))
+                .map(|_| directory.u32())
+                .collect::<Result<Vec<_>, _>>()?;
+
+            streams.push(Some((size, blocks)));
+        }
+
+        Ok(Self {
+            data,
+            block_size,
+            streams,
+        })
+    }
+
+    /// Returns the number of the streams.
+    pub fn stream_count(&self) -> usize {
+        self.streams.len()
+    }
+
+    /// Reads a stream.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The index of the stream.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<u8>, HypervisorError>` - The data of the stream, or an error if the stream doesn't exist.
+    pub fn stream(&self, index: usize) -> Result<Vec<u8>, HypervisorError> {
+        let (size, blocks) = self
+            .streams
+            .get(index)
+            .and_then(Option::as_ref)
+            .ok_or(HypervisorError::InvalidPdb)?;
+
+        Self::read_blocks(self.data, self.block_size, blocks, *size as usize)
+    }
+
+    /// Concatenates blocks of the file, truncated to `size` bytes.
+    fn read_blocks(
+        data: &[u8],
+        block_size: usize,
+        blocks: &[u32],
+        size: usize,
+    ) -> Result<Vec<u8>, HypervisorError> {
+        let mut stream = Vec::with_capacity(blocks.len() * block_size);
+
+        for block in blocks {
+            let start = *block as usize * block_size;
+            let block = start
+                .checked_add(block_size)
+                .and_then(|end| data.get(start..end))
+                .ok_or(HypervisorError::InvalidPdb)?;
+
+            stream.extend_from_slice(block);
+        }
+
+        stream.truncate(size);
+        Ok(stream)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {super::*, alloc::vec};
+
+    /// The block size of the files built by `build_msf`.
+    pub(crate) const BLOCK_SIZE: usize = 512;
+
+    /// Builds an MSF file with the given streams, or `None` for streams that don't exist.
+    ///
+    /// Block 0 contains the superblock and block 1 the block map. They are followed by the blocks of the
+    /// stream directory and the blocks of the streams, in order.
+    pub(crate) fn build_msf(streams: &[Option<&[u8]>]) -> Vec<u8> {
+        let stream_blocks = streams
+            .iter()
+            .flatten()
+            .map(|stream| stream.len().div_ceil(BLOCK_SIZE))
+            .sum::<usize>();
+
+        let mut directory = Vec::new();
+        directory.extend_from_slice(&(streams.len() as u32).to_le_bytes());
+        for stream in streams {
+            let size = stream.map_or(NIL_STREAM_SIZE, |stream| stream.len() as u32);
+            directory.extend_from_slice(&size.to_le_bytes());
+        }
+
+        let directory_blocks = (directory.len() + stream_blocks * 4).div_ceil(BLOCK_SIZE);
+        let mut next_block = 2 + directory_blocks;
+        let mut blocks = Vec::new();
+
+        for stream in streams.iter().flatten() {
+            for chunk in stream.chunks(BLOCK_SIZE) {
+                directory.extend_from_slice(&(next_block as u32).to_le_bytes());
+                blocks.push((next_block, chunk));
+                next_block += 1;
+            }
+        }
+
+        let mut file = vec![0u8; next_block * BLOCK_SIZE];
+        file[..MSF_MAGIC.len()].copy_from_slice(MSF_MAGIC);
+
+        let superblock = [
+            BLOCK_SIZE as u32,
+            1,
+            next_block as u32,
+            directory.len() as u32,
+            0,
+            1,
+        ];
+        for (index, value) in superblock.iter().enumerate() {
+            let offset = MSF_MAGIC.len() + index * 4;
+            file[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+
+        for index in 0..directory_blocks {
+            let block = 2 + index;
+            let offset = BLOCK_SIZE + index * 4;
+            file[offset..offset + 4].copy_from_slice(&(block as u32).to_le_bytes());
+
+            let chunk = directory.chunks(BLOCK_SIZE).nth(index).unwrap();
+            file[block * BLOCK_SIZE..block * BLOCK_SIZE + chunk.len()].copy_from_slice(chunk);
+        }
+
+        for (block, chunk) in blocks {
+            file[block * BLOCK_SIZE..block * BLOCK_SIZE + chunk.len()].copy_from_slice(chunk);
+        }
+
+        file
+    }
+
+    /// Writes a little-endian `u32` at an offset of a file.
+    fn write_u32(file: &mut [u8], offset: usize, value: u32) {
+        file[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    #[test]
+    fn streams_are_read_across_blocks() {
+        let large = (0..1300).map(|index| index as u8).collect::<Vec<_>>();
+        let file = build_msf(&[Some(b"first"), None, Some(&large), Some(&[])]);
+        let msf = Msf::parse(&file).unwrap();
+
+        assert_eq!(msf.stream_count(), 4);
+        assert_eq!(msf.stream(0).unwrap(), b"first");
+        assert!(matches!(msf.stream(1), Err(HypervisorError::InvalidPdb)));
+        assert_eq!(msf.stream(2).unwrap(), large);
+        assert!(msf.stream(3).unwrap().is_empty());
+        assert!(matches!(msf.stream(4), Err(HypervisorError::InvalidPdb)));
+    }
+
+    #[test]
+    fn invalid_superblocks_are_rejected() {
+        let file = build_msf(&[Some(b"stream")]);
+
+        let mut invalid = file.clone();
+        invalid[0] = b'm';
+        assert!(matches!(
+            Msf::parse(&invalid),
+            Err(HypervisorError::InvalidPdb)
+        ));
+
+        let mut invalid = file.clone();
+        write_u32(&mut invalid, MSF_MAGIC.len(), 1000);
+        assert!(matches!(
+            Msf::parse(&invalid),
+            Err(HypervisorError::InvalidPdb)
+        ));
+
+        let mut invalid = file.clone();
+        write_u32(&mut invalid, MSF_MAGIC.len() + 8, u32::MAX);
+        assert!(matches!(
+            Msf::parse(&invalid),
+            Err(HypervisorError::InvalidPdb)
+        ));
+
+        let mut invalid = file.clone();
+        write_u32(&mut invalid, MSF_MAGIC.len() + 20, u32::MAX);
+        assert!(matches!(
+            Msf::parse(&invalid),
+            Err(HypervisorError::InvalidPdb)
+        ));
+
+        assert!(matches!(
+            Msf::parse(&file[..MSF_MAGIC.len() + 8]),
+            Err(HypervisorError::InvalidPdb)
+        ));
+    }
+
+    #[test]
+    fn blocks_outside_the_file_are_rejected() {
+        let mut file = build_msf(&[Some(b"stream")]);
+
+        // The directory is in block 2: the number of streams, the size and the block of the stream.
+        write_u32(&mut file, 2 * BLOCK_SIZE + 8, u32::MAX);
+        let msf = Msf::parse(&file).unwrap();
+
+        assert!(matches!(msf.stream(0), Err(HypervisorError::InvalidPdb)));
+    }
+
+    #[test]
+    fn truncated_directories_are_rejected() {
+        let mut file = build_msf(&[Some(b"stream")]);
+
+        // Claim more streams than the directory lists.
+        write_u32(&mut file, 2 * BLOCK_SIZE, 1000);
+
+        assert!(matches!(
+            Msf::parse(&file),
+            Err(HypervisorError::InvalidPdb)
+        ));
+    }
+}
@@ -0,0 +1,536 @@
+//! Provides a reader for the type information (TPI) stream of a PDB file.
+//!
+//! The TPI stream contains the CodeView type records. Structures (`LF_STRUCTURE`, `LF_CLASS`, `LF_UNION`)
+//! reference a field list (`LF_FIELDLIST`), whose members (`LF_MEMBER`) contain the offsets of the fields.
+//! Bitfields are members whose type is an `LF_BITFIELD` record.
+
+use {
+    crate::{
+        error::HypervisorError,
+        utils::{
+            bytes::ByteReader,
+            symbols::{Field, Struct},
+        },
+    },
+    alloc::{string::String, vec::Vec},
+};
+
+/// The kind of a class type record.
+const LF_CLASS: u16 = 0x1504;
+
+/// The kind of a structure type record.
+const LF_STRUCTURE: u16 = 0x1505;
+
+/// The kind of a union type record.
+const LF_UNION: u16 = 0x1506;
+
+/// The kind of a field list type record.
+const LF_FIELDLIST: u16 = 0x1203;
+
+/// The kind of a bitfield type record.
+const LF_BITFIELD: u16 = 0x1205;
+
+/// The kinds of the members of a field list.
+const LF_BCLASS: u16 = 0x1400;
+const LF_VBCLASS: u16 = 0x1401;
+const LF_IVBCLASS: u16 = 0x1402;
+const LF_INDEX: u16 = 0x1404;
+const LF_VFUNCTAB: u16 = 0x1409;
+const LF_ENUMERATE: u16 = 0x1502;
+const LF_MEMBER: u16 = 0x150D;
+const LF_STMEMBER: u16 = 0x150E;
+const LF_METHOD: u16 = 0x150F;
+const LF_NESTTYPE: u16 = 0x1510;
+const LF_ONEMETHOD: u16 = 0x1511;
+
+/// The kinds of numeric leaves, which follow the value when it doesn't fit in 15 bits.
+const LF_NUMERIC: u16 = 0x8000;
+const LF_CHAR: u16 = 0x8000;
+const LF_SHORT: u16 = 0x8001;
+const LF_USHORT: u16 = 0x8002;
+const LF_LONG: u16 = 0x8003;
+const LF_ULONG: u16 = 0x8004;
+const LF_QUADWORD: u16 = 0x8009;
+const LF_UQUADWORD: u16 = 0x800A;
+
+/// The property of a type record that is only a forward reference to the definition.
+const PROPERTY_FORWARD_REFERENCE: u16 = 0x80;
+
+/// The first byte of padding between the members of a field list (`LF_PAD0`).
+const LF_PAD0: u8 = 0xF0;
+
+/// Maximum number of field lists chained with `LF_INDEX`.
+const MAX_FIELD_LIST_CHAIN: usize = 64;
+
+/// The type records of the TPI stream.
+#[derive(Debug, Clone)]
+pub struct TypeInfo<'a> {
+    /// The data of the TPI stream.
+    data: &'a [u8],
+
+    /// The type index of the first record.
+    first_index: u32,
+
+    /// The offsets of the records in `data`, indexed by type index minus `first_index`.
+    offsets: Vec<usize>,
+}
+
+impl<'a> TypeInfo<'a> {
+    /// Parses the header of the TPI stream and indexes the type records.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The data of the TPI stream (stream 2).
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, HypervisorError>` - The type records, or an error if the stream is invalid.
+    pub fn parse(data: &'a [u8]) -> Result<Self, HypervisorError> {
+        let mut reader = ByteReader::new(data, || HypervisorError::InvalidPdb);
+
+        let _version = reader.u32()?;
+        let header_size = reader.u32()? as usize;
+        let first_index = reader.u32()?;
+        let end_index = reader.u32()?;
+        let records_size = reader.u32()? as usize;
+
+        let count = end_index
+            .checked_sub(first_index)
+            .ok_or(HypervisorError::InvalidPdb)? as usize;
+
+        reader.seek(header_size)?;
+        let end = header_size
+            .checked_add(records_size)
+            .ok_or(HypervisorError::InvalidPdb)?;
+        let mut offsets = Vec::with_capacity(count);
+
+        while offsets.len() < count && reader.position() < end {
+            offsets.push(reader.position());
+
+            // The length doesn't include the length field itself.
+            let length = reader.u16()? as usize;
+            reader.skip(length)?;
+        }
+
+        Ok(Self {
+            data,
+            first_index,
+            offsets,
+        })
+    }
+
+    /// Returns the kind and a reader over the data of a type record.
+    fn record(&self, index: u32) -> Result<(u16, ByteReader<'a>), HypervisorError> {
+        let offset = index
+            .checked_sub(self.first_index)
+            .and_then(|index| self.offsets.get(index as usize))
+            .ok_or(HypervisorError::InvalidPdb)?;
+
+        let mut reader = ByteReader::new(self.data, || HypervisorError::InvalidPdb);
+        reader.seek(*offset)?;
+
+        let length = reader.u16()? as usize;
+        let record = reader.bytes(length)?;
+
+        let mut reader = ByteReader::new(record, || HypervisorError::InvalidPdb);
+        let kind = reader.u16()?;
+
+        Ok((kind, reader))
+    }
+
+    /// Finds the definition of a structure, class or union by its name and returns its layout.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the type, for example `_EPROCESS`.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<Struct>, HypervisorError>` - The layout, `None` if the PDB doesn't define the type, or an error if a record is invalid.
+    pub fn find_struct(&self, name: &str) -> Result<Option<Struct>, HypervisorError> {
+        for offset in 0..self.offsets.len() {
+            let (kind, mut reader) = self.record(self.first_index + offset as u32)?;
+
+            if !matches!(kind, LF_CLASS | LF_STRUCTURE | LF_UNION) {
+                continue;
+            }
+
+            let _count = reader.u16()?;
+            let property = reader.u16()?;
+            let field_list = reader.u32()?;
+
+            if kind != LF_UNION {
+                let _derived = reader.u32()?;
+                let _vtable_shape = reader.u32()?;
+            }
+
+            let size = numeric(&mut reader)?;
+
+            if property & PROPERTY_FORWARD_REFERENCE != 0 || reader.c_str()? != name {
+                continue;
+            }
+
+            return Ok(Some(Struct {
+                size: size as u32,
+                fields: self.fields(field_list)?,
+            }));
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the data members of a field list, following continuations.
+    fn fields(&self, mut field_list: u32) -> Result<Vec<Field>, HypervisorError> {
+        let mut fields = Vec::new();
+
+        for _ in 0..MAX_FIELD_LIST_CHAIN {
+            let (kind, mut reader) = self.record(field_list)?;
+            if kind != LF_FIELDLIST {
+                return Err(HypervisorError::InvalidPdb);
+            }
+
+            let mut continuation = None;
+
+            while !reader.is_empty() {
+                match reader.u16()? {
+                    LF_MEMBER => {
+                        let _attributes = reader.u16()?;
+                        let member_type = reader.u32()?;
+                        let offset = numeric(&mut reader)? as u32;
+                        let name = String::from(reader.c_str()?);
+
+                        fields.push(Field {
+                            name,
+                            offset,
+                            bitfield: self.bitfield(member_type)?,
+                        });
+                    }
+                    LF_BCLASS => {
+                        reader.skip(6)?;
+                        numeric(&mut reader)?;
+                    }
+                    LF_VBCLASS | LF_IVBCLASS => {
+                        reader.skip(10)?;
+                        numeric(&mut reader)?;
+                        numeric(&mut reader)?;
+                    }
+                    LF_ENUMERATE => {
+                        reader.skip(2)?;
+                        numeric(&mut reader)?;
+                        reader.c_str()?;
+                    }
+                    LF_STMEMBER | LF_NESTTYPE => {
+                        reader.skip(6)?;
+                        reader.c_str()?;
+                    }
+                    LF_METHOD => {
+                        reader.skip(6)?;
+                        reader.c_str()?;
+                    }
+                    LF_ONEMETHOD => {
+                        let attributes = reader.u16()?;
+                        reader.skip(4)?;
+
+                        // Introducing virtual methods have the offset in the virtual function table.
+                        if matches!((attributes >> 2) & 0x7, 4 | 6) {
+                            reader.skip(4)?;
+                        }
+
+                        reader.c_str()?;
+                    }
+                    LF_VFUNCTAB => reader.skip(6)?,
+                    LF_INDEX => {
+                        reader.skip(2)?;
+                        continuation = Some(reader.u32()?);
+                    }
+                    kind => {
+                        log::debug!("Unsupported field list member {:#x}", kind);
+                        return Ok(fields);
+                    }
+                }
+
+                skip_padding(&mut reader)?;
+            }
+
+            match continuation {
+                Some(next) => field_list = next,
+                None => return Ok(fields),
+            }
+        }
+
+        Err(HypervisorError::InvalidPdb)
+    }
+
+    /// Returns the bit position and length of a member type, if it's a bitfield.
+    fn bitfield(&self, member_type: u32) -> Result<Option<(u8, u8)>, HypervisorError> {
+        // Indices below the first index are simple types, which are never bitfields.
+        if member_type < self.first_index {
+            return Ok(None);
+        }
+
+        let (kind, mut reader) = self.record(member_type)?;
+        if kind != LF_BITFIELD {
+            return Ok(None);
+        }
+
+        let _base_type = reader.u32()?;
+        let length = reader.u8()?;
+        let position = reader.u8()?;
+
+        Ok(Some((position, length)))
+    }
+}
+
+/// Reads a numeric leaf, which is either a 15-bit value or a leaf kind followed by the value.
+fn numeric(reader: &mut ByteReader) -> Result<u64, HypervisorError> {
+    let value = reader.u16()?;
+
+    if value < LF_NUMERIC {
+        return Ok(value as u64);
+    }
+
+    Ok(match value {
+        LF_CHAR => reader.u8()? as i8 as u64,
+        LF_SHORT => reader.u16()? as i16 as u64,
+        LF_USHORT => reader.u16()? as u64,
+        LF_LONG => reader.u32()? as i32 as u64,
+        LF_ULONG => reader.u32()? as u64,
+        LF_QUADWORD | LF_UQUADWORD => reader.u64()?,
+        _ => return Err(HypervisorError::InvalidPdb),
+    })
+}
+
+/// Skips the padding bytes between the members of a field list. The low nibble of the first padding
+/// byte is the number of bytes to skip, including itself.
+fn skip_padding(reader: &mut ByteReader) -> Result<(), HypervisorError> {
+    let mut peek = reader.clone();
+
+    match peek.u8() {
+        Ok(byte) if byte > LF_PAD0 => reader.skip((byte & 0xF) as usize),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {super::*, alloc::vec};
+
+    /// The size of the TPI stream header.
+    const TPI_HEADER_SIZE: u32 = 56;
+
+    /// The type index of the first record of the streams built by `build_tpi`.
+    pub(crate) const FIRST_INDEX: u32 = 0x1000;
+
+    /// The simple type index of `unsigned long`.
+    const T_ULONG: u32 = 0x22;
+
+    /// Builds a TPI stream from type records built by `type_record`.
+    pub(crate) fn build_tpi(records: &[Vec<u8>]) -> Vec<u8> {
+        let count = records.len() as u32;
+        let records = records.concat();
+
+        let mut tpi = Vec::new();
+        for value in [
+            20040203,
+            TPI_HEADER_SIZE,
+            FIRST_INDEX,
+            FIRST_INDEX + count,
+            records.len() as u32,
+        ] {
+            tpi.extend_from_slice(&value.to_le_bytes());
+        }
+        tpi.resize(TPI_HEADER_SIZE as usize, 0);
+        tpi.extend_from_slice(&records);
+        tpi
+    }
+
+    /// Builds a type record of a kind.
+    pub(crate) fn type_record(kind: u16, data: &[u8]) -> Vec<u8> {
+        let mut record = ((data.len() + 2) as u16).to_le_bytes().to_vec();
+        record.extend_from_slice(&kind.to_le_bytes());
+        record.extend_from_slice(data);
+        record
+    }
+
+    /// Builds an `LF_STRUCTURE` record.
+    pub(crate) fn structure(name: &str, field_list: u32, size: u16, property: u16) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&1_u16.to_le_bytes());
+        data.extend_from_slice(&property.to_le_bytes());
+        data.extend_from_slice(&field_list.to_le_bytes());
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&size.to_le_bytes());
+        data.extend_from_slice(name.as_bytes());
+        data.push(0);
+        type_record(LF_STRUCTURE, &data)
+    }
+
+    /// Builds an `LF_MEMBER` member of a field list, followed by the padding to 4 bytes.
+    pub(crate) fn member(name: &str, member_type: u32, offset: &[u8]) -> Vec<u8> {
+        let mut data = LF_MEMBER.to_le_bytes().to_vec();
+        data.extend_from_slice(&3_u16.to_le_bytes());
+        data.extend_from_slice(&member_type.to_le_bytes());
+        data.extend_from_slice(offset);
+        data.extend_from_slice(name.as_bytes());
+        data.push(0);
+
+        let padding = data.len().next_multiple_of(4) - data.len();
+        data.extend((0..padding).rev().map(|index| LF_PAD0 + index as u8 + 1));
+        data
+    }
+
+    /// Encodes a numeric leaf that fits in 15 bits.
+    pub(crate) fn small(value: u16) -> Vec<u8> {
+        value.to_le_bytes().to_vec()
+    }
+
+    /// Builds the records of `_KTHREAD`, preceded by a forward reference:
+    ///
+    /// - 0x1000: the forward reference.
+    /// - 0x1001: `LF_BITFIELD` of 3 bits at position 5.
+    /// - 0x1002: the continuation of the field list, with `Process`.
+    /// - 0x1003: the field list, with `Header`, the bitfield `Alerted` and `StackBase`.
+    /// - 0x1004: the definition.
+    fn kthread() -> Vec<Vec<u8>> {
+        let mut bitfield = T_ULONG.to_le_bytes().to_vec();
+        bitfield.extend_from_slice(&[3, 5]);
+
+        let continuation = member("Process", T_ULONG, &{
+            let mut offset = LF_USHORT.to_le_bytes().to_vec();
+            offset.extend_from_slice(&0x9000_u16.to_le_bytes());
+            offset
+        });
+
+        let mut field_list = member("Header", T_ULONG, &small(0));
+        field_list.extend(member("Alerted", FIRST_INDEX + 1, &small(0x18)));
+        field_list.extend(member("StackBase", T_ULONG, &small(0x38)));
+        field_list.extend_from_slice(&LF_INDEX.to_le_bytes());
+        field_list.extend_from_slice(&[0, 0]);
+        field_list.extend_from_slice(&(FIRST_INDEX + 2).to_le_bytes());
+
+        vec![
+            structure("_KTHREAD", 0, 0, PROPERTY_FORWARD_REFERENCE),
+            type_record(LF_BITFIELD, &bitfield),
+            type_record(LF_FIELDLIST, &continuation),
+            type_record(LF_FIELDLIST, &field_list),
+            structure("_KTHREAD", FIRST_INDEX + 3, 0x430, 0),
+        ]
+    }
+
+    #[test]
+    fn structures_are_found_by_name() {
+        let tpi = build_tpi(&kthread());
+        let types = TypeInfo::parse(&tpi).unwrap();
+
+        let layout = types.find_struct("_KTHREAD").unwrap().unwrap();
+        assert_eq!(layout.size, 0x430);
+        assert_eq!(
+            layout.fields,
+            [
+                Field {
+                    name: String::from("Header"),
+                    offset: 0,
+                    bitfield: None,
+                },
+                Field {
+                    name: String::from("Alerted"),
+                    offset: 0x18,
+                    bitfield: Some((5, 3)),
+                },
+                Field {
+                    name: String::from("StackBase"),
+                    offset: 0x38,
+                    bitfield: None,
+                },
+                Field {
+                    name: String::from("Process"),
+                    offset: 0x9000,
+                    bitfield: None,
+                },
+            ]
+        );
+
+        assert_eq!(types.find_struct("_EPROCESS").unwrap(), None);
+    }
+
+    #[test]
+    fn numeric_leaves_are_decoded() {
+        let leaves: [(&[u8], u64); 5] = [
+            (&[0x34, 0x12], 0x1234),
+            (&[0x00, 0x80, 0xFF], u64::MAX),
+            (&[0x02, 0x80, 0xFF, 0xFF], 0xFFFF),
+            (&[0x04, 0x80, 0x00, 0x00, 0x01, 0x00], 0x1_0000),
+            (&[0x0A, 0x80, 1, 0, 0, 0, 1, 0, 0, 0], 0x1_0000_0001),
+        ];
+
+        for (bytes, value) in leaves {
+            assert_eq!(
+                numeric(&mut ByteReader::new(bytes, || HypervisorError::InvalidPdb)).unwrap(),
+                value
+            );
+        }
+
+        assert!(matches!(
+            numeric(&mut ByteReader::new(&[0x10, 0x80], || {
+                HypervisorError::InvalidPdb
+            })),
+            Err(HypervisorError::InvalidPdb)
+        ));
+    }
+
+    #[test]
+    fn field_lists_must_be_field_lists() {
+        let records = vec![
+            type_record(LF_BITFIELD, &[0x22, 0, 0, 0, 1, 0]),
+            structure("_KTHREAD", FIRST_INDEX, 0x430, 0),
+        ];
+        let tpi = build_tpi(&records);
+
+        assert!(matches!(
+            TypeInfo::parse(&tpi).unwrap().find_struct("_KTHREAD"),
+            Err(HypervisorError::InvalidPdb)
+        ));
+    }
+
+    #[test]
+    fn cyclic_field_lists_are_rejected() {
+        let mut field_list = LF_INDEX.to_le_bytes().to_vec();
+        field_list.extend_from_slice(&[0, 0]);
+        field_list.extend_from_slice(&FIRST_INDEX.to_le_bytes());
+
+        let records = vec![
+            type_record(LF_FIELDLIST, &field_list),
+            structure("_KTHREAD", FIRST_INDEX, 0x430, 0),
+        ];
+        let tpi = build_tpi(&records);
+
+        assert!(matches!(
+            TypeInfo::parse(&tpi).unwrap().find_struct("_KTHREAD"),
+            Err(HypervisorError::InvalidPdb)
+        ));
+    }
+
+    #[test]
+    fn invalid_headers_are_rejected() {
+        let mut tpi = build_tpi(&kthread());
+        tpi[12..16].copy_from_slice(&0_u32.to_le_bytes());
+        assert!(matches!(
+            TypeInfo::parse(&tpi),
+            Err(HypervisorError::InvalidPdb)
+        ));
+
+        let mut tpi = build_tpi(&kthread());
+        tpi[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            TypeInfo::parse(&tpi),
+            Err(HypervisorError::InvalidPdb)
+        ));
+
+        let mut tpi = build_tpi(&kthread());
+        tpi.truncate(tpi.len() - 4);
+        assert!(matches!(
+            TypeInfo::parse(&tpi),
+            Err(HypervisorError::InvalidPdb)
+        ));
+    }
+}
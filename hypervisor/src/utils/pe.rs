@@ -0,0 +1,1653 @@
+//! Provides a parser for PE32+ images, such as the kernel modules loaded in memory.
+//!
+//! The parser covers the DOS and NT headers, the section table, the export directory (including
+//! forwarded exports), the import directory, the exception directory with the unwind information
+//! and the base relocations. The exception directory gives the boundaries of all non-leaf functions,
+//! which allows to verify that an address is the start of a function before hooking it.
+//! It only operates on a byte slice, so it doesn't depend on the kernel and can parse both images
+//! mapped in memory and PE files read from disk.
+//!
+//! Malformed images are reported with `HypervisorError::InvalidPeImage`. The iterators stop at the
+//! first entry that is out of bounds.
+
+use {
+    crate::error::HypervisorError,
+    core::{mem::size_of, str},
+};
+
+/// The signature of the DOS header (`MZ`).
+const DOS_SIGNATURE: u16 = 0x5A4D;
+
+/// The signature of the NT headers (`PE\0\0`).
+const NT_SIGNATURE: u32 = 0x0000_4550;
+
+/// The magic of the PE32+ optional header.
+const OPTIONAL_HEADER_MAGIC_PE32_PLUS: u16 = 0x20B;
+
+/// Offset of `e_lfanew` in the DOS header.
+const DOS_E_LFANEW_OFFSET: usize = 0x3C;
+
+/// Offset of the file header in the NT headers (after the signature).
+const FILE_HEADER_OFFSET: usize = 0x4;
+
+/// Offset of the optional header in the NT headers (after the signature and the file header).
+const OPTIONAL_HEADER_OFFSET: usize = 0x18;
+
+/// Offset of the data directories in the PE32+ optional header.
+const DATA_DIRECTORIES_OFFSET: usize = 0x70;
+
+/// Size of a section header (`IMAGE_SECTION_HEADER`).
+const SECTION_HEADER_SIZE: usize = 40;
+
+/// Size of an import descriptor (`IMAGE_IMPORT_DESCRIPTOR`).
+const IMPORT_DESCRIPTOR_SIZE: usize = 20;
+
+/// Size of a runtime function entry (`RUNTIME_FUNCTION`).
+const RUNTIME_FUNCTION_SIZE: usize = 12;
+
+/// Size of the header of a base relocation block (`IMAGE_BASE_RELOCATION`).
+const RELOCATION_BLOCK_HEADER_SIZE: usize = 8;
+
+/// The flag of an import thunk that imports by ordinal.
+const IMPORT_BY_ORDINAL_FLAG: u64 = 1 << 63;
+
+/// The section characteristic of executable sections (`IMAGE_SCN_MEM_EXECUTE`).
+const SECTION_MEM_EXECUTE: u32 = 0x2000_0000;
+
+/// The flag of unwind information that is chained to the unwind information of the primary function (`UNW_FLAG_CHAININFO`).
+const UNWIND_FLAG_CHAIN_INFO: u8 = 0x4;
+
+/// Size of the header of the unwind information (`UNWIND_INFO` without the unwind codes).
+const UNWIND_INFO_HEADER_SIZE: u32 = 4;
+
+/// Maximum number of chained unwind information followed to the primary function.
+const MAX_UNWIND_CHAIN_DEPTH: usize = 32;
+
+/// Size of a debug directory entry (`IMAGE_DEBUG_DIRECTORY`).
+const DEBUG_DIRECTORY_ENTRY_SIZE: usize = 28;
+
+/// The type of a debug directory entry that contains CodeView information (`IMAGE_DEBUG_TYPE_CODEVIEW`).
+const DEBUG_TYPE_CODEVIEW: u32 = 2;
+
+/// The signature of a CodeView PDB 7.0 record.
+const CODEVIEW_RSDS_SIGNATURE: &[u8; 4] = b"RSDS";
+
+/// The layout of the image bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// The image is mapped with its section alignment, as loaded in memory. RVAs are offsets.
+    Mapped,
+
+    /// The image is laid out as a PE file on disk. RVAs are translated through the section table.
+    File,
+}
+
+/// The data directories of the optional header.
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataDirectory {
+    Export = 0,
+    Import = 1,
+    Resource = 2,
+    Exception = 3,
+    Security = 4,
+    BaseRelocation = 5,
+    Debug = 6,
+    Architecture = 7,
+    GlobalPointer = 8,
+    Tls = 9,
+    LoadConfig = 10,
+    BoundImport = 11,
+    ImportAddressTable = 12,
+    DelayImport = 13,
+    ComDescriptor = 14,
+}
+
+/// A parsed PE32+ image.
+#[derive(Debug, Clone, Copy)]
+pub struct PeImage<'a> {
+    /// The bytes of the image.
+    data: &'a [u8],
+
+    /// The layout of the bytes.
+    layout: Layout,
+
+    /// Offset of the NT headers.
+    nt_headers: usize,
+
+    /// Offset of the section table.
+    section_table: usize,
+
+    /// Number of the sections.
+    number_of_sections: usize,
+}
+
+impl<'a> PeImage<'a> {
+    /// Parses an image mapped with its section alignment, as loaded in memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The bytes of the image.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, HypervisorError>` - The parsed image, or an error if the headers are invalid.
+    pub fn parse(data: &'a [u8]) -> Result<Self, HypervisorError> {
+        Self::parse_with_layout(data, Layout::Mapped)
+    }
+
+    /// Parses a PE file as stored on disk.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The bytes of the file.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, HypervisorError>` - The parsed image, or an error if the headers are invalid.
+    pub fn parse_file(data: &'a [u8]) -> Result<Self, HypervisorError> {
+        Self::parse_with_layout(data, Layout::File)
+    }
+
+    /// Parses an image loaded in memory, for example a kernel module found with `Sysinfo::get_module_base`.
+    ///
+    /// # Arguments
+    ///
+    /// * `base` - The base address of the image.
+    /// * `size` - The size of the image.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, HypervisorError>` - The parsed image, or an error if the headers are invalid.
+    ///
+    /// # Safety
+    ///
+    /// The whole image must be mapped and readable for the lifetime `'a`. Note that discardable
+    /// sections (such as `INIT`) of loaded drivers are unmapped after initialization.
+    pub unsafe fn from_base(base: *const u8, size: usize) -> Result<Self, HypervisorError> {
+        Self::parse(core::slice::from_raw_parts(base, size))
+    }
+
+    /// Parses the headers of an image with the given layout.
+    fn parse_with_layout(data: &'a [u8], layout: Layout) -> Result<Self, HypervisorError> {
+        if read_u16(data, 0)? != DOS_SIGNATURE {
+            return Err(HypervisorError::InvalidPeImage);
+        }
+
+        let nt_headers = read_u32(data, DOS_E_LFANEW_OFFSET)? as usize;
+        if read_u32(data, nt_headers)? != NT_SIGNATURE {
+            return Err(HypervisorError::InvalidPeImage);
+        }
+
+        let optional_header = nt_headers + OPTIONAL_HEADER_OFFSET;
+        if read_u16(data, optional_header)? != OPTIONAL_HEADER_MAGIC_PE32_PLUS {
+            return Err(HypervisorError::InvalidPeImage);
+        }
+
+        let file_header = nt_headers + FILE_HEADER_OFFSET;
+        let number_of_sections = read_u16(data, file_header + 0x2)? as usize;
+        let size_of_optional_header = read_u16(data, file_header + 0x10)? as usize;
+        let section_table = optional_header + size_of_optional_header;
+
+        // The section table must be within the headers.
+        data.get(section_table..section_table + number_of_sections * SECTION_HEADER_SIZE)
+            .ok_or(HypervisorError::InvalidPeImage)?;
+
+        Ok(Self {
+            data,
+            layout,
+            nt_headers,
+            section_table,
+            number_of_sections,
+        })
+    }
+
+    /// Returns the bytes of the image.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Returns the layout of the image bytes.
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Returns the machine type of the file header.
+    pub fn machine(&self) -> u16 {
+        self.header_u16(self.nt_headers + FILE_HEADER_OFFSET)
+    }
+
+    /// Returns the time stamp of the file header.
+    pub fn time_date_stamp(&self) -> u32 {
+        self.header_u32(self.nt_headers + FILE_HEADER_OFFSET + 0x4)
+    }
+
+    /// Returns the RVA of the entry point.
+    pub fn entry_point(&self) -> u32 {
+        self.header_u32(self.optional_header() + 0x10)
+    }
+
+    /// Returns the preferred base address of the image.
+    pub fn image_base(&self) -> u64 {
+        read_u64(self.data, self.optional_header() + 0x18).unwrap_or_default()
+    }
+
+    /// Returns the size of the image in memory.
+    pub fn size_of_image(&self) -> u32 {
+        self.header_u32(self.optional_header() + 0x38)
+    }
+
+    /// Returns the size of the headers.
+    pub fn size_of_headers(&self) -> u32 {
+        self.header_u32(self.optional_header() + 0x3C)
+    }
+
+    /// Returns the RVA and size of a data directory, or `None` if the image doesn't have it.
+    pub fn data_directory(&self, directory: DataDirectory) -> Option<(u32, u32)> {
+        let optional_header = self.optional_header();
+        let number_of_directories = read_u32(self.data, optional_header + 0x6C).ok()? as usize;
+
+        if directory as usize >= number_of_directories {
+            return None;
+        }
+
+        let offset = optional_header + DATA_DIRECTORIES_OFFSET + directory as usize * 8;
+        let rva = read_u32(self.data, offset).ok()?;
+        let size = read_u32(self.data, offset + 4).ok()?;
+
+        (rva != 0 && size != 0).then_some((rva, size))
+    }
+
+    /// Returns an iterator over the sections.
+    pub fn sections(&self) -> impl Iterator<Item = Section<'a>> + '_ {
+        (0..self.number_of_sections).map(|index| self.section(index))
+    }
+
+    /// Returns the section with the given name, for example `.text` or `PAGE`.
+    pub fn section_by_name(&self, name: &str) -> Option<Section<'a>> {
+        self.sections()
+            .find(|section| section.name == name.as_bytes())
+    }
+
+    /// Returns the section containing an RVA.
+    pub fn section_containing(&self, rva: u32) -> Option<Section<'a>> {
+        self.sections().find(|section| section.contains(rva))
+    }
+
+    /// Returns the bytes of a section, as laid out in the image.
+    pub fn section_data(&self, section: &Section) -> Option<&'a [u8]> {
+        let (start, size) = match self.layout {
+            Layout::Mapped => (section.virtual_address, section.virtual_size),
+            Layout::File => (
+                section.pointer_to_raw_data,
+                section.size_of_raw_data.min(section.virtual_size),
+            ),
+        };
+
+        self.data
+            .get(start as usize..start as usize + size as usize)
+    }
+
+    /// Translates an RVA to an offset in the image bytes.
+    pub fn rva_to_offset(&self, rva: u32) -> Option<usize> {
+        let offset = match self.layout {
+            Layout::Mapped => rva as usize,
+            Layout::File if rva < self.size_of_headers() => rva as usize,
+            Layout::File => {
+                let section = self.section_containing(rva)?;
+                let offset_in_section = rva - section.virtual_address;
+
+                if offset_in_section >= section.size_of_raw_data {
+                    return None;
+                }
+
+                section.pointer_to_raw_data.checked_add(offset_in_section)? as usize
+            }
+        };
+
+        (offset < self.data.len()).then_some(offset)
+    }
+
+    /// Returns the bytes starting at an RVA.
+    pub fn bytes_at(&self, rva: u32) -> Option<&'a [u8]> {
+        self.data.get(self.rva_to_offset(rva)?..)
+    }
+
+    /// Returns the export directory.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Exports<'a>, HypervisorError>` - The export directory, or an error if the image has no exports.
+    pub fn exports(&self) -> Result<Exports<'a>, HypervisorError> {
+        Exports::parse(*self)
+    }
+
+    /// Finds an export by its name.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the export.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Export<'a>, HypervisorError>` - The export, or an error if it wasn't found.
+    pub fn export(&self, name: &str) -> Result<Export<'a>, HypervisorError> {
+        self.exports()?.by_name(name)
+    }
+
+    /// Returns an iterator over the import descriptors, one for each imported module.
+    pub fn imports(&self) -> impl Iterator<Item = ImportDescriptor<'a>> + '_ {
+        let directory = self.data_directory(DataDirectory::Import);
+
+        (0..)
+            .map_while(move |index| {
+                let (rva, _) = directory?;
+                let descriptor = element_rva(rva, index, IMPORT_DESCRIPTOR_SIZE as u32).ok()?;
+                let offset = self.rva_to_offset(descriptor)?;
+
+                ImportDescriptor::parse(*self, offset)
+            })
+            .fuse()
+    }
+
+    /// Returns the entries of the exception directory, which are sorted by their begin address.
+    pub fn runtime_functions(&self) -> RuntimeFunctions<'a> {
+        let entries = self
+            .data_directory(DataDirectory::Exception)
+            .and_then(|(rva, size)| {
+                let offset = self.rva_to_offset(rva)?;
+                self.data.get(offset..offset + size as usize)
+            })
+            .unwrap_or_default();
+
+        RuntimeFunctions { entries }
+    }
+
+    /// Parses the unwind information of an entry of the exception directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `function` - The entry of the exception directory.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<UnwindInfo, HypervisorError>` - The unwind information, or an error if it's out of bounds.
+    pub fn unwind_info(&self, function: &RuntimeFunction) -> Result<UnwindInfo, HypervisorError> {
+        let rva = function.unwind_info_address;
+        let header = self
+            .bytes_at(rva)
+            .and_then(|bytes| bytes.get(..UNWIND_INFO_HEADER_SIZE as usize))
+            .ok_or(HypervisorError::InvalidPeImage)?;
+
+        let flags = header[0] >> 3;
+        let count_of_codes = header[2];
+
+        // The chained entry follows the unwind codes, which are padded to an even count.
+        let chained = if flags & UNWIND_FLAG_CHAIN_INFO != 0 {
+            let offset = UNWIND_INFO_HEADER_SIZE + ((count_of_codes as u32 + 1) & !1) * 2;
+            Some(self.runtime_function_at(rva_add(rva, offset)?)?)
+        } else {
+            None
+        };
+
+        Ok(UnwindInfo {
+            version: header[0] & 0x7,
+            flags,
+            size_of_prolog: header[1],
+            count_of_codes,
+            frame_register: header[3] & 0xF,
+            frame_offset: header[3] >> 4,
+            chained,
+        })
+    }
+
+    /// Finds the function containing an RVA, using the exception directory.
+    ///
+    /// Functions split into several fragments have chained unwind information. In this case the primary
+    /// function, whose entry point is the start of the function, is returned for all the fragments.
+    /// Leaf functions, which don't modify the stack or nonvolatile registers, don't have an entry and
+    /// can't be found.
+    ///
+    /// # Arguments
+    ///
+    /// * `rva` - The RVA of an instruction.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Function>` - The function, or `None` if no entry of the exception directory contains the RVA.
+    pub fn function_containing(&self, rva: u32) -> Option<Function> {
+        let mut entry = self.runtime_functions().find(rva)?;
+
+        for _ in 0..MAX_UNWIND_CHAIN_DEPTH {
+            // Older images chain entries by pointing to the primary entry, marked with the lowest bit.
+            if entry.unwind_info_address & 1 != 0 {
+                entry = self
+                    .runtime_function_at(entry.unwind_info_address & !1)
+                    .ok()?;
+                continue;
+            }
+
+            let unwind_info = self.unwind_info(&entry).ok()?;
+
+            match unwind_info.chained {
+                Some(primary) => entry = primary,
+                None => {
+                    return Some(Function {
+                        begin_address: entry.begin_address,
+                        end_address: entry.end_address,
+                        prologue_size: unwind_info.size_of_prolog,
+                    })
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Reads the `RUNTIME_FUNCTION` at an RVA.
+    fn runtime_function_at(&self, rva: u32) -> Result<RuntimeFunction, HypervisorError> {
+        Ok(RuntimeFunction {
+            begin_address: self.u32_at(rva)?,
+            end_address: self.u32_at(rva_add(rva, 4)?)?,
+            unwind_info_address: self.u32_at(rva_add(rva, 8)?)?,
+        })
+    }
+
+    /// Returns an iterator over the base relocations.
+    pub fn relocations(&self) -> Relocations<'a> {
+        let blocks = self
+            .data_directory(DataDirectory::BaseRelocation)
+            .and_then(|(rva, size)| {
+                let offset = self.rva_to_offset(rva)?;
+                self.data.get(offset..offset + size as usize)
+            })
+            .unwrap_or_default();
+
+        Relocations {
+            blocks,
+            block: &[],
+            page_rva: 0,
+        }
+    }
+
+    /// Returns the CodeView record of the debug directory, which identifies the PDB of the image.
+    pub fn codeview(&self) -> Option<CodeView<'a>> {
+        let (rva, size) = self.data_directory(DataDirectory::Debug)?;
+
+        (0..size / DEBUG_DIRECTORY_ENTRY_SIZE as u32).find_map(|index| {
+            let entry = element_rva(rva, index, DEBUG_DIRECTORY_ENTRY_SIZE as u32).ok()?;
+
+            if self.u32_at(rva_add(entry, 12).ok()?).ok()? != DEBUG_TYPE_CODEVIEW {
+                return None;
+            }
+
+            let size_of_data = self.u32_at(rva_add(entry, 16).ok()?).ok()? as usize;
+            let address_of_raw_data = self.u32_at(rva_add(entry, 20).ok()?).ok()?;
+            let record = self.bytes_at(address_of_raw_data)?.get(..size_of_data)?;
+
+            // The record consists of the signature, the GUID, the age and the path of the PDB.
+            if record.get(..4)? != CODEVIEW_RSDS_SIGNATURE {
+                return None;
+            }
+
+            let path = record.get(24..)?;
+            let path_length = path.iter().position(|byte| *byte == 0)?;
+
+            Some(CodeView {
+                guid: record.get(4..20)?.try_into().ok()?,
+                age: read_u32(record, 20).ok()?,
+                path: str::from_utf8(&path[..path_length]).ok()?,
+            })
+        })
+    }
+
+    /// Reads the null-terminated ASCII string at an RVA.
+    pub fn c_str_at(&self, rva: u32) -> Result<&'a str, HypervisorError> {
+        let bytes = self.bytes_at(rva).ok_or(HypervisorError::InvalidPeImage)?;
+        let length = bytes
+            .iter()
+            .position(|byte| *byte == 0)
+            .ok_or(HypervisorError::InvalidPeImage)?;
+
+        str::from_utf8(&bytes[..length]).map_err(|_| HypervisorError::InvalidPeImage)
+    }
+
+    /// Reads a little-endian `u16` at an RVA.
+    pub fn u16_at(&self, rva: u32) -> Result<u16, HypervisorError> {
+        read_u16(self.data, self.rva_offset(rva)?)
+    }
+
+    /// Reads a little-endian `u32` at an RVA.
+    pub fn u32_at(&self, rva: u32) -> Result<u32, HypervisorError> {
+        read_u32(self.data, self.rva_offset(rva)?)
+    }
+
+    /// Reads a little-endian `u64` at an RVA.
+    pub fn u64_at(&self, rva: u32) -> Result<u64, HypervisorError> {
+        read_u64(self.data, self.rva_offset(rva)?)
+    }
+
+    /// Translates an RVA to an offset, failing with `InvalidPeImage`.
+    fn rva_offset(&self, rva: u32) -> Result<usize, HypervisorError> {
+        self.rva_to_offset(rva)
+            .ok_or(HypervisorError::InvalidPeImage)
+    }
+
+    /// Returns the offset of the optional header.
+    fn optional_header(&self) -> usize {
+        self.nt_headers + OPTIONAL_HEADER_OFFSET
+    }
+
+    /// Reads a `u16` of the headers, which were validated when parsing.
+    fn header_u16(&self, offset: usize) -> u16 {
+        read_u16(self.data, offset).unwrap_or_default()
+    }
+
+    /// Reads a `u32` of the headers, which were validated when parsing.
+    fn header_u32(&self, offset: usize) -> u32 {
+        read_u32(self.data, offset).unwrap_or_default()
+    }
+
+    /// Returns the section at an index of the section table.
+    fn section(&self, index: usize) -> Section<'a> {
+        let offset = self.section_table + index * SECTION_HEADER_SIZE;
+        let name = &self.data[offset..offset + 8];
+        let name_length = name.iter().position(|byte| *byte == 0).unwrap_or(8);
+
+        Section {
+            name: &name[..name_length],
+            virtual_size: self.header_u32(offset + 0x8),
+            virtual_address: self.header_u32(offset + 0xC),
+            size_of_raw_data: self.header_u32(offset + 0x10),
+            pointer_to_raw_data: self.header_u32(offset + 0x14),
+            characteristics: self.header_u32(offset + 0x24),
+        }
+    }
+}
+
+/// A section of an image (`IMAGE_SECTION_HEADER`).
+#[derive(Debug, Clone, Copy)]
+pub struct Section<'a> {
+    /// The name of the section, without padding.
+    pub name: &'a [u8],
+
+    /// The size of the section in memory.
+    pub virtual_size: u32,
+
+    /// The RVA of the section.
+    pub virtual_address: u32,
+
+    /// The size of the section in the file.
+    pub size_of_raw_data: u32,
+
+    /// The offset of the section in the file.
+    pub pointer_to_raw_data: u32,
+
+    /// The characteristics of the section (`IMAGE_SCN_*`).
+    pub characteristics: u32,
+}
+
+impl Section<'_> {
+    /// Checks whether the section contains an RVA.
+    pub fn contains(&self, rva: u32) -> bool {
+        let size = self.virtual_size.max(self.size_of_raw_data);
+        rva >= self.virtual_address && rva - self.virtual_address < size
+    }
+
+    /// Checks whether the section is executable.
+    pub fn is_executable(&self) -> bool {
+        self.characteristics & SECTION_MEM_EXECUTE != 0
+    }
+}
+
+/// The target of an export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportTarget<'a> {
+    /// The export is implemented in the image at the RVA.
+    Rva(u32),
+
+    /// The export is forwarded to another module, for example `NTOSKRNL.KeBugCheckEx` or `HAL.#12`.
+    Forwarder(&'a str),
+}
+
+/// An export of an image.
+#[derive(Debug, Clone, Copy)]
+pub struct Export<'a> {
+    /// The name of the export, or `None` if it's only exported by ordinal.
+    pub name: Option<&'a str>,
+
+    /// The ordinal of the export, including the ordinal base.
+    pub ordinal: u32,
+
+    /// The target of the export.
+    pub target: ExportTarget<'a>,
+}
+
+impl<'a> Export<'a> {
+    /// Returns the RVA of the export, or `None` if it's forwarded.
+    pub fn rva(&self) -> Option<u32> {
+        match self.target {
+            ExportTarget::Rva(rva) => Some(rva),
+            ExportTarget::Forwarder(_) => None,
+        }
+    }
+
+    /// Returns the module and the function (or `#ordinal`) of a forwarded export.
+    pub fn forwarder(&self) -> Option<(&'a str, &'a str)> {
+        match self.target {
+            ExportTarget::Forwarder(forwarder) => forwarder.split_once('.'),
+            ExportTarget::Rva(_) => None,
+        }
+    }
+}
+
+/// The export directory of an image (`IMAGE_EXPORT_DIRECTORY`).
+#[derive(Debug, Clone, Copy)]
+pub struct Exports<'a> {
+    /// The image.
+    image: PeImage<'a>,
+
+    /// The RVA range of the export directory, which contains the forwarder strings.
+    directory: (u32, u32),
+
+    /// The ordinal base.
+    ordinal_base: u32,
+
+    /// Number of the exported functions.
+    number_of_functions: u32,
+
+    /// Number of the exports by name.
+    number_of_names: u32,
+
+    /// RVA of the array of function RVAs.
+    address_of_functions: u32,
+
+    /// RVA of the array of name RVAs, sorted by name.
+    address_of_names: u32,
+
+    /// RVA of the array of ordinals (without the base) corresponding to the names.
+    address_of_name_ordinals: u32,
+}
+
+impl<'a> Exports<'a> {
+    /// Parses the export directory of an image.
+    fn parse(image: PeImage<'a>) -> Result<Self, HypervisorError> {
+        let (rva, size) = image
+            .data_directory(DataDirectory::Export)
+            .ok_or(HypervisorError::ExportNotFound)?;
+
+        Ok(Self {
+            image,
+            directory: (rva, rva_add(rva, size)?),
+            ordinal_base: image.u32_at(rva_add(rva, 0x10)?)?,
+            number_of_functions: image.u32_at(rva_add(rva, 0x14)?)?,
+            number_of_names: image.u32_at(rva_add(rva, 0x18)?)?,
+            address_of_functions: image.u32_at(rva_add(rva, 0x1C)?)?,
+            address_of_names: image.u32_at(rva_add(rva, 0x20)?)?,
+            address_of_name_ordinals: image.u32_at(rva_add(rva, 0x24)?)?,
+        })
+    }
+
+    /// Returns the name of the image in the export directory, for example `ntoskrnl.exe`.
+    pub fn module_name(&self) -> Result<&'a str, HypervisorError> {
+        self.image
+            .c_str_at(self.image.u32_at(rva_add(self.directory.0, 0xC)?)?)
+    }
+
+    /// Returns the number of the exports by name.
+    pub fn len(&self) -> usize {
+        self.number_of_names as usize
+    }
+
+    /// Checks whether the image has no exports by name.
+    pub fn is_empty(&self) -> bool {
+        self.number_of_names == 0
+    }
+
+    /// Returns an iterator over the exports by name.
+    pub fn iter(&self) -> impl Iterator<Item = Export<'a>> + '_ {
+        (0..self.number_of_names).map_while(|index| self.named_export(index).ok())
+    }
+
+    /// Finds an export by its name.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the export.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Export<'a>, HypervisorError>` - The export, or an error if it wasn't found.
+    pub fn by_name(&self, name: &str) -> Result<Export<'a>, HypervisorError> {
+        for index in 0..self.number_of_names {
+            if self.name(index)? == name {
+                return self.named_export(index);
+            }
+        }
+
+        Err(HypervisorError::ExportNotFound)
+    }
+
+    /// Finds an export by its ordinal.
+    ///
+    /// # Arguments
+    ///
+    /// * `ordinal` - The ordinal of the export, including the ordinal base.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Export<'a>, HypervisorError>` - The export, or an error if it wasn't found.
+    pub fn by_ordinal(&self, ordinal: u32) -> Result<Export<'a>, HypervisorError> {
+        let index = ordinal
+            .checked_sub(self.ordinal_base)
+            .filter(|index| *index < self.number_of_functions)
+            .ok_or(HypervisorError::ExportNotFound)?;
+
+        let name = (0..self.number_of_names)
+            .find(|name_index| {
+                element_rva(self.address_of_name_ordinals, *name_index, 2)
+                    .and_then(|rva| self.image.u16_at(rva))
+                    .is_ok_and(|name_ordinal| name_ordinal as u32 == index)
+            })
+            .map(|name_index| self.name(name_index))
+            .transpose()?;
+
+        Ok(Export {
+            name,
+            ordinal,
+            target: self.target(index)?,
+        })
+    }
+
+    /// Returns the name at an index of the name array.
+    fn name(&self, index: u32) -> Result<&'a str, HypervisorError> {
+        let name_rva = self
+            .image
+            .u32_at(element_rva(self.address_of_names, index, 4)?)?;
+        self.image.c_str_at(name_rva)
+    }
+
+    /// Returns the export at an index of the name array.
+    fn named_export(&self, index: u32) -> Result<Export<'a>, HypervisorError> {
+        let function_index =
+            self.image
+                .u16_at(element_rva(self.address_of_name_ordinals, index, 2)?)? as u32;
+
+        Ok(Export {
+            name: Some(self.name(index)?),
+            ordinal: self.ordinal_base + function_index,
+            target: self.target(function_index)?,
+        })
+    }
+
+    /// Returns the target of the function at an index of the function array.
+    fn target(&self, function_index: u32) -> Result<ExportTarget<'a>, HypervisorError> {
+        if function_index >= self.number_of_functions {
+            return Err(HypervisorError::InvalidPeImage);
+        }
+
+        let rva = self
+            .image
+            .u32_at(element_rva(self.address_of_functions, function_index, 4)?)?;
+
+        // Forwarded exports point to a string within the export directory.
+        if (self.directory.0..self.directory.1).contains(&rva) {
+            Ok(ExportTarget::Forwarder(self.image.c_str_at(rva)?))
+        } else {
+            Ok(ExportTarget::Rva(rva))
+        }
+    }
+}
+
+/// The name of an imported function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportName<'a> {
+    /// The function is imported by name, with a hint into the export name array.
+    Name { hint: u16, name: &'a str },
+
+    /// The function is imported by ordinal.
+    Ordinal(u16),
+}
+
+/// An imported function.
+#[derive(Debug, Clone, Copy)]
+pub struct Import<'a> {
+    /// The name of the function.
+    pub name: ImportName<'a>,
+
+    /// The RVA of the import address table entry of the function.
+    pub iat_rva: u32,
+}
+
+/// An import descriptor of an image (`IMAGE_IMPORT_DESCRIPTOR`).
+#[derive(Debug, Clone, Copy)]
+pub struct ImportDescriptor<'a> {
+    /// The image.
+    image: PeImage<'a>,
+
+    /// The name of the imported module, for example `ntoskrnl.exe`.
+    pub module_name: &'a str,
+
+    /// The RVA of the import lookup table, or 0 if the image only has the import address table.
+    pub original_first_thunk: u32,
+
+    /// The RVA of the import address table.
+    pub first_thunk: u32,
+}
+
+impl<'a> ImportDescriptor<'a> {
+    /// Parses the import descriptor at an offset, or returns `None` for the terminating descriptor.
+    fn parse(image: PeImage<'a>, offset: usize) -> Option<Self> {
+        let original_first_thunk = read_u32(image.data, offset).ok()?;
+        let name = read_u32(image.data, offset + 0xC).ok()?;
+        let first_thunk = read_u32(image.data, offset + 0x10).ok()?;
+
+        if name == 0 || first_thunk == 0 {
+            return None;
+        }
+
+        Some(Self {
+            image,
+            module_name: image.c_str_at(name).ok()?,
+            original_first_thunk,
+            first_thunk,
+        })
+    }
+
+    /// Returns an iterator over the imported functions.
+    ///
+    /// The names are read from the import lookup table. If the image doesn't have one, they are read
+    /// from the import address table, which only works before the imports are bound.
+    pub fn functions(&self) -> impl Iterator<Item = Import<'a>> + '_ {
+        let lookup_table = match self.original_first_thunk {
+            0 => self.first_thunk,
+            rva => rva,
+        };
+
+        (0..)
+            .map_while(move |index: u32| {
+                let thunk_size = size_of::<u64>() as u32;
+                let thunk = self
+                    .image
+                    .u64_at(element_rva(lookup_table, index, thunk_size).ok()?)
+                    .ok()?;
+
+                if thunk == 0 {
+                    return None;
+                }
+
+                let name = if thunk & IMPORT_BY_ORDINAL_FLAG != 0 {
+                    ImportName::Ordinal(thunk as u16)
+                } else {
+                    let hint_name = thunk as u32;
+                    ImportName::Name {
+                        hint: self.image.u16_at(hint_name).ok()?,
+                        name: self.image.c_str_at(rva_add(hint_name, 2).ok()?).ok()?,
+                    }
+                };
+
+                Some(Import {
+                    name,
+                    iat_rva: element_rva(self.first_thunk, index, thunk_size).ok()?,
+                })
+            })
+            .fuse()
+    }
+}
+
+/// An entry of the exception directory (`RUNTIME_FUNCTION`), describing the unwind data of a function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RuntimeFunction {
+    /// The RVA of the start of the function.
+    pub begin_address: u32,
+
+    /// The RVA of the end of the function (exclusive).
+    pub end_address: u32,
+
+    /// The RVA of the unwind information.
+    pub unwind_info_address: u32,
+}
+
+impl RuntimeFunction {
+    /// Checks whether the function contains an RVA.
+    pub fn contains(&self, rva: u32) -> bool {
+        (self.begin_address..self.end_address).contains(&rva)
+    }
+}
+
+/// The entries of the exception directory.
+#[derive(Debug, Clone, Copy)]
+pub struct RuntimeFunctions<'a> {
+    /// The bytes of the exception directory.
+    entries: &'a [u8],
+}
+
+impl<'a> RuntimeFunctions<'a> {
+    /// Returns the number of the entries.
+    pub fn len(&self) -> usize {
+        self.entries.len() / RUNTIME_FUNCTION_SIZE
+    }
+
+    /// Checks whether the exception directory is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the entry at an index.
+    pub fn get(&self, index: usize) -> Option<RuntimeFunction> {
+        let offset = index * RUNTIME_FUNCTION_SIZE;
+
+        Some(RuntimeFunction {
+            begin_address: read_u32(self.entries, offset).ok()?,
+            end_address: read_u32(self.entries, offset + 4).ok()?,
+            unwind_info_address: read_u32(self.entries, offset + 8).ok()?,
+        })
+    }
+
+    /// Returns an iterator over the entries.
+    pub fn iter(&self) -> impl Iterator<Item = RuntimeFunction> + 'a {
+        let functions = *self;
+        (0..self.len()).filter_map(move |index| functions.get(index))
+    }
+
+    /// Finds the entry containing an RVA with a binary search.
+    pub fn find(&self, rva: u32) -> Option<RuntimeFunction> {
+        let (mut low, mut high) = (0, self.len());
+
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let function = self.get(middle)?;
+
+            if rva < function.begin_address {
+                high = middle;
+            } else if rva >= function.end_address {
+                low = middle + 1;
+            } else {
+                return Some(function);
+            }
+        }
+
+        None
+    }
+}
+
+/// The unwind information of a function (`UNWIND_INFO`), without the unwind codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnwindInfo {
+    /// The version of the unwind information (1 or 2).
+    pub version: u8,
+
+    /// The flags (`UNW_FLAG_*`).
+    pub flags: u8,
+
+    /// The size of the prologue in bytes.
+    pub size_of_prolog: u8,
+
+    /// The number of the unwind code slots.
+    pub count_of_codes: u8,
+
+    /// The frame pointer register, or 0 if the function doesn't use one.
+    pub frame_register: u8,
+
+    /// The scaled offset of the frame pointer from RSP (in 16 byte units).
+    pub frame_offset: u8,
+
+    /// The entry of the function this unwind information is chained to, if `UNW_FLAG_CHAININFO` is set.
+    pub chained: Option<RuntimeFunction>,
+}
+
+/// The boundaries of a function, as described by the exception directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Function {
+    /// The RVA of the start of the function.
+    pub begin_address: u32,
+
+    /// The RVA of the end of the function (exclusive). Chained fragments of the function can be located elsewhere.
+    pub end_address: u32,
+
+    /// The size of the prologue in bytes.
+    pub prologue_size: u8,
+}
+
+impl Function {
+    /// Returns the size of the function in bytes, or `None` if the end precedes the start.
+    pub fn size(&self) -> Option<u32> {
+        self.end_address.checked_sub(self.begin_address)
+    }
+
+    /// Checks whether an RVA is the start of the function.
+    pub fn starts_at(&self, rva: u32) -> bool {
+        self.begin_address == rva
+    }
+}
+
+/// The CodeView PDB 7.0 record of an image (`RSDS`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodeView<'a> {
+    /// The GUID of the PDB, as raw bytes.
+    pub guid: [u8; 16],
+
+    /// The age of the PDB.
+    pub age: u32,
+
+    /// The path of the PDB, for example `ntkrnlmp.pdb`.
+    pub path: &'a str,
+}
+
+/// A base relocation of an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Relocation {
+    /// The RVA of the relocated value.
+    pub rva: u32,
+
+    /// The type of the relocation (`IMAGE_REL_BASED_*`), for example 10 (`DIR64`).
+    pub kind: u8,
+}
+
+/// An iterator over the base relocations of an image.
+#[derive(Debug, Clone)]
+pub struct Relocations<'a> {
+    /// The remaining relocation blocks.
+    blocks: &'a [u8],
+
+    /// The remaining entries of the current block.
+    block: &'a [u8],
+
+    /// The page RVA of the current block.
+    page_rva: u32,
+}
+
+impl Iterator for Relocations<'_> {
+    type Item = Relocation;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        /// The relocation type used for padding (`IMAGE_REL_BASED_ABSOLUTE`).
+        const RELOCATION_ABSOLUTE: u8 = 0;
+
+        loop {
+            if let Some((entry, rest)) = self.block.split_first_chunk::<2>() {
+                self.block = rest;
+
+                let entry = u16::from_le_bytes(*entry);
+                let kind = (entry >> 12) as u8;
+
+                if kind == RELOCATION_ABSOLUTE {
+                    continue;
+                }
+
+                return Some(Relocation {
+                    rva: self.page_rva.checked_add((entry & 0xFFF) as u32)?,
+                    kind,
+                });
+            }
+
+            let page_rva = read_u32(self.blocks, 0).ok()?;
+            let block_size = read_u32(self.blocks, 4).ok()? as usize;
+
+            if block_size < RELOCATION_BLOCK_HEADER_SIZE || block_size > self.blocks.len() {
+                return None;
+            }
+
+            self.page_rva = page_rva;
+            self.block = &self.blocks[RELOCATION_BLOCK_HEADER_SIZE..block_size];
+            self.blocks = &self.blocks[block_size..];
+        }
+    }
+}
+
+/// Adds an offset to an RVA, failing with `InvalidPeImage` if the sum overflows.
+fn rva_add(rva: u32, offset: u32) -> Result<u32, HypervisorError> {
+    rva.checked_add(offset)
+        .ok_or(HypervisorError::InvalidPeImage)
+}
+
+/// Returns the RVA of the element at an index of an array, failing with `InvalidPeImage` if it overflows.
+fn element_rva(array: u32, index: u32, element_size: u32) -> Result<u32, HypervisorError> {
+    index
+        .checked_mul(element_size)
+        .and_then(|offset| array.checked_add(offset))
+        .ok_or(HypervisorError::InvalidPeImage)
+}
+
+/// Reads a little-endian `u16` at an offset.
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, HypervisorError> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .ok_or(HypervisorError::InvalidPeImage)
+}
+
+/// Reads a little-endian `u32` at an offset.
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, HypervisorError> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or(HypervisorError::InvalidPeImage)
+}
+
+/// Reads a little-endian `u64` at an offset.
+fn read_u64(data: &[u8], offset: usize) -> Result<u64, HypervisorError> {
+    data.get(offset..offset + 8)
+        .map(|bytes| {
+            let mut value = [0u8; 8];
+            value.copy_from_slice(bytes);
+            u64::from_le_bytes(value)
+        })
+        .ok_or(HypervisorError::InvalidPeImage)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {
+        super::*,
+        alloc::{vec, vec::Vec},
+    };
+
+    /// Offset of the NT headers in the images built by `TestImage`.
+    const NT_HEADERS: usize = 0x80;
+
+    /// Offset of the optional header in the images built by `TestImage`.
+    const OPTIONAL_HEADER: usize = NT_HEADERS + OPTIONAL_HEADER_OFFSET;
+
+    /// Size of the optional header in the images built by `TestImage`, with all the data directories.
+    const SIZE_OF_OPTIONAL_HEADER: usize = DATA_DIRECTORIES_OFFSET + 16 * 8;
+
+    /// Size of the headers in the images built by `TestImage`, which is also the RVA of the first section.
+    pub(crate) const HEADERS_SIZE: u32 = 0x1000;
+
+    /// A DLL with exports, imports, chained unwind information, relocations and a CodeView record.
+    const FIXTURE: &[u8] = include_bytes!("../../tests/fixtures/fixture.dll");
+
+    /// The RVA of the `First` export of `FIXTURE`.
+    const FIRST: u32 = 0x1000;
+
+    /// The RVA of the `Second` export of `FIXTURE`, which is split into a primary function and two fragments.
+    const SECOND: u32 = 0x1015;
+
+    /// The RVA of the entry point (`DllMain`) of `FIXTURE`, which is a leaf function.
+    const DLL_MAIN: u32 = 0x1029;
+
+    /// The RVAs of the fragments of `Second`, chained through the unwind information and in the older format.
+    const SECOND_FRAGMENTS: [u32; 2] = [0x102F, 0x1037];
+
+    /// Maps a PE file with its section alignment, as the loader does before applying the relocations.
+    pub(crate) fn map_image(file: &[u8]) -> Vec<u8> {
+        let image = PeImage::parse_file(file).unwrap();
+        let mut mapped = vec![0; image.size_of_image() as usize];
+        let headers = image.size_of_headers() as usize;
+        mapped[..headers].copy_from_slice(&file[..headers]);
+
+        for section in image.sections() {
+            let data = image.section_data(&section).unwrap();
+            let start = section.virtual_address as usize;
+            mapped[start..start + data.len()].copy_from_slice(data);
+        }
+
+        mapped
+    }
+
+    /// Builds PE32+ images mapped with their section alignment, with a single `.text` section
+    /// covering everything after the headers.
+    ///
+    /// It's used to corrupt the structures of an image at known RVAs. The well-formed images are the
+    /// fixtures in `tests/fixtures`, which are built by a real linker.
+    pub(crate) struct TestImage {
+        /// The bytes of the image.
+        pub(crate) data: Vec<u8>,
+    }
+
+    impl TestImage {
+        /// Creates an image of the given size, which must be larger than the headers.
+        pub(crate) fn new(size: u32) -> Self {
+            let mut image = Self {
+                data: vec![0; size as usize],
+            };
+
+            image.write_u16(0, DOS_SIGNATURE);
+            image.write_u32(DOS_E_LFANEW_OFFSET as u32, NT_HEADERS as u32);
+            image.write_u32(NT_HEADERS as u32, NT_SIGNATURE);
+
+            let file_header = (NT_HEADERS + FILE_HEADER_OFFSET) as u32;
+            image.write_u16(file_header, 0x8664);
+            image.write_u16(file_header + 0x2, 1);
+            image.write_u16(file_header + 0x10, SIZE_OF_OPTIONAL_HEADER as u16);
+
+            let optional_header = OPTIONAL_HEADER as u32;
+            image.write_u16(optional_header, OPTIONAL_HEADER_MAGIC_PE32_PLUS);
+            image.write_u64(optional_header + 0x18, 0x1_4000_0000);
+            image.write_u32(optional_header + 0x38, size);
+            image.write_u32(optional_header + 0x3C, HEADERS_SIZE);
+            image.write_u32(optional_header + 0x6C, 16);
+
+            let section = (OPTIONAL_HEADER + SIZE_OF_OPTIONAL_HEADER) as u32;
+            image.write(section, b".text");
+            image.write_u32(section + 0x8, size - HEADERS_SIZE);
+            image.write_u32(section + 0xC, HEADERS_SIZE);
+            image.write_u32(section + 0x10, size - HEADERS_SIZE);
+            image.write_u32(section + 0x14, HEADERS_SIZE);
+            image.write_u32(section + 0x24, SECTION_MEM_EXECUTE);
+
+            image
+        }
+
+        /// Sets the RVA and size of a data directory.
+        pub(crate) fn with_directory(
+            mut self,
+            directory: DataDirectory,
+            rva: u32,
+            size: u32,
+        ) -> Self {
+            let offset =
+                (OPTIONAL_HEADER + DATA_DIRECTORIES_OFFSET + directory as usize * 8) as u32;
+            self.write_u32(offset, rva);
+            self.write_u32(offset + 4, size);
+            self
+        }
+
+        /// Writes bytes at an RVA.
+        pub(crate) fn with_bytes(mut self, rva: u32, bytes: &[u8]) -> Self {
+            self.write(rva, bytes);
+            self
+        }
+
+        /// Writes an export directory at an RVA, with the exports in the given order and the ordinal base 1.
+        pub(crate) fn with_exports(
+            mut self,
+            rva: u32,
+            module_name: &str,
+            exports: &[(&str, u32)],
+        ) -> Self {
+            let count = exports.len() as u32;
+            let address_of_functions = rva + 0x28;
+            let address_of_names = address_of_functions + count * 4;
+            let address_of_name_ordinals = address_of_names + count * 4;
+            let mut string = address_of_name_ordinals + count * 2;
+
+            self.write_u32(rva + 0xC, string);
+            self.write_c_str(string, module_name);
+            string += module_name.len() as u32 + 1;
+
+            self.write_u32(rva + 0x10, 1);
+            self.write_u32(rva + 0x14, count);
+            self.write_u32(rva + 0x18, count);
+            self.write_u32(rva + 0x1C, address_of_functions);
+            self.write_u32(rva + 0x20, address_of_names);
+            self.write_u32(rva + 0x24, address_of_name_ordinals);
+
+            for (index, (name, function)) in exports.iter().enumerate() {
+                let index = index as u32;
+
+                self.write_u32(address_of_names + index * 4, string);
+                self.write_c_str(string, name);
+                string += name.len() as u32 + 1;
+
+                self.write_u16(address_of_name_ordinals + index * 2, index as u16);
+                self.write_u32(address_of_functions + index * 4, *function);
+            }
+
+            self.with_directory(DataDirectory::Export, rva, string - rva)
+        }
+
+        /// Returns the bytes of the image.
+        pub(crate) fn bytes(&self) -> &[u8] {
+            &self.data
+        }
+
+        /// Writes bytes at an RVA.
+        pub(crate) fn write(&mut self, rva: u32, bytes: &[u8]) {
+            self.data[rva as usize..rva as usize + bytes.len()].copy_from_slice(bytes);
+        }
+
+        /// Writes a null-terminated string at an RVA.
+        pub(crate) fn write_c_str(&mut self, rva: u32, string: &str) {
+            self.write(rva, string.as_bytes());
+            self.write(rva + string.len() as u32, &[0]);
+        }
+
+        /// Writes a little-endian `u16` at an RVA.
+        pub(crate) fn write_u16(&mut self, rva: u32, value: u16) {
+            self.write(rva, &value.to_le_bytes());
+        }
+
+        /// Writes a little-endian `u32` at an RVA.
+        pub(crate) fn write_u32(&mut self, rva: u32, value: u32) {
+            self.write(rva, &value.to_le_bytes());
+        }
+
+        /// Writes a little-endian `u64` at an RVA.
+        pub(crate) fn write_u64(&mut self, rva: u32, value: u64) {
+            self.write(rva, &value.to_le_bytes());
+        }
+    }
+
+    /// Builds an image with exports, imports and relocations at known RVAs, for the tests of malformed input.
+    fn test_image() -> TestImage {
+        let mut image = TestImage::new(0x4000)
+            .with_exports(0x2000, "test.sys", &[("First", 0x1000), ("Second", 0x1100)])
+            .with_directory(DataDirectory::Import, 0x2400, 0x28)
+            .with_directory(DataDirectory::BaseRelocation, 0x2800, 0x10);
+
+        // An import descriptor of ntoskrnl.exe, importing a function by name.
+        image.write_u32(0x2400, 0x2480);
+        image.write_u32(0x2400 + 0xC, 0x24C0);
+        image.write_u32(0x2400 + 0x10, 0x24A0);
+        image.write_u64(0x2480, 0x24D0);
+        image.write_c_str(0x24C0, "ntoskrnl.exe");
+        image.write_c_str(0x24D2, "ExAllocatePool");
+
+        // Two relocation blocks with a DIR64 relocation each.
+        image.write_u32(0x2800, 0x1000);
+        image.write_u32(0x2804, 0xC);
+        image.write_u16(0x2808, 0xA008);
+        image.write_u32(0x280C, 0x2000);
+        image.write_u32(0x2810, 0xC);
+        image.write_u16(0x2814, 0xA010);
+
+        image
+    }
+
+    #[test]
+    fn headers_are_parsed() {
+        let image = PeImage::parse_file(FIXTURE).unwrap();
+
+        assert_eq!(image.layout(), Layout::File);
+        assert_eq!(image.machine(), 0x8664);
+        assert_eq!(image.entry_point(), DLL_MAIN);
+        assert_eq!(image.image_base(), 0x1_8000_0000);
+        assert_eq!(image.size_of_image(), 0x6000);
+        assert_eq!(image.size_of_headers(), 0x400);
+        assert_eq!(image.data_directory(DataDirectory::Resource), None);
+        assert_eq!(
+            image.data_directory(DataDirectory::Exception),
+            Some((0x4000, 0x30))
+        );
+
+        let names: Vec<_> = image.sections().map(|section| section.name).collect();
+        assert_eq!(
+            names,
+            [&b".text"[..], b".rdata", b".data", b".pdata", b".reloc"]
+        );
+
+        let text = image.section_by_name(".text").unwrap();
+        assert!(text.is_executable());
+        assert_eq!(text.virtual_address, 0x1000);
+        assert!(!image.section_by_name(".rdata").unwrap().is_executable());
+        assert_eq!(image.section_containing(SECOND).unwrap().name, b".text");
+        assert!(image.section_containing(0x6000).is_none());
+    }
+
+    #[test]
+    fn malformed_headers_are_rejected() {
+        let valid = test_image();
+
+        let mut image = test_image();
+        image.write_u16(0, 0);
+        assert!(matches!(
+            PeImage::parse(image.bytes()),
+            Err(HypervisorError::InvalidPeImage)
+        ));
+
+        let mut image = test_image();
+        image.write_u32(DOS_E_LFANEW_OFFSET as u32, 0xFFFF_FFF0);
+        assert!(matches!(
+            PeImage::parse(image.bytes()),
+            Err(HypervisorError::InvalidPeImage)
+        ));
+
+        let mut image = test_image();
+        image.write_u16(0x80 + OPTIONAL_HEADER_OFFSET as u32, 0x10B);
+        assert!(matches!(
+            PeImage::parse(image.bytes()),
+            Err(HypervisorError::InvalidPeImage)
+        ));
+
+        let mut image = test_image();
+        image.write_u16(0x80 + FILE_HEADER_OFFSET as u32 + 0x2, 0xFFFF);
+        assert!(matches!(
+            PeImage::parse(image.bytes()),
+            Err(HypervisorError::InvalidPeImage)
+        ));
+
+        assert!(matches!(
+            PeImage::parse(&valid.bytes()[..0x100]),
+            Err(HypervisorError::InvalidPeImage)
+        ));
+    }
+
+    #[test]
+    fn exports_are_found_by_name_and_ordinal() {
+        let exports = PeImage::parse_file(FIXTURE).unwrap().exports().unwrap();
+
+        assert_eq!(exports.module_name().unwrap(), "fixture.dll");
+        assert_eq!(exports.len(), 4);
+        assert_eq!(exports.by_name("First").unwrap().rva(), Some(FIRST));
+        assert_eq!(exports.by_name("Second").unwrap().rva(), Some(SECOND));
+        assert_eq!(exports.by_name("Second").unwrap().ordinal, 13);
+        assert_eq!(exports.by_name("Table").unwrap().rva(), Some(0x3000));
+        assert_eq!(exports.by_ordinal(11).unwrap().name, Some("First"));
+        assert!(matches!(
+            exports.by_name("Missing"),
+            Err(HypervisorError::ExportNotFound)
+        ));
+
+        // OrdinalOnly is exported without a name.
+        let ordinal_only = exports.by_ordinal(10).unwrap();
+        assert_eq!(ordinal_only.name, None);
+        assert_eq!(ordinal_only.rva(), Some(SECOND));
+        assert!(matches!(
+            exports.by_name("OrdinalOnly"),
+            Err(HypervisorError::ExportNotFound)
+        ));
+
+        for ordinal in [9, 15] {
+            assert!(matches!(
+                exports.by_ordinal(ordinal),
+                Err(HypervisorError::ExportNotFound)
+            ));
+        }
+
+        let names: Vec<_> = exports.iter().filter_map(|export| export.name).collect();
+        assert_eq!(names, ["First", "Forwarded", "Second", "Table"]);
+    }
+
+    #[test]
+    fn forwarded_exports_name_the_target() {
+        let exports = PeImage::parse_file(FIXTURE).unwrap().exports().unwrap();
+        let forwarded = exports.by_name("Forwarded").unwrap();
+
+        assert_eq!(forwarded.rva(), None);
+        assert_eq!(
+            forwarded.target,
+            ExportTarget::Forwarder("imported.ImportedFunction")
+        );
+        assert_eq!(
+            forwarded.forwarder(),
+            Some(("imported", "ImportedFunction"))
+        );
+        assert_eq!(exports.by_name("First").unwrap().forwarder(), None);
+    }
+
+    #[test]
+    fn exports_with_overflowing_arrays_are_rejected() {
+        let mut image = test_image();
+        image.write_u32(0x2000 + 0x20, 0xFFFF_FFFE);
+        let exports = PeImage::parse(image.bytes()).unwrap().exports().unwrap();
+
+        assert!(matches!(
+            exports.by_name("First"),
+            Err(HypervisorError::InvalidPeImage)
+        ));
+        assert_eq!(exports.iter().count(), 0);
+
+        let image = test_image().with_directory(DataDirectory::Export, 0x2000, u32::MAX);
+        assert!(matches!(
+            PeImage::parse(image.bytes()).unwrap().exports(),
+            Err(HypervisorError::InvalidPeImage)
+        ));
+
+        let image = test_image().with_directory(DataDirectory::Export, 0xFFFF_FFF0, 0x10);
+        assert!(matches!(
+            PeImage::parse(image.bytes()).unwrap().exports(),
+            Err(HypervisorError::InvalidPeImage)
+        ));
+    }
+
+    #[test]
+    fn imports_are_read_from_the_lookup_table() {
+        let image = PeImage::parse_file(FIXTURE).unwrap();
+        let descriptors: Vec<_> = image.imports().collect();
+
+        assert_eq!(descriptors.len(), 1);
+        assert_eq!(descriptors[0].module_name, "imported.dll");
+
+        let functions: Vec<_> = descriptors[0].functions().collect();
+        assert_eq!(functions.len(), 2);
+        assert_eq!(functions[0].name, ImportName::Ordinal(7));
+        assert_eq!(
+            functions[1].name,
+            ImportName::Name {
+                hint: 0,
+                name: "ImportedFunction"
+            }
+        );
+        assert_eq!(functions[1].iat_rva, functions[0].iat_rva + 8);
+
+        // First calls ImportedFunction and then ImportedByOrdinal through the import address table.
+        let calls = image.bytes_at(FIRST + 4).unwrap();
+        for (index, function) in [functions[1], functions[0]].iter().enumerate() {
+            let call = &calls[index * 6..index * 6 + 6];
+            let displacement = i32::from_le_bytes(call[2..].try_into().unwrap());
+
+            assert_eq!(call[..2], [0xFF, 0x15]);
+            assert_eq!(
+                function.iat_rva,
+                (FIRST + 4 + (index as u32 + 1) * 6).wrapping_add_signed(displacement)
+            );
+        }
+    }
+
+    #[test]
+    fn imports_stop_at_overflowing_descriptors() {
+        let image = test_image().with_directory(DataDirectory::Import, u32::MAX - 4, 0x28);
+
+        assert_eq!(PeImage::parse(image.bytes()).unwrap().imports().count(), 0);
+    }
+
+    #[test]
+    fn functions_are_found_through_the_exception_directory() {
+        let image = PeImage::parse_file(FIXTURE).unwrap();
+
+        assert_eq!(image.runtime_functions().len(), 4);
+        assert_eq!(image.function_containing(FIRST - 1), None);
+        assert_eq!(image.function_containing(DLL_MAIN), None);
+
+        let function = image.function_containing(FIRST + 0x10).unwrap();
+        assert_eq!(function.begin_address, FIRST);
+        assert_eq!(function.size(), Some(0x15));
+        assert_eq!(function.prologue_size, 0x4);
+        assert!(function.starts_at(FIRST));
+
+        let unwind_info = image
+            .unwind_info(&image.runtime_functions().find(SECOND).unwrap())
+            .unwrap();
+        assert_eq!(unwind_info.version, 1);
+        assert_eq!(unwind_info.size_of_prolog, 0x5);
+        assert_eq!(unwind_info.count_of_codes, 2);
+        assert_eq!(unwind_info.chained, None);
+
+        // Both fragments resolve to the primary function.
+        let primary = image.function_containing(SECOND).unwrap();
+        assert_eq!(primary.begin_address, SECOND);
+        assert_eq!(primary.end_address, DLL_MAIN);
+        assert_eq!(primary.prologue_size, 0x5);
+
+        for fragment in SECOND_FRAGMENTS {
+            assert_eq!(image.function_containing(fragment), Some(primary));
+        }
+
+        let fragment = image.runtime_functions().find(SECOND_FRAGMENTS[0]).unwrap();
+        let unwind_info = image.unwind_info(&fragment).unwrap();
+        assert_eq!(unwind_info.flags, UNWIND_FLAG_CHAIN_INFO);
+        assert_eq!(unwind_info.chained, image.runtime_functions().find(SECOND));
+    }
+
+    #[test]
+    fn function_size_rejects_inverted_boundaries() {
+        let function = Function {
+            begin_address: 0x1100,
+            end_address: 0x1000,
+            prologue_size: 0,
+        };
+
+        assert_eq!(function.size(), None);
+    }
+
+    #[test]
+    fn relocations_skip_the_padding() {
+        let image = PeImage::parse_file(FIXTURE).unwrap();
+        let relocations: Vec<_> = image.relocations().collect();
+
+        // The block of the export table has three DIR64 relocations and an entry of padding.
+        assert_eq!(
+            image.data_directory(DataDirectory::BaseRelocation),
+            Some((0x5000, 0x10))
+        );
+        assert_eq!(
+            relocations,
+            [0x3000, 0x3008, 0x3010].map(|rva| Relocation { rva, kind: 10 })
+        );
+
+        let targets: Vec<_> = relocations
+            .iter()
+            .map(|relocation| image.u64_at(relocation.rva).unwrap() - image.image_base())
+            .collect();
+        assert_eq!(targets, [FIRST as u64, SECOND as u64, DLL_MAIN as u64]);
+    }
+
+    #[test]
+    fn relocations_stop_at_overflowing_pages() {
+        let mut image = test_image();
+        image.write_u32(0x280C, u32::MAX - 0xC);
+
+        assert_eq!(
+            PeImage::parse(image.bytes()).unwrap().relocations().count(),
+            1
+        );
+    }
+
+    #[test]
+    fn codeview_identifies_the_pdb() {
+        let codeview = PeImage::parse_file(FIXTURE).unwrap().codeview().unwrap();
+
+        assert_eq!(codeview.age, 1);
+        assert_eq!(codeview.path, "fixture.pdb");
+    }
+
+    #[test]
+    fn file_and_mapped_layouts_read_the_same_image() {
+        let mapped = map_image(FIXTURE);
+        let file = PeImage::parse_file(FIXTURE).unwrap();
+        let image = PeImage::parse(&mapped).unwrap();
+
+        assert_eq!(image.layout(), Layout::Mapped);
+        assert_eq!(file.rva_to_offset(0x10), Some(0x10));
+        assert_eq!(file.rva_to_offset(FIRST), Some(0x400));
+        assert_eq!(file.rva_to_offset(0x3010), Some(0x810));
+        assert_eq!(image.rva_to_offset(0x3010), Some(0x3010));
+
+        // The virtual size of .text is smaller than its raw data, which is padded to the file alignment.
+        assert_eq!(file.rva_to_offset(0x1100), Some(0x500));
+        assert_eq!(file.rva_to_offset(0x1200), None);
+
+        assert_eq!(
+            image.bytes_at(FIRST).unwrap()[..0x56],
+            file.bytes_at(FIRST).unwrap()[..0x56]
+        );
+        assert_eq!(image.export("Second").unwrap().rva(), Some(SECOND));
+        assert_eq!(image.imports().count(), 1);
+        assert_eq!(
+            image.function_containing(SECOND_FRAGMENTS[1]),
+            file.function_containing(SECOND)
+        );
+        assert_eq!(image.relocations().count(), 3);
+        assert_eq!(image.codeview(), file.codeview());
+    }
+}
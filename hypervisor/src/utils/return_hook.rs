@@ -0,0 +1,193 @@
+//! Provides post-call (return) hooks for root-mode hook callbacks.
+//!
+//! When a hooked function is entered, the return address on the guest stack is replaced with the address
+//! of a shared return thunk consisting of `int3` instructions. The original return address and the entry
+//! arguments are recorded in a fixed-size table keyed by the stack pointer the function returns with.
+//! When the function returns, the thunk causes a breakpoint VM exit, the return callbacks of the hook are
+//! executed with the return value in RAX and execution resumes at the original return address.
+//!
+//! The table doesn't allocate, so it can be used in VMX root operation. Note that replacing the return
+//! address breaks stack walking through the hooked function while it executes, and is incompatible with
+//! kernel-mode hardware-enforced stack protection (CET shadow stacks).
+
+use {
+    crate::{
+        error::HypervisorError, intel::guest_memory::GuestMemory, utils::capture::GuestRegisters,
+    },
+    alloc::{boxed::Box, vec},
+    core::mem::size_of,
+    spin::Mutex,
+};
+
+/// Maximum number of calls that can be pending a return at the same time.
+pub const MAX_PENDING_RETURNS: usize = 256;
+
+/// Size of the return thunk.
+const RETURN_THUNK_SIZE: usize = 16;
+
+/// Number of the arguments recorded at function entry (the register arguments of the Microsoft x64 calling convention).
+const SAVED_ARGUMENTS: usize = 4;
+
+/// The start of the canonical kernel-mode address space.
+const KERNEL_ADDRESS_START: u64 = 0xFFFF_8000_0000_0000;
+
+/// A callback executed in VMX root operation when a hooked function returns.
+///
+/// The callback receives the guest registers after the return (the return value is in RAX), an accessor
+/// for the guest memory and the context recorded at function entry. The return value can be rewritten by
+/// modifying RAX.
+pub type ReturnCallback = fn(&mut GuestRegisters, &GuestMemory, &CallContext);
+
+/// The context of a call recorded at function entry.
+#[derive(Debug, Clone, Copy)]
+pub struct CallContext {
+    /// The address of the hooked function.
+    pub function_address: u64,
+
+    /// The original return address of the call.
+    pub return_address: u64,
+
+    /// The stack pointer at function entry. Stack arguments remain accessible relative to it,
+    /// as they reside in the frame of the caller.
+    pub entry_rsp: u64,
+
+    /// The guest CR3 at function entry.
+    pub cr3: u64,
+
+    /// The register arguments (RCX, RDX, R8, R9) at function entry.
+    pub arguments: [u64; SAVED_ARGUMENTS],
+}
+
+impl CallContext {
+    /// Returns the stack pointer the function returns with.
+    fn return_rsp(&self) -> u64 {
+        self.entry_rsp + size_of::<u64>() as u64
+    }
+
+    /// Checks whether the context belongs to a return with the given stack pointer and CR3.
+    ///
+    /// Kernel stacks are unique across address spaces, so the CR3 is only compared for user-mode stacks.
+    fn matches(&self, rsp: u64, cr3: u64) -> bool {
+        self.return_rsp() == rsp && (rsp >= KERNEL_ADDRESS_START || self.cr3 == cr3)
+    }
+}
+
+/// Manages the return thunk and the calls pending a return.
+pub struct ReturnHooks {
+    /// The return thunk the return addresses are redirected to.
+    thunk: Box<[u8]>,
+
+    /// The calls pending a return.
+    pending: Mutex<[Option<CallContext>; MAX_PENDING_RETURNS]>,
+}
+
+impl ReturnHooks {
+    /// Creates a new `ReturnHooks` instance and allocates the return thunk.
+    pub fn new() -> Self {
+        // The thunk only consists of int3 instructions, so any return to it causes a breakpoint VM exit.
+        let thunk = vec![0xCC_u8; RETURN_THUNK_SIZE].into_boxed_slice();
+
+        log::debug!("Return thunk address: {:#x}", thunk.as_ptr() as u64);
+
+        Self {
+            thunk,
+            pending: Mutex::new([None; MAX_PENDING_RETURNS]),
+        }
+    }
+
+    /// Returns the address of the return thunk.
+    pub fn thunk_address(&self) -> u64 {
+        self.thunk.as_ptr() as u64
+    }
+
+    /// Checks whether the address is the address of the return thunk.
+    pub fn is_thunk(&self, address: u64) -> bool {
+        address == self.thunk_address()
+    }
+
+    /// Redirects the return address of the current call to the return thunk and records the call.
+    ///
+    /// Must be called at function entry, when RSP points to the return address.
+    ///
+    /// # Arguments
+    ///
+    /// * `guest_registers` - The guest registers at function entry.
+    /// * `guest_memory` - The accessor for the guest memory.
+    /// * `function_address` - The address of the hooked function.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), HypervisorError>` - `Ok` if the return hook was installed, or an error if the
+    ///   return address couldn't be accessed or too many calls are pending.
+    pub fn install(
+        &self,
+        guest_registers: &GuestRegisters,
+        guest_memory: &GuestMemory,
+        function_address: u64,
+    ) -> Result<(), HypervisorError> {
+        let return_address = guest_memory.read::<u64>(guest_registers.rsp)?;
+
+        let context = CallContext {
+            function_address,
+            return_address,
+            entry_rsp: guest_registers.rsp,
+            cr3: guest_memory.cr3(),
+            arguments: GuestMemory::register_arguments(guest_registers),
+        };
+
+        let mut pending = self.pending.lock();
+
+        // A call that returns with the same stack pointer must have been unwound without returning
+        // (for example by an exception), so its entry is stale and can be reused.
+        let slot = pending
+            .iter()
+            .position(|entry| {
+                entry.is_some_and(|entry| entry.matches(context.return_rsp(), context.cr3))
+            })
+            .or_else(|| pending.iter().position(Option::is_none))
+            .ok_or(HypervisorError::TooManyPendingReturns)?;
+
+        guest_memory.write(guest_registers.rsp, self.thunk_address())?;
+
+        pending[slot] = Some(context);
+
+        log::trace!(
+            "Installed return hook for {:#x} returning to {:#x}",
+            function_address,
+            return_address
+        );
+
+        Ok(())
+    }
+
+    /// Takes the context of the call that returned to the return thunk.
+    ///
+    /// # Arguments
+    ///
+    /// * `guest_registers` - The guest registers after the return to the thunk.
+    /// * `guest_memory` - The accessor for the guest memory.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<CallContext>` - The context recorded at function entry, or `None` if no call is pending a return with the current stack pointer.
+    pub fn take_return(
+        &self,
+        guest_registers: &GuestRegisters,
+        guest_memory: &GuestMemory,
+    ) -> Option<CallContext> {
+        let mut pending = self.pending.lock();
+
+        pending
+            .iter_mut()
+            .find(|entry| {
+                entry.is_some_and(|entry| entry.matches(guest_registers.rsp, guest_memory.cr3()))
+            })
+            .and_then(Option::take)
+    }
+}
+
+impl Default for ReturnHooks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,516 @@
+//! Provides a signature scanning engine for PE images.
+//!
+//! A `Signature` describes how to find an address that isn't exported, such as a global variable or a
+//! non-exported function. It consists of several patterns (for example one per Windows build), the
+//! sections they are searched in, and a chain of resolvers that turn the address of a match into the
+//! address of the target, for example by following the RIP-relative operand of an instruction.
+//!
+//! The engine only operates on the bytes of an image, so it doesn't depend on the kernel. Addresses are
+//! relative to a base address chosen by the caller, which is the address the image is loaded at in the
+//! kernel.
+
+use {
+    crate::{
+        error::HypervisorError,
+        utils::pe::{PeImage, Section},
+    },
+    alloc::vec::Vec,
+};
+
+/// A byte pattern with wildcards, for example `48 8B 05 ? ? ? ? C3`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    /// The bytes of the pattern, or `None` for wildcards.
+    bytes: Vec<Option<u8>>,
+}
+
+impl Pattern {
+    /// Parses a pattern of hexadecimal bytes separated by whitespace. Wildcards are written as `?` or `??`.
+    ///
+    /// # Arguments
+    ///
+    /// * `pattern` - The pattern, for example `4C 8D 15 ? ? ? ?`.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, HypervisorError>` - The pattern, or an error if a byte isn't hexadecimal or the pattern is empty.
+    pub fn parse(pattern: &str) -> Result<Self, HypervisorError> {
+        let bytes = pattern
+            .split_whitespace()
+            .map(|byte| match byte {
+                "?" | "??" => Ok(None),
+                _ => u8::from_str_radix(byte, 16)
+                    .map(Some)
+                    .map_err(|_| HypervisorError::HexParseError),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if bytes.is_empty() {
+            return Err(HypervisorError::HexParseError);
+        }
+
+        Ok(Self { bytes })
+    }
+
+    /// Returns the length of the pattern in bytes.
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Checks whether the pattern is empty.
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Checks whether the pattern matches the start of `data`.
+    pub fn matches(&self, data: &[u8]) -> bool {
+        data.len() >= self.bytes.len()
+            && data
+                .iter()
+                .zip(&self.bytes)
+                .all(|(byte, pattern_byte)| pattern_byte.is_none_or(|b| *byte == b))
+    }
+
+    /// Returns an iterator over the offsets of all matches in `data`, including overlapping ones.
+    pub fn find_all<'a>(&'a self, data: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+        // The first non-wildcard byte is used to skip ahead quickly.
+        let anchor = self
+            .bytes
+            .iter()
+            .position(Option::is_some)
+            .map(|index| (index, self.bytes[index].unwrap_or_default()));
+
+        data.windows(self.bytes.len())
+            .enumerate()
+            .filter(move |(_, window)| {
+                anchor.is_none_or(|(index, byte)| window[index] == byte) && self.matches(window)
+            })
+            .map(|(offset, _)| offset)
+    }
+
+    /// Returns the offset of the first match in `data`.
+    pub fn find(&self, data: &[u8]) -> Option<usize> {
+        self.find_all(data).next()
+    }
+}
+
+/// A step turning the address of a match into the address of the target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolver {
+    /// Adds a displacement to the address, for example to move to an instruction after the start of the match.
+    Add(i64),
+
+    /// Follows the 32-bit displacement of a RIP-relative or relative branch instruction at the address.
+    ///
+    /// The target is `address + instruction_length + rel32`, where `rel32` is read at `address + offset`.
+    /// For example `lea r11, [rip + rel32]` (`4C 8D 1D rel32`) has the displacement at offset 3 and a length of 7.
+    Rel32 {
+        offset: usize,
+        instruction_length: usize,
+    },
+
+    /// Reads the qword at the address, for example to load a global pointer.
+    Deref,
+}
+
+/// A signature of an address that isn't exported.
+#[derive(Debug, Clone, Copy)]
+pub struct Signature {
+    /// The name of the target, used for logging.
+    pub name: &'static str,
+
+    /// The patterns, in order of preference. Usually one for each range of Windows builds.
+    pub patterns: &'static [&'static str],
+
+    /// The names of the sections that are searched, for example `.text` and `PAGE`. All executable
+    /// sections are searched if it's empty.
+    pub sections: &'static [&'static str],
+
+    /// The resolvers applied to the address of a match, in order.
+    pub resolvers: &'static [Resolver],
+}
+
+/// A match of a signature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match {
+    /// The index of the pattern that matched.
+    pub pattern_index: usize,
+
+    /// The address of the match.
+    pub address: u64,
+
+    /// The address of the target, after applying the resolvers to the address of the match.
+    pub target: u64,
+}
+
+impl Signature {
+    /// Finds all matches of all patterns of the signature in an image.
+    ///
+    /// Matches whose resolvers fail (for example because a displacement points outside the image) are skipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `image` - The image that is searched.
+    /// * `base` - The address the image is loaded at.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<Match>, HypervisorError>` - The matches, ordered by pattern and address, or an error if a pattern is invalid.
+    pub fn scan(&self, image: &PeImage, base: u64) -> Result<Vec<Match>, HypervisorError> {
+        let sections = self.sections(image);
+        let mut matches = Vec::new();
+
+        for (pattern_index, pattern) in self.patterns.iter().enumerate() {
+            let pattern = Pattern::parse(pattern)?;
+
+            for section in &sections {
+                let Some(data) = image.section_data(section) else {
+                    continue;
+                };
+
+                for offset in pattern.find_all(data) {
+                    let address = base + section.virtual_address as u64 + offset as u64;
+
+                    match resolve(image, base, address, self.resolvers) {
+                        Ok(target) => matches.push(Match {
+                            pattern_index,
+                            address,
+                            target,
+                        }),
+                        Err(e) => {
+                            log::trace!("Failed to resolve {} at {:#x}: {}", self.name, address, e)
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(matches)
+    }
+
+    /// Finds the target of the signature in an image.
+    ///
+    /// The patterns are tried in order of preference, and the first one with matches is used. If the matches
+    /// of that pattern resolve to different targets, the signature is ambiguous and an error is returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `image` - The image that is searched.
+    /// * `base` - The address the image is loaded at.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<u64, HypervisorError>` - The address of the target, or an error if no pattern matched or the matches are ambiguous.
+    pub fn find(&self, image: &PeImage, base: u64) -> Result<u64, HypervisorError> {
+        let matches = self.scan(image, base)?;
+
+        let Some(first) = matches.first() else {
+            log::debug!("Signature {} not found", self.name);
+            return Err(HypervisorError::PatternNotFound);
+        };
+
+        let ambiguous = matches
+            .iter()
+            .filter(|m| m.pattern_index == first.pattern_index)
+            .any(|m| m.target != first.target);
+
+        if ambiguous {
+            log::debug!(
+                "Signature {} (pattern {}) is ambiguous",
+                self.name,
+                first.pattern_index
+            );
+            return Err(HypervisorError::AmbiguousPattern);
+        }
+
+        log::trace!(
+            "Signature {} (pattern {}) matched at {:#x}, target {:#x}",
+            self.name,
+            first.pattern_index,
+            first.address,
+            first.target
+        );
+
+        Ok(first.target)
+    }
+
+    /// Returns the sections that are searched.
+    fn sections<'a>(&self, image: &PeImage<'a>) -> Vec<Section<'a>> {
+        image
+            .sections()
+            .filter(|section| match self.sections {
+                [] => section.is_executable(),
+                names => names.iter().any(|name| section.name == name.as_bytes()),
+            })
+            .collect()
+    }
+}
+
+/// Applies resolvers to an address within an image.
+///
+/// # Arguments
+///
+/// * `image` - The image the address belongs to.
+/// * `base` - The address the image is loaded at.
+/// * `address` - The address the resolvers are applied to.
+/// * `resolvers` - The resolvers, applied in order.
+///
+/// # Returns
+///
+/// * `Result<u64, HypervisorError>` - The resolved address, or an error if a resolver reads outside the image.
+pub fn resolve(
+    image: &PeImage,
+    base: u64,
+    address: u64,
+    resolvers: &[Resolver],
+) -> Result<u64, HypervisorError> {
+    resolvers
+        .iter()
+        .try_fold(address, |address, resolver| match *resolver {
+            Resolver::Add(displacement) => Ok(address.wrapping_add_signed(displacement)),
+            Resolver::Rel32 {
+                offset,
+                instruction_length,
+            } => {
+                let rva = image_rva(base, address + offset as u64)?;
+                let displacement = image.u32_at(rva)? as i32;
+
+                Ok((address + instruction_length as u64).wrapping_add_signed(displacement as i64))
+            }
+            Resolver::Deref => image.u64_at(image_rva(base, address)?),
+        })
+}
+
+/// Converts an address to an RVA of the image loaded at `base`.
+fn image_rva(base: u64, address: u64) -> Result<u32, HypervisorError> {
+    address
+        .checked_sub(base)
+        .and_then(|rva| u32::try_from(rva).ok())
+        .ok_or(HypervisorError::InvalidPeImage)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::utils::pe::tests::TestImage,
+        alloc::{vec, vec::Vec},
+    };
+
+    /// The address the test images are loaded at.
+    const BASE: u64 = 0xFFFF_F800_0000_0000;
+
+    /// Encodes `mov rax, [rip + rel32]` at an RVA, loading the qword at the target RVA.
+    fn mov_rax_rip(rva: u32, target: u32) -> Vec<u8> {
+        let rel32 = target.wrapping_sub(rva + 7);
+
+        let mut bytes = vec![0x48, 0x8B, 0x05];
+        bytes.extend_from_slice(&rel32.to_le_bytes());
+        bytes
+    }
+
+    /// Builds an image loading the global pointer at 0x2000 from the instruction at 0x1100.
+    fn image() -> TestImage {
+        TestImage::new(0x3000)
+            .with_bytes(0x1100, &mov_rax_rip(0x1100, 0x2000))
+            .with_bytes(0x2000, &0xFFFF_F800_1234_5678_u64.to_le_bytes())
+    }
+
+    /// A signature following `mov rax, [rip + rel32]` to the global pointer and loading it.
+    const GLOBAL_POINTER: Signature = Signature {
+        name: "GlobalPointer",
+        patterns: &["11 22 33 44", "48 8B 05 ? ? ? ?"],
+        sections: &[],
+        resolvers: &[
+            Resolver::Rel32 {
+                offset: 3,
+                instruction_length: 7,
+            },
+            Resolver::Deref,
+        ],
+    };
+
+    #[test]
+    fn patterns_parse_bytes_and_wildcards() {
+        let pattern = Pattern::parse("48 ? ?? c3").unwrap();
+
+        assert_eq!(pattern.len(), 4);
+        assert!(pattern.matches(&[0x48, 0x01, 0x02, 0xC3, 0xFF]));
+        assert!(!pattern.matches(&[0x48, 0x01, 0x02, 0xC2]));
+        assert!(!pattern.matches(&[0x48, 0x01, 0x02]));
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        for pattern in ["", "   ", "48 GG", "123", "48 ???", "-1"] {
+            assert!(
+                matches!(Pattern::parse(pattern), Err(HypervisorError::HexParseError)),
+                "{:?}",
+                pattern
+            );
+        }
+    }
+
+    #[test]
+    fn find_all_returns_overlapping_matches() {
+        let data = [0xAA, 0xAA, 0xAA, 0xAA, 0xAA];
+        let pattern = Pattern::parse("AA ? AA").unwrap();
+
+        assert_eq!(pattern.find_all(&data).collect::<Vec<_>>(), [0, 1, 2]);
+        assert_eq!(pattern.find(&data), Some(0));
+    }
+
+    #[test]
+    fn find_all_anchors_after_leading_wildcards() {
+        let data = [0xBB, 0xBB, 0xCC, 0xBB];
+
+        let pattern = Pattern::parse("? BB").unwrap();
+        assert_eq!(pattern.find_all(&data).collect::<Vec<_>>(), [0, 2]);
+
+        let pattern = Pattern::parse("?? ??").unwrap();
+        assert_eq!(pattern.find_all(&data).collect::<Vec<_>>(), [0, 1, 2]);
+
+        let pattern = Pattern::parse("CC BB ?").unwrap();
+        assert_eq!(pattern.find(&data), None);
+    }
+
+    #[test]
+    fn resolvers_are_applied_in_order() {
+        let image = image();
+        let image = PeImage::parse(image.bytes()).unwrap();
+        let address = BASE + 0x1100;
+
+        assert_eq!(resolve(&image, BASE, address, &[]).unwrap(), address);
+        assert_eq!(
+            resolve(&image, BASE, address, &[Resolver::Add(-0x100)]).unwrap(),
+            BASE + 0x1000
+        );
+        assert_eq!(
+            resolve(&image, BASE, address, &GLOBAL_POINTER.resolvers[..1]).unwrap(),
+            BASE + 0x2000
+        );
+        assert_eq!(
+            resolve(&image, BASE, address, GLOBAL_POINTER.resolvers).unwrap(),
+            0xFFFF_F800_1234_5678
+        );
+
+        // The same instruction, reached from the start of the preceding bytes.
+        let resolvers = [
+            Resolver::Add(0x10),
+            GLOBAL_POINTER.resolvers[0],
+            GLOBAL_POINTER.resolvers[1],
+        ];
+        assert_eq!(
+            resolve(&image, BASE, BASE + 0x10F0, &resolvers).unwrap(),
+            0xFFFF_F800_1234_5678
+        );
+    }
+
+    #[test]
+    fn rel32_follows_negative_displacements() {
+        let image = TestImage::new(0x3000).with_bytes(0x2100, &mov_rax_rip(0x2100, 0x1800));
+        let image = PeImage::parse(image.bytes()).unwrap();
+
+        assert_eq!(
+            resolve(&image, BASE, BASE + 0x2100, &GLOBAL_POINTER.resolvers[..1]).unwrap(),
+            BASE + 0x1800
+        );
+    }
+
+    #[test]
+    fn resolvers_reading_outside_the_image_fail() {
+        let image = TestImage::new(0x3000).with_bytes(0x1100, &mov_rax_rip(0x1100, 0x4000));
+        let image = PeImage::parse(image.bytes()).unwrap();
+
+        assert!(resolve(&image, BASE, BASE + 0x1100, GLOBAL_POINTER.resolvers).is_err());
+        assert!(resolve(&image, BASE, BASE - 8, &[Resolver::Deref]).is_err());
+        assert!(resolve(&image, BASE, BASE + 0x1_0000_0000, &[Resolver::Deref]).is_err());
+        assert!(resolve(&image, BASE, BASE + 0x2FFC, &[Resolver::Deref]).is_err());
+    }
+
+    #[test]
+    fn signatures_use_the_first_pattern_with_matches() {
+        let image = image();
+        let image = PeImage::parse(image.bytes()).unwrap();
+
+        assert_eq!(
+            GLOBAL_POINTER.scan(&image, BASE).unwrap(),
+            [Match {
+                pattern_index: 1,
+                address: BASE + 0x1100,
+                target: 0xFFFF_F800_1234_5678,
+            }]
+        );
+        assert_eq!(
+            GLOBAL_POINTER.find(&image, BASE).unwrap(),
+            0xFFFF_F800_1234_5678
+        );
+    }
+
+    #[test]
+    fn signatures_skip_matches_that_fail_to_resolve() {
+        let image = image().with_bytes(0x1200, &mov_rax_rip(0x1200, 0x4000));
+        let image = PeImage::parse(image.bytes()).unwrap();
+
+        assert_eq!(GLOBAL_POINTER.scan(&image, BASE).unwrap().len(), 1);
+        assert_eq!(
+            GLOBAL_POINTER.find(&image, BASE).unwrap(),
+            0xFFFF_F800_1234_5678
+        );
+    }
+
+    #[test]
+    fn signatures_with_different_targets_are_ambiguous() {
+        // A second load of the same pointer resolves to the same target.
+        let image = image().with_bytes(0x1200, &mov_rax_rip(0x1200, 0x2000));
+        let parsed = PeImage::parse(image.bytes()).unwrap();
+        assert_eq!(GLOBAL_POINTER.scan(&parsed, BASE).unwrap().len(), 2);
+        assert_eq!(
+            GLOBAL_POINTER.find(&parsed, BASE).unwrap(),
+            0xFFFF_F800_1234_5678
+        );
+
+        let image = image.with_bytes(0x1300, &mov_rax_rip(0x1300, 0x2008));
+        let parsed = PeImage::parse(image.bytes()).unwrap();
+        assert!(matches!(
+            GLOBAL_POINTER.find(&parsed, BASE),
+            Err(HypervisorError::AmbiguousPattern)
+        ));
+    }
+
+    #[test]
+    fn signatures_only_search_the_given_sections() {
+        let image = image();
+        let image = PeImage::parse(image.bytes()).unwrap();
+
+        let signature = Signature {
+            sections: &["PAGE"],
+            ..GLOBAL_POINTER
+        };
+        assert!(matches!(
+            signature.find(&image, BASE),
+            Err(HypervisorError::PatternNotFound)
+        ));
+
+        let signature = Signature {
+            sections: &[".text"],
+            ..GLOBAL_POINTER
+        };
+        assert_eq!(signature.find(&image, BASE).unwrap(), 0xFFFF_F800_1234_5678);
+    }
+
+    #[test]
+    fn signatures_with_invalid_patterns_fail() {
+        let image = image();
+        let image = PeImage::parse(image.bytes()).unwrap();
+
+        let signature = Signature {
+            patterns: &["48 8B 0G"],
+            ..GLOBAL_POINTER
+        };
+        assert!(matches!(
+            signature.scan(&image, BASE),
+            Err(HypervisorError::HexParseError)
+        ));
+    }
+}
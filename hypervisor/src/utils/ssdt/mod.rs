@@ -1,3 +1,8 @@
 pub mod ssdt_find;
+#[cfg(windows)]
 pub mod ssdt_hook;
+#[cfg(windows)]
 pub mod sys_info;
+pub mod syscall_number;
+#[cfg(windows)]
+pub mod win32k;
@@ -1,6 +1,147 @@
 use crate::error::HypervisorError;
-use crate::utils::ssdt::sys_info::Sysinfo;
-use alloc::vec::Vec;
+use crate::utils::ssdt::syscall_number::SyscallNames;
+#[cfg(windows)]
+use crate::utils::{pe::PeImage, ssdt::sys_info::Sysinfo, windows_build};
+
+/// The service descriptor table (`KSERVICE_TABLE_DESCRIPTOR`).
+#[repr(C)]
+pub struct SSDTStruct {
+    /// The service table, containing the offsets of the services relative to the table, shifted left by 4.
+    /// The low 4 bits contain the number of arguments passed on the stack.
+    pub p_service_table: *const i32,
+
+    /// The counter table (only used by checked builds).
+    pub p_counter_table: *const u8,
+
+    /// The number of services in the table.
+    pub number_of_services: u64,
+
+    /// The argument table, containing the number of bytes of the stack arguments of each service.
+    pub p_argument_table: *const u8,
+}
+
+/// The service tables of the system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceTable {
+    /// The service table of ntoskrnl (`nt!KiServiceTable`).
+    Nt,
+
+    /// The service table of win32k (`win32k!W32pServiceTable`).
+    Win32k,
+}
+
+impl ServiceTable {
+    /// Returns the syscall number of the first service in the table.
+    pub const fn first_syscall_number(self) -> u32 {
+        match self {
+            ServiceTable::Nt => 0,
+            // Win32k APIs start from 0x1000
+            ServiceTable::Win32k => 0x1000,
+        }
+    }
+}
+
+/// An entry of a service table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SsdtEntry {
+    /// The service table containing the entry.
+    pub table: ServiceTable,
+
+    /// The index of the entry in the service table.
+    pub index: u32,
+
+    /// The decoded address of the service.
+    pub address: u64,
+
+    /// The number of bytes of the arguments passed on the stack, from the argument table.
+    pub argument_bytes: u8,
+
+    /// The number of arguments passed on the stack, from the low 4 bits of the service table entry.
+    pub stack_arguments: u8,
+}
+
+impl SsdtEntry {
+    /// Returns the syscall number of the entry.
+    pub const fn syscall_number(&self) -> u32 {
+        self.table.first_syscall_number() + self.index
+    }
+
+    /// Checks whether the service is located inside the given module.
+    ///
+    /// Services outside of the module that provides the table indicate a foreign SSDT modification.
+    ///
+    /// # Arguments
+    ///
+    /// * `module_base` - The base address of the module.
+    /// * `module_size` - The size of the module.
+    pub fn is_within(&self, module_base: u64, module_size: u64) -> bool {
+        (module_base..module_base + module_size).contains(&self.address)
+    }
+
+    /// Returns the `Nt*` name of the entry, if its name is known.
+    ///
+    /// The names of the Nt services come from the stubs exported by ntoskrnl (`SyscallNames::from_image`).
+    /// win32k doesn't export stubs for its services, so the names of the Win32k services must come from
+    /// the user mode stubs of win32u.dll (`SyscallNames::from_user_image`).
+    ///
+    /// # Arguments
+    ///
+    /// * `names` - The mapping between syscall numbers and names of the service table of the entry.
+    pub fn name<'a>(&self, names: &'a SyscallNames) -> Option<&'a str> {
+        names.name(self.syscall_number())
+    }
+}
+
+/// An iterator over the entries of a service table.
+pub struct SsdtEntries<'a> {
+    /// The service descriptor table.
+    descriptor: &'a SSDTStruct,
+
+    /// The service table that is iterated.
+    table: ServiceTable,
+
+    /// The index of the next entry.
+    index: u32,
+}
+
+impl<'a> SsdtEntries<'a> {
+    /// Decodes the entry at `index` of the service table.
+    fn decode(&self, index: u32) -> Option<SsdtEntry> {
+        if index as u64 >= self.descriptor.number_of_services {
+            return None;
+        }
+
+        let service_table = self.descriptor.p_service_table;
+
+        // The offset is signed, so it has to be shifted arithmetically.
+        let value = unsafe { service_table.add(index as usize).read() };
+        let address = (service_table as i64 + (value >> 4) as i64) as u64;
+
+        let argument_bytes = if self.descriptor.p_argument_table.is_null() {
+            0
+        } else {
+            unsafe { self.descriptor.p_argument_table.add(index as usize).read() }
+        };
+
+        Some(SsdtEntry {
+            table: self.table,
+            index,
+            address,
+            argument_bytes,
+            stack_arguments: (value & 0xF) as u8,
+        })
+    }
+}
+
+impl<'a> Iterator for SsdtEntries<'a> {
+    type Item = SsdtEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.decode(self.index)?;
+        self.index += 1;
+        Some(entry)
+    }
+}
 
 pub struct SsdtFind {
     pub nt_table: *const u64,
@@ -8,57 +149,30 @@ pub struct SsdtFind {
 }
 
 impl SsdtFind {
+    #[cfg(windows)]
     pub fn find_ssdt() -> Result<Self, HypervisorError> {
+        let build = windows_build::current()?;
+        log::debug!("Using the signatures of {}", build.name);
+
         let (kernel_base, kernel_size) = Self::get_kernel_base()?;
         log::debug!("Kernel base address: {:p}", kernel_base);
         log::debug!("Kernel size: {}", kernel_size);
 
-        /*
-           14042ba50  uint64_t KiSystemServiceStart(int64_t arg1, int64_t arg2, uint64_t arg3, int64_t arg4, int32_t arg5 @ rax, uint64_t arg6 @ rbx, int128_t* arg7 @ rbp, uint64_t arg8 @ ssp)
-
-           14042ba50  4889a390000000     mov     qword [rbx+0x90], rsp {__return_addr}
-           14042ba57  8bf8               mov     edi, eax
-           14042ba59  c1ef07             shr     edi, 0x7
-           14042ba5c  83e720             and     edi, 0x20
-           14042ba5f  25ff0f0000         and     eax, 0xfff
-
-           14042ba64  4c8d15555e9d00     lea     r10, [rel KeServiceDescriptorTable]
-           14042ba6b  4c8d1d8e368f00     lea     r11, [rel KeServiceDescriptorTableShadow]
-        */
-        let ki_service_system_start_pattern = "8B F8 C1 EF 07 83 E7 20 25 FF 0F 00 00";
-        let signature_size = 13;
-
         // Read Windows Kernel (ntoskrnl.exe) from memory
         let ntoskrnl_data =
             unsafe { core::slice::from_raw_parts(kernel_base as *const u8, kernel_size as usize) };
+        let ntoskrnl = PeImage::parse(ntoskrnl_data)?;
 
-        // Find the KiServiceSystemStart signature
-        let offset = Self::pattern_scan(ntoskrnl_data, ki_service_system_start_pattern)?
-            .ok_or(HypervisorError::PatternNotFound)?;
+        let ke_service_descriptor_table_shadow = build
+            .ke_service_descriptor_table_shadow
+            .find(&ntoskrnl, kernel_base as u64)?
+            as *const u8;
 
-        // Calculate the address of KiServiceSystemStart using .add(),
-        // which is, `14042ba57  8bf8               mov     edi, eax` in this case.
-        let ki_service_system_start = unsafe { kernel_base.add(offset) };
         log::info!(
-            "KiServiceSystemStart address: {:p}",
-            ki_service_system_start
+            "KeServiceDescriptorTableShadow address: {:p}",
+            ke_service_descriptor_table_shadow
         );
 
-        // Address of the 'lea r10, [rel KeServiceDescriptorTable]' instruction
-        let lea_r10_address = unsafe { ki_service_system_start.add(signature_size) };
-
-        // Address of the 'lea r11, [rel KeServiceDescriptorTableShadow]' instruction
-        let lea_r11_address = unsafe { lea_r10_address.add(7) }; // 7 bytes after lea r10
-
-        // Reading the 4-byte relative offset for KeServiceDescriptorTableShadow
-        let relative_offset = unsafe { *(lea_r11_address.add(3) as *const i32) }; // 3 bytes after the opcode
-
-        log::info!("Relative offset: {:x}", relative_offset);
-
-        // Compute the absolute address of KeServiceDescriptorTableShadow
-        let ke_service_descriptor_table_shadow =
-            unsafe { lea_r11_address.add(7).offset(relative_offset as isize) };
-
         // Extracting nt!KiServiceTable and win32k!W32pServiceTable addresses
         let shadow = ke_service_descriptor_table_shadow;
 
@@ -66,7 +180,7 @@ impl SsdtFind {
         let nt_table = shadow as *const u64;
 
         // Win32kTable Address of Win32k Syscall Table
-        let win32k_table = unsafe { shadow.offset(0x20) as *const u64 };
+        let win32k_table = unsafe { shadow.add(build.offsets.win32k_descriptor) as *const u64 };
 
         log::info!("NtTable address: {:p}", nt_table);
         log::info!("Win32kTable address: {:p}", win32k_table);
@@ -77,11 +191,131 @@ impl SsdtFind {
         })
     }
 
+    /// Returns the service descriptor table of the given service table.
+    ///
+    /// Note that the Win32k service table is located in session space and is only valid while
+    /// attached to a GUI process.
+    pub fn descriptor(&self, table: ServiceTable) -> Result<&SSDTStruct, HypervisorError> {
+        let descriptor = match table {
+            ServiceTable::Nt => unsafe { &*(self.nt_table as *const SSDTStruct) },
+            ServiceTable::Win32k => unsafe { &*(self.win32k_table as *const SSDTStruct) },
+        };
+
+        if descriptor.p_service_table.is_null() {
+            return Err(HypervisorError::SsdtNotFound);
+        }
+
+        Ok(descriptor)
+    }
+
+    /// Returns an iterator over all entries of the given service table.
+    ///
+    /// # Arguments
+    ///
+    /// * `table` - The service table to enumerate.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<SsdtEntries, HypervisorError>` - The iterator, or an error if the service table isn't available.
+    pub fn entries(&self, table: ServiceTable) -> Result<SsdtEntries<'_>, HypervisorError> {
+        Ok(SsdtEntries {
+            descriptor: self.descriptor(table)?,
+            table,
+            index: 0,
+        })
+    }
+
+    /// Returns the entry at `index` of the given service table.
+    ///
+    /// # Arguments
+    ///
+    /// * `table` - The service table containing the entry.
+    /// * `index` - The index of the entry, relative to the start of the table.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<SsdtEntry, HypervisorError>` - The entry, or an error if the index is out of range.
+    pub fn entry(&self, table: ServiceTable, index: u32) -> Result<SsdtEntry, HypervisorError> {
+        self.entries(table)?
+            .decode(index)
+            .ok_or(HypervisorError::SsdtEntryNotFound)
+    }
+
+    /// Finds the entry of an Nt service by its name.
+    ///
+    /// # Arguments
+    ///
+    /// * `names` - The mapping between syscall numbers and names.
+    /// * `name` - The name of the service, for example `NtCreateFile`.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<SsdtEntry, HypervisorError>` - The entry, or an error if the name is unknown.
+    pub fn entry_by_name(
+        &self,
+        names: &SyscallNames,
+        name: &str,
+    ) -> Result<SsdtEntry, HypervisorError> {
+        let number = names
+            .number(name)
+            .ok_or(HypervisorError::SyscallNumberNotFound)?;
+
+        self.entry(ServiceTable::Nt, number)
+    }
+
+    /// Builds the mapping between syscall numbers and names from the `Zw*` stubs exported by ntoskrnl.
+    #[cfg(windows)]
+    pub fn syscall_names() -> Result<SyscallNames, HypervisorError> {
+        let (kernel_base, kernel_size) = Self::get_kernel_base()?;
+
+        // Read Windows Kernel (ntoskrnl.exe) from memory
+        let ntoskrnl_data =
+            unsafe { core::slice::from_raw_parts(kernel_base as *const u8, kernel_size as usize) };
+
+        SyscallNames::from_image(ntoskrnl_data)
+    }
+
+    /// Logs all entries of the Nt service table with their names, and flags the entries that
+    /// point outside of ntoskrnl.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<usize, HypervisorError>` - The number of entries pointing outside of ntoskrnl.
+    #[cfg(windows)]
+    pub fn dump_nt_table(&self) -> Result<usize, HypervisorError> {
+        let (kernel_base, kernel_size) = Self::get_kernel_base()?;
+        let names = Self::syscall_names()?;
+        let mut foreign_entries = 0;
+
+        for entry in self.entries(ServiceTable::Nt)? {
+            let foreign = !entry.is_within(kernel_base as u64, kernel_size as u64);
+            if foreign {
+                foreign_entries += 1;
+            }
+
+            log::info!(
+                "{:#06x} {:#x} {} (stack arguments: {}){}",
+                entry.syscall_number(),
+                entry.address,
+                entry.name(&names).unwrap_or("<unknown>"),
+                entry.stack_arguments,
+                if foreign {
+                    " [outside of ntoskrnl]"
+                } else {
+                    ""
+                }
+            );
+        }
+
+        Ok(foreign_entries)
+    }
+
     /// Gets the base address and size of the kernel module.
     ///
     /// # Returns
     ///
     /// A tuple with the base address and size of the kernel module.
+    #[cfg(windows)]
     pub fn get_kernel_base() -> Result<(*mut u8, u32), HypervisorError> {
         let mut sys_info = Sysinfo::new()?;
 
@@ -91,36 +325,44 @@ impl SsdtFind {
 
         Ok((kernel_base as _, kernel_size))
     }
+}
 
-    /// Convert a combo pattern to bytes without wildcards
-    pub fn get_bytes_as_hex(pattern: &str) -> Result<Vec<Option<u8>>, HypervisorError> {
-        let mut pattern_bytes = Vec::new();
-
-        for x in pattern.split_whitespace() {
-            match x {
-                "?" => pattern_bytes.push(None),
-                _ => pattern_bytes.push(
-                    u8::from_str_radix(x, 16)
-                        .map(Some)
-                        .map_err(|_| HypervisorError::HexParseError)?,
-                ),
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use {super::*, core::ptr::null};
 
-        Ok(pattern_bytes)
+    /// Encodes a service table entry from the offset of the service and the number of stack arguments.
+    fn encode(offset: i32, stack_arguments: i32) -> i32 {
+        (offset << 4) | stack_arguments
     }
 
-    /// Pattern or Signature scan a region of memory
-    pub fn pattern_scan(data: &[u8], pattern: &str) -> Result<Option<usize>, HypervisorError> {
-        let pattern_bytes = Self::get_bytes_as_hex(pattern)?;
+    #[test]
+    fn entries_decode_the_offsets_and_stack_arguments() {
+        let service_table = [encode(0x100, 0), encode(-0x40, 7), encode(0x7FF_FFF0, 0xF)];
+        let argument_table = [0u8, 0x38, 0x78];
+        let descriptor = SSDTStruct {
+            p_service_table: service_table.as_ptr(),
+            p_counter_table: null(),
+            number_of_services: service_table.len() as u64,
+            p_argument_table: argument_table.as_ptr(),
+        };
+        let base = service_table.as_ptr() as u64;
 
-        let offset = data.windows(pattern_bytes.len()).position(|window| {
-            window
-                .iter()
-                .zip(&pattern_bytes)
-                .all(|(byte, pattern_byte)| pattern_byte.map_or(true, |b| *byte == b))
-        });
+        let entries = SsdtEntries {
+            descriptor: &descriptor,
+            table: ServiceTable::Win32k,
+            index: 0,
+        }
+        .collect::<alloc::vec::Vec<_>>();
 
-        Ok(offset)
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].address, base + 0x100);
+        assert_eq!(entries[0].stack_arguments, 0);
+        assert_eq!(entries[1].address, base - 0x40);
+        assert_eq!(entries[1].stack_arguments, 7);
+        assert_eq!(entries[1].argument_bytes, 0x38);
+        assert_eq!(entries[1].syscall_number(), 0x1001);
+        assert_eq!(entries[2].address, base + 0x7FF_FFF0);
+        assert_eq!(entries[2].stack_arguments, 0xF);
     }
 }
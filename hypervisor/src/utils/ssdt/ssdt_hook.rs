@@ -1,13 +1,7 @@
 use crate::error::HypervisorError;
-use crate::utils::ssdt::ssdt_find::SsdtFind;
-
-#[repr(C)]
-struct SSDTStruct {
-    p_service_table: *const i32,
-    p_counter_table: *const u8,
-    number_of_services: u64,
-    p_argument_table: *const u8,
-}
+use crate::utils::ssdt::ssdt_find::{ServiceTable, SsdtFind};
+use crate::utils::ssdt::syscall_number::resolve_syscall_number;
+use crate::utils::ssdt::win32k::{resolve_win32k_function, SessionAttachment};
 
 pub struct SsdtHook {
     /// The original function address.
@@ -15,46 +9,108 @@ pub struct SsdtHook {
 
     /// The hook function address
     pub api_number: i32,
+
+    /// The attachment to a GUI process for Win32k syscalls, which keeps the session space containing
+    /// `function_address` mapped until the `SsdtHook` is dropped. The hook must be installed before that.
+    pub session: Option<SessionAttachment>,
 }
 
 /// Find entry from SSDT table of Nt functions and Win32k syscalls
 impl SsdtHook {
+    /// Finds the function address of a syscall from its SSDT entry.
+    ///
+    /// Win32k syscalls are resolved while attached to a GUI process, following the thunks in win32k.sys
+    /// to the implementation in win32kbase.sys or win32kfull.sys. The returned address is located in
+    /// session space, so the returned `SsdtHook` stays attached to the process until it's dropped, and
+    /// the hook on the address has to be installed before dropping it.
+    ///
+    /// # Arguments
+    ///
+    /// * `api_number` - The syscall number. Win32k syscall numbers start at 0x1000.
+    /// * `get_from_win32k` - Whether the syscall is a Win32k syscall.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, HypervisorError>` - The function address of the syscall, or an error if the entry couldn't be found.
     pub fn find_ssdt_function_address(
-        mut api_number: i32,
+        api_number: i32,
         get_from_win32k: bool,
     ) -> Result<Self, HypervisorError> {
         log::debug!("Finding SSDT function address");
 
+        if get_from_win32k {
+            return Self::find_win32k_function_address(api_number);
+        }
+
         let ssdt = SsdtFind::find_ssdt()?;
 
+        let table = ServiceTable::Nt;
+
         // Index of the function to hook
-        let ssdt = if !get_from_win32k {
-            unsafe { &*(ssdt.nt_table as *const SSDTStruct) }
-        } else {
-            // Win32k APIs start from 0x1000
-            api_number = api_number - 0x1000;
-            unsafe { &*(ssdt.win32k_table as *const SSDTStruct) }
-        };
-
-        let ssdt_base = ssdt.p_service_table as *mut u8;
-
-        if ssdt_base.is_null() {
-            return Err(HypervisorError::SsdtNotFound);
-        }
+        let index = (api_number as u32).wrapping_sub(table.first_syscall_number());
 
-        log::info!("SSDT base address: {:p}", ssdt_base);
+        log::info!(
+            "SSDT base address: {:p}",
+            ssdt.descriptor(table)?.p_service_table
+        );
 
-        // Calculate offset
-        let offset = unsafe { ssdt.p_service_table.add(api_number as usize).read() as usize >> 4 };
+        let entry = ssdt.entry(table, index)?;
 
-        // Add offset to base address
-        let function_address = unsafe { ssdt_base.add(offset) as *const u8 };
+        let function_address = entry.address as *const u8;
 
         log::info!("SSDT function address: {:p}", function_address);
 
         Ok(Self {
             function_address,
             api_number,
+            session: None,
+        })
+    }
+
+    /// Finds the SSDT entry of an Nt function by its name.
+    ///
+    /// The syscall number is resolved from the `Zw*` stub exported by ntoskrnl, so it doesn't
+    /// depend on a specific Windows build.
+    ///
+    /// # Arguments
+    ///
+    /// * `function_name` - The name of the syscall, for example `NtCreateFile`.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, HypervisorError>` - The SSDT entry of the function, or an error if the syscall number couldn't be resolved.
+    pub fn find_ssdt_function_address_by_name(
+        function_name: &str,
+    ) -> Result<Self, HypervisorError> {
+        let (kernel_base, kernel_size) = SsdtFind::get_kernel_base()?;
+
+        // Read Windows Kernel (ntoskrnl.exe) from memory
+        let ntoskrnl_data =
+            unsafe { core::slice::from_raw_parts(kernel_base as *const u8, kernel_size as usize) };
+
+        let api_number = resolve_syscall_number(ntoskrnl_data, function_name)?;
+
+        log::debug!("Syscall number of {}: {:#x}", function_name, api_number);
+
+        Self::find_ssdt_function_address(api_number as i32, false)
+    }
+
+    /// Finds the implementation of a Win32k syscall and stays attached to a GUI process, so the address remains mapped.
+    fn find_win32k_function_address(api_number: i32) -> Result<Self, HypervisorError> {
+        let attachment = SessionAttachment::attach_to_gui_process()?;
+
+        let function = resolve_win32k_function(api_number as u32, &attachment)?;
+
+        log::info!(
+            "Win32k function address: {:#x} ({:?})",
+            function.address,
+            function.module
+        );
+
+        Ok(Self {
+            function_address: function.address as *const u8,
+            api_number,
+            session: Some(attachment),
         })
     }
 }
@@ -95,6 +95,71 @@ impl Sysinfo {
 
         None
     }
+
+    /// Finds a module by its file name, ignoring the case.
+    ///
+    /// The extension can be omitted, as in the names of forwarded exports (`NTOSKRNL.KeBugCheckEx`).
+    ///
+    /// # Arguments
+    ///
+    /// * `module_name` - The file name of the module, for example `ntoskrnl.exe`, `CI.dll` or `tcpip`.
+    ///
+    /// # Returns
+    ///
+    /// A tuple with the base address and size of the module if found, or `None` if not found.
+    pub fn find_module(&self, module_name: &str) -> Option<(*mut c_void, u32)> {
+        let module_info = unsafe { &*self.module_info };
+        let module_name = module_name.as_bytes();
+
+        // The buffer is sized for all the modules, which can be more than the declared array length.
+        let modules = unsafe {
+            core::slice::from_raw_parts(
+                module_info.modules.as_ptr(),
+                module_info.modules_count as usize,
+            )
+        };
+
+        modules
+            .iter()
+            .find(|module| {
+                let file_name = module.file_name();
+                let stem = file_name
+                    .rfind_byte(b'.')
+                    .map_or(file_name, |dot| &file_name[..dot]);
+
+                file_name.eq_ignore_ascii_case(module_name)
+                    || (!module_name.contains(&b'.') && stem.eq_ignore_ascii_case(module_name))
+            })
+            .map(|module| (module.image_base, module.size))
+    }
+
+    /// Finds the module containing an address.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - An address within the image of the module.
+    ///
+    /// # Returns
+    ///
+    /// A tuple with the base address and size of the module if found, or `None` if not found.
+    pub fn module_containing(&self, address: u64) -> Option<(*mut c_void, u32)> {
+        let module_info = unsafe { &*self.module_info };
+
+        let modules = unsafe {
+            core::slice::from_raw_parts(
+                module_info.modules.as_ptr(),
+                module_info.modules_count as usize,
+            )
+        };
+
+        modules
+            .iter()
+            .find(|module| {
+                let base = module.image_base as u64;
+                address >= base && address - base < module.size as u64
+            })
+            .map(|module| (module.image_base, module.size))
+    }
 }
 
 impl Drop for Sysinfo {
@@ -129,6 +194,11 @@ pub struct SystemModuleInformation {
     pub modules: [SystemModule; 256],
 }
 
+/// A loaded module (`RTL_PROCESS_MODULE_INFORMATION`).
+///
+/// The four fields after `flags` are 16-bit, as defined by the kernel. `find_module` relies on
+/// `offset_to_file_name` to compare the file name instead of the full path. With 8-bit fields, the entries
+/// were four bytes too short, so `image_name` and all the modules after the first were read at the wrong offsets.
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct SystemModule {
@@ -137,9 +207,24 @@ pub struct SystemModule {
     pub image_base: *mut c_void,
     pub size: u32,
     pub flags: u32,
-    pub index: u8,
-    pub name_length: u8,
-    pub load_count: u8,
-    pub path_length: u8,
+    pub load_order_index: u16,
+    pub init_order_index: u16,
+    pub load_count: u16,
+    /// Offset of the file name in `image_name`, which holds the full path.
+    pub offset_to_file_name: u16,
     pub image_name: [u8; 256],
 }
+
+impl SystemModule {
+    /// Returns the file name of the module, for example `ntoskrnl.exe`.
+    pub fn file_name(&self) -> &[u8] {
+        let path = &self.image_name[..self
+            .image_name
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(self.image_name.len())];
+
+        path.get(self.offset_to_file_name as usize..)
+            .unwrap_or(path)
+    }
+}
@@ -0,0 +1,411 @@
+//! Resolves system call numbers by name from the system call stubs of a PE image.
+//!
+//! Both `ntdll.dll` and `ntoskrnl.exe` export stubs that load the system call number into EAX:
+//!
+//! ```asm
+//! ; ntdll!NtCreateFile
+//! mov r10, rcx
+//! mov eax, 55h
+//! ...
+//! syscall
+//!
+//! ; nt!ZwCreateFile
+//! mov rax, rsp
+//! cli
+//! sub rsp, 10h
+//! push rax
+//! pushfq
+//! push 10h
+//! lea rax, [KiServiceLinkage]
+//! push rax
+//! mov eax, 55h
+//! jmp KiServiceInternal
+//! ```
+//!
+//! Only exports with exactly these shapes are accepted as stubs. The `Nt*` exports of ntoskrnl are the
+//! implementations of the system calls, which can start with `mov eax, imm32` as well.
+//!
+//! The functions in this module operate on the bytes of a mapped image only, so they don't depend on the kernel.
+
+use {
+    crate::{error::HypervisorError, utils::pe::PeImage},
+    alloc::{format, string::String, vec, vec::Vec},
+    iced_x86::{Code, Decoder, DecoderOptions, FlowControl, Instruction, Register},
+};
+
+/// Maximum number of bytes of a system call stub that are searched for the system call number.
+pub const MAX_STUB_LEN: usize = 64;
+
+/// The first instruction of the user mode system call stubs (`mov r10, rcx`).
+const USER_STUB_PROLOGUE: [u8; 3] = [0x4C, 0x8B, 0xD1];
+
+/// The first instructions of the kernel mode system call stubs (`mov rax, rsp; cli`).
+const KERNEL_STUB_PROLOGUE: [u8; 4] = [0x48, 0x8B, 0xC4, 0xFA];
+
+/// Maximum number of instructions of a system call stub that are searched for the system call number.
+const MAX_STUB_INSTRUCTIONS: usize = 16;
+
+/// Extracts the system call number from the bytes of a system call stub.
+///
+/// The user mode stubs load the system call number with the `mov eax, imm32` right after `mov r10, rcx`.
+/// The kernel mode stubs start with `mov rax, rsp; cli`, build an interrupt frame and load the system call
+/// number with the `mov eax, imm32` right before the jmp to `KiServiceInternal`. Any other bytes, such as
+/// a function starting with `mov eax, imm32`, aren't a stub.
+///
+/// # Arguments
+///
+/// * `stub` - The bytes at the start of the stub.
+///
+/// # Returns
+///
+/// * `Option<u32>` - The system call number, or `None` if the bytes don't contain a system call stub.
+pub fn syscall_number_from_stub(stub: &[u8]) -> Option<u32> {
+    let stub = &stub[..stub.len().min(MAX_STUB_LEN)];
+
+    if let Some(stub) = stub.strip_prefix(&USER_STUB_PROLOGUE) {
+        let instr = Decoder::new(64, stub, DecoderOptions::NONE).decode();
+
+        return is_syscall_number_load(&instr).then(|| instr.immediate32());
+    }
+
+    let stub = stub.strip_prefix(&KERNEL_STUB_PROLOGUE)?;
+    let mut decoder = Decoder::new(64, stub, DecoderOptions::NONE);
+
+    for _ in 0..MAX_STUB_INSTRUCTIONS {
+        let instr = decoder.decode();
+        if instr.is_invalid() || instr.flow_control() != FlowControl::Next {
+            return None;
+        }
+
+        if is_syscall_number_load(&instr) {
+            let jmp = decoder.decode();
+
+            return (!jmp.is_invalid() && jmp.flow_control() == FlowControl::UnconditionalBranch)
+                .then(|| instr.immediate32());
+        }
+    }
+
+    None
+}
+
+/// Checks whether an instruction is the `mov eax, imm32` loading the system call number.
+fn is_syscall_number_load(instr: &Instruction) -> bool {
+    instr.code() == Code::Mov_r32_imm32 && instr.op0_register() == Register::EAX
+}
+
+/// Resolves the system call number of a function from the stubs exported by a mapped PE image.
+///
+/// For `ntoskrnl.exe` the `Nt*` exports are the actual implementations, so the corresponding `Zw*`
+/// stub is tried first, and the `Nt*` export is only used if it has the shape of a stub. For `ntdll.dll`
+/// both names refer to the same stub.
+///
+/// # Arguments
+///
+/// * `image` - The bytes of the image, mapped with its section alignment (as loaded in memory).
+/// * `name` - The name of the system call, for example `NtCreateFile` or `ZwCreateFile`.
+///
+/// # Returns
+///
+/// * `Result<u32, HypervisorError>` - The system call number, or an error if no stub for the name was found.
+pub fn resolve_syscall_number(image: &[u8], name: &str) -> Result<u32, HypervisorError> {
+    let exports = PeImage::parse(image)?.exports()?;
+    let mut last_error = HypervisorError::ExportNotFound;
+
+    for candidate in stub_names(name) {
+        // Forwarded exports don't have a stub in this image.
+        let Some(rva) = exports
+            .by_name(&candidate)
+            .ok()
+            .and_then(|export| export.rva())
+        else {
+            continue;
+        };
+
+        let stub = image
+            .get(rva as usize..)
+            .ok_or(HypervisorError::InvalidPeImage)?;
+
+        match syscall_number_from_stub(stub) {
+            Some(number) => {
+                log::trace!("Resolved {} to syscall number {:#x}", candidate, number);
+                return Ok(number);
+            }
+            None => last_error = HypervisorError::SyscallNumberNotFound,
+        }
+    }
+
+    Err(last_error)
+}
+
+/// Returns the names of the exports that can contain the system call stub of `name`, in order of preference.
+fn stub_names(name: &str) -> Vec<String> {
+    if let Some(base) = name.strip_prefix("Nt") {
+        vec![format!("Zw{}", base), String::from(name)]
+    } else if let Some(base) = name.strip_prefix("Zw") {
+        vec![String::from(name), format!("Nt{}", base)]
+    } else {
+        vec![String::from(name)]
+    }
+}
+
+/// A mapping between system call numbers and `Nt*` names.
+#[derive(Debug, Clone, Default)]
+pub struct SyscallNames {
+    /// The system call numbers and names, sorted by number.
+    names: Vec<(u32, String)>,
+}
+
+impl SyscallNames {
+    /// Builds the mapping from the `Zw*` stubs exported by a mapped PE image.
+    ///
+    /// Note that ntoskrnl only exports stubs for a subset of the system calls, while ntdll exports all of them.
+    ///
+    /// # Arguments
+    ///
+    /// * `image` - The bytes of the image, mapped with its section alignment (as loaded in memory).
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, HypervisorError>` - The mapping, or an error if the image is invalid.
+    pub fn from_image(image: &[u8]) -> Result<Self, HypervisorError> {
+        let image = PeImage::parse(image)?;
+
+        Self::from_exports(&image, |name, _| {
+            name.strip_prefix("Zw").map(|base| format!("Nt{}", base))
+        })
+    }
+
+    /// Builds the mapping from the `Nt*` stubs exported by a user mode image, such as ntdll.dll or win32u.dll.
+    ///
+    /// win32k doesn't export stubs for its services, so win32u.dll is the source of the names of the
+    /// Win32k services. Only the exports starting with `mov r10, rcx` are considered stubs, which excludes
+    /// the `Nt*` exports of ntdll.dll that are regular functions.
+    ///
+    /// # Arguments
+    ///
+    /// * `image` - The image, either mapped or read from disk (for example `\SystemRoot\System32\win32u.dll`).
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, HypervisorError>` - The mapping, or an error if the image has no exports.
+    pub fn from_user_image(image: &PeImage) -> Result<Self, HypervisorError> {
+        Self::from_exports(image, |name, stub| {
+            (name.starts_with("Nt") && stub.starts_with(&USER_STUB_PROLOGUE))
+                .then(|| String::from(name))
+        })
+    }
+
+    /// Builds the mapping from the exported stubs for which `name_of` returns the `Nt*` name.
+    fn from_exports(
+        image: &PeImage,
+        name_of: impl Fn(&str, &[u8]) -> Option<String>,
+    ) -> Result<Self, HypervisorError> {
+        let mut names = Vec::new();
+
+        for export in image.exports()?.iter() {
+            let (Some(name), Some(stub)) = (
+                export.name,
+                export.rva().and_then(|rva| image.bytes_at(rva)),
+            ) else {
+                continue;
+            };
+
+            let Some(name) = name_of(name, stub) else {
+                continue;
+            };
+
+            let Some(number) = syscall_number_from_stub(stub) else {
+                continue;
+            };
+
+            names.push((number, name));
+        }
+
+        names.sort_unstable_by_key(|(number, _)| *number);
+        names.dedup_by_key(|(number, _)| *number);
+
+        Ok(Self { names })
+    }
+
+    /// Returns the `Nt*` name of a system call number.
+    pub fn name(&self, number: u32) -> Option<&str> {
+        self.names
+            .binary_search_by_key(&number, |(number, _)| *number)
+            .ok()
+            .map(|index| self.names[index].1.as_str())
+    }
+
+    /// Returns the system call number of an `Nt*` or `Zw*` name.
+    pub fn number(&self, name: &str) -> Option<u32> {
+        let base = name
+            .strip_prefix("Nt")
+            .or_else(|| name.strip_prefix("Zw"))
+            .unwrap_or(name);
+
+        self.names
+            .iter()
+            .find(|(_, candidate)| candidate.strip_prefix("Nt") == Some(base))
+            .map(|(number, _)| *number)
+    }
+
+    /// Returns an iterator over the system call numbers and names, sorted by number.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &str)> {
+        self.names
+            .iter()
+            .map(|(number, name)| (*number, name.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::utils::pe::tests::map_image};
+
+    /// The stub of `ntdll!NtCreateFile`.
+    const NTDLL_STUB: &[u8] = &[
+        0x4C, 0x8B, 0xD1, // mov r10, rcx
+        0xB8, 0x55, 0x00, 0x00, 0x00, // mov eax, 55h
+        0xF6, 0x04, 0x25, 0x08, 0x03, 0xFE, 0x7F, 0x01, // test byte ptr [7FFE0308h], 1
+        0x75, 0x03, // jne
+        0x0F, 0x05, // syscall
+        0xC3, // ret
+    ];
+
+    /// The stub of `nt!ZwCreateFile`.
+    const KERNEL_STUB: &[u8] = &[
+        0x48, 0x8B, 0xC4, // mov rax, rsp
+        0xFA, // cli
+        0x48, 0x83, 0xEC, 0x10, // sub rsp, 10h
+        0x50, // push rax
+        0x9C, // pushfq
+        0x6A, 0x10, // push 10h
+        0x48, 0x8D, 0x05, 0x00, 0x00, 0x00, 0x00, // lea rax, [KiServiceLinkage]
+        0x50, // push rax
+        0xB8, 0x55, 0x00, 0x00, 0x00, // mov eax, 55h
+        0xE9, 0x00, 0x00, 0x00, 0x00, // jmp KiServiceInternal
+    ];
+
+    /// A function that isn't a stub, such as the implementation of `nt!NtCreateFile`.
+    const IMPLEMENTATION: &[u8] = &[
+        0x48, 0x83, 0xEC, 0x28, // sub rsp, 28h
+        0xE8, 0x00, 0x00, 0x00, 0x00, // call
+        0xB8, 0x01, 0x00, 0x00, 0x00, // mov eax, 1
+        0x48, 0x83, 0xC4, 0x28, // add rsp, 28h
+        0xC3, // ret
+    ];
+
+    /// An implementation starting with `mov eax, imm32`, such as a system call returning a constant status.
+    const CONSTANT_IMPLEMENTATION: &[u8] = &[
+        0xB8, 0xBB, 0x00, 0x00, 0xC0, // mov eax, STATUS_NOT_SUPPORTED
+        0xC3, // ret
+    ];
+
+    /// A kernel image exporting the stubs of `ZwCreateFile` (0x55) and `ZwClose` (0xF), the implementations
+    /// of `NtCreateFile` (also exported as `ZwImplementation`) and `NtConstant`, and a forwarded `ZwForwarded`.
+    const KERNEL: &[u8] = include_bytes!("../../../tests/fixtures/kernel.sys");
+
+    /// A user mode image exporting the stubs of `NtGdiCreateFile` (0x55) and `NtUserGetDC` (0x100A, also
+    /// exported as `ZwUserGetDC`), and the regular function `NtUserRegularFunction`.
+    const USER: &[u8] = include_bytes!("../../../tests/fixtures/user.dll");
+
+    /// Maps the kernel image, as it's loaded in memory.
+    fn kernel_image() -> Vec<u8> {
+        map_image(KERNEL)
+    }
+
+    #[test]
+    fn syscall_number_is_read_from_the_stubs() {
+        assert_eq!(syscall_number_from_stub(NTDLL_STUB), Some(0x55));
+        assert_eq!(syscall_number_from_stub(KERNEL_STUB), Some(0x55));
+    }
+
+    #[test]
+    fn syscall_number_is_not_searched_past_the_flow_of_the_stub() {
+        assert_eq!(syscall_number_from_stub(IMPLEMENTATION), None);
+        assert_eq!(
+            syscall_number_from_stub(&[0xC3, 0xB8, 0x55, 0x00, 0x00, 0x00]),
+            None
+        );
+        assert_eq!(syscall_number_from_stub(&KERNEL_STUB[..22]), None);
+        assert_eq!(syscall_number_from_stub(&KERNEL_STUB[..25]), None);
+        assert_eq!(syscall_number_from_stub(&[]), None);
+    }
+
+    #[test]
+    fn implementations_loading_eax_are_not_stubs() {
+        let image = kernel_image();
+
+        assert_eq!(syscall_number_from_stub(CONSTANT_IMPLEMENTATION), None);
+        assert!(matches!(
+            resolve_syscall_number(&image, "NtConstant"),
+            Err(HypervisorError::SyscallNumberNotFound)
+        ));
+        assert!(matches!(
+            resolve_syscall_number(&image, "ZwConstant"),
+            Err(HypervisorError::SyscallNumberNotFound)
+        ));
+    }
+
+    #[test]
+    fn nt_names_resolve_through_the_zw_stub() {
+        let image = kernel_image();
+
+        assert_eq!(
+            resolve_syscall_number(&image, "NtCreateFile").unwrap(),
+            0x55
+        );
+        assert_eq!(
+            resolve_syscall_number(&image, "ZwCreateFile").unwrap(),
+            0x55
+        );
+        assert_eq!(resolve_syscall_number(&image, "NtClose").unwrap(), 0xF);
+    }
+
+    #[test]
+    fn unresolvable_names_are_reported() {
+        let image = kernel_image();
+
+        assert!(matches!(
+            resolve_syscall_number(&image, "NtMissing"),
+            Err(HypervisorError::ExportNotFound)
+        ));
+        assert!(matches!(
+            resolve_syscall_number(&image, "ZwForwarded"),
+            Err(HypervisorError::ExportNotFound)
+        ));
+        assert!(matches!(
+            resolve_syscall_number(&image, "ZwImplementation"),
+            Err(HypervisorError::SyscallNumberNotFound)
+        ));
+        assert!(matches!(
+            resolve_syscall_number(&[0; 0x40], "NtCreateFile"),
+            Err(HypervisorError::InvalidPeImage)
+        ));
+    }
+
+    #[test]
+    fn names_are_mapped_by_number() {
+        let image = kernel_image();
+        let names = SyscallNames::from_image(&image).unwrap();
+
+        assert_eq!(
+            names.iter().collect::<Vec<_>>(),
+            [(0xF, "NtClose"), (0x55, "NtCreateFile")]
+        );
+        assert_eq!(names.name(0x55), Some("NtCreateFile"));
+        assert_eq!(names.name(0x56), None);
+        assert_eq!(names.number("ZwClose"), Some(0xF));
+        assert_eq!(names.number("NtCreateFile"), Some(0x55));
+        assert_eq!(names.number("NtMissing"), None);
+    }
+
+    #[test]
+    fn user_names_are_read_from_the_nt_stubs() {
+        let image = map_image(USER);
+        let names = SyscallNames::from_user_image(&PeImage::parse(&image).unwrap()).unwrap();
+
+        assert_eq!(
+            names.iter().collect::<Vec<_>>(),
+            [(0x55, "NtGdiCreateFile"), (0x100A, "NtUserGetDC")]
+        );
+    }
+}
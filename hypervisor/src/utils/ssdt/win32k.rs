@@ -0,0 +1,385 @@
+//! Provides resolution and hooking of Win32k system calls.
+//!
+//! The Win32k service table (`win32k!W32pServiceTable`) and the functions it references are located in session
+//! space, which is only mapped while attached to a process of a session with a GUI. Since Windows 10, the entries
+//! of the table point to thunks in `win32k.sys`, which jump to the implementations in `win32kbase.sys` and
+//! `win32kfull.sys` through their import address table.
+//!
+//! Session-space pages are shared by the processes of a session, so EPT hooks on Win32k functions created while
+//! attached to a process apply to the whole session of that process.
+
+#![allow(non_snake_case)]
+
+use {
+    crate::{
+        error::HypervisorError,
+        intel::ept::hooks::Hook,
+        utils::{
+            function_hook::HookHandler,
+            ssdt::{
+                ssdt_find::{ServiceTable, SsdtEntry, SsdtFind},
+                sys_info::Sysinfo,
+            },
+        },
+    },
+    alloc::{boxed::Box, vec::Vec},
+    iced_x86::{Code, Decoder, DecoderOptions, OpKind},
+    wdk_sys::{
+        ntddk::{
+            KeStackAttachProcess, KeUnstackDetachProcess, ObfDereferenceObject,
+            PsLookupProcessByProcessId,
+        },
+        _KAPC_STATE, HANDLE, NT_SUCCESS, PEPROCESS, PRKPROCESS, ULONG,
+    },
+};
+
+/// The image name of the process that is used to attach to a session.
+const SESSION_PROCESS_NAME: &[u8] = b"csrss.exe";
+
+/// The highest process ID that is searched for the session process.
+const MAX_PROCESS_ID: usize = 0x10000;
+
+/// Maximum number of thunks that are followed to find the implementation of a Win32k function.
+const MAX_THUNK_DEPTH: usize = 4;
+
+/// Number of bytes decoded to detect a thunk.
+const THUNK_DECODE_LEN: usize = 32;
+
+/// The modules implementing the Win32k system calls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Win32kModule {
+    /// `win32k.sys`, which contains the service table and the thunks.
+    Win32k,
+
+    /// `win32kbase.sys`, which contains the base implementations.
+    Win32kBase,
+
+    /// `win32kfull.sys`, which contains the full implementations.
+    Win32kFull,
+}
+
+impl Win32kModule {
+    /// All Win32k modules.
+    pub const ALL: [Win32kModule; 3] = [
+        Win32kModule::Win32k,
+        Win32kModule::Win32kBase,
+        Win32kModule::Win32kFull,
+    ];
+
+    /// Returns the null-terminated image name of the module, as used by `Sysinfo::get_module_base`.
+    pub const fn image_name(self) -> &'static str {
+        match self {
+            Win32kModule::Win32k => "win32k.sys\0",
+            Win32kModule::Win32kBase => "win32kbase.sys\0",
+            Win32kModule::Win32kFull => "win32kfull.sys\0",
+        }
+    }
+}
+
+/// The address ranges of the loaded Win32k modules.
+pub struct Win32kModules {
+    /// The module, its base address and its size.
+    modules: Vec<(Win32kModule, u64, u64)>,
+}
+
+impl Win32kModules {
+    /// Queries the address ranges of the loaded Win32k modules.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, HypervisorError>` - The address ranges, or an error if `win32k.sys` isn't loaded.
+    pub fn query() -> Result<Self, HypervisorError> {
+        let mut sys_info = Sysinfo::new()?;
+
+        let modules: Vec<_> = Win32kModule::ALL
+            .iter()
+            .filter_map(|module| {
+                sys_info
+                    .get_module_base(module.image_name())
+                    .map(|(base, size)| (*module, base as u64, size as u64))
+            })
+            .collect();
+
+        if !modules
+            .iter()
+            .any(|(module, _, _)| *module == Win32kModule::Win32k)
+        {
+            return Err(HypervisorError::Win32kNotLoaded);
+        }
+
+        Ok(Self { modules })
+    }
+
+    /// Returns the Win32k module containing the address.
+    pub fn module_of(&self, address: u64) -> Option<Win32kModule> {
+        self.modules
+            .iter()
+            .find(|(_, base, size)| (*base..*base + *size).contains(&address))
+            .map(|(module, _, _)| *module)
+    }
+}
+
+/// An attachment of the current thread to the address space of a process of a GUI session.
+///
+/// The thread is detached and the process is dereferenced when the attachment is dropped.
+pub struct SessionAttachment {
+    /// The referenced process the thread is attached to.
+    process: PEPROCESS,
+
+    /// The saved APC state. It's boxed, because the kernel may link list entries to it.
+    apc_state: Box<_KAPC_STATE>,
+}
+
+impl SessionAttachment {
+    /// Attaches the current thread to the address space of a process.
+    ///
+    /// # Arguments
+    ///
+    /// * `process` - The process to attach to. The attachment takes over the reference to the process.
+    ///
+    /// # Safety
+    ///
+    /// The process must be referenced and the caller must run at IRQL <= APC_LEVEL.
+    pub unsafe fn attach(process: PEPROCESS) -> Self {
+        let mut apc_state = Box::new(_KAPC_STATE::default());
+
+        KeStackAttachProcess(process as PRKPROCESS, apc_state.as_mut());
+
+        Self { process, apc_state }
+    }
+
+    /// Attaches the current thread to the session process (`csrss.exe`) of an interactive session.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, HypervisorError>` - The attachment, or an error if no process of an interactive session was found.
+    pub fn attach_to_gui_process() -> Result<Self, HypervisorError> {
+        for process_id in (4..MAX_PROCESS_ID).step_by(4) {
+            let mut process: PEPROCESS = core::ptr::null_mut();
+
+            let status = unsafe { PsLookupProcessByProcessId(process_id as HANDLE, &mut process) };
+            if !NT_SUCCESS(status) {
+                continue;
+            }
+
+            // Session 0 doesn't host interactive GUI processes.
+            if Self::image_file_name(process) == SESSION_PROCESS_NAME
+                && unsafe { PsGetProcessSessionId(process) } != 0
+            {
+                log::debug!(
+                    "Attaching to session process {:#x} ({:p})",
+                    process_id,
+                    process
+                );
+                return Ok(unsafe { Self::attach(process) });
+            }
+
+            unsafe { ObfDereferenceObject(process as _) };
+        }
+
+        Err(HypervisorError::SessionProcessNotFound)
+    }
+
+    /// Returns the process the thread is attached to.
+    pub fn process(&self) -> PEPROCESS {
+        self.process
+    }
+
+    /// Returns the session ID of the process the thread is attached to.
+    pub fn session_id(&self) -> u32 {
+        unsafe { PsGetProcessSessionId(self.process) }
+    }
+
+    /// Returns the image file name of a process.
+    fn image_file_name<'a>(process: PEPROCESS) -> &'a [u8] {
+        let name = unsafe { PsGetProcessImageFileName(process) };
+        if name.is_null() {
+            return &[];
+        }
+
+        // The image file name is stored in a 15-byte buffer, which isn't null-terminated if the name is longer.
+        let name = unsafe { core::slice::from_raw_parts(name, 15) };
+        let length = name
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(name.len());
+
+        &name[..length]
+    }
+}
+
+impl Drop for SessionAttachment {
+    fn drop(&mut self) {
+        unsafe {
+            KeUnstackDetachProcess(self.apc_state.as_mut());
+            ObfDereferenceObject(self.process as _);
+        }
+    }
+}
+
+/// The target of a thunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThunkTarget {
+    /// The thunk jumps directly to the address.
+    Direct(u64),
+
+    /// The thunk jumps to the address stored at the address (for example an import address table entry).
+    Indirect(u64),
+}
+
+/// Decodes a thunk at the start of a function.
+///
+/// The following thunks are recognized:
+/// - `jmp rel8/rel32`
+/// - `jmp qword ptr [rip+disp32]`
+/// - `mov reg, qword ptr [rip+disp32]` followed by `jmp reg`
+///
+/// # Arguments
+///
+/// * `bytes` - The bytes at the start of the function.
+/// * `address` - The address of the function.
+///
+/// # Returns
+///
+/// * `Option<ThunkTarget>` - The target of the thunk, or `None` if the function isn't a thunk.
+pub fn decode_thunk(bytes: &[u8], address: u64) -> Option<ThunkTarget> {
+    let mut decoder = Decoder::with_ip(64, bytes, address, DecoderOptions::NONE);
+
+    let first = decoder.decode();
+    match first.code() {
+        Code::Jmp_rel8_64 | Code::Jmp_rel32_64 => {
+            Some(ThunkTarget::Direct(first.near_branch_target()))
+        }
+        Code::Jmp_rm64
+            if first.op0_kind() == OpKind::Memory && first.is_ip_rel_memory_operand() =>
+        {
+            Some(ThunkTarget::Indirect(first.ip_rel_memory_address()))
+        }
+        Code::Mov_r64_rm64
+            if first.op1_kind() == OpKind::Memory && first.is_ip_rel_memory_operand() =>
+        {
+            let second = decoder.decode();
+            let jumps_to_register = second.code() == Code::Jmp_rm64
+                && second.op0_kind() == OpKind::Register
+                && second.op0_register() == first.op0_register();
+
+            jumps_to_register.then(|| ThunkTarget::Indirect(first.ip_rel_memory_address()))
+        }
+        _ => None,
+    }
+}
+
+/// Follows the thunks starting at `address` to the implementation of a function.
+///
+/// # Arguments
+///
+/// * `address` - The address of the function. Must be mapped in the current address space.
+///
+/// # Returns
+///
+/// * `u64` - The address of the implementation.
+fn follow_thunks(mut address: u64) -> u64 {
+    for _ in 0..MAX_THUNK_DEPTH {
+        let bytes = unsafe { core::slice::from_raw_parts(address as *const u8, THUNK_DECODE_LEN) };
+
+        address = match decode_thunk(bytes, address) {
+            Some(ThunkTarget::Direct(target)) => target,
+            Some(ThunkTarget::Indirect(pointer)) => unsafe { (pointer as *const u64).read() },
+            None => break,
+        };
+
+        log::trace!("Followed thunk to {:#x}", address);
+    }
+
+    address
+}
+
+/// A resolved Win32k system call.
+#[derive(Debug, Clone, Copy)]
+pub struct Win32kFunction {
+    /// The entry of the Win32k service table.
+    pub entry: SsdtEntry,
+
+    /// The address of the implementation, after following the thunks.
+    pub address: u64,
+
+    /// The module containing the implementation.
+    pub module: Option<Win32kModule>,
+}
+
+/// Resolves a Win32k system call to its implementation.
+///
+/// # Arguments
+///
+/// * `syscall_number` - The syscall number, starting at 0x1000.
+/// * `_attachment` - The attachment to a GUI process, which maps the session space.
+///
+/// # Returns
+///
+/// * `Result<Win32kFunction, HypervisorError>` - The resolved function, or an error if the service table or the entry wasn't found.
+pub fn resolve_win32k_function(
+    syscall_number: u32,
+    _attachment: &SessionAttachment,
+) -> Result<Win32kFunction, HypervisorError> {
+    let ssdt = SsdtFind::find_ssdt()?;
+
+    let index = syscall_number
+        .checked_sub(ServiceTable::Win32k.first_syscall_number())
+        .ok_or(HypervisorError::SsdtEntryNotFound)?;
+
+    let entry = ssdt.entry(ServiceTable::Win32k, index)?;
+    let address = follow_thunks(entry.address);
+    let module = Win32kModules::query()?.module_of(address);
+
+    log::debug!(
+        "Win32k syscall {:#x}: table entry {:#x}, implementation {:#x} ({:?})",
+        syscall_number,
+        entry.address,
+        address,
+        module
+    );
+
+    Ok(Win32kFunction {
+        entry,
+        address,
+        module,
+    })
+}
+
+/// Creates an EPT hook on the implementation of a Win32k system call.
+///
+/// The function attaches to a GUI process, so the page of the implementation can be copied and locked,
+/// and its physical address resolved. The hook applies to the session of that process.
+///
+/// # Arguments
+///
+/// * `syscall_number` - The syscall number, starting at 0x1000.
+/// * `handler` - The handler of the hook.
+///
+/// # Returns
+///
+/// * `Result<Hook, HypervisorError>` - The hook, or an error if the function couldn't be resolved or hooked.
+pub fn hook_win32k_function(
+    syscall_number: u32,
+    handler: HookHandler,
+) -> Result<Hook, HypervisorError> {
+    let attachment = SessionAttachment::attach_to_gui_process()?;
+
+    log::debug!("Attached to session {}", attachment.session_id());
+
+    let function = resolve_win32k_function(syscall_number, &attachment)?;
+
+    let sys_info = Sysinfo::new()?;
+
+    Hook::hook_function_ptr_with_handler(&sys_info, function.address, handler)
+        .ok_or(HypervisorError::HookError)
+}
+
+#[link(name = "ntoskrnl")]
+extern "system" {
+    /// Returns the session ID of a process.
+    fn PsGetProcessSessionId(Process: PEPROCESS) -> ULONG;
+
+    /// Returns the image file name of a process (at most 15 characters).
+    fn PsGetProcessImageFileName(Process: PEPROCESS) -> *const u8;
+}
@@ -0,0 +1,328 @@
+//! Provides the symbols and structure layouts of the kernel that aren't exported.
+//!
+//! The symbols are extracted offline from the PDB of ntoskrnl with `utils::pdb::Pdb::extract` and
+//! serialized into a compact blob with `Symbols::to_bytes`. The blob is passed to the driver at load
+//! time, for example in the `Symbols` value of the service key, parsed with `Symbols::from_bytes` and
+//! installed after verifying that it was extracted from the PDB of the running kernel.
+//!
+//! The blob is little-endian and consists of a header (magic, version, PDB GUID and age, counts),
+//! the symbols (name and RVA) and the structures (name, size and fields). Names are prefixed with
+//! their `u16` length.
+
+#[cfg(windows)]
+use crate::utils::ssdt::sys_info::Sysinfo;
+use {
+    crate::{
+        error::HypervisorError,
+        utils::{bytes::ByteReader, pe::PeImage},
+    },
+    alloc::{collections::BTreeMap, string::String, vec::Vec},
+    spin::Once,
+};
+
+/// The magic of the symbols blob (`HVSY`).
+pub const SYMBOLS_MAGIC: u32 = u32::from_le_bytes(*b"HVSY");
+
+/// The version of the symbols blob format.
+pub const SYMBOLS_VERSION: u32 = 1;
+
+/// The symbols installed for the running kernel.
+static SYMBOLS: Once<Symbols> = Once::new();
+
+/// Identifies the PDB matching an image, as stored in the CodeView record of the image.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PdbIdentity {
+    /// The GUID of the PDB, as raw bytes.
+    pub guid: [u8; 16],
+
+    /// The age of the PDB.
+    pub age: u32,
+}
+
+/// A data member of a structure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    /// The name of the field.
+    pub name: String,
+
+    /// The offset of the field in the structure.
+    pub offset: u32,
+
+    /// The bit position and length, if the field is a bitfield.
+    pub bitfield: Option<(u8, u8)>,
+}
+
+/// The layout of a structure.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Struct {
+    /// The size of the structure.
+    pub size: u32,
+
+    /// The data members, in declaration order.
+    pub fields: Vec<Field>,
+}
+
+impl Struct {
+    /// Returns a field by its name.
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+}
+
+/// The symbols and structure layouts extracted from a PDB.
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+    /// The identity of the PDB the symbols were extracted from.
+    identity: PdbIdentity,
+
+    /// The RVAs of the symbols by their names.
+    symbols: BTreeMap<String, u32>,
+
+    /// The layouts of the structures by their names.
+    structs: BTreeMap<String, Struct>,
+}
+
+impl Symbols {
+    /// Creates an empty `Symbols` instance for the PDB with the given identity.
+    pub fn new(identity: PdbIdentity) -> Self {
+        Self {
+            identity,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the identity of the PDB the symbols were extracted from.
+    pub fn identity(&self) -> PdbIdentity {
+        self.identity
+    }
+
+    /// Adds a symbol.
+    pub fn add_symbol(&mut self, name: &str, rva: u32) {
+        self.symbols.insert(String::from(name), rva);
+    }
+
+    /// Adds the layout of a structure.
+    pub fn add_struct(&mut self, name: &str, layout: Struct) {
+        self.structs.insert(String::from(name), layout);
+    }
+
+    /// Returns the number of the symbols.
+    pub fn symbol_count(&self) -> usize {
+        self.symbols.len()
+    }
+
+    /// Returns the number of the structures.
+    pub fn struct_count(&self) -> usize {
+        self.structs.len()
+    }
+
+    /// Returns the RVA of a symbol.
+    pub fn symbol_rva(&self, name: &str) -> Option<u32> {
+        self.symbols.get(name).copied()
+    }
+
+    /// Returns the address of a symbol in the image loaded at `image_base`.
+    pub fn address(&self, name: &str, image_base: u64) -> Option<u64> {
+        self.symbol_rva(name).map(|rva| image_base + rva as u64)
+    }
+
+    /// Returns the layout of a structure.
+    pub fn structure(&self, name: &str) -> Option<&Struct> {
+        self.structs.get(name)
+    }
+
+    /// Returns the offset of a field of a structure, for example `_EPROCESS` and `UniqueProcessId`.
+    pub fn field_offset(&self, struct_name: &str, field_name: &str) -> Option<u32> {
+        self.structure(struct_name)?
+            .field(field_name)
+            .map(|field| field.offset)
+    }
+
+    /// Returns an iterator over the symbols and their RVAs.
+    pub fn symbols(&self) -> impl Iterator<Item = (&str, u32)> {
+        self.symbols.iter().map(|(name, rva)| (name.as_str(), *rva))
+    }
+
+    /// Returns an iterator over the structures and their layouts.
+    pub fn structs(&self) -> impl Iterator<Item = (&str, &Struct)> {
+        self.structs
+            .iter()
+            .map(|(name, layout)| (name.as_str(), layout))
+    }
+
+    /// Checks whether the symbols were extracted from the PDB of an image, using its CodeView record.
+    pub fn matches(&self, image: &PeImage) -> bool {
+        image.codeview().is_some_and(|codeview| {
+            codeview.guid == self.identity.guid && codeview.age == self.identity.age
+        })
+    }
+
+    /// Serializes the symbols into a blob.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut blob = Vec::new();
+
+        blob.extend_from_slice(&SYMBOLS_MAGIC.to_le_bytes());
+        blob.extend_from_slice(&SYMBOLS_VERSION.to_le_bytes());
+        blob.extend_from_slice(&self.identity.guid);
+        blob.extend_from_slice(&self.identity.age.to_le_bytes());
+        blob.extend_from_slice(&(self.symbols.len() as u32).to_le_bytes());
+        blob.extend_from_slice(&(self.structs.len() as u32).to_le_bytes());
+
+        for (name, rva) in &self.symbols {
+            write_name(&mut blob, name);
+            blob.extend_from_slice(&rva.to_le_bytes());
+        }
+
+        for (name, layout) in &self.structs {
+            write_name(&mut blob, name);
+            blob.extend_from_slice(&layout.size.to_le_bytes());
+            blob.extend_from_slice(&(layout.fields.len() as u32).to_le_bytes());
+
+            for field in &layout.fields {
+                let (position, length) = field.bitfield.unwrap_or_default();
+
+                write_name(&mut blob, &field.name);
+                blob.extend_from_slice(&field.offset.to_le_bytes());
+                blob.extend_from_slice(&[position, length]);
+            }
+        }
+
+        blob
+    }
+
+    /// Parses a blob created by `to_bytes`.
+    ///
+    /// # Arguments
+    ///
+    /// * `blob` - The blob.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, HypervisorError>` - The symbols, or an error if the blob is invalid or has a different version.
+    pub fn from_bytes(blob: &[u8]) -> Result<Self, HypervisorError> {
+        Self::read(&mut ByteReader::new(blob, || {
+            HypervisorError::InvalidSymbols
+        }))
+    }
+
+    /// Reads the symbols from the blob.
+    fn read(reader: &mut ByteReader) -> Result<Self, HypervisorError> {
+        if reader.u32()? != SYMBOLS_MAGIC || reader.u32()? != SYMBOLS_VERSION {
+            return Err(HypervisorError::InvalidSymbols);
+        }
+
+        let mut symbols = Self::new(PdbIdentity {
+            guid: reader.array()?,
+            age: reader.u32()?,
+        });
+
+        let symbol_count = reader.u32()?;
+        let struct_count = reader.u32()?;
+
+        for _ in 0..symbol_count {
+            let name = reader.prefixed_str()?;
+            let rva = reader.u32()?;
+
+            symbols.add_symbol(name, rva);
+        }
+
+        for _ in 0..struct_count {
+            let name = reader.prefixed_str()?;
+            let size = reader.u32()?;
+            let field_count = reader.u32()?;
+
+            let fields = (0..field_count)
+                .map(|_| {
+                    let name = String::from(reader.prefixed_str()?);
+                    let offset = reader.u32()?;
+                    let [position, length] = reader.array()?;
+
+                    Ok(Field {
+                        name,
+                        offset,
+                        bitfield: (length != 0).then_some((position, length)),
+                    })
+                })
+                .collect::<Result<Vec<_>, HypervisorError>>()?;
+
+            symbols.add_struct(name, Struct { size, fields });
+        }
+
+        Ok(symbols)
+    }
+}
+
+/// Writes a name prefixed with its `u16` length.
+fn write_name(blob: &mut Vec<u8>, name: &str) {
+    let name = &name.as_bytes()[..name.len().min(u16::MAX as usize)];
+
+    blob.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    blob.extend_from_slice(name);
+}
+
+/// Installs the symbols of the running kernel, after verifying that they were extracted from its PDB.
+///
+/// The symbols can only be installed once. Later calls return the installed symbols.
+///
+/// # Arguments
+///
+/// * `symbols` - The symbols.
+///
+/// # Returns
+///
+/// * `Result<&'static Symbols, HypervisorError>` - The installed symbols, or an error if they don't match the kernel.
+#[cfg(windows)]
+pub fn install(symbols: Symbols) -> Result<&'static Symbols, HypervisorError> {
+    let (kernel_base, kernel_size) = kernel_image()?;
+    let kernel = unsafe { PeImage::from_base(kernel_base as *const u8, kernel_size)? };
+
+    if !symbols.matches(&kernel) {
+        log::error!("Symbols don't match the PDB of the kernel");
+        return Err(HypervisorError::SymbolsMismatch);
+    }
+
+    log::debug!(
+        "Installing {} symbols and {} structures",
+        symbols.symbol_count(),
+        symbols.struct_count()
+    );
+
+    Ok(SYMBOLS.call_once(|| symbols))
+}
+
+/// Returns the installed symbols of the running kernel.
+pub fn installed() -> Option<&'static Symbols> {
+    SYMBOLS.get()
+}
+
+/// Returns the address of a symbol of the running kernel from the installed symbols.
+///
+/// # Arguments
+///
+/// * `name` - The name of the symbol, for example `PspCidTable`.
+///
+/// # Returns
+///
+/// * `Result<u64, HypervisorError>` - The address of the symbol, or an error if no symbols are installed or the symbol is unknown.
+#[cfg(windows)]
+pub fn kernel_symbol(name: &str) -> Result<u64, HypervisorError> {
+    let rva = installed()
+        .and_then(|symbols| symbols.symbol_rva(name))
+        .ok_or(HypervisorError::SymbolNotFound)?;
+
+    let (kernel_base, _) = kernel_image()?;
+
+    Ok(kernel_base + rva as u64)
+}
+
+/// Returns the base address and size of the kernel image.
+#[cfg(windows)]
+fn kernel_image() -> Result<(u64, usize), HypervisorError> {
+    let sys_info = Sysinfo::new()?;
+
+    let (kernel_base, kernel_size) = sys_info
+        .find_module("ntoskrnl.exe")
+        .ok_or(HypervisorError::GetKernelBaseFailed)?;
+
+    Ok((kernel_base as u64, kernel_size as usize))
+}
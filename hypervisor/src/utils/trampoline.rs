@@ -0,0 +1,611 @@
+//! Provides a pure trampoline builder that relocates the prologue of a function.
+//! The builder operates on byte slices only, so it doesn't touch live memory or allocate
+//! executable memory. This allows it to be used both by `FunctionHook` and outside the kernel.
+
+use {
+    crate::error::HypervisorError,
+    alloc::vec::Vec,
+    iced_x86::{
+        BlockEncoder, BlockEncoderOptions, Decoder, DecoderOptions, FlowControl, Instruction,
+        InstructionBlock,
+    },
+};
+
+/// Length of JMP shellcode.
+pub const JMP_SHELLCODE_LEN: usize = 14;
+
+/// Maximum length of a single x86-64 instruction.
+pub const MAX_INSTRUCTION_LEN: usize = 15;
+
+/// Creates the jmp shellcode.
+///
+/// ## How it works.
+///
+/// We are using the following assembly shellcode:
+/// ```asm
+/// jmp [rip+00h]
+/// 0xDEADBEEF
+/// ```
+///
+/// Or in a different format:
+///
+/// ```asm
+/// jmp qword ptr cs:jmp_add
+/// jmp_addr: dq 0xDEADBEEF
+/// ```
+///
+/// The core premise behind it is, that we jump to the address that is right
+/// after the current instruction.
+///
+/// ## Why use this instead of `mov rax, jmp rax`?
+///
+/// This shellcode has one very important feature: **It doesn't require any
+/// registers to store the jmp address**. And because of that, we don't
+/// have to fear overwriting some register values.
+pub fn jmp_shellcode(target_address: u64) -> [u8; JMP_SHELLCODE_LEN] {
+    let mut shellcode = [
+        0xff, 0x25, 0x00, 0x00, 0x00, 0x00, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC,
+    ];
+
+    shellcode[6..].copy_from_slice(&target_address.to_le_bytes());
+
+    shellcode
+}
+
+/// Describes where an instruction of the original prologue ended up in the trampoline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstructionBoundary {
+    /// Offset of the instruction relative to the original address.
+    pub original_offset: usize,
+
+    /// Offset of the relocated instruction relative to the trampoline address.
+    pub relocated_offset: usize,
+
+    /// Length of the instruction in the original prologue.
+    pub original_length: usize,
+}
+
+/// The output of the `TrampolineBuilder`.
+#[derive(Debug, Clone)]
+pub struct Trampoline {
+    /// The encoded trampoline, including the jmp back to the original function.
+    pub bytes: Vec<u8>,
+
+    /// Number of bytes of the original function that were relocated into the trampoline.
+    pub prologue_length: usize,
+
+    /// Instruction-boundary map between the original prologue and the trampoline.
+    pub boundaries: Vec<InstructionBoundary>,
+}
+
+impl Trampoline {
+    /// Returns the original offset of the first instruction boundary that is at or after `offset`.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - An offset relative to the original address.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<usize>` - The offset of the instruction boundary, or `None` if `offset` is beyond the prologue.
+    pub fn boundary_at_or_after(&self, offset: usize) -> Option<usize> {
+        self.boundaries
+            .iter()
+            .map(|boundary| boundary.original_offset)
+            .chain(core::iter::once(self.prologue_length))
+            .find(|boundary| *boundary >= offset)
+    }
+
+    /// Translates an address inside the original prologue to the address in the trampoline.
+    ///
+    /// # Arguments
+    ///
+    /// * `original_address` - The address of the original function.
+    /// * `trampoline_address` - The address of the trampoline.
+    /// * `address` - The address inside the original prologue.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<u64>` - The relocated address, or `None` if `address` isn't an instruction boundary.
+    pub fn relocate_address(
+        &self,
+        original_address: u64,
+        trampoline_address: u64,
+        address: u64,
+    ) -> Option<u64> {
+        let offset = address.checked_sub(original_address)? as usize;
+
+        self.boundaries
+            .iter()
+            .find(|boundary| boundary.original_offset == offset)
+            .map(|boundary| trampoline_address + boundary.relocated_offset as u64)
+    }
+}
+
+/// Builds trampolines from the original bytes of a function.
+///
+/// The builder decodes the instructions at the start of the function until at least `required_size`
+/// bytes are covered, relocates them to the trampoline address (including RIP-relative operands and
+/// relative branches) and appends a jmp back to the first instruction that wasn't relocated.
+pub struct TrampolineBuilder<'a> {
+    /// The original bytes of the function.
+    bytes: &'a [u8],
+
+    /// The address the bytes were read from.
+    original_address: u64,
+
+    /// The address the trampoline will be placed at.
+    trampoline_address: u64,
+
+    /// The minimum number of bytes to relocate.
+    required_size: usize,
+
+    /// The maximum number of bytes that may be relocated, if known.
+    max_size: Option<usize>,
+}
+
+impl<'a> TrampolineBuilder<'a> {
+    /// Creates a new `TrampolineBuilder`.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The original bytes of the function. Must contain at least `required_size` plus
+    ///   `MAX_INSTRUCTION_LEN - 1` bytes, so the last instruction can be decoded entirely.
+    /// * `original_address` - The address of the original function.
+    /// * `trampoline_address` - The address the trampoline will be placed at.
+    pub fn new(bytes: &'a [u8], original_address: u64, trampoline_address: u64) -> Self {
+        Self {
+            bytes,
+            original_address,
+            trampoline_address,
+            required_size: 1,
+            max_size: None,
+        }
+    }
+
+    /// Sets the minimum number of bytes that need to be relocated (for example the size of the hook shellcode).
+    pub fn required_size(mut self, required_size: usize) -> Self {
+        self.required_size = required_size;
+        self
+    }
+
+    /// Sets the maximum number of bytes that may be relocated (for example the size of the function),
+    /// so the prologue never extends into the next function.
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Decodes the prologue of the original function.
+    ///
+    /// Decoding stops at a return or an unconditional branch, because the bytes after it might not belong
+    /// to the function. If such an instruction ends before `required_size` bytes are covered, the function
+    /// is too small to be hooked.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<Instruction>, HypervisorError>` - The instructions covering at least `required_size` bytes,
+    ///   or `NotEnoughBytes` if the function ends or the bytes run out before.
+    pub fn decode_prologue(&self) -> Result<Vec<Instruction>, HypervisorError> {
+        let mut decoder =
+            Decoder::with_ip(64, self.bytes, self.original_address, DecoderOptions::NONE);
+
+        let mut total_bytes = 0;
+        let mut instructions = Vec::new();
+
+        while total_bytes < self.required_size {
+            if !decoder.can_decode() {
+                return Err(HypervisorError::NotEnoughBytes);
+            }
+
+            let instr = decoder.decode();
+            if instr.is_invalid() {
+                return Err(HypervisorError::InvalidBytes);
+            }
+
+            match instr.flow_control() {
+                FlowControl::Next
+                | FlowControl::Return
+                | FlowControl::Call
+                | FlowControl::IndirectCall
+                | FlowControl::ConditionalBranch
+                | FlowControl::UnconditionalBranch
+                | FlowControl::IndirectBranch => {}
+                FlowControl::Interrupt | FlowControl::XbeginXabortXend | FlowControl::Exception => {
+                    return Err(HypervisorError::UnsupportedInstruction);
+                }
+            };
+
+            if self
+                .max_size
+                .is_some_and(|max_size| total_bytes + instr.len() > max_size)
+            {
+                return Err(HypervisorError::NotEnoughBytes);
+            }
+
+            total_bytes += instr.len();
+            instructions.push(instr);
+
+            let ends_function = matches!(
+                instr.flow_control(),
+                FlowControl::Return
+                    | FlowControl::UnconditionalBranch
+                    | FlowControl::IndirectBranch
+            );
+
+            if ends_function && total_bytes < self.required_size {
+                return Err(HypervisorError::NotEnoughBytes);
+            }
+        }
+
+        if instructions.is_empty() {
+            return Err(HypervisorError::NoInstructions);
+        }
+
+        Ok(instructions)
+    }
+
+    /// Builds the trampoline.
+    ///
+    /// ## Returns
+    ///
+    /// The encoded trampoline together with the prologue length and the instruction-boundary map.
+    pub fn build(&self) -> Result<Trampoline, HypervisorError> {
+        let instructions = self.decode_prologue()?;
+
+        let block = InstructionBlock::new(&instructions, self.trampoline_address);
+
+        let result = BlockEncoder::encode(
+            64,
+            block,
+            BlockEncoderOptions::RETURN_NEW_INSTRUCTION_OFFSETS,
+        )
+        .map_err(|_| HypervisorError::EncodingFailed)?;
+
+        let mut boundaries = Vec::with_capacity(instructions.len());
+        for (instr, relocated_offset) in instructions.iter().zip(result.new_instruction_offsets) {
+            if relocated_offset == u32::MAX {
+                return Err(HypervisorError::EncodingFailed);
+            }
+
+            boundaries.push(InstructionBoundary {
+                original_offset: (instr.ip() - self.original_address) as usize,
+                relocated_offset: relocated_offset as usize,
+                original_length: instr.len(),
+            });
+        }
+
+        let prologue_length = instructions
+            .last()
+            .map(|instr| (instr.next_ip() - self.original_address) as usize)
+            .ok_or(HypervisorError::NoInstructions)?;
+
+        // Add jmp to the first instruction of the original function that wasn't relocated.
+        let mut bytes = result.code_buffer;
+        bytes.extend_from_slice(&jmp_shellcode(
+            self.original_address + prologue_length as u64,
+        ));
+
+        Ok(Trampoline {
+            bytes,
+            prologue_length,
+            boundaries,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, iced_x86::OpKind};
+
+    /// The address of the original function.
+    const ORIGINAL_ADDRESS: u64 = 0x1000;
+
+    /// The address of the trampoline.
+    const TRAMPOLINE_ADDRESS: u64 = 0x8000_0000;
+
+    /// Pads a function with int3 instructions, so the last instruction can be decoded entirely.
+    fn padded(function: &[u8]) -> Vec<u8> {
+        let mut bytes = function.to_vec();
+        bytes.resize(function.len() + MAX_INSTRUCTION_LEN, 0xCC);
+        bytes
+    }
+
+    /// Decodes the prologue of a function for a hook of `required_size` bytes.
+    fn decode(function: &[u8], required_size: usize) -> Result<Vec<Instruction>, HypervisorError> {
+        let bytes = padded(function);
+
+        TrampolineBuilder::new(&bytes, ORIGINAL_ADDRESS, TRAMPOLINE_ADDRESS)
+            .required_size(required_size)
+            .decode_prologue()
+    }
+
+    #[test]
+    fn decodes_whole_instructions_covering_the_required_size() {
+        // push rbp; mov rbp, rsp; sub rsp, 0x20; xor eax, eax; ret
+        let function = [
+            0x55, 0x48, 0x89, 0xE5, 0x48, 0x83, 0xEC, 0x20, 0x31, 0xC0, 0xC3,
+        ];
+
+        let instructions = decode(&function, 5).unwrap();
+
+        assert_eq!(instructions.len(), 3);
+        assert_eq!(instructions.iter().map(Instruction::len).sum::<usize>(), 8);
+    }
+
+    #[test]
+    fn stops_at_return_before_the_required_size() {
+        // xor eax, eax; ret
+        let function = [0x31, 0xC0, 0xC3];
+
+        assert!(matches!(
+            decode(&function, JMP_SHELLCODE_LEN),
+            Err(HypervisorError::NotEnoughBytes)
+        ));
+    }
+
+    #[test]
+    fn accepts_return_ending_at_the_required_size() {
+        // xor eax, eax; ret
+        let function = [0x31, 0xC0, 0xC3];
+
+        assert_eq!(decode(&function, 3).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn stops_at_unconditional_branch_before_the_required_size() {
+        // jmp rel32
+        let function = [0xE9, 0x00, 0x10, 0x00, 0x00];
+
+        assert!(matches!(
+            decode(&function, JMP_SHELLCODE_LEN),
+            Err(HypervisorError::NotEnoughBytes)
+        ));
+    }
+
+    #[test]
+    fn stops_at_indirect_branch_before_the_required_size() {
+        // mov rax, rcx; jmp rax
+        let function = [0x48, 0x89, 0xC8, 0xFF, 0xE0];
+
+        assert!(matches!(
+            decode(&function, JMP_SHELLCODE_LEN),
+            Err(HypervisorError::NotEnoughBytes)
+        ));
+    }
+
+    #[test]
+    fn continues_after_conditional_branch_and_call() {
+        // test ecx, ecx; jz +2; call rel32; nop
+        let function = [0x85, 0xC9, 0x74, 0x02, 0xE8, 0x00, 0x00, 0x00, 0x00, 0x90];
+
+        assert_eq!(decode(&function, 10).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn fails_when_the_bytes_run_out() {
+        let bytes = [0x90; 4];
+
+        assert!(matches!(
+            TrampolineBuilder::new(&bytes, ORIGINAL_ADDRESS, TRAMPOLINE_ADDRESS)
+                .required_size(JMP_SHELLCODE_LEN)
+                .decode_prologue(),
+            Err(HypervisorError::NotEnoughBytes)
+        ));
+    }
+
+    #[test]
+    fn respects_the_maximum_size() {
+        let bytes = padded(&[0x90; 32]);
+
+        assert!(matches!(
+            TrampolineBuilder::new(&bytes, ORIGINAL_ADDRESS, TRAMPOLINE_ADDRESS)
+                .required_size(JMP_SHELLCODE_LEN)
+                .max_size(8)
+                .decode_prologue(),
+            Err(HypervisorError::NotEnoughBytes)
+        ));
+    }
+
+    #[test]
+    fn rejects_interrupts_and_invalid_bytes() {
+        // int3
+        assert!(matches!(
+            decode(&[0xCC], 1),
+            Err(HypervisorError::UnsupportedInstruction)
+        ));
+        // push es, which is invalid in 64-bit mode
+        assert!(matches!(
+            decode(&[0x06], 1),
+            Err(HypervisorError::InvalidBytes)
+        ));
+    }
+
+    #[test]
+    fn build_relocates_rip_relative_operands_and_jumps_back() {
+        // mov rax, [rip + 0x100]; push rbx; push rsi; push rdi; sub rsp, 0x20
+        let function = [
+            0x48, 0x8B, 0x05, 0x00, 0x01, 0x00, 0x00, 0x53, 0x56, 0x57, 0x48, 0x83, 0xEC, 0x20,
+        ];
+        let bytes = padded(&function);
+
+        let trampoline = TrampolineBuilder::new(&bytes, ORIGINAL_ADDRESS, TRAMPOLINE_ADDRESS)
+            .required_size(JMP_SHELLCODE_LEN)
+            .build()
+            .unwrap();
+
+        assert_eq!(trampoline.prologue_length, function.len());
+        assert_eq!(trampoline.boundaries.len(), 5);
+        assert_eq!(
+            trampoline.bytes[trampoline.bytes.len() - JMP_SHELLCODE_LEN..],
+            jmp_shellcode(ORIGINAL_ADDRESS + function.len() as u64)
+        );
+
+        // The relocated load still reads the original target.
+        let relocated = Decoder::with_ip(
+            64,
+            &trampoline.bytes,
+            TRAMPOLINE_ADDRESS,
+            DecoderOptions::NONE,
+        )
+        .decode();
+        assert_eq!(
+            relocated.ip_rel_memory_address(),
+            ORIGINAL_ADDRESS + 7 + 0x100
+        );
+
+        assert_eq!(
+            trampoline.relocate_address(ORIGINAL_ADDRESS, TRAMPOLINE_ADDRESS, ORIGINAL_ADDRESS + 7),
+            Some(TRAMPOLINE_ADDRESS + trampoline.boundaries[1].relocated_offset as u64)
+        );
+        assert_eq!(trampoline.boundary_at_or_after(8), Some(8));
+        assert_eq!(trampoline.boundary_at_or_after(11), Some(function.len()));
+    }
+
+    /// The address of the ntoskrnl function of the corpus.
+    const KERNEL_ADDRESS: u64 = 0xFFFF_F806_2A41_3C50;
+
+    /// The address of the trampoline of the ntoskrnl function, within the ±2 GB reach of RIP-relative
+    /// operands like the pool allocations the hooks use.
+    const KERNEL_TRAMPOLINE_ADDRESS: u64 = 0xFFFF_F806_3B00_0000;
+
+    /// Prologues of ntoskrnl functions, covering the relative operands that need to be relocated.
+    const KERNEL_PROLOGUES: &[(&str, &[u8])] = &[
+        (
+            // NtCreateFile: mov r11, rsp; sub rsp, 0x88; xor eax, eax; mov [r11 - 0x10], rax
+            "stack frame without relative operands",
+            &[
+                0x4C, 0x8B, 0xDC, 0x48, 0x81, 0xEC, 0x88, 0x00, 0x00, 0x00, 0x33, 0xC0, 0x49, 0x89,
+                0x43, 0xF0,
+            ],
+        ),
+        (
+            // PsGetCurrentProcess: mov rax, gs:[0x188]; mov rax, [rax + 0xB8]; ret
+            "absolute GS operand",
+            &[
+                0x65, 0x48, 0x8B, 0x04, 0x25, 0x88, 0x01, 0x00, 0x00, 0x48, 0x8B, 0x80, 0xB8, 0x00,
+                0x00, 0x00, 0xC3,
+            ],
+        ),
+        (
+            // mov [rsp + 8], rbx; push rdi; sub rsp, 0x40; mov rax, [rip + __security_cookie];
+            // xor rax, rsp; mov [rsp + 0x38], rax
+            "RIP-relative load of the security cookie",
+            &[
+                0x48, 0x89, 0x5C, 0x24, 0x08, 0x57, 0x48, 0x83, 0xEC, 0x40, 0x48, 0x8B, 0x05, 0xA9,
+                0x62, 0x5C, 0x00, 0x48, 0x33, 0xC4, 0x48, 0x89, 0x44, 0x24, 0x38,
+            ],
+        ),
+        (
+            // test rcx, rcx; jz +0x1F; mov [rsp + 8], rbx; push rdi; sub rsp, 0x20
+            "jcc rel8 on a null argument",
+            &[
+                0x48, 0x85, 0xC9, 0x74, 0x1F, 0x48, 0x89, 0x5C, 0x24, 0x08, 0x57, 0x48, 0x83, 0xEC,
+                0x20,
+            ],
+        ),
+        (
+            // sub rsp, 0x28; cmp byte ptr [rip + KdDebuggerEnabled], 0; jnz rel32
+            "RIP-relative compare with an immediate and jcc rel32",
+            &[
+                0x48, 0x83, 0xEC, 0x28, 0x80, 0x3D, 0x5D, 0x1A, 0x4F, 0x00, 0x00, 0x0F, 0x85, 0x8B,
+                0x2C, 0x19, 0x00,
+            ],
+        ),
+        (
+            // sub rsp, 0x28; call rel32 (backwards); mov rcx, rax; xor edx, edx
+            "call rel32",
+            &[
+                0x48, 0x83, 0xEC, 0x28, 0xE8, 0xF3, 0x5B, 0xFE, 0xFF, 0x48, 0x8B, 0xC8, 0x33, 0xD2,
+            ],
+        ),
+        (
+            // mov rax, rsp; mov [rax + 8], rbx; lea rcx, [rip + 0x3F9B2E]; mov rbx, rdx
+            "lea with RIP displacement",
+            &[
+                0x48, 0x8B, 0xC4, 0x48, 0x89, 0x58, 0x08, 0x48, 0x8D, 0x0D, 0x2E, 0x9B, 0x3F, 0x00,
+                0x48, 0x8B, 0xDA,
+            ],
+        ),
+        (
+            // push rbx; sub rsp, 0x20; mov [rip + 0x527C11], rcx; mov eax, [rip + 0x527C03]
+            "mov to and from RIP displacements",
+            &[
+                0x40, 0x53, 0x48, 0x83, 0xEC, 0x20, 0x48, 0x89, 0x0D, 0x11, 0x7C, 0x52, 0x00, 0x8B,
+                0x05, 0x03, 0x7C, 0x52, 0x00,
+            ],
+        ),
+        (
+            // mov rax, [rip + __security_cookie]; test rdx, rdx; jz rel32; call rel32
+            "RIP-relative load, jcc rel32 and call rel32",
+            &[
+                0x48, 0x8B, 0x05, 0x71, 0x3E, 0x61, 0x00, 0x48, 0x85, 0xD2, 0x0F, 0x84, 0x12, 0x01,
+                0x00, 0x00, 0xE8, 0x6B, 0xA4, 0xF9, 0xFF,
+            ],
+        ),
+    ];
+
+    /// Returns the absolute targets of the relative operands of the instructions: the targets of the near
+    /// branches and the addresses of the RIP-relative memory operands.
+    fn absolute_targets(instructions: &[Instruction]) -> Vec<u64> {
+        instructions
+            .iter()
+            .filter_map(|instr| match instr.op0_kind() {
+                OpKind::NearBranch64 => Some(instr.near_branch_target()),
+                _ if instr.is_ip_rel_memory_operand() => Some(instr.ip_rel_memory_address()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn kernel_prologues_round_trip_with_their_absolute_targets() {
+        for (name, function) in KERNEL_PROLOGUES {
+            let bytes = padded(function);
+            let builder = TrampolineBuilder::new(&bytes, KERNEL_ADDRESS, KERNEL_TRAMPOLINE_ADDRESS)
+                .required_size(JMP_SHELLCODE_LEN);
+
+            let original = builder.decode_prologue().unwrap();
+            let trampoline = builder.build().unwrap();
+
+            let relocated_length = trampoline.bytes.len() - JMP_SHELLCODE_LEN;
+            let relocated: Vec<Instruction> = Decoder::with_ip(
+                64,
+                &trampoline.bytes[..relocated_length],
+                KERNEL_TRAMPOLINE_ADDRESS,
+                DecoderOptions::NONE,
+            )
+            .into_iter()
+            .collect();
+
+            assert!(
+                relocated.iter().all(|instr| !instr.is_invalid()),
+                "{}",
+                name
+            );
+            assert_eq!(relocated.len(), original.len(), "{}", name);
+            assert_eq!(
+                absolute_targets(&relocated),
+                absolute_targets(&original),
+                "{}",
+                name
+            );
+
+            for (boundary, instr) in trampoline.boundaries.iter().zip(&relocated) {
+                assert_eq!(
+                    KERNEL_TRAMPOLINE_ADDRESS + boundary.relocated_offset as u64,
+                    instr.ip(),
+                    "{}",
+                    name
+                );
+            }
+
+            assert_eq!(
+                trampoline.bytes[relocated_length..],
+                jmp_shellcode(KERNEL_ADDRESS + trampoline.prologue_length as u64),
+                "{}",
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,286 @@
+//! Provides the detection of the running Windows build and a database of the signatures and
+//! structure offsets that depend on it.
+//!
+//! Every entry of the database covers ranges of builds sharing the same signatures and offsets. The
+//! running build is detected once with `RtlGetVersion` by the features that need the database, and
+//! builds that aren't covered by an entry are rejected with `HypervisorError::UnsupportedWindowsBuild`
+//! instead of scanning for patterns that might not exist.
+
+#[cfg(windows)]
+use {
+    crate::error::HypervisorError,
+    spin::Once,
+    wdk_sys::{ntddk::RtlGetVersion, NT_SUCCESS, RTL_OSVERSIONINFOW},
+};
+use {
+    crate::utils::signature::{Resolver, Signature},
+    core::{fmt, ops::RangeInclusive},
+};
+
+/// The signature of `KeServiceDescriptorTableShadow`, referenced by `KiSystemServiceStart`:
+///
+/// ```asm
+/// mov     edi, eax
+/// shr     edi, 7
+/// and     edi, 20h
+/// and     eax, 0FFFh
+/// lea     r10, [KeServiceDescriptorTable]
+/// lea     r11, [KeServiceDescriptorTableShadow]
+/// ```
+const KE_SERVICE_DESCRIPTOR_TABLE_SHADOW: Signature = Signature {
+    name: "KeServiceDescriptorTableShadow",
+    patterns: &["8B F8 C1 EF 07 83 E7 20 25 FF 0F 00 00 4C 8D 15 ? ? ? ? 4C 8D 1D ? ? ? ?"],
+    sections: &[".text"],
+    resolvers: &[
+        // Skip to the `lea r11, [KeServiceDescriptorTableShadow]` instruction.
+        Resolver::Add(20),
+        Resolver::Rel32 {
+            offset: 3,
+            instruction_length: 7,
+        },
+    ],
+};
+
+/// The offsets of the kernel structures used since Windows 10 1507.
+///
+/// The Win32k descriptor follows the Nt descriptor in `KeServiceDescriptorTableShadow`, so its offset is the
+/// size of a service descriptor (`KSERVICE_TABLE_DESCRIPTOR`).
+const WINDOWS_10_OFFSETS: KernelOffsets = KernelOffsets {
+    kpcr_current_thread: 0x188,
+    kthread_process: 0xB8,
+    win32k_descriptor: 0x20,
+};
+
+/// The supported builds, sorted by build number. Builds of the same release share an entry, and a
+/// release whose signatures or offsets change gets its own entry.
+const BUILD_DATABASE: &[BuildEntry] = &[
+    BuildEntry {
+        name: "Windows 10 1507 - 1809, Windows Server 2016 - 2019",
+        builds: &[10240..=17763],
+        ke_service_descriptor_table_shadow: KE_SERVICE_DESCRIPTOR_TABLE_SHADOW,
+        offsets: WINDOWS_10_OFFSETS,
+    },
+    BuildEntry {
+        name: "Windows 10 1903 - 22H2",
+        builds: &[18362..=19045],
+        ke_service_descriptor_table_shadow: KE_SERVICE_DESCRIPTOR_TABLE_SHADOW,
+        offsets: WINDOWS_10_OFFSETS,
+    },
+    BuildEntry {
+        name: "Windows Server 2022",
+        builds: &[20348..=20348],
+        ke_service_descriptor_table_shadow: KE_SERVICE_DESCRIPTOR_TABLE_SHADOW,
+        offsets: WINDOWS_10_OFFSETS,
+    },
+    BuildEntry {
+        name: "Windows 11 21H2 - 23H2",
+        builds: &[22000..=22635],
+        ke_service_descriptor_table_shadow: KE_SERVICE_DESCRIPTOR_TABLE_SHADOW,
+        offsets: WINDOWS_10_OFFSETS,
+    },
+    BuildEntry {
+        name: "Windows 11 24H2 - 25H2, Windows Server 2025",
+        builds: &[26100..=26200],
+        ke_service_descriptor_table_shadow: KE_SERVICE_DESCRIPTOR_TABLE_SHADOW,
+        offsets: WINDOWS_10_OFFSETS,
+    },
+];
+
+/// The version of the running system, as reported by `RtlGetVersion`.
+#[cfg(windows)]
+static WINDOWS_VERSION: Once<WindowsVersion> = Once::new();
+
+/// The version of a Windows system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowsVersion {
+    /// The major version, 10 for Windows 10 and 11.
+    pub major: u32,
+
+    /// The minor version.
+    pub minor: u32,
+
+    /// The build number, for example 19045 for Windows 10 22H2.
+    pub build: u32,
+}
+
+impl WindowsVersion {
+    /// Returns the version of the running system.
+    ///
+    /// The version is queried with `RtlGetVersion` on the first call, which must happen at
+    /// PASSIVE_LEVEL outside of VMX root operation. Later calls return the cached version.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, HypervisorError>` - The version, or an error if `RtlGetVersion` failed.
+    #[cfg(windows)]
+    pub fn current() -> Result<Self, HypervisorError> {
+        WINDOWS_VERSION
+            .try_call_once(|| {
+                let mut info = RTL_OSVERSIONINFOW {
+                    dwOSVersionInfoSize: core::mem::size_of::<RTL_OSVERSIONINFOW>() as u32,
+                    ..Default::default()
+                };
+
+                let status = unsafe { RtlGetVersion(&mut info) };
+                if !NT_SUCCESS(status) {
+                    return Err(HypervisorError::RtlGetVersionFailed);
+                }
+
+                Ok(Self {
+                    major: info.dwMajorVersion,
+                    minor: info.dwMinorVersion,
+                    build: info.dwBuildNumber,
+                })
+            })
+            .copied()
+    }
+}
+
+impl fmt::Display for WindowsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.build)
+    }
+}
+
+/// The signatures and structure offsets of a range of builds.
+#[derive(Debug, Clone)]
+pub struct BuildEntry {
+    /// The name of the releases covered by the entry.
+    pub name: &'static str,
+
+    /// The ranges of the build numbers covered by the entry.
+    pub builds: &'static [RangeInclusive<u32>],
+
+    /// The signature of `KeServiceDescriptorTableShadow`.
+    pub ke_service_descriptor_table_shadow: Signature,
+
+    /// The offsets of the kernel structures.
+    pub offsets: KernelOffsets,
+}
+
+/// The offsets of the fields of the kernel structures that are read by the hypervisor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernelOffsets {
+    /// The offset of `Prcb.CurrentThread` in the `KPCR`.
+    pub kpcr_current_thread: u64,
+
+    /// The offset of `ApcState.Process` in the `KTHREAD`, which is the process of a thread that isn't attached.
+    pub kthread_process: u64,
+
+    /// The offset of the Win32k service descriptor in `KeServiceDescriptorTableShadow`.
+    pub win32k_descriptor: usize,
+}
+
+/// Looks up the entry of the database covering a build.
+///
+/// # Arguments
+///
+/// * `build` - The build number.
+///
+/// # Returns
+///
+/// * `Option<&'static BuildEntry>` - The entry, or `None` if the build isn't supported.
+pub fn lookup(build: u32) -> Option<&'static BuildEntry> {
+    BUILD_DATABASE
+        .iter()
+        .find(|entry| entry.builds.iter().any(|builds| builds.contains(&build)))
+}
+
+/// Returns the entry of the database covering the running build.
+///
+/// # Returns
+///
+/// * `Result<&'static BuildEntry, HypervisorError>` - The entry, or `UnsupportedWindowsBuild` if the running build isn't supported.
+#[cfg(windows)]
+pub fn current() -> Result<&'static BuildEntry, HypervisorError> {
+    let version = WindowsVersion::current()?;
+
+    lookup(version.build).ok_or_else(|| {
+        log::error!("Unsupported Windows build: {}", version);
+        HypervisorError::UnsupportedWindowsBuild
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::utils::pe::{tests::map_image, PeImage},
+    };
+
+    /// A kernel image with the references of `KiSystemServiceStart` to the service descriptor tables,
+    /// which are exported to check the resolved address.
+    const KERNEL: &[u8] = include_bytes!("../../tests/fixtures/kernel.sys");
+
+    /// The address the test image is loaded at.
+    const BASE: u64 = 0xFFFF_F800_0000_0000;
+
+    /// Returns the name of the entry covering a build.
+    fn entry_name(build: u32) -> Option<&'static str> {
+        lookup(build).map(|entry| entry.name)
+    }
+
+    #[test]
+    fn builds_are_looked_up_at_the_boundaries_of_the_ranges() {
+        for entry in BUILD_DATABASE {
+            for builds in entry.builds {
+                assert_eq!(entry_name(*builds.start()), Some(entry.name));
+                assert_eq!(entry_name(*builds.end()), Some(entry.name));
+                assert_ne!(entry_name(builds.start() - 1), Some(entry.name));
+                assert_ne!(entry_name(builds.end() + 1), Some(entry.name));
+            }
+        }
+    }
+
+    #[test]
+    fn the_current_releases_are_supported() {
+        for (build, name) in [
+            (19045, "Windows 10 1903 - 22H2"),
+            (20348, "Windows Server 2022"),
+            (22631, "Windows 11 21H2 - 23H2"),
+            (22635, "Windows 11 21H2 - 23H2"),
+            (26100, "Windows 11 24H2 - 25H2, Windows Server 2025"),
+            (26200, "Windows 11 24H2 - 25H2, Windows Server 2025"),
+        ] {
+            assert_eq!(entry_name(build), Some(name), "{}", build);
+        }
+
+        for build in [9600, 17764, 20000, 22636, 26099, 26201] {
+            assert_eq!(entry_name(build), None, "{}", build);
+        }
+    }
+
+    #[test]
+    fn the_database_is_sorted_and_disjoint() {
+        let ranges = BUILD_DATABASE
+            .iter()
+            .flat_map(|entry| entry.builds)
+            .collect::<alloc::vec::Vec<_>>();
+
+        for ranges in ranges.windows(2) {
+            assert!(ranges[0].start() <= ranges[0].end());
+            assert!(ranges[0].end() < ranges[1].start());
+        }
+    }
+
+    #[test]
+    fn the_shadow_table_is_resolved_from_ki_system_service_start() {
+        let image = map_image(KERNEL);
+        let image = PeImage::parse(&image).unwrap();
+        let shadow = image
+            .export("KeServiceDescriptorTableShadow")
+            .unwrap()
+            .rva()
+            .unwrap();
+
+        for entry in BUILD_DATABASE {
+            assert_eq!(
+                entry
+                    .ke_service_descriptor_table_shadow
+                    .find(&image, BASE)
+                    .unwrap(),
+                BASE + shadow as u64
+            );
+        }
+    }
+}
@@ -0,0 +1,41 @@
+#!/bin/sh
+# Rebuilds the PE fixtures from the sources in src/ with llvm-mc and lld-link.
+#
+# The images are linked with /brepro, so the same sources and linker produce the same bytes.
+# LLD can be set to another lld-link, for example "rust-lld -flavor link".
+
+set -eu
+
+cd "$(dirname "$0")"
+FIXTURES="$(pwd)"
+
+LLVM_MC="${LLVM_MC:-llvm-mc}"
+LLD="${LLD:-lld-link}"
+WORK="$(mktemp -d)"
+trap 'rm -rf "$WORK"' EXIT
+
+# The objects are linked in the temporary directory with relative paths and /pdbsourcepath, because
+# the paths end up in the PDB, whose signature is recorded in the image.
+assemble() {
+    "$LLVM_MC" -triple x86_64-pc-windows-msvc -filetype=obj "$FIXTURES/src/$1.s" -o "$WORK/$1.obj"
+}
+
+link() {
+    output="$1"
+    shift
+    (cd "$WORK" && $LLD /nologo /brepro /nodefaultlib /machine:x64 /out:"$output" "$@")
+    cp "$WORK/$output" "$FIXTURES/$output"
+}
+
+assemble imported
+link imported.dll /dll /entry:DllMain /def:"$FIXTURES/src/imported.def" imported.obj
+
+assemble fixture
+link fixture.dll /dll /entry:DllMain /def:"$FIXTURES/src/fixture.def" /debug /pdbaltpath:fixture.pdb \
+    /pdbsourcepath:/fixtures fixture.obj imported.lib
+
+assemble kernel
+link kernel.sys /driver /subsystem:native /entry:DriverEntry /def:"$FIXTURES/src/kernel.def" kernel.obj
+
+assemble user
+link user.dll /dll /entry:DllMain /def:"$FIXTURES/src/user.def" user.obj
@@ -0,0 +1,81 @@
+# A DLL with exports, imports by name and by ordinal, unwind information and base relocations.
+#
+# Second is split into a primary function and two fragments. The first fragment is chained to the
+# primary function through its unwind information (UNW_FLAG_CHAININFO), and the second one in the
+# older format, with the RVA of the entry of the primary function and the lowest bit set. The unwind
+# information is written by hand, because the assembler doesn't emit chained entries.
+
+	.intel_syntax noprefix
+	.text
+
+	.globl	First
+First:
+	sub	rsp, 0x28
+	call	qword ptr [rip + __imp_ImportedFunction]
+	call	qword ptr [rip + __imp_ImportedByOrdinal]
+	add	rsp, 0x28
+	ret
+FirstEnd:
+
+	.globl	Second
+Second:
+	push	rbx
+	sub	rsp, 0x20
+	test	ecx, ecx
+	jz	SecondFragment
+	cmp	ecx, 1
+	je	SecondOldFragment
+	add	rsp, 0x20
+	pop	rbx
+	ret
+SecondEnd:
+
+	.globl	DllMain
+DllMain:
+	mov	eax, 1
+	ret
+
+SecondFragment:
+	mov	ebx, ecx
+	add	rsp, 0x20
+	pop	rbx
+	ret
+SecondFragmentEnd:
+
+SecondOldFragment:
+	xor	ebx, ebx
+	add	rsp, 0x20
+	pop	rbx
+	ret
+SecondOldFragmentEnd:
+
+	.section .xdata,"dr"
+	.p2align 2
+FirstUnwind:
+	# Version 1, a prologue of 4 bytes and 1 unwind code: sub rsp, 28h.
+	.byte	0x01, 0x04, 0x01, 0x00
+	.byte	0x04, 0x42, 0x00, 0x00
+SecondUnwind:
+	# Version 1, a prologue of 5 bytes and 2 unwind codes: sub rsp, 20h and push rbx.
+	.byte	0x01, 0x05, 0x02, 0x00
+	.byte	0x05, 0x32, 0x01, 0x30
+SecondFragmentUnwind:
+	# Version 1 with UNW_FLAG_CHAININFO, followed by the entry of the primary function.
+	.byte	0x21, 0x00, 0x00, 0x00
+	.rva	Second, SecondEnd, SecondUnwind
+
+	# The entries are sorted by their begin address, so the linker doesn't move them.
+	.section .pdata,"dr"
+	.p2align 2
+	.rva	First, FirstEnd, FirstUnwind
+SecondRuntimeFunction:
+	.rva	Second, SecondEnd, SecondUnwind
+	.rva	SecondFragment, SecondFragmentEnd, SecondFragmentUnwind
+	.rva	SecondOldFragment, SecondOldFragmentEnd, SecondRuntimeFunction + 1
+
+	.data
+	.globl	Table
+Table:
+	.quad	First
+	.quad	Second
+	.quad	DllMain
@@ -0,0 +1,19 @@
+# The module imported by fixture.dll, which is also the target of its forwarded export.
+
+	.intel_syntax noprefix
+	.text
+
+	.globl	ImportedFunction
+ImportedFunction:
+	xor	eax, eax
+	ret
+
+	.globl	ImportedByOrdinal
+ImportedByOrdinal:
+	mov	eax, 1
+	ret
+
+	.globl	DllMain
+DllMain:
+	mov	eax, 1
+	ret
@@ -0,0 +1,90 @@
+# A driver shaped like ntoskrnl.exe, with the Zw* system call stubs, Nt* implementations and the
+# references of KiSystemServiceStart to the service descriptor tables.
+#
+# The instructions whose default encoding differs from the one in ntoskrnl.exe are written as bytes.
+# The dispatcher is in another section, so the jumps of the stubs have a 32-bit displacement.
+
+	.intel_syntax noprefix
+	.text
+
+# The stubs load the system call number and enter the dispatcher with a trap frame.
+	.p2align 4
+	.globl	ZwClose
+ZwClose:
+	.byte	0x48, 0x8B, 0xC4 # mov rax, rsp
+	cli
+	sub	rsp, 0x10
+	push	rax
+	pushfq
+	push	0x10
+	lea	rax, [rip + KiServiceLinkage]
+	push	rax
+	mov	eax, 0xF
+	jmp	KiServiceInternal
+
+	.p2align 4
+	.globl	ZwCreateFile
+ZwCreateFile:
+	.byte	0x48, 0x8B, 0xC4 # mov rax, rsp
+	cli
+	sub	rsp, 0x10
+	push	rax
+	pushfq
+	push	0x10
+	lea	rax, [rip + KiServiceLinkage]
+	push	rax
+	mov	eax, 0x55
+	jmp	KiServiceInternal
+
+	.p2align 4
+	.globl	NtCreateFile
+NtCreateFile:
+	sub	rsp, 0x28
+	call	KiServiceLinkage
+	mov	eax, 1
+	add	rsp, 0x28
+	ret
+
+# A system call returning a constant status, which starts like the user mode stubs after mov r10, rcx.
+	.p2align 4
+	.globl	NtConstant
+NtConstant:
+	mov	eax, 0xC00000BB
+	ret
+
+	.p2align 4
+KiSystemServiceStart:
+	.byte	0x8B, 0xF8 # mov edi, eax
+	shr	edi, 7
+	and	edi, 0x20
+	and	eax, 0xFFF
+	lea	r10, [rip + KeServiceDescriptorTable]
+	lea	r11, [rip + KeServiceDescriptorTableShadow]
+	ret
+
+	.section .text$dispatch,"xr"
+	.p2align 4
+KiServiceInternal:
+	ret
+
+	.p2align 4
+KiServiceLinkage:
+	ret
+
+	.text
+	.p2align 4
+	.globl	DriverEntry
+DriverEntry:
+	xor	eax, eax
+	ret
+
+	.data
+	.p2align 4
+	.globl	KeServiceDescriptorTable
+KeServiceDescriptorTable:
+	.zero	0x20
+
+	.p2align 6
+	.globl	KeServiceDescriptorTableShadow
+KeServiceDescriptorTableShadow:
+	.zero	0x40
@@ -0,0 +1,47 @@
+# A DLL shaped like win32u.dll, with the Nt* system call stubs and a regular function.
+#
+# The instructions whose default encoding differs from the one in win32u.dll are written as bytes.
+
+	.intel_syntax noprefix
+	.text
+
+# The stubs test SharedUserData->SystemCall to choose between syscall and int 2Eh.
+	.p2align 4
+	.globl	NtGdiCreateFile
+NtGdiCreateFile:
+	.byte	0x4C, 0x8B, 0xD1 # mov r10, rcx
+	mov	eax, 0x55
+	test	byte ptr [0x7FFE0308], 1
+	jne	1f
+	syscall
+	ret
+1:
+	int	0x2E
+	ret
+
+	.p2align 4
+	.globl	NtUserGetDC
+NtUserGetDC:
+	.byte	0x4C, 0x8B, 0xD1 # mov r10, rcx
+	mov	eax, 0x100A
+	test	byte ptr [0x7FFE0308], 1
+	jne	1f
+	syscall
+	ret
+1:
+	int	0x2E
+	ret
+
+	.p2align 4
+	.globl	NtUserRegularFunction
+NtUserRegularFunction:
+	sub	rsp, 0x28
+	mov	eax, 1
+	add	rsp, 0x28
+	ret
+
+	.p2align 4
+	.globl	DllMain
+DllMain:
+	mov	eax, 1
+	ret